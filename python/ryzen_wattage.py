@@ -0,0 +1,262 @@
+"""Samples of a ryzen-wattage ``--daemon`` in Python, for scripts and notebooks.
+
+Talks to the daemon's socket the way ``--client`` does, so it needs no MSR
+access of its own and nothing beyond the standard library. Samples are the
+dicts ``--format json`` prints, ``--schema daemon`` describes them.
+
+    import ryzen_wattage
+
+    with ryzen_wattage.Client() as client:
+        print(client.latest()["package_watts"])
+    for sample in ryzen_wattage.samples(package_only=True):
+        print(sample["package_watts"])
+
+In a notebook, watch power while other cells run::
+
+    chart = ryzen_wattage.live_chart()
+    chart.start()
+    ...
+    chart.stop()
+
+The chart is drawn with plotly if it is installed and with matplotlib
+otherwise, neither is needed for the rest.
+"""
+
+import asyncio
+import collections
+import json
+import os
+import socket
+import threading
+
+__all__ = [
+    "Client",
+    "Error",
+    "LiveChart",
+    "default_socket_path",
+    "live_chart",
+    "samples",
+    "samples_async",
+]
+
+
+class Error(Exception):
+    """A request the daemon refused or a connection it closed."""
+
+
+SOCKET_ENV = "RYZEN_WATTAGE_SOCKET"
+
+
+def _socket_paths():
+    """The sockets ``--client`` tries in turn: ``$RYZEN_WATTAGE_SOCKET`` if
+    set, otherwise a daemon running as the user and then a system daemon."""
+    path = os.environ.get(SOCKET_ENV)
+    if path is not None:
+        return [path]
+    runtime_dir = os.environ.get("XDG_RUNTIME_DIR")
+    user = [os.path.join(runtime_dir, "ryzen-wattage.sock")] if runtime_dir else []
+    return user + ["/run/ryzen-wattage.sock"]
+
+
+def default_socket_path():
+    """The socket a client without a path connects to, the first of those
+    ``--client`` tries that exists."""
+    paths = _socket_paths()
+    return next((path for path in paths if os.path.exists(path)), paths[-1])
+
+
+def _subscribe_request(cores, package_only):
+    request = {"command": "subscribe", "package_only": package_only}
+    if cores is not None:
+        request["cores"] = list(cores)
+    return request
+
+
+def _parse(line):
+    if not line:
+        raise Error("the daemon closed the connection")
+    response = json.loads(line)
+    if not response.get("ok"):
+        raise Error(response.get("error", "request failed"))
+    return response
+
+
+class Client:
+    """A connection to the daemon at ``path``, the default socket without one."""
+
+    def __init__(self, path=None, timeout=5.0):
+        paths = [path] if path else _socket_paths()
+        for self.path in paths:
+            self._socket = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+            self._socket.settimeout(timeout)
+            try:
+                self._socket.connect(self.path)
+                break
+            except OSError:
+                self._socket.close()
+                if self.path == paths[-1]:
+                    raise
+        self._lines = self._socket.makefile("r", encoding="utf-8")
+
+    def close(self):
+        self._lines.close()
+        self._socket.close()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc):
+        self.close()
+
+    def _request(self, request):
+        self._socket.sendall((json.dumps(request) + "\n").encode())
+        return _parse(self._lines.readline())
+
+    def latest(self):
+        """The newest sample."""
+        return self._request({"command": "latest"})["sample"]
+
+    def history(self, count=None):
+        """The last ``count`` samples, every one the daemon keeps without,
+        oldest first."""
+        request = {"command": "history"}
+        if count is not None:
+            request["count"] = count
+        return self._request(request)["samples"]
+
+    def ledger(self, day=None):
+        """Energy per process name, most first, on ``day`` like
+        ``"2026-10-14"`` or over every day."""
+        request = {"command": "ledger"}
+        if day is not None:
+            request["day"] = day
+        return self._request(request)["processes"]
+
+    def samples(self, cores=None, package_only=False):
+        """Every new sample as it is taken, for as long as the loop runs.
+
+        Only the ``cores`` listed and, with ``package_only``, no per-core
+        values at all. The connection carries nothing else afterwards.
+        """
+        self._request(_subscribe_request(cores, package_only))
+        # Samples come at the daemon's --interval, longer than any timeout.
+        self._socket.settimeout(None)
+        while True:
+            yield _parse(self._lines.readline())["sample"]
+
+
+def samples(path=None, cores=None, package_only=False):
+    """:meth:`Client.samples` on a connection of its own."""
+    with Client(path) as client:
+        yield from client.samples(cores, package_only)
+
+
+async def samples_async(path=None, cores=None, package_only=False):
+    """:func:`samples` as an async generator, for ``async for`` in a
+    notebook cell without blocking the kernel."""
+    paths = [path] if path else _socket_paths()
+    for candidate in paths[:-1]:
+        try:
+            reader, writer = await asyncio.open_unix_connection(candidate)
+            break
+        except OSError:
+            pass
+    else:
+        reader, writer = await asyncio.open_unix_connection(paths[-1])
+    try:
+        request = _subscribe_request(cores, package_only)
+        writer.write((json.dumps(request) + "\n").encode())
+        await writer.drain()
+        _parse(await reader.readline())
+        while True:
+            yield _parse(await reader.readline())["sample"]
+    finally:
+        writer.close()
+
+
+class LiveChart:
+    """A chart of ``metrics`` over the last ``window`` samples, redrawn on a
+    background thread with every sample until :meth:`stop`."""
+
+    def __init__(self, metrics=("package_watts", "cores_total_watts"), window=120, path=None):
+        self.metrics = list(metrics)
+        self.path = path
+        self.history = {metric: collections.deque(maxlen=window) for metric in self.metrics}
+        self._stopped = threading.Event()
+        self._thread = None
+        self._figure = _Figure(self.metrics)
+
+    def start(self):
+        """Shows the chart and starts following the daemon."""
+        self._figure.show()
+        self._stopped.clear()
+        self._thread = threading.Thread(target=self._follow, daemon=True)
+        self._thread.start()
+        return self
+
+    def stop(self):
+        """Stops after the next sample, the chart stays as it is."""
+        self._stopped.set()
+
+    def add(self, sample):
+        """Plots ``sample``, for samples from elsewhere than the daemon."""
+        for metric, values in self.history.items():
+            value = sample.get(metric)
+            values.append(float("nan") if value is None else value)
+        self._figure.update(self.history)
+
+    def _follow(self):
+        for sample in samples(self.path):
+            if self._stopped.is_set():
+                break
+            self.add(sample)
+
+
+def live_chart(metrics=("package_watts", "cores_total_watts"), window=120, path=None):
+    """A :class:`LiveChart`, call ``start()`` on it."""
+    return LiveChart(metrics, window, path)
+
+
+class _Figure:
+    """The chart in whichever plotting library is there."""
+
+    def __init__(self, metrics):
+        try:
+            import plotly.graph_objects as go
+
+            self._plotly = go.FigureWidget(
+                data=[go.Scatter(y=[], mode="lines", name=metric) for metric in metrics],
+                layout={"yaxis": {"title": "W"}, "xaxis": {"title": "sample"}},
+            )
+            self._matplotlib = None
+        except ImportError:
+            import matplotlib.pyplot as plt
+
+            self._plotly = None
+            figure, self._axes = plt.subplots()
+            plt.close(figure)
+            self._matplotlib = figure
+            self._handle = None
+
+    def show(self):
+        from IPython.display import display
+
+        if self._plotly is not None:
+            display(self._plotly)
+        else:
+            self._handle = display(self._matplotlib, display_id=True)
+
+    def update(self, history):
+        if self._plotly is not None:
+            with self._plotly.batch_update():
+                for trace, values in zip(self._plotly.data, history.values()):
+                    trace.y = list(values)
+            return
+        self._axes.clear()
+        for metric, values in history.items():
+            self._axes.plot(list(values), label=metric)
+        self._axes.set_xlabel("sample")
+        self._axes.set_ylabel("W")
+        self._axes.legend(loc="upper left")
+        if self._handle is not None:
+            self._handle.update(self._matplotlib)
@@ -0,0 +1,46 @@
+"""Tests of ryzen_wattage.py, run with ``python3 -m unittest`` in this
+directory."""
+
+import os
+import socket
+import tempfile
+import unittest
+from unittest import mock
+
+import ryzen_wattage
+
+SYSTEM = "/run/ryzen-wattage.sock"
+
+
+class DefaultSocketPathTest(unittest.TestCase):
+    def test_prefers_the_environment_then_the_user_then_the_system(self):
+        with tempfile.TemporaryDirectory() as runtime_dir:
+            user = os.path.join(runtime_dir, "ryzen-wattage.sock")
+            env = {"XDG_RUNTIME_DIR": runtime_dir}
+            with mock.patch.dict(os.environ, env, clear=True):
+                self.assertEqual(ryzen_wattage.default_socket_path(), SYSTEM)
+                open(user, "w").close()
+                self.assertEqual(ryzen_wattage.default_socket_path(), user)
+
+            env[ryzen_wattage.SOCKET_ENV] = elsewhere = "/tmp/elsewhere.sock"
+            with mock.patch.dict(os.environ, env, clear=True):
+                self.assertEqual(ryzen_wattage.default_socket_path(), elsewhere)
+
+        with mock.patch.dict(os.environ, {}, clear=True):
+            self.assertEqual(ryzen_wattage.default_socket_path(), SYSTEM)
+
+    def test_connects_to_the_user_daemon_first(self):
+        with tempfile.TemporaryDirectory() as runtime_dir:
+            user = os.path.join(runtime_dir, "ryzen-wattage.sock")
+            listener = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+            listener.bind(user)
+            listener.listen()
+            env = {"XDG_RUNTIME_DIR": runtime_dir}
+            with mock.patch.dict(os.environ, env, clear=True):
+                with ryzen_wattage.Client() as client:
+                    self.assertEqual(client.path, user)
+            listener.close()
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,89 @@
+//! `--adaptive`: long windows while the power holds still, short ones as
+//! soon as it moves.
+//!
+//! An idle machine left sampling overnight writes the same row thousands
+//! of times, while a fixed short window for catching a workload starting
+//! makes the file huge. Each window is compared with the ones before it:
+//! one that differs by more than [`CHANGE`] drops straight to the shortest
+//! window, and from there the window doubles with every quiet one, back up
+//! to `--interval`.
+
+use std::{collections::VecDeque, time::Duration};
+
+/// Share of the recent mean a window has to differ by to count as a change.
+pub const CHANGE: f64 = 0.1;
+
+/// Watts below which differences are noise whatever the share, an idle
+/// package sways by that much.
+pub const NOISE_WATTS: f64 = 1.0;
+
+/// Windows the recent mean is taken over.
+const HISTORY: usize = 4;
+
+/// The window to sample next.
+#[derive(Debug, Clone)]
+pub struct Adaptive {
+    min: Duration,
+    current: Duration,
+    recent: VecDeque<f64>,
+}
+
+impl Adaptive {
+    /// Starts with the shortest window, `min`, until there is a history.
+    pub fn new(min: Duration) -> Self {
+        Self {
+            min,
+            current: min,
+            recent: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    /// The next window after one of `power` watts, at most `max`.
+    pub fn next(&mut self, power: f64, max: Duration) -> Duration {
+        if !power.is_finite() {
+            return self.current.min(max);
+        }
+        let changed = match self.recent.len() {
+            0 => true,
+            len => {
+                let mean = self.recent.iter().sum::<f64>() / len as f64;
+                (power - mean).abs() > (mean.abs() * CHANGE).max(NOISE_WATTS)
+            }
+        };
+        // After a change, the windows before it say nothing about the next.
+        if changed {
+            self.recent.clear();
+        } else if self.recent.len() == HISTORY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(power);
+
+        self.current = match changed {
+            true => self.min,
+            false => self.current * 2,
+        }
+        .min(max)
+        .max(self.min.min(max));
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_while_stable_and_drops_on_change() {
+        let (min, max) = (Duration::from_millis(100), Duration::from_secs(1));
+        let mut adaptive = Adaptive::new(min);
+        let windows = [20.0, 20.2, 19.9, 20.1, 20.0, 20.3, 65.0, 64.0]
+            .map(|power| adaptive.next(power, max).as_millis());
+        assert_eq!(windows, [100, 200, 400, 800, 1000, 1000, 100, 200]);
+
+        // A reload can lower the longest window below the current one.
+        assert_eq!(
+            adaptive.next(64.5, Duration::from_millis(150)).as_millis(),
+            150
+        );
+    }
+}
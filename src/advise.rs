@@ -0,0 +1,390 @@
+//! `advise <THREADS>`: which CPUs to pin a workload of that many threads to.
+//!
+//! Every physical core runs the same integer loop on its own for one window
+//! while its power is measured, which gives its work per second and per
+//! joule. Cores of a CCD share their L3 cache, so the recommendation stays on
+//! one CCD if it has enough cores and picks the best of them for the goal;
+//! wider workloads fill whole CCDs first. Only past the physical cores are
+//! SMT siblings added.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt, hint, io,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cpu::Threads,
+    experiment::CpuMask,
+    state::Calibration,
+    topology::{self, Grouping},
+    Cpu, Error, Result,
+};
+
+/// Time for the clocks of a freshly loaded core to ramp up before it is
+/// measured.
+const WARMUP: Duration = Duration::from_millis(200);
+/// Loop iterations between looking at the stop flag.
+const BLOCK: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    /// The most work per joule, performance per watt.
+    Efficiency,
+    /// The most work per second, whatever it draws.
+    Performance,
+}
+
+impl Goal {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Efficiency => "efficiency",
+            Self::Performance => "performance",
+        }
+    }
+}
+
+impl fmt::Display for Goal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Goal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "efficiency" => Ok(Self::Efficiency),
+            "performance" => Ok(Self::Performance),
+            other => Err(format!(
+                "unknown goal `{}`, expected efficiency or performance",
+                other
+            )),
+        }
+    }
+}
+
+/// One physical core under load.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub core: u32,
+    /// The CCD, `None` if the topology doesn't tell.
+    pub group: Option<String>,
+    /// Power of the core, or of the package without per-core counters.
+    pub watts: f64,
+    /// Loop iterations per second.
+    pub work_rate: f64,
+}
+
+impl Measurement {
+    /// Loop iterations per joule.
+    pub fn work_per_joule(&self) -> f64 {
+        match self.watts > 0.0 {
+            true => self.work_rate / self.watts,
+            false => 0.0,
+        }
+    }
+
+    fn score(&self, goal: Goal) -> f64 {
+        match goal {
+            Goal::Efficiency => self.work_per_joule(),
+            Goal::Performance => self.work_rate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recommendation {
+    pub goal: Goal,
+    pub threads: usize,
+    /// The CPUs to pin to, SMT siblings included.
+    pub cpus: Vec<u32>,
+    /// The physical cores among them.
+    pub cores: Vec<u32>,
+    /// CCDs the cores are on.
+    pub groups: Vec<String>,
+    /// Sum of the cores' power and work rate as measured one at a time, an
+    /// estimate for all of them loaded.
+    pub watts: f64,
+    pub work_rate: f64,
+}
+
+impl Recommendation {
+    pub fn cpulist(&self) -> String {
+        topology::format_cpulist(&self.cpus)
+    }
+}
+
+/// Loads every core of `cpu` for one `interval`, one after the other.
+/// `on_core` is called after each.
+pub fn measure(
+    cpu: &Cpu,
+    interval: Duration,
+    calibration: &Calibration,
+    mut on_core: impl FnMut(&Measurement),
+) -> Result<Vec<Measurement>> {
+    let cores = cpu.threads.keys().copied().collect::<Vec<_>>();
+    let ccds = topology::groups(cpu.root(), Grouping::Ccd, &cpu.info, &cores).unwrap_or_default();
+    let group = |core: u32| {
+        ccds.iter()
+            .find(|(_, cores)| cores.contains(&core))
+            .map(|(name, _)| name.clone())
+    };
+
+    let mut measurements = Vec::with_capacity(cores.len());
+    for core in cores {
+        let (watts, work_rate) = load(cpu, core, interval)?;
+        let scale = match cpu.has_core_counters() {
+            true => calibration.cores,
+            false => calibration.package,
+        };
+        let measurement = Measurement {
+            core,
+            group: group(core),
+            watts: watts * scale,
+            work_rate,
+        };
+        on_core(&measurement);
+        measurements.push(measurement);
+    }
+    Ok(measurements)
+}
+
+/// Power and work rate of `core` with a busy loop pinned to it.
+fn load(cpu: &Cpu, core: u32, interval: Duration) -> Result<(f64, f64)> {
+    let stop = AtomicBool::new(false);
+    let work = AtomicU64::new(0);
+    let mask = CpuMask::new(&[core]);
+    // Simulated cores aren't this machine's, there's nothing to pin to.
+    let pin = cpu.backend_name() != "simulated";
+
+    thread::scope(|scope| {
+        let worker = scope.spawn(|| -> io::Result<()> {
+            if pin {
+                mask.apply()?;
+            }
+            let mut state = u64::from(core) | 1;
+            while !stop.load(AtomicOrdering::Relaxed) {
+                for _ in 0..BLOCK {
+                    // xorshift, cheap and impossible to optimize away.
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    hint::black_box(state);
+                }
+                work.fetch_add(BLOCK, AtomicOrdering::Relaxed);
+            }
+            Ok(())
+        });
+
+        let measured = (|| {
+            thread::sleep(WARMUP);
+            let before = cpu.snapshot()?;
+            let (work_before, start) = (work.load(AtomicOrdering::Relaxed), Instant::now());
+            thread::sleep(interval);
+            let after = cpu.snapshot()?;
+            let done = work.load(AtomicOrdering::Relaxed) - work_before;
+            let elapsed = start.elapsed().as_secs_f64();
+
+            let power = cpu.power_between(&before, &after);
+            let watts = power.cores.get(&core).copied().unwrap_or(power.package);
+            Ok((watts, done as f64 / elapsed))
+        })();
+
+        stop.store(true, AtomicOrdering::Relaxed);
+        match worker.join().expect("advise worker panicked") {
+            Ok(()) => measured,
+            Err(err) => Err(Error::io(
+                format!("/sys/devices/system/cpu/cpu{}", core),
+                err,
+            )),
+        }
+    })
+}
+
+/// The CPUs of `threads` to run `count` threads on for `goal`, `None` if
+/// there aren't that many.
+pub fn recommend(
+    measurements: &[Measurement],
+    threads: &Threads,
+    count: usize,
+    goal: Goal,
+) -> Option<Recommendation> {
+    let available = measurements
+        .iter()
+        .map(|measurement| threads.get(&measurement.core).map_or(1, Vec::len))
+        .sum::<usize>();
+    if count == 0 || count > available {
+        return None;
+    }
+
+    let best_first = |a: &&Measurement, b: &&Measurement| {
+        b.score(goal)
+            .partial_cmp(&a.score(goal))
+            .unwrap_or(Ordering::Equal)
+    };
+    let mut groups = BTreeMap::<Option<&str>, Vec<&Measurement>>::new();
+    for measurement in measurements {
+        groups
+            .entry(measurement.group.as_deref())
+            .or_default()
+            .push(measurement);
+    }
+    for cores in groups.values_mut() {
+        cores.sort_by(best_first);
+    }
+
+    let cores_needed = count.min(measurements.len());
+    let mut chosen = match groups
+        .values()
+        .filter(|cores| cores.len() >= cores_needed)
+        .map(|cores| cores[..cores_needed].to_vec())
+        .max_by(|a, b| {
+            total_score(a, goal)
+                .partial_cmp(&total_score(b, goal))
+                .unwrap_or(Ordering::Equal)
+        }) {
+        Some(cores) => cores,
+        // Whole CCDs, the best ones first.
+        None => {
+            let mut groups = groups.into_values().collect::<Vec<_>>();
+            groups.sort_by(|a, b| {
+                total_score(b, goal)
+                    .partial_cmp(&total_score(a, goal))
+                    .unwrap_or(Ordering::Equal)
+            });
+            let mut chosen = groups.into_iter().flatten().collect::<Vec<_>>();
+            chosen.truncate(cores_needed);
+            chosen
+        }
+    };
+    chosen.sort_by(best_first);
+
+    // The first thread of every core, then siblings of the best cores.
+    let mut cpus = chosen
+        .iter()
+        .map(|measurement| measurement.core)
+        .collect::<Vec<_>>();
+    let siblings = chosen.iter().flat_map(|measurement| {
+        threads
+            .get(&measurement.core)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |&cpu| cpu != measurement.core)
+    });
+    cpus.extend(siblings.take(count - cores_needed));
+    cpus.sort_unstable();
+
+    let mut cores = chosen
+        .iter()
+        .map(|measurement| measurement.core)
+        .collect::<Vec<_>>();
+    cores.sort_unstable();
+    let mut group_names = chosen
+        .iter()
+        .filter_map(|measurement| measurement.group.clone())
+        .collect::<Vec<_>>();
+    group_names.sort();
+    group_names.dedup();
+
+    Some(Recommendation {
+        goal,
+        threads: count,
+        cpus,
+        cores,
+        groups: group_names,
+        watts: chosen.iter().map(|measurement| measurement.watts).sum(),
+        work_rate: chosen.iter().map(|measurement| measurement.work_rate).sum(),
+    })
+}
+
+fn total_score(cores: &[&Measurement], goal: Goal) -> f64 {
+    let work_rate = cores.iter().map(|core| core.work_rate).sum::<f64>();
+    let watts = cores.iter().map(|core| core.watts).sum::<f64>();
+    match goal {
+        Goal::Efficiency if watts > 0.0 => work_rate / watts,
+        Goal::Efficiency => 0.0,
+        Goal::Performance => work_rate,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two CCDs of four cores, the second one slower but frugal, with SMT.
+    fn measurements() -> (Vec<Measurement>, Threads) {
+        let measurements = (0..8)
+            .map(|core| Measurement {
+                core,
+                group: Some(format!("ccd{}", core / 4)),
+                watts: if core < 4 {
+                    10.0 - f64::from(core)
+                } else {
+                    4.0
+                },
+                work_rate: if core < 4 { 100.0 } else { 60.0 },
+            })
+            .collect();
+        let threads = (0..8).map(|core| (core, vec![core, core + 8])).collect();
+        (measurements, threads)
+    }
+
+    #[test]
+    fn stays_on_the_best_ccd_for_the_goal() {
+        let (measurements, threads) = measurements();
+
+        let efficient = recommend(&measurements, &threads, 2, Goal::Efficiency).unwrap();
+        // 15 per joule on ccd1, against 100/7 and 100/8 for the best of ccd0.
+        assert_eq!(efficient.cpus, [4, 5]);
+        assert_eq!(efficient.groups, ["ccd1"]);
+        assert_eq!(efficient.watts, 8.0);
+
+        let fast = recommend(&measurements, &threads, 3, Goal::Performance).unwrap();
+        assert_eq!(fast.groups, ["ccd0"]);
+        assert_eq!(fast.cores.len(), 3);
+        assert_eq!(fast.work_rate, 300.0);
+    }
+
+    #[test]
+    fn fills_ccds_then_smt_siblings() {
+        let (measurements, threads) = measurements();
+
+        let wide = recommend(&measurements, &threads, 6, Goal::Efficiency).unwrap();
+        assert_eq!(wide.groups, ["ccd0", "ccd1"]);
+        // All of ccd1, then the two most efficient cores of ccd0.
+        assert_eq!(wide.cpus, [2, 3, 4, 5, 6, 7]);
+
+        let smt = recommend(&measurements, &threads, 10, Goal::Efficiency).unwrap();
+        assert_eq!(smt.cores, (0..8).collect::<Vec<_>>());
+        assert_eq!(smt.cpus.len(), 10);
+        assert!(smt.cpus.contains(&12) && smt.cpus.contains(&13));
+
+        assert!(recommend(&measurements, &threads, 17, Goal::Efficiency).is_none());
+        assert!(recommend(&measurements, &threads, 0, Goal::Efficiency).is_none());
+    }
+
+    #[test]
+    fn measures_simulated_cores() {
+        let cpu = Cpu::simulated(crate::backend::Profile::AllCore);
+        let mut seen = 0;
+        let measurements = measure(
+            &cpu,
+            Duration::from_millis(20),
+            &Calibration::default(),
+            |_| seen += 1,
+        )
+        .unwrap();
+
+        assert_eq!(seen, 8);
+        assert!(measurements
+            .iter()
+            .all(|measurement| measurement.watts > 0.0 && measurement.work_rate > 0.0));
+    }
+}
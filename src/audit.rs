@@ -0,0 +1,159 @@
+//! Append-only record of every change made to the CPU's settings.
+//!
+//! Each privileged write of [`crate::polkit`] adds one JSON line with the
+//! time, the file, the value before and after and whether the write worked,
+//! and who asked: `uid` is the writing process, `by_uid` the user pkexec
+//! authorized it for. Root writes to [`SYSTEM_LOG`], everyone else to
+//! `audit.log` in the state directory. A write that can't be recorded isn't
+//! made at all.
+//!
+//! Nothing ever truncates or rewrites the file. `chattr +a` on it makes the
+//! kernel refuse that to root as well.
+
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{output::json_string, state, timefmt};
+
+/// The log of writes made as root.
+pub const SYSTEM_LOG: &str = "/var/log/ryzen-wattage/audit.log";
+
+extern "C" {
+    fn getuid() -> u32;
+}
+
+/// Where this process records its writes.
+pub fn log_path() -> Option<PathBuf> {
+    match uid() {
+        0 => Some(PathBuf::from(SYSTEM_LOG)),
+        _ => Some(state::state_dir()?.join("audit.log")),
+    }
+}
+
+/// An open log to record writes in.
+#[derive(Debug)]
+pub struct Log {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Log {
+    /// Opens the log of this process for appending, creating it readable by
+    /// its owner only.
+    pub fn open() -> io::Result<Self> {
+        let path = log_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records that `new` was written to `path` over `old`, with the error
+    /// if it failed.
+    pub fn record(
+        &mut self,
+        path: &Path,
+        old: Option<&str>,
+        new: &str,
+        error: Option<&str>,
+    ) -> io::Result<()> {
+        let by_uid = env::var("PKEXEC_UID")
+            .ok()
+            .and_then(|uid| uid.parse().ok())
+            .unwrap_or_else(uid);
+        let line = entry(SystemTime::now(), uid(), by_uid, path, old, new, error);
+        // One write per line, so concurrent writers don't interleave.
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+}
+
+fn entry(
+    time: SystemTime,
+    uid: u32,
+    by_uid: u32,
+    path: &Path,
+    old: Option<&str>,
+    new: &str,
+    error: Option<&str>,
+) -> String {
+    let optional = |value: Option<&str>| value.map_or_else(|| "null".to_owned(), json_string);
+    format!(
+        concat!(
+            "{{\"time\":{},\"uid\":{},\"by_uid\":{},\"path\":{},",
+            "\"old\":{},\"new\":{},\"ok\":{},\"error\":{}}}\n"
+        ),
+        json_string(&timefmt::rfc3339(time, &timefmt::Zone::Utc)),
+        uid,
+        by_uid,
+        json_string(&path.display().to_string()),
+        optional(old),
+        json_string(new),
+        error.is_none(),
+        optional(error),
+    )
+}
+
+fn uid() -> u32 {
+    // SAFETY: getuid can't fail and has no side effects.
+    unsafe { getuid() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{self, Value};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn records_who_changed_what() {
+        let line = entry(
+            UNIX_EPOCH + Duration::from_secs(86400),
+            0,
+            1000,
+            Path::new("/sys/devices/system/cpu/smt/control"),
+            Some("on"),
+            "off",
+            None,
+        );
+        assert!(line.ends_with("}\n"));
+        let recorded = json::parse(&line).unwrap();
+        assert_eq!(
+            recorded.get("time"),
+            Some(&Value::String("1970-01-02T00:00:00.000Z".to_owned()))
+        );
+        assert_eq!(recorded.get("by_uid"), Some(&Value::Number(1000.0)));
+        assert_eq!(recorded.get("old"), Some(&Value::String("on".to_owned())));
+        assert_eq!(recorded.get("new"), Some(&Value::String("off".to_owned())));
+        assert_eq!(recorded.get("ok"), Some(&Value::Bool(true)));
+
+        let failed = json::parse(&entry(
+            UNIX_EPOCH,
+            1000,
+            1000,
+            Path::new("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+            None,
+            "powersave",
+            Some("Permission denied"),
+        ))
+        .unwrap();
+        assert_eq!(failed.get("ok"), Some(&Value::Bool(false)));
+        assert_eq!(failed.get("old"), Some(&Value::Null));
+    }
+}
@@ -0,0 +1,176 @@
+//! Sources of energy counters.
+
+mod msr;
+mod powercap;
+mod simulated;
+
+use std::{fmt, str::FromStr};
+
+use crate::{cpuinfo::CpuInfo, sysfs::Root, Error, Result};
+
+pub use self::{
+    msr::{Device, Msr, MsrReader, Registers},
+    powercap::{powercap_power_limit, powercap_power_limit_path, Powercap},
+    simulated::{Profile, Simulator},
+};
+
+/// A source of cumulative energy counters, in joules.
+pub trait EnergyReader: fmt::Debug + Send + Sync {
+    /// Short name used in output, e.g. `msr`.
+    fn name(&self) -> &'static str;
+
+    /// Cores that have their own energy counter, may be empty.
+    fn core_ids(&self) -> Vec<u32>;
+
+    fn package_energy(&self) -> Result<f64>;
+
+    fn core_energy(&self, core: u32) -> Result<f64>;
+
+    /// Energy in joules at which the package counter wraps back to zero,
+    /// `None` if it never wraps.
+    fn package_energy_range(&self) -> Option<f64>;
+
+    /// Like [`package_energy_range`](Self::package_energy_range) for the
+    /// per-core counters.
+    fn core_energy_range(&self) -> Option<f64>;
+
+    /// Joules per counter increment, the finest energy step the backend can
+    /// resolve, if known.
+    fn energy_unit(&self) -> Option<f64> {
+        None
+    }
+
+    /// Package-wide sub-domains with their own counter, e.g. `dram`.
+    fn domains(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    fn domain_energy(&self, domain: &str) -> Result<f64> {
+        Err(Error::parse("domain", domain))
+    }
+
+    /// Like [`package_energy_range`](Self::package_energy_range) for the
+    /// domain counters.
+    fn domain_energy_range(&self) -> Option<f64> {
+        self.package_energy_range()
+    }
+
+    /// Whether there are per-core counters this user isn't allowed to read,
+    /// so [`core_ids`](Self::core_ids) is empty and only the package is
+    /// measured.
+    fn core_counters_denied(&self) -> bool {
+        false
+    }
+
+    /// Cores left out of [`core_ids`](Self::core_ids) because their counters
+    /// can't be read, while the other cores' can.
+    fn unreadable_cores(&self) -> Vec<u32> {
+        Vec::new()
+    }
+
+    /// Sustained package power limit in W, PPT on AMD and PL1 on Intel, if
+    /// the backend can read it.
+    fn package_power_limit(&self) -> Option<f64> {
+        None
+    }
+
+    /// The package counter in whole [`energy_unit`](Self::energy_unit)s, for
+    /// arithmetic without rounding. By default the joules converted back,
+    /// which is exact as long as they are whole multiples of the unit.
+    fn package_ticks(&self) -> Result<u64> {
+        self.to_ticks(self.package_energy()?)
+    }
+
+    /// Like [`package_ticks`](Self::package_ticks) for a core.
+    fn core_ticks(&self, core: u32) -> Result<u64> {
+        self.to_ticks(self.core_energy(core)?)
+    }
+
+    /// Like [`package_ticks`](Self::package_ticks) for a domain.
+    fn domain_ticks(&self, domain: &str) -> Result<u64> {
+        self.to_ticks(self.domain_energy(domain)?)
+    }
+
+    /// Joules as whole energy units.
+    fn to_ticks(&self, joules: f64) -> Result<u64> {
+        let unit = self.energy_unit().ok_or(Error::NoEnergyUnit {
+            backend: self.name(),
+        })?;
+        Ok((joules / unit).round() as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Use the MSRs if they can be opened, powercap otherwise.
+    Auto,
+    Msr,
+    Powercap,
+}
+
+impl BackendKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Msr => "msr",
+            Self::Powercap => "powercap",
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "msr" => Ok(Self::Msr),
+            "powercap" => Ok(Self::Powercap),
+            other => Err(format!(
+                "unknown backend `{}`, expected msr, powercap or auto",
+                other
+            )),
+        }
+    }
+}
+
+/// Creates the reader for `kind`, with the devices and sysfs files below
+/// `root`. The MSRs are read from `msr_path_template` instead of
+/// `/dev/cpu/{}/msr` if it is set.
+pub fn open(
+    kind: BackendKind,
+    cpu: &CpuInfo,
+    physical_cores: &[u32],
+    root: &Root,
+    msr_path_template: Option<&str>,
+) -> Result<Box<dyn EnergyReader>> {
+    let msr = || {
+        let registers = Registers::for_cpu(cpu)?;
+        let mut msr = match msr_path_template {
+            Some(template) => MsrReader::with_device(
+                physical_cores,
+                registers,
+                Device::Template(template.to_owned()),
+                root,
+            ),
+            None => MsrReader::new(physical_cores, registers, root),
+        };
+        msr.check_readable()?;
+        msr.check_cores();
+        Ok(msr)
+    };
+
+    let powercap = || Powercap::detect(root).ok_or(Error::PowercapMissing);
+
+    let reader: Box<dyn EnergyReader> = match kind {
+        BackendKind::Msr => Box::new(msr()?),
+        BackendKind::Powercap => Box::new(powercap()?),
+        BackendKind::Auto => match msr() {
+            Ok(msr) => Box::new(msr),
+            // Report why the MSRs can't be used if there is no alternative.
+            Err(err) => Box::new(powercap().map_err(|_| err)?),
+        },
+    };
+
+    Ok(reader)
+}
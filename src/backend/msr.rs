@@ -0,0 +1,722 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs::File,
+    io,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use super::EnergyReader;
+use crate::{cpuinfo::CpuInfo, sysfs::Root, Error, Result};
+
+/// Addresses of the energy MSRs of a CPU family.
+///
+/// The sampling code only goes through this table, so a generation that
+/// moves the registers only needs a new entry in [`Registers::for_cpu`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub name: &'static str,
+    pub power_unit: u64,
+    pub package_energy: u64,
+    /// Energy of each physical core, read through that core's device.
+    pub core_energy: Option<u64>,
+    /// Package-wide sub-domains. Not every part implements all of them,
+    /// the ones that can't be read are skipped.
+    pub domains: &'static [(&'static str, u64)],
+    /// Package power limit register, in the power unit of `power_unit`.
+    pub power_limit: Option<u64>,
+}
+
+impl Registers {
+    /// AMD family 17h and later, and Hygon Dhyana which is derived from it.
+    pub const ZEN: Self = Self {
+        name: "amd-zen",
+        power_unit: 0xC0010299,
+        package_energy: 0xC001029B,
+        core_energy: Some(0xC001029A),
+        domains: &[],
+        // PPT lives in the SMU, there is no MSR for it.
+        power_limit: None,
+    };
+
+    /// Intel RAPL. There are no per-core counters, PP0 covers all cores.
+    ///
+    /// Some server parts use a fixed DRAM energy unit that differs from the
+    /// one in MSR_RAPL_POWER_UNIT, `dram` can be off by a constant there.
+    pub const INTEL: Self = Self {
+        name: "intel-rapl",
+        power_unit: 0x606,
+        package_energy: 0x611,
+        core_energy: None,
+        domains: &[("cores", 0x639), ("uncore", 0x641), ("dram", 0x619)],
+        power_limit: Some(0x610),
+    };
+
+    pub fn for_cpu(cpu: &CpuInfo) -> Result<&'static Self> {
+        match (cpu.vendor.as_str(), cpu.family) {
+            // Later families have kept the Zen layout so far.
+            ("AuthenticAMD", 0x17..) | ("HygonGenuine", 0x18) => Ok(&Self::ZEN),
+            ("GenuineIntel", 6) => Ok(&Self::INTEL),
+            _ => Err(Error::UnsupportedCpu {
+                vendor: cpu.vendor.clone(),
+                family: cpu.family,
+            }),
+        }
+    }
+
+    /// Every register the tool reads, with what it holds.
+    pub fn all(&self) -> Vec<(u64, &'static str)> {
+        let mut registers = vec![
+            (self.power_unit, "power unit"),
+            (self.package_energy, "package energy"),
+        ];
+        registers.extend(self.core_energy.map(|offset| (offset, "core energy")));
+        registers.extend(self.domains.iter().map(|&(name, offset)| (offset, name)));
+        registers.extend(
+            self.power_limit
+                .map(|offset| (offset, "package power limit")),
+        );
+        registers.sort_unstable();
+        registers
+    }
+
+    /// The entries `msr-safe` needs in `/dev/cpu/msr_allowlist` to let
+    /// unprivileged users read these registers, all read-only.
+    pub fn msr_safe_allowlist(&self) -> String {
+        let mut allowlist = format!("# MSR # Write Mask # ryzen-wattage {}\n", self.name);
+        for (offset, register) in self.all() {
+            writeln!(
+                allowlist,
+                "{:#010X} 0x0000000000000000 # {}",
+                offset, register
+            )
+            .unwrap();
+        }
+        allowlist
+    }
+}
+
+/// The driver the registers are read through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Device {
+    /// The kernel's `msr` module, usually root-only.
+    Msr,
+    /// LLNL's `msr-safe`, which lets users read the registers an
+    /// administrator put on its allowlist.
+    MsrSafe,
+    /// `msr` devices somewhere else, like bind mounts in a container. `{}`
+    /// in the template is replaced with the CPU number.
+    Template(String),
+}
+
+impl Device {
+    /// `msr` if the first core's device can be opened, `msr-safe` if it
+    /// can't and `msr-safe` is loaded.
+    fn detect(physical_cores: &[u32], root: &Root) -> Self {
+        let Some(&core) = physical_cores.first() else {
+            return Self::Msr;
+        };
+        if File::open(root.path(Self::Msr.path(core))).is_err()
+            && root.path(Self::MsrSafe.path(core)).exists()
+        {
+            return Self::MsrSafe;
+        }
+        Self::Msr
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Msr | Self::Template(_) => "msr",
+            Self::MsrSafe => "msr-safe",
+        }
+    }
+
+    fn path(&self, core: u32) -> PathBuf {
+        match self {
+            Self::Msr => PathBuf::from(format!("/dev/cpu/{}/msr", core)),
+            Self::MsrSafe => PathBuf::from(format!("/dev/cpu/{}/msr_safe", core)),
+            Self::Template(template) => PathBuf::from(template.replace("{}", &core.to_string())),
+        }
+    }
+}
+
+/// Reads the energy MSRs of every physical core.
+#[derive(Debug)]
+pub struct MsrReader {
+    registers: &'static Registers,
+    device: Device,
+    cores: BTreeMap<u32, Msr>,
+    domains: Vec<(&'static str, u64)>,
+    /// Some cores' devices can't be opened, only the package is read.
+    core_counters_denied: bool,
+    /// Cores left out of the per-core readings, their device is missing or
+    /// can't be opened.
+    unreadable: Vec<u32>,
+    root: Root,
+}
+
+impl MsrReader {
+    /// Reads the cores through the CPUs in `physical_cores`, skipping those
+    /// without an MSR device below `root`. Goes through `msr-safe` when the
+    /// plain devices can't be opened.
+    pub fn new(physical_cores: &[u32], registers: &'static Registers, root: &Root) -> Self {
+        let device = Device::detect(physical_cores, root);
+        Self::with_device(physical_cores, registers, device, root)
+    }
+
+    /// Like [`MsrReader::new`], through `device` whichever can be opened.
+    pub fn with_device(
+        physical_cores: &[u32],
+        registers: &'static Registers,
+        device: Device,
+        root: &Root,
+    ) -> Self {
+        let (present, missing) = physical_cores
+            .iter()
+            .partition::<Vec<u32>, _>(|&&core| root.path(device.path(core)).exists());
+        let cores = present
+            .into_iter()
+            .map(|core| (core, Msr::new(core, registers, device.clone(), root)))
+            .collect::<BTreeMap<_, _>>();
+
+        let domains = match cores.values().next() {
+            Some(msr) => registers
+                .domains
+                .iter()
+                .copied()
+                .filter(|&(_, offset)| msr.energy(offset).is_ok())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Self {
+            registers,
+            device,
+            cores,
+            domains,
+            core_counters_denied: false,
+            unreadable: missing,
+            root: root.clone(),
+        }
+    }
+
+    /// Makes sure the first core's device can be opened. `msr-safe` opens
+    /// for everyone allowed to use it, whether the registers are on the
+    /// allowlist only shows when reading them.
+    pub fn check_readable(&self) -> Result<()> {
+        let msr = self.cores.values().next().ok_or(Error::MsrModuleMissing)?;
+        msr.check_readable()?;
+        if self.device == Device::MsrSafe {
+            msr.energy_unit()?;
+            msr.package_energy()?;
+        }
+        Ok(())
+    }
+
+    /// Opens every other core's device and leaves out those that can't be
+    /// opened. Falls back to reading only the package through the first
+    /// core if none of them can be opened for lack of permissions, as with
+    /// udev rules that only open up `/dev/cpu/0/msr`.
+    pub fn check_cores(&mut self) {
+        if self.registers.core_energy.is_none() {
+            // Only the first core is read from.
+            self.unreadable.clear();
+            return;
+        }
+
+        let failed = self
+            .cores
+            .iter()
+            .skip(1)
+            .filter_map(|(&core, msr)| msr.check_readable().err().map(|err| (core, err)))
+            .collect::<Vec<_>>();
+
+        self.core_counters_denied = all_denied(self.cores.len().saturating_sub(1), &failed);
+        if self.core_counters_denied {
+            self.unreadable.clear();
+            return;
+        }
+
+        for (core, _) in failed {
+            self.cores.remove(&core);
+            self.unreadable.push(core);
+        }
+        self.unreadable.sort_unstable();
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn msr(&self, core: u32) -> Result<&Msr> {
+        self.cores.get(&core).ok_or_else(|| {
+            Error::io(
+                self.root.path(self.device.path(core)),
+                io::ErrorKind::NotFound.into(),
+            )
+        })
+    }
+
+    /// The device domains are read through and the register of `domain`.
+    fn domain(&self, domain: &str) -> Result<(&Msr, u64)> {
+        let &(_, offset) = self
+            .domains
+            .iter()
+            .find(|&&(name, _)| name == domain)
+            .ok_or_else(|| Error::parse("domain", domain))?;
+        let (_, msr) = self.cores.iter().next().ok_or(Error::MsrModuleMissing)?;
+        Ok((msr, offset))
+    }
+
+    fn core_offset(&self, core: u32) -> Result<u64> {
+        self.registers.core_energy.ok_or_else(|| {
+            Error::io(
+                self.root.path(self.device.path(core)),
+                io::ErrorKind::Unsupported.into(),
+            )
+        })
+    }
+}
+
+/// Whether all `others` cores failed to open for lack of permissions,
+/// rather than some of them being broken.
+fn all_denied(others: usize, failed: &[(u32, Error)]) -> bool {
+    others > 0
+        && failed.len() == others
+        && failed
+            .iter()
+            .all(|(_, err)| matches!(err, Error::PermissionDenied { .. }))
+}
+
+impl EnergyReader for MsrReader {
+    fn name(&self) -> &'static str {
+        self.device.name()
+    }
+
+    fn core_ids(&self) -> Vec<u32> {
+        match self.registers.core_energy {
+            Some(_) if !self.core_counters_denied => self.cores.keys().copied().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn core_counters_denied(&self) -> bool {
+        self.core_counters_denied
+    }
+
+    fn unreadable_cores(&self) -> Vec<u32> {
+        self.unreadable.clone()
+    }
+
+    fn package_energy(&self) -> Result<f64> {
+        // The package counter reads the same on every core.
+        let (_, msr) = self.cores.iter().next().ok_or(Error::MsrModuleMissing)?;
+        msr.package_energy()
+    }
+
+    fn core_energy(&self, core: u32) -> Result<f64> {
+        self.msr(core)?.core_energy()
+    }
+
+    fn package_energy_range(&self) -> Option<f64> {
+        self.cores.values().next()?.energy_range().ok()
+    }
+
+    fn core_energy_range(&self) -> Option<f64> {
+        self.package_energy_range()
+    }
+
+    fn energy_unit(&self) -> Option<f64> {
+        self.cores.values().next()?.energy_unit().ok()
+    }
+
+    fn domains(&self) -> Vec<&'static str> {
+        self.domains.iter().map(|&(name, _)| name).collect()
+    }
+
+    fn domain_energy(&self, domain: &str) -> Result<f64> {
+        let (msr, offset) = self.domain(domain)?;
+        msr.energy(offset)
+    }
+
+    fn package_power_limit(&self) -> Option<f64> {
+        self.cores.values().next()?.power_limit().ok()?
+    }
+
+    fn package_ticks(&self) -> Result<u64> {
+        let (_, msr) = self.cores.iter().next().ok_or(Error::MsrModuleMissing)?;
+        msr.ticks(self.registers.package_energy)
+    }
+
+    fn core_ticks(&self, core: u32) -> Result<u64> {
+        self.msr(core)?.ticks(self.core_offset(core)?)
+    }
+
+    fn domain_ticks(&self, domain: &str) -> Result<u64> {
+        let (msr, offset) = self.domain(domain)?;
+        msr.ticks(offset)
+    }
+}
+
+#[derive(Debug)]
+pub struct Msr {
+    path: PathBuf,
+    /// `/dev/cpu/0/msr` or `msr_safe`, which only exists with the module
+    /// loaded.
+    first_device: PathBuf,
+    device: Device,
+    registers: &'static Registers,
+    /// Kept open between reads, opening the device costs more than reading
+    /// it.
+    file: Mutex<Option<File>>,
+    /// Joules per increment, fixed at boot, so read only once.
+    energy_unit: OnceLock<f64>,
+}
+
+impl Msr {
+    const ENERGY_UNIT_MASK: u64 = 0x1F00;
+    const POWER_UNIT_MASK: u64 = 0xF;
+    /// PL1 and whether it is enforced.
+    const POWER_LIMIT_MASK: u64 = 0x7FFF;
+    const POWER_LIMIT_ENABLED: u64 = 1 << 15;
+    /// Only the lower 32 bits of the energy registers hold the counter.
+    const ENERGY_COUNTER_MASK: u64 = 0xFFFF_FFFF;
+
+    pub fn new(core: u32, registers: &'static Registers, device: Device, root: &Root) -> Self {
+        Self {
+            path: root.path(device.path(core)),
+            first_device: root.path(device.path(0)),
+            device,
+            registers,
+            file: Mutex::new(None),
+            energy_unit: OnceLock::new(),
+        }
+    }
+
+    pub fn check_readable(&self) -> Result<()> {
+        let file = self.open()?;
+        *self.file.lock().unwrap() = Some(file);
+        Ok(())
+    }
+
+    pub fn core_energy(&self) -> Result<f64> {
+        let offset = self
+            .registers
+            .core_energy
+            .ok_or_else(|| Error::io(&self.path, io::ErrorKind::Unsupported.into()))?;
+        self.energy(offset)
+    }
+
+    pub fn package_energy(&self) -> Result<f64> {
+        self.energy(self.registers.package_energy)
+    }
+
+    /// Reads the energy counter at `offset` in joules.
+    fn energy(&self, offset: u64) -> Result<f64> {
+        Ok(self.ticks(offset)? as f64 * self.energy_unit()?)
+    }
+
+    /// Reads the energy counter at `offset` in energy units.
+    fn ticks(&self, offset: u64) -> Result<u64> {
+        Ok(self.read_register(offset)? & Self::ENERGY_COUNTER_MASK)
+    }
+
+    /// Energy in joules after which the 32 bit counters wrap.
+    pub fn energy_range(&self) -> Result<f64> {
+        Ok((Self::ENERGY_COUNTER_MASK + 1) as f64 * self.energy_unit()?)
+    }
+
+    /// Joules per increment of the energy counters.
+    pub fn energy_unit(&self) -> Result<f64> {
+        if let Some(&unit) = self.energy_unit.get() {
+            return Ok(unit);
+        }
+
+        let units = self.read_register(self.registers.power_unit)?;
+        Ok(*self
+            .energy_unit
+            .get_or_init(|| Self::decode_energy_unit(units)))
+    }
+
+    /// Joules per increment as encoded in the power unit register, 2^-ESU.
+    fn decode_energy_unit(units: u64) -> f64 {
+        0.5_f64.powf(((units & Self::ENERGY_UNIT_MASK) >> 8) as f64)
+    }
+
+    /// PL1 in W from the power limit and power unit registers, `None` unless
+    /// it is enforced.
+    fn decode_power_limit(limit: u64, units: u64) -> Option<f64> {
+        if limit & Self::POWER_LIMIT_ENABLED == 0 {
+            return None;
+        }
+        let unit = 0.5_f64.powf((units & Self::POWER_UNIT_MASK) as f64);
+        Some((limit & Self::POWER_LIMIT_MASK) as f64 * unit)
+    }
+
+    /// The enforced sustained package power limit in W, `None` if there is
+    /// none.
+    pub fn power_limit(&self) -> Result<Option<f64>> {
+        let Some(offset) = self.registers.power_limit else {
+            return Ok(None);
+        };
+
+        let limit = self.read_register(offset)?;
+        let units = self.read_register(self.registers.power_unit)?;
+        Ok(Self::decode_power_limit(limit, units))
+    }
+
+    fn open(&self) -> Result<File> {
+        File::open(&self.path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound if !self.first_device.exists() => Error::MsrModuleMissing,
+            _ => Error::io(&self.path, err),
+        })
+    }
+
+    /// Reads the register at `offset` through the cached device file. The
+    /// file is reopened once if the read fails, in case the device went away
+    /// and came back, like after reloading the msr module.
+    fn read_register(&self, offset: u64) -> Result<u64> {
+        let mut file = self.file.lock().unwrap();
+
+        if let Some(open) = file.as_ref() {
+            match Self::read_at(open, offset) {
+                Ok(data) => return Ok(data),
+                Err(_) => *file = None,
+            }
+        }
+
+        let open = file.insert(self.open()?);
+        Self::read_at(open, offset).map_err(|err| {
+            *file = None;
+            match err.kind() {
+                // msr-safe refuses registers that aren't on its allowlist.
+                io::ErrorKind::PermissionDenied if self.device == Device::MsrSafe => {
+                    Error::MsrSafeNotAllowed {
+                        path: self.path.clone(),
+                        register: offset,
+                    }
+                }
+                _ => Error::io(&self.path, err),
+            }
+        })
+    }
+
+    fn read_at(file: &File, offset: u64) -> io::Result<u64> {
+        let mut data = [0u8; 8];
+        file.read_exact_at(&mut data, offset)?;
+        Ok(u64::from_ne_bytes(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    /// MSR_RAPL_POWER_UNIT as Zen 3 reports it: 1/8 W, 15.3µJ, 976µs.
+    const ZEN_UNITS: u64 = 0x000A_1003;
+    /// The same on a typical Intel desktop part: 1/8 W, 61µJ.
+    const INTEL_UNITS: u64 = 0x000A_0E03;
+
+    fn cpu(vendor: &str, family: u32) -> CpuInfo {
+        CpuInfo {
+            vendor: vendor.to_owned(),
+            family,
+            ..CpuInfo::default()
+        }
+    }
+
+    #[test]
+    fn picks_registers_by_vendor_and_family() {
+        let registers = |vendor, family| Registers::for_cpu(&cpu(vendor, family)).map(|r| r.name);
+        assert_eq!(registers("AuthenticAMD", 0x17).unwrap(), "amd-zen");
+        assert_eq!(registers("AuthenticAMD", 0x1A).unwrap(), "amd-zen");
+        assert_eq!(registers("HygonGenuine", 0x18).unwrap(), "amd-zen");
+        assert_eq!(registers("GenuineIntel", 6).unwrap(), "intel-rapl");
+        assert!(matches!(
+            registers("AuthenticAMD", 0x15),
+            Err(Error::UnsupportedCpu { family: 0x15, .. })
+        ));
+    }
+
+    #[test]
+    fn decodes_energy_unit() {
+        assert_eq!(Msr::decode_energy_unit(ZEN_UNITS), 1.0 / 65536.0);
+        assert_eq!(Msr::decode_energy_unit(INTEL_UNITS), 1.0 / 16384.0);
+        assert_eq!(Msr::decode_energy_unit(0), 1.0);
+    }
+
+    #[test]
+    fn decodes_power_limit() {
+        let limit = Msr::POWER_LIMIT_ENABLED | (125 * 8);
+        assert_eq!(Msr::decode_power_limit(limit, INTEL_UNITS), Some(125.0));
+        assert_eq!(Msr::decode_power_limit(125 * 8, INTEL_UNITS), None);
+        // Only the lowest 15 bits are PL1, PL2 sits above.
+        let both = limit | (250 * 8) << 32 | Msr::POWER_LIMIT_ENABLED << 32;
+        assert_eq!(Msr::decode_power_limit(both, INTEL_UNITS), Some(125.0));
+    }
+
+    #[test]
+    fn reads_cores_with_a_device() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/dev/cpu/0/msr", "")
+            .file("/dev/cpu/1/msr", "");
+        let mut msr = MsrReader::new(&[0, 1, 2], &Registers::ZEN, fixture.root());
+        msr.check_readable().unwrap();
+        msr.check_cores();
+
+        assert!(!msr.core_counters_denied());
+        assert_eq!(msr.core_ids(), [0, 1]);
+        assert_eq!(msr.unreadable_cores(), [2]);
+        assert!(msr.core_energy(2).unwrap_err().is_device_gone());
+    }
+
+    #[test]
+    fn leaves_out_cores_whose_device_cant_be_opened() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/dev/cpu/0/msr", "")
+            .file("/dev/cpu/2/msr", "")
+            .file("/dev/cpu/1/.keep", "");
+        // Opening a socket fails with ENXIO, even for root.
+        let _socket = UnixListener::bind(fixture.root().path("/dev/cpu/1/msr")).unwrap();
+        let mut msr = MsrReader::new(&[0, 1, 2], &Registers::ZEN, fixture.root());
+        msr.check_readable().unwrap();
+        assert!(msr.unreadable_cores().is_empty());
+        msr.check_cores();
+
+        assert!(!msr.core_counters_denied());
+        assert_eq!(msr.core_ids(), [0, 2]);
+        assert_eq!(msr.unreadable_cores(), [1]);
+        assert!(msr.core_energy(1).unwrap_err().is_device_gone());
+
+        // Without core counters only the first core is ever opened.
+        let mut msr = MsrReader::new(&[0, 1, 3], &Registers::INTEL, fixture.root());
+        assert_eq!(msr.unreadable_cores(), [3]);
+        msr.check_cores();
+        assert!(msr.unreadable_cores().is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_the_package_when_every_other_core_is_denied() {
+        let denied = |core| {
+            let path = PathBuf::from(format!("/dev/cpu/{}/msr", core));
+            (core, Error::PermissionDenied { path })
+        };
+        assert!(all_denied(2, &[denied(1), denied(2)]));
+        assert!(!all_denied(2, &[denied(1)]));
+        assert!(!all_denied(0, &[]));
+        let gone = (
+            2,
+            Error::io("/dev/cpu/2/msr", io::ErrorKind::NotFound.into()),
+        );
+        assert!(!all_denied(2, &[denied(1), gone]));
+    }
+
+    #[test]
+    fn reads_intel_counters() {
+        let device = "/dev/cpu/0/msr";
+        let fixture = Fixture::new();
+        // Reads past the end of the file fail, so `uncore` at 0x641 can't be
+        // read.
+        fixture
+            .register(device, Registers::INTEL.power_unit, INTEL_UNITS)
+            // The upper half of the energy registers is reserved.
+            .register(device, 0x611, 0xDEAD_0000_0000_0000 | (16384 * 30))
+            .register(device, 0x619, 16384 * 2)
+            .register(device, 0x639, 16384 * 20);
+        let msr = MsrReader::new(&[0], &Registers::INTEL, fixture.root());
+        msr.check_readable().unwrap();
+
+        assert!(msr.core_ids().is_empty());
+        assert_eq!(msr.energy_unit(), Some(1.0 / 16384.0));
+        assert_eq!(msr.package_energy().unwrap(), 30.0);
+        assert_eq!(msr.package_ticks().unwrap(), 16384 * 30);
+        assert_eq!(msr.package_energy_range(), Some(262144.0));
+        assert_eq!(msr.domains(), ["cores", "dram"]);
+        assert_eq!(msr.domain_energy("cores").unwrap(), 20.0);
+        assert_eq!(msr.domain_ticks("dram").unwrap(), 16384 * 2);
+        assert!(msr.domain_energy("uncore").is_err());
+    }
+
+    #[test]
+    fn reads_devices_from_a_template() {
+        let fixture = Fixture::new();
+        fixture
+            .register("/host/cpu0-msr", Registers::INTEL.power_unit, INTEL_UNITS)
+            .register("/host/cpu0-msr", 0x611, 16384 * 12)
+            .file("/dev/cpu/0/msr", "");
+        let device = Device::Template("/host/cpu{}-msr".to_owned());
+        let msr = MsrReader::with_device(&[0, 1], &Registers::INTEL, device, fixture.root());
+        msr.check_readable().unwrap();
+
+        assert_eq!(msr.name(), "msr");
+        assert_eq!(msr.package_energy().unwrap(), 12.0);
+        assert_eq!(msr.unreadable_cores(), [1]);
+    }
+
+    #[test]
+    fn reads_through_msr_safe_without_msr() {
+        let device = "/dev/cpu/0/msr_safe";
+        let fixture = Fixture::new();
+        fixture
+            .register(device, Registers::INTEL.power_unit, INTEL_UNITS)
+            .register(device, 0x611, 16384 * 30);
+        let msr = MsrReader::new(&[0], &Registers::INTEL, fixture.root());
+        msr.check_readable().unwrap();
+
+        assert_eq!(msr.device(), &Device::MsrSafe);
+        assert_eq!(msr.name(), "msr-safe");
+        assert_eq!(msr.package_energy().unwrap(), 30.0);
+    }
+
+    #[test]
+    fn prefers_msr_over_msr_safe() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/dev/cpu/0/msr", "")
+            .file("/dev/cpu/0/msr_safe", "");
+        let msr = MsrReader::new(&[0], &Registers::ZEN, fixture.root());
+        assert_eq!(msr.device(), &Device::Msr);
+    }
+
+    #[test]
+    fn msr_safe_must_read_the_counters() {
+        // An empty allowlist lets the device open but no register be read.
+        let fixture = Fixture::new();
+        fixture.file("/dev/cpu/0/msr_safe", "");
+        let msr = MsrReader::new(&[0], &Registers::ZEN, fixture.root());
+        assert!(msr.check_readable().is_err());
+    }
+
+    #[test]
+    fn allowlists_every_register() {
+        let allowlist = Registers::ZEN.msr_safe_allowlist();
+        let entries = allowlist
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                "0xC0010299 0x0000000000000000 # power unit",
+                "0xC001029A 0x0000000000000000 # core energy",
+                "0xC001029B 0x0000000000000000 # package energy",
+            ]
+        );
+        assert_eq!(Registers::INTEL.all().len(), 6);
+    }
+
+    #[test]
+    fn no_devices_means_no_module() {
+        let fixture = Fixture::new();
+        let msr = MsrReader::new(&[0, 1], &Registers::ZEN, fixture.root());
+        assert!(matches!(msr.check_readable(), Err(Error::MsrModuleMissing)));
+        assert!(matches!(msr.package_energy(), Err(Error::MsrModuleMissing)));
+    }
+}
@@ -0,0 +1,327 @@
+//! Energy readings from sysfs, for systems where `/dev/cpu/*/msr` can't be
+//! used.
+//!
+//! The package counter comes from the powercap RAPL zone (which the kernel
+//! also names `intel-rapl` on AMD), per-core counters from the out-of-tree
+//! `amd_energy` hwmon driver when it is loaded. Both report microjoules.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::EnergyReader;
+use crate::{sysfs::Root, Error, Result};
+
+const POWERCAP_PATH: &str = "/sys/class/powercap";
+const HWMON_PATH: &str = "/sys/class/hwmon";
+
+#[derive(Debug)]
+pub struct Powercap {
+    package: PathBuf,
+    /// From `max_energy_range_uj`, amd_energy counters don't wrap.
+    package_range: Option<f64>,
+    cores: BTreeMap<u32, PathBuf>,
+    /// `amd_energy` has per-core counters, but they are root-only.
+    cores_denied: bool,
+    root: Root,
+}
+
+impl Powercap {
+    /// Looks for a readable package energy counter below `root`, returns
+    /// `None` if there is none.
+    pub fn detect(root: &Root) -> Option<Self> {
+        let amd_energy = find_amd_energy(root);
+
+        let package = find_rapl_package(root)
+            .or_else(|| amd_energy.as_ref().and_then(|(package, _)| package.clone()))?;
+        let mut cores = amd_energy.map(|(_, cores)| cores).unwrap_or_default();
+
+        // The counters are often root-only, make sure we can actually use them.
+        read_microjoules(&package).ok()?;
+        let cores_denied = cores
+            .values()
+            .any(|core| matches!(read_microjoules(core), Err(Error::PermissionDenied { .. })));
+        if cores_denied {
+            cores.clear();
+        }
+
+        let package_range = package
+            .parent()
+            .and_then(|zone| read_microjoules(&zone.join("max_energy_range_uj")).ok());
+
+        Some(Self {
+            package,
+            package_range,
+            cores,
+            cores_denied,
+            root: root.clone(),
+        })
+    }
+
+    fn core_path(&self, core: u32) -> Result<&Path> {
+        self.cores
+            .get(&core)
+            .map(PathBuf::as_path)
+            .ok_or_else(|| Error::io(self.root.path(HWMON_PATH), io::ErrorKind::NotFound.into()))
+    }
+}
+
+impl EnergyReader for Powercap {
+    fn name(&self) -> &'static str {
+        "powercap"
+    }
+
+    fn core_ids(&self) -> Vec<u32> {
+        self.cores.keys().copied().collect()
+    }
+
+    fn package_energy(&self) -> Result<f64> {
+        read_microjoules(&self.package)
+    }
+
+    fn core_counters_denied(&self) -> bool {
+        self.cores_denied
+    }
+
+    fn core_energy(&self, core: u32) -> Result<f64> {
+        read_microjoules(self.core_path(core)?)
+    }
+
+    fn package_energy_range(&self) -> Option<f64> {
+        self.package_range
+    }
+
+    fn core_energy_range(&self) -> Option<f64> {
+        None
+    }
+
+    fn energy_unit(&self) -> Option<f64> {
+        // sysfs reports whole microjoules.
+        Some(1e-6)
+    }
+
+    fn package_power_limit(&self) -> Option<f64> {
+        powercap_power_limit(&self.root)
+    }
+
+    fn package_ticks(&self) -> Result<u64> {
+        read_counter(&self.package)
+    }
+
+    fn core_ticks(&self, core: u32) -> Result<u64> {
+        read_counter(self.core_path(core)?)
+    }
+}
+
+/// The `long_term` constraint of the RAPL package zone in W, readable
+/// whichever backend reads the counters.
+pub fn powercap_power_limit(root: &Root) -> Option<f64> {
+    read_microjoules(&powercap_power_limit_path(root)?)
+        .ok()
+        // Zones without a limit report 0.
+        .filter(|&watts| watts > 0.0)
+}
+
+/// The file of that constraint, in microwatts, which `limit set package`
+/// writes.
+pub fn powercap_power_limit_path(root: &Root) -> Option<PathBuf> {
+    let zone = find_rapl_zone(root)?;
+
+    (0..)
+        .map_while(|constraint| {
+            let name = fs::read_to_string(zone.join(format!("constraint_{}_name", constraint)));
+            Some((constraint, name.ok()?))
+        })
+        .find(|(_, name)| name.trim_end() == "long_term")
+        .map(|(constraint, _)| zone.join(format!("constraint_{}_power_limit_uw", constraint)))
+}
+
+fn find_rapl_package(root: &Root) -> Option<PathBuf> {
+    find_rapl_zone(root).map(|zone| zone.join("energy_uj"))
+}
+
+fn find_rapl_zone(root: &Root) -> Option<PathBuf> {
+    let zones = fs::read_dir(root.path(POWERCAP_PATH)).ok()?;
+
+    let mut zones = zones
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|zone| {
+            fs::read_to_string(zone.join("name")).is_ok_and(|name| name.trim_end() == "package-0")
+        })
+        .collect::<Vec<_>>();
+    zones.sort();
+
+    zones.into_iter().next()
+}
+
+/// Returns the socket counter and the per-core counters of `amd_energy`.
+fn find_amd_energy(root: &Root) -> Option<(Option<PathBuf>, BTreeMap<u32, PathBuf>)> {
+    let hwmon = fs::read_dir(root.path(HWMON_PATH)).ok()?;
+
+    let dir = hwmon.flatten().map(|entry| entry.path()).find(|dir| {
+        fs::read_to_string(dir.join("name")).is_ok_and(|name| name.trim_end() == "amd_energy")
+    })?;
+
+    let mut socket = None;
+    let mut cores = BTreeMap::new();
+
+    for entry in fs::read_dir(&dir).ok()?.flatten() {
+        let file_name = entry.file_name();
+        let Some(channel) = file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix("_label"))
+        else {
+            continue;
+        };
+
+        let Ok(label) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let input = dir.join(format!("{}_input", channel));
+
+        // Labels look like `Ecore000` and `Esocket0`.
+        let label = label.trim_end();
+        if let Some(core) = label.strip_prefix("Ecore") {
+            if let Ok(core) = core.parse::<u32>() {
+                cores.insert(core, input);
+            }
+        } else if label == "Esocket0" {
+            socket = Some(input);
+        }
+    }
+
+    Some((socket, cores))
+}
+
+fn read_microjoules(path: &Path) -> Result<f64> {
+    Ok(read_counter(path)? as f64 / 1_000_000.0)
+}
+
+/// A counter file as is, in microjoules.
+fn read_counter(path: &Path) -> Result<u64> {
+    let value = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    value
+        .trim_end()
+        .parse::<u64>()
+        .map_err(|_| Error::parse(path, value.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    fn rapl_zone(fixture: &Fixture) {
+        let zone = "/sys/class/powercap/intel-rapl:0";
+        fixture
+            .file(format!("{}/name", zone), "package-0\n")
+            .file(format!("{}/energy_uj", zone), "123456789\n")
+            .file(format!("{}/max_energy_range_uj", zone), "262143328850\n")
+            .file(format!("{}/constraint_0_name", zone), "long_term\n")
+            .file(
+                format!("{}/constraint_0_power_limit_uw", zone),
+                "88000000\n",
+            )
+            .file(format!("{}/constraint_1_name", zone), "short_term\n")
+            .file(
+                format!("{}/constraint_1_power_limit_uw", zone),
+                "121000000\n",
+            )
+            .file("/sys/class/powercap/intel-rapl:0:0/name", "core\n");
+    }
+
+    fn amd_energy(fixture: &Fixture) {
+        let hwmon = "/sys/class/hwmon/hwmon3";
+        fixture
+            .file(format!("{}/name", hwmon), "amd_energy\n")
+            .file(format!("{}/energy1_label", hwmon), "Ecore000\n")
+            .file(format!("{}/energy1_input", hwmon), "1500000\n")
+            .file(format!("{}/energy2_label", hwmon), "Ecore001\n")
+            .file(format!("{}/energy2_input", hwmon), "2500000\n")
+            .file(format!("{}/energy17_label", hwmon), "Esocket0\n")
+            .file(format!("{}/energy17_input", hwmon), "40000000\n")
+            .file("/sys/class/hwmon/hwmon0/name", "k10temp\n");
+    }
+
+    #[test]
+    fn reads_the_rapl_package_zone() {
+        let fixture = Fixture::new();
+        rapl_zone(&fixture);
+        let powercap = Powercap::detect(fixture.root()).unwrap();
+
+        assert!(powercap.core_ids().is_empty());
+        assert_eq!(powercap.package_energy().unwrap(), 123.456789);
+        assert_eq!(powercap.package_ticks().unwrap(), 123456789);
+        assert_eq!(powercap.package_energy_range(), Some(262143.32885));
+        assert_eq!(powercap.package_power_limit(), Some(88.0));
+        assert_eq!(
+            powercap_power_limit_path(fixture.root()),
+            Some(
+                fixture
+                    .root()
+                    .path("/sys/class/powercap/intel-rapl:0/constraint_0_power_limit_uw")
+            )
+        );
+    }
+
+    #[test]
+    fn prefers_rapl_over_amd_energy_for_the_package() {
+        let fixture = Fixture::new();
+        rapl_zone(&fixture);
+        amd_energy(&fixture);
+        let powercap = Powercap::detect(fixture.root()).unwrap();
+
+        assert_eq!(powercap.package_energy().unwrap(), 123.456789);
+        assert_eq!(powercap.core_ids(), [0, 1]);
+        assert_eq!(powercap.core_energy(1).unwrap(), 2.5);
+        assert_eq!(powercap.core_ticks(0).unwrap(), 1500000);
+        assert!(powercap.core_energy(2).unwrap_err().is_device_gone());
+    }
+
+    #[test]
+    fn falls_back_to_the_amd_energy_socket() {
+        let fixture = Fixture::new();
+        amd_energy(&fixture);
+        let powercap = Powercap::detect(fixture.root()).unwrap();
+
+        assert_eq!(powercap.package_energy().unwrap(), 40.0);
+        assert_eq!(powercap.package_energy_range(), None);
+        assert_eq!(powercap.package_power_limit(), None);
+    }
+
+    #[test]
+    fn unset_limits_are_none() {
+        let fixture = Fixture::new();
+        rapl_zone(&fixture);
+        fixture.file(
+            "/sys/class/powercap/intel-rapl:0/constraint_0_power_limit_uw",
+            "0\n",
+        );
+        assert_eq!(powercap_power_limit(fixture.root()), None);
+    }
+
+    #[test]
+    fn needs_a_readable_counter() {
+        let fixture = Fixture::new();
+        assert!(Powercap::detect(fixture.root()).is_none());
+
+        fixture.file("/sys/class/powercap/intel-rapl:0/name", "package-0\n");
+        assert!(Powercap::detect(fixture.root()).is_none());
+    }
+
+    #[test]
+    fn garbage_counters_are_parse_errors() {
+        let fixture = Fixture::new();
+        rapl_zone(&fixture);
+        let powercap = Powercap::detect(fixture.root()).unwrap();
+        fixture.file("/sys/class/powercap/intel-rapl:0/energy_uj", "n/a\n");
+
+        assert!(matches!(
+            powercap.package_energy(),
+            Err(Error::Parse { value, .. }) if value == "n/a"
+        ));
+    }
+}
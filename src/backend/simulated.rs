@@ -0,0 +1,225 @@
+//! Synthetic energy counters following a load profile, so the tool can be
+//! developed and demoed without a Ryzen CPU.
+//!
+//! Power is a deterministic function of the time since the simulation
+//! started, made up of a base load per core, slow drifts and bursts. The
+//! counters integrate it and wrap like the MSRs of a Zen 3 CPU do.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::EnergyReader;
+use crate::{Error, Result};
+
+/// 15.3µJ, the energy unit of Zen CPUs.
+const ENERGY_UNIT: f64 = 1.0 / 65536.0;
+/// The counters are 32 bits wide.
+const ENERGY_RANGE: f64 = ENERGY_UNIT * 4294967296.0;
+/// Step of the numeric integration.
+const STEP: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Desktop doing nothing, with the odd background wakeup.
+    Idle,
+    /// A game: two busy render threads, some helpers and frame-paced bursts.
+    Gaming,
+    /// Every core fully loaded, slowly dropping as the CPU heats up.
+    AllCore,
+}
+
+impl Profile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Gaming => "gaming",
+            Self::AllCore => "all-core",
+        }
+    }
+
+    /// Power of `core` in W, `t` seconds into the simulation.
+    fn core_power(&self, core: u32, t: f64) -> f64 {
+        let bucket = |period: f64| noise(u64::from(core), (t / period) as u64);
+
+        match self {
+            Self::Idle => {
+                let wakeup = bucket(0.1);
+                0.4 + 0.2 * bucket(1.0).abs()
+                    + if wakeup > 0.8 {
+                        12.0 * (wakeup - 0.8)
+                    } else {
+                        0.0
+                    }
+            }
+            Self::Gaming => {
+                let base = match core {
+                    0 | 1 => 11.0,
+                    2..=5 => 4.0,
+                    _ => 1.0,
+                };
+                // Frames every 16.7ms, scenes changing every few seconds.
+                let frame = 0.1 * (t * 60.0 * std::f64::consts::TAU).sin();
+                let scene = 0.15 * (t / 5.0 * std::f64::consts::TAU).sin();
+                (base * (1.0 + frame + scene) + 0.3 * base * bucket(0.05)).max(0.2)
+            }
+            Self::AllCore => {
+                let droop = 1.0 - (-t / 30.0).exp();
+                14.5 - 1.0 * droop + 0.3 * bucket(0.05)
+            }
+        }
+    }
+
+    /// Power of the I/O die and everything else outside the cores in W.
+    fn uncore_power(&self, t: f64) -> f64 {
+        let base = match self {
+            Self::Idle => 20.0,
+            Self::Gaming => 24.0,
+            Self::AllCore => 26.0,
+        };
+        base + 0.5 * noise(u64::MAX, (t / 0.1) as u64)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "idle" => Ok(Self::Idle),
+            "gaming" => Ok(Self::Gaming),
+            "all-core" => Ok(Self::AllCore),
+            other => Err(format!(
+                "unknown profile `{}`, expected idle, gaming or all-core",
+                other
+            )),
+        }
+    }
+}
+
+/// Deterministic noise in [-1, 1] for a stream and a time bucket,
+/// splitmix64 of both.
+fn noise(stream: u64, bucket: u64) -> f64 {
+    let mut x = stream
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        .wrapping_add(bucket);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1_u64 << 52) as f64 - 1.0
+}
+
+#[derive(Debug)]
+pub struct Simulator {
+    profile: Profile,
+    cores: Vec<u32>,
+    start: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Time the energies below were integrated up to.
+    time: Duration,
+    package: f64,
+    cores: Vec<f64>,
+}
+
+impl Simulator {
+    pub fn new(profile: Profile, cores: Vec<u32>) -> Self {
+        let state = State {
+            time: Duration::ZERO,
+            package: 0.0,
+            cores: vec![0.0; cores.len()],
+        };
+
+        Self {
+            profile,
+            cores,
+            start: Instant::now(),
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    /// Integrates power up to now and returns the package and core energies.
+    fn advance(&self) -> (f64, Vec<f64>) {
+        let now = self.start.elapsed();
+        let mut state = self.state.lock().unwrap();
+
+        while state.time < now {
+            let step = STEP.min(now - state.time);
+            // Midpoint rule.
+            let t = (state.time + step / 2).as_secs_f64();
+            let dt = step.as_secs_f64();
+
+            let mut package = self.profile.uncore_power(t);
+            for (index, &core) in self.cores.iter().enumerate() {
+                let power = self.profile.core_power(core, t);
+                state.cores[index] += power * dt;
+                package += power;
+            }
+            state.package += package * dt;
+            state.time += step;
+        }
+
+        (state.package, state.cores.clone())
+    }
+}
+
+/// What an MSR would read: whole energy units, wrapped around.
+fn counter(energy: f64) -> f64 {
+    (energy / ENERGY_UNIT).floor() * ENERGY_UNIT % ENERGY_RANGE
+}
+
+impl EnergyReader for Simulator {
+    fn name(&self) -> &'static str {
+        "simulated"
+    }
+
+    fn core_ids(&self) -> Vec<u32> {
+        self.cores.clone()
+    }
+
+    fn package_energy(&self) -> Result<f64> {
+        Ok(counter(self.advance().0))
+    }
+
+    fn core_energy(&self, core: u32) -> Result<f64> {
+        let index = self
+            .cores
+            .iter()
+            .position(|&other| other == core)
+            .ok_or_else(|| Error::parse("simulated core", core.to_string()))?;
+        Ok(counter(self.advance().1[index]))
+    }
+
+    fn package_energy_range(&self) -> Option<f64> {
+        Some(ENERGY_RANGE)
+    }
+
+    fn core_energy_range(&self) -> Option<f64> {
+        Some(ENERGY_RANGE)
+    }
+
+    fn energy_unit(&self) -> Option<f64> {
+        Some(ENERGY_UNIT)
+    }
+
+    fn package_power_limit(&self) -> Option<f64> {
+        // PPT of a stock 5800X.
+        Some(142.0)
+    }
+}
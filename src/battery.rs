@@ -0,0 +1,279 @@
+//! `--battery`: how much of the battery's drain the CPU package accounts
+//! for.
+//!
+//! A battery reports what it holds in `energy_now`, or as `charge_now` at
+//! `voltage_now`, and updates it only every few seconds up to a minute,
+//! depending on the firmware. The package energy between two updates is
+//! set against what the battery lost over the same time, for the run and
+//! update by update: how closely the two follow each other says whether the
+//! CPU drives the drain or the screen, the radios and the rest do.
+
+use std::{fs, path::Path, time::Duration};
+
+use crate::sysfs::Root;
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// Stretches between updates the correlation needs at least.
+pub const MIN_STEPS: usize = 3;
+
+/// The batteries of a machine, all of them together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub joules: f64,
+    /// Whether any of them is discharging.
+    pub discharging: bool,
+}
+
+/// Reads the batteries below `root`, `None` if there are none.
+pub fn read(root: &Root) -> Option<Reading> {
+    let supplies = fs::read_dir(root.path(POWER_SUPPLY)).ok()?;
+    let mut batteries = supplies
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|dir| attribute(dir, "type").as_deref() == Some("Battery"))
+        .filter_map(|dir| {
+            // µWh, or µAh at µV.
+            let joules = match number(&dir, "energy_now") {
+                Some(energy) => energy * 3600e-6,
+                None => number(&dir, "charge_now")? * number(&dir, "voltage_now")? * 3600e-12,
+            };
+            let discharging = attribute(&dir, "status").as_deref() == Some("Discharging");
+            Some(Reading {
+                joules,
+                discharging,
+            })
+        })
+        .peekable();
+    batteries.peek()?;
+    Some(batteries.fold(
+        Reading {
+            joules: 0.0,
+            discharging: false,
+        },
+        |total, battery| Reading {
+            joules: total.joules + battery.joules,
+            discharging: total.discharging || battery.discharging,
+        },
+    ))
+}
+
+/// A sysfs file of a power supply, without the newline.
+fn attribute(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|value| value.trim_end().to_owned())
+}
+
+fn number(dir: &Path, name: &str) -> Option<f64> {
+    attribute(dir, name)?.parse().ok()
+}
+
+/// From one update of the battery to the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    pub duration: Duration,
+    /// What the battery lost.
+    pub battery: f64,
+    /// What the package took.
+    pub package: f64,
+}
+
+/// The package energy of a run against the battery's drain.
+#[derive(Debug)]
+pub struct Drain {
+    root: Root,
+    /// What the battery held at its last update.
+    last: Option<f64>,
+    /// Whether `last` came from an update seen happen, the first reading
+    /// can be from any time since the one before.
+    aligned: bool,
+    /// The package energy and time since `last`.
+    pending: (f64, Duration),
+    pub steps: Vec<Step>,
+}
+
+impl Drain {
+    /// Compares with the batteries below `root`.
+    pub fn new(root: Root) -> Self {
+        let mut drain = Self {
+            root,
+            last: None,
+            aligned: false,
+            pending: (0.0, Duration::ZERO),
+            steps: Vec::new(),
+        };
+        drain.update(0.0, Duration::ZERO);
+        drain
+    }
+
+    /// Whether there is a battery that is discharging.
+    pub fn is_discharging(&self) -> bool {
+        self.last.is_some()
+    }
+
+    /// Starts over at the next update, after a suspend the battery lost
+    /// what the package didn't measure.
+    pub fn interrupt(&mut self) {
+        self.last = None;
+    }
+
+    /// Adds the package energy `joules` of the `window` since the last call.
+    pub fn update(&mut self, joules: f64, window: Duration) {
+        let Some(reading) = read(&self.root).filter(|reading| reading.discharging) else {
+            // Plugged in, what the battery gains says nothing about the CPU.
+            self.last = None;
+            return;
+        };
+        let Some(last) = self.last else {
+            (self.last, self.aligned, self.pending) =
+                (Some(reading.joules), false, (0.0, Duration::ZERO));
+            return;
+        };
+        self.pending.0 += joules;
+        self.pending.1 += window;
+        if reading.joules == last {
+            return;
+        }
+        // A battery recalibrating can report more than before.
+        if self.aligned && reading.joules < last {
+            self.steps.push(Step {
+                duration: self.pending.1,
+                battery: last - reading.joules,
+                package: self.pending.0,
+            });
+        }
+        (self.last, self.aligned, self.pending) =
+            (Some(reading.joules), true, (0.0, Duration::ZERO));
+    }
+
+    pub fn battery_joules(&self) -> f64 {
+        self.steps.iter().fold(0.0, |sum, step| sum + step.battery)
+    }
+
+    pub fn package_joules(&self) -> f64 {
+        self.steps.iter().fold(0.0, |sum, step| sum + step.package)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|step| step.duration).sum()
+    }
+
+    /// The package's share of the drain in percent, `None` before the
+    /// battery updated twice.
+    pub fn share(&self) -> Option<f64> {
+        let battery = self.battery_joules();
+        (battery > 0.0).then(|| self.package_joules() / battery * 100.0)
+    }
+
+    /// Pearson correlation of the package and battery power step by step,
+    /// near 1 when the drain follows the CPU. `None` below [`MIN_STEPS`] or
+    /// when either stayed exactly the same.
+    pub fn correlation(&self) -> Option<f64> {
+        let powers = self
+            .steps
+            .iter()
+            .filter(|step| !step.duration.is_zero())
+            .map(|step| {
+                let seconds = step.duration.as_secs_f64();
+                (step.package / seconds, step.battery / seconds)
+            })
+            .collect::<Vec<_>>();
+        if powers.len() < MIN_STEPS {
+            return None;
+        }
+        let len = powers.len() as f64;
+        let (package, battery) = powers
+            .iter()
+            .fold((0.0, 0.0), |(p, b), &(package, battery)| {
+                (p + package / len, b + battery / len)
+            });
+        let (mut covariance, mut package_variance, mut battery_variance) = (0.0, 0.0, 0.0);
+        for &(p, b) in &powers {
+            covariance += (p - package) * (b - battery);
+            package_variance += (p - package).powi(2);
+            battery_variance += (b - battery).powi(2);
+        }
+        let spread = (package_variance * battery_variance).sqrt();
+        (spread > 0.0).then(|| covariance / spread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    const BAT: &str = "/sys/class/power_supply/BAT0";
+
+    fn battery(fixture: &Fixture, watt_hours: f64, status: &str) {
+        fixture
+            .file(format!("{}/type", BAT), "Battery\n")
+            .file(
+                format!("{}/energy_now", BAT),
+                &format!("{}\n", (watt_hours * 1e6) as u64),
+            )
+            .file(format!("{}/status", BAT), &format!("{}\n", status));
+    }
+
+    #[test]
+    fn reads_charge_at_voltage() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/sys/class/power_supply/AC/type", "Mains\n")
+            .file(format!("{}/type", BAT), "Battery\n")
+            .file(format!("{}/charge_now", BAT), "2000000\n")
+            .file(format!("{}/voltage_now", BAT), "12000000\n")
+            .file(format!("{}/status", BAT), "Discharging\n");
+        let reading = read(fixture.root()).unwrap();
+        assert!((reading.joules - 2.0 * 12.0 * 3600.0).abs() < 1e-6);
+        assert!(reading.discharging);
+        assert_eq!(read(Fixture::new().root()), None);
+    }
+
+    #[test]
+    fn compares_drain_between_updates() {
+        let fixture = Fixture::new();
+        battery(&fixture, 50.0, "Discharging");
+        let mut drain = Drain::new(fixture.root().clone());
+        let second = Duration::from_secs(1);
+
+        // Until the first update, the time since the one before is unknown.
+        drain.update(10.0, second);
+        battery(&fixture, 49.99, "Discharging");
+        drain.update(10.0, second);
+        assert!(drain.steps.is_empty());
+
+        // 36 J lost over 3 s, 24 J of them in the package.
+        for _ in 0..2 {
+            drain.update(8.0, second);
+        }
+        battery(&fixture, 49.98, "Discharging");
+        drain.update(8.0, second);
+        assert_eq!(drain.steps.len(), 1);
+        assert!((drain.steps[0].battery - 36.0).abs() < 1e-6);
+        assert!((drain.share().unwrap() - 200.0 / 3.0).abs() < 1e-6);
+        assert_eq!(drain.correlation(), None);
+
+        // Charging ends the stretch.
+        battery(&fixture, 49.99, "Charging");
+        drain.update(8.0, second);
+        assert!(!drain.is_discharging());
+        assert_eq!(drain.steps.len(), 1);
+    }
+
+    #[test]
+    fn correlates_package_and_battery_power() {
+        let step = |package: f64, battery: f64| Step {
+            duration: Duration::from_secs(10),
+            battery,
+            package,
+        };
+        let drain = Drain {
+            steps: vec![step(50.0, 100.0), step(150.0, 200.0), step(100.0, 150.0)],
+            ..Drain::new(Fixture::new().root().clone())
+        };
+        assert!((drain.correlation().unwrap() - 1.0).abs() < 1e-9);
+        assert!((drain.share().unwrap() - 300.0 / 450.0 * 100.0).abs() < 1e-9);
+    }
+}
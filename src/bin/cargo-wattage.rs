@@ -0,0 +1,239 @@
+//! `cargo wattage`: measures the energy of a cargo invocation and compares it
+//! with a stored baseline.
+
+use std::{
+    env, fs, io,
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{self, Command, ExitStatus},
+    time::Duration,
+};
+
+use ryzen_wattage::{
+    i18n::{self, trf, Lang},
+    output::{self, TextOptions},
+    run,
+    state::{self, State},
+    sysfs::Root,
+    BackendKind, Cpu,
+};
+
+const USAGE: &str = "\
+Usage: cargo wattage [OPTIONS] <CARGO COMMAND> [ARGS]...
+
+Runs cargo with the given arguments and reports the energy it took. The first
+measurement of a command in a directory becomes its baseline, later runs are
+compared against it.
+
+Options:
+  -b, --backend <BACKEND>  Energy counter source: msr, powercap, auto [default: auto]
+      --set-baseline       Store this run as the new baseline
+      --gha                Also emit a GitHub Actions notice and job summary
+  -h, --help               Print this help
+";
+
+const USAGE_DE: &str = "\
+Aufruf: cargo wattage [OPTIONEN] <CARGO-BEFEHL> [ARGUMENTE]...
+
+Führt cargo mit den angegebenen Argumenten aus und gibt den Energiebedarf aus.
+Die erste Messung eines Befehls in einem Verzeichnis wird zur Referenz, spätere
+Läufe werden damit verglichen.
+
+Optionen:
+  -b, --backend <BACKEND>  Quelle der Energiezähler: msr, powercap, auto [Standard: auto]
+      --set-baseline       Diesen Lauf als neue Referenz speichern
+      --gha                Zusätzlich GitHub-Actions-Hinweis und Job-Zusammenfassung
+  -h, --help               Diese Hilfe anzeigen
+";
+
+fn usage() -> &'static str {
+    match i18n::lang() {
+        Lang::En => USAGE,
+        Lang::De => USAGE_DE,
+    }
+}
+
+/// Short enough that the package counter can't wrap twice in between.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+fn main() {
+    // Cargo runs us as `cargo-wattage wattage ARGS...`.
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("wattage") {
+        args.next();
+    }
+
+    let mut backend = BackendKind::Auto;
+    let mut set_baseline = false;
+    let mut gha = false;
+    let mut cargo_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" if cargo_args.is_empty() => {
+                print!("{}", usage());
+                return;
+            }
+            "-b" | "--backend" if cargo_args.is_empty() => {
+                let value = args.next().unwrap_or_default();
+                backend = value.parse().unwrap_or_else(|err: String| fail_usage(&err));
+            }
+            "--set-baseline" if cargo_args.is_empty() => set_baseline = true,
+            "--gha" if cargo_args.is_empty() => gha = true,
+            _ => cargo_args.push(arg),
+        }
+    }
+
+    if cargo_args.is_empty() {
+        fail_usage("missing cargo command");
+    }
+
+    let cpu = Cpu::new(backend).unwrap_or_else(|err| fail(&err.to_string()));
+    let calibration = State::load().calibration;
+
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command.args(&cargo_args);
+
+    let mut report =
+        run::run(&cpu, &mut command, INTERVAL).unwrap_or_else(|err| fail(&err.to_string()));
+    report.energy *= calibration.package;
+    report.average_power *= calibration.package;
+    report.peak_power *= calibration.package;
+
+    let mut shown = vec!["cargo".to_owned()];
+    shown.extend(cargo_args.iter().cloned());
+    eprint!(
+        "{}",
+        output::run_report(&shown, &report, &TextOptions::default())
+    );
+
+    if gha {
+        println!("{}", output::gha_notice(&shown, &report));
+        if let Err(err) = output::append_gha_summary(&shown, &report) {
+            eprintln!(
+                "cargo-wattage: warning: cannot write the job summary: {}",
+                err
+            );
+        }
+    }
+
+    // Failed builds usually stop early, they make no useful baseline.
+    if report.status.success() {
+        let key = baseline_key(&cargo_args);
+        let mut baselines = Baselines::load();
+
+        match baselines.get(&key) {
+            Some(baseline) if !set_baseline => {
+                let change = (report.energy - baseline) / baseline * 100.0;
+                eprintln!(
+                    "{}",
+                    trf(
+                        "Baseline: {}J, this run {}%",
+                        &[&format!("{:.2}", baseline), &format!("{:+.1}", change)]
+                    )
+                );
+            }
+            _ => {
+                baselines.set(key, report.energy);
+                match baselines.save() {
+                    Ok(()) => eprintln!(
+                        "{}",
+                        trf(
+                            "Stored {}J as the baseline",
+                            &[&format!("{:.2}", report.energy)]
+                        )
+                    ),
+                    Err(err) => {
+                        eprintln!("cargo-wattage: warning: cannot save baseline: {}", err)
+                    }
+                }
+            }
+        }
+    }
+
+    process::exit(exit_code(report.status));
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("cargo-wattage: error: {}", msg);
+    process::exit(1);
+}
+
+fn fail_usage(msg: &str) -> ! {
+    eprintln!("cargo-wattage: {}\n\n{}", msg, usage());
+    process::exit(2);
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// Baselines are per directory and exact command line.
+fn baseline_key(cargo_args: &[String]) -> String {
+    let dir = env::current_dir().unwrap_or_default();
+    format!("{}\t{}", dir.display(), cargo_args.join(" "))
+}
+
+/// Baseline energy in joules per command, one `joules<TAB>key` line each.
+/// Stored next to the machine state since energy is only comparable on the
+/// same machine.
+#[derive(Debug, Default)]
+struct Baselines {
+    entries: Vec<(String, f64)>,
+}
+
+impl Baselines {
+    fn path() -> Option<PathBuf> {
+        Some(state::state_dir()?.join(format!(
+            "{}.cargo-baselines",
+            state::machine_id(&Root::system())
+        )))
+    }
+
+    fn load() -> Self {
+        let contents = Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default();
+
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let (joules, key) = line.split_once('\t')?;
+                Some((key.to_owned(), joules.parse().ok()?))
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    fn get(&self, key: &str) -> Option<f64> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry == key)
+            .map(|&(_, joules)| joules)
+    }
+
+    fn set(&mut self, key: String, joules: f64) {
+        self.entries.retain(|(entry, _)| *entry != key);
+        self.entries.push((key, joules));
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let contents = self
+            .entries
+            .iter()
+            .map(|(key, joules)| format!("{}\t{}\n", joules, key))
+            .collect::<String>();
+        fs::write(path, contents)
+    }
+}
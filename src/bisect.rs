@@ -0,0 +1,72 @@
+//! `git bisect run` helper judging commits by the energy a command takes.
+
+use std::process::Command;
+
+use ryzen_wattage::{
+    i18n::{tr, trf},
+    output::{self, TextOptions},
+    run,
+    state::Calibration,
+    Cpu,
+};
+
+use crate::{args::Args, log};
+
+/// Exit codes `git bisect run` understands.
+pub const GOOD: i32 = 0;
+pub const BAD: i32 = 1;
+pub const SKIP: i32 = 125;
+/// Anything above 127 stops the bisection, for when measuring is broken
+/// rather than the commit.
+pub const ABORT: i32 = 128;
+
+/// Runs the command `args.runs` times and compares the median energy with
+/// the threshold, returning the exit code for `git bisect run`.
+pub fn bisect(cpu: &Cpu, args: &Args, calibration: &Calibration, options: &TextOptions) -> i32 {
+    let (Some(shell_command), Some(threshold)) = (&args.bisect_command, args.threshold_joules)
+    else {
+        log::error("bisect-helper needs --command and --threshold-j");
+        return ABORT;
+    };
+
+    let mut energies = Vec::new();
+    for _ in 0..args.runs {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(shell_command);
+
+        let mut report = match run::run(cpu, &mut command, args.interval) {
+            Ok(report) => report,
+            Err(err) => {
+                log::error(err);
+                return ABORT;
+            }
+        };
+        report.energy *= calibration.package;
+        report.average_power *= calibration.package;
+        report.peak_power *= calibration.package;
+        eprint!(
+            "{}",
+            output::run_report(std::slice::from_ref(shell_command), &report, options)
+        );
+
+        // A commit that doesn't build or run can't be judged by its energy.
+        if !report.status.success() {
+            log::warning(tr("skip: the command failed"));
+            return SKIP;
+        }
+        energies.push(report.energy);
+    }
+
+    energies.sort_by(f64::total_cmp);
+    let median = energies[energies.len() / 2];
+    let (verdict, code) = match median <= threshold {
+        true => ("good: {}J is within the {}J threshold", GOOD),
+        false => ("bad: {}J is above the {}J threshold", BAD),
+    };
+    log::notice(trf(
+        verdict,
+        &[&format!("{:.2}", median), &format!("{:.2}", threshold)],
+    ));
+
+    code
+}
@@ -0,0 +1,449 @@
+//! Energy of the processes on the machine summed by what they run in, for
+//! `--breakdown`: the container of each process with `container`, its
+//! systemd unit with `unit`, the virtual machine QEMU runs it as with `vm`.
+//!
+//! Windows of energy are split like `--track` splits them by name, see
+//! [`Splitter`]. Processes outside of what is broken
+//! down still count towards the whole, they just aren't listed.
+//!
+//! The container of a process is in its cgroup, whose path has the
+//! container's id in it with Docker, Podman, containerd and CRI-O alike.
+//! Names and images come from the Docker API on the runtime's socket, which
+//! Podman serves too. Containers no runtime answers for go by the first 12
+//! characters of their id, like `docker ps` shows them.
+//!
+//! Units are in the cgroup path as they are, in the slices they belong to:
+//! `/system.slice/sshd.service` is `sshd.service` in `system.slice`.
+//! Kernel threads sit in the root cgroup, which systemd calls `-.slice`.
+//!
+//! A guest's vCPUs are threads of its QEMU process, as are the emulator's
+//! own, so the whole process counts for the guest. Its name is the one given
+//! with `-name`, which libvirt sets to the domain's, `qemu-<PID>` without.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fmt, fs,
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    json::{self, Value},
+    process::{self, Process, Splitter},
+};
+
+/// Where Docker listens.
+pub const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+/// Where Podman's system service listens for root, users have theirs in
+/// `$XDG_RUNTIME_DIR/podman/podman.sock`.
+pub const PODMAN_SOCKET: &str = "/run/podman/podman.sock";
+
+/// How long a runtime gets to list its containers.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the energy is broken down by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Container,
+    Unit,
+    Vm,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Container => "container",
+            Self::Unit => "unit",
+            Self::Vm => "vm",
+        }
+    }
+
+    /// The label of [`Owner::detail`], like the image of a container.
+    pub fn detail(&self) -> Option<&'static str> {
+        match self {
+            Self::Container => Some("image"),
+            Self::Unit => Some("slice"),
+            Self::Vm => None,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "container" => Ok(Self::Container),
+            "unit" => Ok(Self::Unit),
+            "vm" => Ok(Self::Vm),
+            other => Err(format!(
+                "unknown breakdown `{}`, expected container, unit or vm",
+                other
+            )),
+        }
+    }
+}
+
+/// Whatever processes are counted under, like a container.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Owner {
+    pub name: String,
+    /// More about it, see [`Kind::detail`].
+    pub detail: Option<String>,
+}
+
+/// The running totals of a breakdown.
+#[derive(Debug)]
+pub struct Breakdown {
+    pub kind: Kind,
+    meter: Splitter,
+    containers: Containers,
+    /// Joules so far of each owner that used any CPU time.
+    pub joules: BTreeMap<Owner, f64>,
+    /// Power of each owner in the last window.
+    pub watts: BTreeMap<Owner, f64>,
+    /// Package energy so far.
+    pub total: f64,
+    pub duration: Duration,
+}
+
+impl Breakdown {
+    /// Starts counting now.
+    pub fn new(kind: Kind, meter: Splitter) -> Self {
+        Self {
+            kind,
+            meter,
+            containers: Containers::default(),
+            joules: BTreeMap::new(),
+            watts: BTreeMap::new(),
+            total: 0.0,
+            duration: Duration::ZERO,
+        }
+    }
+
+    /// Adds the shares of `joules`, the package energy of the `window`
+    /// since the last call, and `core_joules`, that of each core.
+    pub fn update(&mut self, joules: f64, core_joules: &BTreeMap<u32, f64>, window: Duration) {
+        let kind = self.kind;
+        let containers = &mut self.containers;
+        containers.asked = false;
+        let shares = self.meter.split(joules, core_joules, |process| {
+            owner(kind, containers, process)
+        });
+
+        let seconds = window.as_secs_f64();
+        self.watts = shares
+            .iter()
+            .filter(|_| seconds > 0.0)
+            .map(|(owner, joules)| (owner.clone(), joules / seconds))
+            .collect();
+        for (owner, joules) in shares {
+            *self.joules.entry(owner).or_default() += joules;
+        }
+        self.total += joules;
+        self.duration += window;
+    }
+
+    /// Owners and their joules so far, most energy first.
+    pub fn ranked(&self) -> Vec<(&Owner, f64)> {
+        let mut ranked = self
+            .joules
+            .iter()
+            .map(|(owner, &joules)| (owner, joules))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked
+    }
+}
+
+fn owner(kind: Kind, containers: &mut Containers, process: &Process) -> Option<Owner> {
+    match kind {
+        Kind::Container => {
+            let cgroup = process::cgroup(process.pid)?;
+            Some(containers.owner(container_id(&cgroup)?))
+        }
+        Kind::Unit => Some(unit(&process::cgroup(process.pid)?)),
+        // `qemu-system-x86_64` and `qemu-kvm` alike.
+        Kind::Vm if process.name.starts_with("qemu") => {
+            let cmdline = fs::read(format!("/proc/{}/cmdline", process.pid)).ok()?;
+            Some(Owner {
+                name: guest_name(&cmdline).unwrap_or_else(|| format!("qemu-{}", process.pid)),
+                detail: None,
+            })
+        }
+        Kind::Vm => None,
+    }
+}
+
+/// The name of the guest QEMU was started with, out of its NUL separated
+/// `cmdline`: `-name guest=win11,debug-threads=on` or just `-name win11`.
+pub fn guest_name(cmdline: &[u8]) -> Option<String> {
+    let mut args = cmdline
+        .split(|&byte| byte == 0)
+        .map(String::from_utf8_lossy);
+    args.find(|arg| arg == "-name" || arg == "--name")?;
+    let value = args.next()?;
+    // Options are split at commas, and a comma of the value is doubled.
+    let value = value.replace(",,", "\0");
+    value
+        .split(',')
+        .enumerate()
+        .find_map(|(index, option)| match option.split_once('=') {
+            Some(("guest", name)) => Some(name),
+            None if index == 0 => Some(option),
+            _ => None,
+        })
+        .filter(|name| !name.is_empty())
+        .map(|name| name.replace('\0', ","))
+}
+
+/// The systemd unit a cgroup path is of and the slice it is in, the
+/// innermost of each.
+pub fn unit(cgroup: &str) -> Owner {
+    const ROOT: &str = "-.slice";
+    // Below a unit there can be cgroups of the service's own, like the
+    // `container` of a Podman container.
+    let units = cgroup
+        .split('/')
+        .filter(|segment| UNIT_TYPES.iter().any(|kind| segment.ends_with(kind)))
+        .collect::<Vec<_>>();
+    let Some(&name) = units.last() else {
+        return Owner {
+            name: ROOT.to_owned(),
+            detail: None,
+        };
+    };
+    let slice = units[..units.len() - 1]
+        .iter()
+        .rfind(|unit| unit.ends_with(".slice"))
+        .unwrap_or(&ROOT);
+    Owner {
+        name: name.to_owned(),
+        detail: (!name.ends_with(".slice")).then(|| slice.to_string()),
+    }
+}
+
+/// The types of units that have cgroups.
+const UNIT_TYPES: [&str; 6] = [".service", ".scope", ".slice", ".socket", ".mount", ".swap"];
+
+/// The id of the container a cgroup path is of, like the `0123…` of
+/// `/system.slice/docker-0123….scope` or `/docker/0123…`.
+pub fn container_id(cgroup: &str) -> Option<&str> {
+    cgroup.rsplit('/').find_map(|segment| {
+        let unit = segment.strip_suffix(".scope").unwrap_or(segment);
+        // `docker-`, `libpod-`, `libpod-conmon-`, `crio-`, `cri-containerd-`
+        let id = unit.rsplit('-').next()?;
+        (id.len() == 64 && id.bytes().all(|byte| byte.is_ascii_hexdigit())).then_some(id)
+    })
+}
+
+/// Names and images of the containers the runtimes know of, by id.
+#[derive(Debug, Default)]
+struct Containers {
+    known: HashMap<String, Owner>,
+    /// Whether the runtimes were asked in this window already, so
+    /// containers none of them knows of cost one round of requests a window
+    /// rather than one each.
+    asked: bool,
+}
+
+impl Containers {
+    fn owner(&mut self, id: &str) -> Owner {
+        if !self.known.contains_key(id) && !self.asked {
+            self.asked = true;
+            for socket in sockets() {
+                if let Ok(containers) = list(&socket) {
+                    self.known.extend(containers);
+                }
+            }
+        }
+        self.known.get(id).cloned().unwrap_or_else(|| Owner {
+            name: id[..12].to_owned(),
+            detail: None,
+        })
+    }
+}
+
+/// The sockets of Docker and of root's and this user's Podman.
+fn sockets() -> Vec<PathBuf> {
+    let mut sockets = vec![PathBuf::from(DOCKER_SOCKET), PathBuf::from(PODMAN_SOCKET)];
+    if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+        sockets.push(PathBuf::from(runtime_dir).join("podman/podman.sock"));
+    }
+    sockets
+}
+
+/// The running containers of the runtime at `socket`.
+fn list(socket: &Path) -> io::Result<Vec<(String, Owner)>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    // HTTP/1.0 for a body that ends with the connection rather than in
+    // chunks.
+    stream.write_all(b"GET /containers/json HTTP/1.0\r\nHost: localhost\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    parse_containers(&response).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected answer from {}", socket.display()),
+        )
+    })
+}
+
+/// The containers in the answer to `GET /containers/json`.
+fn parse_containers(response: &str) -> Option<Vec<(String, Owner)>> {
+    let (head, body) = response.split_once("\r\n\r\n")?;
+    if head.split_whitespace().nth(1) != Some("200") {
+        return None;
+    }
+    let containers = json::parse(body).ok()?;
+    let containers = containers
+        .as_array()?
+        .iter()
+        .filter_map(|container| {
+            let id = container.get("Id")?.as_str()?;
+            let name = container
+                .get("Names")
+                .and_then(Value::as_array)
+                .and_then(|names| names.first()?.as_str())
+                .map_or_else(
+                    || id[..id.len().min(12)].to_owned(),
+                    |name| name.trim_start_matches('/').to_owned(),
+                );
+            let image = container.get("Image").and_then(Value::as_str);
+            Some((
+                id.to_owned(),
+                Owner {
+                    name,
+                    detail: image.map(str::to_owned),
+                },
+            ))
+        })
+        .collect();
+    Some(containers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "4f1a6c3e9b2d8a7f0c5e1b3d9a8f7e6c5d4b3a2f1e0d9c8b7a6f5e4d3c2b1a0f";
+
+    #[test]
+    fn finds_container_ids_in_cgroups() {
+        for cgroup in [
+            format!("/system.slice/docker-{}.scope", ID),
+            format!("/docker/{}", ID),
+            format!("/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-{}.scope/container", ID),
+            format!("/machine.slice/libpod-conmon-{}.scope", ID),
+            format!("/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod1234.slice/cri-containerd-{}.scope", ID),
+        ] {
+            assert_eq!(container_id(&cgroup), Some(ID), "{}", cgroup);
+        }
+        assert_eq!(container_id("/system.slice/sshd.service"), None);
+        assert_eq!(container_id("/user.slice/user-1000.slice"), None);
+    }
+
+    #[test]
+    fn reads_the_containers_the_runtime_lists() {
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n\
+             [{{\"Id\":\"{}\",\"Names\":[\"/web\"],\"Image\":\"nginx:1.27\"}},\
+             {{\"Id\":\"abc\",\"Names\":[]}}]\n",
+            ID
+        );
+        assert_eq!(
+            parse_containers(&response).unwrap(),
+            [
+                (
+                    ID.to_owned(),
+                    Owner {
+                        name: "web".to_owned(),
+                        detail: Some("nginx:1.27".to_owned()),
+                    }
+                ),
+                (
+                    "abc".to_owned(),
+                    Owner {
+                        name: "abc".to_owned(),
+                        detail: None,
+                    }
+                ),
+            ]
+        );
+        assert!(parse_containers("HTTP/1.1 500 Internal Server Error\r\n\r\n[]").is_none());
+    }
+
+    #[test]
+    fn names_unknown_containers_by_their_short_id() {
+        let mut containers = Containers {
+            asked: true,
+            ..Containers::default()
+        };
+        assert_eq!(containers.owner(ID).name, &ID[..12]);
+        assert!("vms".parse::<Kind>().is_err());
+    }
+
+    #[test]
+    fn finds_units_and_their_slices() {
+        let unit = |cgroup: &str| {
+            let owner = super::unit(cgroup);
+            (owner.name, owner.detail)
+        };
+        let owner = |name: &str, slice: Option<&str>| (name.to_owned(), slice.map(str::to_owned));
+        assert_eq!(
+            unit("/system.slice/sshd.service"),
+            owner("sshd.service", Some("system.slice"))
+        );
+        assert_eq!(
+            unit("/user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox-4711.scope"),
+            owner("app-firefox-4711.scope", Some("app.slice"))
+        );
+        assert_eq!(
+            unit(&format!("/machine.slice/libpod-{}.scope/container", ID)),
+            owner(&format!("libpod-{}.scope", ID), Some("machine.slice"))
+        );
+        assert_eq!(unit("/init.scope"), owner("init.scope", Some("-.slice")));
+        assert_eq!(unit("/user.slice"), owner("user.slice", None));
+        assert_eq!(unit("/"), owner("-.slice", None));
+    }
+
+    #[test]
+    fn names_guests_like_qemu_was_told() {
+        let cmdline = |args: &[&str]| args.join("\0").into_bytes();
+        assert_eq!(
+            guest_name(&cmdline(&[
+                "/usr/bin/qemu-system-x86_64",
+                "-name",
+                "guest=win11,debug-threads=on",
+                "-S",
+            ]))
+            .as_deref(),
+            Some("win11")
+        );
+        assert_eq!(
+            guest_name(&cmdline(&[
+                "qemu-kvm",
+                "-name",
+                "build,,ci,process=qemu-ci"
+            ]))
+            .as_deref(),
+            Some("build,ci")
+        );
+        assert_eq!(
+            guest_name(&cmdline(&["qemu-kvm", "-name", "process=qemu-ci"])),
+            None
+        );
+        assert_eq!(guest_name(&cmdline(&["qemu-kvm", "-m", "4G"])), None);
+    }
+}
@@ -0,0 +1,342 @@
+//! `debug export-report`: what a maintainer needs to reproduce a
+//! platform-specific bug, bundled into one tar archive to attach to an issue.
+//!
+//! The archive only holds plain text files, so users can check what they
+//! share. The hostname, user name and home directory are masked in all of
+//! them before anything is written.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    backend::BackendKind,
+    cpu::read_sysfs,
+    cpuinfo::CpuInfo,
+    crosscheck, output,
+    quirks::{ModelLimits, Quirks},
+    sysfs::Root,
+    topology, Cpu, Error, Result,
+};
+
+/// Directory the files are unpacked into.
+const ROOT: &str = "ryzen-wattage-report";
+/// Counter readings in the raw capture.
+const CAPTURE_READINGS: u32 = 11;
+
+/// `ryzen-wattage-report-<unix time>.tar` in the working directory.
+pub fn default_path() -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    PathBuf::from(format!("{}-{}.tar", ROOT, now.as_secs()))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// File names and their contents.
+    files: Vec<(String, String)>,
+}
+
+impl Report {
+    /// Collects everything, taking about two `interval`s for the capture and
+    /// the cross-check. `cpu` is what the tool would measure with, a failure
+    /// to open it is worth a report too.
+    pub fn collect(
+        info: &CpuInfo,
+        quirks: &Quirks,
+        cpu: std::result::Result<&Cpu, &Error>,
+        interval: Duration,
+    ) -> Self {
+        let mut report = Self::default();
+
+        report.add("system.txt", system(info));
+        report.add("cpuinfo.txt", first_cpuinfo_block());
+        report.add("topology.txt", topology_files());
+        report.add("quirks.txt", output::quirks(info, quirks));
+        report.add("backends.txt", backends(cpu, interval));
+        if let Ok(cpu) = cpu {
+            report.add("capture.csv", capture(cpu, interval));
+        }
+
+        report.sanitize(&private_strings());
+        report
+    }
+
+    pub fn add(&mut self, name: &str, contents: String) {
+        self.files.push((name.to_owned(), contents));
+    }
+
+    pub fn files(&self) -> &[(String, String)] {
+        &self.files
+    }
+
+    /// Replaces every occurrence of the given strings with their
+    /// placeholders, longest first so a user name doesn't break up the home
+    /// directory containing it.
+    pub fn sanitize(&mut self, private: &[(String, &str)]) {
+        let mut private = private
+            .iter()
+            // Masking `a` or `pc` would mangle everything else.
+            .filter(|(value, _)| value.len() >= 3)
+            .collect::<Vec<_>>();
+        private.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+
+        for (_, contents) in &mut self.files {
+            for (value, placeholder) in &private {
+                *contents = contents.replace(value.as_str(), placeholder);
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = fs::File::create(path).map_err(|err| Error::io(path, err))?;
+        let mut out = io::BufWriter::new(file);
+        self.write_tar(&mut out)
+            .and_then(|_| out.flush())
+            .map_err(|err| Error::io(path, err))
+    }
+
+    /// A ustar archive with all files in [`ROOT`].
+    pub fn write_tar(&self, out: &mut impl Write) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        for (name, contents) in &self.files {
+            let contents = contents.as_bytes();
+            out.write_all(&tar_header(
+                &format!("{}/{}", ROOT, name),
+                contents.len(),
+                mtime,
+            ))?;
+            out.write_all(contents)?;
+            out.write_all(&vec![0; padding(contents.len())])?;
+        }
+
+        // Two empty blocks end the archive.
+        out.write_all(&[0; 1024])
+    }
+}
+
+fn padding(len: usize) -> usize {
+    (512 - len % 512) % 512
+}
+
+fn tar_header(name: &str, size: usize, mtime: u64) -> [u8; 512] {
+    let mut header = [0; 512];
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+
+    // Our names are short and ASCII, well within the 100 bytes.
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, format!("{:011o}\0", mtime).as_bytes());
+    // The checksum is computed with its own field set to spaces.
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    let checksum = header.iter().map(|&b| u32::from(b)).sum::<u32>();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+/// Hostname, user name and home directory with their placeholders.
+fn private_strings() -> Vec<(String, &'static str)> {
+    let mut private = vec![(output::hostname(), "<hostname>")];
+    for (var, placeholder) in [
+        ("HOME", "<home>"),
+        ("USER", "<user>"),
+        ("LOGNAME", "<user>"),
+    ] {
+        if let Ok(value) = env::var(var) {
+            private.push((value, placeholder));
+        }
+    }
+    private
+}
+
+fn system(info: &CpuInfo) -> String {
+    let mut out = String::new();
+    let kernel = read_sysfs("/proc/sys/kernel/osrelease").unwrap_or_else(|err| err.to_string());
+
+    writeln!(out, "ryzen-wattage {}", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out, "kernel: {}", kernel).unwrap();
+    writeln!(out, "architecture: {}", env::consts::ARCH).unwrap();
+    writeln!(
+        out,
+        "cpu: {} {} (family {:#x}, model {:#x})",
+        info.vendor, info.model_name, info.family, info.model
+    )
+    .unwrap();
+    match ModelLimits::detect(info) {
+        Some(limits) => writeln!(
+            out,
+            "model limits: package power {}-{}W, temperature {}-{}°C",
+            limits.package_power.0,
+            limits.package_power.1,
+            limits.temperature.0,
+            limits.temperature.1
+        )
+        .unwrap(),
+        None => writeln!(out, "model limits: unknown model").unwrap(),
+    }
+
+    out
+}
+
+/// The entry of the first CPU, with the microcode revision and flags. The
+/// others only differ in their ids and current frequency.
+fn first_cpuinfo_block() -> String {
+    match fs::read_to_string("/proc/cpuinfo") {
+        Ok(cpuinfo) => cpuinfo.split("\n\n").next().unwrap_or_default().to_owned() + "\n",
+        Err(err) => format!("cannot read /proc/cpuinfo: {}\n", err),
+    }
+}
+
+/// The sysfs files topology and chiplet grouping are derived from, as is.
+fn topology_files() -> String {
+    let mut out = String::new();
+    let mut file = |path: String| {
+        let value = read_sysfs(&path).unwrap_or_else(|err| format!("<{}>", err));
+        writeln!(out, "{}: {}", path, value).unwrap();
+    };
+
+    file("/sys/devices/system/cpu/online".to_owned());
+    file("/sys/devices/system/cpu/smt/control".to_owned());
+
+    let online = read_sysfs("/sys/devices/system/cpu/online")
+        .ok()
+        .and_then(|online| topology::parse_cpulist(&online))
+        .unwrap_or_default();
+    for cpu in online {
+        let dir = format!("/sys/devices/system/cpu/cpu{}", cpu);
+        file(format!("{}/topology/core_cpus_list", dir));
+        file(format!("{}/topology/physical_package_id", dir));
+        file(format!("{}/topology/die_id", dir));
+        file(format!("{}/cache/index3/shared_cpu_list", dir));
+    }
+    if let Ok(nodes) = topology::numa_nodes(&Root::system()) {
+        for node in nodes.keys() {
+            file(format!("/sys/devices/system/node/node{}/cpulist", node));
+        }
+    }
+
+    out
+}
+
+/// Whether every backend opens and why not, how the selected one resolves
+/// energy, and how the sources compare.
+fn backends(cpu: std::result::Result<&Cpu, &Error>, interval: Duration) -> String {
+    let mut out = String::new();
+
+    for path in ["/dev/cpu/0/msr", "/sys/module/msr", "/sys/class/powercap"] {
+        let state = match fs::metadata(path) {
+            Ok(meta) => format!("mode {:o}", meta.permissions().mode() & 0o7777),
+            Err(err) => err.to_string(),
+        };
+        writeln!(out, "{}: {}", path, state).unwrap();
+    }
+    if let Ok(zones) = fs::read_dir("/sys/class/powercap") {
+        let mut zones = zones
+            .flatten()
+            .map(|zone| {
+                let name = read_sysfs(zone.path().join("name")).unwrap_or_default();
+                format!("{} ({})", zone.file_name().to_string_lossy(), name)
+            })
+            .collect::<Vec<_>>();
+        zones.sort();
+        writeln!(out, "powercap zones: {}", zones.join(", ")).unwrap();
+    }
+    writeln!(out).unwrap();
+
+    for kind in [BackendKind::Msr, BackendKind::Powercap] {
+        match Cpu::new(kind) {
+            Ok(cpu) => writeln!(
+                out,
+                "{}: ok, counters for cores {:?}",
+                kind.name(),
+                cpu.core_ids()
+            ),
+            Err(err) => writeln!(out, "{}: {}", kind.name(), err),
+        }
+        .unwrap();
+    }
+    writeln!(out).unwrap();
+
+    match cpu {
+        Ok(cpu) => {
+            writeln!(out, "selected:").unwrap();
+            out += &output::resolution(cpu, interval, &Default::default());
+        }
+        Err(err) => writeln!(out, "selected: {}", err).unwrap(),
+    }
+    writeln!(out).unwrap();
+
+    writeln!(out, "cross-check:").unwrap();
+    out += &output::cross_check(&crosscheck::cross_check(interval));
+
+    out
+}
+
+/// Raw counter readings spread over `interval`, before any wraparound
+/// handling or calibration.
+fn capture(cpu: &Cpu, interval: Duration) -> String {
+    let mut out = String::new();
+    let step = interval / (CAPTURE_READINGS - 1);
+    let mut start = None;
+
+    for reading in 0..CAPTURE_READINGS {
+        if reading > 0 {
+            thread::sleep(step);
+        }
+        let snapshot = match cpu.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                writeln!(out, "# {}", err).unwrap();
+                continue;
+            }
+        };
+
+        if start.is_none() {
+            write!(out, "elapsed_s,package_j").unwrap();
+            for core in snapshot.cores.keys() {
+                write!(out, ",core{}_j", core).unwrap();
+            }
+            for domain in snapshot.domains.keys() {
+                write!(out, ",{}_j", domain).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+
+        let (package, time) = snapshot.package;
+        let start = *start.get_or_insert(time);
+        write!(
+            out,
+            "{:.6},{}",
+            time.duration_since(start).as_secs_f64(),
+            package
+        )
+        .unwrap();
+        for (energy, _) in snapshot.cores.values().chain(snapshot.domains.values()) {
+            write!(out, ",{}", energy).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+
+    out
+}
@@ -0,0 +1,371 @@
+//! `--dbus`: the readings as a D-Bus service, for desktop apps that would
+//! rather read a property than talk to the daemon's socket.
+//!
+//! Two processes make it up. The system service is the `--daemon` running
+//! as root with `--dbus system`: it alone reads the hardware, and owns
+//! [`NAME`] on the system bus, which
+//! `data/io.github.valeth.RyzenWattage.conf` in `/usr/share/dbus-1/system.d`
+//! lets it do and everyone call. The session proxy is
+//! `--client --watch --dbus session`, run per user: it subscribes to the
+//! daemon's socket, keeps the latest reading and owns [`NAME`] on the
+//! session bus, so apps in the session get their answers from a process
+//! of their own user, without a round trip to the daemon or a polkit
+//! prompt. `data/io.github.valeth.RyzenWattage.service` in
+//! `/usr/share/dbus-1/services` starts it the first time an app asks.
+//!
+//! Both serve the same object at [`PATH`]: the read-only properties of
+//! [`INTERFACE`], `PackageWatts`, `CoresWatts` (NaN without per-core
+//! counters) and `Timestamp`, announced with `PropertiesChanged` after
+//! every sample, and `Latest()`, the whole sample as `--format json`
+//! prints it.
+
+use std::{
+    fmt, io,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    client::RemoteSample,
+    dbus::{Connection, Kind, Message, Sender, Writer},
+    metrics::METRICS,
+    output::{self, Sample},
+    timefmt,
+};
+
+pub const NAME: &str = "io.github.valeth.RyzenWattage";
+pub const PATH: &str = "/io/github/valeth/RyzenWattage";
+pub const INTERFACE: &str = "io.github.valeth.RyzenWattage1";
+
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER: &str = "org.freedesktop.DBus.Peer";
+
+/// `RequestName` flag: fail instead of waiting for the name.
+const DO_NOT_QUEUE: u32 = 4;
+/// `RequestName` reply: the name is ours.
+const PRIMARY_OWNER: u32 = 1;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.github.valeth.RyzenWattage1">
+    <property name="PackageWatts" type="d" access="read"/>
+    <property name="CoresWatts" type="d" access="read"/>
+    <property name="Timestamp" type="s" access="read"/>
+    <method name="Latest">
+      <arg name="sample" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface" type="s"/>
+      <arg name="changed" type="a{sv}"/>
+      <arg name="invalidated" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+/// `--dbus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    System,
+    Session,
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(Self::System),
+            "session" => Ok(Self::Session),
+            other => Err(format!(
+                "unknown bus `{}`, expected system or session",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::System => "system",
+            Self::Session => "session",
+        })
+    }
+}
+
+/// What the properties say.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub timestamp: String,
+    pub package_watts: f64,
+    pub cores_watts: f64,
+    /// For `Latest()`.
+    pub json: String,
+}
+
+impl Reading {
+    pub fn new(sample: &Sample) -> Self {
+        Self {
+            timestamp: timefmt::machine(sample.timestamp),
+            package_watts: sample.package_power,
+            cores_watts: match sample.core_power.is_empty() {
+                true => f64::NAN,
+                false => sample.cores_total_power,
+            },
+            json: output::json(sample),
+        }
+    }
+
+    /// One the daemon sent.
+    pub fn remote(sample: &RemoteSample) -> Self {
+        let cores = METRICS
+            .iter()
+            .find(|metric| metric.name == "cores_total_watts")
+            .and_then(|metric| sample.values(metric).first().map(|(_, watts)| *watts));
+        Self {
+            timestamp: sample.timestamp().unwrap_or_default().to_owned(),
+            package_watts: sample.package_power().unwrap_or(f64::NAN),
+            cores_watts: cores.unwrap_or(f64::NAN),
+            json: sample.to_json(),
+        }
+    }
+
+    /// The properties as `a{sv}`.
+    fn properties(&self, dict: &mut Writer) {
+        dict.array(8, |entries| {
+            for name in ["PackageWatts", "CoresWatts", "Timestamp"] {
+                entries.structure(|entry| {
+                    entry.string(name);
+                    self.property(name, entry);
+                });
+            }
+        });
+    }
+
+    /// The variant of the property `name`, false if there is none.
+    fn property(&self, name: &str, out: &mut Writer) -> bool {
+        match name {
+            "PackageWatts" => out.variant("d", |v| v.f64(self.package_watts)),
+            "CoresWatts" => out.variant("d", |v| v.f64(self.cores_watts)),
+            "Timestamp" => out.variant("s", |v| v.string(&self.timestamp)),
+            _ => return false,
+        };
+        true
+    }
+}
+
+/// The service, answering on a thread of its own.
+#[derive(Debug)]
+pub struct Service {
+    sender: Sender,
+    latest: Arc<Mutex<Option<Reading>>>,
+}
+
+impl Service {
+    /// Takes [`NAME`] on `bus` and starts answering.
+    pub fn start(bus: Bus) -> io::Result<Self> {
+        let mut connection = match bus {
+            Bus::System => Connection::system()?,
+            Bus::Session => Connection::session()?,
+        };
+        let mut args = Writer::new();
+        args.string(NAME).u32(DO_NOT_QUEUE);
+        let reply = connection.call(Message::method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            ("org.freedesktop.DBus", "RequestName"),
+            "su",
+            args,
+        ))?;
+        if reply.reader().u32() != Some(PRIMARY_OWNER) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is taken on the {} bus", NAME, bus),
+            ));
+        }
+        connection.set_timeout(None)?;
+
+        let latest = Arc::new(Mutex::new(None));
+        let service = Self {
+            sender: connection.sender(),
+            latest: Arc::clone(&latest),
+        };
+        thread::spawn(move || serve(connection, &latest));
+        Ok(service)
+    }
+
+    /// Makes `reading` the latest and announces it.
+    pub fn publish(&self, reading: Reading) -> io::Result<()> {
+        let mut args = Writer::new();
+        args.string(INTERFACE);
+        reading.properties(&mut args);
+        args.array(4, |_| {});
+        *self.latest.lock().unwrap() = Some(reading);
+        self.sender.send(Message::signal(
+            PATH,
+            (PROPERTIES, "PropertiesChanged"),
+            "sa{sv}as",
+            args,
+        ))?;
+        Ok(())
+    }
+}
+
+/// Answers method calls until the bus goes away.
+fn serve(mut connection: Connection, latest: &Mutex<Option<Reading>>) {
+    while let Ok(call) = connection.receive() {
+        if call.kind != Kind::MethodCall {
+            continue;
+        }
+        let reply = answer(&call, latest.lock().unwrap().as_ref());
+        if connection.send(reply).is_err() {
+            break;
+        }
+    }
+}
+
+fn answer(call: &Message, latest: Option<&Reading>) -> Message {
+    if call.path.as_deref() != Some(PATH) {
+        return Message::error(
+            call,
+            "org.freedesktop.DBus.Error.UnknownObject",
+            &format!("no object at {}", call.path.as_deref().unwrap_or("")),
+        );
+    }
+    let no_sample = || Message::error(call, "org.freedesktop.DBus.Error.Failed", "no sample yet");
+
+    let mut body = Writer::new();
+    let mut args = call.reader();
+    match (
+        call.interface.as_deref(),
+        call.member.as_deref().unwrap_or(""),
+    ) {
+        (Some(INTROSPECTABLE) | None, "Introspect") => {
+            body.string(INTROSPECTION);
+            Message::method_return(call, "s", body)
+        }
+        (Some(PEER) | None, "Ping") => Message::method_return(call, "", body),
+        (Some(INTERFACE) | None, "Latest") => match latest {
+            Some(reading) => {
+                body.string(&reading.json);
+                Message::method_return(call, "s", body)
+            }
+            None => no_sample(),
+        },
+        (Some(PROPERTIES) | None, member @ ("Get" | "GetAll")) => {
+            let interface = args.string().unwrap_or_default();
+            if interface != INTERFACE && !interface.is_empty() {
+                return Message::error(
+                    call,
+                    "org.freedesktop.DBus.Error.UnknownInterface",
+                    &format!("no interface {}", interface),
+                );
+            }
+            let Some(reading) = latest else {
+                return no_sample();
+            };
+            match member {
+                "GetAll" => {
+                    reading.properties(&mut body);
+                    Message::method_return(call, "a{sv}", body)
+                }
+                _ => {
+                    let name = args.string().unwrap_or_default();
+                    match reading.property(&name, &mut body) {
+                        true => Message::method_return(call, "v", body),
+                        false => Message::error(
+                            call,
+                            "org.freedesktop.DBus.Error.UnknownProperty",
+                            &format!("no property {}", name),
+                        ),
+                    }
+                }
+            }
+        }
+        (Some(PROPERTIES), "Set") => Message::error(
+            call,
+            "org.freedesktop.DBus.Error.PropertyReadOnly",
+            "the properties are read-only",
+        ),
+        _ => Message::error(
+            call,
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            &format!(
+                "no method {}.{}",
+                call.interface.as_deref().unwrap_or(""),
+                call.member.as_deref().unwrap_or("")
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(interface: &str, member: &str, signature: &str, body: Writer) -> Message {
+        Message {
+            sender: Some(":1.7".to_owned()),
+            serial: 9,
+            ..Message::method_call(NAME, PATH, (interface, member), signature, body)
+        }
+    }
+
+    #[test]
+    fn answers_property_requests() {
+        let reading = Reading::new(&Sample {
+            package_power: 42.5,
+            ..Sample::default()
+        });
+
+        let mut args = Writer::new();
+        args.string(INTERFACE).string("PackageWatts");
+        let reply = answer(&call(PROPERTIES, "Get", "ss", args), Some(&reading));
+        assert_eq!(reply.kind, Kind::MethodReturn);
+        assert_eq!(
+            (reply.reply_serial, reply.destination.as_deref()),
+            (Some(9), Some(":1.7"))
+        );
+        let mut value = reply.reader();
+        assert_eq!(value.signature().as_deref(), Some("d"));
+
+        let mut args = Writer::new();
+        args.string(INTERFACE).string("Frequency");
+        let reply = answer(&call(PROPERTIES, "Get", "ss", args), Some(&reading));
+        assert_eq!(
+            reply.error_name.as_deref(),
+            Some("org.freedesktop.DBus.Error.UnknownProperty")
+        );
+
+        let reply = answer(&call(INTERFACE, "Latest", "", Writer::new()), None);
+        assert_eq!(reply.kind, Kind::Error);
+        let reply = answer(
+            &call(INTERFACE, "Latest", "", Writer::new()),
+            Some(&reading),
+        );
+        assert_eq!(reply.reader().string(), Some(reading.json.clone()));
+    }
+}
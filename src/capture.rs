@@ -0,0 +1,206 @@
+//! `--capture-on`: a firehose capture of every run of some programs, next
+//! to sampling as usual.
+//!
+//! A capture starts when a process with one of the names runs `exec` and
+//! stops when it exits, into `<name>-<time>-<pid>.firehose` in the capture
+//! directory with the name as the tag in its header. Each one reads its own
+//! counters on a thread of its own, a nightly job running twice at once
+//! gets two files.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use ryzen_wattage::{
+    firehose::{self, Capture},
+    i18n::trf,
+    process,
+    procwatch::{Event, Watcher},
+    record::Header,
+    state,
+    timefmt::{self, Zone},
+    Cpu, Result,
+};
+
+use crate::log;
+
+/// How often exits the watcher may have missed are looked for.
+const POLL: Duration = Duration::from_millis(500);
+
+/// `<state dir>/captures`.
+pub fn default_dir() -> Option<PathBuf> {
+    Some(state::state_dir()?.join("captures"))
+}
+
+/// What to capture, and how.
+pub struct Options {
+    pub names: Vec<String>,
+    pub dir: PathBuf,
+    pub interval: Duration,
+    /// The header of every capture, with its own start and tag.
+    pub header: Header,
+}
+
+/// The watcher thread, which stops the captures when it stops.
+#[derive(Debug)]
+pub struct Watching {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+struct Run {
+    name: String,
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Capture>>,
+}
+
+impl Watching {
+    /// Watches for `options.names` starting, capturing from the counters
+    /// `open` returns for each run.
+    pub fn spawn(options: Options, open: impl Fn() -> Result<Cpu> + Send + Sync + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = thread::spawn(move || watch(options, Arc::new(open), &stopped));
+        Self { stop, thread }
+    }
+
+    /// Stops every capture and waits for them to be written.
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+    }
+}
+
+fn watch(options: Options, open: Arc<dyn Fn() -> Result<Cpu> + Send + Sync>, stop: &AtomicBool) {
+    if let Err(err) = fs::create_dir_all(&options.dir) {
+        log::error(format_args!(
+            "cannot create {}: {}",
+            options.dir.display(),
+            err
+        ));
+        return;
+    }
+    let mut watcher = Watcher::open();
+    if watcher.is_polling() {
+        log::warning(trf(
+            "no access to process events, looking for {} every {}ms instead",
+            &[&options.names.join(", "), &POLL.as_millis()],
+        ));
+    }
+
+    let mut runs = HashMap::new();
+    while !stop.load(Ordering::SeqCst) {
+        let events = watcher.next(POLL).unwrap_or_else(|err| {
+            log::warning(format_args!("cannot read process events: {}", err));
+            Vec::new()
+        });
+        for event in events {
+            match event {
+                Event::Started { pid, name } if !runs.contains_key(&pid) => {
+                    let Some(watched) = options
+                        .names
+                        .iter()
+                        .find(|watched| process::is_named(&name, watched))
+                    else {
+                        continue;
+                    };
+                    runs.insert(pid, start(&options, &open, watched, pid));
+                }
+                Event::Started { .. } => {}
+                Event::Exited { pid } => {
+                    if let Some(run) = runs.remove(&pid) {
+                        finish(run);
+                    }
+                }
+            }
+        }
+        // Exits the connector dropped when its buffer was full.
+        let gone = runs
+            .keys()
+            .filter(|pid| !Path::new(&format!("/proc/{}", pid)).exists())
+            .copied()
+            .collect::<Vec<_>>();
+        for pid in gone {
+            finish(runs.remove(&pid).expect("a running capture"));
+        }
+    }
+    for (_, run) in runs {
+        finish(run);
+    }
+}
+
+fn start(
+    options: &Options,
+    open: &Arc<dyn Fn() -> Result<Cpu> + Send + Sync>,
+    name: &str,
+    pid: u32,
+) -> Run {
+    let now = SystemTime::now();
+    let time = timefmt::format(now, &Zone::local(), "%Y%m%d-%H%M%S").expect("valid time format");
+    let path = options
+        .dir
+        .join(format!("{}-{}-{}.firehose", file_name(name), time, pid));
+    log::info(format_args!(
+        "{} started as {}, capturing into {}",
+        name,
+        pid,
+        path.display()
+    ));
+
+    let header = Header {
+        start: now,
+        tag: Some(name.to_owned()),
+        ..options.header.clone()
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let (open, path, stop) = (Arc::clone(open), path.clone(), Arc::clone(&stop));
+        let interval = options.interval;
+        thread::spawn(move || {
+            let cpu = open()?;
+            firehose::capture(&cpu, &path, &header, interval, |_, _| {
+                stop.load(Ordering::SeqCst)
+            })
+        })
+    };
+    Run {
+        name: name.to_owned(),
+        path,
+        stop,
+        thread,
+    }
+}
+
+fn finish(run: Run) {
+    run.stop.store(true, Ordering::SeqCst);
+    match run.thread.join() {
+        Ok(Ok(capture)) => log::info(format_args!(
+            "{} exited, captured {} readings in {:.2}s into {}",
+            run.name,
+            capture.readings,
+            capture.elapsed.as_secs_f64(),
+            run.path.display()
+        )),
+        Ok(Err(err)) => log::error(format_args!("cannot capture {}: {}", run.name, err)),
+        Err(_) => log::error(format_args!("the capture of {} failed", run.name)),
+    }
+}
+
+/// `name` with only characters that are safe in a file name.
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() || "-_.".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
@@ -0,0 +1,66 @@
+//! Nagios-style threshold checks of the package power, for `--warn` and
+//! `--crit`. Status bar formats use the same thresholds for their class.
+
+/// Exit codes of the Nagios plugin API.
+pub const OK: i32 = 0;
+pub const WARNING: i32 = 1;
+pub const CRITICAL: i32 = 2;
+/// Measuring failed, nothing can be said about the power.
+pub const UNKNOWN: i32 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Thresholds {
+    pub warn: Option<f64>,
+    pub crit: Option<f64>,
+}
+
+impl Thresholds {
+    pub fn is_set(&self) -> bool {
+        self.warn.is_some() || self.crit.is_some()
+    }
+
+    /// Exit code for `package_power` in W.
+    pub fn level(&self, package_power: f64) -> i32 {
+        if self.crit.is_some_and(|crit| package_power >= crit) {
+            CRITICAL
+        } else if self.warn.is_some_and(|warn| package_power >= warn) {
+            WARNING
+        } else {
+            OK
+        }
+    }
+
+    /// CSS class for status bars, `normal`, `warning` or `critical`.
+    pub fn class(&self, package_power: f64) -> &'static str {
+        match self.level(package_power) {
+            CRITICAL => "critical",
+            WARNING => "warning",
+            _ => "normal",
+        }
+    }
+
+    /// The exit code and status line for `package_power` in W. Monitoring
+    /// systems parse the line, so it is never translated.
+    pub fn evaluate(&self, package_power: f64) -> (i32, String) {
+        let code = self.level(package_power);
+        let status = match code {
+            CRITICAL => "CRITICAL",
+            WARNING => "WARNING",
+            _ => "OK",
+        };
+
+        let threshold = |threshold: Option<f64>| {
+            threshold.map_or_else(String::new, |watts| format!("{}", watts))
+        };
+        let line = format!(
+            "POWER {} - package power {:.2}W | package={:.2}W;{};{};0",
+            status,
+            package_power,
+            package_power,
+            threshold(self.warn),
+            threshold(self.crit)
+        );
+
+        (code, line)
+    }
+}
@@ -0,0 +1,214 @@
+//! Client of the [`crate::daemon`], the only part of the crate that needs
+//! neither MSR nor sysfs access. Sandboxed apps, like monitoring GUIs in a
+//! Flatpak, can embed it and only need the daemon's socket exposed, e.g.
+//! with `--filesystem=xdg-run/ryzen-wattage.sock` for a daemon running as
+//! the user.
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+
+use crate::{json, metrics::Metric, output::json_string, Error, Result};
+
+/// Environment variable overriding where [`Client::connect_default`] looks.
+pub const SOCKET_ENV: &str = "RYZEN_WATTAGE_SOCKET";
+
+/// A sample as the daemon serves it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteSample {
+    value: json::Value,
+}
+
+impl RemoteSample {
+    /// RFC 3339 time the sample was taken at.
+    pub fn timestamp(&self) -> Option<&str> {
+        self.value.get("timestamp")?.as_str()
+    }
+
+    pub fn package_power(&self) -> Option<f64> {
+        self.value.get("package_watts")?.as_f64()
+    }
+
+    /// Like [`Metric::values`], numeric labels in numeric order. Values the
+    /// daemon couldn't measure are left out.
+    pub fn values(&self, metric: &Metric) -> Vec<(String, f64)> {
+        labeled_values(self.value.get(metric.name))
+    }
+
+    /// Like [`Metric::uncertainty`].
+    pub fn uncertainty(&self, metric: &Metric, label: &str) -> Option<f64> {
+        let uncertainties = self.value.get(&format!("{}_uncertainty", metric.name))?;
+        match uncertainties {
+            json::Value::Object(members) => members.get(label)?.as_f64(),
+            value => value.as_f64(),
+        }
+    }
+
+    /// The sample as JSON, with the keys `--format json` prints in
+    /// alphabetical order.
+    pub fn to_json(&self) -> String {
+        self.value.to_string()
+    }
+}
+
+fn labeled_values(value: Option<&json::Value>) -> Vec<(String, f64)> {
+    let mut values = match value {
+        Some(json::Value::Number(n)) => vec![(String::new(), *n)],
+        Some(json::Value::Object(members)) => members
+            .iter()
+            .filter_map(|(label, value)| Some((label.clone(), value.as_f64()?)))
+            .collect(),
+        _ => Vec::new(),
+    };
+    values.sort_by_key(|(label, _)| (label.parse::<u32>().ok(), label.clone()));
+    values
+}
+
+#[derive(Debug)]
+pub struct Client {
+    path: PathBuf,
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl Client {
+    pub fn connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path).map_err(|err| Error::io(path, err))?;
+        let reader = stream
+            .try_clone()
+            .map(BufReader::new)
+            .map_err(|err| Error::io(path, err))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            stream,
+            reader,
+        })
+    }
+
+    /// Connects to [`SOCKET_ENV`] if set, otherwise to a daemon running as
+    /// the user and then to a system daemon.
+    pub fn connect_default() -> Result<Self> {
+        if let Some(path) = env::var_os(SOCKET_ENV) {
+            return Self::connect(Path::new(&path));
+        }
+
+        let user = env::var_os("XDG_RUNTIME_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(&dir).join("ryzen-wattage.sock"));
+        match user.map(|path| Self::connect(&path)) {
+            Some(Ok(client)) => Ok(client),
+            _ => Self::connect(Path::new("/run/ryzen-wattage.sock")),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The most recent sample.
+    pub fn latest(&mut self) -> Result<RemoteSample> {
+        let response = self.request("{\"command\":\"latest\"}")?;
+        let value = response
+            .get("sample")
+            .cloned()
+            .ok_or_else(|| self.unexpected("missing `sample` in response"))?;
+        Ok(RemoteSample { value })
+    }
+
+    /// Asks for every new sample from now on, of only `cores` if given and
+    /// without per-core values with `package_only`. The connection is good
+    /// for nothing but [`Client::next_sample`] afterwards.
+    pub fn subscribe(&mut self, cores: Option<&[u32]>, package_only: bool) -> Result<()> {
+        let cores = cores.map_or_else(
+            || "null".to_owned(),
+            |cores| {
+                let cores = cores.iter().map(u32::to_string).collect::<Vec<_>>();
+                format!("[{}]", cores.join(","))
+            },
+        );
+        self.request(&format!(
+            "{{\"command\":\"subscribe\",\"cores\":{},\"package_only\":{}}}",
+            cores, package_only
+        ))?;
+        Ok(())
+    }
+
+    /// The next sample after [`Client::subscribe`], as soon as the daemon
+    /// takes it.
+    pub fn next_sample(&mut self) -> Result<RemoteSample> {
+        let response = self.response()?;
+        let value = response
+            .get("sample")
+            .cloned()
+            .ok_or_else(|| self.unexpected("missing `sample` in response"))?;
+        Ok(RemoteSample { value })
+    }
+
+    /// Up to `count` of the most recent samples, oldest first. The daemon
+    /// keeps [`crate::daemon::HISTORY`] of them.
+    pub fn history(&mut self, count: usize) -> Result<Vec<RemoteSample>> {
+        let response = self.request(&format!("{{\"command\":\"history\",\"count\":{}}}", count))?;
+        let samples = response
+            .get("samples")
+            .and_then(json::Value::as_array)
+            .ok_or_else(|| self.unexpected("missing `samples` in response"))?;
+        Ok(samples
+            .iter()
+            .map(|value| RemoteSample {
+                value: value.clone(),
+            })
+            .collect())
+    }
+
+    /// Energy per process name from the daemon's [`crate::ledger`], on `day`
+    /// or over every day, most first.
+    pub fn ledger(&mut self, day: Option<&str>) -> Result<Vec<(String, f64)>> {
+        let day = day.map_or_else(|| "null".to_owned(), json_string);
+        let response = self.request(&format!("{{\"command\":\"ledger\",\"day\":{}}}", day))?;
+        let processes = response
+            .get("processes")
+            .and_then(json::Value::as_array)
+            .ok_or_else(|| self.unexpected("missing `processes` in response"))?;
+        Ok(processes
+            .iter()
+            .filter_map(|process| {
+                let name = process.get("name")?.as_str()?;
+                Some((name.to_owned(), process.get("joules")?.as_f64()?))
+            })
+            .collect())
+    }
+
+    fn request(&mut self, request: &str) -> Result<json::Value> {
+        writeln!(self.stream, "{}", request).map_err(|err| Error::io(&self.path, err))?;
+        self.response()
+    }
+
+    fn response(&mut self) -> Result<json::Value> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => return Err(self.unexpected("connection closed by the daemon")),
+            Ok(_) => {}
+            Err(err) => return Err(Error::io(&self.path, err)),
+        }
+
+        let response = json::parse(&line).map_err(|err| self.unexpected(&err))?;
+        match response.get("ok").and_then(json::Value::as_bool) {
+            Some(true) => Ok(response),
+            _ => {
+                let error = response.get("error").and_then(json::Value::as_str);
+                Err(self.unexpected(error.unwrap_or("request failed")))
+            }
+        }
+    }
+
+    fn unexpected(&self, message: &str) -> Error {
+        Error::Daemon {
+            path: self.path.clone(),
+            message: message.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! `generate client`: a minimal typed client of the daemon's socket in Go,
+//! Python or TypeScript, for tools that would rather not write their own.
+//!
+//! The sample type is generated from [`schema::sample_fields`], the same
+//! fields `--schema daemon` describes, so it has every metric of this
+//! version and can't fall behind them. The rest is a fixed connection that
+//! sends the JSON lines of [`crate::daemon`]: `latest`, `history`, `ledger`
+//! and `subscribe`, with the responses of `ok: false` turned into errors.
+//! Nothing but the standard library of each language is needed.
+
+use std::{fmt::Write, str::FromStr};
+
+use crate::schema::{self, Field, Kind};
+
+/// `--lang`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Go,
+    Python,
+    Ts,
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "go" => Ok(Self::Go),
+            "python" => Ok(Self::Python),
+            "ts" | "typescript" => Ok(Self::Ts),
+            other => Err(format!(
+                "cannot generate a client in `{}`, expected go, python or ts",
+                other
+            )),
+        }
+    }
+}
+
+/// The client in `lang`, one source file.
+pub fn client(lang: Lang) -> String {
+    let fields = schema::sample_fields();
+    match lang {
+        Lang::Go => go(&fields),
+        Lang::Python => python(&fields),
+        Lang::Ts => ts(&fields),
+    }
+}
+
+fn go(fields: &[Field]) -> String {
+    let mut out = format!(
+        "// Code generated by ryzen-wattage generate client --lang go. DO NOT EDIT.\n\n\
+         // Package ryzenwattage reads the samples of a ryzen-wattage --daemon.\n\
+         package ryzenwattage\n{}\n\
+         // SchemaVersion is the schema_version this client was generated for.\n\
+         const SchemaVersion = {}\n\n\
+         // Sample is a sample as --format json prints it.\n\
+         type Sample struct {{\n",
+        GO_IMPORTS,
+        schema::VERSION
+    );
+    let members = fields
+        .iter()
+        .map(|field| {
+            let kind = match field.kind {
+                Kind::Integer => "int64",
+                Kind::Number if field.nullable => "*float64",
+                Kind::Number => "float64",
+                Kind::Boolean => "bool",
+                Kind::String if field.nullable => "*string",
+                Kind::String => "string",
+                Kind::NumberMap => "map[string]*float64",
+                Kind::StringMap => "map[string]string",
+            };
+            (field, go_name(&field.name), kind)
+        })
+        .collect::<Vec<_>>();
+    // Aligned the way gofmt would.
+    let name_width = members.iter().map(|(_, name, _)| name.len()).max();
+    let kind_width = members.iter().map(|(_, _, kind)| kind.len()).max();
+    for (field, name, kind) in &members {
+        let omit = if field.required { "" } else { ",omitempty" };
+        writeln!(out, "\t// {}", field.description).unwrap();
+        writeln!(
+            out,
+            "\t{:names$} {:kinds$} `json:\"{}{}\"`",
+            name,
+            kind,
+            field.name,
+            omit,
+            names = name_width.unwrap_or(0),
+            kinds = kind_width.unwrap_or(0)
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    out.push_str(GO_CLIENT);
+    out
+}
+
+/// `package_watts` as `PackageWatts`.
+fn go_name(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn python(fields: &[Field]) -> String {
+    let mut out = format!(
+        "# Generated by ryzen-wattage generate client --lang python, do not edit.\n\
+         \"\"\"The samples of a ryzen-wattage --daemon, typed, for Python 3.11 and later.\"\"\"\n{}\n\
+         SCHEMA_VERSION = {}\n\n\n\
+         class Sample(TypedDict):\n    \"\"\"A sample as --format json prints it.\"\"\"\n\n",
+        PYTHON_IMPORTS,
+        schema::VERSION
+    );
+    for field in fields {
+        let kind = match field.kind {
+            Kind::Integer => "int".to_owned(),
+            Kind::Number => "float".to_owned(),
+            Kind::Boolean => "bool".to_owned(),
+            Kind::String => "str".to_owned(),
+            Kind::NumberMap => "Dict[str, Optional[float]]".to_owned(),
+            Kind::StringMap => "Dict[str, str]".to_owned(),
+        };
+        let kind = match field.nullable && !matches!(field.kind, Kind::NumberMap) {
+            true => format!("Optional[{}]", kind),
+            false => kind,
+        };
+        let kind = match field.required {
+            true => kind,
+            false => format!("NotRequired[{}]", kind),
+        };
+        writeln!(out, "    #: {}", field.description).unwrap();
+        writeln!(out, "    {}: {}", field.name, kind).unwrap();
+    }
+    out.push_str(PYTHON_CLIENT);
+    out
+}
+
+fn ts(fields: &[Field]) -> String {
+    let mut out = format!(
+        "// Generated by ryzen-wattage generate client --lang ts, do not edit.\n\
+         // The samples of a ryzen-wattage --daemon, typed, for Node.js.\n{}\n\
+         export const SCHEMA_VERSION = {};\n\n\
+         /** A sample as --format json prints it. */\n\
+         export interface Sample {{\n",
+        TS_IMPORTS,
+        schema::VERSION
+    );
+    for field in fields {
+        let kind = match field.kind {
+            Kind::Integer | Kind::Number => "number",
+            Kind::Boolean => "boolean",
+            Kind::String => "string",
+            Kind::NumberMap => "Record<string, number | null>",
+            Kind::StringMap => "Record<string, string>",
+        };
+        let null = match field.nullable && !matches!(field.kind, Kind::NumberMap) {
+            true => " | null",
+            false => "",
+        };
+        let optional = if field.required { "" } else { "?" };
+        writeln!(out, "  /** {} */", field.description).unwrap();
+        writeln!(out, "  {}{}: {}{};", field.name, optional, kind, null).unwrap();
+    }
+    out.push_str("}\n");
+    out.push_str(TS_CLIENT);
+    out
+}
+
+const GO_IMPORTS: &str = r#"
+import (
+	"bufio"
+	"encoding/json"
+	"errors"
+	"net"
+)
+"#;
+
+const GO_CLIENT: &str = r#"
+// Process is the energy of a process name in the daemon's ledger.
+type Process struct {
+	Name   string  `json:"name"`
+	Joules float64 `json:"joules"`
+}
+
+type response struct {
+	OK        bool      `json:"ok"`
+	Error     string    `json:"error"`
+	Sample    *Sample   `json:"sample"`
+	Samples   []Sample  `json:"samples"`
+	Processes []Process `json:"processes"`
+}
+
+// DefaultSocket is where a daemon running as root listens.
+const DefaultSocket = "/run/ryzen-wattage.sock"
+
+// Client is a connection to the daemon.
+type Client struct {
+	conn  net.Conn
+	lines *bufio.Reader
+}
+
+// Dial connects to the daemon listening on path.
+func Dial(path string) (*Client, error) {
+	conn, err := net.Dial("unix", path)
+	if err != nil {
+		return nil, err
+	}
+	return &Client{conn: conn, lines: bufio.NewReader(conn)}, nil
+}
+
+// Close closes the connection.
+func (c *Client) Close() error {
+	return c.conn.Close()
+}
+
+func (c *Client) request(request map[string]any) (*response, error) {
+	line, err := json.Marshal(request)
+	if err != nil {
+		return nil, err
+	}
+	if _, err := c.conn.Write(append(line, '\n')); err != nil {
+		return nil, err
+	}
+	return c.response()
+}
+
+func (c *Client) response() (*response, error) {
+	line, err := c.lines.ReadBytes('\n')
+	if err != nil {
+		return nil, err
+	}
+	var r response
+	if err := json.Unmarshal(line, &r); err != nil {
+		return nil, err
+	}
+	if !r.OK {
+		return nil, errors.New(r.Error)
+	}
+	return &r, nil
+}
+
+// Latest returns the newest sample.
+func (c *Client) Latest() (*Sample, error) {
+	r, err := c.request(map[string]any{"command": "latest"})
+	if err != nil {
+		return nil, err
+	}
+	return r.Sample, nil
+}
+
+// History returns the last count samples, oldest first.
+func (c *Client) History(count int) ([]Sample, error) {
+	r, err := c.request(map[string]any{"command": "history", "count": count})
+	if err != nil {
+		return nil, err
+	}
+	return r.Samples, nil
+}
+
+// Ledger returns the energy per process name, most first, on day, like
+// "2026-10-14", or over every day if day is empty.
+func (c *Client) Ledger(day string) ([]Process, error) {
+	request := map[string]any{"command": "ledger"}
+	if day != "" {
+		request["day"] = day
+	}
+	r, err := c.request(request)
+	if err != nil {
+		return nil, err
+	}
+	return r.Processes, nil
+}
+
+// Subscribe asks for every new sample, Next returns them. The connection
+// carries nothing else afterwards.
+func (c *Client) Subscribe(packageOnly bool) error {
+	_, err := c.request(map[string]any{"command": "subscribe", "package_only": packageOnly})
+	return err
+}
+
+// Next waits for the next sample after Subscribe.
+func (c *Client) Next() (*Sample, error) {
+	r, err := c.response()
+	if err != nil {
+		return nil, err
+	}
+	return r.Sample, nil
+}
+"#;
+
+const PYTHON_IMPORTS: &str = r#"
+import json
+import socket
+from typing import Dict, Iterator, List, NotRequired, Optional, TypedDict
+"#;
+
+const PYTHON_CLIENT: &str = r#"
+
+class Process(TypedDict):
+    """The energy of a process name in the daemon's ledger."""
+
+    name: str
+    joules: float
+
+
+class Error(Exception):
+    """A request the daemon refused or a connection it closed."""
+
+
+#: Where a daemon running as root listens.
+DEFAULT_SOCKET = "/run/ryzen-wattage.sock"
+
+
+class Client:
+    """A connection to the daemon listening on ``path``."""
+
+    def __init__(self, path: str = DEFAULT_SOCKET) -> None:
+        self._socket = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        self._socket.connect(path)
+        self._lines = self._socket.makefile("r", encoding="utf-8")
+
+    def close(self) -> None:
+        self._lines.close()
+        self._socket.close()
+
+    def _request(self, request: dict) -> dict:
+        self._socket.sendall((json.dumps(request) + "\n").encode())
+        return self._response()
+
+    def _response(self) -> dict:
+        line = self._lines.readline()
+        if not line:
+            raise Error("the daemon closed the connection")
+        response = json.loads(line)
+        if not response["ok"]:
+            raise Error(response["error"])
+        return response
+
+    def latest(self) -> Sample:
+        """The newest sample."""
+        return self._request({"command": "latest"})["sample"]
+
+    def history(self, count: int) -> List[Sample]:
+        """The last ``count`` samples, oldest first."""
+        return self._request({"command": "history", "count": count})["samples"]
+
+    def ledger(self, day: Optional[str] = None) -> List[Process]:
+        """The energy per process name, most first, on ``day``, like
+        ``"2026-10-14"``, or over every day."""
+        request = {"command": "ledger"}
+        if day is not None:
+            request["day"] = day
+        return self._request(request)["processes"]
+
+    def subscribe(self, package_only: bool = False) -> Iterator[Sample]:
+        """Every new sample as it is taken. The connection carries nothing
+        else afterwards."""
+        self._request({"command": "subscribe", "package_only": package_only})
+        while True:
+            yield self._response()["sample"]
+"#;
+
+const TS_IMPORTS: &str = r#"
+import { createConnection, Socket } from "node:net";
+import { createInterface } from "node:readline";
+"#;
+
+const TS_CLIENT: &str = r#"
+/** The energy of a process name in the daemon's ledger. */
+export interface Process {
+  name: string;
+  joules: number;
+}
+
+interface Response {
+  ok: boolean;
+  error?: string;
+  sample?: Sample;
+  samples?: Sample[];
+  processes?: Process[];
+}
+
+/** Where a daemon running as root listens. */
+export const DEFAULT_SOCKET = "/run/ryzen-wattage.sock";
+
+/** A connection to the daemon. */
+export class Client {
+  private lines: AsyncIterator<string>;
+
+  private constructor(private socket: Socket) {
+    this.lines = createInterface({ input: socket })[Symbol.asyncIterator]();
+  }
+
+  /** Connects to the daemon listening on `path`. */
+  static connect(path: string = DEFAULT_SOCKET): Promise<Client> {
+    return new Promise((resolve, reject) => {
+      const socket = createConnection(path, () => resolve(new Client(socket)));
+      socket.once("error", reject);
+    });
+  }
+
+  close(): void {
+    this.socket.end();
+  }
+
+  private request(request: object): Promise<Response> {
+    this.socket.write(JSON.stringify(request) + "\n");
+    return this.response();
+  }
+
+  private async response(): Promise<Response> {
+    const { value, done } = await this.lines.next();
+    if (done) {
+      throw new Error("the daemon closed the connection");
+    }
+    const response = JSON.parse(value) as Response;
+    if (!response.ok) {
+      throw new Error(response.error);
+    }
+    return response;
+  }
+
+  /** The newest sample. */
+  async latest(): Promise<Sample> {
+    return (await this.request({ command: "latest" })).sample!;
+  }
+
+  /** The last `count` samples, oldest first. */
+  async history(count: number): Promise<Sample[]> {
+    return (await this.request({ command: "history", count })).samples!;
+  }
+
+  /** The energy per process name, most first, on `day`, like "2026-10-14", or over every day. */
+  async ledger(day?: string): Promise<Process[]> {
+    return (await this.request({ command: "ledger", day })).processes!;
+  }
+
+  /** Every new sample as it is taken. The connection carries nothing else afterwards. */
+  async *subscribe(packageOnly = false): AsyncGenerator<Sample> {
+    await this.request({ command: "subscribe", package_only: packageOnly });
+    for (;;) {
+      yield (await this.response()).sample!;
+    }
+  }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn types_every_sample_field() {
+        let fields = schema::sample_fields();
+        for lang in [Lang::Go, Lang::Python, Lang::Ts] {
+            let client = client(lang);
+            for field in &fields {
+                assert!(
+                    client.contains(field.name.as_str()),
+                    "{:?} leaves out {}",
+                    lang,
+                    field.name
+                );
+            }
+        }
+
+        let go = client(Lang::Go);
+        assert!(go.contains("\tSchemaVersion "));
+        assert!(go.contains("\tPackageWatts "));
+        assert!(go.contains(" `json:\"package_watts,omitempty\"`\n"));
+        let python = client(Lang::Python);
+        assert!(python.contains("    package_watts: NotRequired[Optional[float]]\n"));
+        assert!(python.contains("    timestamp: str\n"));
+        let ts = client(Lang::Ts);
+        assert!(ts.contains("  cores_watts?: Record<string, number | null>;\n"));
+    }
+}
@@ -0,0 +1,232 @@
+//! A/B comparison of commands: `ryzen-wattage compare --label A -- CMD
+//! --label B -- CMD`, each run `--runs` times, taking turns so drift in
+//! temperature or background load hits all of them alike.
+
+use std::{process::Command, time::Duration};
+
+use crate::{
+    output::Sample,
+    run::{self, Report},
+    state::Calibration,
+    stats::{Difference, Stats},
+    Cpu, Result,
+};
+
+/// One of the compared commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub label: String,
+    /// Program and arguments.
+    pub command: Vec<String>,
+}
+
+impl Variant {
+    /// A command given as one string, run through `sh -c`.
+    pub fn shell(label: impl Into<String>, command: &str) -> Self {
+        Self {
+            label: label.into(),
+            command: vec!["sh".to_owned(), "-c".to_owned(), command.to_owned()],
+        }
+    }
+
+    /// `A`, `B` and so on for the variant at `index`.
+    pub fn default_label(index: usize) -> String {
+        match u8::try_from(index) {
+            Ok(index @ 0..26) => char::from(b'A' + index).to_string(),
+            _ => (index + 1).to_string(),
+        }
+    }
+}
+
+/// The measured runs of one variant.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub variant: Variant,
+    /// Joules per successful run.
+    pub energy: Stats,
+    /// Seconds per successful run.
+    pub wall_time: Stats,
+    pub average_power: Stats,
+    /// Runs that exited unsuccessfully, left out of the statistics.
+    pub failures: u32,
+    /// Energy, wall time and average power of each successful run.
+    pub runs: Vec<(f64, f64, f64)>,
+}
+
+/// How a variant differs from the first one.
+#[derive(Debug, Clone)]
+pub struct Delta {
+    /// Difference of the means, the variant minus the baseline.
+    pub absolute: f64,
+    /// `absolute` in percent of the baseline mean.
+    pub percent: f64,
+    /// `None` with fewer than two successful runs on either side.
+    pub difference: Option<Difference>,
+}
+
+impl Outcome {
+    fn new(variant: Variant) -> Self {
+        Self {
+            variant,
+            energy: Stats::default(),
+            wall_time: Stats::default(),
+            average_power: Stats::default(),
+            failures: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// A single run over `samples` recorded earlier, like those of a
+    /// session file.
+    pub fn recorded(label: impl Into<String>, samples: &[Sample]) -> Self {
+        let mut outcome = Self::new(Variant {
+            label: label.into(),
+            command: Vec::new(),
+        });
+        let energy = samples
+            .iter()
+            .map(|sample| sample.package_power * sample.window.as_secs_f64())
+            .sum::<f64>();
+        let wall_time = samples
+            .iter()
+            .map(|sample| sample.window.as_secs_f64())
+            .sum::<f64>();
+        if wall_time > 0.0 {
+            let power = energy / wall_time;
+            outcome.energy.push(energy);
+            outcome.wall_time.push(wall_time);
+            outcome.average_power.push(power);
+            outcome.runs.push((energy, wall_time, power));
+        }
+        outcome
+    }
+
+    /// Differences of energy, wall time and average power from `baseline`.
+    pub fn compare(&self, baseline: &Self) -> [Delta; 3] {
+        let column = |outcome: &Self, pick: fn(&(f64, f64, f64)) -> f64| {
+            outcome.runs.iter().map(pick).collect::<Vec<_>>()
+        };
+        let delta = |stats: fn(&Self) -> &Stats, pick: fn(&(f64, f64, f64)) -> f64| {
+            let (value, base) = (stats(self).mean(), stats(baseline).mean());
+            Delta {
+                absolute: value - base,
+                percent: (value - base) / base * 100.0,
+                difference: Difference::between(&column(baseline, pick), &column(self, pick)),
+            }
+        };
+
+        [
+            delta(|outcome| &outcome.energy, |run| run.0),
+            delta(|outcome| &outcome.wall_time, |run| run.1),
+            delta(|outcome| &outcome.average_power, |run| run.2),
+        ]
+    }
+}
+
+/// Runs every variant `runs` times, one after the other in turns. `on_run`
+/// is called after every run.
+pub fn run(
+    cpu: &Cpu,
+    variants: &[Variant],
+    runs: u32,
+    interval: Duration,
+    calibration: &Calibration,
+    mut on_run: impl FnMut(&Variant, &Report),
+) -> Result<Vec<Outcome>> {
+    let mut outcomes = variants
+        .iter()
+        .cloned()
+        .map(Outcome::new)
+        .collect::<Vec<_>>();
+
+    for _ in 0..runs {
+        for outcome in &mut outcomes {
+            let (program, args) = outcome
+                .variant
+                .command
+                .split_first()
+                .expect("variant without command");
+            let mut command = Command::new(program);
+            command.args(args);
+
+            let mut report = run::run(cpu, &mut command, interval)?;
+            report.energy *= calibration.package;
+            report.average_power *= calibration.package;
+            report.peak_power *= calibration.package;
+            on_run(&outcome.variant, &report);
+
+            if !report.status.success() {
+                outcome.failures += 1;
+                continue;
+            }
+            let wall_time = report.wall_time.as_secs_f64();
+            outcome.energy.push(report.energy);
+            outcome.wall_time.push(wall_time);
+            outcome.average_power.push(report.average_power);
+            outcome
+                .runs
+                .push((report.energy, wall_time, report.average_power));
+        }
+    }
+
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(label: &str, runs: &[(f64, f64)]) -> Outcome {
+        let mut outcome = Outcome::new(Variant::shell(label, "true"));
+        for &(energy, wall_time) in runs {
+            let power = energy / wall_time;
+            outcome.energy.push(energy);
+            outcome.wall_time.push(wall_time);
+            outcome.average_power.push(power);
+            outcome.runs.push((energy, wall_time, power));
+        }
+        outcome
+    }
+
+    #[test]
+    fn sums_up_recorded_samples() {
+        let sample = |package_power, secs| Sample {
+            package_power,
+            window: Duration::from_secs(secs),
+            ..Sample::default()
+        };
+        let outcome = Outcome::recorded("idle.csv", &[sample(10.0, 1), sample(40.0, 2)]);
+        assert_eq!(outcome.runs, [(90.0, 3.0, 30.0)]);
+        assert_eq!(outcome.failures, 0);
+        assert!(Outcome::recorded("empty", &[]).runs.is_empty());
+    }
+
+    #[test]
+    fn labels_variants_by_letter() {
+        assert_eq!(Variant::default_label(0), "A");
+        assert_eq!(Variant::default_label(25), "Z");
+        assert_eq!(Variant::default_label(26), "27");
+    }
+
+    #[test]
+    fn compares_with_the_first_variant() {
+        let a = outcome("A", &[(100.0, 2.0), (102.0, 2.0), (98.0, 2.0)]);
+        let b = outcome("B", &[(80.0, 2.0), (81.0, 2.0), (79.0, 2.0)]);
+        let [energy, wall_time, power] = b.compare(&a);
+
+        assert_eq!(energy.absolute, -20.0);
+        assert_eq!(energy.percent, -20.0);
+        assert!(energy.difference.unwrap().high < 0.0);
+        assert_eq!(wall_time.absolute, 0.0);
+        assert_eq!(power.absolute, -10.0);
+    }
+
+    #[test]
+    fn single_runs_have_no_confidence_interval() {
+        let a = outcome("A", &[(100.0, 2.0)]);
+        let b = outcome("B", &[(150.0, 2.0)]);
+        let [energy, ..] = b.compare(&a);
+        assert_eq!(energy.percent, 50.0);
+        assert!(energy.difference.is_none());
+    }
+}
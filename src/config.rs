@@ -0,0 +1,254 @@
+//! Defaults from `$XDG_CONFIG_HOME/ryzen-wattage/config.toml`, or the file
+//! given with `--config`, so systemd units and aliases don't have to repeat
+//! long option strings. Options on the command line override them.
+//!
+//! ```toml
+//! interval = "500ms"
+//! format = "json"
+//! backend = "msr"
+//! show = ["freq", "temp"]
+//! # Where a bare `--exporter` listens.
+//! exporter = "0.0.0.0:9977"
+//! timezone = "Europe/Berlin"
+//! time_format = "%Y-%m-%d %H:%M:%S%.3f"
+//! # Defaults of --profile, which the keys above override.
+//! profile = "server"
+//! # What exported formats leave out, see --redact.
+//! redact = ["hostname"]
+//! # Processes to estimate the energy of, see --track.
+//! track = ["firefox", "cargo"]
+//!
+//! # Power summed over cores pinned to something, next to --group.
+//! [groups]
+//! game = "0-7"
+//! background = [8, 9, "10-15"]
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ryzen_wattage::{
+    timefmt::{self, Zone},
+    toml::{self, Value},
+    topology, BackendKind, Error, Result,
+};
+
+use crate::args::{self, Format, Preset, Redact, Show};
+
+/// Where a bare `--exporter` listens without a configured address.
+pub const DEFAULT_EXPORTER: &str = "127.0.0.1:9977";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub interval: Option<Duration>,
+    pub format: Option<Format>,
+    pub backend: Option<BackendKind>,
+    pub show: Option<Show>,
+    pub exporter: Option<String>,
+    pub timezone: Option<Zone>,
+    pub time_format: Option<String>,
+    pub profile: Option<Preset>,
+    pub redact: Option<Redact>,
+    pub track: Option<Vec<String>>,
+    /// Named groups of physical cores.
+    pub groups: BTreeMap<String, Vec<u32>>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/ryzen-wattage/config.toml`, by default in
+    /// `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_home.join("ryzen-wattage").join("config.toml"))
+    }
+
+    /// Loads `path`, or the default file if there is one.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents).map_err(|message| Error::config(&path, message)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(err) => Err(Error::io(&path, err)),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let table = toml::parse(contents).map_err(|err| err.to_string())?;
+        let mut config = Self::default();
+
+        for (key, value) in &table {
+            match key.as_str() {
+                "interval" => config.interval = Some(args::parse_duration(string(key, value)?)?),
+                "format" => config.format = Some(string(key, value)?.parse()?),
+                "backend" => config.backend = Some(string(key, value)?.parse()?),
+                "show" => config.show = Some(list(key, value)?.parse()?),
+                "exporter" => config.exporter = Some(string(key, value)?.to_owned()),
+                "timezone" => config.timezone = Some(string(key, value)?.parse()?),
+                "time_format" => {
+                    let format = string(key, value)?;
+                    timefmt::check_format(format)?;
+                    config.time_format = Some(format.to_owned());
+                }
+                "profile" => config.profile = Some(string(key, value)?.parse()?),
+                "redact" => config.redact = Some(list(key, value)?.parse()?),
+                "track" => config.track = Some(args::parse_names(&list(key, value)?)?),
+                "groups" => config.groups = groups(key, value)?,
+                other => {
+                    return Err(format!(
+                        "unknown key `{}`, expected interval, format, backend, show, exporter, \
+                         timezone, time_format, profile, redact, track or groups",
+                        other
+                    ))
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("`{}` must be a string, not {}", key, value.type_name()))
+}
+
+/// An array of strings or a single one, comma separated like on the
+/// command line.
+fn list(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::Array(items) => Ok(items
+            .iter()
+            .map(|item| string(key, item))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(",")),
+        value => Ok(string(key, value)?.to_owned()),
+    }
+}
+
+/// A table of core lists, each a CPU list like `"0-7"` or an array of
+/// cores and CPU lists.
+fn groups(key: &str, value: &Value) -> Result<BTreeMap<String, Vec<u32>>, String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| format!("`{}` must be a table, not {}", key, value.type_name()))?;
+    let mut groups = BTreeMap::new();
+    for (name, cores) in table {
+        let key = format!("{}.{}", key, name);
+        let items = match cores {
+            Value::Array(items) => items.as_slice(),
+            cores => std::slice::from_ref(cores),
+        };
+        let mut list = Vec::new();
+        for item in items {
+            match item {
+                Value::Integer(core) => list.push(core.to_string()),
+                item => list.push(string(&key, item)?.to_owned()),
+            }
+        }
+        let mut cores = topology::parse_cpulist(&list.join(","))
+            .filter(|cores| !cores.is_empty())
+            .ok_or_else(|| format!("`{}` must list cores like \"0-7\" or [0, 1]", key))?;
+        cores.sort_unstable();
+        cores.dedup();
+        groups.insert(name.clone(), cores);
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_key() {
+        let config = Config::parse(concat!(
+            "interval = \"500ms\"\n",
+            "format = \"json\"\n",
+            "backend = \"msr\"\n",
+            "show = [\"freq\", \"temp\"]\n",
+            "exporter = \"0.0.0.0:9977\"\n",
+            "timezone = \"+02:00\"\n",
+            "time_format = \"%H:%M:%S%.3f\"\n",
+            "profile = \"laptop\"\n",
+            "redact = [\"hostname\", \"model\"]\n",
+            "track = [\"firefox\", \"cargo\"]\n",
+            "[groups]\n",
+            "game = \"0-3\"\n",
+            "background = [6, \"4-5\", 7]\n",
+        ))
+        .unwrap();
+
+        assert_eq!(config.interval, Some(Duration::from_millis(500)));
+        assert_eq!(config.format, Some(Format::Json));
+        assert_eq!(config.backend, Some(BackendKind::Msr));
+        assert_eq!(config.show, Some("freq,temp".parse().unwrap()));
+        assert_eq!(config.exporter.as_deref(), Some("0.0.0.0:9977"));
+        assert_eq!(config.timezone, Some(Zone::Fixed(7200)));
+        assert_eq!(config.time_format.as_deref(), Some("%H:%M:%S%.3f"));
+        assert_eq!(config.profile, Some(Preset::Laptop));
+        assert_eq!(config.redact, Some(Redact::all()));
+        assert_eq!(
+            config.track,
+            Some(vec!["firefox".to_owned(), "cargo".to_owned()])
+        );
+        assert_eq!(
+            config.groups,
+            BTreeMap::from([
+                ("game".to_owned(), vec![0, 1, 2, 3]),
+                ("background".to_owned(), vec![4, 5, 6, 7]),
+            ])
+        );
+
+        // A single string works for show too.
+        let config = Config::parse("show = \"gpu\"").unwrap();
+        assert_eq!(config.show, Some("gpu".parse().unwrap()));
+        assert_eq!(Config::parse("# nothing\n").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_bad_values() {
+        assert!(Config::parse("intervall = \"1s\"")
+            .unwrap_err()
+            .starts_with("unknown key `intervall`"));
+        assert_eq!(
+            Config::parse("interval = 1").unwrap_err(),
+            "`interval` must be a string, not integer"
+        );
+        assert_eq!(
+            Config::parse("show = [\"freq\", 2]").unwrap_err(),
+            "`show` must be a string, not integer"
+        );
+        assert!(Config::parse("interval = \"0s\"").is_err());
+        assert!(Config::parse("format = \"yaml\"").is_err());
+        assert!(Config::parse("time_format = \"%q\"").is_err());
+        assert!(Config::parse("timezone = \"Nowhere/Atlantis\"").is_err());
+        assert_eq!(
+            Config::parse("[groups]\ngame = \"7-0\"").unwrap_err(),
+            "`groups.game` must list cores like \"0-7\" or [0, 1]"
+        );
+        assert!(Config::parse("groups = \"0-7\"").is_err());
+        assert_eq!(
+            Config::parse("[interval]").unwrap_err(),
+            "`interval` must be a string, not table"
+        );
+        assert_eq!(
+            Config::parse("interval = \"1s\"\ninterval = \"2s\"").unwrap_err(),
+            "line 2: duplicate key `interval`"
+        );
+    }
+}
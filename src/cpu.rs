@@ -0,0 +1,1189 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::{self, BackendKind, EnergyReader, Profile, Simulator},
+    cpufreq,
+    cpuinfo::CpuInfo,
+    experiment::CpuMask,
+    sanity::{self, CpuTimes},
+    state::{TopologyCache, TopologyKey},
+    sysfs::Root,
+    topology::{self, Grouping},
+    Error, Result,
+};
+
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+const ONLINE_PATH: &str = "/sys/devices/system/cpu/online";
+const SMT_PATH: &str = "/sys/devices/system/cpu/smt/control";
+
+/// Online threads of each physical core.
+pub type Threads = BTreeMap<u32, Vec<u32>>;
+
+/// Energy counter readings at one point in time, in joules, each with the
+/// time right after it was read.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub package: (f64, Instant),
+    pub cores: BTreeMap<u32, (f64, Instant)>,
+    pub domains: BTreeMap<&'static str, (f64, Instant)>,
+    /// Time each core spent idle, empty unless [`Cpu::idle_residency`] is
+    /// set.
+    pub idle: BTreeMap<u32, (Duration, Instant)>,
+}
+
+/// Like [`Snapshot`], but the counters as they are, in whole energy units.
+#[derive(Debug, Clone)]
+pub struct RawSnapshot {
+    pub package: (u64, Instant),
+    pub cores: BTreeMap<u32, (u64, Instant)>,
+    pub domains: BTreeMap<&'static str, (u64, Instant)>,
+}
+
+/// Energy between two [`RawSnapshot`]s in energy units. Integer all the way,
+/// for consumers like billing that need totals without any rounding; only
+/// [`RawEnergy::joules`] brings in floating point.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawEnergy {
+    /// Joules per unit.
+    pub unit: f64,
+    pub package: u64,
+    pub cores: BTreeMap<u32, u64>,
+    pub domains: BTreeMap<&'static str, u64>,
+    /// Length of the package window.
+    pub duration: Duration,
+}
+
+impl RawEnergy {
+    pub fn joules(&self, ticks: u64) -> f64 {
+        ticks as f64 * self.unit
+    }
+
+    /// Adds the energy of a following window. Both have to come from the
+    /// same CPU.
+    pub fn add(&mut self, other: &RawEnergy) {
+        self.unit = other.unit;
+        self.package += other.package;
+        for (&core, &ticks) in &other.cores {
+            *self.cores.entry(core).or_default() += ticks;
+        }
+        for (&domain, &ticks) in &other.domains {
+            *self.domains.entry(domain).or_default() += ticks;
+        }
+        self.duration += other.duration;
+    }
+}
+
+/// Average power in watts over a window.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Power {
+    pub package: f64,
+    /// Empty if there are no (working) per-core counters.
+    pub cores: BTreeMap<u32, f64>,
+    /// Package-wide sub-domains like `dram`, if the backend has any.
+    pub domains: BTreeMap<&'static str, f64>,
+}
+
+/// Estimated error of each [`Power`] value, in watts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Uncertainty {
+    pub package: f64,
+    pub cores: BTreeMap<u32, f64>,
+    pub domains: BTreeMap<&'static str, f64>,
+}
+
+/// RAPL counters only advance about once a millisecond, so a reading can be
+/// that much older than the time it was taken at.
+const COUNTER_UPDATE_INTERVAL: f64 = 0.001;
+
+/// Cores one thread reads in a sweep. Reading an MSR interrupts the core it
+/// belongs to, so one after the other the last of 64 cores is read well
+/// after the first; past this many the sweep is split across threads.
+const SWEEP_SHARE: usize = 16;
+/// Windows in a row the per-core counters may stand still while the
+/// package counter moves before they count as not working.
+const STATIC_WINDOWS: u32 = 3;
+
+#[derive(Debug)]
+pub struct Cpu {
+    pub info: CpuInfo,
+    pub smt_enabled: bool,
+    pub core_count: u32,
+    pub physical_core_count: u32,
+    /// Online threads of each physical core, keyed by the CPU the core is
+    /// read through.
+    pub threads: Threads,
+    /// Also read idle times in [`Cpu::snapshot`].
+    pub idle_residency: bool,
+    reader: Box<dyn EnergyReader>,
+    /// Windows in a row in which the package counter moved but none of the
+    /// per-core ones did. From [`STATIC_WINDOWS`] on the per-core counters
+    /// count as not working until they move again.
+    static_windows: AtomicU32,
+    root: Root,
+    /// Where the MSR devices are, to find them again in [`Cpu::reopen`].
+    msr_path_template: Option<String>,
+    /// The CPUs and cores of each CCD after [`Cpu::read_per_ccd`].
+    ccd_readers: Vec<(CpuMask, Vec<u32>)>,
+}
+
+impl Cpu {
+    /// Opens this machine's counters, with the topology from the
+    /// [`TopologyCache`] if it is still valid.
+    pub fn new(backend: BackendKind) -> Result<Self> {
+        Self::with_msr_path_template(backend, None)
+    }
+
+    /// Like [`Cpu::new`], reading the MSRs from `template` with `{}` for the
+    /// CPU number instead of `/dev/cpu/{}/msr` if it is set.
+    pub fn with_msr_path_template(backend: BackendKind, template: Option<String>) -> Result<Self> {
+        let root = Root::system();
+        let topology = Self::cached_topology(&root)?;
+        Self::open(backend, root, topology, template)
+    }
+
+    /// Like [`Cpu::new`], reading the topology and counters below `root`.
+    pub fn with_root(backend: BackendKind, root: Root) -> Result<Self> {
+        let topology = Self::read_topology(&root)?;
+        Self::open(backend, root, topology, None)
+    }
+
+    fn open(
+        backend: BackendKind,
+        root: Root,
+        (smt_enabled, online, threads): (bool, Vec<u32>, Threads),
+        msr_path_template: Option<String>,
+    ) -> Result<Self> {
+        let info =
+            CpuInfo::read(&root).map_err(|err| Error::io(root.path("/proc/cpuinfo"), err))?;
+        let physical_cores = threads.keys().copied().collect::<Vec<_>>();
+        let reader = backend::open(
+            backend,
+            &info,
+            &physical_cores,
+            &root,
+            msr_path_template.as_deref(),
+        )?;
+
+        Ok(Self {
+            info,
+            smt_enabled,
+            core_count: online.len() as u32,
+            physical_core_count: threads.len() as u32,
+            threads,
+            idle_residency: false,
+            reader,
+            static_windows: AtomicU32::new(0),
+            root,
+            msr_path_template,
+            ccd_readers: Vec::new(),
+        })
+    }
+
+    /// An 8-core Ryzen 7 5800X with SMT whose counters follow `profile`,
+    /// for working on the tool without the hardware.
+    pub fn simulated(profile: Profile) -> Self {
+        let info = CpuInfo {
+            vendor: "AuthenticAMD".to_owned(),
+            family: 0x19,
+            model: 0x21,
+            model_name: "AMD Ryzen 7 5800X 8-Core Processor".to_owned(),
+        };
+        let threads = (0..8)
+            .map(|core| (core, vec![core, core + 8]))
+            .collect::<Threads>();
+
+        Self {
+            info,
+            smt_enabled: true,
+            core_count: 16,
+            physical_core_count: 8,
+            reader: Box::new(Simulator::new(profile, threads.keys().copied().collect())),
+            threads,
+            idle_residency: false,
+            static_windows: AtomicU32::new(0),
+            root: Root::system(),
+            msr_path_template: None,
+            ccd_readers: Vec::new(),
+        }
+    }
+
+    /// Uses the topology below `root` with the given energy counters.
+    pub fn with_reader(reader: Box<dyn EnergyReader>, root: Root) -> Result<Self> {
+        let info = CpuInfo::read(&root).unwrap_or_default();
+        let (smt_enabled, online, threads) = Self::read_topology(&root)?;
+
+        Ok(Self {
+            info,
+            smt_enabled,
+            core_count: online.len() as u32,
+            physical_core_count: threads.len() as u32,
+            threads,
+            idle_residency: false,
+            reader,
+            static_windows: AtomicU32::new(0),
+            root,
+            msr_path_template: None,
+            ccd_readers: Vec::new(),
+        })
+    }
+
+    /// SMT state, the online CPUs and the threads of each physical core.
+    pub fn read_topology(root: &Root) -> Result<(bool, Vec<u32>, Threads)> {
+        let smt_status = root.read(SMT_PATH)?;
+        let smt_enabled = smt_status == "on";
+
+        let online = Self::get_online_cpus(root)?;
+        let threads = Self::get_physical_cores(root, smt_enabled, &online)?;
+        Ok((smt_enabled, online, threads))
+    }
+
+    /// [`Cpu::read_topology`], taking the physical cores from the cache while
+    /// the boot and the online CPUs stay the same, and caching them
+    /// otherwise.
+    pub fn cached_topology(root: &Root) -> Result<(bool, Vec<u32>, Threads)> {
+        let Ok(boot_id) = root.read(BOOT_ID_PATH) else {
+            return Self::read_topology(root);
+        };
+        let key = TopologyKey {
+            boot_id,
+            online: root.read(ONLINE_PATH)?,
+            smt: root.read(SMT_PATH)?,
+        };
+        let smt_enabled = key.smt == "on";
+        let online = Self::get_online_cpus(root)?;
+
+        if let Some(threads) = TopologyCache::load(&key) {
+            return Ok((smt_enabled, online, threads));
+        }
+
+        let threads = Self::get_physical_cores(root, smt_enabled, &online)?;
+        // Only saves time, reading it again next time is fine.
+        let _ = TopologyCache {
+            key,
+            threads: threads.clone(),
+        }
+        .save();
+        Ok((smt_enabled, online, threads))
+    }
+
+    /// Online CPUs. With some of them offlined or isolated this isn't a
+    /// single range, e.g. `0,2-5,8-15`.
+    fn get_online_cpus(root: &Root) -> Result<Vec<u32>> {
+        let online = root.read(ONLINE_PATH)?;
+        topology::parse_cpulist(&online)
+            .filter(|cpus| !cpus.is_empty())
+            .ok_or_else(|| Error::parse(root.path(ONLINE_PATH), &online))
+    }
+
+    /// The online threads of each physical core, keyed by the first one.
+    fn get_physical_cores(root: &Root, smt_enabled: bool, online: &[u32]) -> Result<Threads> {
+        if !smt_enabled {
+            return Ok(online.iter().map(|&cpu| (cpu, vec![cpu])).collect());
+        }
+
+        let mut cores = BTreeMap::new();
+        for cpu in online {
+            let path = format!("/sys/devices/system/cpu/cpu{}/topology/core_cpus_list", cpu);
+            let cpus_list = root.read(&path)?;
+            let siblings = topology::parse_cpulist(&cpus_list)
+                .map(|siblings| {
+                    siblings
+                        .into_iter()
+                        .filter(|sibling| online.contains(sibling))
+                        .collect::<Vec<_>>()
+                })
+                .filter(|siblings| !siblings.is_empty())
+                .ok_or_else(|| Error::parse(root.path(&path), &cpus_list))?;
+            cores.insert(siblings[0], siblings);
+        }
+
+        Ok(cores)
+    }
+
+    /// Opens the backend again and turns the per-core counters back on, for
+    /// counters that stopped advancing. Simulated counters stay as they are.
+    pub fn reopen(&mut self) -> Result<()> {
+        let Some(kind) = self.backend_kind() else {
+            return Ok(());
+        };
+        let physical_cores = self.threads.keys().copied().collect::<Vec<_>>();
+        self.reader = backend::open(
+            kind,
+            &self.info,
+            &physical_cores,
+            &self.root,
+            self.msr_path_template.as_deref(),
+        )?;
+        self.static_windows.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The backend to open for the counters read now, `None` for simulated
+    /// ones.
+    pub fn backend_kind(&self) -> Option<BackendKind> {
+        match self.backend_name() {
+            // The msr backend picks msr-safe again by itself.
+            "msr" | "msr-safe" => Some(BackendKind::Msr),
+            "powercap" => Some(BackendKind::Powercap),
+            _ => None,
+        }
+    }
+
+    /// Where the topology and counters are read from.
+    pub fn root(&self) -> &Root {
+        &self.root
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.reader.name()
+    }
+
+    /// Whether per-core power is available. Some BIOSes disable the per-core
+    /// counters, in which case only the package power is reported.
+    pub fn has_core_counters(&self) -> bool {
+        self.core_counters_working() && !self.reader.core_ids().is_empty()
+    }
+
+    fn core_counters_working(&self) -> bool {
+        self.static_windows.load(Ordering::Relaxed) < STATIC_WINDOWS
+    }
+
+    /// Whether only the package is measured because the per-core counters
+    /// aren't readable for this user.
+    pub fn core_counters_denied(&self) -> bool {
+        self.reader.core_counters_denied()
+    }
+
+    /// Cores without per-core readings because their counters can't be
+    /// read.
+    pub fn unreadable_cores(&self) -> Vec<u32> {
+        self.reader.unreadable_cores()
+    }
+
+    pub fn core_ids(&self) -> Vec<u32> {
+        match self.core_counters_working() {
+            true => self.reader.core_ids(),
+            false => Vec::new(),
+        }
+    }
+
+    /// Reads the counter of `core` once, the package's for `None`.
+    pub fn read_counter(&self, core: Option<u32>) -> Result<f64> {
+        match core {
+            Some(core) => self.reader.core_energy(core),
+            None => self.reader.package_energy(),
+        }
+    }
+
+    /// Package energy in joules and the time right after it was read.
+    pub fn package_energy(&self) -> Result<(f64, Instant)> {
+        Ok((self.reader.package_energy()?, Instant::now()))
+    }
+
+    /// Package-wide sub-domains the backend has counters for.
+    pub fn domains(&self) -> Vec<&'static str> {
+        self.reader.domains()
+    }
+
+    /// Joules per counter increment of the backend, if known.
+    pub fn energy_unit(&self) -> Option<f64> {
+        self.reader.energy_unit()
+    }
+
+    /// Sustained package power limit in W, from the backend or else from
+    /// powercap, if either has one.
+    pub fn package_power_limit(&self) -> Option<f64> {
+        self.reader
+            .package_power_limit()
+            .or_else(|| backend::powercap_power_limit(&self.root))
+    }
+
+    /// Joules after which the package counter wraps to zero, if known.
+    pub fn package_energy_range(&self) -> Option<f64> {
+        self.reader.package_energy_range()
+    }
+
+    /// Like [`Cpu::package_energy_range`] for the per-core counters.
+    pub fn core_energy_range(&self) -> Option<f64> {
+        self.reader.core_energy_range()
+    }
+
+    /// Like [`Cpu::package_energy_range`] for the domain counters.
+    pub fn domain_energy_range(&self) -> Option<f64> {
+        self.reader.domain_energy_range()
+    }
+
+    /// Energy per core in joules, all read in one [sweep](Self::sweep) and
+    /// with its time.
+    ///
+    /// Cores whose device went away, e.g. because they were taken offline,
+    /// are left out. Counters that count as not working are read too, for
+    /// [`Cpu::power_between`] to notice them moving again.
+    pub fn core_energy(&self) -> Result<BTreeMap<u32, (f64, Instant)>> {
+        self.sweep(&self.reader.core_ids(), |reader, core| {
+            reader.core_energy(core)
+        })
+    }
+
+    /// Has sweeps read each CCD's cores on a thread of its own, pinned to
+    /// the CCD. Reading an MSR runs on the core it belongs to, and asked
+    /// from another CCD that is a round trip across the Infinity Fabric;
+    /// with every read local, a sweep is shorter and adds no traffic
+    /// between the chiplets. Returns the number of CCDs.
+    pub fn read_per_ccd(&mut self) -> Result<usize> {
+        let ccds = topology::groups(
+            &self.root,
+            Grouping::Ccd,
+            &self.info,
+            &self.reader.core_ids(),
+        )?;
+        self.ccd_readers = ccds
+            .into_values()
+            .map(|cores| {
+                let cpus = cores
+                    .iter()
+                    .flat_map(|core| self.threads.get(core).cloned().unwrap_or(vec![*core]))
+                    .collect::<Vec<_>>();
+                (CpuMask::new(&cpus), cores)
+            })
+            .collect();
+        Ok(self.ccd_readers.len())
+    }
+
+    /// Reads `cores` with `read` in one tight pass, split across threads
+    /// on parts with many cores or by CCD, so the values are as good as
+    /// simultaneous. They all get the time in the middle of the pass.
+    fn sweep<T: Send>(
+        &self,
+        cores: &[u32],
+        read: impl Fn(&dyn EnergyReader, u32) -> Result<T> + Sync,
+    ) -> Result<BTreeMap<u32, (T, Instant)>> {
+        let reader = &*self.reader;
+        let read_share = |cores: &[u32]| {
+            let mut values = Vec::with_capacity(cores.len());
+            for &core in cores {
+                match read(reader, core) {
+                    Ok(value) => values.push((core, value)),
+                    Err(err) if err.is_device_gone() => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(values)
+        };
+
+        let start = Instant::now();
+        let values = match cores.len() {
+            _ if !self.ccd_readers.is_empty() => thread::scope(|scope| {
+                let shares = self
+                    .ccd_readers
+                    .iter()
+                    .map(|(cpus, ccd)| {
+                        let share = ccd
+                            .iter()
+                            .copied()
+                            .filter(|core| cores.contains(core))
+                            .collect::<Vec<_>>();
+                        scope.spawn(move || {
+                            // Unpinned, the reads are only slower.
+                            let _ = cpus.apply();
+                            read_share(&share)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut values = Vec::with_capacity(cores.len());
+                for share in shares {
+                    values.extend(share.join().expect("sweep thread panicked")?);
+                }
+                Ok::<_, Error>(values)
+            })?,
+            ..=SWEEP_SHARE => read_share(cores)?,
+            _ => thread::scope(|scope| {
+                let mut shares = cores.chunks(SWEEP_SHARE);
+                let first = shares.next().unwrap_or_default();
+                let others = shares
+                    .map(|share| scope.spawn(|| read_share(share)))
+                    .collect::<Vec<_>>();
+                let mut values = read_share(first)?;
+                for other in others {
+                    values.extend(other.join().expect("sweep thread panicked")?);
+                }
+                Ok::<_, Error>(values)
+            })?,
+        };
+        let time = start + start.elapsed() / 2;
+
+        Ok(values
+            .into_iter()
+            .map(|(core, value)| (core, (value, time)))
+            .collect())
+    }
+
+    /// Energy per package domain in joules, like [`core_energy`](Self::core_energy).
+    pub fn domain_energy(&self) -> Result<BTreeMap<&'static str, (f64, Instant)>> {
+        self.reader
+            .domains()
+            .into_iter()
+            .map(|domain| Ok((domain, (self.reader.domain_energy(domain)?, Instant::now()))))
+            .collect()
+    }
+
+    /// Reads all counters once without converting them to joules. Fails if
+    /// the backend doesn't know its energy unit.
+    pub fn raw_snapshot(&self) -> Result<RawSnapshot> {
+        let package = (self.reader.package_ticks()?, Instant::now());
+        let cores = self.sweep(&self.core_ids(), |reader, core| reader.core_ticks(core))?;
+
+        let domains = self
+            .reader
+            .domains()
+            .into_iter()
+            .map(|domain| Ok((domain, (self.reader.domain_ticks(domain)?, Instant::now()))))
+            .collect::<Result<_>>()?;
+
+        Ok(RawSnapshot {
+            package,
+            cores,
+            domains,
+        })
+    }
+
+    /// Reads the package counter and then those of `cores` into `ticks`,
+    /// package first, without allocating. Cores whose device went away read
+    /// as [`u64::MAX`]. Returns when the package counter was read.
+    pub fn read_ticks(&self, cores: &[u32], ticks: &mut [u64]) -> Result<Instant> {
+        ticks[0] = self.reader.package_ticks()?;
+        let time = Instant::now();
+
+        for (&core, ticks) in cores.iter().zip(&mut ticks[1..]) {
+            *ticks = match self.reader.core_ticks(core) {
+                Ok(ticks) => ticks,
+                Err(err) if err.is_device_gone() => u64::MAX,
+                Err(err) => return Err(err),
+            };
+        }
+        Ok(time)
+    }
+
+    /// Like [`Cpu::power_between`], the energy of each counter in whole
+    /// units, with a single wrap corrected for.
+    pub fn ticks_between(&self, before: &RawSnapshot, after: &RawSnapshot) -> Result<RawEnergy> {
+        let unit = self.energy_unit().ok_or(Error::NoEnergyUnit {
+            backend: self.backend_name(),
+        })?;
+        let ticks = |range: Option<f64>| range.map(|range| (range / unit).round() as u64);
+        let package_range = ticks(self.reader.package_energy_range());
+        let core_range = ticks(self.reader.core_energy_range());
+        let domain_range = ticks(self.reader.domain_energy_range());
+
+        let cores = before
+            .cores
+            .iter()
+            .filter_map(|(&core, &(before, _))| {
+                let &(after, _) = after.cores.get(&core)?;
+                Some((core, tick_delta(before, after, core_range)))
+            })
+            .collect();
+        let domains = before
+            .domains
+            .iter()
+            .filter_map(|(&domain, &(before, _))| {
+                let &(after, _) = after.domains.get(domain)?;
+                Some((domain, tick_delta(before, after, domain_range)))
+            })
+            .collect();
+
+        Ok(RawEnergy {
+            unit,
+            package: tick_delta(before.package.0, after.package.0, package_range),
+            cores,
+            domains,
+            duration: after.package.1.duration_since(before.package.1),
+        })
+    }
+
+    /// Reads all counters once.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        Ok(Snapshot {
+            package: self.package_energy()?,
+            cores: self.core_energy()?,
+            domains: self.domain_energy()?,
+            idle: match self.idle_residency {
+                true => self.idle_time(),
+                false => BTreeMap::new(),
+            },
+        })
+    }
+
+    fn idle_time(&self) -> BTreeMap<u32, (Duration, Instant)> {
+        self.core_ids()
+            .into_iter()
+            .filter_map(|core| {
+                Some((
+                    core,
+                    (cpufreq::idle_time(&self.root, core)?, Instant::now()),
+                ))
+            })
+            .collect()
+    }
+
+    /// Share of the time between two snapshots each core spent idle, in
+    /// percent. With SMT this is the idle time of the core's first thread.
+    pub fn idle_between(&self, before: &Snapshot, after: &Snapshot) -> BTreeMap<u32, f64> {
+        before
+            .idle
+            .iter()
+            .filter_map(|(core, &(idle_before, read_before))| {
+                let &(idle_after, read_after) = after.idle.get(core)?;
+                let elapsed = read_after.duration_since(read_before).as_secs_f64();
+                let idle = idle_after.saturating_sub(idle_before).as_secs_f64();
+                (elapsed > 0.0).then(|| (*core, (idle / elapsed * 100.0).min(100.0)))
+            })
+            .collect()
+    }
+
+    /// Current frequency of each core in MHz, for cores that have cpufreq.
+    pub fn frequencies(&self) -> BTreeMap<u32, f64> {
+        self.core_ids()
+            .into_iter()
+            .filter_map(|core| Some((core, cpufreq::current_frequency(&self.root, core)?)))
+            .collect()
+    }
+
+    /// Splits each core's power across its threads in proportion to the
+    /// time they were busy between `before` and `after`. Idle cores are split
+    /// evenly.
+    pub fn thread_power(
+        &self,
+        core_power: &BTreeMap<u32, f64>,
+        before: &BTreeMap<u32, CpuTimes>,
+        after: &BTreeMap<u32, CpuTimes>,
+    ) -> BTreeMap<u32, f64> {
+        let mut thread_power = BTreeMap::new();
+
+        for (core, &power) in core_power {
+            let Some(threads) = self.threads.get(core) else {
+                continue;
+            };
+            let busy = threads
+                .iter()
+                .map(|thread| {
+                    let busy = before
+                        .get(thread)
+                        .zip(after.get(thread))
+                        .and_then(|(before, after)| before.busy_until(after));
+                    (*thread, busy.unwrap_or(0))
+                })
+                .collect::<Vec<_>>();
+            let total = busy.iter().map(|(_, busy)| busy).sum::<u64>();
+
+            for (thread, busy) in busy {
+                let share = match total {
+                    0 => 1.0 / threads.len() as f64,
+                    _ => busy as f64 / total as f64,
+                };
+                thread_power.insert(thread, power * share);
+            }
+        }
+
+        thread_power
+    }
+
+    /// Seconds the threads of each physical core were busy between
+    /// `before` and `after`, summed, so SMT siblings running side by side
+    /// count twice.
+    pub fn core_busy(
+        &self,
+        before: &BTreeMap<u32, CpuTimes>,
+        after: &BTreeMap<u32, CpuTimes>,
+    ) -> BTreeMap<u32, f64> {
+        self.core_ids()
+            .into_iter()
+            .filter_map(|core| {
+                let ticks = self
+                    .threads
+                    .get(&core)?
+                    .iter()
+                    .filter_map(|thread| before.get(thread)?.busy_until(after.get(thread)?));
+                Some((core, ticks.sum::<u64>() as f64 / sanity::USER_HZ))
+            })
+            .collect()
+    }
+
+    /// Average power over `duration`.
+    pub fn power(&self, duration: Duration) -> Result<Power> {
+        let before = self.snapshot()?;
+        thread::sleep(duration);
+        let after = self.snapshot()?;
+
+        Ok(self.power_between(&before, &after))
+    }
+
+    /// Average power between two snapshots.
+    ///
+    /// Every value is divided by the time between its own two reads. The
+    /// cores of a snapshot all share the time in the middle of their sweep,
+    /// see [`Cpu::core_energy`], while the package and each domain have the
+    /// time right after they were read.
+    ///
+    /// A counter that wrapped once in between is corrected for. The MSR
+    /// counters wrap after roughly 65 kJ, so windows far longer than a few
+    /// minutes under heavy load can wrap more than once, which can't be
+    /// detected.
+    pub fn power_between(&self, before: &Snapshot, after: &Snapshot) -> Power {
+        let package_range = self.reader.package_energy_range();
+        let core_range = self.reader.core_energy_range();
+        let domain_range = self.reader.domain_energy_range();
+
+        let package = Self::average_power(before.package, after.package, package_range);
+
+        let domains = before
+            .domains
+            .iter()
+            .zip(&after.domains)
+            .map(|((&domain, &before), (_, &after))| {
+                (domain, Self::average_power(before, after, domain_range))
+            })
+            .collect();
+
+        let package_moved = after.package.0 != before.package.0;
+        let cores_static = before.cores.iter().all(|(core, (before, _))| {
+            after
+                .cores
+                .get(core)
+                .is_none_or(|(after, _)| before == after)
+        });
+
+        // A single window can be static on an idle machine with a coarse
+        // counter, so only several in a row turn them off.
+        if !before.cores.is_empty() {
+            if !cores_static {
+                self.static_windows.store(0, Ordering::Relaxed);
+            } else if package_moved {
+                let _ =
+                    self.static_windows
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                            Some(n.saturating_add(1))
+                        });
+            }
+        }
+
+        if !self.core_counters_working() {
+            return Power {
+                package,
+                cores: BTreeMap::new(),
+                domains,
+            };
+        }
+
+        let cores = before
+            .cores
+            .iter()
+            .filter_map(|(&core, &before)| {
+                let &after = after.cores.get(&core)?;
+                Some((core, Self::average_power(before, after, core_range)))
+            })
+            .collect();
+
+        Power {
+            package,
+            cores,
+            domains,
+        }
+    }
+
+    /// Estimated error of [`Cpu::power_between`] for the same snapshots.
+    ///
+    /// Each value combines the quantization of the counters to the energy
+    /// unit, one more unit where a wrap was corrected, as the exact range
+    /// isn't known to the unit, and the counter update interval blurring
+    /// both ends of the window.
+    pub fn uncertainty_between(&self, before: &Snapshot, after: &Snapshot) -> Uncertainty {
+        let unit = self.energy_unit().unwrap_or(0.0);
+        let uncertainty = |before: (f64, Instant), after: (f64, Instant), range: Option<f64>| {
+            let elapsed = after.1.duration_since(before.1).as_secs_f64();
+            let power = Self::average_power(before, after, range);
+            let wrapped = range.is_some() && after.0 < before.0;
+            let energy_error = match wrapped {
+                true => 2.0 * unit,
+                false => unit,
+            };
+            let time_error = COUNTER_UPDATE_INTERVAL * std::f64::consts::SQRT_2;
+            ((energy_error / elapsed).powi(2) + (power * time_error / elapsed).powi(2)).sqrt()
+        };
+
+        let core_range = self.reader.core_energy_range();
+        let domain_range = self.reader.domain_energy_range();
+        Uncertainty {
+            package: uncertainty(
+                before.package,
+                after.package,
+                self.reader.package_energy_range(),
+            ),
+            cores: before
+                .cores
+                .iter()
+                .filter(|_| self.core_counters_working())
+                .filter_map(|(&core, &before)| {
+                    let &after = after.cores.get(&core)?;
+                    Some((core, uncertainty(before, after, core_range)))
+                })
+                .collect(),
+            domains: before
+                .domains
+                .iter()
+                .zip(&after.domains)
+                .map(|((&domain, &before), (_, &after))| {
+                    (domain, uncertainty(before, after, domain_range))
+                })
+                .collect(),
+        }
+    }
+
+    fn average_power(before: (f64, Instant), after: (f64, Instant), range: Option<f64>) -> f64 {
+        let (energy_before, read_before) = before;
+        let (energy_after, read_after) = after;
+        let elapsed = read_after.duration_since(read_before).as_secs_f64();
+        energy_delta(energy_before, energy_after, range) / elapsed
+    }
+
+    /// CPPC highest performance value per core, as used by the scheduler to
+    /// pick preferred cores. Cores without CPPC information are left out.
+    pub fn highest_perf(&self) -> BTreeMap<u32, u32> {
+        self.core_ids()
+            .into_iter()
+            .filter_map(|core| self.get_highest_perf(core).map(|perf| (core, perf)))
+            .collect()
+    }
+
+    fn get_highest_perf(&self, core: u32) -> Option<u32> {
+        let paths = [
+            format!(
+                "/sys/devices/system/cpu/cpu{}/cpufreq/amd_pstate_highest_perf",
+                core
+            ),
+            format!("/sys/devices/system/cpu/cpu{}/acpi_cppc/highest_perf", core),
+        ];
+
+        paths.iter().find_map(|path| {
+            fs::read_to_string(self.root.path(path))
+                .ok()
+                .and_then(|val| val.trim_end().parse::<u32>().ok())
+        })
+    }
+}
+
+pub(crate) fn read_sysfs(path: impl AsRef<Path>) -> Result<String> {
+    Root::system().read(path)
+}
+
+/// Like [`energy_delta`] in energy units. A counter going backwards without
+/// a known range counts as nothing consumed.
+pub fn tick_delta(before: u64, after: u64, range: Option<u64>) -> u64 {
+    match range {
+        Some(range) if after < before => range - before + after,
+        _ => after.saturating_sub(before),
+    }
+}
+
+/// Energy consumed between two readings of a counter that wraps to zero after
+/// `range` joules.
+pub fn energy_delta(before: f64, after: f64, range: Option<f64>) -> f64 {
+    match range {
+        Some(range) if after < before => after + range - before,
+        _ => after - before,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::{self, Fixture};
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    /// The simulated counters wrap after this many joules, like Zen's.
+    const RANGE: f64 = 65536.0;
+
+    fn topology(listing: &str) -> (bool, Vec<u32>, Threads) {
+        Cpu::read_topology(Fixture::from_listing(listing).root()).unwrap()
+    }
+
+    fn snapshot(start: Instant, package: (f64, f64), cores: &[(u32, f64, f64)]) -> Snapshot {
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        Snapshot {
+            package: (package.0, at(package.1)),
+            cores: cores
+                .iter()
+                .map(|&(core, joules, secs)| (core, (joules, at(secs))))
+                .collect(),
+            domains: BTreeMap::new(),
+            idle: BTreeMap::new(),
+        }
+    }
+
+    /// A part with many cores whose reads remember the thread they ran on.
+    #[derive(Debug, Default)]
+    struct ManyCores {
+        readers: Arc<Mutex<BTreeMap<u32, thread::ThreadId>>>,
+    }
+
+    impl EnergyReader for ManyCores {
+        fn name(&self) -> &'static str {
+            "many"
+        }
+
+        fn core_ids(&self) -> Vec<u32> {
+            (0..64).collect()
+        }
+
+        fn package_energy(&self) -> Result<f64> {
+            Ok(1.0)
+        }
+
+        fn core_energy(&self, core: u32) -> Result<f64> {
+            if core == 40 {
+                return Err(Error::io(
+                    "/dev/cpu/40/msr",
+                    std::io::ErrorKind::NotFound.into(),
+                ));
+            }
+            let mut readers = self.readers.lock().unwrap();
+            readers.insert(core, thread::current().id());
+            Ok(f64::from(core))
+        }
+
+        fn package_energy_range(&self) -> Option<f64> {
+            None
+        }
+
+        fn core_energy_range(&self) -> Option<f64> {
+            None
+        }
+    }
+
+    #[test]
+    fn sweeps_many_cores_at_once() {
+        let fixture = Fixture::from_listing(fixture::DUAL_SOCKET);
+        let reader = ManyCores::default();
+        let readers = Arc::clone(&reader.readers);
+        let cpu = Cpu::with_reader(Box::new(reader), fixture.root().clone()).unwrap();
+        let energy = cpu.core_energy().unwrap();
+
+        assert_eq!(energy.len(), 63);
+        assert!(!energy.contains_key(&40));
+        assert!(energy
+            .iter()
+            .all(|(&core, &(joules, _))| joules == f64::from(core)));
+        let (_, time) = energy[&0];
+        assert!(energy.values().all(|&(_, other)| other == time));
+
+        let threads = readers
+            .lock()
+            .unwrap()
+            .values()
+            .copied()
+            .collect::<HashSet<_>>();
+        assert_eq!(threads.len(), 64 / SWEEP_SHARE);
+    }
+
+    #[test]
+    fn sweeps_by_ccd() {
+        let fixture = Fixture::from_listing(fixture::DUAL_SOCKET);
+        let reader = ManyCores::default();
+        let readers = Arc::clone(&reader.readers);
+        let mut cpu = Cpu::with_reader(Box::new(reader), fixture.root().clone()).unwrap();
+        // Two CCDs of 32 cores, left unpinned by an empty mask.
+        cpu.ccd_readers = vec![
+            (CpuMask::new(&[]), (0..32).collect()),
+            (CpuMask::new(&[]), (32..64).collect()),
+        ];
+        assert_eq!(cpu.core_energy().unwrap().len(), 63);
+
+        let readers = readers.lock().unwrap();
+        assert!((0..32).all(|core| readers[&core] == readers[&0]));
+        assert!((33..64)
+            .filter(|&core| core != 40)
+            .all(|core| readers[&core] == readers[&32]));
+        assert_ne!(readers[&0], readers[&32]);
+    }
+
+    #[test]
+    fn pairs_smt_siblings() {
+        let (smt_enabled, online, threads) = topology(fixture::ZEN3_SMT);
+        assert!(smt_enabled);
+        assert_eq!(online, (0..32).collect::<Vec<_>>());
+        assert_eq!(threads.len(), 16);
+        assert_eq!(threads[&0], [0, 16]);
+        assert_eq!(threads[&15], [15, 31]);
+    }
+
+    #[test]
+    fn one_thread_per_core_without_smt() {
+        let (smt_enabled, online, threads) = topology(fixture::ZEN2_SMT_OFF);
+        assert!(!smt_enabled);
+        assert_eq!(online.len(), 8);
+        assert!(threads.iter().all(|(core, threads)| threads == &[*core]));
+    }
+
+    #[test]
+    fn leaves_out_offline_threads() {
+        let (_, online, threads) = topology(fixture::ZEN3_OFFLINE);
+        assert_eq!(online, [0, 1, 2, 4, 5, 6, 7, 8, 9, 10, 12, 14, 15]);
+        assert_eq!(
+            threads.keys().copied().collect::<Vec<_>>(),
+            [0, 1, 2, 4, 5, 6, 7]
+        );
+        assert_eq!(threads[&4], [4, 12]);
+        assert_eq!(threads[&5], [5]);
+    }
+
+    #[test]
+    fn counts_cores_of_every_socket() {
+        let (_, online, threads) = topology(fixture::DUAL_SOCKET);
+        assert_eq!(online.len(), 32);
+        assert_eq!(threads.len(), 16);
+        assert_eq!(threads[&8], [8, 24]);
+    }
+
+    #[test]
+    fn rejects_malformed_cpu_lists() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/sys/devices/system/cpu/smt/control", "off\n")
+            .file("/sys/devices/system/cpu/online", "0-\n");
+        let err = Cpu::read_topology(fixture.root()).unwrap_err();
+        assert!(
+            matches!(err, Error::Parse { ref value, .. } if value == "0-"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn corrects_a_single_wrap() {
+        assert_eq!(energy_delta(10.0, 25.0, Some(RANGE)), 15.0);
+        assert_eq!(energy_delta(RANGE - 5.0, 10.0, Some(RANGE)), 15.0);
+        // Without a range a counter going backwards is taken as is.
+        assert_eq!(energy_delta(20.0, 10.0, None), -10.0);
+
+        assert_eq!(tick_delta(u32::MAX as u64 - 4, 10, Some(1 << 32)), 15);
+        assert_eq!(tick_delta(20, 10, None), 0);
+    }
+
+    #[test]
+    fn averages_each_counter_over_its_own_window() {
+        let cpu = Cpu::simulated(Profile::Idle);
+        let start = Instant::now();
+        let before = snapshot(start, (100.0, 0.0), &[(0, 1.0, 0.0), (1, 2.0, 0.5)]);
+        let after = snapshot(start, (150.0, 2.0), &[(0, 3.0, 2.0), (1, 5.0, 2.5)]);
+
+        let power = cpu.power_between(&before, &after);
+        assert_eq!(power.package, 25.0);
+        assert_eq!(power.cores[&0], 1.0);
+        assert_eq!(power.cores[&1], 1.5);
+    }
+
+    #[test]
+    fn power_across_a_wrap() {
+        let cpu = Cpu::simulated(Profile::Idle);
+        let start = Instant::now();
+        let before = snapshot(start, (RANGE - 10.0, 0.0), &[(0, RANGE - 1.0, 0.0)]);
+        let after = snapshot(start, (30.0, 1.0), &[(0, 1.0, 1.0)]);
+
+        let power = cpu.power_between(&before, &after);
+        assert_eq!(power.package, 40.0);
+        assert_eq!(power.cores[&0], 2.0);
+
+        // The corrected wrap counts one more unit of uncertainty.
+        let uncertainty = cpu.uncertainty_between(&before, &after);
+        let plain = cpu.uncertainty_between(
+            &snapshot(start, (0.0, 0.0), &[]),
+            &snapshot(start, (40.0, 1.0), &[]),
+        );
+        assert!(uncertainty.package > plain.package);
+    }
+
+    #[test]
+    fn stuck_core_counters_are_turned_off() {
+        let cpu = Cpu::simulated(Profile::Idle);
+        let start = Instant::now();
+        let window = |n: f64, core: f64| {
+            let before = snapshot(start, (10.0 * n, n), &[(0, core, n), (1, 7.0, n)]);
+            let after = snapshot(
+                start,
+                (10.0 * (n + 1.0), n + 1.0),
+                &[(0, core, n + 1.0), (1, 7.0, n + 1.0)],
+            );
+            cpu.power_between(&before, &after)
+        };
+
+        // One static window is an idle core, not a dead counter.
+        assert_eq!(window(0.0, 7.0).cores[&0], 0.0);
+        assert!(cpu.has_core_counters());
+        window(1.0, 7.0);
+        let power = window(2.0, 7.0);
+        assert_eq!(power.package, 10.0);
+        assert!(power.cores.is_empty());
+        assert!(!cpu.has_core_counters());
+        assert!(cpu.core_ids().is_empty());
+
+        // They are still read and come back once they move.
+        let before = snapshot(start, (30.0, 3.0), &[(0, 7.0, 3.0), (1, 7.0, 3.0)]);
+        let after = snapshot(start, (40.0, 4.0), &[(0, 9.0, 4.0), (1, 7.0, 4.0)]);
+        assert_eq!(cpu.power_between(&before, &after).cores[&0], 2.0);
+        assert!(cpu.has_core_counters());
+    }
+
+    #[test]
+    fn wrapped_ticks() {
+        let cpu = Cpu::simulated(Profile::Idle);
+        let start = Instant::now();
+        let range = 1_u64 << 32;
+        let before = RawSnapshot {
+            package: (range - 100, start),
+            cores: BTreeMap::from([(0, (5, start))]),
+            domains: BTreeMap::new(),
+        };
+        let after = RawSnapshot {
+            package: (65436, start + Duration::from_secs(1)),
+            cores: BTreeMap::from([(0, (65541, start))]),
+            domains: BTreeMap::new(),
+        };
+
+        let energy = cpu.ticks_between(&before, &after).unwrap();
+        assert_eq!(energy.package, 65536);
+        assert_eq!(energy.joules(energy.package), 1.0);
+        assert_eq!(energy.cores[&0], 65536);
+        assert_eq!(energy.duration, Duration::from_secs(1));
+
+        let mut total = energy.clone();
+        total.add(&energy);
+        assert_eq!(total.package, 2 * 65536);
+        assert_eq!(total.duration, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn splits_core_power_by_busy_time() {
+        let cpu = Cpu::simulated(Profile::Idle);
+        let times = |user: u64| CpuTimes::parse(&format!("{} 0 0 100 0 0 0 0", user)).unwrap();
+        let before = BTreeMap::from([(0, times(0)), (8, times(0)), (1, times(0)), (9, times(0))]);
+        let after = BTreeMap::from([(0, times(30)), (8, times(10)), (1, times(0)), (9, times(0))]);
+        let core_power = BTreeMap::from([(0, 4.0), (1, 2.0)]);
+
+        let threads = cpu.thread_power(&core_power, &before, &after);
+        assert_eq!(threads[&0], 3.0);
+        assert_eq!(threads[&8], 1.0);
+        // Neither thread of core 1 was busy.
+        assert_eq!(threads[&1], 1.0);
+        assert_eq!(threads[&9], 1.0);
+    }
+}
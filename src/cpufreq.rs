@@ -0,0 +1,91 @@
+//! Core frequency and idle state residency from the cpufreq and cpuidle
+//! sysfs interfaces.
+
+use std::{fs, time::Duration};
+
+use crate::sysfs::Root;
+
+/// Current frequency of `core` in MHz, as last requested or observed by
+/// cpufreq.
+pub fn current_frequency(root: &Root, core: u32) -> Option<f64> {
+    ["scaling_cur_freq", "cpuinfo_cur_freq"]
+        .iter()
+        .find_map(|file| {
+            let path = format!("/sys/devices/system/cpu/cpu{}/cpufreq/{}", core, file);
+            fs::read_to_string(root.path(path))
+                .ok()?
+                .trim_end()
+                .parse::<f64>()
+                .ok()
+        })
+        .map(|khz| khz / 1000.0)
+}
+
+/// Total time `core` spent in idle states since boot. Polling isn't an idle
+/// state, the core keeps running the whole time.
+pub fn idle_time(root: &Root, core: u32) -> Option<Duration> {
+    let dir = format!("/sys/devices/system/cpu/cpu{}/cpuidle", core);
+    let states = fs::read_dir(root.path(dir)).ok()?;
+
+    let mut total = Duration::ZERO;
+    let mut found = false;
+    for state in states.flatten() {
+        let path = state.path();
+        let name = fs::read_to_string(path.join("name")).unwrap_or_default();
+        if name.trim_end() == "POLL" {
+            continue;
+        }
+        let Some(micros) = fs::read_to_string(path.join("time"))
+            .ok()
+            .and_then(|time| time.trim_end().parse::<u64>().ok())
+        else {
+            continue;
+        };
+        total += Duration::from_micros(micros);
+        found = true;
+    }
+
+    found.then_some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    #[test]
+    fn falls_back_to_the_hardware_frequency() {
+        let fixture = Fixture::new();
+        fixture
+            .file(
+                "/sys/devices/system/cpu/cpu0/cpufreq/scaling_cur_freq",
+                "3400000\n",
+            )
+            .file(
+                "/sys/devices/system/cpu/cpu1/cpufreq/cpuinfo_cur_freq",
+                "2200000\n",
+            );
+        assert_eq!(current_frequency(fixture.root(), 0), Some(3400.0));
+        assert_eq!(current_frequency(fixture.root(), 1), Some(2200.0));
+        assert_eq!(current_frequency(fixture.root(), 2), None);
+    }
+
+    #[test]
+    fn leaves_polling_out_of_idle_time() {
+        let fixture = Fixture::new();
+        let state = |index: u32, name: &str, time: &str| {
+            let dir = format!("/sys/devices/system/cpu/cpu3/cpuidle/state{}", index);
+            fixture
+                .file(format!("{}/name", dir), &format!("{}\n", name))
+                .file(format!("{}/time", dir), &format!("{}\n", time));
+        };
+        state(0, "POLL", "5000000");
+        state(1, "C1", "1500000");
+        state(2, "C2", "250000");
+        assert_eq!(
+            idle_time(fixture.root(), 3),
+            Some(Duration::from_micros(1_750_000))
+        );
+        assert_eq!(idle_time(fixture.root(), 0), None);
+    }
+}
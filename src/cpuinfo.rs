@@ -0,0 +1,119 @@
+use std::{fs, io};
+
+use crate::sysfs::Root;
+
+/// Identification of the first CPU in `/proc/cpuinfo`.
+#[derive(Debug, Clone, Default)]
+pub struct CpuInfo {
+    pub vendor: String,
+    pub family: u32,
+    pub model: u32,
+    pub model_name: String,
+}
+
+impl CpuInfo {
+    pub fn read(root: &Root) -> io::Result<Self> {
+        let cpuinfo = fs::read_to_string(root.path("/proc/cpuinfo"))?;
+        Ok(Self::parse(&cpuinfo))
+    }
+
+    pub fn parse(cpuinfo: &str) -> Self {
+        let mut info = Self::default();
+
+        // Only the first processor block is of interest, all others match it.
+        for line in cpuinfo.lines().take_while(|line| !line.trim().is_empty()) {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim() {
+                "vendor_id" => info.vendor = value.to_owned(),
+                "cpu family" => info.family = value.parse().unwrap_or_default(),
+                "model" => info.model = value.parse().unwrap_or_default(),
+                "model name" => info.model_name = value.to_owned(),
+                _ => {}
+            }
+        }
+
+        info
+    }
+
+    /// Microarchitecture of AMD parts, e.g. `Zen 3`, `None` for anything
+    /// else or models not known yet.
+    pub fn generation(&self) -> Option<&'static str> {
+        let generation = match (self.vendor.as_str(), self.family, self.model) {
+            ("HygonGenuine", 0x18, _) => "Zen (Dhyana)",
+            ("AuthenticAMD", 0x17, 0x08 | 0x18) => "Zen+",
+            ("AuthenticAMD", 0x17, 0x00..=0x2F) => "Zen",
+            ("AuthenticAMD", 0x17, _) => "Zen 2",
+            ("AuthenticAMD", 0x19, 0x40..=0x4F) => "Zen 3+",
+            ("AuthenticAMD", 0x19, 0x10..=0x1F | 0x60..=0x7F) => "Zen 4",
+            ("AuthenticAMD", 0x19, 0xA0..=0xAF) => "Zen 4c",
+            ("AuthenticAMD", 0x19, 0x00..=0x5F) => "Zen 3",
+            ("AuthenticAMD", 0x1A, _) => "Zen 5",
+            _ => return None,
+        };
+        Some(generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    #[test]
+    fn reads_the_first_processor() {
+        let cpuinfo = "\
+processor\t: 0
+vendor_id\t: AuthenticAMD
+cpu family\t: 25
+model\t\t: 33
+model name\t: AMD Ryzen 9 5950X 16-Core Processor
+
+processor\t: 1
+vendor_id\t: GenuineIntel
+";
+        let info = CpuInfo::parse(cpuinfo);
+        assert_eq!(info.vendor, "AuthenticAMD");
+        assert_eq!(info.family, 0x19);
+        assert_eq!(info.model, 0x21);
+        assert_eq!(info.model_name, "AMD Ryzen 9 5950X 16-Core Processor");
+    }
+
+    #[test]
+    fn knows_zen_generations() {
+        let generation = |vendor: &str, family, model| {
+            CpuInfo {
+                vendor: vendor.to_owned(),
+                family,
+                model,
+                ..CpuInfo::default()
+            }
+            .generation()
+        };
+        assert_eq!(generation("AuthenticAMD", 0x17, 0x01), Some("Zen"));
+        assert_eq!(generation("AuthenticAMD", 0x17, 0x08), Some("Zen+"));
+        assert_eq!(generation("AuthenticAMD", 0x17, 0x71), Some("Zen 2"));
+        assert_eq!(generation("AuthenticAMD", 0x19, 0x21), Some("Zen 3"));
+        assert_eq!(generation("AuthenticAMD", 0x19, 0x44), Some("Zen 3+"));
+        assert_eq!(generation("AuthenticAMD", 0x19, 0x61), Some("Zen 4"));
+        assert_eq!(generation("AuthenticAMD", 0x1A, 0x44), Some("Zen 5"));
+        assert_eq!(generation("AuthenticAMD", 0x15, 0x02), None);
+        assert_eq!(generation("GenuineIntel", 6, 0x97), None);
+    }
+
+    #[test]
+    fn reads_below_the_root() {
+        let fixture = Fixture::new();
+        assert!(CpuInfo::read(fixture.root()).is_err());
+
+        fixture.file(
+            "/proc/cpuinfo",
+            "vendor_id\t: AuthenticAMD\ncpu family\t: 23\nmodel\t\t: 113\n",
+        );
+        let info = CpuInfo::read(fixture.root()).unwrap();
+        assert_eq!(info.generation(), Some("Zen 2"));
+    }
+}
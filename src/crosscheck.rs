@@ -0,0 +1,108 @@
+//! Side-by-side comparison of every available power source.
+
+use std::{fs, path::Path, thread, time::Duration};
+
+use crate::{BackendKind, Cpu, Result};
+
+/// What one source measured over the window.
+#[derive(Debug)]
+pub struct Reading {
+    pub source: &'static str,
+    pub result: Result<SourcePower>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SourcePower {
+    pub package: f64,
+    /// Sum over cores, `None` if the source has no per-core data.
+    pub cores: Option<f64>,
+}
+
+/// Samples all energy backends and the zenpower SVI2 telemetry over the
+/// same window.
+pub fn cross_check(interval: Duration) -> Vec<Reading> {
+    let sources = [
+        ("msr", BackendKind::Msr),
+        ("powercap", BackendKind::Powercap),
+    ];
+
+    let cpus = sources
+        .iter()
+        .map(|&(name, kind)| (name, Cpu::new(kind)))
+        .collect::<Vec<_>>();
+
+    // Read every source back to back so they cover the same window.
+    let before = cpus
+        .iter()
+        .map(|(_, cpu)| cpu.as_ref().ok().map(Cpu::snapshot))
+        .collect::<Vec<_>>();
+    let zenpower_before = zenpower();
+
+    thread::sleep(interval);
+
+    let after = cpus
+        .iter()
+        .map(|(_, cpu)| cpu.as_ref().ok().map(Cpu::snapshot))
+        .collect::<Vec<_>>();
+    let zenpower_after = zenpower();
+
+    let mut readings = cpus
+        .into_iter()
+        .zip(before.into_iter().zip(after))
+        .map(|((source, cpu), (before, after))| {
+            let result = cpu.and_then(|cpu| {
+                let (before, after) = (before.unwrap()?, after.unwrap()?);
+                let power = cpu.power_between(&before, &after);
+                let cores = (!power.cores.is_empty()).then(|| power.cores.values().sum());
+                Ok(SourcePower {
+                    package: power.package,
+                    cores,
+                })
+            });
+            Reading { source, result }
+        })
+        .collect::<Vec<_>>();
+
+    // SVI2 values are instantaneous, average the readings at both ends.
+    if let (Some(before), Some(after)) = (zenpower_before, zenpower_after) {
+        readings.push(Reading {
+            source: "zenpower",
+            result: Ok(SourcePower {
+                package: (before.package + after.package) / 2.0,
+                cores: before
+                    .cores
+                    .zip(after.cores)
+                    .map(|(before, after)| (before + after) / 2.0),
+            }),
+        });
+    }
+
+    readings
+}
+
+/// Core plus SoC power from the SVI2 telemetry of the `zenpower` driver.
+fn zenpower() -> Option<SourcePower> {
+    let hwmon = fs::read_dir("/sys/class/hwmon").ok()?;
+
+    hwmon.flatten().find_map(|entry| {
+        let dir = entry.path();
+        let name = fs::read_to_string(dir.join("name")).ok()?;
+        if name.trim_end() != "zenpower" {
+            return None;
+        }
+
+        let core = read_microwatts(&dir.join("power1_input"))?;
+        let soc = read_microwatts(&dir.join("power2_input")).unwrap_or_default();
+
+        Some(SourcePower {
+            package: core + soc,
+            cores: Some(core),
+        })
+    })
+}
+
+fn read_microwatts(path: &Path) -> Option<f64> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim_end().parse::<f64>().ok()?;
+    Some(value / 1_000_000.0)
+}
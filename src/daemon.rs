@@ -0,0 +1,540 @@
+//! Daemon mode: one process samples continuously and serves the readings
+//! over a Unix socket, so status bars and scripts don't each need MSR access.
+//!
+//! Clients send one JSON request per line and get one JSON response per line
+//! back, on the same connection for as long as they like:
+//!
+//! ```text
+//! {"command": "latest"}
+//! {"ok": true, "sample": {...}}
+//! {"command": "history", "count": 10}
+//! {"ok": true, "samples": [{...}, ...]}
+//! {"command": "ledger", "day": "2026-10-14"}
+//! {"ok": true, "processes": [{"name": "firefox", "joules": 5120.4}, ...]}
+//! {"command": "subscribe", "cores": [0, 1], "package_only": false}
+//! {"ok": true, "subscribed": true}
+//! {"ok": true, "sample": {...}}
+//! {"ok": true, "sample": {...}}
+//! ```
+//!
+//! Samples are the same objects `--format json` prints, with their
+//! `schema_version`, and `--schema daemon` prints the JSON Schema of the
+//! responses. History is oldest first. The [`Ledger`] has the energy per
+//! process name, most first, on the day given or over every day without
+//! one. Every sample also goes into the [`History`] of minutes and hours
+//! `history query` reads. Failed requests get `{"ok": false, "error": "..."}`.
+//!
+//! After `subscribe` the connection only carries every new sample as it is
+//! taken, with only the cores of `cores` and without any per-core or
+//! per-group values with `package_only`, both optional. A subscriber that
+//! doesn't keep up misses samples past [`SUBSCRIBER_BACKLOG`] rather than
+//! holding up the others.
+//!
+//! The ledger has the process names of every user, so the socket is only
+//! for the daemon's user and group unless [`Access`] says otherwise.
+//! Requests are limited to [`MAX_REQUEST`] bytes and [`MAX_CLIENTS`] are
+//! served at once.
+//!
+//! `python/ryzen_wattage.py` speaks this from Python, with an iterator and
+//! an async generator over `subscribe` and a live chart for notebooks.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::{self as unix_fs, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    history::History,
+    json,
+    ledger::Ledger,
+    metrics::METRICS,
+    output::{self, Sample, View},
+};
+
+/// Samples kept for `history` requests.
+pub const HISTORY: usize = 300;
+/// Longest request line, longer ones close the connection.
+pub const MAX_REQUEST: usize = 4096;
+/// Clients connected at once, more are turned away.
+pub const MAX_CLIENTS: usize = 64;
+/// Samples waiting for a subscriber before newer ones are dropped.
+pub const SUBSCRIBER_BACKLOG: usize = 16;
+
+extern "C" {
+    fn geteuid() -> u32;
+    fn umask(mask: u32) -> u32;
+}
+
+/// Who may connect to the socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    /// Permission bits, `0o660` by default.
+    pub mode: u32,
+    /// Group the socket belongs to, the daemon's if `None`, by name or id.
+    pub group: Option<String>,
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Self {
+            mode: 0o660,
+            group: None,
+        }
+    }
+}
+
+/// Octal permission bits like `660` or `0o660`.
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("invalid socket mode `{}`, expected octal like 660", s))
+}
+
+/// The id of `group`, a name from `/etc/group` or a number.
+fn group_id(group: &str) -> Option<u32> {
+    if let Ok(id) = group.parse() {
+        return Some(id);
+    }
+    let groups = fs::read_to_string("/etc/group").ok()?;
+    groups.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id = fields.nth(1)?;
+        (name == group).then(|| id.parse().ok()).flatten()
+    })
+}
+
+/// `/run/ryzen-wattage.sock` for root, `$XDG_RUNTIME_DIR/ryzen-wattage.sock`
+/// for everyone else.
+pub fn default_socket_path() -> PathBuf {
+    // SAFETY: geteuid can't fail and has no side effects.
+    let root = unsafe { geteuid() } == 0;
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !root && !dir.is_empty())
+        .map(PathBuf::from);
+    runtime_dir
+        .unwrap_or_else(|| PathBuf::from("/run"))
+        .join("ryzen-wattage.sock")
+}
+
+/// Binds `path`, replacing a socket no daemon listens on anymore, with the
+/// group and mode of `access`.
+pub fn bind(path: &Path, access: &Access) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket())
+        && UnixStream::connect(path).is_err()
+    {
+        fs::remove_file(path)?;
+    }
+    let group = match &access.group {
+        Some(group) => Some(group_id(group).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unknown group `{}`", group),
+            )
+        })?),
+        None => None,
+    };
+
+    // Only the owner can connect until the access is set up.
+    // SAFETY: umask can't fail, it only swaps the mask of this process.
+    let mask = unsafe { umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { umask(mask) };
+    let listener = listener?;
+
+    if group.is_some() {
+        unix_fs::chown(path, None, group)?;
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(access.mode))?;
+    Ok(listener)
+}
+
+/// Recent samples, the ledger and the history, shared between the sampling loop and the
+/// clients.
+#[derive(Debug, Default)]
+pub struct Daemon {
+    /// Samples as JSON, serialized once when they are recorded.
+    history: Mutex<VecDeque<String>>,
+    ledger: Mutex<Ledger>,
+    store: Mutex<History>,
+    clients: AtomicUsize,
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+/// A connection after `subscribe`, waiting for samples.
+#[derive(Debug)]
+struct Subscriber {
+    view: View,
+    samples: SyncSender<String>,
+}
+
+impl Daemon {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continues `ledger` and `history`.
+    pub fn resuming(ledger: Ledger, history: History) -> Self {
+        Self {
+            ledger: Mutex::new(ledger),
+            store: Mutex::new(history),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the joules per process name of one window to `day`.
+    pub fn account(&self, day: &str, energy: &BTreeMap<String, f64>) {
+        let mut ledger = self.ledger.lock().unwrap();
+        for (name, joules) in energy {
+            ledger.add(day, name, *joules);
+        }
+    }
+
+    /// Trims the ledger to the top of every day and writes it to disk.
+    pub fn save_ledger(&self) -> io::Result<()> {
+        self.ledger.lock().unwrap().save()
+    }
+
+    /// Writes the minutes and hours to disk.
+    pub fn save_history(&self) -> io::Result<()> {
+        self.store.lock().unwrap().save()
+    }
+
+    pub fn record(&self, sample: &Sample) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back(output::json(sample));
+        drop(history);
+        self.store.lock().unwrap().add(sample);
+
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            let sample = output::json_view(sample, &subscriber.view);
+            !matches!(
+                subscriber.samples.try_send(sample),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+    }
+
+    /// Serves every client on its own thread, up to [`MAX_CLIENTS`] at
+    /// once, forever.
+    pub fn serve(self: &Arc<Self>, listener: &UnixListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            if self.clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                self.clients.fetch_sub(1, Ordering::SeqCst);
+                let _ = writeln!(stream, "{}", error_response("too many clients"));
+                continue;
+            }
+            let daemon = Arc::clone(self);
+            thread::spawn(move || {
+                // A client going away mid-request is its problem, not ours.
+                let _ = daemon.handle(stream);
+                daemon.clients.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+
+    fn handle(&self, stream: UnixStream) -> io::Result<()> {
+        let mut writer = &stream;
+        let mut reader = BufReader::new(&stream);
+        loop {
+            let mut line = String::new();
+            let limit = MAX_REQUEST as u64 + 1;
+            if reader.by_ref().take(limit).read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            // Without a newline in reach the rest would pile up in memory.
+            if line.len() > MAX_REQUEST {
+                let error = format!("request longer than {} bytes", MAX_REQUEST);
+                return writeln!(writer, "{}", error_response(&error));
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match subscription(line.trim_end()) {
+                Ok(Some(view)) => return self.stream(writer, view),
+                Ok(None) => writeln!(writer, "{}", self.respond(line.trim_end()))?,
+                Err(err) => writeln!(writer, "{}", error_response(&err))?,
+            }
+        }
+    }
+
+    /// Writes every new sample to `writer` until the client goes away.
+    fn stream(&self, mut writer: &UnixStream, view: View) -> io::Result<()> {
+        let (samples, receiver) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Subscriber { view, samples });
+        writeln!(writer, "{{\"ok\":true,\"subscribed\":true}}")?;
+        // The sender only goes with the daemon, or once a write failed.
+        for sample in receiver {
+            writeln!(writer, "{{\"ok\":true,\"sample\":{}}}", sample)?;
+        }
+        Ok(())
+    }
+
+    /// The response line to one request line.
+    pub fn respond(&self, request: &str) -> String {
+        match self.try_respond(request) {
+            Ok(response) => response,
+            Err(err) => error_response(&err),
+        }
+    }
+
+    fn try_respond(&self, request: &str) -> Result<String, String> {
+        let request = json::parse(request).map_err(|err| format!("invalid request: {}", err))?;
+        let command = request
+            .get("command")
+            .and_then(json::Value::as_str)
+            .ok_or("missing `command`")?;
+        if command == "ledger" {
+            let day = match request.get("day") {
+                Some(json::Value::Null) | None => None,
+                Some(day) => Some(day.as_str().ok_or("`day` has to be a string")?),
+            };
+            let processes = self
+                .ledger
+                .lock()
+                .unwrap()
+                .entries(day)
+                .iter()
+                .map(|(name, joules)| {
+                    format!(
+                        "{{\"name\":{},\"joules\":{}}}",
+                        output::json_string(name),
+                        output::json_number(*joules)
+                    )
+                })
+                .collect::<Vec<_>>();
+            return Ok(format!(
+                "{{\"ok\":true,\"processes\":[{}]}}",
+                processes.join(",")
+            ));
+        }
+        let history = self.history.lock().unwrap();
+
+        match command {
+            "latest" => {
+                let sample = history.back().ok_or("no sample taken yet")?;
+                Ok(format!("{{\"ok\":true,\"sample\":{}}}", sample))
+            }
+            "history" => {
+                let count = match request.get("count") {
+                    Some(count) => count
+                        .as_f64()
+                        .filter(|count| *count >= 0.0 && count.fract() == 0.0)
+                        .ok_or("`count` has to be a whole number")?
+                        as usize,
+                    None => history.len(),
+                };
+                let skip = history.len().saturating_sub(count);
+                let samples = history.iter().skip(skip).cloned().collect::<Vec<_>>();
+                Ok(format!(
+                    "{{\"ok\":true,\"samples\":[{}]}}",
+                    samples.join(",")
+                ))
+            }
+            other => Err(format!(
+                "unknown command `{}`, expected latest, history, ledger or subscribe",
+                other
+            )),
+        }
+    }
+}
+
+/// What a `subscribe` request filters for, `None` for other requests.
+fn subscription(request: &str) -> Result<Option<View>, String> {
+    let Ok(request) = json::parse(request) else {
+        return Ok(None);
+    };
+    if request.get("command").and_then(json::Value::as_str) != Some("subscribe") {
+        return Ok(None);
+    }
+    let cores = match request.get("cores") {
+        Some(json::Value::Null) | None => None,
+        Some(cores) => Some(
+            cores
+                .as_array()
+                .and_then(|cores| {
+                    cores
+                        .iter()
+                        .map(|core| {
+                            core.as_f64()
+                                .filter(|core| *core >= 0.0 && core.fract() == 0.0)
+                                .map(|core| core as u32)
+                        })
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or("`cores` has to be an array of core ids")?,
+        ),
+    };
+    let package_only = match request.get("package_only") {
+        Some(json::Value::Null) | None => false,
+        Some(package_only) => package_only
+            .as_bool()
+            .ok_or("`package_only` has to be true or false")?,
+    };
+    Ok(Some(View {
+        cores,
+        columns: package_only.then(|| {
+            METRICS
+                .iter()
+                .filter(|metric| metric.label.is_none())
+                .map(|metric| metric.name)
+                .collect()
+        }),
+        ..View::default()
+    }))
+}
+
+fn error_response(error: &str) -> String {
+    format!("{{\"ok\":false,\"error\":{}}}", output::json_string(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ryzen-wattage-{}-{}.sock", name, process::id()))
+    }
+
+    #[test]
+    fn binds_with_the_mode_asked_for() {
+        let path = socket_path("mode");
+        let _listener = bind(&path, &Access::default()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o660);
+
+        assert_eq!(parse_mode("0o640"), Ok(0o640));
+        assert!(parse_mode("888").is_err());
+        assert!(parse_mode("1777").is_err());
+        let access = Access {
+            group: Some("no-such-group-here".to_owned()),
+            ..Access::default()
+        };
+        assert!(bind(&socket_path("group"), &access).is_err());
+    }
+
+    #[test]
+    fn closes_on_requests_past_the_limit() {
+        let daemon = Daemon::new();
+        let (client, server) = UnixStream::pair().unwrap();
+        let handler = thread::spawn(move || daemon.handle(server));
+
+        let mut writer = &client;
+        writeln!(writer, "{{\"command\":\"latest\"}}").unwrap();
+        writer.write_all(&vec![b' '; MAX_REQUEST + 10]).unwrap();
+        let lines = BufReader::new(&client)
+            .lines()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        handler.join().unwrap().unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("no sample taken yet"));
+        assert!(lines[1].contains("request longer than 4096 bytes"));
+    }
+
+    #[test]
+    fn streams_what_subscribers_filter_for() {
+        let daemon = Arc::new(Daemon::new());
+        let (client, server) = UnixStream::pair().unwrap();
+        let handler = {
+            let daemon = Arc::clone(&daemon);
+            thread::spawn(move || daemon.handle(server))
+        };
+
+        writeln!(&client, "{{\"command\":\"subscribe\",\"cores\":[1]}}").unwrap();
+        let mut lines = BufReader::new(&client).lines();
+        let mut next = || json::parse(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(next().get("subscribed"), Some(&json::Value::Bool(true)));
+        daemon.record(&Sample::fixture());
+        let sample = next();
+        let cores = sample
+            .get("sample")
+            .and_then(|sample| sample.get("cores_watts"));
+        assert_eq!(
+            cores
+                .and_then(json::Value::as_object)
+                .map(|cores| cores.len()),
+            Some(1)
+        );
+        assert!(cores.and_then(|cores| cores.get("1")).is_some());
+
+        // Gone once a sample can't be written anymore.
+        drop(client);
+        daemon.record(&Sample::fixture());
+        assert!(handler.join().unwrap().is_err());
+        daemon.record(&Sample::fixture());
+        assert!(daemon.subscribers.lock().unwrap().is_empty());
+
+        assert!(subscription("{\"command\":\"subscribe\",\"cores\":[-1]}").is_err());
+        let view = subscription("{\"command\":\"subscribe\",\"package_only\":true}")
+            .unwrap()
+            .unwrap();
+        assert!(!view.columns.unwrap().contains(&"cores_watts"));
+    }
+
+    #[test]
+    fn turns_away_clients_past_the_limit() {
+        let path = socket_path("clients");
+        let listener = bind(&path, &Access::default()).unwrap();
+        let daemon = Arc::new(Daemon::new());
+        thread::spawn(move || daemon.serve(&listener));
+
+        let ask = |stream: &UnixStream| {
+            // Turned away clients may be gone before the request is out.
+            let _ = writeln!(&*stream, "{{\"command\":\"history\"}}");
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            line
+        };
+        let clients = (0..MAX_CLIENTS)
+            .map(|_| UnixStream::connect(&path).unwrap())
+            .collect::<Vec<_>>();
+        for client in &clients {
+            assert_eq!(ask(client).trim_end(), "{\"ok\":true,\"samples\":[]}");
+        }
+
+        let mut line = String::new();
+        let turned_away = UnixStream::connect(&path).unwrap();
+        BufReader::new(&turned_away).read_line(&mut line).unwrap();
+        assert!(line.contains("too many clients"));
+
+        // A slot frees up once a client leaves.
+        drop(clients);
+        let answer = loop {
+            let answer = ask(&UnixStream::connect(&path).unwrap());
+            if !answer.contains("too many clients") {
+                break answer;
+            }
+            thread::yield_now();
+        };
+        fs::remove_file(&path).unwrap();
+        assert!(answer.contains("\"ok\":true"));
+    }
+}
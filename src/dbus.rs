@@ -0,0 +1,669 @@
+//! Just enough of the D-Bus wire protocol to call methods on the session
+//! bus, like showing a desktop notification, and to serve an object of our
+//! own, see [`crate::bus`].
+//!
+//! Messages are marshalled by hand, little-endian only, as every machine
+//! with a Ryzen is: a [`Writer`] builds a body the way its signature says,
+//! a [`Reader`] takes one apart again. Authentication is `EXTERNAL`, the bus
+//! checks the uid of the socket's peer, no file descriptors are passed.
+
+use std::{
+    env, io,
+    io::{Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixStream},
+    },
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// How long a call waits for its reply.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the system bus is unless `$DBUS_SYSTEM_BUS_ADDRESS` says otherwise.
+const SYSTEM_BUS: &str = "unix:path=/run/dbus/system_bus_socket";
+
+/// Longest message the bus passes on, longer ones are refused.
+const MAX_MESSAGE: usize = 128 << 20;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+extern "C" {
+    fn geteuid() -> u32;
+}
+
+/// The four kinds of message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    MethodCall = 1,
+    MethodReturn = 2,
+    Error = 3,
+    Signal = 4,
+}
+
+/// A message with its header fields, the body still marshalled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: Kind,
+    pub serial: u32,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub reply_serial: Option<u32>,
+    pub destination: Option<String>,
+    pub sender: Option<String>,
+    pub signature: String,
+    pub body: Vec<u8>,
+}
+
+impl Message {
+    /// A call of `interface.member` on `path` of `destination`, with a body
+    /// of `signature`.
+    pub fn method_call(
+        destination: &str,
+        path: &str,
+        (interface, member): (&str, &str),
+        signature: &str,
+        body: Writer,
+    ) -> Self {
+        Self {
+            path: Some(path.to_owned()),
+            interface: Some(interface.to_owned()),
+            member: Some(member.to_owned()),
+            destination: Some(destination.to_owned()),
+            signature: signature.to_owned(),
+            body: body.buf,
+            ..Self::empty()
+        }
+    }
+
+    /// The reply to the method call `call`, with a body of `signature`.
+    pub fn method_return(call: &Message, signature: &str, body: Writer) -> Self {
+        Self {
+            kind: Kind::MethodReturn,
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            signature: signature.to_owned(),
+            body: body.buf,
+            ..Self::empty()
+        }
+    }
+
+    /// The error `name` in reply to `call`, with `text` for people.
+    pub fn error(call: &Message, name: &str, text: &str) -> Self {
+        let mut body = Writer::new();
+        body.string(text);
+        Self {
+            kind: Kind::Error,
+            error_name: Some(name.to_owned()),
+            ..Self::method_return(call, "s", body)
+        }
+    }
+
+    /// The signal `interface.member` of the object at `path`.
+    pub fn signal(
+        path: &str,
+        (interface, member): (&str, &str),
+        signature: &str,
+        body: Writer,
+    ) -> Self {
+        Self {
+            kind: Kind::Signal,
+            path: Some(path.to_owned()),
+            interface: Some(interface.to_owned()),
+            member: Some(member.to_owned()),
+            signature: signature.to_owned(),
+            body: body.buf,
+            ..Self::empty()
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            kind: Kind::MethodCall,
+            serial: 0,
+            path: None,
+            interface: None,
+            member: None,
+            error_name: None,
+            reply_serial: None,
+            destination: None,
+            sender: None,
+            signature: String::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// The body, to read as its signature says.
+    pub fn reader(&self) -> Reader<'_> {
+        Reader::new(&self.body)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut header = Writer::new();
+        header
+            .byte(b'l')
+            .byte(self.kind as u8)
+            .byte(0)
+            .byte(1)
+            .u32(self.body.len() as u32)
+            .u32(self.serial);
+        let strings = [
+            (1, "o", &self.path),
+            (2, "s", &self.interface),
+            (3, "s", &self.member),
+            (4, "s", &self.error_name),
+            (6, "s", &self.destination),
+            (7, "s", &self.sender),
+        ];
+        header.array(8, |fields| {
+            for (code, signature, value) in strings {
+                let Some(value) = value else { continue };
+                fields.structure(|field| {
+                    field.byte(code).variant(signature, |v| match signature {
+                        "o" => v.object_path(value),
+                        _ => v.string(value),
+                    });
+                });
+            }
+            if let Some(serial) = self.reply_serial {
+                fields.structure(|field| {
+                    field.byte(5).variant("u", |v| v.u32(serial));
+                });
+            }
+            if !self.signature.is_empty() {
+                fields.structure(|field| {
+                    field.byte(8).variant("g", |v| v.signature(&self.signature));
+                });
+            }
+        });
+        header.align(8);
+        header.buf.extend_from_slice(&self.body);
+        header.buf
+    }
+
+    /// Reads the next message off `stream`.
+    pub fn read(stream: &mut impl Read) -> io::Result<Self> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+        let mut fixed = [0; 16];
+        stream.read_exact(&mut fixed)?;
+        if fixed[0] != b'l' {
+            return Err(invalid("big-endian D-Bus messages aren't supported"));
+        }
+        let word = |at: usize| u32::from_le_bytes(fixed[at..at + 4].try_into().unwrap()) as usize;
+        let (body_len, fields_len) = (word(4), word(12));
+        let padded = fields_len.next_multiple_of(8);
+        if 16 + padded + body_len > MAX_MESSAGE {
+            return Err(invalid("D-Bus message too long"));
+        }
+        let mut rest = vec![0; padded + body_len];
+        stream.read_exact(&mut rest)?;
+
+        let kind = match fixed[1] {
+            1 => Kind::MethodCall,
+            2 => Kind::MethodReturn,
+            3 => Kind::Error,
+            4 => Kind::Signal,
+            _ => return Err(invalid("unknown D-Bus message type")),
+        };
+        let mut message = Self {
+            kind,
+            serial: word(8) as u32,
+            body: rest.split_off(padded),
+            ..Self::empty()
+        };
+        // Offsets of the fields count from the start of the message.
+        let mut fields = Reader {
+            data: &rest[..fields_len],
+            pos: 0,
+            base: 16,
+        };
+        let malformed = || invalid("malformed D-Bus header");
+        while !fields.is_empty() {
+            fields.align(8);
+            let code = fields.byte().ok_or_else(malformed)?;
+            let signature = fields.signature().ok_or_else(malformed)?;
+            match (code, signature.as_str()) {
+                (5, "u") => message.reply_serial = Some(fields.u32().ok_or_else(malformed)?),
+                (9, "u") => {
+                    fields.u32().ok_or_else(malformed)?;
+                }
+                (8, "g") => message.signature = fields.signature().ok_or_else(malformed)?,
+                (code, "s" | "o") => {
+                    let value = Some(fields.string().ok_or_else(malformed)?);
+                    match code {
+                        1 => message.path = value,
+                        2 => message.interface = value,
+                        3 => message.member = value,
+                        4 => message.error_name = value,
+                        6 => message.destination = value,
+                        7 => message.sender = value,
+                        _ => {}
+                    }
+                }
+                _ => return Err(malformed()),
+            }
+        }
+        Ok(message)
+    }
+}
+
+/// Marshals values one after the other, each aligned to its size.
+#[derive(Debug, Clone, Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn align(&mut self, to: usize) -> &mut Self {
+        let len = self.buf.len().next_multiple_of(to);
+        self.buf.resize(len, 0);
+        self
+    }
+
+    pub fn byte(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.align(4).buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn i32(&mut self, value: i32) -> &mut Self {
+        self.align(4).buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn f64(&mut self, value: f64) -> &mut Self {
+        self.align(8).buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.byte(0)
+    }
+
+    pub fn object_path(&mut self, value: &str) -> &mut Self {
+        self.string(value)
+    }
+
+    pub fn signature(&mut self, value: &str) -> &mut Self {
+        self.byte(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.byte(0)
+    }
+
+    /// An array of elements aligned to `align`, which `elements` writes.
+    pub fn array(&mut self, align: usize, elements: impl FnOnce(&mut Self)) -> &mut Self {
+        self.u32(0);
+        let len_at = self.buf.len() - 4;
+        self.align(align);
+        let start = self.buf.len();
+        elements(self);
+        let len = (self.buf.len() - start) as u32;
+        self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+        self
+    }
+
+    /// A struct or dictionary entry, which `fields` writes.
+    pub fn structure(&mut self, fields: impl FnOnce(&mut Self)) -> &mut Self {
+        self.align(8);
+        fields(self);
+        self
+    }
+
+    /// A variant of the single complete type `signature`, which `value`
+    /// writes.
+    pub fn variant(
+        &mut self,
+        signature: &str,
+        value: impl FnOnce(&mut Self) -> &mut Self,
+    ) -> &mut Self {
+        self.signature(signature);
+        value(self);
+        self
+    }
+}
+
+/// Takes values off a marshalled body in the order of its signature,
+/// `None` once it runs out.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Where `data` starts in what alignment counts from.
+    base: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            base: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn align(&mut self, to: usize) {
+        self.pos = (self.base + self.pos).next_multiple_of(to) - self.base;
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    pub fn byte(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn boolean(&mut self) -> Option<bool> {
+        match self.u32()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Strings and object paths alike.
+    pub fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        let value = String::from_utf8(self.take(len)?.to_vec()).ok()?;
+        self.take(1)?;
+        Some(value)
+    }
+
+    pub fn signature(&mut self) -> Option<String> {
+        let len = self.byte()? as usize;
+        let value = String::from_utf8(self.take(len)?.to_vec()).ok()?;
+        self.take(1)?;
+        Some(value)
+    }
+
+    /// The value of a variant, read by `value` if it has `signature`.
+    pub fn variant<T>(
+        &mut self,
+        signature: &str,
+        value: impl FnOnce(&mut Self) -> Option<T>,
+    ) -> Option<T> {
+        match self.signature()? == signature {
+            true => value(self),
+            false => None,
+        }
+    }
+
+    pub fn structure<T>(&mut self, fields: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        self.align(8);
+        fields(self)
+    }
+}
+
+/// The socket of `address`, the first `unix:path=` or `unix:abstract=` of
+/// its `;` separated entries.
+fn socket(address: &str) -> Option<SocketAddr> {
+    address.split(';').find_map(|entry| {
+        let params = entry.strip_prefix("unix:")?;
+        params.split(',').find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            let value = unescape(value)?;
+            match key {
+                "path" => SocketAddr::from_pathname(PathBuf::from(value)).ok(),
+                "abstract" => SocketAddr::from_abstract_name(value.as_bytes()).ok(),
+                _ => None,
+            }
+        })
+    })
+}
+
+/// An address value with its `%XX` escapes undone.
+fn unescape(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Sends on a [`Connection`] from another thread.
+#[derive(Debug, Clone)]
+pub struct Sender {
+    /// Whole messages at a time, from however many threads.
+    stream: Arc<Mutex<UnixStream>>,
+    serial: Arc<AtomicU32>,
+}
+
+impl Sender {
+    /// Sends `message` with the next serial, which it returns.
+    pub fn send(&self, mut message: Message) -> io::Result<u32> {
+        message.serial = self.serial.fetch_add(1, Ordering::SeqCst) + 1;
+        let encoded = message.encode();
+        self.stream.lock().unwrap().write_all(&encoded)?;
+        Ok(message.serial)
+    }
+}
+
+/// An authenticated connection to a bus.
+#[derive(Debug)]
+pub struct Connection {
+    stream: UnixStream,
+    sender: Sender,
+    /// The name the bus gave this connection, like `:1.42`.
+    pub unique_name: String,
+}
+
+impl Connection {
+    /// The bus of `$DBUS_SYSTEM_BUS_ADDRESS`, else the usual socket.
+    pub fn system() -> io::Result<Self> {
+        let address = env::var("DBUS_SYSTEM_BUS_ADDRESS")
+            .ok()
+            .filter(|address| !address.is_empty())
+            .unwrap_or_else(|| SYSTEM_BUS.to_owned());
+        Self::at(&address)
+    }
+
+    /// The bus of `$DBUS_SESSION_BUS_ADDRESS`, else `$XDG_RUNTIME_DIR/bus`.
+    pub fn session() -> io::Result<Self> {
+        let address = env::var("DBUS_SESSION_BUS_ADDRESS")
+            .ok()
+            .filter(|address| !address.is_empty())
+            .or_else(|| {
+                Some(format!(
+                    "unix:path={}/bus",
+                    env::var("XDG_RUNTIME_DIR").ok()?
+                ))
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no session bus"))?;
+        Self::at(&address)
+    }
+
+    fn at(address: &str) -> io::Result<Self> {
+        let socket = socket(address).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported bus address `{}`", address),
+            )
+        })?;
+        Self::open(&socket)
+    }
+
+    fn open(socket: &SocketAddr) -> io::Result<Self> {
+        let mut stream = UnixStream::connect_addr(socket)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        // The uid as hex of its decimal digits.
+        // SAFETY: geteuid can't fail and touches no memory of ours.
+        let uid = unsafe { geteuid() }.to_string();
+        let hex = uid
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+        let answer = read_line(&mut stream)?;
+        if !answer.starts_with("OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("the bus refused to authenticate: {}", answer),
+            ));
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+
+        let mut connection = Self {
+            sender: Sender {
+                stream: Arc::new(Mutex::new(stream.try_clone()?)),
+                serial: Arc::new(AtomicU32::new(0)),
+            },
+            stream,
+            unique_name: String::new(),
+        };
+        let hello =
+            Message::method_call(BUS_NAME, BUS_PATH, (BUS_NAME, "Hello"), "", Writer::new());
+        let reply = connection.call(hello)?;
+        connection.unique_name = reply.reader().string().unwrap_or_default();
+        Ok(connection)
+    }
+
+    /// Sends `message` with the next serial, which it returns.
+    pub fn send(&self, message: Message) -> io::Result<u32> {
+        self.sender.send(message)
+    }
+
+    /// A handle to send with while another thread waits for messages.
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    /// The next message for this connection.
+    pub fn receive(&mut self) -> io::Result<Message> {
+        Message::read(&mut self.stream)
+    }
+
+    /// Waits up to `timeout` for a message, forever for `None`.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// Sends the method call `message` and waits for its reply, skipping
+    /// signals on the way. An error reply is an error, with its name and
+    /// message.
+    pub fn call(&mut self, message: Message) -> io::Result<Message> {
+        let serial = self.send(message)?;
+        loop {
+            let reply = self.receive()?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            return match reply.kind {
+                Kind::Error => Err(io::Error::other(format!(
+                    "{}: {}",
+                    reply.error_name.as_deref().unwrap_or("error"),
+                    reply.reader().string().unwrap_or_default()
+                ))),
+                _ => Ok(reply),
+            };
+        }
+    }
+}
+
+/// A line of the authentication, without `\r\n`. Byte by byte, after it the
+/// stream carries messages a buffer would take away.
+fn read_line(stream: &mut UnixStream) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+        if line.len() > 512 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "overlong authentication line",
+            ));
+        }
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_round_trip() {
+        let mut body = Writer::new();
+        body.string("ryzen-wattage")
+            .u32(7)
+            .array(8, |hints| {
+                hints.structure(|entry| {
+                    entry.string("urgency").variant("y", |v| v.byte(1));
+                });
+            })
+            .i32(-1);
+        let mut message = Message::method_call(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            ("org.freedesktop.Notifications", "Notify"),
+            "sua{sv}i",
+            body,
+        );
+        message.serial = 3;
+        let encoded = message.encode();
+        assert_eq!(encoded.len() % 8, message.body.len() % 8);
+        let decoded = Message::read(&mut encoded.as_slice()).unwrap();
+        assert_eq!(decoded, message);
+
+        let mut reader = decoded.reader();
+        assert_eq!(reader.string().as_deref(), Some("ryzen-wattage"));
+        assert_eq!(reader.u32(), Some(7));
+        // The array's length, then padding to its first entry.
+        assert_eq!(reader.u32(), Some(16));
+    }
+
+    #[test]
+    fn parses_bus_addresses() {
+        let path = socket("tcp:host=x;unix:path=/run/user/1000/b%75s,guid=0").unwrap();
+        assert_eq!(
+            path.as_pathname(),
+            Some(std::path::Path::new("/run/user/1000/bus"))
+        );
+        let name = socket("unix:abstract=/tmp/dbus-x").unwrap();
+        assert_eq!(name.as_abstract_name(), Some(&b"/tmp/dbus-x"[..]));
+        assert!(socket("tcp:host=localhost,port=1").is_none());
+    }
+}
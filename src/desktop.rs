@@ -0,0 +1,90 @@
+//! `--notify`: desktop notifications when package power stays past
+//! `--exceed-watts`, through the freedesktop notification service on the
+//! session bus, for keeping an eye on it without a terminal open.
+//!
+//! The notification that power is back below replaces the one that it went
+//! above, so only the latest stays in the tray.
+
+use std::{io, time::Duration};
+
+use crate::{
+    dbus::{Connection, Message, Writer},
+    i18n::trf,
+};
+
+const SERVICE: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+const APP_NAME: &str = "ryzen-wattage";
+
+/// Normal, low is quietly put in the tray and critical stays until closed.
+const URGENCY_NORMAL: u8 = 1;
+
+/// A connection to the notification service.
+#[derive(Debug)]
+pub struct Notifications {
+    connection: Connection,
+    /// The notification shown last, the next one replaces it.
+    last: u32,
+}
+
+impl Notifications {
+    pub fn connect() -> io::Result<Self> {
+        Ok(Self {
+            connection: Connection::session()?,
+            last: 0,
+        })
+    }
+
+    /// That package power stayed at `limit` or more for `sustain`.
+    pub fn exceeded(&mut self, limit: f64, sustain: Duration, power: f64) -> io::Result<()> {
+        self.show(
+            &trf("Package power above {} W for {}", &[&limit, &span(sustain)]),
+            &trf("{} W in the last sample", &[&format!("{:.1}", power)]),
+            "dialog-warning",
+        )
+    }
+
+    /// That it stayed below `limit` again.
+    pub fn recovered(&mut self, limit: f64, power: f64) -> io::Result<()> {
+        self.show(
+            &trf("Package power below {} W again", &[&limit]),
+            &trf("{} W in the last sample", &[&format!("{:.1}", power)]),
+            "dialog-information",
+        )
+    }
+
+    fn show(&mut self, summary: &str, body: &str, icon: &str) -> io::Result<()> {
+        let mut args = Writer::new();
+        args.string(APP_NAME)
+            .u32(self.last)
+            .string(icon)
+            .string(summary)
+            .string(body)
+            .array(4, |_| {})
+            .array(8, |hints| {
+                hints.structure(|hint| {
+                    hint.string("urgency")
+                        .variant("y", |v| v.byte(URGENCY_NORMAL));
+                });
+            })
+            // The server's default time on screen.
+            .i32(-1);
+        let reply = self.connection.call(Message::method_call(
+            SERVICE,
+            PATH,
+            (SERVICE, "Notify"),
+            "susssasa{sv}i",
+            args,
+        ))?;
+        self.last = reply.reader().u32().unwrap_or(0);
+        Ok(())
+    }
+}
+
+/// `sustain` the way people say it, like `2 min` or `30s`.
+fn span(sustain: Duration) -> String {
+    match sustain.as_secs() {
+        secs if secs >= 60 && secs % 60 == 0 => format!("{} min", secs / 60),
+        _ => format!("{}s", sustain.as_secs_f64()),
+    }
+}
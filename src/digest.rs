@@ -0,0 +1,445 @@
+//! `--summary-command` and `--summary-webhook`: a summary of every day or
+//! week of continuous sampling, sent once it is over, for keeping an eye on
+//! a machine without a dashboard.
+//!
+//! A period is a local calendar day, or a week from Monday. Its summary has
+//! the package energy, the mean and the peak power and, with
+//! `--exceed-watts`, the stretches past the limit, the highest first. A
+//! command gets it in environment variables, a webhook as JSON with a
+//! `text` field, which Slack, Mattermost and Matrix hookshot all take, over
+//! plain HTTP or, through `curl`, HTTPS.
+
+use std::{
+    fmt, io,
+    process::Command,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    i18n::trf,
+    network::Network,
+    output::{json_number, json_string, Sample},
+    timefmt::{self, Zone},
+};
+
+/// Alerts a summary names, the rest are only counted.
+pub const TOP_ALERTS: usize = 3;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `--summary-every`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Self::Day),
+            "week" => Ok(Self::Week),
+            other => Err(format!(
+                "unknown summary period `{}`, expected day or week",
+                other
+            )),
+        }
+    }
+}
+
+impl Period {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+        }
+    }
+
+    /// The date `time` is on in `zone`, or that of the Monday before for
+    /// weeks, like `2026-10-12`.
+    fn start(self, time: SystemTime, zone: &Zone) -> String {
+        let date = |time| timefmt::format(time, zone, "%Y-%m-%d").expect("valid time format");
+        match self {
+            Self::Day => date(time),
+            Self::Week => {
+                let clock = timefmt::format(time, zone, "%a %H %M %S").expect("valid time format");
+                let mut fields = clock.split(' ');
+                let weekday = fields.next();
+                let since_monday = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+                    .iter()
+                    .position(|day| Some(*day) == weekday)
+                    .unwrap_or(0) as u64;
+                let of_day = fields
+                    .map(|field| field.parse::<u64>().unwrap_or(0))
+                    .fold(0, |secs, field| secs * 60 + field);
+                // Stepping back whole days from around noon, a day of 23 or
+                // 25 hours on the way doesn't land on the wrong date.
+                let noon = time - Duration::from_secs(of_day) + Duration::from_secs(12 * 3600);
+                date(noon - Duration::from_secs(86_400 * since_monday))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A stretch of package power past `--exceed-watts`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub peak: f64,
+}
+
+/// One period, summed up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Digest {
+    pub period: Period,
+    /// See [`Period::start`].
+    pub start: String,
+    pub joules: f64,
+    /// Time sampled.
+    pub duration: Duration,
+    pub peak: f64,
+    pub alerts: Vec<Alert>,
+}
+
+impl Digest {
+    fn new(period: Period, start: String) -> Self {
+        Self {
+            period,
+            start,
+            joules: 0.0,
+            duration: Duration::ZERO,
+            peak: f64::NAN,
+            alerts: Vec::new(),
+        }
+    }
+
+    pub fn mean_power(&self) -> f64 {
+        self.joules / self.duration.as_secs_f64()
+    }
+
+    /// The [`TOP_ALERTS`] highest alerts, highest first.
+    pub fn top_alerts(&self) -> Vec<&Alert> {
+        let mut alerts = self.alerts.iter().collect::<Vec<_>>();
+        alerts.sort_by(|a, b| b.peak.total_cmp(&a.peak));
+        alerts.truncate(TOP_ALERTS);
+        alerts
+    }
+
+    /// One line, with the highest alerts after it. `hostname` is left out
+    /// when redacted.
+    pub fn text(&self, hostname: Option<&str>) -> String {
+        let mut out = hostname
+            .map(|name| format!("{}: ", name))
+            .unwrap_or_default();
+        out.push_str(&match self.period {
+            Period::Day => trf("Day of {}", &[&self.start]),
+            Period::Week => trf("Week of {}", &[&self.start]),
+        });
+        out.push_str(&trf(
+            ", {} Wh of package energy over {}h, mean {} W, peak {} W",
+            &[
+                &format!("{:.1}", self.joules / 3600.0),
+                &format!("{:.1}", self.duration.as_secs_f64() / 3600.0),
+                &format!("{:.1}", self.mean_power()),
+                &format!("{:.1}", self.peak),
+            ],
+        ));
+        if !self.alerts.is_empty() {
+            out.push_str(&trf(", {} times past the limit", &[&self.alerts.len()]));
+        }
+        for alert in self.top_alerts() {
+            out.push_str(&trf(
+                "\n- {} W at {} for {}s",
+                &[
+                    &format!("{:.1}", alert.peak),
+                    &timefmt::human(alert.start),
+                    &alert.duration.as_secs(),
+                ],
+            ));
+        }
+        out
+    }
+
+    /// The body of a webhook.
+    pub fn json(&self, hostname: Option<&str>) -> String {
+        let alerts = self
+            .top_alerts()
+            .into_iter()
+            .map(|alert| {
+                format!(
+                    "{{\"start\":{},\"duration_seconds\":{},\"peak_watts\":{}}}",
+                    json_string(&timefmt::machine(alert.start)),
+                    json_number(alert.duration.as_secs_f64()),
+                    json_number(alert.peak)
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            concat!(
+                "{{\"text\":{},{}\"period\":{},\"start\":{},\"joules\":{},",
+                "\"duration_seconds\":{},\"mean_watts\":{},\"peak_watts\":{},",
+                "\"alert_count\":{},\"alerts\":[{}]}}"
+            ),
+            json_string(&self.text(hostname)),
+            hostname
+                .map(|name| format!("\"host\":{},", json_string(name)))
+                .unwrap_or_default(),
+            json_string(self.period.name()),
+            json_string(&self.start),
+            json_number(self.joules),
+            json_number(self.duration.as_secs_f64()),
+            json_number(self.mean_power()),
+            json_number(self.peak),
+            self.alerts.len(),
+            alerts.join(",")
+        )
+    }
+}
+
+/// Sums up samples period by period.
+#[derive(Debug)]
+pub struct Digests {
+    period: Period,
+    zone: Zone,
+    current: Option<Digest>,
+    /// The alert still going on.
+    open: Option<Alert>,
+}
+
+impl Digests {
+    pub fn new(period: Period) -> Self {
+        Self::with_zone(period, Zone::local())
+    }
+
+    pub fn with_zone(period: Period, zone: Zone) -> Self {
+        Self {
+            period,
+            zone,
+            current: None,
+            open: None,
+        }
+    }
+
+    /// Adds `sample`, `exceeded` if power is past the limit as far as
+    /// [`PowerWatch`](crate::hooks::PowerWatch) is concerned. Returns the
+    /// summary of the period before once the sample is in a new one. The
+    /// first period only counts from the first sample.
+    pub fn add(&mut self, sample: &Sample, exceeded: bool) -> Option<Digest> {
+        let start = self.period.start(sample.timestamp, &self.zone);
+        let mut finished = None;
+        if self.current.as_ref().map(|current| &current.start) != Some(&start) {
+            finished = self.current.replace(Digest::new(self.period, start));
+            // An alert going on ends with the period, and starts over.
+            if let Some(digest) = &mut finished {
+                digest.alerts.extend(self.open.take());
+            }
+        }
+
+        let current = self.current.as_mut().expect("a current period");
+        if exceeded {
+            let open = self.open.get_or_insert(Alert {
+                start: sample.timestamp,
+                duration: Duration::ZERO,
+                peak: sample.package_power,
+            });
+            open.duration += sample.window;
+            open.peak = open.peak.max(sample.package_power);
+        } else {
+            current.alerts.extend(self.open.take());
+        }
+        if sample.package_power.is_finite() {
+            current.joules += sample.package_power * sample.window.as_secs_f64();
+            current.duration += sample.window;
+            current.peak = current.peak.max(sample.package_power);
+        }
+        finished
+    }
+}
+
+/// Where summaries go.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Run through `sh`.
+    Command(String),
+    Webhook(Network, Webhook),
+}
+
+impl Target {
+    /// Sends `digest`, blocking until the command exited or the webhook
+    /// answered.
+    pub fn send(&self, digest: &Digest, hostname: Option<&str>) -> io::Result<()> {
+        match self {
+            Self::Command(command) => {
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("RYZEN_WATTAGE_SUMMARY", digest.text(hostname))
+                    .env("RYZEN_WATTAGE_SUMMARY_JSON", digest.json(hostname))
+                    .env("RYZEN_WATTAGE_PERIOD", digest.period.name())
+                    .env("RYZEN_WATTAGE_PERIOD_START", &digest.start)
+                    .env("RYZEN_WATTAGE_JOULES", format!("{:.3}", digest.joules))
+                    .env(
+                        "RYZEN_WATTAGE_MEAN_WATTS",
+                        format!("{:.3}", digest.mean_power()),
+                    )
+                    .env("RYZEN_WATTAGE_PEAK_WATTS", format!("{:.3}", digest.peak))
+                    .env("RYZEN_WATTAGE_ALERTS", digest.alerts.len().to_string())
+                    .status()?;
+                match status.success() {
+                    true => Ok(()),
+                    false => Err(io::Error::other(format!("`{}` {}", command, status))),
+                }
+            }
+            Self::Webhook(network, webhook) if webhook.tls => {
+                let options = [
+                    ("request", "POST"),
+                    ("header", "Content-Type: application/json"),
+                    ("data-binary", &digest.json(hostname)),
+                ];
+                network
+                    .curl(&webhook.to_string(), &options, TIMEOUT)
+                    .map(drop)
+            }
+            Self::Webhook(network, webhook) => network.post_json(
+                (&webhook.host, webhook.port, &webhook.path),
+                &digest.json(hostname),
+                TIMEOUT,
+            ),
+        }
+    }
+}
+
+/// A webhook URL, `http[s]://HOST[:PORT]/PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    /// HTTPS, which `curl` posts to.
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid webhook `{}`, expected http[s]://HOST[:PORT]/PATH",
+                url
+            )
+        };
+        let (tls, rest) = match url.split_once("://") {
+            Some(("http", rest)) => (false, rest),
+            Some(("https", rest)) => (true, rest),
+            _ => return Err(invalid()),
+        };
+        let (address, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (address, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            tls,
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        write!(f, "{}://{}:{}{}", scheme, self.host, self.port, self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn sample(secs: u64, package_power: f64) -> Sample {
+        Sample {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            window: Duration::from_secs(3600),
+            package_power,
+            ..Sample::default()
+        }
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // Sunday 2026-10-18, 23:30 in Berlin, and the Monday a week and a
+        // day later at 01:30, after the clocks went back an hour.
+        let berlin = "Europe/Berlin".parse::<Zone>().unwrap();
+        let sunday = UNIX_EPOCH + Duration::from_secs(1_792_359_000);
+        assert_eq!(Period::Day.start(sunday, &berlin), "2026-10-18");
+        assert_eq!(Period::Week.start(sunday, &berlin), "2026-10-12");
+        let monday = sunday + Duration::from_secs(7 * 86_400 + 3 * 3600);
+        assert_eq!(Period::Week.start(monday, &berlin), "2026-10-26");
+    }
+
+    #[test]
+    fn sums_up_each_day() {
+        let mut digests = Digests::with_zone(Period::Day, Zone::Utc);
+        let day = 20_000 * 86_400;
+        assert_eq!(digests.add(&sample(day + 3600, 10.0), false), None);
+        assert_eq!(digests.add(&sample(day + 7200, 80.0), true), None);
+        assert_eq!(digests.add(&sample(day + 10_800, 30.0), false), None);
+        let digest = digests.add(&sample(day + 86_400, 10.0), true).unwrap();
+        assert_eq!(digest.start, "2024-10-04");
+        assert_eq!(digest.joules, 120.0 * 3600.0);
+        assert_eq!(digest.mean_power(), 40.0);
+        assert_eq!(digest.peak, 80.0);
+        assert_eq!(digest.alerts.len(), 1);
+        assert_eq!(digest.alerts[0].duration, Duration::from_secs(3600));
+        assert!(digest.json(Some("box")).contains("\"alert_count\":1"));
+
+        // An alert goes with the day it started on.
+        let digest = digests.add(&sample(day + 2 * 86_400, 10.0), false).unwrap();
+        assert_eq!(digest.start, "2024-10-05");
+        assert_eq!(digest.alerts.len(), 1);
+    }
+
+    #[test]
+    fn parses_webhooks() {
+        let webhook = "http://chat.lan:8008/hooks/abc".parse::<Webhook>().unwrap();
+        assert_eq!(
+            (webhook.host.as_str(), webhook.port, webhook.path.as_str()),
+            ("chat.lan", 8008, "/hooks/abc")
+        );
+        assert!(!webhook.tls);
+        let webhook = "http://chat.lan".parse::<Webhook>().unwrap();
+        assert_eq!((webhook.port, webhook.path.as_str()), (80, "/"));
+        let webhook = "https://hooks.slack.com/services/T0/B0/x"
+            .parse::<Webhook>()
+            .unwrap();
+        assert!(webhook.tls);
+        assert_eq!(
+            webhook.to_string(),
+            "https://hooks.slack.com:443/services/T0/B0/x"
+        );
+        assert!("chat.lan/x".parse::<Webhook>().is_err());
+        assert!("ftp://chat.lan/x".parse::<Webhook>().is_err());
+    }
+}
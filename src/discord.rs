@@ -0,0 +1,338 @@
+//! `--discord`: a message in a Discord channel with the package power of
+//! the last minute, for showing off a build to a server's hardware channel.
+//!
+//! The message is posted through a channel webhook once and edited every
+//! [`INTERVAL`] after that, so the channel doesn't fill up with readings
+//! and Discord's rate limits are far away. A message deleted in the
+//! meantime is posted anew. Discord only serves HTTPS, which goes through
+//! `curl`.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, ErrorKind},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    i18n::tr,
+    json,
+    network::Network,
+    output::{json_string, Sample},
+    sink::Sink,
+    timefmt,
+};
+
+/// How often the message is edited.
+pub const INTERVAL: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The stripe of the embed, AMD red.
+const COLOR: u32 = 0xed1c24;
+
+/// A channel webhook, `https://discord.com/api/webhooks/ID/TOKEN`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    /// Up to the token.
+    url: String,
+    /// Like `thread_id=...`, kept for every request.
+    query: Option<String>,
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid Discord webhook `{}`, expected \
+                 https://discord.com/api/webhooks/ID/TOKEN",
+                s
+            )
+        };
+        let (url, query) = match s.split_once('?') {
+            Some((url, query)) => (url, Some(query.to_owned())),
+            None => (s, None),
+        };
+        let rest = url.strip_prefix("https://").ok_or_else(invalid)?;
+        let parts = rest.split('/').collect::<Vec<_>>();
+        let [host, "api", "webhooks", id, token] = parts.as_slice() else {
+            return Err(invalid());
+        };
+        if host.is_empty() || id.is_empty() || token.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            url: url.to_owned(),
+            query,
+        })
+    }
+}
+
+impl fmt::Display for Webhook {
+    /// Without the token, for messages and `/healthz`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let id = self.url.rsplit_once('/').map_or("", |(id, _)| id);
+        write!(f, "{}/…", id)
+    }
+}
+
+impl Webhook {
+    /// Where a new message is posted, waiting for it to come back with its
+    /// ID.
+    fn post_url(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?wait=true&{}", self.url, query),
+            None => format!("{}?wait=true", self.url),
+        }
+    }
+
+    fn message_url(&self, id: &str) -> String {
+        match &self.query {
+            Some(query) => format!("{}/messages/{}?{}", self.url, id, query),
+            None => format!("{}/messages/{}", self.url, id),
+        }
+    }
+}
+
+/// Package and core energy since the message was last edited.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Minute {
+    seconds: f64,
+    joules: f64,
+    peak: f64,
+    cores: BTreeMap<u32, f64>,
+}
+
+impl Minute {
+    fn add(&mut self, sample: &Sample) {
+        let seconds = sample.window.as_secs_f64();
+        self.seconds += seconds;
+        self.joules += sample.package_power * seconds;
+        self.peak = self.peak.max(sample.package_power);
+        for (&core, power) in &sample.core_power {
+            *self.cores.entry(core).or_default() += power * seconds;
+        }
+    }
+
+    fn busiest(&self) -> Option<(u32, f64)> {
+        let (core, joules) = self.cores.iter().max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        Some((*core, joules / self.seconds))
+    }
+}
+
+/// Keeps editing one message through a webhook.
+#[derive(Debug)]
+pub struct Discord {
+    network: Network,
+    webhook: Webhook,
+    hostname: Option<String>,
+    model: Option<String>,
+    /// The ID of the message posted, `None` until then.
+    message: Option<String>,
+    minute: Minute,
+    edited: Option<Instant>,
+}
+
+impl Discord {
+    pub fn new(
+        network: Network,
+        webhook: Webhook,
+        hostname: Option<&str>,
+        model: Option<&str>,
+    ) -> Self {
+        Self {
+            network,
+            webhook,
+            hostname: hostname.map(str::to_owned),
+            model: model.map(str::to_owned),
+            message: None,
+            minute: Minute::default(),
+            edited: None,
+        }
+    }
+
+    /// The message as Discord takes it, an embed with a field per value.
+    fn body(&self, sample: &Sample) -> String {
+        let minute = &self.minute;
+        let mut fields = vec![
+            (
+                tr("Mean package power"),
+                format!("{:.1} W", minute.joules / minute.seconds),
+            ),
+            (tr("Peak package power"), format!("{:.1} W", minute.peak)),
+        ];
+        if let Some((core, watts)) = minute.busiest() {
+            fields.push((tr("Busiest core"), format!("{}: {:.2} W", core, watts)));
+        }
+        let fields = fields
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{{\"name\":{},\"value\":{},\"inline\":true}}",
+                    json_string(name),
+                    json_string(value)
+                )
+            })
+            .collect::<Vec<_>>();
+        let title = self.hostname.as_deref().unwrap_or("ryzen-wattage");
+        let footer = match &self.model {
+            Some(model) => format!(",\"footer\":{{\"text\":{}}}", json_string(model)),
+            None => String::new(),
+        };
+        format!(
+            "{{\"username\":\"ryzen-wattage\",\"embeds\":[{{\"title\":{},\"fields\":[{}],\
+             \"timestamp\":{},\"color\":{}{}}}]}}",
+            json_string(title),
+            fields.join(","),
+            json_string(&timefmt::rfc3339(sample.timestamp, &timefmt::Zone::Utc)),
+            COLOR,
+            footer
+        )
+    }
+
+    fn send(&mut self, body: &str) -> io::Result<()> {
+        let json = ("header", "Content-Type: application/json");
+        if let Some(id) = &self.message {
+            let url = self.webhook.message_url(id);
+            let options = [("request", "PATCH"), json, ("data-binary", body)];
+            match self.network.curl(&url, &options, TIMEOUT) {
+                // Deleted from the channel, post it again.
+                Err(err) if err.to_string().contains("404") => self.message = None,
+                result => return result.map(drop),
+            }
+        }
+        let options = [("request", "POST"), json, ("data-binary", body)];
+        let reply = self
+            .network
+            .curl(&self.webhook.post_url(), &options, TIMEOUT)?;
+        self.message = Some(message_id(&reply).ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                "Discord answered without a message ID",
+            )
+        })?);
+        Ok(())
+    }
+}
+
+impl Sink for Discord {
+    /// Edits the message once [`INTERVAL`] has passed, and tries again with
+    /// every sample after it failed.
+    fn deliver(&mut self, sample: &Sample) -> io::Result<()> {
+        self.minute.add(sample);
+        if self
+            .edited
+            .is_some_and(|edited| edited.elapsed() < INTERVAL)
+            || self.minute.seconds <= 0.0
+        {
+            return Ok(());
+        }
+        let body = self.body(sample);
+        self.send(&body)?;
+        self.minute = Minute::default();
+        self.edited = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// The `id` of the message Discord echoes back.
+fn message_id(reply: &str) -> Option<String> {
+    Some(json::parse(reply).ok()?.get("id")?.as_str()?.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_webhooks_without_showing_their_token() {
+        let webhook = "https://discord.com/api/webhooks/123/abc-XYZ"
+            .parse::<Webhook>()
+            .unwrap();
+        assert_eq!(
+            webhook.to_string(),
+            "https://discord.com/api/webhooks/123/…"
+        );
+        assert_eq!(
+            webhook.post_url(),
+            "https://discord.com/api/webhooks/123/abc-XYZ?wait=true"
+        );
+        assert_eq!(
+            webhook.message_url("42"),
+            "https://discord.com/api/webhooks/123/abc-XYZ/messages/42"
+        );
+
+        let webhook = "https://discord.com/api/webhooks/123/abc?thread_id=7"
+            .parse::<Webhook>()
+            .unwrap();
+        assert_eq!(
+            webhook.post_url(),
+            "https://discord.com/api/webhooks/123/abc?wait=true&thread_id=7"
+        );
+        assert_eq!(
+            webhook.message_url("42"),
+            "https://discord.com/api/webhooks/123/abc/messages/42?thread_id=7"
+        );
+
+        for url in [
+            "http://discord.com/api/webhooks/123/abc",
+            "https://discord.com/api/webhooks/123",
+            "https://discord.com/api/channels/123/abc",
+            "https://discord.com/api/webhooks/123/abc/github",
+        ] {
+            assert!(url.parse::<Webhook>().is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "offline"))]
+    fn sums_up_the_minute_for_the_message() {
+        use std::time::UNIX_EPOCH;
+
+        let network = Network::access().unwrap();
+        let webhook = "https://discord.com/api/webhooks/1/t".parse().unwrap();
+        let mut discord = Discord::new(network, webhook, Some("box"), Some("AMD Ryzen 7 5800X"));
+        for (package, core) in [(20.0, 3.0), (40.0, 2.0)] {
+            discord.minute.add(&Sample {
+                timestamp: UNIX_EPOCH + Duration::from_secs(30),
+                window: Duration::from_secs(30),
+                package_power: package,
+                core_power: BTreeMap::from([(0, 2.0), (1, core)]),
+                ..Sample::default()
+            });
+        }
+        assert_eq!(discord.minute.busiest(), Some((1, 2.5)));
+
+        let sample = Sample {
+            timestamp: UNIX_EPOCH + Duration::from_secs(60),
+            ..Sample::default()
+        };
+        let body = json::parse(&discord.body(&sample)).unwrap();
+        let embed = &body.get("embeds").unwrap().as_array().unwrap()[0];
+        assert_eq!(embed.get("title").unwrap().as_str(), Some("box"));
+        assert_eq!(
+            embed.get("timestamp").unwrap().as_str(),
+            Some("1970-01-01T00:01:00.000Z")
+        );
+        assert_eq!(
+            embed.get("footer").unwrap().get("text").unwrap().as_str(),
+            Some("AMD Ryzen 7 5800X")
+        );
+        let values = embed
+            .get("fields")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field.get("value").unwrap().as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, ["30.0 W", "40.0 W", "1: 2.50 W"]);
+
+        assert_eq!(
+            message_id("{\"id\":\"99\",\"type\":0}"),
+            Some("99".to_owned())
+        );
+        assert_eq!(message_id("{\"message\":\"Unknown Webhook\"}"), None);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fmt, io, path::PathBuf};
+
+use crate::migrate::Refusal;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// `/dev/cpu/*/msr` doesn't exist.
+    MsrModuleMissing,
+    PermissionDenied {
+        path: PathBuf,
+    },
+    /// `msr-safe` refused to read a register missing from its allowlist.
+    MsrSafeNotAllowed {
+        path: PathBuf,
+        register: u64,
+    },
+    /// The CPU doesn't have the energy registers of any supported backend.
+    UnsupportedCpu {
+        vendor: String,
+        family: u32,
+    },
+    /// No readable powercap or amd_energy counters were found.
+    PowercapMissing,
+    /// A sysfs or procfs file had unexpected contents.
+    Parse {
+        path: PathBuf,
+        value: String,
+    },
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A manifest or configuration file is malformed.
+    Config {
+        path: PathBuf,
+        message: String,
+    },
+    /// The daemon answered with an error or something unexpected.
+    Daemon {
+        path: PathBuf,
+        message: String,
+    },
+    /// polkit didn't authorize a privileged operation.
+    NotAuthorized {
+        action: &'static str,
+    },
+    /// Raw counter values were asked for, but the backend doesn't know its
+    /// energy unit.
+    NoEnergyUnit {
+        backend: &'static str,
+    },
+    /// A limit didn't pass the [`crate::guardrail`] checks.
+    LimitRefused {
+        limit: String,
+        reason: String,
+    },
+    /// A setting would have been written under `--read-only`.
+    ReadOnly {
+        path: PathBuf,
+    },
+    /// A stored file of a version that can't be migrated.
+    Migration {
+        path: PathBuf,
+        refusal: Refusal,
+    },
+}
+
+impl Error {
+    /// Wraps an I/O error on `path`, recognizing missing permissions.
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        let path = path.into();
+
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied { path },
+            _ => Self::Io { path, source },
+        }
+    }
+
+    pub fn config(path: impl Into<PathBuf>, message: impl Into<String>) -> Self {
+        Self::Config {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Whether this is a device that doesn't exist (anymore), like the MSR
+    /// device of an offline CPU.
+    pub fn is_device_gone(&self) -> bool {
+        // ENXIO and ENODEV.
+        matches!(self, Self::Io { source, .. }
+            if source.kind() == io::ErrorKind::NotFound
+                || matches!(source.raw_os_error(), Some(6 | 19)))
+    }
+
+    pub fn parse(path: impl Into<PathBuf>, value: impl Into<String>) -> Self {
+        Self::Parse {
+            path: path.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MsrModuleMissing => write!(
+                f,
+                "no MSR devices found in /dev/cpu, try `modprobe msr` or `--backend powercap`"
+            ),
+            Self::PermissionDenied { path } => write!(
+                f,
+                "permission denied reading {}, try running as root or `--backend powercap`",
+                path.display()
+            ),
+            Self::MsrSafeNotAllowed { path, register } => write!(
+                f,
+                "register {:#x} is not on the msr-safe allowlist for {}, add the entries of \
+                 `ryzen-wattage debug msr-safe-allowlist` to /dev/cpu/msr_allowlist",
+                register,
+                path.display()
+            ),
+            Self::UnsupportedCpu { vendor, family } => write!(
+                f,
+                "unsupported CPU ({}, family {:#x}), the energy MSRs are only known for AMD family 17h \
+                 and later and Intel family 6, try `--backend powercap`",
+                vendor, family
+            ),
+            Self::PowercapMissing => write!(
+                f,
+                "no readable powercap or amd_energy counters found in /sys/class, \
+                 check that the intel_rapl_common module is loaded and readable"
+            ),
+            Self::Parse { path, value } => {
+                write!(f, "unexpected contents `{}` in {}", value, path.display())
+            }
+            Self::Io { path, source } => write!(f, "cannot read {}: {}", path.display(), source),
+            Self::Config { path, message } => write!(f, "{}: {}", path.display(), message),
+            Self::Daemon { path, message } => {
+                write!(f, "daemon on {}: {}", path.display(), message)
+            }
+            Self::NotAuthorized { action } => {
+                write!(f, "not authorized by polkit for {}, try running as root", action)
+            }
+            Self::NoEnergyUnit { backend } => {
+                write!(f, "the {} backend has no known energy unit", backend)
+            }
+            Self::LimitRefused { limit, reason } => {
+                write!(f, "refusing to set a {}: {}", limit, reason)
+            }
+            Self::ReadOnly { path } => {
+                write!(f, "not writing {}, this run is read-only", path.display())
+            }
+            Self::Migration { path, refusal } => write!(f, "{}: {}", path.display(), refusal),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Migration { refusal, .. } => Some(refusal),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Reproducible benchmark matrices: every workload of a manifest is measured
+//! under every setting, with warmup runs and repeats.
+//!
+//! ```toml
+//! warmup = 1
+//! repeats = 5
+//!
+//! [[workload]]
+//! name = "build"
+//! command = "cargo build --release"
+//!
+//! [[setting]]
+//! name = "performance"
+//! governor = "performance"
+//! smt = false
+//! cpus = "0-7"
+//! ```
+//!
+//! Settings change the cpufreq governor of all CPUs, SMT and the CPUs the
+//! workload is pinned to, and are all optional. Without any settings every
+//! workload runs once with the system as it is. Governor and SMT need root,
+//! which is asked for through polkit otherwise, and are restored once the
+//! experiment is done.
+
+use std::{
+    fs, io,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use crate::{
+    cpu::read_sysfs,
+    polkit::{self, SMT_CONTROL},
+    run::{self, Report},
+    state::Calibration,
+    stats::{Difference, Stats},
+    toml::{self, Table, Value},
+    topology, Cpu, Error, Result,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// Unmeasured runs before the repeats of each cell.
+    pub warmup: u32,
+    pub repeats: u32,
+    pub workloads: Vec<Workload>,
+    pub settings: Vec<Setting>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workload {
+    pub name: String,
+    /// Run through `sh -c`.
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Setting {
+    pub name: String,
+    pub governor: Option<String>,
+    pub smt: Option<bool>,
+    /// CPUs the workload is pinned to.
+    pub cpus: Option<Vec<u32>>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+        let table = toml::parse(&contents).map_err(|err| Error::config(path, err.to_string()))?;
+        Self::from_table(&table).map_err(|message| Error::config(path, message))
+    }
+
+    fn from_table(table: &Table) -> Result<Self, String> {
+        let count = |key: &str, default: u32| match table.get(key) {
+            None => Ok(default),
+            Some(value) => value
+                .as_integer()
+                .and_then(|count| u32::try_from(count).ok())
+                .ok_or_else(|| format!("`{}` must be a non-negative integer", key)),
+        };
+        let warmup = count("warmup", 0)?;
+        let repeats = count("repeats", 1)?;
+        if repeats == 0 {
+            return Err("`repeats` must be at least 1".to_owned());
+        }
+
+        let workloads = tables(table, "workload")?
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                let command = string(table, "command")?
+                    .ok_or_else(|| format!("workload {} has no `command`", index + 1))?;
+                let name = string(table, "name")?.unwrap_or_else(|| command.clone());
+                Ok(Workload { name, command })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if workloads.is_empty() {
+            return Err("no `[[workload]]` declared".to_owned());
+        }
+
+        let mut settings = tables(table, "setting")?
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                let smt = match table.get("smt") {
+                    None => None,
+                    Some(value) => Some(value.as_bool().ok_or("`smt` must be true or false")?),
+                };
+                let cpus = match table.get("cpus") {
+                    None => None,
+                    Some(value) => Some(cpus(value)?),
+                };
+                Ok(Setting {
+                    name: string(table, "name")?.unwrap_or_else(|| format!("setting{}", index + 1)),
+                    governor: string(table, "governor")?,
+                    smt,
+                    cpus,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if settings.is_empty() {
+            settings.push(Setting {
+                name: "default".to_owned(),
+                ..Setting::default()
+            });
+        }
+
+        Ok(Self {
+            warmup,
+            repeats,
+            workloads,
+            settings,
+        })
+    }
+}
+
+fn tables<'a>(table: &'a Table, key: &str) -> Result<Vec<&'a Table>, String> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_table()
+                    .ok_or_else(|| format!("`{}` must be declared as `[[{}]]`", key, key))
+            })
+            .collect(),
+        Some(_) => Err(format!("`{}` must be declared as `[[{}]]`", key, key)),
+    }
+}
+
+fn string(table: &Table, key: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(other) => Err(format!(
+            "`{}` must be a string, not {}",
+            key,
+            other.type_name()
+        )),
+    }
+}
+
+/// A CPU list string like `"0-3,8"` or an array of CPU numbers.
+fn cpus(value: &Value) -> Result<Vec<u32>, String> {
+    let cpus = match value {
+        Value::String(list) => topology::parse_cpulist(list),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_integer().and_then(|cpu| u32::try_from(cpu).ok()))
+            .collect(),
+        _ => None,
+    };
+
+    cpus.filter(|cpus| !cpus.is_empty())
+        .ok_or_else(|| "`cpus` must be a CPU list like \"0-3,8\" or an array of CPUs".to_owned())
+}
+
+/// Measurements of one workload under one setting.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub workload: String,
+    pub setting: String,
+    /// Joules per successful run.
+    pub energy: Stats,
+    /// Seconds per successful run.
+    pub wall_time: Stats,
+    pub average_power: Stats,
+    /// Measured runs that exited unsuccessfully, left out of the statistics.
+    pub failures: u32,
+    /// The energy and wall time of each successful run, for comparisons.
+    pub runs: Vec<(f64, f64)>,
+}
+
+/// How a setting differs from the first setting for the same workload.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub workload: String,
+    pub baseline: String,
+    pub setting: String,
+    /// Joules, `None` with fewer than two successful runs on either side.
+    pub energy: Option<Difference>,
+    /// Seconds.
+    pub wall_time: Option<Difference>,
+    /// Means of the baseline, to put the differences in relation.
+    pub baseline_energy: f64,
+    pub baseline_wall_time: f64,
+}
+
+/// Compares every setting with the first one, workload by workload.
+pub fn compare(cells: &[Cell]) -> Vec<Comparison> {
+    let mut comparisons = Vec::new();
+
+    for (index, baseline) in cells.iter().enumerate() {
+        // The baseline is the first cell of its workload.
+        if cells[..index]
+            .iter()
+            .any(|cell| cell.workload == baseline.workload)
+        {
+            continue;
+        }
+
+        let column = |cell: &Cell, pick: fn(&(f64, f64)) -> f64| {
+            cell.runs.iter().map(pick).collect::<Vec<_>>()
+        };
+        for cell in cells[index + 1..]
+            .iter()
+            .filter(|cell| cell.workload == baseline.workload)
+        {
+            comparisons.push(Comparison {
+                workload: cell.workload.clone(),
+                baseline: baseline.setting.clone(),
+                setting: cell.setting.clone(),
+                energy: Difference::between(
+                    &column(baseline, |run| run.0),
+                    &column(cell, |run| run.0),
+                ),
+                wall_time: Difference::between(
+                    &column(baseline, |run| run.1),
+                    &column(cell, |run| run.1),
+                ),
+                baseline_energy: baseline.energy.mean(),
+                baseline_wall_time: baseline.wall_time.mean(),
+            });
+        }
+    }
+
+    comparisons
+}
+
+/// Runs the whole matrix, setting by setting. `on_run` is called after every
+/// measured run.
+///
+/// The governor and SMT state from before are restored afterwards, also when
+/// the experiment fails halfway.
+pub fn run(
+    cpu: &Cpu,
+    manifest: &Manifest,
+    interval: Duration,
+    calibration: &Calibration,
+    mut on_run: impl FnMut(&Workload, &Setting, &Report),
+) -> Result<Vec<Cell>> {
+    // Before anything ran rather than at the first setting that changes.
+    let first_write =
+        manifest
+            .settings
+            .iter()
+            .find_map(|setting| match (setting.smt, &setting.governor) {
+                (Some(_), _) => Some(PathBuf::from(SMT_CONTROL)),
+                (None, Some(_)) => governor_paths().into_iter().next(),
+                (None, None) => None,
+            });
+    if let Some(path) = first_write.filter(|_| !polkit::writable()) {
+        return Err(Error::ReadOnly { path });
+    }
+    let saved = SystemSettings::read()?;
+
+    let result = (|| {
+        let mut cells = Vec::new();
+
+        for setting in &manifest.settings {
+            apply(setting)?;
+
+            for workload in &manifest.workloads {
+                for _ in 0..manifest.warmup {
+                    let status = command(workload, setting)
+                        .status()
+                        .map_err(|err| Error::io("sh", err))?;
+                    if !status.success() {
+                        break;
+                    }
+                }
+
+                let mut cell = Cell {
+                    workload: workload.name.clone(),
+                    setting: setting.name.clone(),
+                    energy: Stats::default(),
+                    wall_time: Stats::default(),
+                    average_power: Stats::default(),
+                    failures: 0,
+                    runs: Vec::new(),
+                };
+
+                for _ in 0..manifest.repeats {
+                    let mut report = run::run(cpu, &mut command(workload, setting), interval)?;
+                    report.energy *= calibration.package;
+                    report.average_power *= calibration.package;
+                    report.peak_power *= calibration.package;
+                    on_run(workload, setting, &report);
+
+                    if !report.status.success() {
+                        cell.failures += 1;
+                        continue;
+                    }
+                    cell.energy.push(report.energy);
+                    cell.wall_time.push(report.wall_time.as_secs_f64());
+                    cell.average_power.push(report.average_power);
+                    cell.runs
+                        .push((report.energy, report.wall_time.as_secs_f64()));
+                }
+
+                cells.push(cell);
+            }
+        }
+
+        Ok(cells)
+    })();
+
+    let restored = saved.restore();
+    let cells = result?;
+    restored?;
+    Ok(cells)
+}
+
+fn command(workload: &Workload, setting: &Setting) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(&workload.command);
+    if let Some(cpus) = setting.cpus.clone() {
+        let mask = CpuMask::new(&cpus);
+        // SAFETY: sched_setaffinity is async-signal-safe and the mask is
+        // built before forking.
+        unsafe {
+            command.pre_exec(move || mask.apply());
+        }
+    }
+    command
+}
+
+/// `cpu_set_t` of glibc and musl, 1024 CPUs.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CpuMask([u64; 16]);
+
+extern "C" {
+    fn sched_setaffinity(pid: i32, size: usize, mask: *const u64) -> i32;
+}
+
+impl CpuMask {
+    pub(crate) fn new(cpus: &[u32]) -> Self {
+        let mut mask = [0; 16];
+        for &cpu in cpus.iter().filter(|&&cpu| cpu < 1024) {
+            mask[cpu as usize / 64] |= 1 << (cpu % 64);
+        }
+        Self(mask)
+    }
+
+    /// Pins the calling thread, or the process it is about to exec.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        // SAFETY: the mask outlives the call and its size is passed along.
+        match unsafe { sched_setaffinity(0, size_of_val(&self.0), self.0.as_ptr()) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+fn apply(setting: &Setting) -> Result<()> {
+    // SMT first, CPUs brought online get the governor too.
+    if let Some(smt) = setting.smt {
+        let smt = if smt { "on" } else { "off" };
+        polkit::write_sysfs(&[(SMT_CONTROL.into(), smt.to_owned())])?;
+    }
+    if let Some(governor) = &setting.governor {
+        let writes = governor_paths()
+            .into_iter()
+            .map(|path| (path, governor.clone()))
+            .collect::<Vec<_>>();
+        polkit::write_sysfs(&writes)?;
+    }
+    Ok(())
+}
+
+/// Governor and SMT state to restore after an experiment.
+struct SystemSettings {
+    smt: Option<String>,
+    governors: Vec<(PathBuf, String)>,
+}
+
+impl SystemSettings {
+    fn read() -> Result<Self> {
+        let governors = governor_paths()
+            .into_iter()
+            .map(|path| read_sysfs(&path).map(|governor| (path, governor)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            smt: read_sysfs(SMT_CONTROL).ok(),
+            governors,
+        })
+    }
+
+    fn restore(&self) -> Result<()> {
+        if let Some(smt) = self
+            .smt
+            .as_deref()
+            .filter(|smt| matches!(*smt, "on" | "off"))
+        {
+            if read_sysfs(SMT_CONTROL).ok().as_deref() != Some(smt) {
+                polkit::write_sysfs(&[(SMT_CONTROL.into(), smt.to_owned())])?;
+            }
+        }
+        let changed = self
+            .governors
+            .iter()
+            .filter(|(path, governor)| read_sysfs(path).ok().as_ref() != Some(governor))
+            .cloned()
+            .collect::<Vec<_>>();
+        polkit::write_sysfs(&changed)
+    }
+}
+
+/// The `scaling_governor` files of all online CPUs.
+fn governor_paths() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir("/sys/devices/system/cpu") else {
+        return Vec::new();
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            let cpu = name.to_str()?.strip_prefix("cpu")?;
+            cpu.parse::<u32>().ok()?;
+            let path = Path::new("/sys/devices/system/cpu")
+                .join(&name)
+                .join("cpufreq/scaling_governor");
+            path.exists().then_some(path)
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
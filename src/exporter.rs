@@ -0,0 +1,245 @@
+//! Prometheus exporter serving the latest sample over HTTP, along with the
+//! health of the sinks on `/metrics` and `/healthz`, and the stream overlay
+//! of [`crate::overlay`] on `/overlay`.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bmc,
+    breakdown::{Kind, Owner},
+    output::{self, Sample},
+    overlay,
+    sink::Health,
+    stats::Sum,
+};
+
+/// Latest sample and running totals, shared between the sampling loop and
+/// the HTTP server.
+#[derive(Debug, Default)]
+pub struct Exporter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    latest: Option<Sample>,
+    /// Package energy in joules since the exporter started.
+    package_joules: Sum,
+    sinks: Vec<Arc<Health>>,
+    /// Watts of the whole node from the BMC and when they were read.
+    node_power: Option<(f64, Instant)>,
+    /// Power in the last window of what `--breakdown` found.
+    breakdown: Option<(Kind, BTreeMap<Owner, f64>)>,
+    /// Labels of `ryzen_info`, none to leave it out.
+    info: Vec<(&'static str, String)>,
+}
+
+impl Exporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `sample` the one served and adds its energy to the totals.
+    /// Samples have to follow each other without gaps for the totals to be
+    /// exact.
+    pub fn record(&self, sample: Sample) {
+        let mut state = self.state.lock().unwrap();
+        state.package_joules += sample.package_power * sample.window.as_secs_f64();
+        state.latest = Some(sample);
+    }
+
+    /// Serves `watts` as the power of the whole node until it is
+    /// outdated.
+    pub fn record_node_power(&self, watts: f64) {
+        self.state.lock().unwrap().node_power = Some((watts, Instant::now()));
+    }
+
+    /// Serves `watts` as the power of each owner of a `--breakdown` by
+    /// `kind`, until the next window.
+    pub fn record_breakdown(&self, kind: Kind, watts: BTreeMap<Owner, f64>) {
+        self.state.lock().unwrap().breakdown = Some((kind, watts));
+    }
+
+    /// Serves `ryzen_info` with `labels`.
+    pub fn set_info(&self, labels: Vec<(&'static str, String)>) {
+        self.state.lock().unwrap().info = labels;
+    }
+
+    /// Reports the health of `sink` too.
+    pub fn watch(&self, sink: Arc<Health>) {
+        self.state.lock().unwrap().sinks.push(sink);
+    }
+
+    /// The metrics page as Prometheus scrapes it.
+    pub fn metrics(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let sinks = state
+            .sinks
+            .iter()
+            .map(|sink| (sink.name, sink.target.as_str(), sink.status()))
+            .collect::<Vec<_>>();
+        // A BMC that stopped answering has no current reading.
+        let node_power = state
+            .node_power
+            .filter(|(_, read)| read.elapsed() < 3 * bmc::INTERVAL)
+            .map(|(watts, _)| watts);
+        output::prometheus(state.latest.as_ref(), state.package_joules.value())
+            + &output::prometheus_node(node_power)
+            + &state
+                .breakdown
+                .as_ref()
+                .map(|(kind, watts)| output::prometheus_breakdown(*kind, watts))
+                .unwrap_or_default()
+            + &output::prometheus_info(&state.info)
+            + &output::prometheus_sinks(&sinks)
+    }
+
+    /// Whether there is a sample to serve and every sink works, with a line
+    /// on each of them.
+    pub fn health(&self) -> (bool, String) {
+        let state = self.state.lock().unwrap();
+        let mut healthy = state.latest.is_some();
+        let mut out = match healthy {
+            true => "sampling: ok\n".to_owned(),
+            false => "sampling: no sample yet\n".to_owned(),
+        };
+        for sink in &state.sinks {
+            let status = sink.status();
+            healthy &= !status.failing;
+            let error = status.last_error.as_deref().unwrap_or("unknown error");
+            match status.failing {
+                true => out += &format!("{} {}: failing: {}\n", sink.name, sink.target, error),
+                false => out += &format!("{} {}: ok\n", sink.name, sink.target),
+            }
+        }
+        (healthy, out)
+    }
+
+    /// Answers requests on `listener` one at a time, forever.
+    pub fn serve(&self, listener: &TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            // A client going away mid-request is its problem, not ours.
+            let _ = stream.and_then(|stream| self.respond(stream));
+        }
+        Ok(())
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        // Skip the headers, nothing in them matters here.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let path = target.split('?').next().unwrap_or("");
+
+        let (status, content_type, body) = match (method, path) {
+            ("GET" | "HEAD", "/metrics") => (
+                "200 OK",
+                "text/plain; version=0.0.4; charset=utf-8",
+                self.metrics(),
+            ),
+            ("GET" | "HEAD", "/healthz") => {
+                let (healthy, body) = self.health();
+                let status = match healthy {
+                    true => "200 OK",
+                    false => "503 Service Unavailable",
+                };
+                (status, "text/plain; charset=utf-8", body)
+            }
+            ("GET" | "HEAD", "/overlay") => ("200 OK", "text/html; charset=utf-8", overlay::page()),
+            ("GET" | "HEAD", "/overlay.json") => {
+                let latest = self.state.lock().unwrap().latest.clone();
+                (
+                    "200 OK",
+                    "application/json",
+                    overlay::json(latest.as_ref()),
+                )
+            }
+            ("GET" | "HEAD", "/") => (
+                "200 OK",
+                "text/html; charset=utf-8",
+                "<html><body><a href=\"/metrics\">Metrics</a> <a href=\"/healthz\">Health</a> <a href=\"/overlay\">Overlay</a></body></html>\n".to_owned(),
+            ),
+            ("GET" | "HEAD", _) => (
+                "404 Not Found",
+                "text/plain; charset=utf-8",
+                "not found\n".to_owned(),
+            ),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain; charset=utf-8",
+                "method not allowed\n".to_owned(),
+            ),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )?;
+        if method != "HEAD" {
+            stream.write_all(body.as_bytes())?;
+        }
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_failing_sinks() {
+        let exporter = Exporter::new();
+        let mqtt = Arc::new(Health::new("mqtt", "mqtt://broker:1883".to_owned()));
+        exporter.watch(Arc::clone(&mqtt));
+        assert_eq!(
+            exporter.health(),
+            (
+                false,
+                "sampling: no sample yet\nmqtt mqtt://broker:1883: ok\n".to_owned()
+            )
+        );
+
+        exporter.record(Sample::fixture());
+        mqtt.failed(&io::Error::other("connection refused"));
+        let (healthy, body) = exporter.health();
+        assert!(!healthy);
+        assert!(body.ends_with("mqtt mqtt://broker:1883: failing: connection refused\n"));
+        assert!(exporter
+            .metrics()
+            .contains("ryzen_sink_failures_total{sink=\"mqtt\",target=\"mqtt://broker:1883\"} 1"));
+
+        mqtt.delivered();
+        assert!(exporter.health().0);
+    }
+
+    #[test]
+    fn serves_node_power_next_to_the_package() {
+        let exporter = Exporter::new();
+        exporter.record(Sample::fixture());
+        assert!(!exporter.metrics().contains("ryzen_node_watts"));
+
+        exporter.record_node_power(230.0);
+        let metrics = exporter.metrics();
+        assert!(metrics.contains("\nryzen_package_watts "));
+        assert!(metrics.contains("\nryzen_node_watts 230.000\n"));
+    }
+}
@@ -0,0 +1,328 @@
+//! `--firehose`: the raw counters at a kilohertz and more, where formatting
+//! every window can't keep up.
+//!
+//! Nothing is converted or formatted while capturing. The sampling loop
+//! writes fixed-size records into buffers from a preallocated pool and hands
+//! full ones to a single writer thread, which passes them back once they are
+//! on disk. A writer that falls behind gets more buffers instead of readings
+//! being dropped.
+//!
+//! The file starts with [`PREFIX`] and the version of the [`FORMAT`] on a
+//! line of their own, then the [`Header`] of `--record` session
+//! files on one line and a line with the energy unit and the cores in record
+//! order. Each record after that is the time in nanoseconds since the
+//! capture started, the package counter and the counter of every core, all
+//! little-endian `u64` in energy units. [`Session::load`] reads these files
+//! too, so `replay` works on them as on session files.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Write as _},
+    mem,
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    json,
+    migrate::{self, Format},
+    record::{Header, Reading, Session},
+    signal, Cpu, Error, Result,
+};
+
+/// First bytes of a firehose file, before the version.
+pub const PREFIX: &str = "RYZEN-WATTAGE-FIREHOSE ";
+
+/// The record layout and the session header. A new version of
+/// [`record::FORMAT`](crate::record::FORMAT) is a new version of this one
+/// too, its migration converts the header line.
+pub const FORMAT: Format<Vec<u8>> = Format {
+    name: "firehose file",
+    first: 1,
+    current: 1,
+    migrations: &[],
+};
+
+/// Buffers allocated up front, each holding about [`BUFFER_SPAN`] of records.
+const POOL: usize = 8;
+const BUFFER_SPAN: Duration = Duration::from_millis(100);
+/// Cap on the records per buffer for intervals of a few microseconds.
+const MAX_RECORDS: u128 = 16384;
+
+/// How a capture went.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capture {
+    pub readings: u64,
+    pub elapsed: Duration,
+    /// Readings taken more than one interval after they were due. The
+    /// schedule starts over from there, the records have the real times.
+    pub late: u64,
+    /// Buffers allocated on top of the pool because the writer fell behind.
+    pub grown: usize,
+}
+
+/// Buffers going back and forth between the sampling loop and the writer.
+struct Pool {
+    full: Sender<Vec<u8>>,
+    empty: Receiver<Vec<u8>>,
+    capacity: usize,
+    grown: usize,
+}
+
+impl Pool {
+    /// Hands `buffer` to the writer and replaces it with an empty one.
+    /// Returns `false` once the writer has given up.
+    fn swap(&mut self, buffer: &mut Vec<u8>) -> bool {
+        let empty = self.empty.try_recv().unwrap_or_else(|_| {
+            self.grown += 1;
+            Vec::with_capacity(self.capacity)
+        });
+        self.full.send(mem::replace(buffer, empty)).is_ok()
+    }
+}
+
+/// Reads the counters of `cpu` every `interval` into `path` until `stop`,
+/// asked with the readings so far and the time since the start, says so or
+/// the process is interrupted.
+pub fn capture(
+    cpu: &Cpu,
+    path: &Path,
+    header: &Header,
+    interval: Duration,
+    stop: impl Fn(u64, Duration) -> bool,
+) -> Result<Capture> {
+    let unit = cpu.energy_unit().ok_or(Error::NoEnergyUnit {
+        backend: cpu.backend_name(),
+    })?;
+    let cores = cpu.core_ids();
+
+    let mut file = File::create(path).map_err(|err| Error::io(path, err))?;
+    file.write_all(&preamble(header, unit, &cores))
+        .map_err(|err| Error::io(path, err))?;
+
+    let record_len = 8 * (2 + cores.len());
+    let records = (BUFFER_SPAN.as_nanos() / interval.as_nanos().max(1)).clamp(1, MAX_RECORDS);
+    let capacity = record_len * records as usize;
+
+    let (full, written) = mpsc::channel::<Vec<u8>>();
+    let (returned, empty) = mpsc::channel();
+    for _ in 0..POOL {
+        returned.send(Vec::with_capacity(capacity)).unwrap();
+    }
+    let pool = Pool {
+        full,
+        empty,
+        capacity,
+        grown: 0,
+    };
+
+    thread::scope(|scope| {
+        let writer = scope.spawn(move || -> io::Result<()> {
+            for mut buffer in written {
+                file.write_all(&buffer)?;
+                buffer.clear();
+                // The sampling loop may be done already.
+                let _ = returned.send(buffer);
+            }
+            Ok(())
+        });
+
+        // Hangs up on the writer when it returns, so the join can't block.
+        let capture = sample(cpu, &cores, interval, stop, pool);
+        let written = writer.join().expect("firehose writer panicked");
+        written.map_err(|err| Error::io(path, err))?;
+        capture
+    })
+}
+
+fn sample(
+    cpu: &Cpu,
+    cores: &[u32],
+    interval: Duration,
+    stop: impl Fn(u64, Duration) -> bool,
+    mut pool: Pool,
+) -> Result<Capture> {
+    let mut ticks = vec![0; 1 + cores.len()];
+    let record_len = 8 * (1 + ticks.len());
+    let mut buffer = pool.empty.recv().unwrap();
+    let mut capture = Capture::default();
+
+    let start = Instant::now();
+    let mut due = start;
+    while !signal::interrupted() && !stop(capture.readings, start.elapsed()) {
+        signal::sleep(due.saturating_duration_since(Instant::now()));
+
+        let time = cpu.read_ticks(cores, &mut ticks)?;
+        let nanos =
+            u64::try_from(time.saturating_duration_since(start).as_nanos()).unwrap_or(u64::MAX);
+        buffer.extend_from_slice(&nanos.to_le_bytes());
+        for ticks in &ticks {
+            buffer.extend_from_slice(&ticks.to_le_bytes());
+        }
+        capture.readings += 1;
+
+        if buffer.len() + record_len > pool.capacity && !pool.swap(&mut buffer) {
+            break;
+        }
+
+        due += interval;
+        let now = Instant::now();
+        if now > due + interval {
+            capture.late += 1;
+            due = now;
+        }
+    }
+
+    if !buffer.is_empty() {
+        pool.swap(&mut buffer);
+    }
+    capture.elapsed = start.elapsed();
+    capture.grown = pool.grown;
+    Ok(capture)
+}
+
+/// Everything before the first record.
+fn preamble(header: &Header, unit: f64, cores: &[u32]) -> Vec<u8> {
+    let cores = cores.iter().map(u32::to_string).collect::<Vec<_>>();
+    let layout = format!(
+        "{{\"energy_unit\":{},\"cores\":[{}]}}",
+        unit,
+        cores.join(",")
+    );
+
+    let mut preamble = Vec::new();
+    for line in [
+        format!("{}{}", PREFIX, FORMAT.current),
+        header.to_json(),
+        layout,
+    ] {
+        preamble.extend_from_slice(line.as_bytes());
+        preamble.push(b'\n');
+    }
+    preamble
+}
+
+/// The version of a firehose file, `None` for other files.
+pub(crate) fn version(contents: &[u8]) -> Option<u32> {
+    migrate::line_version(contents, PREFIX)
+}
+
+/// Reads a firehose file of the current version, or returns what isn't
+/// valid about it.
+pub(crate) fn parse(contents: &[u8]) -> std::result::Result<Session, String> {
+    if version(contents) != Some(FORMAT.current) {
+        return Err("not a firehose file".to_owned());
+    }
+    let mut rest = contents;
+    let mut line = || {
+        let end = rest.iter().position(|&byte| byte == b'\n')?;
+        let line = String::from_utf8_lossy(&rest[..end]).into_owned();
+        rest = &rest[end + 1..];
+        Some(line)
+    };
+
+    line();
+    let first = line().unwrap_or_default();
+    let header = json::parse(&first)
+        .ok()
+        .as_ref()
+        .and_then(Header::parse)
+        .ok_or(first)?;
+    let second = line().unwrap_or_default();
+    let (unit, cores) = json::parse(&second)
+        .ok()
+        .and_then(|layout| {
+            let cores = layout
+                .get("cores")?
+                .as_array()?
+                .iter()
+                .map(|core| core.as_f64().map(|core| core as u32))
+                .collect::<Option<Vec<_>>>()?;
+            Some((layout.get("energy_unit")?.as_f64()?, cores))
+        })
+        .ok_or(second)?;
+
+    // A capture cut short can end in half a record, which is left out.
+    let readings = rest
+        .chunks_exact(8 * (2 + cores.len()))
+        .map(|record| {
+            let mut values = record
+                .chunks_exact(8)
+                .map(|value| u64::from_le_bytes(value.try_into().unwrap()));
+            let time = Duration::from_nanos(values.next().unwrap());
+            let package = values.next().unwrap() as f64 * unit;
+            let cores = cores
+                .iter()
+                .zip(values)
+                .filter(|&(_, ticks)| ticks != u64::MAX)
+                .map(|(&core, ticks)| (core, ticks as f64 * unit))
+                .collect();
+
+            Reading {
+                time,
+                package,
+                cores,
+                domains: BTreeMap::new(),
+            }
+        })
+        .collect();
+
+    Ok(Session { header, readings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Profile;
+    use std::{env, fs, process};
+
+    #[test]
+    fn captures_what_replay_reads_back() {
+        let cpu = Cpu::simulated(Profile::AllCore);
+        let header = Header::new(&cpu, "host", &Default::default(), BTreeMap::new());
+        let path = env::temp_dir().join(format!("ryzen-wattage-firehose-{}", process::id()));
+
+        let capture = capture(
+            &cpu,
+            &path,
+            &header,
+            Duration::from_millis(1),
+            |readings, _| readings == 300,
+        )
+        .unwrap();
+        let session = Session::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(capture.readings, 300);
+        assert_eq!(session.header.hostname, "host");
+        assert_eq!(session.readings.len(), 300);
+        assert_eq!(session.readings[0].cores.len(), 8);
+
+        let samples = session.samples(Duration::from_millis(100), &BTreeMap::new());
+        assert!(samples.len() >= 2);
+        assert!(samples[0].package_power > 0.0);
+        assert!(samples[0].cores_total_power > 0.0);
+    }
+
+    #[test]
+    fn leaves_out_gone_cores_and_partial_records() {
+        let cpu = Cpu::simulated(Profile::Idle);
+        let header = Header::new(&cpu, "host", &Default::default(), BTreeMap::new());
+        let mut file = preamble(&header, 0.5, &[0, 1]);
+        for value in [1_000_000_000, 10, 4, u64::MAX, 7] {
+            file.extend_from_slice(&u64::to_le_bytes(value));
+        }
+
+        let session = parse(&file).unwrap();
+        assert_eq!(session.readings.len(), 1);
+        let reading = &session.readings[0];
+        assert_eq!(reading.time, Duration::from_secs(1));
+        assert_eq!(reading.package, 5.0);
+        assert_eq!(reading.cores, BTreeMap::from([(0, 2.0)]));
+
+        assert!(parse(&file[..PREFIX.len() + 5]).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Board power of AMD GPUs from the `amdgpu` hwmon devices.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::sysfs::Root;
+
+/// Average power of each AMD GPU in watts, keyed by its DRM card name like
+/// `card0`. Empty without `amdgpu`.
+pub fn power(root: &Root) -> BTreeMap<String, f64> {
+    let Ok(hwmon) = fs::read_dir(root.path("/sys/class/hwmon")) else {
+        return BTreeMap::new();
+    };
+
+    hwmon
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|dir| {
+            fs::read_to_string(dir.join("name")).is_ok_and(|name| name.trim_end() == "amdgpu")
+        })
+        .filter_map(|dir| Some((card_name(&dir)?, read_power(&dir)?)))
+        .collect()
+}
+
+/// RDNA 3 and later only have the current power in `power1_input`.
+fn read_power(dir: &Path) -> Option<f64> {
+    ["power1_average", "power1_input"].iter().find_map(|file| {
+        let microwatts = fs::read_to_string(dir.join(file)).ok()?;
+        let microwatts = microwatts.trim_end().parse::<f64>().ok()?;
+        Some(microwatts / 1e6)
+    })
+}
+
+/// DRM card of the GPU, falling back to its PCI address.
+fn card_name(dir: &Path) -> Option<String> {
+    let device = dir.join("device");
+
+    let card = fs::read_dir(device.join("drm")).ok().and_then(|cards| {
+        cards
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| {
+                name.strip_prefix("card")
+                    .is_some_and(|number| number.parse::<u32>().is_ok())
+            })
+            .min()
+    });
+
+    card.or_else(|| {
+        let pci = fs::read_link(&device).ok()?;
+        Some(pci.file_name()?.to_str()?.to_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    #[test]
+    fn reads_every_amdgpu() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/sys/class/hwmon/hwmon0/name", "k10temp\n")
+            .file("/sys/class/hwmon/hwmon0/power1_average", "1000000\n")
+            .file("/sys/class/hwmon/hwmon2/name", "amdgpu\n")
+            .file("/sys/class/hwmon/hwmon2/power1_average", "35000000\n")
+            .file("/sys/class/hwmon/hwmon2/device/drm/card1/dev", "226:1\n")
+            .file(
+                "/sys/class/hwmon/hwmon2/device/drm/renderD128/dev",
+                "226:128\n",
+            )
+            .file("/sys/class/hwmon/hwmon3/name", "amdgpu\n")
+            .file("/sys/class/hwmon/hwmon3/power1_input", "12500000\n")
+            .file("/sys/class/hwmon/hwmon3/device/drm/card0/dev", "226:0\n");
+        assert_eq!(
+            power(fixture.root()),
+            BTreeMap::from([("card0".to_owned(), 12.5), ("card1".to_owned(), 35.0)])
+        );
+        assert!(power(Fixture::new().root()).is_empty());
+    }
+}
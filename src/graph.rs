@@ -0,0 +1,149 @@
+//! Small inline plots of power over time.
+
+use std::{collections::VecDeque, env, fmt::Write as _, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// Braille patterns, eight dots per character cell.
+    Braille,
+    Ascii,
+}
+
+impl Style {
+    /// `self`, unless that needs Unicode the terminal most likely can't show.
+    pub fn for_terminal(self) -> Self {
+        match self {
+            Self::Braille if !unicode_supported() => Self::Ascii,
+            style => style,
+        }
+    }
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "braille" => Ok(Self::Braille),
+            "ascii" => Ok(Self::Ascii),
+            other => Err(format!(
+                "unknown graph style `{}`, expected braille or ascii",
+                other
+            )),
+        }
+    }
+}
+
+/// Whether the locale uses UTF-8. The Linux console has no braille glyphs
+/// even then.
+pub fn unicode_supported() -> bool {
+    if env::var_os("TERM").is_some_and(|term| term == "linux") {
+        return false;
+    }
+
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .map(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+        .unwrap_or(false)
+}
+
+/// History of values drawn as an area plot scaled from zero to the largest
+/// value shown.
+#[derive(Debug, Clone)]
+pub struct Graph {
+    values: VecDeque<f64>,
+    /// Width of the plot in character cells, without the axis labels.
+    width: usize,
+    /// Height of the plot in character cells.
+    height: usize,
+}
+
+impl Graph {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            values: VecDeque::new(),
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.values
+            .push_back(if value.is_finite() { value } else { 0.0 });
+        // Keep enough for the densest style, two values per cell.
+        while self.values.len() > self.width * 2 {
+            self.values.pop_front();
+        }
+    }
+
+    /// Renders the plot, newest values on the right, labeling the top and
+    /// bottom rows with the scale in `unit`.
+    pub fn render(&self, style: Style, unit: &str) -> String {
+        let per_cell = match style {
+            Style::Braille => 2,
+            Style::Ascii => 1,
+        };
+        let levels_per_cell = match style {
+            Style::Braille => 4,
+            Style::Ascii => 1,
+        };
+
+        let shown = self.values.len().min(self.width * per_cell);
+        let values = self.values.iter().skip(self.values.len() - shown);
+        let max = values.clone().copied().fold(0.0, f64::max);
+
+        let levels = self.height * levels_per_cell;
+        // Right-align the newest value by padding the start with zeros.
+        let heights = std::iter::repeat_n(0, self.width * per_cell - shown)
+            .chain(values.map(|value| match max > 0.0 {
+                true => (value / max * levels as f64).round() as usize,
+                false => 0,
+            }))
+            .collect::<Vec<_>>();
+
+        let mut out = String::new();
+        for row in 0..self.height {
+            let label = match row {
+                0 => format!("{:.1}{}", max, unit),
+                _ if row == self.height - 1 => format!("0{}", unit),
+                _ => String::new(),
+            };
+            write!(out, "{:>8} ", label).unwrap();
+
+            // Dot levels covered by this row, counted from the bottom.
+            let row_bottom = (self.height - 1 - row) * levels_per_cell;
+            for cell in heights.chunks(per_cell) {
+                match style {
+                    Style::Braille => out.push(braille_cell(cell, row_bottom)),
+                    Style::Ascii => out.push(if cell[0] > row_bottom { '#' } else { ' ' }),
+                }
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+/// The braille character for two columns of heights, filling the four dot
+/// rows of a cell starting at level `row_bottom`.
+fn braille_cell(heights: &[usize], row_bottom: usize) -> char {
+    // Dot bits from bottom to top, for the left and the right column.
+    const DOTS: [[u32; 4]; 2] = [[0x40, 0x04, 0x02, 0x01], [0x80, 0x20, 0x10, 0x08]];
+
+    let mut bits = 0;
+    for (column, &height) in heights.iter().enumerate() {
+        for (level, dot) in DOTS[column].iter().enumerate() {
+            if height > row_bottom + level {
+                bits |= dot;
+            }
+        }
+    }
+
+    char::from_u32(0x2800 + bits).unwrap_or(' ')
+}
@@ -0,0 +1,153 @@
+//! Checks every limit has to pass before it is written to the hardware.
+//!
+//! A value is only allowed inside the [`ModelLimits`] of the detected CPU, so
+//! unknown models can't have limits written at all. Even then the user has to
+//! pass `--force` and confirm the change interactively.
+
+use std::{
+    fmt,
+    io::{self, BufRead, IsTerminal, Write},
+};
+
+use crate::{cpuinfo::CpuInfo, i18n, quirks::ModelLimits, Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    /// Package power limit (PPT) in W.
+    PackagePower(f64),
+    /// Temperature limit in °C.
+    Temperature(f64),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PackagePower(watts) => write!(f, "package power limit of {:.0}W", watts),
+            Self::Temperature(celsius) => write!(f, "temperature limit of {:.0}°C", celsius),
+        }
+    }
+}
+
+/// Allows writing `limit` only if it is within the bounds of the CPU model,
+/// `force` is set and `confirm` returns true for the prompt it is given.
+pub fn check(
+    cpu: &CpuInfo,
+    limit: Limit,
+    force: bool,
+    confirm: impl FnOnce(&str) -> bool,
+) -> Result<()> {
+    within_bounds(cpu, limit)?;
+    let refuse = |reason: String| Error::LimitRefused {
+        limit: limit.to_string(),
+        reason,
+    };
+
+    if !force {
+        return Err(refuse("pass `--force` to write limits".to_owned()));
+    }
+
+    let prompt = i18n::trf(
+        "Set a {} on {}? Type `yes` to continue: ",
+        &[&limit, &cpu.model_name],
+    );
+    if !confirm(&prompt) {
+        return Err(refuse("not confirmed".to_owned()));
+    }
+
+    Ok(())
+}
+
+/// The part of [`check`] the privileged helper repeats for whatever it is
+/// asked to write: whether `limit` is within the bounds of the CPU model.
+pub fn within_bounds(cpu: &CpuInfo, limit: Limit) -> Result<()> {
+    let refuse = |reason: String| Error::LimitRefused {
+        limit: limit.to_string(),
+        reason,
+    };
+
+    let bounds = ModelLimits::detect(cpu)
+        .ok_or_else(|| refuse(format!("no safe bounds are known for {}", cpu.model_name)))?;
+    let (value, (min, max), unit) = match limit {
+        Limit::PackagePower(watts) => (watts, bounds.package_power, "W"),
+        Limit::Temperature(celsius) => (celsius, bounds.temperature, "°C"),
+    };
+    if !(min..=max).contains(&value) {
+        return Err(refuse(format!(
+            "the safe range for {} is {:.0}{unit} to {:.0}{unit}",
+            cpu.model_name, min, max
+        )));
+    }
+    Ok(())
+}
+
+/// Asks on the terminal, only an explicit `yes` confirms. Without a terminal
+/// there is nobody to ask, so nothing is confirmed.
+pub fn confirm_interactively(prompt: &str) -> bool {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return false;
+    }
+
+    eprint!("{}", prompt);
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    if stdin.lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "yes" | "ja")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(model_name: &str) -> CpuInfo {
+        CpuInfo {
+            vendor: "AuthenticAMD".to_owned(),
+            family: 0x19,
+            model_name: model_name.to_owned(),
+            ..CpuInfo::default()
+        }
+    }
+
+    fn reason(result: Result<()>) -> String {
+        match result {
+            Err(Error::LimitRefused { reason, .. }) => reason,
+            result => panic!("not refused: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn only_writes_forced_and_confirmed_limits_within_bounds() {
+        let cpu = cpu("AMD Ryzen 9 7950X 16-Core Processor");
+        let limit = Limit::PackagePower(120.0);
+        assert!(check(&cpu, limit, true, |_| true).is_ok());
+
+        assert_eq!(
+            reason(check(&cpu, limit, false, |_| true)),
+            "pass `--force` to write limits"
+        );
+        assert_eq!(reason(check(&cpu, limit, true, |_| false)), "not confirmed");
+        assert_eq!(
+            reason(check(&cpu, Limit::PackagePower(400.0), true, |_| true)),
+            "the safe range for AMD Ryzen 9 7950X 16-Core Processor is 30W to 230W"
+        );
+        assert!(
+            reason(check(&cpu, Limit::Temperature(105.0), true, |_| true))
+                .starts_with("the safe range")
+        );
+    }
+
+    #[test]
+    fn never_confirms_out_of_bounds_or_unknown_models() {
+        let asked = |_: &str| panic!("asked to confirm");
+        let limit = Limit::PackagePower(10.0);
+        assert!(reason(check(&cpu("AMD Ryzen 9 7950X"), limit, true, asked))
+            .starts_with("the safe range"));
+        assert_eq!(
+            reason(check(&cpu("AMD EPYC 9654"), limit, true, asked)),
+            "no safe bounds are known for AMD EPYC 9654"
+        );
+    }
+}
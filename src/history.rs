@@ -0,0 +1,872 @@
+//! The daemon's power history for weeks back: the package and core energy
+//! of every minute of the last day and of every hour of the last year, kept
+//! next to the [`Ledger`](crate::ledger::Ledger).
+//!
+//! The latest samples themselves stay in the daemon's memory for `history`
+//! requests. Every sample also goes into the minute and the hour it ended
+//! in, as energy, so a minute or an hour averages over all of its samples
+//! however long their windows were. Minutes past [`MINUTES`] and hours past
+//! [`HOURS`] are dropped, the hours have them summed up by then.
+//!
+//! The first line has the version of the [`FORMAT`], like the ledger, and
+//! a history of a newer one is left alone.
+//!
+//! `history query` asks it a [`Query`] like
+//! `avg(package) by hour where ts > -7d`:
+//!
+//! ```text
+//! query = avg|min|max|sum "(" package|coreN ")" [by minute|hour|day]
+//!         [where ts <|<=|>|>= TIME [and ...]]
+//! TIME  = -7d, -12h, -30m, -2w, ... ago or an RFC 3339 time
+//! ```
+//!
+//! `avg` is the mean power over the time sampled, `min` and `max` are of
+//! the minutes or hours in a group and `sum` is their energy. Points are
+//! compared by when they start. The minutes answer `by minute` and queries
+//! from a time they still cover on, the hours everything else.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Write as _},
+    fs, io,
+    iter::Peekable,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    metrics::Unit,
+    migrate::{self, Format, Refusal},
+    output::Sample,
+    state::{machine_id, state_dir},
+    sysfs::Root,
+    timefmt::{self, Zone},
+};
+
+/// Minutes kept, a day of them.
+pub const MINUTES: usize = 24 * 60;
+/// Hours kept, a year of them.
+pub const HOURS: usize = 366 * 24;
+
+const HEADER: &str = "# ryzen-wattage history ";
+
+pub const FORMAT: Format<String> = Format {
+    name: "history",
+    first: 1,
+    current: 1,
+    migrations: &[],
+};
+
+/// How long the points of a [`Tier`] are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub fn length(&self) -> Duration {
+        match self {
+            Self::Minute => Duration::from_secs(60),
+            Self::Hour => Duration::from_secs(60 * 60),
+        }
+    }
+
+    fn kept(&self) -> usize {
+        match self {
+            Self::Minute => MINUTES,
+            Self::Hour => HOURS,
+        }
+    }
+
+    fn letter(&self) -> &'static str {
+        match self {
+            Self::Minute => "m",
+            Self::Hour => "h",
+        }
+    }
+}
+
+/// The energy of one minute or hour.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub start: SystemTime,
+    /// How much of it samples covered.
+    pub seconds: f64,
+    /// Joules.
+    pub package: f64,
+    pub cores: BTreeMap<u32, f64>,
+}
+
+impl Point {
+    /// The mean power of the package over what was sampled.
+    pub fn package_power(&self) -> f64 {
+        self.package / self.seconds
+    }
+
+    /// The mean power of `core`, `None` if it wasn't sampled.
+    pub fn core_power(&self, core: u32) -> Option<f64> {
+        Some(self.cores.get(&core)? / self.seconds)
+    }
+
+    fn add(&mut self, other: &Point) {
+        self.seconds += other.seconds;
+        self.package += other.package;
+        for (&core, joules) in &other.cores {
+            *self.cores.entry(core).or_default() += joules;
+        }
+    }
+}
+
+/// Points of one resolution, oldest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    pub resolution: Resolution,
+    pub points: VecDeque<Point>,
+}
+
+impl Tier {
+    fn new(resolution: Resolution) -> Self {
+        Self {
+            resolution,
+            points: VecDeque::new(),
+        }
+    }
+
+    /// Adds `energy` to the point it falls into, starting a new one once
+    /// it is past the last.
+    fn add(&mut self, energy: &Point) {
+        let length = self.resolution.length().as_secs();
+        let secs = energy
+            .start
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let start = UNIX_EPOCH + Duration::from_secs(secs - secs % length);
+        match self.points.back_mut() {
+            Some(last) if last.start == start => last.add(energy),
+            // Clocks set back don't reorder what's there.
+            Some(last) if last.start > start => last.add(energy),
+            _ => {
+                self.points.push_back(Point {
+                    start,
+                    ..energy.clone()
+                });
+                if self.points.len() > self.resolution.kept() {
+                    self.points.pop_front();
+                }
+            }
+        }
+    }
+}
+
+/// The minutes and hours of this machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    pub minutes: Tier,
+    pub hours: Tier,
+    /// Whether this stands in for a history that was refused and mustn't
+    /// be overwritten.
+    unsaved: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            minutes: Tier::new(Resolution::Minute),
+            hours: Tier::new(Resolution::Hour),
+            unsaved: false,
+        }
+    }
+}
+
+impl History {
+    /// Loads the history of this machine, an empty one if there is none
+    /// yet.
+    pub fn load() -> Result<Self, Refusal> {
+        match Self::path().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(contents) => Self::migrate(contents).map(|contents| Self::parse(&contents)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// An empty history that [`History::save`] never writes.
+    pub fn unsaved() -> Self {
+        Self {
+            unsaved: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn migrate(contents: String) -> Result<String, Refusal> {
+        let version = migrate::line_version(contents.as_bytes(), HEADER).unwrap_or(FORMAT.first);
+        FORMAT.migrate(version, contents)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if self.unsaved {
+            return Ok(());
+        }
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temporary = path.with_extension("history.tmp");
+        fs::write(&temporary, self.serialize())?;
+        fs::rename(temporary, path)
+    }
+
+    /// `$XDG_STATE_HOME/ryzen-wattage/<machine id>.history`.
+    pub fn path() -> Option<PathBuf> {
+        Some(state_dir()?.join(format!("{}.history", machine_id(&Root::system()))))
+    }
+
+    /// Adds the energy of `sample` to its minute and hour.
+    pub fn add(&mut self, sample: &Sample) {
+        let seconds = sample.window.as_secs_f64();
+        if !(sample.package_power.is_finite() && seconds > 0.0) {
+            return;
+        }
+        let energy = Point {
+            start: sample.timestamp,
+            seconds,
+            package: sample.package_power * seconds,
+            cores: sample
+                .core_power
+                .iter()
+                .filter(|(_, power)| power.is_finite())
+                .map(|(&core, power)| (core, power * seconds))
+                .collect(),
+        };
+        self.minutes.add(&energy);
+        self.hours.add(&energy);
+    }
+
+    /// The tier of `resolution`.
+    pub fn tier(&self, resolution: Resolution) -> &Tier {
+        match resolution {
+            Resolution::Minute => &self.minutes,
+            Resolution::Hour => &self.hours,
+        }
+    }
+
+    /// One `m|h start seconds package core:joules,...` line per point.
+    pub fn parse(contents: &str) -> Self {
+        let mut history = Self::default();
+        for line in contents.lines().filter(|line| !line.starts_with('#')) {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            let (Some(tier), Some(point)) = (
+                match fields.first() {
+                    Some(&"m") => Some(&mut history.minutes),
+                    Some(&"h") => Some(&mut history.hours),
+                    _ => None,
+                },
+                parse_point(&fields[1..]),
+            ) else {
+                continue;
+            };
+            tier.add(&point);
+        }
+        history
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = format!(
+            "{}{}: package and core energy per minute and hour, in joules\n",
+            HEADER, FORMAT.current
+        );
+        for tier in [&self.minutes, &self.hours] {
+            for point in &tier.points {
+                let start = point.start.duration_since(UNIX_EPOCH).unwrap_or_default();
+                let cores = point
+                    .cores
+                    .iter()
+                    .map(|(core, joules)| format!("{}:{:.3}", core, joules))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(
+                    out,
+                    "{} {} {:.3} {:.3} {}",
+                    tier.resolution.letter(),
+                    start.as_secs(),
+                    point.seconds,
+                    point.package,
+                    cores
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+fn parse_point(fields: &[&str]) -> Option<Point> {
+    let [start, seconds, package, cores] = fields else {
+        return None;
+    };
+    let cores = cores
+        .split(',')
+        .filter(|core| !core.is_empty())
+        .map(|core| {
+            let (core, joules) = core.split_once(':')?;
+            Some((core.parse().ok()?, joules.parse().ok()?))
+        })
+        .collect::<Option<_>>()?;
+    Some(Point {
+        start: UNIX_EPOCH + Duration::from_secs(start.parse().ok()?),
+        seconds: seconds
+            .parse()
+            .ok()
+            .filter(|seconds: &f64| *seconds > 0.0)?,
+        package: package.parse().ok()?,
+        cores,
+    })
+}
+
+/// How the points of a group are summed up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Avg,
+    Min,
+    Max,
+    Sum,
+}
+
+impl Aggregate {
+    pub fn unit(&self) -> Unit {
+        match self {
+            Self::Sum => Unit::Joules,
+            _ => Unit::Watts,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Avg => "avg",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Sum => "sum",
+        }
+    }
+
+    /// Over the joules and seconds of some points.
+    fn of(&self, points: impl Iterator<Item = (f64, f64)>) -> Option<f64> {
+        let mut points = points.peekable();
+        points.peek()?;
+        Some(match self {
+            Self::Avg => {
+                let (joules, seconds) = points.fold((0.0, 0.0), |(joules, seconds), point| {
+                    (joules + point.0, seconds + point.1)
+                });
+                joules / seconds
+            }
+            Self::Min => points
+                .map(|(joules, seconds)| joules / seconds)
+                .fold(f64::INFINITY, f64::min),
+            Self::Max => points
+                .map(|(joules, seconds)| joules / seconds)
+                .fold(f64::NEG_INFINITY, f64::max),
+            Self::Sum => points.map(|(joules, _)| joules).sum(),
+        })
+    }
+}
+
+/// Whose power a [`Query`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Series {
+    Package,
+    Core(u32),
+}
+
+impl fmt::Display for Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Package => write!(f, "package"),
+            Self::Core(core) => write!(f, "core{}", core),
+        }
+    }
+}
+
+/// The groups of `by`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Minute,
+    Hour,
+    /// Local days.
+    Day,
+}
+
+impl Grouping {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    /// The start of the group `time` is in.
+    fn start(&self, time: SystemTime, zone: &Zone) -> SystemTime {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let into = match self {
+            Self::Minute => secs.rem_euclid(60),
+            Self::Hour => secs.rem_euclid(3600),
+            Self::Day => (secs + zone.offset_at(secs).0 as i64).rem_euclid(86400),
+        };
+        UNIX_EPOCH + Duration::from_secs((secs - into).max(0) as u64)
+    }
+}
+
+/// A time in a `where`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Moment {
+    Ago(Duration),
+    At(SystemTime),
+}
+
+impl Moment {
+    fn at(&self, now: SystemTime) -> SystemTime {
+        match self {
+            Self::Ago(ago) => now.checked_sub(*ago).unwrap_or(UNIX_EPOCH),
+            Self::At(time) => *time,
+        }
+    }
+}
+
+impl FromStr for Moment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(ago) = s.strip_prefix('-') else {
+            return timefmt::parse_rfc3339(s)
+                .map(Self::At)
+                .ok_or_else(|| format!("invalid time `{}`, expected like -7d or RFC 3339", s));
+        };
+        let split = ago.find(|c: char| !c.is_ascii_digit()).unwrap_or(ago.len());
+        let (number, unit) = ago.split_at(split);
+        let number = number
+            .parse::<u64>()
+            .map_err(|_| format!("invalid time `{}`, expected like -7d or RFC 3339", s))?;
+        let unit = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 7 * 86400,
+            _ => {
+                return Err(format!(
+                    "invalid time unit in `{}`, expected s, m, h, d or w",
+                    s
+                ))
+            }
+        };
+        Ok(Self::Ago(Duration::from_secs(number.saturating_mul(unit))))
+    }
+}
+
+/// How a point's start compares to a [`Moment`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Before,
+    UpTo,
+    After,
+    From,
+}
+
+impl Comparison {
+    fn holds(&self, time: SystemTime, moment: SystemTime) -> bool {
+        match self {
+            Self::Before => time < moment,
+            Self::UpTo => time <= moment,
+            Self::After => time > moment,
+            Self::From => time >= moment,
+        }
+    }
+}
+
+/// A question to the history, see the [module](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub aggregate: Aggregate,
+    pub series: Series,
+    /// One row over everything if `None`.
+    pub by: Option<Grouping>,
+    pub conditions: Vec<(Comparison, Moment)>,
+}
+
+/// An answer to a [`Query`], of the group starting at `start`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub start: SystemTime,
+    pub value: f64,
+}
+
+impl Query {
+    /// The rows of the groups with points, oldest first, with `by day` in
+    /// days of `zone`.
+    pub fn run(&self, history: &History, now: SystemTime, zone: &Zone) -> Vec<Row> {
+        let conditions = self
+            .conditions
+            .iter()
+            .map(|(comparison, moment)| (*comparison, moment.at(now)))
+            .collect::<Vec<_>>();
+        let since = conditions
+            .iter()
+            .filter(|(comparison, _)| matches!(comparison, Comparison::After | Comparison::From))
+            .map(|(_, moment)| *moment)
+            .max();
+        let minutes = self.by == Some(Grouping::Minute)
+            || history
+                .minutes
+                .points
+                .front()
+                .zip(since)
+                .is_some_and(|(first, since)| first.start <= since);
+        let tier = history.tier(match minutes {
+            true => Resolution::Minute,
+            false => Resolution::Hour,
+        });
+
+        let mut groups = Vec::<(SystemTime, Vec<(f64, f64)>)>::new();
+        for point in &tier.points {
+            if !conditions
+                .iter()
+                .all(|(comparison, moment)| comparison.holds(point.start, *moment))
+            {
+                continue;
+            }
+            let joules = match self.series {
+                Series::Package => point.package,
+                Series::Core(core) => match point.cores.get(&core) {
+                    Some(joules) => *joules,
+                    None => continue,
+                },
+            };
+            let start = match self.by {
+                Some(by) => by.start(point.start, zone),
+                None => groups.first().map_or(point.start, |(start, _)| *start),
+            };
+            match groups.last_mut() {
+                Some((last, points)) if *last == start => points.push((joules, point.seconds)),
+                _ => groups.push((start, vec![(joules, point.seconds)])),
+            }
+        }
+        groups
+            .into_iter()
+            .filter_map(|(start, points)| {
+                let value = self.aggregate.of(points.into_iter())?;
+                Some(Row { start, value })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Query {
+    /// Without the `where`, as the heading of the answer.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.aggregate.name(), self.series)?;
+        if let Some(by) = self.by {
+            write!(f, " by {}", by.name())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            query: s,
+            tokens: tokens(s).into_iter().peekable(),
+        };
+        let aggregate = match parser.next("avg, min, max or sum")?.as_str() {
+            "avg" => Aggregate::Avg,
+            "min" => Aggregate::Min,
+            "max" => Aggregate::Max,
+            "sum" => Aggregate::Sum,
+            other => return Err(parser.unexpected("avg, min, max or sum", other)),
+        };
+        parser.expect("(")?;
+        let series = match parser.next("package or coreN")?.as_str() {
+            "package" => Series::Package,
+            other => other
+                .strip_prefix("core")
+                .and_then(|core| core.parse().ok())
+                .map(Series::Core)
+                .ok_or_else(|| parser.unexpected("package or coreN", other))?,
+        };
+        parser.expect(")")?;
+
+        let mut query = Self {
+            aggregate,
+            series,
+            by: None,
+            conditions: Vec::new(),
+        };
+        if parser.next_if("by") {
+            query.by = Some(match parser.next("minute, hour or day")?.as_str() {
+                "minute" => Grouping::Minute,
+                "hour" => Grouping::Hour,
+                "day" => Grouping::Day,
+                other => return Err(parser.unexpected("minute, hour or day", other)),
+            });
+        }
+        if parser.next_if("where") {
+            loop {
+                parser.expect("ts")?;
+                let comparison = match parser.next("<, <=, > or >=")?.as_str() {
+                    "<" => Comparison::Before,
+                    "<=" => Comparison::UpTo,
+                    ">" => Comparison::After,
+                    ">=" => Comparison::From,
+                    other => return Err(parser.unexpected("<, <=, > or >=", other)),
+                };
+                let moment = parser.next("a time")?.parse()?;
+                query.conditions.push((comparison, moment));
+                if !parser.next_if("and") {
+                    break;
+                }
+            }
+        }
+        match parser.tokens.next() {
+            Some(token) => Err(parser.unexpected("`by`, `where` or `and`", &token)),
+            None => Ok(query),
+        }
+    }
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    tokens: Peekable<std::vec::IntoIter<String>>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self, expected: &str) -> Result<String, String> {
+        self.tokens.next().ok_or_else(|| {
+            format!(
+                "invalid query `{}`: expected {} at the end",
+                self.query, expected
+            )
+        })
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        let expected = format!("`{}`", token);
+        match self.next(&expected)? {
+            next if next == token => Ok(()),
+            next => Err(self.unexpected(&expected, &next)),
+        }
+    }
+
+    fn next_if(&mut self, keyword: &str) -> bool {
+        self.tokens.next_if(|token| token == keyword).is_some()
+    }
+
+    fn unexpected(&self, expected: &str, token: &str) -> String {
+        format!(
+            "invalid query `{}`: expected {}, found `{}`",
+            self.query, expected, token
+        )
+    }
+}
+
+/// Words, parentheses and comparisons, which need no spaces around them.
+fn tokens(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut token = c.to_string();
+        match c {
+            _ if c.is_whitespace() => continue,
+            '(' | ')' => {}
+            '<' | '>' | '=' => {
+                while let Some(c) = chars.next_if(|c| matches!(c, '<' | '>' | '=')) {
+                    token.push(c);
+                }
+            }
+            _ => {
+                while let Some(c) = chars
+                    .next_if(|c| !(c.is_whitespace() || matches!(c, '(' | ')' | '<' | '>' | '=')))
+                {
+                    token.push(c);
+                }
+            }
+        }
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(secs: u64, package_power: f64) -> Sample {
+        Sample {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            window: Duration::from_secs(10),
+            package_power,
+            core_power: BTreeMap::from([(0, package_power / 2.0)]),
+            ..Sample::default()
+        }
+    }
+
+    #[test]
+    fn compacts_samples_into_minutes_and_hours() {
+        let mut history = History::default();
+        // Two in the first minute, one in the next and one an hour later.
+        let hour = 500_000 * 3600;
+        for (secs, watts) in [(10, 20.0), (50, 40.0), (70, 30.0), (3610, 10.0)] {
+            history.add(&sample(hour + secs, watts));
+        }
+
+        let minutes = &history.minutes.points;
+        assert_eq!(minutes.len(), 3);
+        assert_eq!(minutes[0].start, UNIX_EPOCH + Duration::from_secs(hour));
+        assert_eq!(minutes[0].package_power(), 30.0);
+        assert_eq!(minutes[0].core_power(0), Some(15.0));
+        assert_eq!(minutes[1].package, 300.0);
+
+        let hours = &history.hours.points;
+        assert_eq!(hours.len(), 2);
+        assert_eq!(hours[0].seconds, 30.0);
+        assert_eq!(hours[0].package_power(), 30.0);
+        assert_eq!(hours[1].package_power(), 10.0);
+        assert_eq!(hours[1].core_power(1), None);
+
+        assert_eq!(History::parse(&history.serialize()), history);
+    }
+
+    #[test]
+    fn keeps_a_day_of_minutes() {
+        let mut history = History::default();
+        for minute in 0..MINUTES as u64 + 10 {
+            history.add(&sample(minute * 60, 5.0));
+        }
+        assert_eq!(history.minutes.points.len(), MINUTES);
+        assert_eq!(
+            history.minutes.points[0].start,
+            UNIX_EPOCH + Duration::from_secs(600)
+        );
+        assert_eq!(history.hours.points.len(), 25);
+    }
+
+    #[test]
+    fn refuses_newer_histories() {
+        assert!(History::migrate(format!("{}2: later\n", HEADER)).is_err());
+        let contents = format!("{}1: now\nm 60 10.000 50.000 0:25.000\n", HEADER);
+        let history = History::parse(&History::migrate(contents).unwrap());
+        assert_eq!(history.minutes.points[0].package_power(), 5.0);
+        assert!(history.hours.points.is_empty());
+    }
+
+    #[test]
+    fn parses_queries() {
+        let query = "avg(package) by hour where ts > -7d"
+            .parse::<Query>()
+            .unwrap();
+        assert_eq!(
+            query,
+            Query {
+                aggregate: Aggregate::Avg,
+                series: Series::Package,
+                by: Some(Grouping::Hour),
+                conditions: vec![(
+                    Comparison::After,
+                    Moment::Ago(Duration::from_secs(7 * 86400))
+                )],
+            }
+        );
+        assert_eq!(query.to_string(), "avg(package) by hour");
+
+        let query = "max( core3 ) where ts>=2026-10-01T00:00:00Z and ts<-1h"
+            .parse::<Query>()
+            .unwrap();
+        assert_eq!(query.series, Series::Core(3));
+        assert_eq!(query.by, None);
+        assert_eq!(
+            query.conditions,
+            [
+                (
+                    Comparison::From,
+                    Moment::At(timefmt::parse_rfc3339("2026-10-01T00:00:00Z").unwrap())
+                ),
+                (Comparison::Before, Moment::Ago(Duration::from_secs(3600))),
+            ]
+        );
+
+        for (query, error) in [
+            (
+                "median(package)",
+                "expected avg, min, max or sum, found `median`",
+            ),
+            ("avg(gpu)", "expected package or coreN, found `gpu`"),
+            ("avg(package", "expected `)` at the end"),
+            (
+                "avg(package) by week",
+                "expected minute, hour or day, found `week`",
+            ),
+            ("avg(package) where ts > -7y", "expected s, m, h, d or w"),
+            (
+                "avg(package) where ts > yesterday",
+                "expected like -7d or RFC 3339",
+            ),
+            (
+                "avg(package) where ts",
+                "expected <, <=, > or >= at the end",
+            ),
+            (
+                "avg(package) by hour by day",
+                "expected `by`, `where` or `and`, found `by`",
+            ),
+        ] {
+            let err = query.parse::<Query>().unwrap_err();
+            assert!(err.contains(error), "{}: {}", query, err);
+        }
+    }
+
+    #[test]
+    fn answers_queries_from_the_finest_points_that_cover_them() {
+        let now = UNIX_EPOCH + Duration::from_secs(500_000 * 3600);
+        let mut history = History::default();
+        // Two hours ago 10 W for a minute, in the last hour 20 W and 40 W.
+        for (ago, watts) in [(7200, 10.0), (1800, 20.0), (600, 40.0)] {
+            history.add(&sample(500_000 * 3600 - ago, watts));
+        }
+        history.minutes.points.pop_front();
+        let run = |query: &str| {
+            let query = query.parse::<Query>().unwrap();
+            query.run(&history, now, &Zone::Utc)
+        };
+
+        // Only the hours go back two hours.
+        assert_eq!(
+            run("avg(package) by hour where ts > -3h"),
+            [
+                Row {
+                    start: now - Duration::from_secs(7200),
+                    value: 10.0
+                },
+                Row {
+                    start: now - Duration::from_secs(3600),
+                    value: 30.0
+                },
+            ]
+        );
+        // The minutes have the last half hour apart.
+        let rows = run("max(package) where ts >= -30m");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].value, 40.0);
+        assert_eq!(run("min(package) by hour where ts >= -30m")[0].value, 20.0);
+        assert_eq!(run("sum(core0) by day")[0].value, 350.0);
+        assert_eq!(run("avg(package) by minute").len(), 2);
+        assert!(run("avg(core1)").is_empty());
+        assert!(run("avg(package) where ts > -1s").is_empty());
+    }
+}
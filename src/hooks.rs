@@ -0,0 +1,158 @@
+//! User commands run around measurements, e.g. to drop caches or set the
+//! governor right before a window starts, and when package power stays past
+//! a limit, as watched by [`PowerWatch`].
+
+use std::{io, process::Command, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    /// Before the first sample.
+    PreRun,
+    /// Before every sampling window. The window starts once it finished.
+    PreSample,
+    /// After every sampling window.
+    PostSample,
+    /// After the last sample.
+    PostRun,
+    /// Once package power stayed at or above the limit.
+    OnExceed,
+    /// Once it stayed below the limit again.
+    OnRecover,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PreRun => "pre_run",
+            Self::PreSample => "pre_sample",
+            Self::PostSample => "post_sample",
+            Self::PostRun => "post_run",
+            Self::OnExceed => "on_exceed",
+            Self::OnRecover => "on_recover",
+        }
+    }
+}
+
+/// Shell commands for each hook, none by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hooks {
+    pub pre_run: Option<String>,
+    pub pre_sample: Option<String>,
+    pub post_sample: Option<String>,
+    pub post_run: Option<String>,
+    pub on_exceed: Option<String>,
+    pub on_recover: Option<String>,
+}
+
+impl Hooks {
+    pub fn get(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::PreRun => self.pre_run.as_deref(),
+            Hook::PreSample => self.pre_sample.as_deref(),
+            Hook::PostSample => self.post_sample.as_deref(),
+            Hook::PostRun => self.post_run.as_deref(),
+            Hook::OnExceed => self.on_exceed.as_deref(),
+            Hook::OnRecover => self.on_recover.as_deref(),
+        }
+    }
+
+    /// Runs the command for `hook` through `sh` and waits for it. The hook
+    /// name is passed in `RYZEN_WATTAGE_HOOK`, along with `env`.
+    pub fn run(&self, hook: Hook, env: &[(&str, String)]) -> io::Result<()> {
+        let Some(command) = self.get(hook) else {
+            return Ok(());
+        };
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("RYZEN_WATTAGE_HOOK", hook.name())
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .status()?;
+
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("`{}` {}", command, status))),
+        }
+    }
+}
+
+/// Package power against a limit, with how long it has to stay past it
+/// before [`Hook::OnExceed`] or [`Hook::OnRecover`] runs, so a short burst
+/// doesn't set off either.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerWatch {
+    /// Watts.
+    pub limit: f64,
+    pub sustain: Duration,
+    exceeded: bool,
+    /// How long power has been on the other side of the limit.
+    crossing: Duration,
+}
+
+impl PowerWatch {
+    pub fn new(limit: f64, sustain: Duration) -> Self {
+        Self {
+            limit,
+            sustain,
+            exceeded: false,
+            crossing: Duration::ZERO,
+        }
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    /// The hook to run after a window of `power`, if any.
+    pub fn update(&mut self, power: f64, window: Duration) -> Option<Hook> {
+        let above = power >= self.limit;
+        if above == self.exceeded {
+            self.crossing = Duration::ZERO;
+            return None;
+        }
+
+        self.crossing += window;
+        if self.crossing < self.sustain {
+            return None;
+        }
+        self.exceeded = above;
+        self.crossing = Duration::ZERO;
+        Some(match above {
+            true => Hook::OnExceed,
+            false => Hook::OnRecover,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_power_to_stay_past_the_limit() {
+        let second = Duration::from_secs(1);
+        let mut watch = PowerWatch::new(100.0, Duration::from_secs(3));
+        let hooks = [
+            90.0, 120.0, 130.0, 80.0, 100.0, 110.0, 105.0, 50.0, 99.0, 60.0,
+        ]
+        .map(|power| watch.update(power, second));
+
+        assert_eq!(
+            hooks,
+            [
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(Hook::OnExceed),
+                None,
+                None,
+                Some(Hook::OnRecover),
+            ]
+        );
+        assert!(!watch.is_exceeded());
+    }
+}
@@ -0,0 +1,525 @@
+//! `report --html`: a recorded session as a single HTML file, charts and
+//! all, that opens anywhere without a network, for attaching to benchmark
+//! results or tickets.
+//!
+//! Next to the statistics of [`Summary`] it splits the run into phases of
+//! steady package power, the way [`crate::adaptive`] tells them apart, and
+//! lists the stretches above `--exceed-watts` as alerts.
+
+use std::{fmt::Write as _, time::Duration};
+
+use crate::{
+    adaptive::{CHANGE, NOISE_WATTS},
+    output::Sample,
+    record::Header,
+    stats::Summary,
+    timefmt,
+};
+
+/// Samples a phase needs to stand on its own, shorter ones are spikes of
+/// the phase around them.
+pub const MIN_PHASE_SAMPLES: usize = 3;
+
+const CHART_WIDTH: f64 = 800.0;
+const CHART_HEIGHT: f64 = 240.0;
+
+/// A stretch of steady package power.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phase {
+    /// Since the start of the run.
+    pub start: Duration,
+    pub duration: Duration,
+    pub samples: usize,
+    pub joules: f64,
+}
+
+impl Phase {
+    pub fn power(&self) -> f64 {
+        self.joules / self.duration.as_secs_f64()
+    }
+
+    fn add(&mut self, other: &Phase) {
+        self.duration += other.duration;
+        self.samples += other.samples;
+        self.joules += other.joules;
+    }
+}
+
+/// A stretch of package power at or above a limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub start: Duration,
+    pub duration: Duration,
+    /// The highest power of its samples.
+    pub peak: f64,
+}
+
+/// Whether `power` is a change from a phase of `mean` watts.
+fn changed(mean: f64, power: f64) -> bool {
+    (power - mean).abs() > (mean.abs() * CHANGE).max(NOISE_WATTS)
+}
+
+/// The phases of `samples`, in order.
+pub fn phases(samples: &[Sample]) -> Vec<Phase> {
+    let mut phases = Vec::<Phase>::new();
+    let mut start = Duration::ZERO;
+    for sample in samples
+        .iter()
+        .filter(|sample| sample.package_power.is_finite())
+    {
+        let phase = Phase {
+            start,
+            duration: sample.window,
+            samples: 1,
+            joules: sample.package_power * sample.window.as_secs_f64(),
+        };
+        start += sample.window;
+        match phases.last_mut() {
+            Some(last) if !changed(last.power(), sample.package_power) => last.add(&phase),
+            _ => phases.push(phase),
+        }
+    }
+
+    // Spikes go to the phase before them, which can then run into the one
+    // after.
+    let mut merged = Vec::<Phase>::new();
+    for phase in phases {
+        match merged.last_mut() {
+            Some(last)
+                if phase.samples < MIN_PHASE_SAMPLES || !changed(last.power(), phase.power()) =>
+            {
+                last.add(&phase)
+            }
+            _ => merged.push(phase),
+        }
+    }
+    merged
+}
+
+/// Where package power stayed at or above `limit` for `sustain` or longer.
+pub fn alerts(samples: &[Sample], limit: f64, sustain: Duration) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    let mut current = None::<Alert>;
+    let mut start = Duration::ZERO;
+    for sample in samples {
+        if sample.package_power >= limit {
+            let alert = current.get_or_insert(Alert {
+                start,
+                duration: Duration::ZERO,
+                peak: sample.package_power,
+            });
+            alert.duration += sample.window;
+            alert.peak = alert.peak.max(sample.package_power);
+        } else if let Some(alert) = current.take() {
+            alerts.push(alert);
+        }
+        start += sample.window;
+    }
+    alerts.extend(current);
+    alerts.retain(|alert| alert.duration >= sustain);
+    alerts
+}
+
+/// `text` with the characters HTML gives a meaning escaped.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// What goes into a report beside the samples.
+#[derive(Debug, Clone)]
+pub struct Report<'a> {
+    /// Where the session was read from.
+    pub source: &'a str,
+    pub header: &'a Header,
+    /// `--exceed-watts` and `--exceed-for`.
+    pub limit: Option<(f64, Duration)>,
+}
+
+/// The whole page.
+pub fn render(report: &Report, samples: &[Sample]) -> String {
+    let mut summary = Summary::with_distribution();
+    for sample in samples {
+        summary.add(sample);
+    }
+    let phases = phases(samples);
+    let alerts = report
+        .limit
+        .map(|(limit, sustain)| alerts(samples, limit, sustain))
+        .unwrap_or_default();
+    let header = report.header;
+    let title = match &header.tag {
+        Some(tag) => format!("{} - {}", tag, report.source),
+        None => report.source.to_owned(),
+    };
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        concat!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n",
+            "<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<h1>{}</h1>"
+        ),
+        escape(&title),
+        STYLE,
+        escape(&title)
+    )
+    .unwrap();
+
+    // Metadata.
+    let hostname = match header.hostname.is_empty() {
+        true => "(redacted)",
+        false => &header.hostname,
+    };
+    let rows = [
+        ("Host", escape(hostname)),
+        ("CPU", escape(&header.cpu)),
+        (
+            "Cores",
+            format!(
+                "{} physical, {} threads, SMT {}",
+                header.physical_core_count,
+                header.core_count,
+                if header.smt_enabled { "on" } else { "off" }
+            ),
+        ),
+        ("Backend", escape(header.backend)),
+        ("Start", escape(&timefmt::human(header.start))),
+        ("Duration", seconds(summary.duration)),
+        ("Samples", summary.samples().to_string()),
+    ];
+    out.push_str("<table class=\"meta\">\n");
+    for (name, value) in rows {
+        writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", name, value).unwrap();
+    }
+    out.push_str("</table>\n");
+
+    if summary.samples() == 0 {
+        out.push_str("<p>The session has no complete window.</p>\n</body>\n</html>\n");
+        return out;
+    }
+
+    out.push_str("<h2>Package power</h2>\n");
+    out.push_str(&chart(samples, &phases, &alerts));
+
+    // Summary statistics.
+    let distribution = summary.distribution.as_ref().expect("with a distribution");
+    let package = &summary.package;
+    out.push_str("<h2>Summary</h2>\n<table>\n");
+    out.push_str("<tr><th>Min</th><th>Mean</th><th>p50</th><th>p90</th><th>p99</th>");
+    out.push_str("<th>Max</th><th>Stddev</th><th>Energy</th></tr>\n");
+    writeln!(
+        out,
+        "<tr>{}{}{}{}{}{}{}<td>{}</td></tr>\n</table>",
+        watts_cell(package.power.min),
+        watts_cell(package.power.mean()),
+        watts_cell(distribution.percentile(50.0)),
+        watts_cell(distribution.percentile(90.0)),
+        watts_cell(distribution.percentile(99.0)),
+        watts_cell(package.power.max),
+        watts_cell(package.power.stddev()),
+        joules(package.energy.value())
+    )
+    .unwrap();
+
+    // Per core, and per group if the session was replayed with some.
+    let total = package.energy.value();
+    for (heading, domains) in [
+        (
+            "Cores",
+            summary
+                .cores
+                .iter()
+                .map(|(core, domain)| (format!("Core {}", core), domain))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "Groups",
+            summary
+                .groups
+                .iter()
+                .map(|(group, domain)| (escape(group), domain))
+                .collect(),
+        ),
+    ] {
+        if domains.is_empty() {
+            continue;
+        }
+        writeln!(out, "<h2>{}</h2>\n<table>", heading).unwrap();
+        out.push_str("<tr><th></th><th>Mean</th><th>Max</th><th>Energy</th><th>Share</th></tr>\n");
+        for (name, domain) in domains {
+            writeln!(
+                out,
+                "<tr><th>{}</th>{}{}<td>{}</td><td>{:.1}%</td></tr>",
+                name,
+                watts_cell(domain.power.mean()),
+                watts_cell(domain.power.max),
+                joules(domain.energy.value()),
+                domain.energy.value() / total * 100.0
+            )
+            .unwrap();
+        }
+        out.push_str("</table>\n");
+    }
+
+    out.push_str("<h2>Phases</h2>\n<table>\n");
+    out.push_str("<tr><th></th><th>Start</th><th>Duration</th><th>Mean</th><th>Energy</th></tr>\n");
+    for (index, phase) in phases.iter().enumerate() {
+        writeln!(
+            out,
+            "<tr><th>{}</th><td>{}</td><td>{}</td>{}<td>{}</td></tr>",
+            index + 1,
+            seconds(phase.start),
+            seconds(phase.duration),
+            watts_cell(phase.power()),
+            joules(phase.joules)
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+
+    if let Some((limit, sustain)) = report.limit {
+        writeln!(
+            out,
+            "<h2>Alerts</h2>\n<p>Package power at {:.1} W or more for {} or longer.</p>",
+            limit,
+            seconds(sustain)
+        )
+        .unwrap();
+        match alerts.is_empty() {
+            true => out.push_str("<p>None.</p>\n"),
+            false => {
+                out.push_str("<table>\n<tr><th>Start</th><th>Duration</th><th>Peak</th></tr>\n");
+                for alert in &alerts {
+                    writeln!(
+                        out,
+                        "<tr><td>{}</td><td>{}</td>{}</tr>",
+                        seconds(alert.start),
+                        seconds(alert.duration),
+                        watts_cell(alert.peak)
+                    )
+                    .unwrap();
+                }
+                out.push_str("</table>\n");
+            }
+        }
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Package and core power over the run as an inline SVG, with the phases
+/// as lines and the alerts shaded.
+fn chart(samples: &[Sample], phases: &[Phase], alerts: &[Alert]) -> String {
+    let total = samples
+        .iter()
+        .map(|sample| sample.window)
+        .sum::<Duration>()
+        .as_secs_f64();
+    let highest = samples
+        .iter()
+        .map(|sample| sample.package_power)
+        .filter(|power| power.is_finite())
+        .fold(0.0, f64::max);
+    let top = match highest > 0.0 {
+        true => highest * 1.1,
+        false => 1.0,
+    };
+    let x = |at: Duration| at.as_secs_f64() / total * CHART_WIDTH;
+    let y = |power: f64| CHART_HEIGHT - power.clamp(0.0, top) / top * CHART_HEIGHT;
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        concat!(
+            "<svg class=\"chart\" viewBox=\"-50 -10 {} {}\" role=\"img\" ",
+            "aria-label=\"Package power over time\">"
+        ),
+        CHART_WIDTH + 60.0,
+        CHART_HEIGHT + 35.0
+    )
+    .unwrap();
+    for alert in alerts {
+        writeln!(
+            out,
+            "<rect class=\"alert\" x=\"{:.1}\" y=\"0\" width=\"{:.1}\" height=\"{}\"/>",
+            x(alert.start),
+            x(alert.duration),
+            CHART_HEIGHT
+        )
+        .unwrap();
+    }
+    for phase in phases.iter().skip(1) {
+        let at = x(phase.start);
+        writeln!(
+            out,
+            "<line class=\"phase\" x1=\"{:.1}\" y1=\"0\" x2=\"{:.1}\" y2=\"{}\"/>",
+            at, at, CHART_HEIGHT
+        )
+        .unwrap();
+    }
+
+    let mut lines = [
+        ("package", String::new(), Duration::ZERO),
+        ("cores", String::new(), Duration::ZERO),
+    ];
+    for sample in samples {
+        for (name, points, end) in &mut lines {
+            *end += sample.window;
+            let power = match *name {
+                "package" => sample.package_power,
+                _ if sample.core_power.is_empty() => continue,
+                _ => sample.cores_total_power,
+            };
+            if power.is_finite() {
+                // At the middle of the window the power is the mean of.
+                let at = *end - sample.window / 2;
+                write!(points, "{:.1},{:.1} ", x(at), y(power)).unwrap();
+            }
+        }
+    }
+    for (name, points, _) in &lines {
+        if !points.is_empty() {
+            writeln!(
+                out,
+                "<polyline class=\"{}\" points=\"{}\"/>",
+                name,
+                points.trim_end()
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(
+        out,
+        concat!(
+            "<line class=\"axis\" x1=\"0\" y1=\"{h}\" x2=\"{w}\" y2=\"{h}\"/>",
+            "<line class=\"axis\" x1=\"0\" y1=\"0\" x2=\"0\" y2=\"{h}\"/>\n",
+            "<text x=\"-5\" y=\"5\" text-anchor=\"end\">{top:.0} W</text>",
+            "<text x=\"-5\" y=\"{h}\" text-anchor=\"end\">0 W</text>",
+            "<text x=\"0\" y=\"{label}\">0 s</text>",
+            "<text x=\"{w}\" y=\"{label}\" text-anchor=\"end\">{end}</text>\n",
+            "</svg>\n",
+            "<p class=\"legend\"><span class=\"package\">Package</span> ",
+            "<span class=\"cores\">Cores total</span></p>"
+        ),
+        h = CHART_HEIGHT,
+        w = CHART_WIDTH,
+        top = top,
+        label = CHART_HEIGHT + 20.0,
+        end = seconds(Duration::from_secs_f64(total)),
+    )
+    .unwrap();
+    out
+}
+
+fn watts_cell(watts: f64) -> String {
+    match watts.is_finite() {
+        true => format!("<td>{:.2} W</td>", watts),
+        false => "<td>-</td>".to_owned(),
+    }
+}
+
+fn joules(joules: f64) -> String {
+    match joules.abs() >= 1000.0 {
+        true => format!("{:.2} kJ", joules / 1000.0),
+        false => format!("{:.1} J", joules),
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    match seconds >= 60.0 {
+        true => format!("{}m {:.0}s", (seconds / 60.0) as u64, seconds % 60.0),
+        false => format!("{:.2} s", seconds),
+    }
+}
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; margin: 2em auto; max-width: 60em; color: #222; }
+table { border-collapse: collapse; margin: 0.5em 0 1.5em; }
+th, td { padding: 0.2em 0.8em; border-bottom: 1px solid #ddd; text-align: right; }
+table.meta th, table.meta td { text-align: left; }
+svg.chart { width: 100%; height: auto; font-size: 12px; }
+.chart .package { fill: none; stroke: #c0392b; stroke-width: 1.5; }
+.chart .cores { fill: none; stroke: #2980b9; stroke-width: 1; }
+.chart .axis { stroke: #555; }
+.chart .phase { stroke: #999; stroke-dasharray: 4 3; }
+.chart .alert { fill: #f5b7b1; opacity: 0.5; }
+.legend .package { color: #c0392b; }
+.legend .cores { color: #2980b9; }
+@media (prefers-color-scheme: dark) {
+  body { background: #1e1e1e; color: #ddd; }
+  th, td { border-color: #444; }
+  .chart text { fill: #ddd; }
+  .chart .axis { stroke: #aaa; }
+}
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(powers: &[f64]) -> Vec<Sample> {
+        powers
+            .iter()
+            .map(|&package_power| Sample {
+                window: Duration::from_secs(1),
+                package_power,
+                ..Sample::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_into_phases() {
+        // Idle, a spike, a load, idle again.
+        let samples = samples(&[
+            10.0, 10.5, 10.2, 60.0, 10.1, 10.0, 80.0, 81.0, 79.0, 80.5, 10.0, 10.2, 9.9,
+        ]);
+        let phases = phases(&samples);
+        let starts = phases
+            .iter()
+            .map(|phase| phase.start.as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(starts, [0, 6, 10]);
+        assert_eq!(phases[1].samples, 4);
+        assert!((phases[1].power() - 80.125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn alerts_on_sustained_power() {
+        let samples = samples(&[50.0, 90.0, 95.0, 91.0, 50.0, 92.0, 50.0, 99.0, 93.0]);
+        let alerts = alerts(&samples, 90.0, Duration::from_secs(2));
+        assert_eq!(
+            alerts,
+            [
+                Alert {
+                    start: Duration::from_secs(1),
+                    duration: Duration::from_secs(3),
+                    peak: 95.0
+                },
+                Alert {
+                    start: Duration::from_secs(7),
+                    duration: Duration::from_secs(2),
+                    peak: 99.0
+                },
+            ]
+        );
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Minimal message translation for human readable output.
+//!
+//! Messages are looked up by their English text, like gettext does, so a
+//! missing translation just falls back to English. Machine readable formats
+//! are never translated.
+
+use std::{env, fmt::Display, sync::OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    De,
+}
+
+impl Lang {
+    /// Picks the language from the usual locale variables, in the order
+    /// gettext checks them.
+    pub fn from_env() -> Self {
+        ["LANGUAGE", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map(|value| Self::from_locale(&value))
+            .unwrap_or(Self::En)
+    }
+
+    /// `de_DE.UTF-8` and `de` are German, everything else English.
+    pub fn from_locale(locale: &str) -> Self {
+        match locale.split(['_', '.', ':', '@']).next() {
+            Some("de") => Self::De,
+            _ => Self::En,
+        }
+    }
+}
+
+pub fn lang() -> Lang {
+    static LANG: OnceLock<Lang> = OnceLock::new();
+    *LANG.get_or_init(Lang::from_env)
+}
+
+const DE: &[(&str, &str)] = &[
+    ("Package", "Package"),
+    ("Core", "Kern"),
+    ("Power", "Leistung"),
+    ("Work", "Arbeit"),
+    ("Work per joule", "Arbeit pro Joule"),
+    (
+        "For {} threads, best {}: CPUs {}",
+        "Für {} Threads, beste {}: CPUs {}",
+    ),
+    (
+        "For {} threads, best {}: CPUs {} on {}",
+        "Für {} Threads, beste {}: CPUs {} auf {}",
+    ),
+    (
+        "about {} doing {}, {}, measured one core at a time",
+        "etwa {} bei {}, {}, einzeln pro Kern gemessen",
+    ),
+    ("core {}: {} doing {}", "Kern {}: {} bei {}"),
+    (
+        "{} with {} of the busy CPU time in {} processes, {} in total",
+        "{} bei {} der belegten CPU-Zeit in {} Prozessen, {} insgesamt",
+    ),
+    (
+        "{} ({}) and everything it ran: {} over {}, {} of CPU time",
+        "{} ({}) und alles, was darin lief: {} in {}, {} CPU-Zeit",
+    ),
+    ("process {} exited", "Prozess {} wurde beendet"),
+    (
+        "package power at {}W or more for {}s",
+        "Package-Leistung {}W oder mehr seit {}s",
+    ),
+    (
+        "package power below {}W again",
+        "Package-Leistung wieder unter {}W",
+    ),
+    ("No energy in the ledger yet", "Noch keine Energie erfasst"),
+    (
+        "No history matches the query",
+        "Kein Verlauf passt zur Abfrage",
+    ),
+    (
+        "{} to {}: {} samples delivered, {} failed, {} dropped",
+        "{} an {}: {} Messwerte zugestellt, {} fehlgeschlagen, {} verworfen",
+    ),
+    (", last error: {}", ", letzter Fehler: {}"),
+    ("Energy per process on {}", "Energie pro Prozess am {}"),
+    (
+        "Estimated energy of tracked processes over {}",
+        "Geschätzte Energie der verfolgten Prozesse über {}",
+    ),
+    (
+        "Estimated energy of containers over {}",
+        "Geschätzte Energie der Container über {}",
+    ),
+    (
+        "no container used any CPU time",
+        "kein Container hat CPU-Zeit verbraucht",
+    ),
+    (
+        "Estimated energy of systemd units over {}",
+        "Geschätzte Energie der systemd-Units über {}",
+    ),
+    (
+        "no unit used any CPU time",
+        "keine Unit hat CPU-Zeit verbraucht",
+    ),
+    (
+        "Estimated energy of virtual machines over {}",
+        "Geschätzte Energie der virtuellen Maschinen über {}",
+    ),
+    (
+        "no virtual machine used any CPU time",
+        "keine virtuelle Maschine hat CPU-Zeit verbraucht",
+    ),
+    (
+        "Energy per process over all days",
+        "Energie pro Prozess über alle Tage",
+    ),
+    ("Process", "Prozess"),
+    ("Share", "Anteil"),
+    ("Battery", "Akku"),
+    ("Rest", "Rest"),
+    (
+        "Battery drain over {} in {} updates",
+        "Entladung des Akkus über {} in {} Aktualisierungen",
+    ),
+    (
+        "Correlation of package and battery power: {}",
+        "Korrelation von Package- und Akkuleistung: {}",
+    ),
+    (
+        "a correlation needs at least {} battery updates",
+        "eine Korrelation braucht mindestens {} Aktualisierungen des Akkus",
+    ),
+    (
+        "no battery is discharging, --battery compares once one is",
+        "kein Akku entlädt sich, --battery vergleicht, sobald einer es tut",
+    ),
+    (
+        "the battery did not update while discharging, nothing to compare with",
+        "der Akku hat sich beim Entladen nicht aktualisiert, nichts zum Vergleichen",
+    ),
+    ("{} in total", "{} insgesamt"),
+    ("Thread", "Thread"),
+    ("Domain", "Domäne"),
+    ("Group", "Gruppe"),
+    ("Cores Total", "Kerne gesamt"),
+    ("Power limit", "Leistungsgrenze"),
+    ("Power limit used", "Auslastung der Leistungsgrenze"),
+    ("Time", "Zeit"),
+    ("Counters stuck for", "Zähler hängen seit"),
+    ("highest perf", "höchste Leistung"),
+    ("frequency", "Frequenz"),
+    ("idle", "Leerlauf"),
+    ("Session", "Sitzung"),
+    ("active", "aktiv"),
+    ("locked", "gesperrt"),
+    ("Temperature", "Temperatur"),
+    ("watts", "Watt"),
+    ("joules", "Joule"),
+    ("seconds", "Sekunden"),
+    ("megahertz", "Megahertz"),
+    ("percent", "Prozent"),
+    ("degrees Celsius", "Grad Celsius"),
+    ("Command", "Befehl"),
+    ("up", "seit"),
+    ("{} samples over {}", "{} Messungen über {}"),
+    ("avg", "Mittel"),
+    (
+        "Sampled {} in {} samples: {}, {} average package power",
+        "{} in {} Messungen gemessen: {}, {} durchschnittliche Package-Leistung",
+    ),
+    ("stddev", "Standardabw."),
+    ("total", "gesamt"),
+    ("samples", "Messungen"),
+    ("to", "bis"),
+    (
+        "Package power percentiles",
+        "Perzentile der Package-Leistung",
+    ),
+    (
+        "Cores by energy per busy second, most efficient first:",
+        "Kerne nach Energie pro belegter Sekunde, die effizientesten zuerst:",
+    ),
+    (
+        "per busy second, {} busy",
+        "pro belegter Sekunde, {} belegt",
+    ),
+    (
+        "Suspended {} times for {}, awake {}: {} per day of use like this",
+        "{} Mal für {} im Ruhezustand, {} wach: {} pro Tag mit dieser Nutzung",
+    ),
+    (
+        "suspended for {}s, leaving out the window around it",
+        "{}s im Ruhezustand, das Fenster darum wird ausgelassen",
+    ),
+    ("press Enter to stop", "Enter drücken zum Beenden"),
+    ("wrote {} samples to {}", "{} Messungen nach {} geschrieben"),
+    (
+        "wrote a report of {} samples to {}",
+        "Bericht über {} Messungen nach {} geschrieben",
+    ),
+    ("wrote the trace to {}", "Trace nach {} geschrieben"),
+    (
+        "sampling woke up its core {} times per window",
+        "das Messen hat seinen Kern {}-mal pro Fenster aufgeweckt",
+    ),
+    (
+        "{} has no samples over the time of {}",
+        "{} hat keine Messungen über die Zeit von {}",
+    ),
+    (
+        "collecting diagnostics...",
+        "Diagnosedaten werden gesammelt...",
+    ),
+    (
+        "wrote {}, please check it and attach it to the issue",
+        "{} geschrieben, bitte prüfen und an das Issue anhängen",
+    ),
+    ("exit status {}", "Exit-Status {}"),
+    ("killed by a signal", "durch ein Signal beendet"),
+    ("Wall time", "Laufzeit"),
+    ("Energy", "Energie"),
+    (
+        "Average package power",
+        "Durchschnittliche Package-Leistung",
+    ),
+    ("Peak package power", "Höchste Package-Leistung"),
+    ("Mean package power", "Mittlere Package-Leistung"),
+    ("Busiest core", "Meistbelasteter Kern"),
+    ("Workload", "Last"),
+    ("Setting", "Einstellung"),
+    ("Runs", "Läufe"),
+    ("plus or minus", "plus minus"),
+    ("{} with {}: {} in {}", "{} mit {}: {} in {}"),
+    ("{}: {} compared to {}", "{}: {} im Vergleich zu {}"),
+    ("{} compared to {}", "{} im Vergleich zu {}"),
+    (
+        "{} has no {} topology recorded",
+        "in {} ist keine {}-Topologie aufgezeichnet",
+    ),
+    (
+        "group {} has cores {} this CPU doesn't, leaving them out",
+        "Gruppe {} enthält Kerne {}, die diese CPU nicht hat, sie werden ausgelassen",
+    ),
+    (
+        "captured {} readings in {}, {} of them late",
+        "{} Messwerte in {} aufgezeichnet, {} davon verspätet",
+    ),
+    (
+        "writing fell behind, {} buffers were added to keep up",
+        "das Schreiben kam nicht hinterher, {} Puffer wurden dazugenommen",
+    ),
+    ("Label", "Bezeichnung"),
+    ("Energy per run", "Energie pro Lauf"),
+    ("Difference", "Unterschied"),
+    ("95% CI {} to {}", "95%-KI {} bis {}"),
+    ("{} uses less energy", "{} braucht weniger Energie"),
+    ("{} uses more energy", "{} braucht mehr Energie"),
+    ("{} is faster", "{} ist schneller"),
+    ("{} is slower", "{} ist langsamer"),
+    (
+        "no significant difference",
+        "kein signifikanter Unterschied",
+    ),
+    (
+        "too few successful runs to compare",
+        "zu wenige erfolgreiche Läufe für einen Vergleich",
+    ),
+    (
+        "Baseline: {}J, this run {}%",
+        "Referenz: {}J, dieser Lauf {}%",
+    ),
+    (
+        "skip: the command failed",
+        "überspringen: der Befehl ist fehlgeschlagen",
+    ),
+    (
+        "good: {}J is within the {}J threshold",
+        "gut: {}J liegen innerhalb der Schwelle von {}J",
+    ),
+    (
+        "bad: {}J is above the {}J threshold",
+        "schlecht: {}J liegen über der Schwelle von {}J",
+    ),
+    ("Stored {}J as the baseline", "{}J als Referenz gespeichert"),
+    ("Source", "Quelle"),
+    ("Backend", "Backend"),
+    ("Energy resolution", "Energieauflösung"),
+    ("Counter range", "Zählerbereich"),
+    (
+        "Minimum measurable power at {}",
+        "Kleinste messbare Leistung bei {}",
+    ),
+    ("giga", "Giga"),
+    ("mega", "Mega"),
+    ("kilo", "Kilo"),
+    ("milli", "Milli"),
+    ("micro", "Mikro"),
+    ("nano", "Nano"),
+    ("Cores", "Kerne"),
+    ("Package delta", "Abweichung"),
+    ("unavailable", "nicht verfügbar"),
+    ("Counter", "Zähler"),
+    ("reads", "Lesevorgänge"),
+    (
+        "one sample of {} counters takes {}, at most {} samples per second",
+        "eine Messung von {} Zählern dauert {}, höchstens {} Messungen pro Sekunde",
+    ),
+    (
+        "core {} reads {} times slower than the median core",
+        "Kern {} liest {}-mal langsamer als der mittlere Kern",
+    ),
+    ("reference", "Referenz"),
+    ("unknown", "unbekannt"),
+    ("on", "an"),
+    ("off", "aus"),
+    ("Online CPUs", "Aktive CPUs"),
+    ("Per-core counters", "Zähler pro Kern"),
+    ("Domains", "Domänen"),
+    ("none", "keine"),
+    ("Model limits", "Grenzwerte des Modells"),
+    ("PM table", "PM-Tabelle"),
+    ("package power", "Package-Leistung"),
+    ("temperature", "Temperatur"),
+    (
+        "{} of {} quirks applied (marked with *)",
+        "{} von {} Quirks angewendet (mit * markiert)",
+    ),
+    (
+        "per-core energy counters are not advancing (possibly disabled by the BIOS), \
+         only package power is reported",
+        "die Energiezähler der Kerne ändern sich nicht (eventuell im BIOS deaktiviert), \
+         es wird nur die Package-Leistung angezeigt",
+    ),
+    (
+        "per-core energy counters are advancing again",
+        "die Energiezähler der Kerne ändern sich wieder",
+    ),
+    (
+        "the package energy counter has not advanced in {} windows while the CPU \
+         was busy, opening the {} backend again",
+        "der Energiezähler des Packages hat sich in {} Messfenstern trotz Last nicht \
+         geändert, das Backend {} wird neu geöffnet",
+    ),
+    (
+        "the per-core energy counters are not readable for this user, only package \
+         power is reported",
+        "die Energiezähler der Kerne sind für diesen Benutzer nicht lesbar, es wird nur \
+         die Package-Leistung angezeigt",
+    ),
+    (
+        "cannot read the MSR devices of cores {}, they are left out of the per-core power",
+        "die MSR-Geräte der Kerne {} sind nicht lesbar, sie fehlen in der Leistung pro Kern",
+    ),
+    (
+        "the package energy counter is advancing again",
+        "der Energiezähler des Packages ändert sich wieder",
+    ),
+    ("reloaded the configuration", "Konfiguration neu geladen"),
+    (
+        "cannot reload the configuration, keeping the current one: {}",
+        "Konfiguration kann nicht neu geladen werden, die bisherige bleibt: {}",
+    ),
+    (
+        "Set a {} on {}? Type `yes` to continue: ",
+        "{} für {} setzen? Zum Fortfahren `ja` eingeben: ",
+    ),
+    ("set the {}", "{} gesetzt"),
+    ("CPU: {}", "CPU: {}"),
+    ("{} backend: works", "Backend {}: funktioniert"),
+    ("{} backend: {}", "Backend {}: {}"),
+    (
+        "No backend can read the energy counters as this user yet, \
+         the config leaves it to auto-detection",
+        "Noch kann kein Backend die Energiezähler als dieser Benutzer lesen, \
+         die Konfiguration überlässt es der automatischen Erkennung",
+    ),
+    ("Sampling interval", "Messintervall"),
+    (
+        "Port to serve Prometheus metrics on, empty for none",
+        "Port für Prometheus-Metriken, leer für keinen",
+    ),
+    ("invalid port `{}`", "ungültiger Port `{}`"),
+    (
+        "Serve the readings to status bars and scripts as a daemon?",
+        "Die Messwerte als Daemon für Statusleisten und Skripte anbieten?",
+    ),
+    ("{} exists, replace it?", "{} existiert, ersetzen?"),
+    ("wrote {}", "{} geschrieben"),
+    (
+        "Write a systemd unit for it?",
+        "Eine systemd-Unit dafür schreiben?",
+    ),
+    (
+        "start it with: {} daemon-reload && {} enable --now ryzen-wattage",
+        "starten mit: {} daemon-reload && {} enable --now ryzen-wattage",
+    ),
+    ("Nothing to remove", "Nichts zu entfernen"),
+    ("Remove these?", "Diese entfernen?"),
+    ("removed {}", "{} entfernt"),
+    ("cannot remove {}: {}", "kann {} nicht entfernen: {}"),
+    (
+        "no access to process events, looking for {} every {}ms instead",
+        "kein Zugriff auf Prozessereignisse, stattdessen wird nach {} alle {}ms gesucht",
+    ),
+    (
+        "only one CCD, --reader-threads ccd reads it on one thread",
+        "nur ein CCD, --reader-threads ccd liest ihn mit einem Thread",
+    ),
+    ("Day of {}", "Tag vom {}"),
+    ("Week of {}", "Woche vom {}"),
+    (
+        ", {} Wh of package energy over {}h, mean {} W, peak {} W",
+        ", {} Wh Package-Energie in {}h, im Mittel {} W, höchstens {} W",
+    ),
+    (", {} times past the limit", ", {}-mal über der Grenze"),
+    ("\n- {} W at {} for {}s", "\n- {} W um {} für {}s"),
+    (
+        "Package power above {} W for {}",
+        "Package-Leistung über {} W seit {}",
+    ),
+    (
+        "Package power below {} W again",
+        "Package-Leistung wieder unter {} W",
+    ),
+    ("{} W in the last sample", "{} W bei der letzten Messung"),
+];
+
+/// Translates `msgid` into the current language.
+pub fn tr(msgid: &'static str) -> &'static str {
+    let table = match lang() {
+        Lang::En => return msgid,
+        Lang::De => DE,
+    };
+
+    table
+        .iter()
+        .find(|(en, _)| *en == msgid)
+        .map(|(_, translated)| *translated)
+        .unwrap_or(msgid)
+}
+
+/// Translates `msgid` and fills its `{}` placeholders with `args` in order.
+pub fn trf(msgid: &'static str, args: &[&dyn Display]) -> String {
+    let mut parts = tr(msgid).split("{}");
+    let mut out = parts.next().unwrap_or_default().to_owned();
+
+    for (index, part) in parts.enumerate() {
+        if let Some(arg) = args.get(index) {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+
+    out
+}
@@ -0,0 +1,333 @@
+//! Samples exported with `--format json`, `--format csv` or `--log`, read
+//! back as a [`Session`] so `replay`, `report` and `compare` work on logs
+//! of older versions and of other tools too, not just on `--record`.
+//!
+//! Exports have power where sessions have counters, so the readings of the
+//! session are the energy of the samples added up: each row's power held
+//! over the time since the row before. The first row only marks the start,
+//! nothing says how long its window was. A gap in the log, like while the
+//! tool wasn't running, reads as the power of the row after it held over
+//! the whole gap.
+//!
+//! CSV files of other tools work as long as they have the same columns: a
+//! `timestamp` in RFC 3339 and `package_watts`, optionally `core<N>_watts`
+//! and `<domain>_watts` for the domains of the energy MSRs.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    json::{self, Value},
+    output::csv_fields,
+    record::{self, Header, Reading, Session},
+    state::Calibration,
+    timefmt,
+};
+
+/// One exported sample, as far as a session can use it.
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    time: SystemTime,
+    package: f64,
+    cores: BTreeMap<u32, f64>,
+    domains: BTreeMap<&'static str, f64>,
+}
+
+/// Whether `contents` are exported samples rather than a session file.
+pub fn is_export(contents: &str) -> bool {
+    let first = first_line(contents);
+    if first == "timestamp" || first.starts_with("timestamp,") {
+        return true;
+    }
+    json::parse(first)
+        .is_ok_and(|value| value.get("timestamp").is_some() && value.get("package_watts").is_some())
+}
+
+/// Reads exported samples, or returns the first line that isn't valid.
+pub fn parse(contents: &str) -> Result<Session, &str> {
+    let first = first_line(contents);
+    let lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let (header, rows) = match first.starts_with('{') {
+        true => parse_ndjson(lines)?,
+        false => (export_header(), parse_csv(lines)?),
+    };
+    let start = rows.first().ok_or(first)?.time;
+    Ok(Session {
+        readings: readings(start, &rows),
+        header: Header { start, ..header },
+    })
+}
+
+fn first_line(contents: &str) -> &str {
+    contents
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default()
+        .trim_end_matches('\r')
+}
+
+/// The energy of `rows` added up, starting at `start`.
+fn readings(start: SystemTime, rows: &[Row]) -> Vec<Reading> {
+    let Some(first) = rows.first() else {
+        return Vec::new();
+    };
+    let mut energy = Reading {
+        time: Duration::ZERO,
+        package: 0.0,
+        cores: first.cores.keys().map(|&core| (core, 0.0)).collect(),
+        domains: first.domains.keys().map(|&domain| (domain, 0.0)).collect(),
+    };
+    let mut readings = vec![energy.clone()];
+
+    let mut last = first.time;
+    for row in &rows[1..] {
+        // Out of order, like after the clock was set back.
+        let Some(window) = row
+            .time
+            .duration_since(last)
+            .ok()
+            .filter(|window| !window.is_zero())
+        else {
+            continue;
+        };
+        let secs = window.as_secs_f64();
+        energy.package += row.package * secs;
+        for (&core, watts) in &row.cores {
+            *energy.cores.entry(core).or_default() += watts * secs;
+        }
+        for (&domain, watts) in &row.domains {
+            *energy.domains.entry(domain).or_default() += watts * secs;
+        }
+        energy.time = row.time.duration_since(start).unwrap_or_default();
+        readings.push(energy.clone());
+        last = row.time;
+    }
+    readings
+}
+
+/// One JSON object per line like `--format json` prints, the machine
+/// described by the first one.
+fn parse_ndjson<'a>(lines: impl Iterator<Item = &'a str>) -> Result<(Header, Vec<Row>), &'a str> {
+    let mut header = None;
+    let mut rows = Vec::new();
+    for line in lines {
+        let value = json::parse(line).map_err(|_| line)?;
+        header.get_or_insert_with(|| json_header(&value));
+        let time = value
+            .get("timestamp")
+            .and_then(Value::as_str)
+            .and_then(timefmt::parse_rfc3339)
+            .ok_or(line)?;
+        // Left out of the export when it couldn't be read.
+        let Some(package) = value.get("package_watts").and_then(Value::as_f64) else {
+            continue;
+        };
+        let map = |key| {
+            value
+                .get(key)
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .filter_map(|(label, watts)| Some((label.as_str(), watts.as_f64()?)))
+        };
+        rows.push(Row {
+            time,
+            package,
+            cores: map("cores_watts")
+                .filter_map(|(core, watts)| Some((core.parse().ok()?, watts)))
+                .collect(),
+            domains: map("domains_watts")
+                .filter_map(|(domain, watts)| Some((record::domain_name(domain)?, watts)))
+                .collect(),
+        });
+    }
+    Ok((header.unwrap_or_else(export_header), rows))
+}
+
+/// What the first sample says about the machine, defaults for what other
+/// tools leave out.
+fn json_header(value: &Value) -> Header {
+    let default = export_header();
+    let count = |key| {
+        value
+            .get(key)
+            .and_then(Value::as_f64)
+            .map(|count| count as u32)
+    };
+    Header {
+        backend: value
+            .get("backend")
+            .and_then(Value::as_str)
+            .map_or(default.backend, record::backend_name),
+        smt_enabled: value
+            .get("smt_enabled")
+            .and_then(Value::as_bool)
+            .unwrap_or(default.smt_enabled),
+        core_count: count("core_count").unwrap_or(default.core_count),
+        physical_core_count: count("physical_core_count").unwrap_or(default.physical_core_count),
+        ..default
+    }
+}
+
+/// A header line of column names, then a row per sample like `--log`
+/// writes them. Cells left empty weren't measured.
+fn parse_csv<'a>(mut lines: impl Iterator<Item = &'a str>) -> Result<Vec<Row>, &'a str> {
+    let columns = lines
+        .next()
+        .map(|header| csv_fields(header.trim_end_matches('\r')))
+        .unwrap_or_default();
+
+    let mut rows = Vec::new();
+    for line in lines {
+        let fields = csv_fields(line.trim_end_matches('\r'));
+        if fields.len() > columns.len() {
+            return Err(line);
+        }
+        let mut time = None;
+        let mut row = Row {
+            time: SystemTime::UNIX_EPOCH,
+            package: f64::NAN,
+            cores: BTreeMap::new(),
+            domains: BTreeMap::new(),
+        };
+        for (column, field) in columns.iter().zip(&fields) {
+            if column == "timestamp" {
+                time = Some(timefmt::parse_rfc3339(field).ok_or(line)?);
+                continue;
+            }
+            if field.is_empty() {
+                continue;
+            }
+            let Some(name) = column.strip_suffix("_watts") else {
+                continue;
+            };
+            let core = name
+                .strip_prefix("core")
+                .and_then(|core| core.parse::<u32>().ok());
+            let domain = record::domain_name(name);
+            if name != "package" && core.is_none() && domain.is_none() {
+                continue;
+            }
+            let watts = field.parse::<f64>().map_err(|_| line)?;
+            match (core, domain) {
+                (Some(core), _) => {
+                    row.cores.insert(core, watts);
+                }
+                (_, Some(domain)) => {
+                    row.domains.insert(domain, watts);
+                }
+                _ => row.package = watts,
+            }
+        }
+        row.time = time.ok_or(line)?;
+        if !row.package.is_nan() {
+            rows.push(row);
+        }
+    }
+    Ok(rows)
+}
+
+/// A header of what an export doesn't say: no counter ranges, since the
+/// energy is added up rather than read, and calibration already applied.
+fn export_header() -> Header {
+    Header {
+        start: SystemTime::UNIX_EPOCH,
+        hostname: String::new(),
+        cpu: String::new(),
+        backend: "unknown",
+        package_range: None,
+        core_range: None,
+        domain_range: None,
+        calibration: Calibration::default(),
+        smt_enabled: false,
+        core_count: 0,
+        physical_core_count: 0,
+        groups: BTreeMap::new(),
+        tag: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{self, CsvLog, Sample};
+    use std::{fs, time::UNIX_EPOCH};
+
+    fn sample(secs: u64, package_power: f64, cores: &[(u32, f64)]) -> Sample {
+        Sample {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            package_power,
+            core_power: cores.iter().copied().collect(),
+            backend: "msr",
+            smt_enabled: true,
+            core_count: 4,
+            physical_core_count: 2,
+            ..Sample::default()
+        }
+    }
+
+    fn samples() -> Vec<Sample> {
+        vec![
+            sample(1_775_000_000, 99.0, &[(0, 9.0), (1, 9.0)]),
+            sample(1_775_000_001, 40.0, &[(0, 10.0), (1, 2.0)]),
+            sample(1_775_000_002, 20.0, &[(0, 4.0), (1, 6.0)]),
+            sample(1_775_000_004, 30.0, &[(0, 8.0), (1, 1.0)]),
+        ]
+    }
+
+    fn check(session: &Session) {
+        assert_eq!(session.header.start, samples()[0].timestamp);
+        assert_eq!(session.readings.len(), 4);
+        assert_eq!(session.cores(), [0, 1]);
+
+        // The first row's power is lost, its window isn't known.
+        let replayed = session.samples(Duration::from_secs(2), &BTreeMap::new());
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(replayed[0].package_power, 30.0);
+        assert_eq!(replayed[0].core_power[&0], 7.0);
+        assert_eq!(replayed[1].package_power, 30.0);
+        assert_eq!(replayed[1].window, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn reads_ndjson() {
+        let contents = samples()
+            .iter()
+            .map(|sample| output::json(sample) + "\n")
+            .collect::<String>();
+        assert!(is_export(&contents));
+        let session = parse(&contents).unwrap();
+        check(&session);
+        assert_eq!(session.header.backend, "msr");
+        assert_eq!(session.header.physical_core_count, 2);
+    }
+
+    #[test]
+    fn reads_csv_logs() {
+        let path =
+            std::env::temp_dir().join(format!("ryzen-wattage-import-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut log = CsvLog::open(&path).unwrap();
+        for sample in samples() {
+            log.append(&sample).unwrap();
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(is_export(&contents));
+        check(&parse(&contents).unwrap());
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        let csv = "timestamp,package_watts\n2026-04-03T11:04:05Z,12.5\nyesterday,13\n";
+        assert_eq!(parse(csv).unwrap_err(), "yesterday,13");
+        assert_eq!(
+            parse("timestamp,package_watts\n").unwrap_err(),
+            "timestamp,package_watts"
+        );
+        assert!(!is_export("{\"ryzen_wattage_session\":1}"));
+    }
+}
@@ -0,0 +1,145 @@
+//! `info`: everything the tool knows about the machine, gathered in one
+//! place for [`crate::output::info`] and [`crate::output::info_json`].
+
+use std::collections::BTreeMap;
+
+use crate::{
+    cpu::Threads,
+    cpuinfo::CpuInfo,
+    quirks::{ModelLimits, Quirk, Quirks},
+    smu::PmTable,
+    sysfs::Root,
+    topology::{self, Grouping},
+    Cpu, Error, Result,
+};
+
+#[derive(Debug)]
+pub struct Info {
+    pub cpu: CpuInfo,
+    pub bios_version: Option<String>,
+    pub smt_enabled: bool,
+    /// Online threads of each physical core.
+    pub threads: Threads,
+    /// Physical cores of each package, `None` if sysfs doesn't say.
+    pub packages: Option<BTreeMap<u32, Vec<u32>>>,
+    pub ccds: Option<BTreeMap<String, Vec<u32>>>,
+    pub ccxs: Option<BTreeMap<String, Vec<u32>>>,
+    /// What the backend can do, or why it can't be opened.
+    pub backend: std::result::Result<Backend, String>,
+    pub model_limits: Option<&'static ModelLimits>,
+    pub quirks: Vec<&'static Quirk>,
+    /// The layout of the SMU's PM table, or why it can't be decoded.
+    pub pm_table: std::result::Result<PmTable, String>,
+}
+
+/// Capabilities of the counters the tool measures with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backend {
+    pub name: &'static str,
+    /// Cores with their own counter, empty without per-core counters.
+    pub cores: Vec<u32>,
+    pub domains: Vec<&'static str>,
+    /// Joules per counter increment.
+    pub energy_unit: Option<f64>,
+    /// Joules after which the package counter wraps.
+    pub counter_range: Option<f64>,
+    /// Sustained package power limit in W.
+    pub power_limit: Option<f64>,
+}
+
+impl Info {
+    /// Gathers the topology of `cpu`, or of this machine if it can't be
+    /// opened, along with that error.
+    pub fn collect(
+        info: &CpuInfo,
+        quirks: &Quirks,
+        cpu: std::result::Result<&Cpu, &Error>,
+    ) -> Result<Self> {
+        let (root, smt_enabled, threads) = match cpu {
+            Ok(cpu) => (cpu.root().clone(), cpu.smt_enabled, cpu.threads.clone()),
+            Err(_) => {
+                let root = Root::system();
+                let (smt_enabled, _, threads) = Cpu::read_topology(&root)?;
+                (root, smt_enabled, threads)
+            }
+        };
+        let cores = threads.keys().copied().collect::<Vec<_>>();
+        let groups = |grouping| topology::groups(&root, grouping, info, &cores).ok();
+
+        Ok(Self {
+            cpu: info.clone(),
+            bios_version: quirks.bios_version.clone(),
+            smt_enabled,
+            packages: topology::packages(&root, &cores).ok(),
+            ccds: groups(Grouping::Ccd),
+            ccxs: groups(Grouping::Ccx),
+            threads,
+            backend: cpu.map(Backend::of).map_err(Error::to_string),
+            model_limits: ModelLimits::detect(info),
+            quirks: quirks.applied.clone(),
+            pm_table: PmTable::open(&root),
+        })
+    }
+
+    /// Online CPUs, the threads of all cores.
+    pub fn online(&self) -> Vec<u32> {
+        let mut online = self.threads.values().flatten().copied().collect::<Vec<_>>();
+        online.sort_unstable();
+        online
+    }
+}
+
+impl Backend {
+    pub fn of(cpu: &Cpu) -> Self {
+        Self {
+            name: cpu.backend_name(),
+            cores: cpu.core_ids(),
+            domains: cpu.domains(),
+            energy_unit: cpu.energy_unit(),
+            counter_range: cpu.package_energy_range(),
+            power_limit: cpu.package_power_limit(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sysfs::fixture::{self, Fixture},
+        BackendKind,
+    };
+
+    fn quirks() -> Quirks {
+        Quirks {
+            bios_version: Some("F37d".to_owned()),
+            applied: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn collects_packages_and_the_backend() {
+        let fixture = Fixture::from_listing(fixture::DUAL_SOCKET);
+        fixture
+            .file(
+                "/proc/cpuinfo",
+                "vendor_id\t: AuthenticAMD\ncpu family\t: 25\nmodel\t\t: 1\n",
+            )
+            .file("/sys/class/powercap/intel-rapl:0/name", "package-0\n")
+            .file("/sys/class/powercap/intel-rapl:0/energy_uj", "1000000\n");
+        let cpu = Cpu::with_root(BackendKind::Powercap, fixture.root().clone()).unwrap();
+        let info = Info::collect(&cpu.info, &quirks(), Ok(&cpu)).unwrap();
+
+        let packages = info.packages.as_ref().unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[&1], (8..16).collect::<Vec<_>>());
+        assert_eq!(info.online().len(), 32);
+        assert_eq!(info.bios_version.as_deref(), Some("F37d"));
+
+        let backend = info.backend.unwrap();
+        assert_eq!(backend.name, "powercap");
+        assert!(backend.cores.is_empty());
+        assert_eq!(backend.energy_unit, Some(1e-6));
+        assert_eq!(backend.power_limit, None);
+    }
+}
@@ -0,0 +1,135 @@
+//! One instance reading the hardware at a time.
+//!
+//! Two instances sampling at once read every MSR twice as often and, given
+//! the same `--log`, write interleaved rows into one CSV. The first one
+//! takes an exclusive `flock` on `instance.lock` in the state directory and
+//! keeps it until it exits, the kernel drops it for a crashed one too. The
+//! file says which process holds it and the socket it serves readings on
+//! with `--daemon`, so the second one can read from that instead.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+const LOCK_EX: i32 = 2;
+const LOCK_NB: i32 = 4;
+
+extern "C" {
+    fn flock(fd: i32, operation: i32) -> i32;
+}
+
+/// `<state dir>/instance.lock`.
+pub fn default_path() -> Option<PathBuf> {
+    Some(crate::state::state_dir()?.join("instance.lock"))
+}
+
+/// The lock of the running instance, released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// The instance holding the lock.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Holder {
+    pub pid: Option<u32>,
+    /// Where it serves readings, if it does.
+    pub socket: Option<PathBuf>,
+}
+
+impl Lock {
+    /// Takes the lock at `path` for this process serving on `socket`, or
+    /// says who has it.
+    pub fn acquire(path: &Path, socket: Option<&Path>) -> io::Result<Result<Self, Holder>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        // SAFETY: the descriptor is open for as long as `file` lives.
+        if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)?;
+            return Ok(Err(Holder::parse(&contents)));
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(
+            Holder {
+                pid: Some(process::id()),
+                socket: socket.map(Path::to_owned),
+            }
+            .serialize()
+            .as_bytes(),
+        )?;
+        Ok(Ok(Self { _file: file }))
+    }
+}
+
+impl Holder {
+    fn parse(contents: &str) -> Self {
+        let mut holder = Self::default();
+        for line in contents.lines() {
+            match line.split_once('=') {
+                Some(("pid", pid)) => holder.pid = pid.parse().ok(),
+                Some(("socket", socket)) => holder.socket = Some(PathBuf::from(socket)),
+                _ => {}
+            }
+        }
+        holder
+    }
+
+    fn serialize(&self) -> String {
+        let mut out = String::new();
+        if let Some(pid) = self.pid {
+            out += &format!("pid={}\n", pid);
+        }
+        if let Some(socket) = &self.socket {
+            out += &format!("socket={}\n", socket.display());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn second_instance_learns_about_the_first() {
+        let path = env::temp_dir().join(format!("ryzen-wattage-lock-{}", process::id()));
+        let socket = Path::new("/run/ryzen-wattage.sock");
+        let lock = Lock::acquire(&path, Some(socket)).unwrap().unwrap();
+
+        let holder = Lock::acquire(&path, None).unwrap().unwrap_err();
+        assert_eq!(holder.pid, Some(process::id()));
+        assert_eq!(holder.socket.as_deref(), Some(socket));
+
+        drop(lock);
+        let lock = Lock::acquire(&path, None).unwrap().unwrap();
+        assert_eq!(
+            Lock::acquire(&path, None).unwrap().unwrap_err(),
+            Holder {
+                pid: Some(process::id()),
+                socket: None,
+            }
+        );
+        drop(lock);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+//! Minimal JSON parser for the daemon protocol. Output is written by hand in
+//! [`crate::output`], this is mostly for reading it back.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::output::json_string;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(members) => members.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Self::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+/// Compact JSON again.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(s) => f.write_str(&json_string(s)),
+            Self::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Self::Object(members) => {
+                f.write_str("{")?;
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}:{}", json_string(key), value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        input: input.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    match parser.pos == parser.input.len() {
+        true => Ok(value),
+        false => Err(format!("unexpected input at byte {}", parser.pos)),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        match self.input[self.pos..].starts_with(literal.as_bytes()) {
+            true => {
+                self.pos += literal.len();
+                Ok(())
+            }
+            false => Err(format!("expected `{}` at byte {}", literal, self.pos)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+        match self.input.get(self.pos) {
+            Some(b'n') => self.expect("null").map(|_| Value::Null),
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(format!("unexpected character at byte {}", self.pos)),
+            None => Err("unexpected end of input".to_owned()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        let number = std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+        number
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| format!("invalid number `{}`", number))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut out = Vec::new();
+
+        loop {
+            let Some(&b) = self.input.get(self.pos) else {
+                return Err("unterminated string".to_owned());
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.input.get(self.pos).copied();
+                    self.pos += 1;
+                    let c = match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(format!("invalid escape at byte {}", self.pos - 1)),
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b => out.push(b),
+            }
+        }
+
+        String::from_utf8(out).map_err(|_| "invalid UTF-8 in string".to_owned())
+    }
+
+    /// The code point after `\u`, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xd800..=0xdbff => {
+                self.expect("\\u")?;
+                match self.hex4()? {
+                    low @ 0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _ => return Err(format!("invalid low surrogate at byte {}", self.pos - 4)),
+                }
+            }
+            _ => high,
+        };
+        char::from_u32(code).ok_or_else(|| format!("invalid code point {:#x}", code))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| format!("invalid \\u escape at byte {}", self.pos))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect("[")?;
+        let mut values = Vec::new();
+
+        self.whitespace();
+        if self.input.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.whitespace();
+            match self.input.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(format!("expected `,` or `]` at byte {}", self.pos)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect("{")?;
+        let mut members = BTreeMap::new();
+
+        self.whitespace();
+        if self.input.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(":")?;
+            members.insert(key, self.value()?);
+            self.whitespace();
+            match self.input.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(format!("expected `,` or `}}` at byte {}", self.pos)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_type() {
+        let value =
+            parse(r#" {"a": [1, -2.5e3, true, false, null], "b": {"c": "d"}, "e": []} "#).unwrap();
+
+        assert_eq!(
+            value.get("a").and_then(Value::as_array),
+            Some(
+                &[
+                    Value::Number(1.0),
+                    Value::Number(-2500.0),
+                    Value::Bool(true),
+                    Value::Bool(false),
+                    Value::Null,
+                ][..]
+            )
+        );
+        assert_eq!(
+            value
+                .get("b")
+                .and_then(|b| b.get("c"))
+                .and_then(Value::as_str),
+            Some("d")
+        );
+        assert_eq!(value.get("e").and_then(Value::as_array), Some(&[][..]));
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(parse("{}").unwrap().as_object(), Some(&BTreeMap::new()));
+    }
+
+    #[test]
+    fn unescapes_strings() {
+        assert_eq!(
+            parse(r#""\"\\\/\b\f\n\r\té😀 ü""#).unwrap(),
+            Value::String("\"\\/\u{8}\u{c}\n\r\té😀 ü".to_owned())
+        );
+        assert!(parse(r#""\x""#).is_err());
+        assert!(parse(r#""\ud83d""#).is_err());
+        assert!(parse(r#""\ud83dA""#).is_err());
+        assert!(parse(r#""\u12""#).is_err());
+        assert!(parse(r#""open"#).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let input = r#"{"control":"\u0001","name":"a \"b\"\n","values":[1.5,null,"x"]}"#;
+        let value = parse(input).unwrap();
+        assert_eq!(value.to_string(), input);
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+        // JSON has no NaN.
+        assert_eq!(Value::Number(f64::NAN).to_string(), "null");
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for input in [
+            "",
+            "[1,",
+            "[1 2]",
+            "{\"a\" 1}",
+            "{\"a\":1,}",
+            "nul",
+            "1 2",
+            "-",
+            "{1:2}",
+        ] {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+        assert_eq!(parse("[1] x").unwrap_err(), "unexpected input at byte 4");
+    }
+}
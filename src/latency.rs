@@ -0,0 +1,183 @@
+//! `debug read-latency`: how long reading each energy counter takes.
+//!
+//! An MSR read runs on the CPU the register belongs to, by an interrupt
+//! from the reading one, and takes longer the further away that CPU is.
+//! Some cores, whole CCDs on dual-CCD parts, answer much slower than the
+//! rest, and one sample reads every counter one after the other, so the
+//! slowest ones bound how short a sampling window can usefully be.
+
+use std::{iter, time::Instant};
+
+use crate::{
+    stats::{Bucket, Distribution},
+    BackendKind, Cpu, Result,
+};
+
+/// Reads of each counter without `-n`.
+pub const DEFAULT_READS: usize = 1000;
+
+/// Cores whose median read takes this many times the median core's are
+/// pointed out.
+pub const SLOW_FACTOR: f64 = 1.5;
+
+/// The reads of one backend.
+#[derive(Debug)]
+pub struct Latencies {
+    pub backend: &'static str,
+    pub result: Result<Vec<Counter>>,
+}
+
+/// How long the reads of one counter took.
+#[derive(Debug, Clone, Default)]
+pub struct Counter {
+    /// `None` for the package counter.
+    pub core: Option<u32>,
+    /// Seconds per read.
+    pub seconds: Distribution,
+}
+
+/// Times `reads` reads of every counter of each backend that opens.
+pub fn measure_backends(reads: usize) -> Vec<Latencies> {
+    [BackendKind::Msr, BackendKind::Powercap]
+        .into_iter()
+        .map(|kind| Latencies {
+            backend: kind.name(),
+            result: Cpu::new(kind).and_then(|cpu| measure(&cpu, reads)),
+        })
+        .collect()
+}
+
+/// Times `reads` reads of the package counter and each core's of `cpu`.
+pub fn measure(cpu: &Cpu, reads: usize) -> Result<Vec<Counter>> {
+    let mut counters = iter::once(None)
+        .chain(cpu.core_ids().into_iter().map(Some))
+        .map(|core| Counter {
+            core,
+            seconds: Distribution::default(),
+        })
+        .collect::<Vec<_>>();
+
+    // Round after round instead of one counter at a time, so whatever else
+    // the machine does at some point slows all of them alike.
+    for _ in 0..reads {
+        for counter in &mut counters {
+            let start = Instant::now();
+            cpu.read_counter(counter.core)?;
+            counter.seconds.push(start.elapsed().as_secs_f64());
+        }
+    }
+    Ok(counters)
+}
+
+/// Seconds one sample of all `counters` takes at their medians.
+pub fn sample_seconds(counters: &[Counter]) -> f64 {
+    counters
+        .iter()
+        .map(|counter| counter.seconds.percentile(50.0))
+        .sum()
+}
+
+/// Cores reading at least [`SLOW_FACTOR`] times slower than the median
+/// core, with how many times.
+pub fn slow_cores(counters: &[Counter]) -> Vec<(u32, f64)> {
+    let mut medians = counters
+        .iter()
+        .filter_map(|counter| Some((counter.core?, counter.seconds.percentile(50.0))))
+        .collect::<Vec<_>>();
+    let mut sorted = medians
+        .iter()
+        .map(|&(_, median)| median)
+        .collect::<Vec<_>>();
+    sorted.sort_by(f64::total_cmp);
+    let Some(&typical) = sorted
+        .get(sorted.len() / 2)
+        .filter(|&&typical| typical > 0.0)
+    else {
+        return Vec::new();
+    };
+
+    medians.retain(|&(_, median)| median >= typical * SLOW_FACTOR);
+    medians
+        .into_iter()
+        .map(|(core, median)| (core, median / typical))
+        .collect()
+}
+
+/// The reads of all `counters` in buckets below 1µs, then doubling in
+/// width up to the slowest read.
+pub fn histogram(counters: &[Counter]) -> Vec<Bucket> {
+    let total = counters
+        .iter()
+        .map(|counter| counter.seconds.len())
+        .sum::<usize>();
+    let slowest = counters
+        .iter()
+        .map(|counter| counter.seconds.percentile(100.0))
+        .fold(0.0, f64::max);
+    let below = |seconds: f64| {
+        counters
+            .iter()
+            .map(|counter| counter.seconds.count_below(seconds))
+            .sum::<usize>()
+    };
+
+    let mut buckets = Vec::new();
+    let (mut low, mut high) = (0.0, 1e-6);
+    while total > 0 && low <= slowest {
+        let count = match high > slowest {
+            true => total - below(low),
+            false => below(high) - below(low),
+        };
+        buckets.push(Bucket { low, high, count });
+        (low, high) = (high, high * 2.0);
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Profile;
+
+    fn counter(core: Option<u32>, micros: &[f64]) -> Counter {
+        let mut seconds = Distribution::default();
+        for &value in micros {
+            seconds.push(value * 1e-6);
+        }
+        Counter { core, seconds }
+    }
+
+    #[test]
+    fn times_every_counter() {
+        let counters = measure(&Cpu::simulated(Profile::Idle), 10).unwrap();
+        assert_eq!(counters.len(), 9);
+        assert_eq!(counters[0].core, None);
+        assert_eq!(counters[8].core, Some(7));
+        assert!(counters.iter().all(|counter| counter.seconds.len() == 10));
+    }
+
+    #[test]
+    fn points_out_slow_cores() {
+        let counters = [
+            counter(None, &[1.0]),
+            counter(Some(0), &[2.0]),
+            counter(Some(1), &[2.0]),
+            counter(Some(8), &[7.0]),
+        ];
+        let slow = slow_cores(&counters);
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].0, 8);
+        assert!((slow[0].1 - 3.5).abs() < 1e-9);
+        assert!((sample_seconds(&counters) - 12e-6).abs() < 1e-12);
+
+        let buckets = histogram(&counters);
+        let counts = buckets
+            .iter()
+            .map(|bucket| bucket.count)
+            .collect::<Vec<_>>();
+        // Below 1µs, 1-2µs, 2-4µs and 4-8µs.
+        assert_eq!(counts, [0, 1, 2, 1]);
+        assert!((buckets[3].high - 8e-6).abs() < 1e-12);
+        assert!(histogram(&[]).is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+//! The daemon's ledger of estimated CPU energy per process name and day,
+//! for seeing which applications used the most energy over weeks without a
+//! metrics stack to keep the history in.
+//!
+//! Every window's package energy is split by CPU time, like `attribute`
+//! does for one tree. Only the [`TOP`] names of a day are kept, everything
+//! below them adds up under [`OTHER`]. Days are local dates, `2026-10-14`.
+//!
+//! The first line has the version of the [`FORMAT`]. Ledgers of a newer one
+//! are left alone, the daemon goes on with an empty ledger it doesn't save.
+
+use std::{collections::BTreeMap, fmt::Write as _, fs, io, path::PathBuf, time::Duration};
+
+use crate::{
+    migrate::{self, Format, Refusal},
+    state::{machine_id, state_dir},
+    sysfs::Root,
+};
+
+/// Process names kept per day.
+pub const TOP: usize = 50;
+/// Days kept, the oldest go first.
+pub const DAYS: usize = 366;
+/// Where the energy of the names that didn't make it into the top goes.
+pub const OTHER: &str = "(other)";
+/// How often the daemon writes the ledger, a crash loses at most that.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+const HEADER: &str = "# ryzen-wattage ledger ";
+
+/// Version 0 is the ledger from before it had one, the same lines under
+/// another comment.
+pub const FORMAT: Format<String> = Format {
+    name: "ledger",
+    first: 0,
+    current: 1,
+    migrations: &[|contents| contents],
+};
+
+/// Joules per process name and day.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ledger {
+    days: BTreeMap<String, BTreeMap<String, f64>>,
+    /// Whether this stands in for a ledger that was refused and mustn't be
+    /// overwritten.
+    unsaved: bool,
+}
+
+impl Ledger {
+    /// Loads the ledger of this machine, an empty one if there is none yet.
+    pub fn load() -> Result<Self, Refusal> {
+        match Self::path().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(contents) => Self::migrate(contents).map(|contents| Self::parse(&contents)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// An empty ledger that [`Ledger::save`] never writes, for going on
+    /// without the one that was refused.
+    pub fn unsaved() -> Self {
+        Self {
+            unsaved: true,
+            ..Self::default()
+        }
+    }
+
+    /// Brings the contents of a ledger file to the current version.
+    pub fn migrate(contents: String) -> Result<String, Refusal> {
+        let version = migrate::line_version(contents.as_bytes(), HEADER).unwrap_or(0);
+        FORMAT.migrate(version, contents)
+    }
+
+    /// Saves the ledger with every day cut down to its [`TOP`] names.
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.unsaved {
+            return Ok(());
+        }
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        self.trim();
+        // Written next to it and renamed, a daemon killed halfway doesn't
+        // lose the days before.
+        let temporary = path.with_extension("ledger.tmp");
+        fs::write(&temporary, self.serialize())?;
+        fs::rename(temporary, path)
+    }
+
+    /// `$XDG_STATE_HOME/ryzen-wattage/<machine id>.ledger`.
+    pub fn path() -> Option<PathBuf> {
+        Some(state_dir()?.join(format!("{}.ledger", machine_id(&Root::system()))))
+    }
+
+    /// Adds `joules` to `name` on `day`.
+    pub fn add(&mut self, day: &str, name: &str, joules: f64) {
+        if joules.is_nan() || joules <= 0.0 {
+            return;
+        }
+        // Names end up in a line based file, and `comm` can be anything.
+        let name = name.replace(|c: char| c.is_control(), "?");
+        *self
+            .days
+            .entry(day.to_owned())
+            .or_default()
+            .entry(name)
+            .or_default() += joules;
+    }
+
+    /// Names and joules on `day`, or summed over every day without one,
+    /// most energy first. [`OTHER`] comes last however much it is.
+    pub fn entries(&self, day: Option<&str>) -> Vec<(String, f64)> {
+        let mut totals = BTreeMap::<&str, f64>::new();
+        let days = self
+            .days
+            .iter()
+            .filter(|(date, _)| day.is_none_or(|day| day == date.as_str()));
+        for (_, names) in days {
+            for (name, joules) in names {
+                *totals.entry(name).or_default() += joules;
+            }
+        }
+
+        let mut entries = totals
+            .into_iter()
+            .map(|(name, joules)| (name.to_owned(), joules))
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            (a.0 == OTHER)
+                .cmp(&(b.0 == OTHER))
+                .then(b.1.total_cmp(&a.1))
+        });
+        entries
+    }
+
+    /// Folds every name below the [`TOP`] of its day into [`OTHER`] and
+    /// drops the days beyond [`DAYS`].
+    pub fn trim(&mut self) {
+        while self.days.len() > DAYS {
+            self.days.pop_first();
+        }
+
+        for names in self.days.values_mut() {
+            let other = names.remove(OTHER).unwrap_or(0.0);
+            let mut ranked = std::mem::take(names).into_iter().collect::<Vec<_>>();
+            ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let rest = ranked.split_off(ranked.len().min(TOP));
+
+            names.extend(ranked);
+            let other = other + rest.iter().map(|(_, joules)| joules).sum::<f64>();
+            if other > 0.0 {
+                names.insert(OTHER.to_owned(), other);
+            }
+        }
+    }
+
+    /// One `day joules name` line per entry, the name last since it can
+    /// have spaces, in the current version.
+    pub fn parse(contents: &str) -> Self {
+        let mut ledger = Self::default();
+
+        for line in contents.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, ' ');
+            let (Some(day), Some(joules), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if let Ok(joules) = joules.parse() {
+                ledger.add(day, name, joules);
+            }
+        }
+
+        ledger
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = format!(
+            "{}{}: energy per process and day, in joules\n",
+            HEADER, FORMAT.current
+        );
+
+        for (day, names) in &self.days {
+            for (name, joules) in names {
+                writeln!(out, "{} {:.3} {}", day, joules, name).unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_and_round_trips() {
+        let mut ledger = Ledger::default();
+        ledger.add("2026-10-13", "firefox", 100.0);
+        ledger.add("2026-10-14", "firefox", 50.0);
+        ledger.add("2026-10-14", "Web Content", 80.0);
+        ledger.add("2026-10-14", "bad\nname", 1.0);
+        ledger.add("2026-10-14", "idle", 0.0);
+
+        assert_eq!(
+            ledger.entries(Some("2026-10-14")),
+            [
+                ("Web Content".to_owned(), 80.0),
+                ("firefox".to_owned(), 50.0),
+                ("bad?name".to_owned(), 1.0),
+            ]
+        );
+        assert_eq!(ledger.entries(None)[0], ("firefox".to_owned(), 150.0));
+        assert!(ledger.entries(Some("2026-10-15")).is_empty());
+        assert_eq!(Ledger::parse(&ledger.serialize()), ledger);
+    }
+
+    #[test]
+    fn keeps_the_top_of_every_day() {
+        let mut ledger = Ledger::default();
+        for n in 0..TOP + 5 {
+            ledger.add("2026-10-14", &format!("p{}", n), n as f64 + 1.0);
+        }
+        ledger.add("2026-10-14", OTHER, 10.0);
+        ledger.trim();
+
+        let entries = ledger.entries(Some("2026-10-14"));
+        assert_eq!(entries.len(), TOP + 1);
+        assert_eq!(entries[0], (format!("p{}", TOP + 4), TOP as f64 + 5.0));
+        // p0 to p4 with 1 to 5 J, and what was there already.
+        assert_eq!(entries[TOP], (OTHER.to_owned(), 25.0));
+    }
+
+    #[test]
+    fn migrates_unversioned_ledgers_and_refuses_newer_ones() {
+        let old = "# ryzen-wattage energy per process and day, in joules\n\
+                   2026-10-14 12.500 firefox\n";
+        let ledger = Ledger::parse(&Ledger::migrate(old.to_owned()).unwrap());
+        assert_eq!(ledger.entries(None), [("firefox".to_owned(), 12.5)]);
+        assert!(ledger.serialize().starts_with("# ryzen-wattage ledger 1:"));
+        assert_eq!(
+            Ledger::migrate(ledger.serialize()).unwrap(),
+            ledger.serialize()
+        );
+
+        let newer = Ledger::migrate("# ryzen-wattage ledger 2: joules\n".to_owned());
+        assert!(matches!(newer, Err(Refusal::Newer { version: 2, .. })));
+
+        let mut unsaved = Ledger::unsaved();
+        unsaved.add("2026-10-14", "firefox", 1.0);
+        assert!(unsaved.save().is_ok());
+    }
+}
@@ -0,0 +1,87 @@
+pub mod adaptive;
+pub mod advise;
+pub mod audit;
+pub mod backend;
+pub mod battery;
+pub mod bmc;
+pub mod breakdown;
+pub mod bugreport;
+#[cfg(feature = "dbus")]
+pub mod bus;
+pub mod client;
+pub mod codegen;
+pub mod compare;
+pub mod cpu;
+pub mod cpufreq;
+pub mod cpuinfo;
+pub mod crosscheck;
+pub mod daemon;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(feature = "dbus")]
+pub mod desktop;
+pub mod digest;
+pub mod discord;
+pub mod error;
+pub mod experiment;
+#[cfg(feature = "exporter")]
+pub mod exporter;
+pub mod firehose;
+pub mod gpu;
+pub mod graph;
+pub mod guardrail;
+pub mod history;
+pub mod hooks;
+pub mod html;
+pub mod i18n;
+pub mod import;
+pub mod info;
+pub mod instance;
+pub mod json;
+pub mod latency;
+pub mod ledger;
+#[cfg(feature = "dbus")]
+pub mod logind;
+pub mod metrics;
+pub mod migrate;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod network;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod output;
+pub mod overlay;
+pub mod perf;
+pub mod polkit;
+pub mod process;
+pub mod procwatch;
+pub mod quirks;
+pub mod record;
+pub mod run;
+pub mod sanity;
+pub mod sched;
+pub mod schema;
+pub mod signal;
+pub mod sink;
+pub mod smu;
+pub mod spool;
+pub mod state;
+pub mod stats;
+pub mod status;
+pub mod suspend;
+pub mod sysfs;
+pub mod systemd;
+pub mod temperature;
+pub mod timefmt;
+pub mod toml;
+pub mod topology;
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod tune;
+
+pub use self::{
+    backend::{BackendKind, EnergyReader},
+    cpu::{Cpu, Power, RawEnergy},
+    error::{Error, Result},
+};
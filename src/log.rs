@@ -0,0 +1,74 @@
+//! Messages on stderr, prefixed with the program name and level on a
+//! terminal, and with a syslog priority the journal picks up under
+//! `--service`.
+
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static JOURNAL: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Notice,
+    Info,
+}
+
+impl Level {
+    /// As in syslog(3), which is what the journal expects in `<N>`.
+    fn priority(self) -> u8 {
+        match self {
+            Self::Error => 3,
+            Self::Warning => 4,
+            Self::Notice => 5,
+            Self::Info => 6,
+        }
+    }
+}
+
+/// Writes messages the way the journal reads them from now on.
+pub fn to_journal() {
+    JOURNAL.store(true, Ordering::Relaxed);
+}
+
+/// Leaves out notices and info from now on.
+pub fn quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+pub fn log(level: Level, message: impl Display) {
+    if QUIET.load(Ordering::Relaxed) && matches!(level, Level::Notice | Level::Info) {
+        return;
+    }
+    if JOURNAL.load(Ordering::Relaxed) {
+        // The journal has the program name already, and shows the level.
+        eprintln!("<{}>{}", level.priority(), message);
+        return;
+    }
+    match level {
+        Level::Error => eprintln!("ryzen-wattage: error: {}", message),
+        Level::Warning => eprintln!("ryzen-wattage: warning: {}", message),
+        Level::Notice => eprintln!("ryzen-wattage: notice: {}", message),
+        Level::Info => eprintln!("ryzen-wattage: {}", message),
+    }
+}
+
+pub fn error(message: impl Display) {
+    log(Level::Error, message);
+}
+
+pub fn warning(message: impl Display) {
+    log(Level::Warning, message);
+}
+
+pub fn notice(message: impl Display) {
+    log(Level::Notice, message);
+}
+
+pub fn info(message: impl Display) {
+    log(Level::Info, message);
+}
@@ -0,0 +1,140 @@
+//! `--show presence`: whether the desktop session was in use, idle or
+//! locked, from logind on the system bus, so a laptop's power over a day
+//! falls apart into the time it was used and the time it wasn't.
+//!
+//! The session is the one of `$XDG_SESSION_ID`, or else for a daemon
+//! outside of any session, the active one of `seat0`, asked for again every
+//! time so switching users is followed.
+
+use std::{env, io};
+
+use crate::{
+    dbus::{Connection, Message, Reader, Writer},
+    output::Presence,
+};
+
+const SERVICE: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER: &str = "org.freedesktop.login1.Manager";
+const SEAT_PATH: &str = "/org/freedesktop/login1/seat/seat0";
+const SEAT: &str = "org.freedesktop.login1.Seat";
+const SESSION: &str = "org.freedesktop.login1.Session";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// A connection to logind.
+#[derive(Debug)]
+pub struct Logind {
+    connection: Connection,
+    /// The object of `$XDG_SESSION_ID`, `None` to follow the seat.
+    session: Option<String>,
+}
+
+impl Logind {
+    pub fn connect() -> io::Result<Self> {
+        let mut connection = Connection::system()?;
+        let session = match env::var("XDG_SESSION_ID") {
+            Ok(id) if !id.is_empty() => {
+                let mut args = Writer::new();
+                args.string(&id);
+                let reply = connection.call(Message::method_call(
+                    SERVICE,
+                    MANAGER_PATH,
+                    (MANAGER, "GetSession"),
+                    "s",
+                    args,
+                ))?;
+                Some(reply.reader().string().ok_or_else(unexpected)?)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            connection,
+            session,
+        })
+    }
+
+    /// The presence of the session now, `None` when no one is logged in on
+    /// the seat.
+    pub fn presence(&mut self) -> io::Result<Option<Presence>> {
+        let session = match &self.session {
+            Some(session) => session.clone(),
+            None => {
+                let reply = self.get(SEAT_PATH, SEAT, "ActiveSession")?;
+                match active_session(reply.reader()).ok_or_else(unexpected)? {
+                    Some(session) => session,
+                    None => return Ok(None),
+                }
+            }
+        };
+        let mut hint = |name| {
+            let reply = self.get(&session, SESSION, name)?;
+            let hint = reply.reader().variant("b", Reader::boolean);
+            hint.ok_or_else(unexpected)
+        };
+        let locked = hint("LockedHint")?;
+        let idle = hint("IdleHint")?;
+        Ok(Some(Presence::of(locked, idle)))
+    }
+
+    fn get(&mut self, path: &str, interface: &str, name: &str) -> io::Result<Message> {
+        let mut args = Writer::new();
+        args.string(interface).string(name);
+        self.connection.call(Message::method_call(
+            SERVICE,
+            path,
+            (PROPERTIES, "Get"),
+            "ss",
+            args,
+        ))
+    }
+}
+
+/// The object of the seat's `ActiveSession`, a variant of its ID and path,
+/// `Some(None)` for an empty ID, which is none.
+fn active_session(mut reply: Reader) -> Option<Option<String>> {
+    let (id, path) = reply.variant("(so)", |value| {
+        value.structure(|fields| Some((fields.string()?, fields.string()?)))
+    })?;
+    Some((!id.is_empty()).then_some(path))
+}
+
+fn unexpected() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "unexpected reply from logind")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_active_session_of_the_seat() {
+        let mut body = Writer::new();
+        body.variant("(so)", |value| {
+            value.structure(|fields| {
+                fields
+                    .string("2")
+                    .object_path("/org/freedesktop/login1/session/_32");
+            })
+        });
+        let reply = Message::method_call(SERVICE, SEAT_PATH, (PROPERTIES, "Get"), "v", body);
+        assert_eq!(
+            active_session(reply.reader()),
+            Some(Some("/org/freedesktop/login1/session/_32".to_owned()))
+        );
+
+        let mut body = Writer::new();
+        body.variant("(so)", |value| {
+            value.structure(|fields| {
+                fields.string("").object_path("/");
+            })
+        });
+        let reply = Message::method_call(SERVICE, SEAT_PATH, (PROPERTIES, "Get"), "v", body);
+        assert_eq!(active_session(reply.reader()), Some(None));
+
+        let mut body = Writer::new();
+        body.variant("b", |value| value.u32(1));
+        let reply = Message::method_call(SERVICE, SEAT_PATH, (PROPERTIES, "Get"), "v", body);
+        assert_eq!(active_session(reply.reader()), None);
+        assert_eq!(reply.reader().variant("b", Reader::boolean), Some(true));
+    }
+}
@@ -1,186 +1,2275 @@
 #![allow(dead_code)]
 
+mod args;
+mod bisect;
+mod capture;
+mod check;
+mod config;
+mod log;
+mod omitted;
+mod setup;
+mod shell;
+
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fs::{self, File},
-    io::{self, Read, Seek, SeekFrom},
-    path::PathBuf,
+    io::{self, Write},
+    path::Path,
+    process::{self, ExitStatus},
+    sync::Arc,
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
-type MsrMap = BTreeMap<u32, Msr>;
+use args::{Args, Command, Format, ReaderThreads, Show};
+use ryzen_wattage::{
+    adaptive::Adaptive,
+    advise,
+    backend::{self, Registers},
+    battery::Drain,
+    breakdown::Breakdown,
+    bugreport::{self, Report},
+    client::Client,
+    codegen, compare,
+    cpu::Snapshot,
+    cpuinfo::CpuInfo,
+    crosscheck,
+    daemon::{self, Daemon},
+    digest::{self, Digests},
+    discord::Discord,
+    experiment::{self, Manifest},
+    firehose, gpu,
+    graph::Graph,
+    guardrail::{self, Limit},
+    history::History,
+    hooks::{Hook, Hooks, PowerWatch},
+    html,
+    i18n::{tr, trf},
+    info::Info,
+    instance::{self, Lock},
+    latency::{self, Latencies},
+    ledger::{self, Ledger},
+    network::{self, Network},
+    output::{self, CsvLog, Sample, Source, TextOptions},
+    overlay,
+    perf::{self, Recording, Script},
+    polkit,
+    process::{NameMeter, Splitter, TreeMeter, Watchlist},
+    quirks::Quirks,
+    record::{Header, Recorder, Session},
+    run,
+    sanity::{self, MigrationNoise, Watchdog},
+    sched::{SchedMeter, Tracer},
+    schema, signal,
+    sink::{Change, Health, Worker},
+    smu::PmTable,
+    state::{Calibration, State},
+    stats::{Smoother, Summary},
+    status::{self, StatusFile},
+    suspend::Suspends,
+    sysfs::Root,
+    systemd::Notifier,
+    temperature,
+    timefmt::{self, Zone},
+    topology::{self, Grouping},
+    trace::Trace,
+    tune, BackendKind, Cpu, Error, Result,
+};
+use shell::Shell;
 
-#[derive(Debug)]
-struct Cpu {
-    pub smt_enabled: bool,
-    pub core_count: u32,
-    pub physical_core_count: u32,
-    core_msr: MsrMap,
-}
+#[cfg(not(feature = "exporter"))]
+use omitted::exporter::Exporter;
+#[cfg(not(feature = "tui"))]
+use omitted::tui::{self, Dashboard};
+#[cfg(not(feature = "dbus"))]
+use omitted::{
+    bus::{Bus, Reading, Service},
+    desktop::Notifications,
+    logind::Logind,
+};
+#[cfg(feature = "mqtt")]
+use ryzen_wattage::mqtt::Publisher;
+#[cfg(feature = "exporter")]
+use ryzen_wattage::{
+    bmc::{self, NodePower},
+    exporter::Exporter,
+};
+#[cfg(feature = "dbus")]
+use ryzen_wattage::{
+    bus::{self, Bus, Reading, Service},
+    desktop::Notifications,
+    logind::Logind,
+};
+#[cfg(feature = "tui")]
+use ryzen_wattage::{
+    graph,
+    tui::{self, Dashboard},
+};
+#[cfg(feature = "otlp")]
+use ryzen_wattage::{otlp::Pusher, spool::Spool};
 
-impl Cpu {
-    pub fn new() -> io::Result<Self> {
-        let smt_status = fs::read_to_string("/sys/devices/system/cpu/smt/control")?;
-        let smt_enabled = smt_status.trim_end() == "on";
+/// Of `--print-dockerfile`, the image the exporter usually runs in.
+const DOCKERFILE: &str = include_str!("../data/Dockerfile");
 
-        let core_count = Self::get_cores()?;
-        let physical_core_count = Self::get_physical_cores(smt_enabled, core_count)?;
-        let core_msr = Self::get_msr_info(physical_core_count);
+fn main() {
+    // pkexec runs us again as root for writes that need it.
+    let raw_args = std::env::args().skip(1).collect::<Vec<_>>();
+    if raw_args.first().map(String::as_str) == Some(polkit::HELPER_COMMAND) {
+        if let Err(err) = polkit::run_helper(&raw_args[1..]) {
+            log::error(err);
+            process::exit(1);
+        }
+        return;
+    }
 
-        Ok(Self {
-            smt_enabled,
-            core_count,
-            physical_core_count,
-            core_msr,
-        })
+    let mut args = match Args::from_env() {
+        Ok(args) => args,
+        Err(args::Error::Help) => {
+            print!("{}", args::usage());
+            return;
+        }
+        Err(err) => {
+            eprintln!("ryzen-wattage: {}\n\n{}", err, args::usage());
+            process::exit(2);
+        }
+    };
+
+    if args.offline {
+        network::forbid();
+    }
+    if args.read_only {
+        polkit::forbid_writes();
+    }
+    timefmt::configure(args.timezone.clone(), args.time_format.clone());
+    if let Some(lang) = args.lang {
+        print!("{}", codegen::client(lang));
+        return;
+    }
+    if let Some(document) = args.schema {
+        print!("{}", schema::json_schema(document));
+        return;
+    }
+    if args.print_dockerfile {
+        print!("{}", DOCKERFILE);
+        return;
+    }
+    if args.service {
+        log::to_journal();
+    }
+    if args.quiet {
+        log::quiet();
     }
 
-    fn get_cores() -> io::Result<u32> {
-        let cores_online = fs::read_to_string("/sys/devices/system/cpu/online")?;
-        let (_, max) = cores_online.trim_end().split_once("-").unwrap();
-        let cores_online_max = max.parse::<u32>().unwrap() + 1;
-        Ok(cores_online_max)
-    }
-
-    fn get_physical_cores(smt_enabled: bool, core_count: u32) -> io::Result<u32> {
-        let core_count = if smt_enabled {
-            let mut cores = BTreeSet::new();
-            for core_id in 0..core_count {
-                let cpus_list = fs::read_to_string(format!(
-                    "/sys/devices/system/cpu/cpu{}/topology/core_cpus_list",
-                    core_id
-                ))?;
-                let min_cpu_id = cpus_list
-                    .trim_end()
-                    .split(",")
-                    .map(|val| val.parse::<u32>().unwrap())
-                    .min()
-                    .unwrap();
-                cores.insert(min_cpu_id);
-            }
-            cores.len() as u32
-        } else {
-            core_count
+    // Everything comes from the daemon, the hardware isn't touched at all.
+    if args.client {
+        process::exit(run_client(&args));
+    }
+    if args.command == Command::Ledger {
+        process::exit(show_ledger(&args));
+    }
+    if args.command == Command::HistoryQuery {
+        process::exit(history_query(&args));
+    }
+    if args.command == Command::SetLimit {
+        process::exit(set_limit(&args));
+    }
+    if matches!(args.command, Command::Init | Command::Teardown) {
+        let (input, output) = (&mut io::stdin().lock(), &mut io::stdout());
+        let result = match args.command {
+            Command::Init => setup::init(input, output),
+            _ => setup::teardown(input, output),
         };
+        if let Err(err) = result {
+            log::error(err);
+            process::exit(1);
+        }
+        return;
+    }
+    // Like here, from a session file.
+    if args.command == Command::Replay {
+        replay(&args);
+        return;
+    }
+    if args.command == Command::Report {
+        html_report(&args);
+        return;
+    }
+    if args.command == Command::PerfSidecar {
+        perf_sidecar(&args);
+        return;
+    }
+    if args.command == Command::PerfFold {
+        perf_fold(&args);
+        return;
+    }
+    if args.command == Command::Compare && !args.compare_files.is_empty() {
+        compare_files(&args);
+        return;
+    }
+
+    // The energy MSRs only exist on x86; elsewhere only sysfs can work.
+    let is_x86 = cfg!(any(target_arch = "x86", target_arch = "x86_64"));
+    if !is_x86 && args.backend == BackendKind::Msr {
+        log::error(format_args!(
+            "unsupported architecture `{}` for the msr backend, energy MSRs are only available on x86 CPUs",
+            std::env::consts::ARCH
+        ));
+        process::exit(1);
+    }
 
-        Ok(core_count)
+    let saved_state = State::load();
+    let mut state = saved_state.clone();
+
+    if let Some(calibration) = args.calibrate {
+        state.calibration = calibration;
     }
 
-    fn get_msr_info(physical_core_count: u32) -> MsrMap {
-        let mut map = MsrMap::new();
+    let cpu_info = CpuInfo::read(&Root::system()).unwrap_or_default();
+    let mut quirks = Quirks::detect(&Root::system(), &cpu_info);
+    quirks.keep(&state.quirks);
+    state.quirks = quirks.ids();
+
+    if args.list_quirks {
+        print!("{}", output::quirks(&cpu_info, &quirks));
+        return;
+    }
 
-        for core in 0..physical_core_count {
-            let msr = Msr::new(core);
-            map.insert(core, msr);
+    if args.command == Command::CrossCheck {
+        let readings = crosscheck::cross_check(args.interval);
+        print!("{}", output::cross_check(&readings));
+        return;
+    }
+
+    if args.command == Command::MsrSafeAllowlist {
+        match Registers::for_cpu(&cpu_info) {
+            Ok(registers) => print!("{}", registers.msr_safe_allowlist()),
+            Err(err) => exit_with_error(err),
         }
+        return;
+    }
 
-        map
+    if args.command == Command::ReadLatency {
+        let reads = args.samples.unwrap_or(latency::DEFAULT_READS);
+        let backends = match args.simulate {
+            Some(profile) => vec![Latencies {
+                backend: "simulated",
+                result: latency::measure(&Cpu::simulated(profile), reads),
+            }],
+            None => latency::measure_backends(reads),
+        };
+        match args.format {
+            Format::Json => println!("{}", output::read_latency_json(&backends)),
+            _ => {
+                let text_options = TextOptions {
+                    screen_reader: args.screen_reader,
+                    ..TextOptions::default()
+                };
+                print!("{}", output::read_latency(&backends, &text_options))
+            }
+        }
+        return;
     }
 
-    pub fn package_energy(&self) -> f64 {
-        let (_, energy) = self
-            .core_msr
-            .iter()
-            .map(|(core, msr)| (core, msr.package_energy().unwrap()))
-            .next()
-            .unwrap();
+    // Before opening the CPU, not being able to is worth reporting too.
+    if args.command == Command::ExportReport {
+        export_report(&args, &cpu_info, &quirks);
+        return;
+    }
+
+    let backend = match args.backend {
+        BackendKind::Auto if !is_x86 => BackendKind::Powercap,
+        backend => backend,
+    };
+    // Simulated counters can't be read too often, info is over in a moment.
+    let _instance =
+        (args.simulate.is_none() && !args.allow_multiple && args.command != Command::Info)
+            .then(|| lock_instance(&mut args))
+            .flatten();
+    let cpu = match args.simulate {
+        Some(profile) => Ok(Cpu::simulated(profile)),
+        None => open_cpu(
+            backend,
+            args.msr_path_template.clone(),
+            args.tune_backend,
+            &mut state,
+        ),
+    };
 
-        energy
+    if args.command == Command::Info {
+        let info = Info::collect(&cpu_info, &quirks, cpu.as_ref())
+            .unwrap_or_else(|err| exit_with_error(err));
+        match args.format {
+            Format::Json => println!("{}", output::info_json(&info)),
+            _ => print!(
+                "{}",
+                output::info(
+                    &info,
+                    &TextOptions {
+                        screen_reader: args.screen_reader,
+                        timestamps: false,
+                        view: args.view.clone(),
+                    },
+                )
+            ),
+        }
+        return;
     }
 
-    pub fn core_energy(&self) -> BTreeMap<u32, f64> {
-        self.core_msr
-            .iter()
-            .map(|(core, msr)| (*core, msr.core_energy().unwrap()))
-            .collect()
+    let mut cpu = cpu.unwrap_or_else(|err| {
+        if args.command == Command::BisectHelper {
+            log::error(err);
+            process::exit(bisect::ABORT);
+        }
+        if args.is_check() {
+            log::error(err);
+            process::exit(check::UNKNOWN);
+        }
+        exit_with_error(err)
+    });
+    cpu.idle_residency = args.show.cstate;
+    if args.reader_threads == ReaderThreads::Ccd {
+        match cpu.read_per_ccd() {
+            Ok(1) => log::notice(tr(
+                "only one CCD, --reader-threads ccd reads it on one thread",
+            )),
+            Ok(_) => {}
+            Err(err) => log::warning(format_args!(
+                "cannot find the CCDs, reading without a thread per CCD: {}",
+                err
+            )),
+        }
+    }
+
+    if state != saved_state {
+        if let Err(err) = state.save() {
+            log::warning(format_args!("cannot save machine state: {}", err));
+        }
+    }
+
+    let text_options = TextOptions {
+        screen_reader: args.screen_reader,
+        timestamps: args.show.time,
+        view: args.view.clone(),
+    };
+
+    if args.verbose {
+        eprint!("{}", output::resolution(&cpu, args.interval, &text_options));
+    }
+    if cpu.core_counters_denied() {
+        log::notice(tr(
+            "the per-core energy counters are not readable for this user, only package \
+                power is reported",
+        ));
+    }
+    let unreadable = cpu.unreadable_cores();
+    if !unreadable.is_empty() {
+        log::warning(trf(
+            "cannot read the MSR devices of cores {}, they are left out of the per-core power",
+            &[&topology::format_cpulist(&unreadable)],
+        ));
+    }
+
+    if args.command == Command::BisectHelper {
+        process::exit(bisect::bisect(
+            &cpu,
+            &args,
+            &state.calibration,
+            &text_options,
+        ));
+    }
+
+    if args.command == Command::Experiment {
+        run_experiment(&cpu, &args, &state.calibration, &text_options);
+        return;
+    }
+
+    if args.command == Command::Compare {
+        run_compare(&cpu, &args, &state.calibration, &text_options);
+        return;
+    }
+
+    if args.command == Command::Advise {
+        advise(&cpu, &args, &state.calibration, &text_options);
+        return;
+    }
+
+    if args.command == Command::Attribute {
+        attribute(&cpu, &args, &state.calibration, &text_options);
+        return;
+    }
+
+    if args.command == Command::Run {
+        let status = run_program(&cpu, &args, &state.calibration, &text_options);
+        process::exit(exit_code(status));
     }
 
-    pub fn power(&self, duration: Duration) -> (f64, BTreeMap<u32, f64>) {
-        let package_energy_before = self.package_energy();
-        let core_energy_before = self.core_energy();
+    let mut groups = match args.group {
+        Some(grouping) => topology::groups(cpu.root(), grouping, &cpu.info, &cpu.core_ids())
+            .unwrap_or_else(|err| exit_with_error(err)),
+        None => BTreeMap::new(),
+    };
+    groups.extend(named_groups(&args.groups, &cpu.core_ids()));
 
-        thread::sleep(duration);
+    if args.command == Command::Shell {
+        let mut shell = Shell::new(&cpu, &quirks, &state.calibration, &groups, &text_options);
+        shell.format = args.format;
+        shell.interval = args.interval;
+        shell.show = args.show;
+        shell.run();
+        return;
+    }
+
+    if let Some(path) = &args.firehose {
+        capture_firehose(&cpu, &args, &state.calibration, path);
+        return;
+    }
+
+    let mut csv_log = args.log.as_ref().map(|path| match CsvLog::open(path) {
+        Ok(log) => {
+            let health = Health::new("csv", path.display().to_string());
+            (log, Arc::new(health))
+        }
+        Err(err) => {
+            log::error(format_args!("cannot open {}: {}", path.display(), err));
+            process::exit(1);
+        }
+    });
+
+    // Graphs need a visual terminal, screen readers get the numbers only.
+    let graph_style = args
+        .graph
+        .filter(|_| args.format == Format::Text && !args.screen_reader)
+        .map(|style| style.for_terminal());
+    let mut graph = Graph::new(graph_width(), 4);
+
+    // Offline builds stop at `network_for`, the rest of these is unreachable.
+    // Builds without them have stand-ins that are never there.
+    #[cfg(feature = "exporter")]
+    #[cfg_attr(feature = "offline", allow(unused_variables))]
+    let exporter = args.exporter.as_ref().map(|addr| {
+        let network = network_for("--exporter");
+        let listener = network.listen(addr).unwrap_or_else(|err| {
+            log::error(format_args!("cannot listen on {}: {}", addr, err));
+            process::exit(1);
+        });
+        log::info(format_args!("serving metrics on http://{}/metrics", addr));
+
+        let exporter = Arc::new(Exporter::new());
+        if args.exporter_info {
+            let host = args.redact.hostname().map(|host| ("host", host));
+            let model = args.redact.model(&cpu.info.model_name);
+            exporter.set_info(
+                host.into_iter()
+                    .chain(model.map(|model| ("model", model.to_owned())))
+                    .chain([("backend", cpu.backend_name().to_owned())])
+                    .collect(),
+            );
+        }
+        let server = Arc::clone(&exporter);
+        thread::spawn(move || server.serve(&listener));
+        if let Some(source) = args.node_power.clone() {
+            let exporter = Arc::clone(&exporter);
+            thread::spawn(move || read_node_power(network, &source, &exporter));
+        }
+        exporter
+    });
+    #[cfg(not(feature = "exporter"))]
+    let exporter = None::<Arc<Exporter>>;
+
+    let socket = args.daemon.then(|| {
+        args.socket
+            .clone()
+            .unwrap_or_else(daemon::default_socket_path)
+    });
+    let daemon = socket.as_ref().map(|path| {
+        let listener = daemon::bind(path, &args.socket_access).unwrap_or_else(|err| {
+            log::error(format_args!("cannot listen on {}: {}", path.display(), err));
+            process::exit(1);
+        });
+        log::info(format_args!("serving readings on {}", path.display()));
 
-        let package_energy_after = self.package_energy();
-        let core_energy_after = self.core_energy();
+        let ledger = Ledger::load().unwrap_or_else(|refusal| {
+            log::warning(format_args!("not keeping the ledger: {}", refusal));
+            Ledger::unsaved()
+        });
+        let history = History::load().unwrap_or_else(|refusal| {
+            log::warning(format_args!("not keeping the history: {}", refusal));
+            History::unsaved()
+        });
+        let daemon = Arc::new(Daemon::resuming(ledger, history));
+        let server = Arc::clone(&daemon);
+        thread::spawn(move || server.serve(&listener));
+        daemon
+    });
 
-        let duration = duration.as_secs() as f64;
+    let mut ledger = daemon
+        .as_ref()
+        .map(|_| (NameMeter::new(), Zone::local(), Instant::now()));
 
-        let package_energy = (package_energy_after - package_energy_before) / duration;
+    // Brokers and collectors get samples on threads of their own, a slow
+    // one must not stretch the sampling windows.
+    #[cfg(feature = "mqtt")]
+    #[cfg_attr(feature = "offline", allow(unused_variables))]
+    let mqtt = args.mqtt.clone().map(|broker| {
+        log::info(format_args!("publishing to {}", broker));
+        let health = Arc::new(Health::new("mqtt", broker.to_string()));
+        let publisher = Publisher::new(
+            network_for("--mqtt"),
+            broker,
+            args.mqtt_topic.clone(),
+            args.mqtt_cores,
+            args.redact.hostname().as_deref(),
+            args.redact.model(&cpu.info.model_name),
+        );
+        Worker::spawn(publisher, health, report_sink)
+    });
 
-        let cores_energy = core_energy_before
+    #[cfg(not(feature = "mqtt"))]
+    let mqtt = None::<Worker>;
+
+    #[cfg(feature = "otlp")]
+    #[cfg_attr(feature = "offline", allow(unused_variables))]
+    let otlp = args.otlp.clone().map(|endpoint| {
+        log::info(format_args!("pushing metrics to {}", endpoint));
+        let health = Arc::new(Health::new("otlp", endpoint.to_string()));
+        let mut pusher = Pusher::new(
+            network_for("--otlp"),
+            endpoint,
+            args.redact.hostname().as_deref(),
+            args.redact.model(&cpu.info.model_name),
+        );
+        match args
+            .spool
+            .map(|retention| Spool::in_state_dir("otlp", retention))
+        {
+            Some(Some(spool)) => {
+                if !spool.is_empty() {
+                    log::info(format_args!(
+                        "{} samples spooled in {} to push",
+                        spool.len(),
+                        spool.path().display()
+                    ));
+                }
+                pusher = pusher.with_spool(spool);
+            }
+            Some(None) => log::warning("no state directory to spool OTLP samples in"),
+            None => {}
+        }
+        Worker::spawn(pusher, health, report_sink)
+    });
+    #[cfg(not(feature = "otlp"))]
+    let otlp = None::<Worker>;
+
+    #[cfg_attr(feature = "offline", allow(unused_variables))]
+    let discord = args.discord.clone().map(|webhook| {
+        log::info(format_args!(
+            "keeping a message up to date through {}",
+            webhook
+        ));
+        let health = Arc::new(Health::new("discord", webhook.to_string()));
+        let discord = Discord::new(
+            network_for("--discord"),
+            webhook,
+            args.redact.hostname().as_deref(),
+            args.redact.model(&cpu.info.model_name),
+        );
+        Worker::spawn(discord, health, report_sink)
+    });
+
+    let sinks = csv_log
+        .iter()
+        .map(|(_, health)| health)
+        .chain(mqtt.iter().chain(&otlp).chain(&discord).map(Worker::health))
+        .cloned()
+        .collect::<Vec<_>>();
+    if let Some(exporter) = &exporter {
+        for sink in &sinks {
+            exporter.watch(Arc::clone(sink));
+        }
+    }
+
+    // The dashboard needs a visual terminal like the graph does.
+    #[cfg(feature = "tui")]
+    let mut dashboard = (args.tui
+        && !args.screen_reader
+        && exporter.is_none()
+        && daemon.is_none()
+        && mqtt.is_none()
+        && otlp.is_none())
+    .then(|| {
+        print!("{}", tui::ENTER);
+        Dashboard::new(args.graph.unwrap_or(graph::Style::Braille).for_terminal())
+    });
+    #[cfg(not(feature = "tui"))]
+    let mut dashboard = None::<Dashboard>;
+
+    let mut summary = args.summarizes().then(|| match args.histogram {
+        true => Summary::with_distribution(),
+        false => Summary::new(),
+    });
+    let started = Instant::now();
+
+    // Long running modes stop on Ctrl-C after a last, shorter sample and
+    // wrap up, instead of dying halfway through printing one.
+    if args.watch
+        || exporter.is_some()
+        || daemon.is_some()
+        || mqtt.is_some()
+        || otlp.is_some()
+        || dashboard.is_some()
+        || summary.is_some()
+    {
+        signal::catch_interrupts();
+    }
+    let mut notifier = args.service.then(|| {
+        signal::catch_hangups();
+        Notifier::from_env().unwrap_or_else(|err| {
+            log::warning(format_args!("cannot notify systemd: {}", err));
+            Notifier::new(None, None).expect("no socket to open")
+        })
+    });
+    let mut ready = false;
+    let mut session = Summary::new();
+    let mut watchdog = Watchdog::default();
+    // Sources --max-skew left out, to say so once.
+    let mut skewed = BTreeSet::new();
+    let mut deviation_warned = false;
+    let mut migration_noise = MigrationNoise::default();
+    let mut power_watch = args
+        .exceed_watts
+        .map(|limit| PowerWatch::new(limit, args.exceed_for));
+    let mut notifications = args
+        .notify
+        .then(Notifications::connect)
+        .and_then(|connected| {
+            connected
+                .inspect_err(|err| {
+                    log::warning(format_args!("cannot show desktop notifications: {}", err))
+                })
+                .ok()
+        });
+    let mut logind = args
+        .show
+        .presence
+        .then(Logind::connect)
+        .and_then(|connected| {
+            connected
+                .inspect_err(|err| {
+                    log::warning(format_args!(
+                        "cannot ask logind for --show presence: {}",
+                        err
+                    ))
+                })
+                .ok()
+        });
+    if args.show.smu {
+        if let Err(err) = PmTable::open(cpu.root()) {
+            log::warning(format_args!("no PM table for --show smu: {}", err));
+        }
+    }
+    let mut smoother = args.smoothing.map(Smoother::new);
+    let hostname = args.redact.hostname();
+    let mut recorder = args.record.as_ref().map(|path| {
+        let header = session_header(&cpu, hostname.as_deref(), &state.calibration);
+        Recorder::create(path, &header).unwrap_or_else(|err| {
+            log::error(format_args!("cannot write {}: {}", path.display(), err));
+            process::exit(1);
+        })
+    });
+
+    run_hook(&args.hooks, Hook::PreRun, &[]);
+
+    let splitter = || {
+        let sched = args.sched.then(|| {
+            SchedMeter::start(&cpu.threads)
+                .inspect_err(|err| {
+                    log::warning(format_args!(
+                        "cannot trace sched_switch for --sched, splitting by CPU time: {}",
+                        err
+                    ))
+                })
+                .ok()
+        });
+        Splitter::new(sched.flatten())
+    };
+    let mut watchlist = (!args.track.is_empty()).then(|| Watchlist::new(&args.track, splitter()));
+    let mut trace = args.trace.as_ref().map(|_| {
+        let tracer = args.sched.then(|| {
+            Tracer::start(true)
+                .inspect_err(|err| {
+                    log::warning(format_args!(
+                        "cannot trace sched_switch for --trace, leaving it out: {}",
+                        err
+                    ))
+                })
+                .ok()
+        });
+        Trace::new(tracer.flatten())
+    });
+    let mut breakdown = args.breakdown.map(|kind| Breakdown::new(kind, splitter()));
+    let mut drain = (args.battery && !args.client).then(|| Drain::new(Root::system()));
+    let status_file = args.status_file.then(|| {
+        let path = args
+            .status_path
+            .clone()
+            .unwrap_or_else(status::default_path);
+        log::info(format_args!("keeping the status in {}", path.display()));
+        StatusFile::new(&path)
+    });
+    let mangohud = args.mangohud.as_deref().map(StatusFile::new);
+    let dbus = args.dbus.map(start_dbus);
+    // Warned about once, until they can be written again.
+    let mut status_failing = false;
+    #[cfg_attr(feature = "offline", allow(unused_variables))]
+    let summary_targets = args
+        .summary_command
+        .clone()
+        .map(digest::Target::Command)
+        .into_iter()
+        .chain(
+            args.summary_webhook
+                .clone()
+                .map(|webhook| digest::Target::Webhook(network_for("--summary-webhook"), webhook)),
+        )
+        .collect::<Vec<_>>();
+    let mut digests = (!summary_targets.is_empty())
+        .then(|| Digests::new(args.summary_every.unwrap_or(digest::Period::Day)));
+    if drain.as_ref().is_some_and(|drain| !drain.is_discharging()) {
+        log::notice(tr(
+            "no battery is discharging, --battery compares once one is",
+        ));
+    }
+    let capturing = (!args.capture_on.is_empty()).then(|| {
+        let Some(dir) = args.capture_dir.clone().or_else(capture::default_dir) else {
+            log::error("no --capture-dir and no state directory for the captures");
+            process::exit(1);
+        };
+        let options = capture::Options {
+            names: args.capture_on.clone(),
+            dir,
+            interval: args.capture_interval,
+            header: session_header(&cpu, hostname.as_deref(), &state.calibration),
+        };
+        let (simulate, template) = (args.simulate, args.msr_path_template.clone());
+        capture::Watching::spawn(options, move || match simulate {
+            Some(profile) => Ok(Cpu::simulated(profile)),
+            None => Cpu::with_msr_path_template(backend, template.clone()),
+        })
+    });
+    let mut adaptive = args.adaptive.map(Adaptive::new);
+    let mut interval = args.adaptive.unwrap_or(args.interval);
+    // Each window starts where the last one ended, so no energy goes
+    // uncounted in between.
+    let mut before = cpu.snapshot().unwrap_or_else(|err| exit_with_error(err));
+    record(&mut recorder, &before);
+    let mut suspends = Suspends::new();
+    // How often sampling itself wakes up a core, for --verbose.
+    let wakeups = args.verbose.then(signal::wakeups).flatten();
+    let mut windows = 0;
+
+    while !signal::interrupted() {
+        windows += 1;
+        if let Some(notifier) = notifier.as_ref().filter(|_| signal::take_hangup()) {
+            reload(&mut args, &mut cpu, notifier);
+        }
+
+        // Unless a hook has to run in between, then the window only starts
+        // once it is done.
+        if args.hooks.pre_sample.is_some() {
+            run_hook(&args.hooks, Hook::PreSample, &[]);
+            before = cpu.snapshot().unwrap_or_else(|err| exit_with_error(err));
+            record(&mut recorder, &before);
+        }
+
+        let had_core_counters = cpu.has_core_counters();
+        let busy_before = args
+            .rank_cores
+            .then(sanity::CpuTimes::read_per_cpu)
+            .flatten()
+            .map(|times| (times, Instant::now()));
+        let (mut sample, after) = measure(
+            &cpu,
+            &quirks,
+            &state.calibration,
+            &groups,
+            args.show,
+            &before,
+            interval,
+        )
+        .unwrap_or_else(|err| {
+            if dashboard.is_some() {
+                print!("{}", tui::LEAVE);
+            }
+            if args.is_check() {
+                log::error(err);
+                process::exit(check::UNKNOWN);
+            }
+            exit_with_error(err)
+        });
+        if let Some(suspended) = suspends.check() {
+            log::notice(trf(
+                "suspended for {}s, leaving out the window around it",
+                &[&suspended.as_secs()],
+            ));
+            session.suspend(suspended);
+            if let Some(drain) = &mut drain {
+                drain.interrupt();
+            }
+            if let Some(summary) = &mut summary {
+                summary.suspend(suspended);
+            }
+            record(&mut recorder, &after);
+            before = after;
+            continue;
+        }
+        if let Some((busy, read)) = &busy_before {
+            if let Some(busy_after) = sanity::CpuTimes::read_per_cpu() {
+                sample.core_busy = cpu.core_busy(busy, &busy_after);
+                sample.skew.insert(
+                    Source::Busy,
+                    window_skew((before.package.1, after.package.1), (*read, Instant::now())),
+                );
+            }
+        }
+        let was_stuck = watchdog.stuck_for().is_some();
+        let reopen = watchdog.check(
+            after.package.0 != before.package.0,
+            sample.utilization,
+            sample.window,
+        );
+        sample.stuck_for = watchdog.stuck_for();
+        if let Some(connection) = &mut logind {
+            match connection.presence() {
+                Ok(presence) => sample.presence = presence,
+                Err(err) => {
+                    log::warning(format_args!(
+                        "cannot ask logind for --show presence: {}",
+                        err
+                    ));
+                    logind = None;
+                }
+            }
+        }
+        if let Some(max_skew) = args.max_skew {
+            for source in sample.drop_skewed(max_skew) {
+                if skewed.insert(source) {
+                    log::notice(format_args!(
+                        "leaving out {} read more than {:?} apart from the energy counters",
+                        source.name(),
+                        max_skew
+                    ));
+                }
+            }
+        }
+        if let Some(diagnostic) = sanity::reporting_deviation(&sample)
+            .and_then(sanity::check_reporting_deviation)
+            .filter(|_| !deviation_warned)
+        {
+            log::warning(diagnostic);
+            deviation_warned = true;
+        }
+        if let Some(recommendation) = migration_noise.check(sample.load_migration, interval) {
+            log::notice(recommendation);
+        }
+        record(&mut recorder, &after);
+        before = after;
+        if reopen {
+            log::warning(trf(
+                "the package energy counter has not advanced in {} windows while the CPU \
+                    was busy, opening the {} backend again",
+                &[&watchdog.stuck_windows(), &cpu.backend_name()],
+            ));
+            match cpu.reopen() {
+                Ok(()) => {
+                    before = cpu.snapshot().unwrap_or_else(|err| exit_with_error(err));
+                    record(&mut recorder, &before);
+                }
+                Err(err) => log::warning(format_args!("cannot reopen: {}", err)),
+            }
+        } else if was_stuck && sample.stuck_for.is_none() {
+            log::notice(tr("the package energy counter is advancing again"));
+        }
+        session.add(&sample);
+
+        run_hook(
+            &args.hooks,
+            Hook::PostSample,
+            &[
+                (
+                    "RYZEN_WATTAGE_TIMESTAMP",
+                    timefmt::machine(sample.timestamp),
+                ),
+                (
+                    "RYZEN_WATTAGE_PACKAGE_WATTS",
+                    format!("{:.3}", sample.package_power),
+                ),
+            ],
+        );
+
+        if let Some(watch) = &mut power_watch {
+            if let Some(hook) = watch.update(sample.package_power, sample.window) {
+                match hook {
+                    Hook::OnExceed => log::notice(trf(
+                        "package power at {}W or more for {}s",
+                        &[&watch.limit, &watch.sustain.as_secs_f64()],
+                    )),
+                    _ => log::notice(trf("package power below {}W again", &[&watch.limit])),
+                }
+                if let Some(notifications) = &mut notifications {
+                    let shown = match hook {
+                        Hook::OnExceed => {
+                            notifications.exceeded(watch.limit, watch.sustain, sample.package_power)
+                        }
+                        _ => notifications.recovered(watch.limit, sample.package_power),
+                    };
+                    if let Err(err) = shown {
+                        log::warning(format_args!("cannot show a desktop notification: {}", err));
+                    }
+                }
+                run_hook(
+                    &args.hooks,
+                    hook,
+                    &[
+                        (
+                            "RYZEN_WATTAGE_TIMESTAMP",
+                            timefmt::machine(sample.timestamp),
+                        ),
+                        (
+                            "RYZEN_WATTAGE_PACKAGE_WATTS",
+                            format!("{:.3}", sample.package_power),
+                        ),
+                        ("RYZEN_WATTAGE_LIMIT_WATTS", format!("{:.3}", watch.limit)),
+                    ],
+                );
+            }
+        }
+
+        if had_core_counters && !cpu.has_core_counters() {
+            log::notice(tr(
+                "per-core energy counters are not advancing (possibly disabled by the BIOS), \
+                    only package power is reported",
+            ));
+        } else if !had_core_counters && cpu.has_core_counters() {
+            log::notice(tr("per-core energy counters are advancing again"));
+        }
+
+        // Only what people read is smoothed, logs, statistics and the line
+        // protocols get the measured values.
+        let mut shown = sample.clone();
+        if let Some(smoother) = &mut smoother {
+            smoother.apply(&mut shown);
+        }
+
+        match args.format {
+            // The exporter runs unattended, its output is the metrics page.
+            _ if exporter.is_some()
+                || daemon.is_some()
+                || mqtt.is_some()
+                || otlp.is_some()
+                || dashboard.is_some()
+                || summary.is_some() => {}
+            _ if args.is_check() => {}
+            Format::Text => {
+                print!("{}", output::text(&shown, &text_options));
+                if let Some(style) = graph_style {
+                    graph.push(shown.package_power);
+                    print!("{}", graph.render(style, "W"));
+                }
+            }
+            Format::Json => println!("{}", output::json_view(&shown, &args.view)),
+            Format::Influx => print!("{}", output::influx(&sample, hostname.as_deref())),
+            Format::Ndjson => print!("{}", output::ndjson(&sample, hostname.as_deref())),
+            Format::Statusbar => println!("{}", output::statusbar(shown.package_power)),
+            Format::Waybar => {
+                let class = args.thresholds.class(shown.package_power);
+                let tooltip = output::text(&shown, &text_options);
+                println!("{}", output::waybar(shown.package_power, &tooltip, class));
+            }
+        }
+
+        let written = status_file
             .iter()
-            .zip(&core_energy_after)
-            .map(|((&core, &before), (_, &after))| (core, (after - before) / duration))
-            .collect();
+            .map(|file| (file, file.write(&sample, interval)))
+            .chain(
+                mangohud
+                    .iter()
+                    .map(|file| (file, file.replace(&overlay::mangohud(&sample)))),
+            );
+        if let Some(service) = &dbus {
+            if let Err(err) = service.publish(Reading::new(&sample)) {
+                log::warning(format_args!("cannot publish on D-Bus: {}", err));
+            }
+        }
+        let mut failed = false;
+        for (file, result) in written {
+            if let Err(err) = result {
+                if !status_failing {
+                    log::warning(format_args!(
+                        "cannot write {}: {}",
+                        file.path().display(),
+                        err
+                    ));
+                }
+                failed = true;
+            }
+        }
+        status_failing = failed;
+        if let Some((log, health)) = &mut csv_log {
+            // A full disk loses rows, not the rest of the session.
+            let _ = health.deliver(log.append(&sample), &report_sink);
+        }
+        if let Some(adaptive) = &mut adaptive {
+            interval = adaptive.next(sample.package_power, args.interval);
+        }
+        if watchlist.is_some() || breakdown.is_some() {
+            let seconds = sample.window.as_secs_f64();
+            let core_joules = sample
+                .core_power
+                .iter()
+                .map(|(&core, watts)| (core, watts * seconds))
+                .collect();
+            if let Some(watchlist) = &mut watchlist {
+                watchlist.update(sample.package_power * seconds, &core_joules, sample.window);
+            }
+            if let Some(breakdown) = &mut breakdown {
+                breakdown.update(sample.package_power * seconds, &core_joules, sample.window);
+            }
+        }
+        if let Some(trace) = &mut trace {
+            trace.add(&sample);
+        }
+        if let Some(drain) = &mut drain {
+            drain.update(
+                sample.package_power * sample.window.as_secs_f64(),
+                sample.window,
+            );
+        }
+        let exceeded = power_watch
+            .as_ref()
+            .is_some_and(|watch| watch.is_exceeded());
+        if let Some(digest) = digests
+            .as_mut()
+            .and_then(|digests| digests.add(&sample, exceeded))
+        {
+            send_summary(&summary_targets, digest, hostname.clone());
+        }
+
+        if let Some(summary) = &mut summary {
+            if started.elapsed() < args.warmup {
+                continue;
+            }
+            summary.add(&sample);
+
+            let done = args
+                .samples
+                .is_some_and(|samples| summary.samples() >= samples)
+                || args
+                    .duration
+                    .is_some_and(|duration| summary.duration >= duration);
+            if done {
+                break;
+            }
+            continue;
+        }
+
+        if let Some(daemon) = &daemon {
+            daemon.record(&sample);
+        }
+        if let (Some(daemon), Some((meter, zone, saved))) = (&daemon, &mut ledger) {
+            let joules = sample.package_power * sample.window.as_secs_f64();
+            let day =
+                timefmt::format(sample.timestamp, zone, "%Y-%m-%d").expect("valid time format");
+            daemon.account(&day, &meter.update(joules));
+            if saved.elapsed() >= ledger::SAVE_INTERVAL {
+                *saved = Instant::now();
+                save_state(daemon);
+            }
+        }
+        for worker in mqtt.iter().chain(&otlp).chain(&discord) {
+            worker.send(&sample);
+        }
+        if let Some(notifier) = &mut notifier {
+            // A stuck counter is what the watchdog is there for.
+            let notified = if !ready {
+                ready = true;
+                notifier.ready()
+            } else if sample.stuck_for.is_none() {
+                notifier.ping()
+            } else {
+                Ok(())
+            };
+            let status = notifier.status(&format!("{:.1} W", sample.package_power));
+            if let Err(err) = notified.and(status) {
+                log::warning(format_args!("cannot notify systemd: {}", err));
+            }
+        }
+        if let Some(exporter) = &exporter {
+            if let Some(breakdown) = &breakdown {
+                exporter.record_breakdown(breakdown.kind, breakdown.watts.clone());
+            }
+            exporter.record(sample);
+            continue;
+        }
+        if daemon.is_some() || mqtt.is_some() || otlp.is_some() {
+            continue;
+        }
+
+        if let Some(dashboard) = &mut dashboard {
+            dashboard.update(shown);
+            print!("{}", dashboard.render());
+            let _ = io::stdout().flush();
+            continue;
+        }
+
+        if !args.watch {
+            break;
+        }
+
+        if args.format == Format::Text {
+            println!();
+        }
+    }
+
+    if let Some((before, after)) = wakeups.zip(signal::wakeups()) {
+        log::info(trf(
+            "sampling woke up its core {} times per window",
+            &[&format!(
+                "{:.1}",
+                after.saturating_sub(before) as f64 / f64::from(windows.max(1))
+            )],
+        ));
+    }
+    if dashboard.is_some() {
+        print!("{}", tui::LEAVE);
+    }
+    if let Some(notifier) = &notifier {
+        let _ = notifier.stopping();
+    }
+    if let Some(path) = &socket {
+        let _ = std::fs::remove_file(path);
+    }
+    for file in status_file.iter().chain(&mangohud) {
+        file.remove();
+    }
+    if let Some(daemon) = &daemon {
+        save_state(daemon);
+    }
+    if let Some(capturing) = capturing {
+        capturing.finish();
+    }
+    // Says goodbye to the broker and waits for the last deliveries.
+    drop(mqtt);
+    drop(otlp);
+    drop(discord);
+
+    if args.is_check() {
+        let (code, line) = args.thresholds.evaluate(session.package.power.mean());
+        println!("{}", line);
+        run_hook(&args.hooks, Hook::PostRun, &[]);
+        process::exit(code);
+    }
+
+    if let Some(summary) = &summary {
+        match args.format {
+            Format::Json => println!("{}", output::summary_json(summary)),
+            _ => print!("{}", output::summary(summary, &text_options)),
+        }
+    } else if signal::interrupted() {
+        eprintln!("{}", output::session_summary(&session, &text_options));
+    }
+    // Next to the statistics, away from the samples of a stream otherwise.
+    if let Some(watchlist) = &watchlist {
+        match args.format {
+            Format::Json if summary.is_some() => {
+                println!("{}", output::watchlist_json(watchlist))
+            }
+            _ if summary.is_some() => print!("{}", output::watchlist(watchlist, &text_options)),
+            _ => eprint!("{}", output::watchlist(watchlist, &text_options)),
+        }
+    }
+    if let Some(breakdown) = &breakdown {
+        match args.format {
+            Format::Json if summary.is_some() => {
+                println!("{}", output::breakdown_json(breakdown))
+            }
+            _ if summary.is_some() => print!("{}", output::breakdown(breakdown, &text_options)),
+            _ => eprint!("{}", output::breakdown(breakdown, &text_options)),
+        }
+    }
+    if let (Some(trace), Some(path)) = (&trace, &args.trace) {
+        match std::fs::write(path, trace.render()) {
+            Ok(()) => log::info(trf("wrote the trace to {}", &[&path.display()])),
+            Err(err) => log::error(format_args!("cannot write {}: {}", path.display(), err)),
+        }
+    }
+    if let Some(drain) = &drain {
+        match output::battery(drain, &text_options) {
+            _ if args.format == Format::Json && summary.is_some() => {
+                println!("{}", output::battery_json(drain))
+            }
+            Some(report) if summary.is_some() => print!("{}", report),
+            Some(report) => eprint!("{}", report),
+            None => log::notice(tr(
+                "the battery did not update while discharging, nothing to compare with",
+            )),
+        }
+    }
+    for sink in &sinks {
+        let status = sink.status();
+        if signal::interrupted() || status.failed > 0 || status.dropped > 0 {
+            eprintln!("{}", output::sink_summary(sink.name, &sink.target, &status));
+        }
+    }
+
+    run_hook(&args.hooks, Hook::PostRun, &[]);
+}
+
+/// Sends `digest` to each of `targets` on a thread, a webhook that doesn't
+/// answer must not hold up sampling.
+fn send_summary(targets: &[digest::Target], digest: digest::Digest, hostname: Option<String>) {
+    let targets = targets.to_vec();
+    thread::spawn(move || {
+        for target in &targets {
+            if let Err(err) = target.send(&digest, hostname.as_deref()) {
+                log::warning(format_args!("cannot send the summary: {}", err));
+            }
+        }
+    });
+}
+
+/// The service of `--dbus` on `bus`, exiting if the name can't be had.
+#[cfg(feature = "dbus")]
+fn start_dbus(bus: Bus) -> Service {
+    let service = Service::start(bus).unwrap_or_else(|err| {
+        log::error(format_args!("cannot serve on the {} bus: {}", bus, err));
+        process::exit(1);
+    });
+    log::info(format_args!("serving {} on the {} bus", bus::NAME, bus));
+    service
+}
+
+#[cfg(not(feature = "dbus"))]
+fn start_dbus(bus: Bus) -> Service {
+    match bus {}
+}
 
-        (package_energy, cores_energy)
+/// Network access for `option`, which the arguments only allow with it.
+fn network_for(option: &str) -> Network {
+    Network::access().unwrap_or_else(|| {
+        log::error(format_args!(
+            "{} needs the network, this run is offline",
+            option
+        ));
+        process::exit(1);
+    })
+}
+
+/// Hands the BMC's readings to the exporter every [`bmc::INTERVAL`], forever.
+#[cfg(feature = "exporter")]
+fn read_node_power(network: Network, source: &NodePower, exporter: &Exporter) {
+    let mut failing = false;
+    loop {
+        match source.read(network) {
+            Ok(watts) => {
+                exporter.record_node_power(watts);
+                if std::mem::take(&mut failing) {
+                    log::notice(format_args!("reading node power from {} again", source));
+                }
+            }
+            Err(err) if !failing => {
+                failing = true;
+                log::warning(format_args!(
+                    "cannot read node power from {}: {}",
+                    source, err
+                ));
+            }
+            Err(_) => {}
+        }
+        thread::sleep(bmc::INTERVAL);
     }
 }
 
-#[derive(Debug)]
-struct Msr {
-    path: PathBuf,
+/// Logs an outage of a sink when it starts and when it is over, the sink is
+/// tried again with every sample in between.
+fn report_sink(sink: &Health, change: Change) {
+    let target = &sink.target;
+    match (sink.name, change) {
+        ("mqtt", Change::Failing(err)) => {
+            log::warning(format_args!("cannot publish to {}: {}", target, err))
+        }
+        ("mqtt", Change::Recovered) => log::notice(format_args!("publishing to {} again", target)),
+        ("otlp", Change::Failing(err)) => {
+            log::warning(format_args!("cannot push to {}: {}", target, err))
+        }
+        ("otlp", Change::Recovered) => log::notice(format_args!("pushing to {} again", target)),
+        ("discord", Change::Failing(err)) => {
+            log::warning(format_args!("cannot update {}: {}", target, err))
+        }
+        ("discord", Change::Recovered) => log::notice(format_args!("updating {} again", target)),
+        (_, Change::Failing(err)) => log::warning(format_args!("cannot write {}: {}", target, err)),
+        (_, Change::Recovered) => log::notice(format_args!("writing {} again", target)),
+    }
 }
 
-impl Msr {
-    const POWER_UNIT_OFFSET: u64 = 0xC0010299;
-    const CORE_ENERGY_OFFSET: u64 = 0xC001029A;
-    const PACKAGE_ENERGY_OFFSET: u64 = 0xC001029B;
-    const ENERGY_UNIT_MASK: u64 = 0x1F00;
+/// Reads the command line and configuration file again on SIGHUP under
+/// `--service`. Only the interval and the extra columns change, everything
+/// else is set up once at the start.
+fn reload(args: &mut Args, cpu: &mut Cpu, notifier: &Notifier) {
+    let _ = notifier.reloading();
+    match Args::from_env() {
+        Ok(reloaded) => {
+            args.interval = reloaded.interval;
+            args.show = reloaded.show;
+            cpu.idle_residency = args.show.cstate;
+            log::notice(tr("reloaded the configuration"));
+        }
+        Err(err) => log::warning(trf(
+            "cannot reload the configuration, keeping the current one: {}",
+            &[&err],
+        )),
+    }
+    let _ = notifier.ready();
+}
 
-    pub fn new(core: u32) -> Self {
-        let path = PathBuf::from(format!("/dev/cpu/{}/msr", core));
-        Self { path }
+fn run_hook(hooks: &Hooks, hook: Hook, env: &[(&str, String)]) {
+    if let Err(err) = hooks.run(hook, env) {
+        log::warning(format_args!("{} hook failed: {}", hook.name(), err));
     }
+}
+
+fn export_report(args: &Args, cpu_info: &CpuInfo, quirks: &Quirks) {
+    let cpu = match args.simulate {
+        Some(profile) => Ok(Cpu::simulated(profile)),
+        None => Cpu::with_msr_path_template(args.backend, args.msr_path_template.clone()),
+    };
+    log::info(tr("collecting diagnostics..."));
+    let report = Report::collect(cpu_info, quirks, cpu.as_ref(), args.interval);
 
-    pub fn core_energy(&self) -> io::Result<f64> {
-        let core_energy = self.read_register(Self::CORE_ENERGY_OFFSET)?;
-        let core_energy = core_energy as f64 * self.energy_unit()?;
-        Ok(core_energy)
+    let path = args.report.clone().unwrap_or_else(bugreport::default_path);
+    if let Err(err) = report.write(&path) {
+        exit_with_error(err);
     }
+    log::info(trf(
+        "wrote {}, please check it and attach it to the issue",
+        &[&path.display()],
+    ));
+}
 
-    pub fn package_energy(&self) -> io::Result<f64> {
-        let energy = self.read_register(Self::PACKAGE_ENERGY_OFFSET)?;
-        let energy = energy as f64 * self.energy_unit()?;
-        Ok(energy)
+/// Takes the instance lock, or reads from the instance that has it if it
+/// serves what `args` ask for.
+fn lock_instance(args: &mut Args) -> Option<Lock> {
+    let path = instance::default_path()?;
+    let socket = args.daemon.then(|| {
+        args.socket
+            .clone()
+            .unwrap_or_else(daemon::default_socket_path)
+    });
+    let holder = match Lock::acquire(&path, socket.as_deref()) {
+        Ok(Ok(lock)) => return Some(lock),
+        Ok(Err(holder)) => holder,
+        Err(err) => {
+            log::warning(format_args!("cannot lock {}: {}", path.display(), err));
+            return None;
+        }
+    };
+
+    let pid = holder
+        .pid
+        .map_or_else(|| "?".to_owned(), |pid| pid.to_string());
+    match holder.socket {
+        Some(socket) if args.can_attach() => {
+            log::notice(format_args!(
+                "instance {} is already measuring, reading from its daemon on {}",
+                pid,
+                socket.display()
+            ));
+            args.client = true;
+            args.socket = Some(socket);
+            process::exit(run_client(args));
+        }
+        _ => {
+            log::error(format_args!(
+                "instance {} is already measuring, --allow-multiple measures anyway",
+                pid
+            ));
+            process::exit(1);
+        }
     }
+}
 
-    fn energy_unit(&self) -> io::Result<f64> {
-        let units = self.read_register(Self::POWER_UNIT_OFFSET)?;
-        let unit = (units & Self::ENERGY_UNIT_MASK) >> 8;
-        Ok((0.5_f64).powf(unit as f64))
+/// Opens the backend auto-detection settled on last time first, and
+/// remembers the one it picks now.
+fn open_cpu(
+    backend: BackendKind,
+    msr_path_template: Option<String>,
+    tune: bool,
+    state: &mut State,
+) -> Result<Cpu> {
+    let open = |backend| Cpu::with_msr_path_template(backend, msr_path_template.clone());
+    if backend != BackendKind::Auto {
+        return open(backend);
+    }
+    if tune {
+        state.backend = tune_backend(&open);
     }
 
-    fn read_register(&self, offset: u64) -> io::Result<u64> {
-        let mut msr_file = File::open(&self.path)?;
-        msr_file.seek(SeekFrom::Start(offset))?;
+    let cpu = match state.backend.map(open) {
+        Some(Ok(cpu)) => cpu,
+        _ => open(BackendKind::Auto)?,
+    };
+    state.backend = cpu.backend_kind();
 
-        let mut data = [0u8; 8];
-        msr_file.read_exact(&mut data)?;
+    Ok(cpu)
+}
 
-        let data = u64::from_ne_bytes(data);
-        Ok(data)
+/// Benchmarks every backend that opens and logs how they did, the best one
+/// if any does.
+fn tune_backend(open: &impl Fn(BackendKind) -> Result<Cpu>) -> Option<BackendKind> {
+    let micros = |duration: Duration| duration.as_secs_f64() * 1e6;
+    let mut benchmarks = Vec::new();
+    for backend in tune::CANDIDATES {
+        match open(backend).and_then(|cpu| tune::benchmark(&cpu)) {
+            Ok(benchmark) => {
+                log::info(format_args!(
+                    "{}: {:.1} µs per sweep, {}, {}",
+                    backend.name(),
+                    micros(benchmark.sweep),
+                    match benchmark.granularity {
+                        Some(granularity) => format!("updates every {:.0} µs", micros(granularity)),
+                        None => "barely updates".to_owned(),
+                    },
+                    match benchmark.core_counters {
+                        true => "with per-core counters",
+                        false => "package only",
+                    },
+                ));
+                benchmarks.push(benchmark);
+            }
+            Err(err) => log::info(format_args!("{}: {}", backend.name(), err)),
+        }
     }
+    let backend = tune::pick(&benchmarks)?.backend;
+    log::notice(format_args!(
+        "auto uses the {} backend from now on",
+        backend.name()
+    ));
+    Some(backend)
 }
 
-fn main() {
-    let cpu = Cpu::new().unwrap();
+/// Runs the program from `args` and prints its report on stderr, keeping
+/// stdout to the program itself.
+fn run_program(
+    cpu: &Cpu,
+    args: &Args,
+    calibration: &Calibration,
+    text_options: &TextOptions,
+) -> ExitStatus {
+    let mut command = process::Command::new(&args.program[0]);
+    command.args(&args.program[1..]);
 
-    let (package_power, cores_power) = cpu.power(Duration::from_secs(1));
+    run_hook(&args.hooks, Hook::PreRun, &[]);
+    let mut report =
+        run::run(cpu, &mut command, args.interval).unwrap_or_else(|err| exit_with_error(err));
+    run_hook(&args.hooks, Hook::PostRun, &[]);
+    report.energy *= calibration.package;
+    report.average_power *= calibration.package;
+    report.peak_power *= calibration.package;
 
-    println!("Package: {:.2}W", package_power);
+    match args.format {
+        Format::Json => eprintln!("{}", output::run_report_json(&args.program, &report)),
+        _ => eprint!(
+            "{}",
+            output::run_report(&args.program, &report, text_options)
+        ),
+    }
 
-    let mut core_sum = 0.0;
+    if args.gha {
+        report_to_gha(&args.program, &report);
+    }
+
+    report.status
+}
+
+fn run_experiment(cpu: &Cpu, args: &Args, calibration: &Calibration, text_options: &TextOptions) {
+    let path = args
+        .manifest
+        .as_deref()
+        .expect("experiment without manifest");
+    let manifest = Manifest::load(path).unwrap_or_else(|err| exit_with_error(err));
+
+    run_hook(&args.hooks, Hook::PreRun, &[]);
+    let cells = experiment::run(
+        cpu,
+        &manifest,
+        args.interval,
+        calibration,
+        |workload, setting, report| {
+            eprintln!(
+                "{}",
+                trf(
+                    "{} with {}: {} in {}",
+                    &[
+                        &workload.name,
+                        &setting.name,
+                        &format!("{:.2}J", report.energy),
+                        &format!("{:.2}s", report.wall_time.as_secs_f64()),
+                    ]
+                )
+            );
+        },
+    )
+    .unwrap_or_else(|err| exit_with_error(err));
+    run_hook(&args.hooks, Hook::PostRun, &[]);
+
+    match args.format {
+        Format::Json => println!("{}", output::experiment_json(&cells)),
+        _ => print!("{}", output::experiment(&cells, text_options)),
+    }
+}
+
+fn run_compare(cpu: &Cpu, args: &Args, calibration: &Calibration, text_options: &TextOptions) {
+    run_hook(&args.hooks, Hook::PreRun, &[]);
+    let outcomes = compare::run(
+        cpu,
+        &args.variants,
+        args.runs,
+        args.interval,
+        calibration,
+        |variant, report| {
+            eprintln!(
+                "{}",
+                trf(
+                    "{}: {} in {}",
+                    &[
+                        &variant.label,
+                        &format!("{:.2}J", report.energy),
+                        &format!("{:.2}s", report.wall_time.as_secs_f64()),
+                    ]
+                )
+            );
+        },
+    )
+    .unwrap_or_else(|err| exit_with_error(err));
+    run_hook(&args.hooks, Hook::PostRun, &[]);
+
+    match args.format {
+        Format::Json => println!("{}", output::comparison_json(&outcomes)),
+        _ => print!("{}", output::comparison(&outcomes, text_options)),
+    }
+}
+
+/// [`run_compare`] of recorded or exported files, every one a run of its
+/// own labeled by its file name.
+fn compare_files(args: &Args) {
+    let outcomes = args
+        .compare_files
+        .iter()
+        .map(|path| {
+            let session = Session::load(path).unwrap_or_else(|err| exit_with_error(err));
+            let samples = session.samples(Duration::ZERO, &BTreeMap::new());
+            let label = path.file_name().unwrap_or_default().to_string_lossy();
+            compare::Outcome::recorded(label, &samples)
+        })
+        .collect::<Vec<_>>();
+    let text_options = TextOptions {
+        screen_reader: args.screen_reader,
+        timestamps: args.show.time,
+        view: args.view.clone(),
+    };
+
+    match args.format {
+        Format::Json => println!("{}", output::comparison_json(&outcomes)),
+        _ => print!("{}", output::comparison(&outcomes, &text_options)),
+    }
+}
+
+/// Splits package energy off to a process tree every window until it exits
+/// or Ctrl-C.
+fn attribute(cpu: &Cpu, args: &Args, calibration: &Calibration, text_options: &TextOptions) {
+    let mut meter = TreeMeter::new(args.pid).unwrap_or_else(|err| exit_with_error(err));
+    signal::catch_interrupts();
+
+    let start = Instant::now();
+    let mut before = cpu.snapshot().unwrap_or_else(|err| exit_with_error(err));
+    while !signal::interrupted() {
+        signal::sleep(args.interval);
+        let after = cpu.snapshot().unwrap_or_else(|err| exit_with_error(err));
+        let window = after.package.1.duration_since(before.package.1);
+        let joules =
+            cpu.power_between(&before, &after).package * calibration.package * window.as_secs_f64();
+        before = after;
+
+        let Some(share) = meter.update(joules) else {
+            log::notice(trf("process {} exited", &[&args.pid]));
+            break;
+        };
+        match args.format {
+            Format::Json => println!("{}", output::tree_share_json(&meter, &share, window)),
+            _ => print!(
+                "{}",
+                output::tree_share(&meter, &share, window, text_options)
+            ),
+        }
+    }
 
-    for (core, core_power) in cores_power {
-        core_sum += core_power;
-        println!("Core {}: {:.2}W", core, core_power);
+    if args.format != Format::Json {
+        println!(
+            "\n{}",
+            output::tree_total(&meter, start.elapsed(), text_options)
+        );
     }
+}
+
+fn save_state(daemon: &Daemon) {
+    if let Err(err) = daemon.save_ledger() {
+        log::warning(format_args!("cannot save the ledger: {}", err));
+    }
+    if let Err(err) = daemon.save_history() {
+        log::warning(format_args!("cannot save the history: {}", err));
+    }
+}
+
+/// Asks the daemon, which has the latest minute too, else reads the
+/// ledger it saved.
+fn show_ledger(args: &Args) -> i32 {
+    let day = args.today.then(|| {
+        timefmt::format(SystemTime::now(), &Zone::local(), "%Y-%m-%d").expect("valid time format")
+    });
+    let client = match &args.socket {
+        Some(path) => Client::connect(path),
+        None => Client::connect_default(),
+    };
+    let entries = match client.and_then(|mut client| client.ledger(day.as_deref())) {
+        Ok(entries) => entries,
+        Err(err @ Error::Daemon { .. }) => {
+            log::error(err);
+            return 1;
+        }
+        Err(_) => match Ledger::load() {
+            Ok(ledger) => ledger.entries(day.as_deref()),
+            Err(refusal) => {
+                log::error(refusal);
+                return 1;
+            }
+        },
+    };
+
+    let text_options = TextOptions {
+        screen_reader: args.screen_reader,
+        timestamps: args.show.time,
+        view: args.view.clone(),
+    };
+    match args.format {
+        Format::Json => println!("{}", output::ledger_json(&entries, day.as_deref())),
+        _ => print!(
+            "{}",
+            output::ledger(&entries, day.as_deref(), &text_options)
+        ),
+    }
+    0
+}
 
-    println!(
-        "Cores Total: {:.2}W",
-        core_sum * ((cpu.core_count / cpu.physical_core_count) as f64)
+/// Reads the history the daemon saved, up to the last minute.
+fn history_query(args: &Args) -> i32 {
+    let query = args.query.as_ref().expect("history query without a query");
+    let history = match History::load() {
+        Ok(history) => history,
+        Err(refusal) => {
+            log::error(refusal);
+            return 1;
+        }
+    };
+    let zone = args.timezone.clone().unwrap_or_else(Zone::local);
+    let rows = query.run(&history, SystemTime::now(), &zone);
+
+    let text_options = TextOptions {
+        screen_reader: args.screen_reader,
+        timestamps: args.show.time,
+        view: args.view.clone(),
+    };
+    match args.format {
+        Format::Json => println!("{}", output::query_json(query, &rows)),
+        _ => print!("{}", output::query(query, &rows, &text_options)),
+    }
+    0
+}
+
+/// `limit set`: the package power limit goes to the long-term constraint of
+/// the RAPL zone, once the guardrails let it through.
+fn set_limit(args: &Args) -> i32 {
+    let limit = args.limit.expect("limit set without a limit");
+    let Limit::PackagePower(watts) = limit else {
+        unreachable!("only package power limits are set");
+    };
+    let root = Root::system();
+    let result = CpuInfo::read(&root)
+        .map_err(|err| Error::io(root.path("/proc/cpuinfo"), err))
+        .and_then(|info| {
+            let path = backend::powercap_power_limit_path(&root).ok_or(Error::PowercapMissing)?;
+            guardrail::check(&info, limit, args.force, guardrail::confirm_interactively)?;
+            polkit::write_sysfs(&[(path, format!("{}", (watts * 1e6).round() as u64))])
+        });
+    match result {
+        Ok(()) => {
+            log::notice(trf("set the {}", &[&limit]));
+            0
+        }
+        Err(err) => {
+            log::error(err);
+            1
+        }
+    }
+}
+
+fn advise(cpu: &Cpu, args: &Args, calibration: &Calibration, text_options: &TextOptions) {
+    let measurements = advise::measure(cpu, args.interval, calibration, |measurement| {
+        log::info(trf(
+            "core {}: {} doing {}",
+            &[
+                &measurement.core,
+                &format!("{:.2}W", measurement.watts),
+                &format!("{:.1}M/s", measurement.work_rate / 1e6),
+            ],
+        ));
+    })
+    .unwrap_or_else(|err| exit_with_error(err));
+
+    let Some(recommendation) =
+        advise::recommend(&measurements, &cpu.threads, args.advise_threads, args.goal)
+    else {
+        log::error(format_args!(
+            "cannot run {} threads on {} CPUs",
+            args.advise_threads, cpu.core_count
+        ));
+        process::exit(1);
+    };
+    match args.format {
+        Format::Json => println!("{}", output::advice_json(&measurements, &recommendation)),
+        _ => print!(
+            "{}",
+            output::advice(&measurements, &recommendation, text_options)
+        ),
+    }
+}
+
+/// Workflow commands go to stdout, that's where the runner looks for them.
+fn report_to_gha(command: &[String], report: &run::Report) {
+    println!("{}", output::gha_notice(command, report));
+    if let Err(err) = output::append_gha_summary(command, report) {
+        log::warning(format_args!("cannot write the job summary: {}", err));
+    }
+}
+
+/// Exit code to pass on from a child, shells use 128 + the signal number for
+/// programs killed by a signal.
+fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// Plot width fitting the terminal next to the axis labels, if the shell
+/// exports its width.
+fn graph_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<usize>().ok())
+        .map_or(60, |columns| columns.saturating_sub(10))
+}
+
+fn exit_with_error(err: Error) -> ! {
+    log::error(err);
+    process::exit(1);
+}
+
+/// Prints the daemon's latest sample, and with `--watch` every new one.
+fn run_client(args: &Args) -> i32 {
+    let text_options = TextOptions {
+        screen_reader: args.screen_reader,
+        timestamps: args.show.time,
+        view: args.view.clone(),
+    };
+    let client = match &args.socket {
+        Some(path) => Client::connect(path),
+        None => Client::connect_default(),
+    };
+    let mut client = match client {
+        Ok(client) => client,
+        Err(err) => {
+            log::error(format_args!("{}, is the daemon running?", err));
+            return 1;
+        }
+    };
+
+    // Passed on instead of printed.
+    let dbus = args.dbus.map(start_dbus);
+
+    // Watching, the daemon sends every sample as it takes it.
+    if args.watch {
+        signal::catch_interrupts();
+        let package_only = args.format == Format::Statusbar;
+        if let Err(err) = client.subscribe(args.view.cores.as_deref(), package_only) {
+            log::error(err);
+            return 1;
+        }
+    }
+    let mut last_timestamp = None;
+
+    while !signal::interrupted() {
+        let sample = match args.watch {
+            true => client.next_sample(),
+            false => client.latest(),
+        };
+        let sample = match sample {
+            Ok(sample) => sample,
+            Err(err) => {
+                log::error(err);
+                return 1;
+            }
+        };
+
+        // The daemon samples at its own pace, don't print one twice.
+        let timestamp = sample.timestamp().map(str::to_owned);
+        if let Some(service) = &dbus {
+            if let Err(err) = service.publish(Reading::remote(&sample)) {
+                log::error(format_args!("cannot publish on D-Bus: {}", err));
+                return 1;
+            }
+        } else if timestamp.is_none() || timestamp != last_timestamp {
+            if last_timestamp.is_some() && args.format == Format::Text {
+                println!();
+            }
+            match args.format {
+                Format::Text => print!("{}", output::remote_text(&sample, &text_options)),
+                Format::Json => println!("{}", sample.to_json()),
+                // Ruled out when parsing the arguments.
+                Format::Influx | Format::Ndjson => unreachable!(),
+                Format::Statusbar => {
+                    let package_power = sample.package_power().unwrap_or(f64::NAN);
+                    println!("{}", output::statusbar(package_power));
+                }
+                Format::Waybar => {
+                    let package_power = sample.package_power().unwrap_or(f64::NAN);
+                    let class = args.thresholds.class(package_power);
+                    let tooltip = output::remote_text(&sample, &text_options);
+                    println!("{}", output::waybar(package_power, &tooltip, class));
+                }
+            }
+            last_timestamp = timestamp;
+        }
+
+        if !args.watch {
+            break;
+        }
+    }
+
+    0
+}
+
+/// The `[groups]` of the config with the cores of `cores` in them, warning
+/// about the others.
+fn named_groups(groups: &BTreeMap<String, Vec<u32>>, cores: &[u32]) -> BTreeMap<String, Vec<u32>> {
+    groups
+        .iter()
+        .map(|(name, members)| {
+            let (present, missing): (Vec<u32>, Vec<u32>) =
+                members.iter().partition(|core| cores.contains(core));
+            if !missing.is_empty() {
+                log::warning(trf(
+                    "group {} has cores {} this CPU doesn't, leaving them out",
+                    &[name, &topology::format_cpulist(&missing)],
+                ));
+            }
+            (name.clone(), present)
+        })
+        .collect()
+}
+
+/// The header of `--record` and `--firehose` files, with whichever
+/// groupings replay might be asked for. A redacted hostname is empty.
+fn session_header(cpu: &Cpu, hostname: Option<&str>, calibration: &Calibration) -> Header {
+    let groups = [Grouping::Ccd, Grouping::Ccx, Grouping::Numa]
+        .into_iter()
+        .filter_map(|grouping| {
+            let groups = topology::groups(cpu.root(), grouping, &cpu.info, &cpu.core_ids()).ok()?;
+            Some((grouping.name().to_owned(), groups))
+        })
+        .collect();
+    Header::new(cpu, hostname.unwrap_or_default(), calibration, groups)
+}
+
+/// Runs `--firehose` until interrupted, or for `-n` readings or `-d`.
+fn capture_firehose(cpu: &Cpu, args: &Args, calibration: &Calibration, path: &Path) {
+    signal::catch_interrupts();
+    let header = session_header(cpu, args.redact.hostname().as_deref(), calibration);
+    let stop = |readings: u64, elapsed: Duration| {
+        args.samples
+            .is_some_and(|samples| readings >= samples as u64)
+            || args.duration.is_some_and(|duration| elapsed >= duration)
+    };
+
+    let capture = firehose::capture(cpu, path, &header, args.interval, stop)
+        .unwrap_or_else(|err| exit_with_error(err));
+    log::notice(trf(
+        "captured {} readings in {}, {} of them late",
+        &[
+            &capture.readings,
+            &format!("{:.2}s", capture.elapsed.as_secs_f64()),
+            &capture.late,
+        ],
+    ));
+    if capture.grown > 0 {
+        log::warning(trf(
+            "writing fell behind, {} buffers were added to keep up",
+            &[&capture.grown],
+        ));
+    }
+}
+
+fn record(recorder: &mut Option<Recorder>, snapshot: &Snapshot) {
+    let Some(recorder) = recorder else {
+        return;
+    };
+    if let Err(err) = recorder.record(snapshot) {
+        log::error(format_args!(
+            "cannot write {}: {}",
+            recorder.path().display(),
+            err
+        ));
+        process::exit(1);
+    }
+}
+
+/// Prints the samples of a recorded session, or statistics over them with
+/// `-n` or `-d`.
+fn html_report(args: &Args) {
+    let (session, samples) = session_samples(args);
+    let path = args.html.as_deref().expect("report without --html");
+    let source = args.session.as_deref().expect("report without session");
+    let report = html::Report {
+        source: &source.file_name().unwrap_or_default().to_string_lossy(),
+        header: &session.header,
+        limit: args.exceed_watts.map(|limit| (limit, args.exceed_for)),
+    };
+    if let Err(err) = std::fs::write(path, html::render(&report, &samples)) {
+        log::error(format_args!("cannot write {}: {}", path.display(), err));
+        process::exit(1);
+    }
+    log::info(trf(
+        "wrote a report of {} samples to {}",
+        &[&samples.len(), &path.display()],
+    ));
+}
+
+/// The session of `replay` or `report` with its samples over windows of
+/// `--interval`, grouped like asked for.
+fn session_samples(args: &Args) -> (Session, Vec<Sample>) {
+    let path = args.session.as_deref().expect("replay without session");
+    let session = Session::load(path).unwrap_or_else(|err| exit_with_error(err));
+    if let Some(grouping) = args
+        .group
+        .filter(|grouping| !session.header.groups.contains_key(grouping.name()))
+    {
+        log::error(trf(
+            "{} has no {} topology recorded",
+            &[&path.display(), &grouping.name()],
+        ));
+        process::exit(1);
+    }
+
+    let mut groups = args
+        .group
+        .and_then(|grouping| session.header.groups.get(grouping.name()))
+        .cloned()
+        .unwrap_or_default();
+    groups.extend(named_groups(&args.groups, &session.cores()));
+    let samples = session.samples(args.interval, &groups);
+    (session, samples)
+}
+
+fn perf_sidecar(args: &Args) {
+    let path = args
+        .perf_data
+        .as_deref()
+        .expect("perf sidecar without perf.data");
+    let session = args
+        .session
+        .as_deref()
+        .expect("perf sidecar without session");
+    let recording = Recording::read(path).unwrap_or_else(|err| exit_with_error(err));
+    let (_, samples) = session_samples(args);
+    let sidecar = recording.sidecar(&samples);
+    if sidecar.lines().count() == 1 {
+        log::warning(trf(
+            "{} has no samples over the time of {}",
+            &[&session.display(), &path.display()],
+        ));
+    }
+    print!("{}", sidecar);
+}
+
+fn perf_fold(args: &Args) {
+    let path = args
+        .perf_data
+        .as_deref()
+        .expect("perf fold without perf.data");
+    let recording = Recording::read(path).unwrap_or_else(|err| exit_with_error(err));
+    let script = Script::read(io::stdin().lock()).unwrap_or_else(|err| {
+        log::error(format_args!("cannot read perf script: {}", err));
+        process::exit(1);
+    });
+    let (session, samples) = session_samples(args);
+    let joules = recording.fold(&samples, &script, &session.cores());
+    print!("{}", perf::folded(&joules));
+}
+
+fn replay(args: &Args) {
+    let (session, samples) = session_samples(args);
+    let text_options = TextOptions {
+        screen_reader: args.screen_reader,
+        timestamps: args.show.time,
+        view: args.view.clone(),
+    };
+
+    if args.summarizes() {
+        let mut summary = match args.histogram {
+            true => Summary::with_distribution(),
+            false => Summary::new(),
+        };
+        // The warmup is over the windows of the session, not how long
+        // replaying them takes.
+        let mut warming = Duration::ZERO;
+        for sample in &samples {
+            if warming < args.warmup {
+                warming += sample.window;
+                continue;
+            }
+            summary.add(sample);
+            let done = args
+                .samples
+                .is_some_and(|samples| summary.samples() >= samples)
+                || args
+                    .duration
+                    .is_some_and(|duration| summary.duration >= duration);
+            if done {
+                break;
+            }
+        }
+        match args.format {
+            Format::Json => println!("{}", output::summary_json(&summary)),
+            _ => print!("{}", output::summary(&summary, &text_options)),
+        }
+        return;
+    }
+
+    // Sessions without one were recorded with it redacted.
+    let hostname = Some(session.header.hostname.as_str())
+        .filter(|hostname| !hostname.is_empty() && !args.redact.hostname);
+    for (index, sample) in samples.iter().enumerate() {
+        match args.format {
+            Format::Text => {
+                if index > 0 {
+                    println!();
+                }
+                print!("{}", output::text(sample, &text_options));
+            }
+            Format::Json => println!("{}", output::json_view(sample, &args.view)),
+            Format::Influx => print!("{}", output::influx(sample, hostname)),
+            Format::Ndjson => print!("{}", output::ndjson(sample, hostname)),
+            Format::Statusbar => println!("{}", output::statusbar(sample.package_power)),
+            Format::Waybar => {
+                let class = args.thresholds.class(sample.package_power);
+                let tooltip = output::text(sample, &text_options);
+                println!("{}", output::waybar(sample.package_power, &tooltip, class));
+            }
+        }
+    }
+}
+
+/// Independent errors of summed values add up in quadrature.
+fn quadrature_sum(errors: impl Iterator<Item = f64>) -> f64 {
+    errors.map(|error| error * error).sum::<f64>().sqrt()
+}
+
+fn measure(
+    cpu: &Cpu,
+    quirks: &Quirks,
+    calibration: &Calibration,
+    groups: &BTreeMap<String, Vec<u32>>,
+    show: Show,
+    before: &Snapshot,
+    interval: Duration,
+) -> Result<(Sample, Snapshot)> {
+    let cpu_times_before = (sanity::CpuTimes::read(), Instant::now());
+    let thread_times_before = show
+        .per_thread
+        .then(sanity::CpuTimes::read_per_cpu)
+        .flatten()
+        .map(|times| (times, Instant::now()));
+    // Where the load ran in either half of the window, for --show migration.
+    let migration_halves = match show
+        .migration
+        .then(sanity::CpuTimes::read_per_cpu)
+        .flatten()
+    {
+        Some(start) => {
+            signal::sleep(interval / 2);
+            let middle = sanity::CpuTimes::read_per_cpu();
+            signal::sleep(interval - interval / 2);
+            middle.map(|middle| (start, middle))
+        }
+        None => {
+            signal::sleep(interval);
+            None
+        }
+    };
+    let after = cpu.snapshot()?;
+    let power = cpu.power_between(before, &after);
+    let package_power = power.package * calibration.package;
+    let mut core_power = power.cores;
+    core_power
+        .values_mut()
+        .for_each(|power| *power *= calibration.cores);
+
+    // Every other source is timed against the energy counters' window.
+    let mut skew = BTreeMap::new();
+    let window = (before.package.1, after.package.1);
+    let cpu_times_after = sanity::CpuTimes::read();
+    skew.insert(
+        Source::Utilization,
+        window_skew(window, (cpu_times_before.1, Instant::now())),
     );
+    let thread_power = match thread_times_before {
+        Some((before, read)) => sanity::CpuTimes::read_per_cpu()
+            .map(|after| {
+                skew.insert(Source::Threads, window_skew(window, (read, Instant::now())));
+                cpu.thread_power(&core_power, &before, &after)
+            })
+            .unwrap_or_default(),
+        None => BTreeMap::new(),
+    };
+
+    let mut uncertainty = cpu.uncertainty_between(before, &after);
+    uncertainty.package *= calibration.package;
+    uncertainty
+        .cores
+        .values_mut()
+        .for_each(|error| *error *= calibration.cores);
+
+    let mut temperatures = match show.temp {
+        true => {
+            let temperatures = temperature::read(cpu.root());
+            skew.insert(Source::Temperature, apart(after.package.1, Instant::now()));
+            temperatures
+        }
+        false => BTreeMap::new(),
+    };
+    quirks.add_tdie(&mut temperatures);
+    // The die temperature, for telling an idle package from a busy one.
+    let tdie = match show.temp {
+        true => temperatures
+            .get("tdie")
+            .or(temperatures.get("tctl"))
+            .copied(),
+        false => temperature::tctl(cpu.root()).map(|tctl| quirks.correct_tctl(tctl)),
+    };
+    let pm_table = show.smu.then(|| PmTable::open(cpu.root()).ok()).flatten();
+    let smu = match &pm_table {
+        Some(pm_table) => {
+            let values = pm_table.read(cpu.root());
+            skew.insert(Source::Smu, apart(after.package.1, Instant::now()));
+            values
+        }
+        None => BTreeMap::new(),
+    };
+
+    let utilization = cpu_times_before
+        .0
+        .zip(cpu_times_after)
+        .and_then(|(before, after)| before.utilization_until(&after));
+    let load_migration = migration_halves.and_then(|(start, middle)| {
+        let end = sanity::CpuTimes::read_per_cpu()?;
+        sanity::load_migration(
+            &cpu.core_busy(&start, &middle),
+            &cpu.core_busy(&middle, &end),
+        )
+    });
+    if let Some(diagnostic) = sanity::check_package_power(package_power, utilization, tdie) {
+        log::warning(diagnostic);
+    }
+
+    let core_sum: f64 = core_power.values().sum();
+    let group_power = topology::group_power(groups, &core_power);
+    let group_uncertainty = groups
+        .iter()
+        .map(|(group, cores)| {
+            let errors = cores
+                .iter()
+                .filter_map(|core| uncertainty.cores.get(core).copied());
+            (group.clone(), quadrature_sum(errors))
+        })
+        .collect();
+    let sample = Sample {
+        timestamp: SystemTime::now(),
+        window: after.package.1.duration_since(before.package.1),
+        package_power,
+        package_limit: show.limits.then(|| cpu.package_power_limit()).flatten(),
+        stuck_for: None,
+        core_power,
+        domain_power: power.domains,
+        group_power,
+        cores_total_power: core_sum,
+        cores_total_uncertainty: quadrature_sum(uncertainty.cores.values().copied()),
+        thread_power,
+        core_busy: BTreeMap::new(),
+        group_uncertainty,
+        uncertainty,
+        highest_perf: cpu.highest_perf(),
+        core_frequency: match show.freq {
+            true => {
+                let frequencies = cpu.frequencies();
+                skew.insert(Source::Frequency, apart(after.package.1, Instant::now()));
+                frequencies
+            }
+            false => BTreeMap::new(),
+        },
+        core_idle: cpu.idle_between(before, &after),
+        gpu_power: match show.gpu {
+            true => {
+                let power = gpu::power(cpu.root());
+                skew.insert(Source::Gpu, apart(after.package.1, Instant::now()));
+                power
+            }
+            false => BTreeMap::new(),
+        },
+        temperatures,
+        smu,
+        pm_table: pm_table.map(|pm_table| pm_table.layout.codename),
+        backend: cpu.backend_name(),
+        utilization,
+        load_migration,
+        // Asked of logind by the caller, it keeps the connection.
+        presence: None,
+        core_counters: cpu.has_core_counters(),
+        core_counters_denied: cpu.core_counters_denied(),
+        smt_enabled: cpu.smt_enabled,
+        core_count: cpu.core_count,
+        physical_core_count: cpu.physical_core_count,
+        skew,
+    };
+
+    Ok((sample, after))
+}
+
+/// How far apart `a` and `b` are, whichever is first.
+fn apart(a: Instant, b: Instant) -> Duration {
+    a.max(b).duration_since(a.min(b))
+}
+
+/// How far a source read over `read` is off the energy counters' `window`,
+/// at whichever end is further off.
+fn window_skew(window: (Instant, Instant), read: (Instant, Instant)) -> Duration {
+    apart(window.0, read.0).max(apart(window.1, read.1))
 }
@@ -0,0 +1,440 @@
+//! Registry of every metric the tool reports.
+//!
+//! Output formats iterate over [`METRICS`] instead of picking fields out of
+//! a [`Sample`] themselves, so a metric added here shows up in all of them.
+
+use crate::{output::Sample, sanity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Watts,
+    Joules,
+    Seconds,
+    Megahertz,
+    Percent,
+    Celsius,
+    Amperes,
+    /// A plain number without unit, like a performance ranking.
+    Count,
+}
+
+impl Unit {
+    /// Suffix for human readable output.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Watts => "W",
+            Self::Joules => "J",
+            Self::Seconds => "s",
+            Self::Megahertz => "MHz",
+            Self::Percent => "%",
+            Self::Celsius => "°C",
+            Self::Amperes => "A",
+            Self::Count => "",
+        }
+    }
+
+    /// Unit written out, for screen readers.
+    pub fn spelled(&self) -> &'static str {
+        match self {
+            Self::Watts => "watts",
+            Self::Joules => "joules",
+            Self::Seconds => "seconds",
+            Self::Megahertz => "megahertz",
+            Self::Percent => "percent",
+            Self::Celsius => "degrees Celsius",
+            Self::Amperes => "amperes",
+            Self::Count => "",
+        }
+    }
+
+    /// Decimal places for human and machine readable output.
+    pub fn precision(&self) -> (usize, usize) {
+        match self {
+            Self::Watts | Self::Joules | Self::Seconds | Self::Amperes => (2, 3),
+            Self::Percent | Self::Celsius => (1, 1),
+            Self::Megahertz | Self::Count => (0, 0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Metric {
+    /// Machine readable name, also the JSON key.
+    pub name: &'static str,
+    /// Name in the Prometheus exporter, following its naming conventions.
+    pub prometheus: &'static str,
+    /// Human readable name.
+    pub title: &'static str,
+    pub help: &'static str,
+    pub unit: Unit,
+    /// Name of the label distinguishing the values of this metric, if the
+    /// metric has more than one value.
+    pub label: Option<&'static str>,
+    /// CSV column name, `{}` is replaced with the label value.
+    pub column: &'static str,
+    values: Values,
+    /// Estimated error of each value, for metrics that are measured rather
+    /// than read.
+    uncertainty: Option<Values>,
+}
+
+/// Values of a metric in a sample, with their label values.
+type Values = fn(&Sample) -> Vec<(String, f64)>;
+
+impl Metric {
+    /// Values of this metric in `sample`, with the label value of each
+    /// (empty for unlabeled metrics).
+    pub fn values(&self, sample: &Sample) -> Vec<(String, f64)> {
+        (self.values)(sample)
+    }
+
+    /// Estimated errors of the values in `sample`, like [`Metric::values`].
+    pub fn uncertainties(&self, sample: &Sample) -> Option<Vec<(String, f64)>> {
+        self.uncertainty.map(|uncertainty| uncertainty(sample))
+    }
+
+    /// Estimated error of the value with `label`.
+    pub fn uncertainty(&self, sample: &Sample, label: &str) -> Option<f64> {
+        self.uncertainties(sample)?
+            .into_iter()
+            .find(|(other, _)| other == label)
+            .map(|(_, error)| error)
+    }
+
+    /// Whether [`Metric::uncertainties`] has any.
+    pub fn has_uncertainty(&self) -> bool {
+        self.uncertainty.is_some()
+    }
+
+    pub fn column_name(&self, label: &str) -> String {
+        self.column.replace("{}", label)
+    }
+}
+
+pub const METRICS: &[Metric] = &[
+    Metric {
+        name: "package_watts",
+        prometheus: "ryzen_package_watts",
+        title: "Package",
+        help: "Average package power over the sampling window",
+        unit: Unit::Watts,
+        label: None,
+        column: "package_watts",
+        uncertainty: Some(|sample| vec![(String::new(), sample.uncertainty.package)]),
+        values: |sample| vec![(String::new(), sample.package_power)],
+    },
+    Metric {
+        name: "stuck_counter_seconds",
+        prometheus: "ryzen_stuck_counter_seconds",
+        title: "Counters stuck for",
+        help: "How long the package energy counter has not advanced while the CPU was busy",
+        unit: Unit::Seconds,
+        label: None,
+        column: "stuck_counter_seconds",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .stuck_for
+                .map(|stuck_for| (String::new(), stuck_for.as_secs_f64()))
+                .into_iter()
+                .collect()
+        },
+    },
+    Metric {
+        name: "package_limit_watts",
+        prometheus: "ryzen_package_limit_watts",
+        title: "Power limit",
+        help: "Sustained package power limit, PPT on AMD and PL1 on Intel",
+        unit: Unit::Watts,
+        label: None,
+        column: "package_limit_watts",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .package_limit
+                .map(|limit| (String::new(), limit))
+                .into_iter()
+                .collect()
+        },
+    },
+    Metric {
+        name: "package_limit_percent",
+        prometheus: "ryzen_package_limit_percent",
+        title: "Power limit used",
+        help: "Package power as a share of the sustained power limit",
+        unit: Unit::Percent,
+        label: None,
+        column: "package_limit_percent",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .package_limit
+                .map(|limit| (String::new(), sample.package_power / limit * 100.0))
+                .into_iter()
+                .collect()
+        },
+    },
+    Metric {
+        name: "load_migration_percent",
+        prometheus: "ryzen_load_migration_percent",
+        title: "Load migration",
+        help: "Share of the load that moved to other cores within the window",
+        unit: Unit::Percent,
+        label: None,
+        column: "load_migration_percent",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .load_migration
+                .map(|migration| (String::new(), migration))
+                .into_iter()
+                .collect()
+        },
+    },
+    Metric {
+        name: "reporting_deviation_percent",
+        prometheus: "ryzen_power_reporting_deviation_percent",
+        title: "Reporting deviation",
+        help: "Share of package power the SMU's PPT accounts for under full load",
+        unit: Unit::Percent,
+        label: None,
+        column: "reporting_deviation_percent",
+        uncertainty: None,
+        values: |sample| {
+            sanity::reporting_deviation(sample)
+                .map(|deviation| (String::new(), deviation))
+                .into_iter()
+                .collect()
+        },
+    },
+    Metric {
+        name: "cores_watts",
+        prometheus: "ryzen_core_watts",
+        title: "Core",
+        help: "Average power of each physical core over the sampling window",
+        unit: Unit::Watts,
+        label: Some("core"),
+        column: "core{}_watts",
+        uncertainty: Some(|sample| {
+            sample
+                .uncertainty
+                .cores
+                .iter()
+                .map(|(core, error)| (core.to_string(), *error))
+                .collect()
+        }),
+        values: |sample| {
+            sample
+                .core_power
+                .iter()
+                .map(|(core, power)| (core.to_string(), *power))
+                .collect()
+        },
+    },
+    Metric {
+        name: "threads_watts",
+        prometheus: "ryzen_thread_watts",
+        title: "Thread",
+        help: "Share of its core's power attributed to each thread by utilization",
+        unit: Unit::Watts,
+        label: Some("thread"),
+        column: "cpu{}_watts",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .thread_power
+                .iter()
+                .map(|(thread, power)| (thread.to_string(), *power))
+                .collect()
+        },
+    },
+    Metric {
+        name: "domains_watts",
+        prometheus: "ryzen_domain_watts",
+        title: "Domain",
+        help: "Average power of package sub-domains like DRAM over the sampling window",
+        unit: Unit::Watts,
+        label: Some("domain"),
+        column: "{}_watts",
+        uncertainty: Some(|sample| {
+            sample
+                .uncertainty
+                .domains
+                .iter()
+                .map(|(domain, error)| (domain.to_string(), *error))
+                .collect()
+        }),
+        values: |sample| {
+            sample
+                .domain_power
+                .iter()
+                .map(|(domain, power)| (domain.to_string(), *power))
+                .collect()
+        },
+    },
+    Metric {
+        name: "groups_watts",
+        prometheus: "ryzen_group_watts",
+        title: "Group",
+        help: "Summed power of the cores on each CCD or CCX",
+        unit: Unit::Watts,
+        label: Some("group"),
+        column: "{}_watts",
+        uncertainty: Some(|sample| {
+            sample
+                .group_uncertainty
+                .iter()
+                .map(|(group, error)| (group.clone(), *error))
+                .collect()
+        }),
+        values: |sample| {
+            sample
+                .group_power
+                .iter()
+                .map(|(group, power)| (group.clone(), *power))
+                .collect()
+        },
+    },
+    Metric {
+        name: "gpu_watts",
+        prometheus: "ryzen_gpu_watts",
+        title: "GPU",
+        help: "Average board power of each AMD GPU as reported by amdgpu",
+        unit: Unit::Watts,
+        label: Some("gpu"),
+        column: "{}_watts",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .gpu_power
+                .iter()
+                .map(|(gpu, power)| (gpu.clone(), *power))
+                .collect()
+        },
+    },
+    Metric {
+        name: "cores_total_watts",
+        prometheus: "ryzen_cores_total_watts",
+        title: "Cores Total",
+        help: "Sum of the power of all cores",
+        unit: Unit::Watts,
+        label: None,
+        column: "cores_total_watts",
+        uncertainty: Some(|sample| vec![(String::new(), sample.cores_total_uncertainty)]),
+        values: |sample| match sample.core_power.is_empty() {
+            true => Vec::new(),
+            false => vec![(String::new(), sample.cores_total_power)],
+        },
+    },
+    Metric {
+        name: "temperature_celsius",
+        prometheus: "ryzen_temperature_celsius",
+        title: "Temperature",
+        help: "Tctl, Tdie and per-CCD temperatures from k10temp",
+        unit: Unit::Celsius,
+        label: Some("sensor"),
+        column: "{}_celsius",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .temperatures
+                .iter()
+                .map(|(sensor, celsius)| (sensor.clone(), *celsius))
+                .collect()
+        },
+    },
+    Metric {
+        name: "smu_watts",
+        prometheus: "ryzen_smu_watts",
+        title: "SMU power",
+        help: "Power the SMU limits the package by and its limits, from the PM table",
+        unit: Unit::Watts,
+        label: Some("value"),
+        column: "smu_{}_watts",
+        uncertainty: None,
+        values: |sample| smu_values(sample, Unit::Watts),
+    },
+    Metric {
+        name: "smu_amperes",
+        prometheus: "ryzen_smu_amperes",
+        title: "SMU current",
+        help: "Current the SMU limits the package by and its limits, from the PM table",
+        unit: Unit::Amperes,
+        label: Some("value"),
+        column: "smu_{}_amperes",
+        uncertainty: None,
+        values: |sample| smu_values(sample, Unit::Amperes),
+    },
+    Metric {
+        name: "smu_celsius",
+        prometheus: "ryzen_smu_celsius",
+        title: "SMU temperature",
+        help: "Temperature the SMU limits the package by and its limit, from the PM table",
+        unit: Unit::Celsius,
+        label: Some("value"),
+        column: "smu_{}_celsius",
+        uncertainty: None,
+        values: |sample| smu_values(sample, Unit::Celsius),
+    },
+    Metric {
+        name: "highest_perf",
+        prometheus: "ryzen_highest_perf",
+        title: "highest perf",
+        help: "CPPC highest performance of each core, preferred cores rank higher",
+        unit: Unit::Count,
+        label: Some("core"),
+        column: "core{}_highest_perf",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .highest_perf
+                .iter()
+                .map(|(core, perf)| (core.to_string(), f64::from(*perf)))
+                .collect()
+        },
+    },
+    Metric {
+        name: "frequency_mhz",
+        prometheus: "ryzen_core_frequency_megahertz",
+        title: "frequency",
+        help: "Current frequency of each core as reported by cpufreq",
+        unit: Unit::Megahertz,
+        label: Some("core"),
+        column: "core{}_mhz",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .core_frequency
+                .iter()
+                .map(|(core, mhz)| (core.to_string(), *mhz))
+                .collect()
+        },
+    },
+    Metric {
+        name: "idle_percent",
+        prometheus: "ryzen_core_idle_percent",
+        title: "idle",
+        help: "Share of the sampling window each core spent in C-states",
+        unit: Unit::Percent,
+        label: Some("core"),
+        column: "core{}_idle_percent",
+        uncertainty: None,
+        values: |sample| {
+            sample
+                .core_idle
+                .iter()
+                .map(|(core, idle)| (core.to_string(), *idle))
+                .collect()
+        },
+    },
+];
+
+/// The PM table values of `sample` in `unit`.
+fn smu_values(sample: &Sample, unit: Unit) -> Vec<(String, f64)> {
+    sample
+        .smu
+        .iter()
+        .filter(|(_, (field_unit, _))| *field_unit == unit)
+        .map(|(name, (_, value))| (name.to_string(), *value))
+        .collect()
+}
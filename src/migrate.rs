@@ -0,0 +1,144 @@
+//! Versions of the files kept for later, and the migrations that bring
+//! files of older versions up to date, so months of history survive an
+//! upgrade of the tool.
+//!
+//! Every format says which version it writes. Older files are migrated one
+//! version at a time as they are read and written back in the current
+//! version the next time they are saved. Files of a newer version are
+//! refused rather than misread, or worse, overwritten with what this version
+//! understood of them.
+
+use std::fmt;
+
+/// A file format and how to get from each of its versions to the next.
+#[derive(Debug)]
+pub struct Format<T: 'static> {
+    pub name: &'static str,
+    /// The oldest version there are files of. 0 is for files from before
+    /// the format had a version.
+    pub first: u32,
+    /// The version written.
+    pub current: u32,
+    /// `migrations[n]` turns version `first + n` into the version after it.
+    pub migrations: &'static [fn(T) -> T],
+}
+
+/// Why a file can't be migrated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refusal {
+    /// Written by a later version of the tool.
+    Newer {
+        name: &'static str,
+        version: u32,
+        current: u32,
+    },
+    /// A version that never existed.
+    Unknown { name: &'static str, version: u32 },
+}
+
+impl<T> Format<T> {
+    /// Brings `data` of `version` up to [`Format::current`].
+    pub fn migrate(&self, version: u32, mut data: T) -> Result<T, Refusal> {
+        if version > self.current {
+            return Err(Refusal::Newer {
+                name: self.name,
+                version,
+                current: self.current,
+            });
+        }
+        if version < self.first {
+            return Err(Refusal::Unknown {
+                name: self.name,
+                version,
+            });
+        }
+        debug_assert_eq!(
+            self.migrations.len() as u32,
+            self.current - self.first,
+            "a migration for every version of the {} format",
+            self.name
+        );
+
+        for migration in &self.migrations[(version - self.first) as usize..] {
+            data = migration(data);
+        }
+        Ok(data)
+    }
+}
+
+/// The version in the first line of `contents` if it starts with `prefix`,
+/// like 2 in `# ryzen-wattage spool 2`.
+pub fn line_version(contents: &[u8], prefix: &str) -> Option<u32> {
+    let rest = contents.strip_prefix(prefix.as_bytes())?;
+    let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+    std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Newer {
+                name,
+                version,
+                current,
+            } => write!(
+                f,
+                "{} version {} is newer than the {} this version of ryzen-wattage knows, \
+                 upgrade to read it",
+                name, version, current
+            ),
+            Self::Unknown { name, version } => write!(f, "unknown {} version {}", name, version),
+        }
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: Format<String> = Format {
+        name: "test",
+        first: 0,
+        current: 2,
+        migrations: &[|data| data + " v1", |data| data.replace("v1", "v2")],
+    };
+
+    #[test]
+    fn migrates_one_version_at_a_time() {
+        assert_eq!(LINES.migrate(0, "old".to_owned()).unwrap(), "old v2");
+        assert_eq!(LINES.migrate(1, "old v1".to_owned()).unwrap(), "old v2");
+        assert_eq!(LINES.migrate(2, "new".to_owned()).unwrap(), "new");
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let refusal = LINES.migrate(3, String::new()).unwrap_err();
+        assert_eq!(
+            refusal,
+            Refusal::Newer {
+                name: "test",
+                version: 3,
+                current: 2
+            }
+        );
+        assert!(refusal.to_string().contains("upgrade"));
+
+        let format = Format { first: 1, ..LINES };
+        assert_eq!(
+            format.migrate(0, String::new()),
+            Err(Refusal::Unknown {
+                name: "test",
+                version: 0
+            })
+        );
+    }
+
+    #[test]
+    fn reads_versions_from_the_first_line() {
+        assert_eq!(line_version(b"# test 12: more\nrest", "# test "), Some(12));
+        assert_eq!(line_version(b"# test\n", "# test "), None);
+        assert_eq!(line_version(b"1 body\n", "# test "), None);
+    }
+}
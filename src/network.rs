@@ -0,0 +1,222 @@
+//! `--offline`: nothing the tool reads leaves the machine.
+//!
+//! Everything that talks to another machine takes a [`Network`]: the
+//! exporter's listener, MQTT, OTLP and Redfish open their sockets through
+//! it, and there is no other way to get one than [`Network::access`]. Once
+//! [`forbid`] ran, which `--offline` does before anything else, it hands out
+//! none. Built with the `offline` feature, `Network` has no values at all and
+//! the code of those sinks can't be reached, which anyone can check without
+//! trusting the command line of a process running as root. HTTPS goes
+//! through `curl`, which [`Network::curl`] only runs with one too.
+//!
+//! The daemon's Unix socket stays, it doesn't reach past the machine. Hooks,
+//! `run` and `--exec` run the user's own commands, what those do is theirs.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+static FORBIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Permission to use the network.
+#[cfg(not(feature = "offline"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network(());
+
+/// Permission to use the network, which an offline build never has.
+#[cfg(feature = "offline")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {}
+
+/// Takes away network access for the rest of the process.
+pub fn forbid() {
+    FORBIDDEN.store(true, Ordering::SeqCst);
+}
+
+/// Whether this build can use the network at all.
+pub const fn built_in() -> bool {
+    cfg!(not(feature = "offline"))
+}
+
+impl Network {
+    /// Network access, `None` after [`forbid`] and in an offline build.
+    pub fn access() -> Option<Self> {
+        if FORBIDDEN.load(Ordering::SeqCst) {
+            return None;
+        }
+        #[cfg(not(feature = "offline"))]
+        return Some(Self(()));
+        #[cfg(feature = "offline")]
+        return None;
+    }
+
+    /// Connects to the first address of `host` that answers within
+    /// `timeout`.
+    pub fn connect(self, host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+        let host = host.trim_matches(['[', ']']);
+        let mut last_error =
+            io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host));
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn listen(self, addr: &str) -> io::Result<TcpListener> {
+        TcpListener::bind(addr)
+    }
+
+    /// POSTs the JSON `body` to `path` on `host` over plain HTTP, waiting at
+    /// most `timeout` for each step. Fails unless the answer is a 2xx.
+    pub fn post_json(
+        self,
+        (host, port, path): (&str, u16, &str),
+        body: &str,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let mut stream = self.connect(host, port, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let request = format!(
+            concat!(
+                "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n",
+                "Content-Length: {}\r\nConnection: close\r\n\r\n{}"
+            ),
+            path,
+            host,
+            port,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes())?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        let status = status.trim_end();
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            Some(_) => Err(io::Error::other(format!("{} answered `{}`", host, status))),
+            None => Err(io::Error::other(format!(
+                "{} didn't answer over HTTP",
+                host
+            ))),
+        }
+    }
+
+    /// Has `curl` request `url`, for HTTPS, which this crate doesn't speak
+    /// itself, within `timeout`. The URL and the `options` of curl's config
+    /// file syntax, like `("user", "NAME:PASSWORD")`, go to it on stdin,
+    /// not on its command line where every user can read tokens and
+    /// credentials. Returns the body, failing unless the answer is a 2xx.
+    pub fn curl(
+        self,
+        url: &str,
+        options: &[(&str, &str)],
+        timeout: Duration,
+    ) -> io::Result<String> {
+        let mut curl = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--config", "-"])
+            .args(["--max-time", &format!("{:.3}", timeout.as_secs_f64())])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = curl.stdin.take() {
+            writeln!(stdin, "url = {}", curl_quote(url))?;
+            for (name, value) in options {
+                writeln!(stdin, "{} = {}", name, curl_quote(value))?;
+            }
+        }
+        let output = curl.wait_with_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "curl failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+        String::from_utf8(output.stdout).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "curl got a body that isn't UTF-8",
+            )
+        })
+    }
+}
+
+/// A string in curl's config file syntax.
+fn curl_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "offline"))]
+mod tests {
+    use super::*;
+    use std::{io::Read, net::TcpListener, thread};
+
+    #[test]
+    fn hands_curl_its_request_on_stdin() {
+        if Command::new("curl").arg("--version").output().is_err() {
+            return;
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\npong")
+                .unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+
+        let url = format!("http://127.0.0.1:{}/hook", port);
+        let body = "{\"text\":\"a \\\"quoted\\\"\\nline\"}";
+        let options = [
+            ("request", "PATCH"),
+            ("header", "Content-Type: application/json"),
+            ("data-binary", body),
+        ];
+        let answer = Network::access()
+            .unwrap()
+            .curl(&url, &options, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(answer, "pong");
+        let (head, sent) = server.join().unwrap();
+        assert!(head.starts_with("PATCH /hook HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json\r\n"));
+        assert_eq!(sent, body);
+
+        let err = Network::access()
+            .unwrap()
+            .curl(&url, &[], Duration::from_secs(5))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("curl failed: "));
+    }
+}
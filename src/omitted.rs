@@ -0,0 +1,175 @@
+//! Stand-ins for what a build leaves out with its cargo features, so the
+//! rest of the binary reads the same either way.
+//!
+//! The arguments refuse every option that needs one of them, the address
+//! types by failing to parse, so none of these types ever has a value: like
+//! [`Network`](ryzen_wattage::network::Network) in an offline build, they are
+//! enums without variants.
+
+/// Why `option` is refused in a build without `feature`.
+pub fn message(option: &str, feature: &str) -> String {
+    format!(
+        "this build leaves out {}, build it with `--features {}`",
+        option, feature
+    )
+}
+
+/// An address for an option a build left out, which never parses.
+#[cfg(not(all(feature = "mqtt", feature = "otlp", feature = "dbus")))]
+macro_rules! address {
+    ($name:ident, $option:literal, $feature:literal) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {}
+
+        impl std::str::FromStr for $name {
+            type Err = String;
+
+            fn from_str(_: &str) -> Result<Self, String> {
+                Err(super::message($option, $feature))
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, _: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match *self {}
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "tui"))]
+pub mod tui {
+    use ryzen_wattage::output::Sample;
+
+    pub const LEAVE: &str = "";
+
+    pub enum Dashboard {}
+
+    impl Dashboard {
+        pub fn update(&mut self, _: Sample) {
+            match *self {}
+        }
+
+        pub fn render(&self) -> String {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(not(feature = "exporter"))]
+pub mod exporter {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use ryzen_wattage::{
+        breakdown::{Kind, Owner},
+        output::Sample,
+        sink::Health,
+    };
+
+    pub enum Exporter {}
+
+    impl Exporter {
+        pub fn record(&self, _: Sample) {
+            match *self {}
+        }
+
+        pub fn record_breakdown(&self, _: Kind, _: BTreeMap<Owner, f64>) {
+            match *self {}
+        }
+
+        pub fn watch(&self, _: Arc<Health>) {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(not(feature = "mqtt"))]
+pub mod mqtt {
+    address!(Broker, "--mqtt", "mqtt");
+}
+
+#[cfg(not(feature = "otlp"))]
+pub mod otlp {
+    address!(Endpoint, "--otlp", "otlp");
+}
+
+#[cfg(not(feature = "dbus"))]
+pub mod bus {
+    use std::io;
+
+    use ryzen_wattage::{client::RemoteSample, output::Sample};
+
+    address!(Bus, "--dbus", "dbus");
+
+    pub struct Reading;
+
+    impl Reading {
+        pub fn new(_: &Sample) -> Self {
+            Self
+        }
+
+        pub fn remote(_: &RemoteSample) -> Self {
+            Self
+        }
+    }
+
+    pub enum Service {}
+
+    impl Service {
+        pub fn publish(&self, _: Reading) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(not(feature = "dbus"))]
+pub mod desktop {
+    use std::{io, time::Duration};
+
+    pub enum Notifications {}
+
+    impl Notifications {
+        pub fn connect() -> io::Result<Self> {
+            Err(io::Error::other(super::message("--notify", "dbus")))
+        }
+
+        pub fn exceeded(&mut self, _: f64, _: Duration, _: f64) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub fn recovered(&mut self, _: f64, _: f64) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(not(feature = "dbus"))]
+pub mod logind {
+    use std::io;
+
+    use ryzen_wattage::output::Presence;
+
+    pub enum Logind {}
+
+    impl Logind {
+        pub fn connect() -> io::Result<Self> {
+            Err(io::Error::other(super::message("--show presence", "dbus")))
+        }
+
+        pub fn presence(&mut self) -> io::Result<Option<Presence>> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(not(feature = "mqtt"))]
+    fn addresses_never_parse() {
+        assert_eq!(
+            "localhost".parse::<super::mqtt::Broker>(),
+            Err("this build leaves out --mqtt, build it with `--features mqtt`".to_owned())
+        );
+    }
+}
@@ -0,0 +1,508 @@
+//! `--otlp`: package and core power pushed to an OpenTelemetry collector
+//! after every sample, so the readings end up next to the rest of an
+//! application's telemetry without a Prometheus in between.
+//!
+//! Only OTLP over HTTP with JSON bodies, which every collector accepts on
+//! `/v1/metrics`. Power is a gauge, energy a monotonic cumulative sum that
+//! starts with the first sample, both with the host on the resource and the
+//! core on every point of a core.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    network::Network,
+    output::{json_number, json_string, Sample},
+    sink::Sink,
+    spool::Spool,
+};
+
+const DEFAULT_PORT: u16 = 4318;
+const METRICS_PATH: &str = "/v1/metrics";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE`.
+const CUMULATIVE: u8 = 2;
+
+/// A collector URL, `http://HOST[:PORT][/PATH]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    /// `/v1/metrics` unless the URL has a path of its own.
+    pub path: String,
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid OTLP endpoint `{}`, expected http://HOST[:PORT][/PATH]",
+                url
+            )
+        };
+        if url.starts_with("https://") {
+            return Err(format!(
+                "TLS collectors aren't supported, use http:// in `{}`",
+                url
+            ));
+        }
+        let rest = url.strip_prefix("http://").unwrap_or(url);
+        let (address, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        let path = match path.trim_end_matches('/') {
+            "" => METRICS_PATH,
+            path => path,
+        };
+
+        // IPv6 addresses keep their brackets, like in URLs.
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (address, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// Pushes samples and keeps the energy totals between them.
+#[derive(Debug)]
+pub struct Pusher {
+    network: Network,
+    endpoint: Endpoint,
+    /// Left out of the resource if `None`.
+    hostname: Option<String>,
+    model: Option<String>,
+    /// When the totals started, the first sample's window.
+    start: Option<SystemTime>,
+    package_joules: f64,
+    core_joules: BTreeMap<u32, f64>,
+    spool: Option<Spool>,
+}
+
+impl Pusher {
+    pub fn new(
+        network: Network,
+        endpoint: Endpoint,
+        hostname: Option<&str>,
+        model: Option<&str>,
+    ) -> Self {
+        Self {
+            network,
+            endpoint,
+            hostname: hostname.map(str::to_owned),
+            model: model.map(str::to_owned),
+            start: None,
+            package_joules: 0.0,
+            core_joules: BTreeMap::new(),
+            spool: None,
+        }
+    }
+
+    /// Keeps what couldn't be pushed in `spool` and pushes it later.
+    pub fn with_spool(self, spool: Spool) -> Self {
+        Self {
+            spool: Some(spool),
+            ..self
+        }
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Adds `sample` to the totals and pushes it, after what the spool kept.
+    /// A sample that couldn't be pushed still counts in the next one's
+    /// energy, and goes into the spool if there is one.
+    pub fn push(&mut self, sample: &Sample) -> io::Result<()> {
+        let seconds = sample.window.as_secs_f64();
+        self.start
+            .get_or_insert_with(|| sample.timestamp - sample.window);
+        self.package_joules += sample.package_power * seconds;
+        if sample.core_counters {
+            for (core, watts) in &sample.core_power {
+                *self.core_joules.entry(*core).or_default() += watts * seconds;
+            }
+        }
+
+        let body = self.request(sample);
+        let Some(spool) = &self.spool else {
+            return self.post(&body);
+        };
+        let result = spool
+            .replay(|body| self.post(body))
+            .and_then(|_| self.post(&body));
+        if let Err(err) = &result {
+            if let Err(spool_err) = spool.push(sample.timestamp, &body) {
+                return Err(io::Error::other(format!(
+                    "{}, and cannot spool it in {}: {}",
+                    err,
+                    spool.path().display(),
+                    spool_err
+                )));
+            }
+        }
+        result
+    }
+
+    /// The `ExportMetricsServiceRequest` of `sample`.
+    pub fn request(&self, sample: &Sample) -> String {
+        let time = nanos(sample.timestamp);
+        let start = nanos(self.start.unwrap_or(sample.timestamp));
+        let core_power = match sample.core_counters {
+            true => &sample.core_power,
+            false => &BTreeMap::new(),
+        };
+
+        let mut metrics = vec![
+            gauge(
+                "ryzen_wattage.package.power",
+                "Package power",
+                "W",
+                &[point(&time, None, sample.package_power)],
+            ),
+            sum(
+                "ryzen_wattage.package.energy",
+                "Package energy since the first sample",
+                "J",
+                &[point(&time, Some(&start), self.package_joules)],
+            ),
+        ];
+        if !core_power.is_empty() {
+            let points = core_power
+                .iter()
+                .map(|(core, watts)| core_point(*core, &time, None, *watts))
+                .collect::<Vec<_>>();
+            metrics.push(gauge(
+                "ryzen_wattage.core.power",
+                "Core power",
+                "W",
+                &points,
+            ));
+            let points = self
+                .core_joules
+                .iter()
+                .filter(|(core, _)| core_power.contains_key(core))
+                .map(|(core, joules)| core_point(*core, &time, Some(&start), *joules))
+                .collect::<Vec<_>>();
+            metrics.push(sum(
+                "ryzen_wattage.core.energy",
+                "Core energy since the first sample",
+                "J",
+                &points,
+            ));
+        }
+
+        let mut attributes = vec![attribute("service.name", "ryzen-wattage")];
+        if let Some(hostname) = &self.hostname {
+            attributes.push(attribute("host.name", hostname));
+        }
+        if let Some(model) = &self.model {
+            attributes.push(attribute("host.cpu.model.name", model));
+        }
+        format!(
+            concat!(
+                "{{\"resourceMetrics\":[{{\"resource\":{{\"attributes\":[{}]}},",
+                "\"scopeMetrics\":[{{\"scope\":{{\"name\":\"ryzen-wattage\",\"version\":{}}},",
+                "\"metrics\":[{}]}}]}}]}}"
+            ),
+            attributes.join(","),
+            json_string(env!("CARGO_PKG_VERSION")),
+            metrics.join(","),
+        )
+    }
+
+    fn post(&self, body: &str) -> io::Result<()> {
+        // Not waiting minutes for an unreachable collector, it holds up
+        // sampling.
+        let endpoint = &self.endpoint;
+        self.network.post_json(
+            (&endpoint.host, endpoint.port, &endpoint.path),
+            body,
+            TIMEOUT,
+        )
+    }
+}
+
+impl Sink for Pusher {
+    fn deliver(&mut self, sample: &Sample) -> io::Result<()> {
+        self.push(sample)
+    }
+}
+
+/// Nanoseconds since the epoch, which OTLP JSON has as strings.
+fn nanos(time: SystemTime) -> String {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    json_string(&nanos.to_string())
+}
+
+fn attribute(key: &str, value: &str) -> String {
+    format!(
+        "{{\"key\":{},\"value\":{{\"stringValue\":{}}}}}",
+        json_string(key),
+        json_string(value)
+    )
+}
+
+/// A data point, with `start` for sums.
+fn point(time: &str, start: Option<&str>, value: f64) -> String {
+    let start = start.map_or_else(String::new, |start| {
+        format!("\"startTimeUnixNano\":{},", start)
+    });
+    format!(
+        "{{{}\"timeUnixNano\":{},\"asDouble\":{}}}",
+        start,
+        time,
+        json_number(value)
+    )
+}
+
+fn core_point(core: u32, time: &str, start: Option<&str>, value: f64) -> String {
+    let point = point(time, start, value);
+    format!(
+        "{{\"attributes\":[{{\"key\":\"core\",\"value\":{{\"intValue\":\"{}\"}}}}],{}",
+        core,
+        &point[1..]
+    )
+}
+
+fn gauge(name: &str, description: &str, unit: &str, points: &[String]) -> String {
+    format!(
+        "{{\"name\":{},\"description\":{},\"unit\":{},\"gauge\":{{\"dataPoints\":[{}]}}}}",
+        json_string(name),
+        json_string(description),
+        json_string(unit),
+        points.join(",")
+    )
+}
+
+fn sum(name: &str, description: &str, unit: &str, points: &[String]) -> String {
+    format!(
+        concat!(
+            "{{\"name\":{},\"description\":{},\"unit\":{},\"sum\":{{\"dataPoints\":[{}],",
+            "\"aggregationTemporality\":{},\"isMonotonic\":true}}}}"
+        ),
+        json_string(name),
+        json_string(description),
+        json_string(unit),
+        points.join(","),
+        CUMULATIVE
+    )
+}
+
+#[cfg(all(test, not(feature = "offline")))]
+mod tests {
+    use super::*;
+    use crate::json::{self, Value};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    fn sample() -> Sample {
+        Sample {
+            timestamp: UNIX_EPOCH + Duration::from_secs(10),
+            window: Duration::from_secs(2),
+            ..Sample::fixture()
+        }
+    }
+
+    /// One request the way a collector reads it, headers and body.
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        request + &String::from_utf8(body).unwrap()
+    }
+
+    fn metric<'a>(request: &'a Value, name: &str) -> &'a Value {
+        let metrics = request
+            .get("resourceMetrics")
+            .and_then(Value::as_array)
+            .unwrap()[0]
+            .get("scopeMetrics")
+            .and_then(Value::as_array)
+            .unwrap()[0]
+            .get("metrics")
+            .and_then(Value::as_array)
+            .unwrap();
+        metrics
+            .iter()
+            .find(|metric| metric.get("name").and_then(Value::as_str) == Some(name))
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        let endpoint = "http://collector.lan".parse::<Endpoint>().unwrap();
+        assert_eq!(endpoint.to_string(), "http://collector.lan:4318/v1/metrics");
+        assert_eq!(
+            "[::1]:4000/otlp/v1/metrics".parse(),
+            Ok(Endpoint {
+                host: "[::1]".to_owned(),
+                port: 4000,
+                path: "/otlp/v1/metrics".to_owned(),
+            })
+        );
+
+        assert!("https://collector.lan".parse::<Endpoint>().is_err());
+        assert!("http://collector.lan:port".parse::<Endpoint>().is_err());
+        assert!("http://".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn pushes_gauges_and_cumulative_sums() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let collector = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&mut stream));
+                stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            }
+            requests
+        });
+
+        let endpoint = format!("http://127.0.0.1:{}", port).parse().unwrap();
+        let mut pusher = Pusher::new(
+            Network::access().unwrap(),
+            endpoint,
+            Some("desktop"),
+            Some("AMD Ryzen 7 5800X"),
+        );
+        pusher.push(&sample()).unwrap();
+        pusher.push(&sample()).unwrap();
+
+        let requests = collector.join().unwrap();
+        assert!(requests[1].starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        let (_, body) = requests[1].split_once("\r\n\r\n").unwrap();
+        let request = json::parse(body).unwrap();
+
+        let power = metric(&request, "ryzen_wattage.package.power");
+        let points = power.get("gauge").unwrap().get("dataPoints").unwrap();
+        let point = &points.as_array().unwrap()[0];
+        assert_eq!(point.get("asDouble").and_then(Value::as_f64), Some(42.5));
+        assert_eq!(
+            point.get("timeUnixNano").and_then(Value::as_str),
+            Some("10000000000")
+        );
+
+        let energy = metric(&request, "ryzen_wattage.core.energy")
+            .get("sum")
+            .unwrap();
+        assert_eq!(
+            energy.get("isMonotonic").and_then(Value::as_bool),
+            Some(true)
+        );
+        let point = &energy.get("dataPoints").and_then(Value::as_array).unwrap()[1];
+        // Two windows of 2 s at 2.5 W, since the first one started.
+        assert_eq!(point.get("asDouble").and_then(Value::as_f64), Some(10.0));
+        assert_eq!(
+            point.get("startTimeUnixNano").and_then(Value::as_str),
+            Some("8000000000")
+        );
+        let core = &point.get("attributes").and_then(Value::as_array).unwrap()[0];
+        assert_eq!(
+            core.get("value")
+                .unwrap()
+                .get("intValue")
+                .and_then(Value::as_str),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn leaves_redacted_resource_attributes_out() {
+        let keys = |hostname, model| {
+            let endpoint = "http://127.0.0.1".parse().unwrap();
+            let pusher = Pusher::new(Network::access().unwrap(), endpoint, hostname, model);
+            let request = json::parse(&pusher.request(&sample())).unwrap();
+            request
+                .get("resourceMetrics")
+                .and_then(Value::as_array)
+                .unwrap()[0]
+                .get("resource")
+                .and_then(|resource| resource.get("attributes"))
+                .and_then(Value::as_array)
+                .unwrap()
+                .iter()
+                .map(|attribute| {
+                    attribute
+                        .get("key")
+                        .and_then(Value::as_str)
+                        .unwrap()
+                        .to_owned()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(Some("desktop"), Some("AMD Ryzen 7 5800X")),
+            ["service.name", "host.name", "host.cpu.model.name"]
+        );
+        assert_eq!(keys(None, None), ["service.name"]);
+    }
+
+    #[test]
+    fn fails_on_errors_of_the_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let collector = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&mut stream);
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .unwrap();
+        });
+
+        let endpoint = format!("http://127.0.0.1:{}", port).parse().unwrap();
+        let mut pusher = Pusher::new(
+            Network::access().unwrap(),
+            endpoint,
+            Some("desktop"),
+            Some("AMD Ryzen 7 5800X"),
+        );
+        let err = pusher.push(&sample()).unwrap_err();
+        assert!(err.to_string().contains("400 Bad Request"));
+        collector.join().unwrap();
+    }
+}
@@ -0,0 +1,3264 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, Read as _, Write as _},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    advise::{Measurement, Recommendation},
+    battery::{self, Drain},
+    breakdown::{self, Breakdown, Owner},
+    client::RemoteSample,
+    compare::{Delta, Outcome},
+    cpu::Uncertainty,
+    cpuinfo::CpuInfo,
+    crosscheck::Reading,
+    experiment::{self, Cell},
+    history::{Query, Row},
+    i18n::{tr, trf},
+    info::Info,
+    latency::{self, Counter, Latencies},
+    metrics::{Metric, Unit, METRICS},
+    process::{self, Share, TreeMeter, Watchlist},
+    quirks::{Quirks, QUIRKS},
+    run::Report,
+    schema,
+    sink::Status,
+    stats::{Difference, Distribution, PowerSummary, Stats, Summary},
+    timefmt, topology, Cpu,
+};
+
+/// One measurement window, ready to be printed in any output format.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub timestamp: SystemTime,
+    /// Length of the window the sample averages over.
+    pub window: Duration,
+    pub package_power: f64,
+    /// Sustained package power limit in W, `None` unless requested and
+    /// known.
+    pub package_limit: Option<f64>,
+    /// How long the package counter has not advanced on a busy CPU, once
+    /// the [`Watchdog`](crate::sanity::Watchdog) calls it stuck.
+    pub stuck_for: Option<Duration>,
+    pub core_power: BTreeMap<u32, f64>,
+    pub domain_power: BTreeMap<&'static str, f64>,
+    /// Summed core power per chiplet, empty unless grouping was requested.
+    pub group_power: BTreeMap<String, f64>,
+    pub cores_total_power: f64,
+    /// Estimated errors of the measured power values.
+    pub uncertainty: Uncertainty,
+    pub group_uncertainty: BTreeMap<String, f64>,
+    pub cores_total_uncertainty: f64,
+    /// Core power split across sibling threads, empty unless requested.
+    pub thread_power: BTreeMap<u32, f64>,
+    /// Seconds the threads of each core were busy in the window, summed,
+    /// empty unless requested.
+    pub core_busy: BTreeMap<u32, f64>,
+    pub highest_perf: BTreeMap<u32, u32>,
+    /// MHz per core, empty unless requested.
+    pub core_frequency: BTreeMap<u32, f64>,
+    /// Percent of the window each core was idle, empty unless requested.
+    pub core_idle: BTreeMap<u32, f64>,
+    /// Watts per AMD GPU, empty unless requested.
+    pub gpu_power: BTreeMap<String, f64>,
+    /// °C per `k10temp` sensor, empty unless requested.
+    pub temperatures: BTreeMap<String, f64>,
+    /// Values of the SMU's PM table by name, empty unless requested.
+    pub smu: BTreeMap<&'static str, (Unit, f64)>,
+    /// Codename of the PM table layout [`Sample::smu`] was decoded with.
+    pub pm_table: Option<&'static str>,
+    pub backend: &'static str,
+    /// Share of the window the CPU was busy, if `/proc/stat` could be read.
+    pub utilization: Option<f64>,
+    /// Percent of the load that moved to other cores between the two halves
+    /// of the window, see [`sanity::load_migration`](crate::sanity::load_migration).
+    /// `None` unless requested.
+    pub load_migration: Option<f64>,
+    /// False when only package power is available.
+    pub core_counters: bool,
+    /// Set when that is for lack of permissions.
+    pub core_counters_denied: bool,
+    pub smt_enabled: bool,
+    pub core_count: u32,
+    pub physical_core_count: u32,
+    /// How far apart from the energy counters each other source was read,
+    /// at the start or the end of the window, whichever is more.
+    pub skew: BTreeMap<Source, Duration>,
+    /// Whether the desktop session was in use at the end of the window,
+    /// `None` unless requested and known.
+    pub presence: Option<Presence>,
+}
+
+/// What the desktop session was up to, as logind has it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Presence {
+    Active,
+    Idle,
+    Locked,
+}
+
+impl Presence {
+    /// Locked over idle, a locked screen is usually idle too.
+    pub fn of(locked: bool, idle: bool) -> Self {
+        match (locked, idle) {
+            (true, _) => Self::Locked,
+            (false, true) => Self::Idle,
+            (false, false) => Self::Active,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Idle => "idle",
+            Self::Locked => "locked",
+        }
+    }
+}
+
+/// What a sample reads besides the energy counters. They are read one after
+/// the other and not at the same instant, so each has its own [`Sample::skew`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    /// `/proc/stat` for [`Sample::utilization`].
+    Utilization,
+    /// `/proc/stat` per CPU for [`Sample::thread_power`].
+    Threads,
+    /// `/proc/stat` per CPU for [`Sample::core_busy`].
+    Busy,
+    Temperature,
+    Frequency,
+    Gpu,
+    /// The PM table for [`Sample::smu`].
+    Smu,
+}
+
+impl Source {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Utilization => "utilization",
+            Self::Threads => "threads",
+            Self::Busy => "busy",
+            Self::Temperature => "temperature",
+            Self::Frequency => "frequency",
+            Self::Gpu => "gpu",
+            Self::Smu => "smu",
+        }
+    }
+}
+
+impl Sample {
+    /// Leaves out what was read more than `max` apart from the energy
+    /// counters instead of pretending it was read at the same time. Returns
+    /// the sources left out.
+    pub fn drop_skewed(&mut self, max: Duration) -> Vec<Source> {
+        let skewed = self
+            .skew
+            .iter()
+            .filter(|(_, skew)| **skew > max)
+            .map(|(source, _)| *source)
+            .collect::<Vec<_>>();
+        for source in &skewed {
+            match source {
+                Source::Utilization => self.utilization = None,
+                Source::Threads => self.thread_power.clear(),
+                Source::Busy => self.core_busy.clear(),
+                Source::Temperature => self.temperatures.clear(),
+                Source::Frequency => self.core_frequency.clear(),
+                Source::Gpu => self.gpu_power.clear(),
+                Source::Smu => self.smu.clear(),
+            }
+        }
+        skewed
+    }
+}
+
+/// A sample of nothing at the epoch, to fill in with struct update syntax.
+impl Default for Sample {
+    fn default() -> Self {
+        Self {
+            timestamp: UNIX_EPOCH,
+            window: Duration::ZERO,
+            package_power: 0.0,
+            package_limit: None,
+            stuck_for: None,
+            core_power: BTreeMap::new(),
+            domain_power: BTreeMap::new(),
+            group_power: BTreeMap::new(),
+            cores_total_power: 0.0,
+            uncertainty: Uncertainty::default(),
+            group_uncertainty: BTreeMap::new(),
+            cores_total_uncertainty: 0.0,
+            thread_power: BTreeMap::new(),
+            core_busy: BTreeMap::new(),
+            highest_perf: BTreeMap::new(),
+            core_frequency: BTreeMap::new(),
+            core_idle: BTreeMap::new(),
+            gpu_power: BTreeMap::new(),
+            temperatures: BTreeMap::new(),
+            smu: BTreeMap::new(),
+            pm_table: None,
+            backend: "unknown",
+            utilization: None,
+            load_migration: None,
+            presence: None,
+            core_counters: false,
+            core_counters_denied: false,
+            smt_enabled: false,
+            core_count: 0,
+            physical_core_count: 0,
+            skew: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Sample {
+    /// Two cores of a 4 thread CPU at 42.5 W, for the tests of the sinks.
+    pub(crate) fn fixture() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            package_power: 42.5,
+            core_power: BTreeMap::from([(0, 10.0), (1, 2.5)]),
+            cores_total_power: 12.5,
+            backend: "msr",
+            core_counters: true,
+            smt_enabled: true,
+            core_count: 4,
+            physical_core_count: 2,
+            ..Self::default()
+        }
+    }
+}
+
+/// Layout of human readable output.
+#[derive(Debug, Clone, Default)]
+pub struct TextOptions {
+    /// Spell out units and leave out punctuation a screen reader would read
+    /// aloud. Anything interactive or animated is turned off as well.
+    pub screen_reader: bool,
+    /// Start with the time the sample was taken.
+    pub timestamps: bool,
+    pub view: View,
+}
+
+/// Order of the per-core values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoreSort {
+    #[default]
+    Core,
+    /// Highest power first.
+    Power,
+}
+
+impl FromStr for CoreSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "core" => Ok(Self::Core),
+            "power" => Ok(Self::Power),
+            other => Err(format!("unknown sort `{}`, expected power or core", other)),
+        }
+    }
+}
+
+/// Which metrics and cores text and JSON output show, for machines with
+/// too many cores to read them all every sample. Logs and the exporter
+/// always get everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct View {
+    pub sort: CoreSort,
+    /// Only this many cores, after sorting.
+    pub top: Option<usize>,
+    /// Only these physical cores.
+    pub cores: Option<Vec<u32>>,
+    /// Only the metrics of these names, e.g. `cores_watts`.
+    pub columns: Option<Vec<&'static str>>,
+}
+
+impl View {
+    /// Parses comma separated metric names.
+    pub fn parse_columns(s: &str) -> Result<Vec<&'static str>, String> {
+        s.split(',')
+            .map(|column| {
+                METRICS
+                    .iter()
+                    .find(|metric| metric.name == column.trim())
+                    .map(|metric| metric.name)
+                    .ok_or_else(|| {
+                        let names = METRICS.iter().map(|metric| metric.name).collect::<Vec<_>>();
+                        format!(
+                            "unknown column `{}`, expected {}",
+                            column.trim(),
+                            names.join(", ")
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    pub fn shows(&self, metric: &Metric) -> bool {
+        self.columns
+            .as_ref()
+            .is_none_or(|columns| columns.contains(&metric.name))
+    }
+
+    /// Labels of the cores to show in order, picked from the per-core
+    /// `power`, or from `values` without per-core power.
+    fn cores(&self, power: &[(String, f64)], values: &[(String, f64)]) -> Vec<String> {
+        let (mut cores, by_power) = match power.is_empty() {
+            true => (values.to_vec(), false),
+            false => (power.to_vec(), true),
+        };
+        if let Some(selected) = &self.cores {
+            cores.retain(|(core, _)| {
+                core.parse::<u32>()
+                    .is_ok_and(|core| selected.contains(&core))
+            });
+        }
+        if self.sort == CoreSort::Power && by_power {
+            // Stable, so equal power keeps the lower core first.
+            cores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        }
+        if let Some(top) = self.top {
+            cores.truncate(top);
+        }
+        cores.into_iter().map(|(core, _)| core).collect()
+    }
+
+    /// `values` of `metric` as far as this view shows them, `power` being
+    /// the values of `cores_watts` that per-core metrics are sorted by.
+    pub fn select(
+        &self,
+        metric: &Metric,
+        values: Vec<(String, f64)>,
+        power: &[(String, f64)],
+    ) -> Vec<(String, f64)> {
+        if !self.shows(metric) {
+            return Vec::new();
+        }
+        if metric.label != Some("core") || *self == Self::default() {
+            return values;
+        }
+
+        self.cores(power, &values)
+            .into_iter()
+            .filter_map(|core| values.iter().find(|(label, _)| *label == core).cloned())
+            .collect()
+    }
+}
+
+/// Human readable output. Labeled metrics are grouped into one line per
+/// label value, led by the first metric with that label.
+pub fn text(sample: &Sample, options: &TextOptions) -> String {
+    let mut out = text_with(
+        Some(timefmt::human(sample.timestamp)),
+        |metric| metric.values(sample),
+        |metric, label| metric.uncertainty(sample, label),
+        options,
+    );
+    if let Some(presence) = sample.presence {
+        writeln!(out, "{}: {}", tr("Session"), tr(presence.name())).unwrap();
+    }
+    out
+}
+
+/// [`text`] of a sample the daemon handed out. Its time is shown the way
+/// the daemon wrote it.
+pub fn remote_text(sample: &RemoteSample, options: &TextOptions) -> String {
+    text_with(
+        sample.timestamp().map(str::to_owned),
+        |metric| sample.values(metric),
+        |metric, label| sample.uncertainty(metric, label),
+        options,
+    )
+}
+
+fn text_with(
+    timestamp: Option<String>,
+    values: impl Fn(&Metric) -> Vec<(String, f64)>,
+    uncertainty: impl Fn(&Metric, &str) -> Option<f64>,
+    options: &TextOptions,
+) -> String {
+    let power = METRICS
+        .iter()
+        .find(|metric| metric.name == "cores_watts")
+        .map(&values)
+        .unwrap_or_default();
+    let values = |metric: &Metric| options.view.select(metric, values(metric), &power);
+    let text_value = |metric: &Metric, label: &str, value: f64| match uncertainty(metric, label) {
+        Some(uncertainty) => text_measurement(value, uncertainty, metric.unit, options),
+        None => text_quantity(value, metric.unit, options),
+    };
+    let mut out = String::new();
+    let mut printed_labels = Vec::new();
+
+    if let Some(timestamp) = timestamp.filter(|_| options.timestamps) {
+        writeln!(out, "{}: {}", tr("Time"), timestamp).unwrap();
+    }
+
+    for metric in METRICS {
+        let Some(label) = metric.label else {
+            for (label_value, value) in values(metric) {
+                writeln!(
+                    out,
+                    "{}: {}",
+                    tr(metric.title),
+                    text_value(metric, &label_value, value)
+                )
+                .unwrap();
+            }
+            continue;
+        };
+
+        if printed_labels.contains(&label) {
+            continue;
+        }
+        printed_labels.push(label);
+
+        let related = METRICS
+            .iter()
+            .filter(|other| other.label == Some(label) && !std::ptr::eq(*other, metric))
+            .map(|other| (other, values(other)))
+            .collect::<Vec<_>>();
+
+        for (label_value, value) in values(metric) {
+            write!(
+                out,
+                "{} {}: {}",
+                tr(metric.title),
+                label_value,
+                text_value(metric, &label_value, value)
+            )
+            .unwrap();
+
+            for (other, values) in &related {
+                if let Some((_, value)) = values
+                    .iter()
+                    .find(|(other_label, _)| *other_label == label_value)
+                {
+                    let value = text_value(other, &label_value, *value);
+                    match options.screen_reader {
+                        true => write!(out, ", {} {}", tr(other.title), value),
+                        false => write!(out, " ({} {})", tr(other.title), value),
+                    }
+                    .unwrap();
+                }
+            }
+
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+/// `value ± uncertainty`, with as many decimals as the first significant
+/// digit of the uncertainty needs, but no more than the unit usually gets.
+/// The uncertainty is rounded up, never pretending more precision.
+fn text_measurement(value: f64, uncertainty: f64, unit: Unit, options: &TextOptions) -> String {
+    if !(uncertainty.is_finite() && uncertainty > 0.0) {
+        return text_quantity(value, unit, options);
+    }
+
+    let (precision, _) = unit.precision();
+    let decimals = (-uncertainty.log10().floor()).clamp(0.0, precision as f64) as usize;
+    let scale = 10_f64.powi(decimals as i32);
+    let uncertainty = (uncertainty * scale).ceil() / scale;
+
+    match (options.screen_reader, unit.spelled()) {
+        (true, spelled) => format!(
+            "{:.*} {} {:.*} {}",
+            decimals,
+            value,
+            tr("plus or minus"),
+            decimals,
+            uncertainty,
+            tr(spelled)
+        )
+        .trim_end()
+        .to_owned(),
+        (false, _) => format!(
+            "{:.*} ± {:.*}{}",
+            decimals,
+            value,
+            decimals,
+            uncertainty,
+            unit.symbol()
+        ),
+    }
+}
+
+fn text_quantity(value: f64, unit: Unit, options: &TextOptions) -> String {
+    let (precision, _) = unit.precision();
+    match (options.screen_reader, unit.spelled()) {
+        (true, "") => format!("{:.*}", precision, value),
+        (true, spelled) => format!("{:.*} {}", precision, value, tr(spelled)),
+        (false, _) => format!("{:.*}{}", precision, value, unit.symbol()),
+    }
+}
+
+/// Package power alone, compact enough for a status bar.
+pub fn statusbar(package_power: f64) -> String {
+    format!("{:.1}W", package_power)
+}
+
+/// JSON for a Waybar custom module: the [`statusbar`] text, the full
+/// [`text`] output as tooltip, and `class` to style it by.
+pub fn waybar(package_power: f64, tooltip: &str, class: &str) -> String {
+    format!(
+        "{{\"text\":{},\"tooltip\":{},\"class\":{}}}",
+        json_string(&statusbar(package_power)),
+        json_string(tooltip.trim_end()),
+        json_string(class)
+    )
+}
+
+fn machine_value(metric: &Metric, value: f64) -> String {
+    let (_, precision) = metric.unit.precision();
+    format!("{:.*}", precision, value)
+}
+
+pub fn json(sample: &Sample) -> String {
+    json_view(sample, &View::default())
+}
+
+/// [`json`] with only what `view` shows. Uncertainties are left out along
+/// with their metric.
+pub fn json_view(sample: &Sample, view: &View) -> String {
+    let mut out = format!(
+        "{{\"schema_version\":{},\"timestamp\":{}",
+        schema::VERSION,
+        json_string(&timefmt::machine(sample.timestamp))
+    );
+    let power = METRICS
+        .iter()
+        .find(|metric| metric.name == "cores_watts")
+        .map(|metric| metric.values(sample))
+        .unwrap_or_default();
+
+    for metric in METRICS.iter().filter(|metric| view.shows(metric)) {
+        let values = view.select(metric, metric.values(sample), &power);
+
+        match metric.label {
+            None => {
+                let value = values.first().map(|(_, value)| *value).unwrap_or(f64::NAN);
+                write!(out, ",\"{}\":{}", metric.name, json_value(metric, value)).unwrap();
+            }
+            Some(_) => {
+                let values = values
+                    .iter()
+                    .map(|(label, value)| {
+                        format!("{}:{}", json_string(label), json_value(metric, *value))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                write!(out, ",\"{}\":{{{}}}", metric.name, values).unwrap();
+            }
+        }
+
+        let Some(uncertainties) = metric.uncertainties(sample) else {
+            continue;
+        };
+        match metric.label {
+            None => {
+                let uncertainty = uncertainties
+                    .first()
+                    .map(|(_, error)| *error)
+                    .unwrap_or(f64::NAN);
+                write!(
+                    out,
+                    ",\"{}_uncertainty\":{}",
+                    metric.name,
+                    json_value(metric, uncertainty)
+                )
+                .unwrap();
+            }
+            Some(_) => {
+                // In the order and for the labels of the values.
+                let uncertainties = values
+                    .iter()
+                    .filter_map(|(label, _)| {
+                        uncertainties
+                            .iter()
+                            .find(|(error_label, _)| error_label == label)
+                    })
+                    .map(|(label, error)| {
+                        format!("{}:{}", json_string(label), json_value(metric, *error))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                write!(
+                    out,
+                    ",\"{}_uncertainty\":{{{}}}",
+                    metric.name, uncertainties
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    write!(
+        out,
+        concat!(
+            ",\"smt_enabled\":{},\"core_count\":{},\"physical_core_count\":{},",
+            "\"backend\":{},\"core_counters\":{},\"core_counters_denied\":{},",
+            "\"pm_table\":{},\"presence\":{},\"skew_seconds\":{{{}}}}}"
+        ),
+        sample.smt_enabled,
+        sample.core_count,
+        sample.physical_core_count,
+        json_string(sample.backend),
+        sample.core_counters,
+        sample.core_counters_denied,
+        sample.pm_table.map_or("null".to_owned(), json_string),
+        sample
+            .presence
+            .map_or("null".to_owned(), |presence| json_string(presence.name())),
+        sample
+            .skew
+            .iter()
+            .map(|(source, skew)| format!("\"{}\":{:.6}", source.name(), skew.as_secs_f64()))
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+    .unwrap();
+
+    out
+}
+
+fn json_value(metric: &Metric, value: f64) -> String {
+    match value.is_finite() {
+        true => machine_value(metric, value),
+        false => "null".to_owned(),
+    }
+}
+
+pub fn quirks(cpu: &CpuInfo, quirks: &Quirks) -> String {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "CPU: {} (family {:#x}, model {:#x})",
+        cpu.model_name, cpu.family, cpu.model
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "BIOS: {}",
+        quirks.bios_version.as_deref().unwrap_or(tr("unknown"))
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+
+    for quirk in QUIRKS {
+        let marker = if quirks.is_applied(quirk.id) {
+            '*'
+        } else {
+            ' '
+        };
+        writeln!(out, "{} {}: {}", marker, quirk.id, quirk.description).unwrap();
+    }
+
+    writeln!(
+        out,
+        "\n{}",
+        trf(
+            "{} of {} quirks applied (marked with *)",
+            &[&quirks.applied.len(), &QUIRKS.len()]
+        )
+    )
+    .unwrap();
+
+    out
+}
+
+/// Resolution of the energy counters and the smallest power they can tell
+/// apart from nothing within `interval`.
+pub fn resolution(cpu: &Cpu, interval: Duration, options: &TextOptions) -> String {
+    let mut out = String::new();
+    let unknown = || tr("unknown").to_owned();
+
+    writeln!(out, "{}: {}", tr("Backend"), cpu.backend_name()).unwrap();
+    let unit = cpu.energy_unit();
+    writeln!(
+        out,
+        "{}: {}",
+        tr("Energy resolution"),
+        unit.map_or_else(unknown, |unit| si_quantity(unit, Unit::Joules, options))
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}: {}",
+        tr("Counter range"),
+        cpu.package_energy_range()
+            .map_or_else(unknown, |range| si_quantity(range, Unit::Joules, options))
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}: {}",
+        trf(
+            "Minimum measurable power at {}",
+            &[&text_quantity(
+                interval.as_secs_f64(),
+                Unit::Seconds,
+                options
+            )]
+        ),
+        unit.map_or_else(unknown, |unit| si_quantity(
+            unit / interval.as_secs_f64(),
+            Unit::Watts,
+            options
+        ))
+    )
+    .unwrap();
+
+    out
+}
+
+/// Everything `info` gathered, grouped into CPU, topology, backend and
+/// quirks.
+pub fn info(info: &Info, options: &TextOptions) -> String {
+    let mut out = String::new();
+    let unknown = || tr("unknown").to_owned();
+    let separator = match options.screen_reader {
+        true => ", ",
+        false => ",",
+    };
+    let cpulist = |cpus: &[u32]| topology::format_cpulist(cpus).replace(',', separator);
+
+    writeln!(
+        out,
+        "CPU: {} (family {:#x}, model {:#x})",
+        info.cpu.model_name, info.cpu.family, info.cpu.model
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}: {}",
+        tr("Generation"),
+        info.cpu.generation().map_or_else(unknown, str::to_owned)
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "BIOS: {}",
+        info.bios_version.clone().unwrap_or_else(unknown)
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(
+        out,
+        "{}: {}",
+        tr("Packages"),
+        info.packages
+            .as_ref()
+            .map_or_else(unknown, |packages| packages.len().to_string())
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}: {}, SMT {}",
+        tr("Cores"),
+        info.threads.len(),
+        tr(if info.smt_enabled { "on" } else { "off" })
+    )
+    .unwrap();
+    writeln!(out, "{}: {}", tr("Online CPUs"), cpulist(&info.online())).unwrap();
+    for (core, threads) in &info.threads {
+        writeln!(out, "{} {}: {}", tr("Core"), core, cpulist(threads)).unwrap();
+    }
+    for (title, groups) in [("CCDs", &info.ccds), ("CCXs", &info.ccxs)] {
+        let groups = groups.as_ref().map_or_else(unknown, |groups| {
+            groups
+                .iter()
+                .map(|(group, cores)| format!("{} ({})", group, cpulist(cores)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+        writeln!(out, "{}: {}", title, groups).unwrap();
+    }
+    writeln!(out).unwrap();
+
+    match &info.backend {
+        Ok(backend) => {
+            let none = || tr("none").to_owned();
+            writeln!(out, "{}: {}", tr("Backend"), backend.name).unwrap();
+            writeln!(
+                out,
+                "{}: {}",
+                tr("Per-core counters"),
+                match backend.cores.is_empty() {
+                    true => none(),
+                    false => cpulist(&backend.cores),
+                }
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}: {}",
+                tr("Domains"),
+                match backend.domains.is_empty() {
+                    true => none(),
+                    false => backend.domains.join(", "),
+                }
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}: {}",
+                tr("Energy resolution"),
+                backend.energy_unit.map_or_else(unknown, |unit| si_quantity(
+                    unit,
+                    Unit::Joules,
+                    options
+                ))
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}: {}",
+                tr("Counter range"),
+                backend
+                    .counter_range
+                    .map_or_else(unknown, |range| si_quantity(range, Unit::Joules, options))
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}: {}",
+                tr("Power limit"),
+                backend
+                    .power_limit
+                    .map_or_else(unknown, |limit| text_quantity(limit, Unit::Watts, options))
+            )
+            .unwrap();
+        }
+        Err(err) => writeln!(out, "{}: {}: {}", tr("Backend"), tr("unavailable"), err).unwrap(),
+    }
+    let limits = info.model_limits.map_or_else(unknown, |limits| {
+        let range = |(low, high): (f64, f64), unit: Unit| {
+            format!(
+                "{} - {}",
+                text_quantity(low, unit, options),
+                text_quantity(high, unit, options)
+            )
+        };
+        format!(
+            "{} {}, {} {}",
+            tr("package power"),
+            range(limits.package_power, Unit::Watts),
+            tr("temperature"),
+            range(limits.temperature, Unit::Celsius)
+        )
+    });
+    writeln!(out, "{}: {}", tr("Model limits"), limits).unwrap();
+    match &info.pm_table {
+        Ok(pm_table) => writeln!(
+            out,
+            "{}: {} ({:#x})",
+            tr("PM table"),
+            pm_table.layout.codename,
+            pm_table.version
+        )
+        .unwrap(),
+        Err(err) => writeln!(out, "{}: {}: {}", tr("PM table"), tr("unavailable"), err).unwrap(),
+    }
+    writeln!(out).unwrap();
+
+    match info.quirks.is_empty() {
+        true => writeln!(out, "{}: {}", tr("Quirks"), tr("none")).unwrap(),
+        false => {
+            writeln!(out, "{}:", tr("Quirks")).unwrap();
+            for quirk in &info.quirks {
+                writeln!(out, "  {}: {}", quirk.id, quirk.description).unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+/// [`info`] as one JSON object.
+pub fn info_json(info: &Info) -> String {
+    let list = |values: &[u32]| {
+        let values = values.iter().map(u32::to_string).collect::<Vec<_>>();
+        format!("[{}]", values.join(","))
+    };
+    let object = |entries: Vec<(String, String)>| {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| format!("{}:{}", json_string(&key), value))
+            .collect::<Vec<_>>();
+        format!("{{{}}}", entries.join(","))
+    };
+    let groups = |groups: &Option<BTreeMap<String, Vec<u32>>>| match groups {
+        Some(groups) => object(
+            groups
+                .iter()
+                .map(|(group, cores)| (group.clone(), list(cores)))
+                .collect(),
+        ),
+        None => "null".to_owned(),
+    };
+    let number = |value: Option<f64>| match value {
+        Some(value) => json_number(value),
+        None => "null".to_owned(),
+    };
+
+    let cpu = object(vec![
+        ("vendor".to_owned(), json_string(&info.cpu.vendor)),
+        ("family".to_owned(), info.cpu.family.to_string()),
+        ("model".to_owned(), info.cpu.model.to_string()),
+        ("model_name".to_owned(), json_string(&info.cpu.model_name)),
+        (
+            "generation".to_owned(),
+            info.cpu.generation().map_or("null".to_owned(), json_string),
+        ),
+        (
+            "bios_version".to_owned(),
+            info.bios_version
+                .as_deref()
+                .map_or("null".to_owned(), json_string),
+        ),
+    ]);
+    let topology = object(vec![
+        ("smt_enabled".to_owned(), info.smt_enabled.to_string()),
+        ("online".to_owned(), list(&info.online())),
+        (
+            "packages".to_owned(),
+            match &info.packages {
+                Some(packages) => object(
+                    packages
+                        .iter()
+                        .map(|(package, cores)| (package.to_string(), list(cores)))
+                        .collect(),
+                ),
+                None => "null".to_owned(),
+            },
+        ),
+        (
+            "cores".to_owned(),
+            object(
+                info.threads
+                    .iter()
+                    .map(|(core, threads)| (core.to_string(), list(threads)))
+                    .collect(),
+            ),
+        ),
+        ("ccds".to_owned(), groups(&info.ccds)),
+        ("ccxs".to_owned(), groups(&info.ccxs)),
+    ]);
+    let backend = match &info.backend {
+        Ok(backend) => object(vec![
+            ("name".to_owned(), json_string(backend.name)),
+            ("core_counters".to_owned(), list(&backend.cores)),
+            (
+                "domains".to_owned(),
+                format!(
+                    "[{}]",
+                    backend
+                        .domains
+                        .iter()
+                        .map(|domain| json_string(domain))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+            ),
+            // Far below the 3 decimals of `json_number`.
+            (
+                "energy_unit_joules".to_owned(),
+                backend
+                    .energy_unit
+                    .map_or("null".to_owned(), |unit| format!("{:e}", unit)),
+            ),
+            (
+                "counter_range_joules".to_owned(),
+                number(backend.counter_range),
+            ),
+            ("power_limit_watts".to_owned(), number(backend.power_limit)),
+        ]),
+        Err(err) => object(vec![("error".to_owned(), json_string(err))]),
+    };
+    let model_limits = match info.model_limits {
+        Some(limits) => {
+            let range =
+                |(low, high): (f64, f64)| format!("[{},{}]", json_number(low), json_number(high));
+            object(vec![
+                (
+                    "package_power_watts".to_owned(),
+                    range(limits.package_power),
+                ),
+                ("temperature_celsius".to_owned(), range(limits.temperature)),
+            ])
+        }
+        None => "null".to_owned(),
+    };
+    let pm_table = match &info.pm_table {
+        Ok(pm_table) => object(vec![
+            ("codename".to_owned(), json_string(pm_table.layout.codename)),
+            ("version".to_owned(), pm_table.version.to_string()),
+        ]),
+        Err(err) => object(vec![("error".to_owned(), json_string(err))]),
+    };
+    let quirks = info
+        .quirks
+        .iter()
+        .map(|quirk| {
+            object(vec![
+                ("id".to_owned(), json_string(quirk.id)),
+                ("description".to_owned(), json_string(quirk.description)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    object(vec![
+        ("cpu".to_owned(), cpu),
+        ("topology".to_owned(), topology),
+        ("backend".to_owned(), backend),
+        ("model_limits".to_owned(), model_limits),
+        ("pm_table".to_owned(), pm_table),
+        ("quirks".to_owned(), format!("[{}]", quirks.join(","))),
+    ])
+}
+
+/// `value` with an SI prefix keeping it between 1 and 1000, e.g. `15.3µJ`.
+fn si_quantity(value: f64, unit: Unit, options: &TextOptions) -> String {
+    const PREFIXES: [(f64, &str, &str); 7] = [
+        (1e9, "G", "giga"),
+        (1e6, "M", "mega"),
+        (1e3, "k", "kilo"),
+        (1.0, "", ""),
+        (1e-3, "m", "milli"),
+        (1e-6, "µ", "micro"),
+        (1e-9, "n", "nano"),
+    ];
+
+    let &(scale, symbol, spelled) = PREFIXES
+        .iter()
+        .find(|(scale, _, _)| value.abs() >= *scale)
+        .unwrap_or(&PREFIXES[PREFIXES.len() - 1]);
+    let value = value / scale;
+
+    match options.screen_reader {
+        true if spelled.is_empty() => format!("{:.1} {}", value, tr(unit.spelled())),
+        true => format!("{:.1} {} {}", value, tr(spelled), tr(unit.spelled())),
+        false => format!("{:.1}{}{}", value, symbol, unit.symbol()),
+    }
+}
+
+/// Comparison table of [`crosscheck`](crate::crosscheck) readings, with
+/// deltas relative to the first source that could be read.
+pub fn cross_check(readings: &[Reading]) -> String {
+    let mut out = String::new();
+
+    let reference = readings
+        .iter()
+        .find_map(|reading| reading.result.as_ref().ok());
+
+    writeln!(
+        out,
+        "{:<10} {:>10} {:>10} {:>18}",
+        tr("Source"),
+        tr("Package"),
+        tr("Cores"),
+        tr("Package delta")
+    )
+    .unwrap();
+
+    for reading in readings {
+        let power = match &reading.result {
+            Ok(power) => power,
+            Err(err) => {
+                writeln!(out, "{:<10} {}: {}", reading.source, tr("unavailable"), err).unwrap();
+                continue;
+            }
+        };
+
+        let cores = power
+            .cores
+            .map(|cores| format!("{:.2}W", cores))
+            .unwrap_or_else(|| "-".to_owned());
+
+        let delta = match reference {
+            Some(reference) if !std::ptr::eq(reference, power) => {
+                let delta = power.package - reference.package;
+                format!(
+                    "{:+.2}W ({:+.1}%)",
+                    delta,
+                    delta / reference.package * 100.0
+                )
+            }
+            _ => tr("reference").to_owned(),
+        };
+
+        writeln!(
+            out,
+            "{:<10} {:>9.2}W {:>10} {:>18}",
+            reading.source, power.package, cores, delta
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+/// Read latency percentiles of every counter of each backend and a
+/// histogram of all its reads.
+pub fn read_latency(backends: &[Latencies], options: &TextOptions) -> String {
+    let mut out = String::new();
+    let seconds = |value| si_quantity(value, Unit::Seconds, options);
+
+    for (index, backend) in backends.iter().enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        let counters = match &backend.result {
+            Ok(counters) => counters,
+            Err(err) => {
+                writeln!(out, "{}: {}: {}", backend.backend, tr("unavailable"), err).unwrap();
+                continue;
+            }
+        };
+        let sample = latency::sample_seconds(counters);
+        writeln!(
+            out,
+            "{}: {}",
+            backend.backend,
+            trf(
+                "one sample of {} counters takes {}, at most {} samples per second",
+                &[
+                    &counters.len(),
+                    &seconds(sample),
+                    &format!("{:.0}", 1.0 / sample)
+                ]
+            )
+        )
+        .unwrap();
+
+        let rows = counters
+            .iter()
+            .map(|counter| {
+                let mut row = vec![counter_name(counter)];
+                row.extend(
+                    [50.0, 90.0, 99.0, 100.0]
+                        .map(|percent| seconds(counter.seconds.percentile(percent))),
+                );
+                row
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&table(
+            &[tr("Counter"), "p50", "p90", "p99", "max"],
+            &rows,
+            1,
+            options,
+        ));
+        for (core, factor) in latency::slow_cores(counters) {
+            writeln!(
+                out,
+                "{}",
+                trf(
+                    "core {} reads {} times slower than the median core",
+                    &[&core, &format!("{:.1}", factor)]
+                )
+            )
+            .unwrap();
+        }
+
+        let buckets = latency::histogram(counters);
+        let fullest = buckets.iter().map(|bucket| bucket.count).max().unwrap_or(1);
+        for bucket in &buckets {
+            let range = format!("{} - {}", seconds(bucket.low), seconds(bucket.high));
+            if options.screen_reader {
+                writeln!(out, "{}: {} {}", range, bucket.count, tr("reads")).unwrap();
+                continue;
+            }
+            let bar = "#".repeat(bucket.count * HISTOGRAM_WIDTH / fullest.max(1));
+            let line = format!("{:>17}  {:>8}  {}", range, bucket.count, bar);
+            writeln!(out, "{}", line.trim_end()).unwrap();
+        }
+    }
+    out
+}
+
+fn counter_name(counter: &Counter) -> String {
+    match counter.core {
+        Some(core) => format!("{} {}", tr("Core"), core),
+        None => tr("Package").to_owned(),
+    }
+}
+
+pub fn read_latency_json(backends: &[Latencies]) -> String {
+    // Reads take microseconds, far below the 3 decimals of `json_number`.
+    let seconds = |value: f64| format!("{:.9}", value);
+    let backends = backends
+        .iter()
+        .map(|backend| {
+            let counters = match &backend.result {
+                Ok(counters) => counters,
+                Err(err) => {
+                    return format!(
+                        "{{\"backend\":{},\"error\":{}}}",
+                        json_string(backend.backend),
+                        json_string(&err.to_string())
+                    )
+                }
+            };
+            let rows = counters
+                .iter()
+                .map(|counter| {
+                    format!(
+                        concat!(
+                            "{{\"core\":{},\"reads\":{},\"p50_seconds\":{},",
+                            "\"p90_seconds\":{},\"p99_seconds\":{},\"max_seconds\":{}}}"
+                        ),
+                        counter
+                            .core
+                            .map_or_else(|| "null".to_owned(), |core| core.to_string()),
+                        counter.seconds.len(),
+                        seconds(counter.seconds.percentile(50.0)),
+                        seconds(counter.seconds.percentile(90.0)),
+                        seconds(counter.seconds.percentile(99.0)),
+                        seconds(counter.seconds.percentile(100.0)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let buckets = latency::histogram(counters)
+                .iter()
+                .map(|bucket| {
+                    format!(
+                        "{{\"low_seconds\":{},\"high_seconds\":{},\"reads\":{}}}",
+                        seconds(bucket.low),
+                        seconds(bucket.high),
+                        bucket.count
+                    )
+                })
+                .collect::<Vec<_>>();
+            let slow = latency::slow_cores(counters)
+                .iter()
+                .map(|(core, factor)| {
+                    format!("{{\"core\":{},\"factor\":{}}}", core, json_number(*factor))
+                })
+                .collect::<Vec<_>>();
+            format!(
+                concat!(
+                    "{{\"backend\":{},\"sample_seconds\":{},\"counters\":[{}],",
+                    "\"slow_cores\":[{}],\"histogram\":[{}]}}"
+                ),
+                json_string(backend.backend),
+                seconds(latency::sample_seconds(counters)),
+                rows.join(","),
+                slow.join(","),
+                buckets.join(",")
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("{{\"backends\":[{}]}}", backends.join(","))
+}
+
+/// Prometheus text exposition format of `sample`, plus the package energy
+/// counter the [`exporter`](crate::exporter) keeps.
+pub fn prometheus(sample: Option<&Sample>, package_joules: f64) -> String {
+    let mut out = String::new();
+
+    for metric in METRICS {
+        let values = sample
+            .map(|sample| metric.values(sample))
+            .unwrap_or_default();
+        if values.is_empty() {
+            continue;
+        }
+
+        writeln!(out, "# HELP {} {}", metric.prometheus, metric.help).unwrap();
+        writeln!(out, "# TYPE {} gauge", metric.prometheus).unwrap();
+        for (label_value, value) in values {
+            let value = machine_value(metric, value);
+            match metric.label {
+                Some(label) => writeln!(
+                    out,
+                    "{}{{{}=\"{}\"}} {}",
+                    metric.prometheus,
+                    label,
+                    prometheus_label_value(&label_value),
+                    value
+                ),
+                None => writeln!(out, "{} {}", metric.prometheus, value),
+            }
+            .unwrap();
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP ryzen_package_joules_total Package energy since the exporter started"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE ryzen_package_joules_total counter").unwrap();
+    writeln!(out, "ryzen_package_joules_total {:.3}", package_joules).unwrap();
+
+    out
+}
+
+/// Power of the whole node from `--node-power`, nothing without a current
+/// reading.
+pub fn prometheus_node(node_power: Option<f64>) -> String {
+    let Some(watts) = node_power else {
+        return String::new();
+    };
+    format!(
+        "# HELP ryzen_node_watts Power of the whole node as its BMC reports it\n\
+         # TYPE ryzen_node_watts gauge\n\
+         ryzen_node_watts {:.3}\n",
+        watts
+    )
+}
+
+/// The power of every owner `--breakdown` found in the last window, like
+/// `ryzen_container_watts{container="web",image="nginx"}`.
+pub fn prometheus_breakdown(kind: breakdown::Kind, watts: &BTreeMap<Owner, f64>) -> String {
+    if watts.is_empty() {
+        return String::new();
+    }
+    let name = format!("ryzen_{}_watts", kind);
+    let mut out = format!(
+        "# HELP {} Estimated package power of each {} by the CPU time of its processes\n\
+         # TYPE {} gauge\n",
+        name, kind, name
+    );
+    for (owner, watts) in watts {
+        let mut labels = format!("{}=\"{}\"", kind, prometheus_label_value(&owner.name));
+        if let (Some(label), Some(detail)) = (kind.detail(), &owner.detail) {
+            write!(labels, ",{}=\"{}\"", label, prometheus_label_value(detail)).unwrap();
+        }
+        writeln!(out, "{}{{{}}} {:.3}", name, labels, watts).unwrap();
+    }
+    out
+}
+
+/// The constant `ryzen_info` gauge carrying `labels`, for joining onto the
+/// other metrics in queries.
+pub fn prometheus_info(labels: &[(&str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, prometheus_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "# HELP ryzen_info Host, CPU and backend of the readings\n\
+         # TYPE ryzen_info gauge\n\
+         ryzen_info{{{}}} 1\n",
+        labels
+    )
+}
+
+/// Health of the sinks samples go to, labeled with their name and target.
+pub fn prometheus_sinks(sinks: &[(&str, &str, Status)]) -> String {
+    let mut out = String::new();
+    if sinks.is_empty() {
+        return out;
+    }
+
+    let mut family = |name: &str, kind: &str, help: &str, value: fn(&Status) -> u64| {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+        for (sink, target, status) in sinks {
+            writeln!(
+                out,
+                "{}{{sink=\"{}\",target=\"{}\"}} {}",
+                name,
+                prometheus_label_value(sink),
+                prometheus_label_value(target),
+                value(status)
+            )
+            .unwrap();
+        }
+    };
+    family(
+        "ryzen_sink_up",
+        "gauge",
+        "Whether the last delivery to the sink worked",
+        |status| u64::from(!status.failing),
+    );
+    family(
+        "ryzen_sink_delivered_total",
+        "counter",
+        "Samples delivered to the sink",
+        |status| status.delivered,
+    );
+    family(
+        "ryzen_sink_failures_total",
+        "counter",
+        "Deliveries to the sink that failed",
+        |status| status.failed,
+    );
+    family(
+        "ryzen_sink_dropped_total",
+        "counter",
+        "Samples dropped because the sink fell behind",
+        |status| status.dropped,
+    );
+
+    out
+}
+
+/// The machine's hostname, for tagging streamed samples.
+pub fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| {
+            let name = std::fs::read_to_string(path).ok()?;
+            let name = name.trim();
+            (!name.is_empty()).then(|| name.to_owned())
+        })
+        .unwrap_or_else(|| "localhost".to_owned())
+}
+
+/// Values sharing a label value, or no label at all.
+struct Series {
+    /// The label and its value, like `("core", "3")`.
+    tag: Option<(&'static str, String)>,
+    /// Metric names and their values.
+    fields: Vec<(&'static str, f64)>,
+}
+
+/// Values of a sample grouped into series: one for the unlabeled metrics,
+/// then one per label value, each with the metrics that have it as fields.
+fn series(sample: &Sample) -> Vec<Series> {
+    let mut series = vec![Series {
+        tag: None,
+        fields: Vec::new(),
+    }];
+
+    for metric in METRICS {
+        for (label_value, value) in metric.values(sample) {
+            if !value.is_finite() {
+                continue;
+            }
+            let tag = metric.label.map(|label| (label, label_value));
+            match series.iter_mut().find(|series| series.tag == tag) {
+                Some(series) => series.fields.push((metric.name, value)),
+                None => series.push(Series {
+                    tag,
+                    fields: vec![(metric.name, value)],
+                }),
+            }
+        }
+    }
+
+    series.retain(|series| !series.fields.is_empty());
+    series
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// InfluxDB line protocol, one line per [`series`] of the sample in the
+/// `ryzen_wattage` measurement, tagged with `host` unless it's redacted and
+/// the label.
+pub fn influx(sample: &Sample, host: Option<&str>) -> String {
+    let mut out = String::new();
+    let timestamp = unix_nanos(sample.timestamp);
+
+    for Series { tag, fields } in series(sample) {
+        out += "ryzen_wattage";
+        if let Some(host) = host {
+            write!(out, ",host={}", influx_escape(host)).unwrap();
+        }
+        if let Some((label, value)) = tag {
+            write!(out, ",{}={}", label, influx_escape(&value)).unwrap();
+        }
+        let fields = fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(out, " {} {}", fields, timestamp).unwrap();
+    }
+
+    out
+}
+
+/// Escapes tag values, which end at unescaped commas, spaces and equal
+/// signs.
+fn influx_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Like [`influx`], but every line a JSON object with `name`, `tags`,
+/// `fields` and a nanosecond `timestamp`.
+pub fn ndjson(sample: &Sample, host: Option<&str>) -> String {
+    let mut out = String::new();
+    let timestamp = unix_nanos(sample.timestamp);
+
+    for Series { tag, fields } in series(sample) {
+        let tags = host
+            .map(|host| ("host", host.to_owned()))
+            .into_iter()
+            .chain(tag)
+            .map(|(label, value)| format!("\"{}\":{}", label, json_string(&value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let fields = fields
+            .iter()
+            .map(|(name, value)| format!("\"{}\":{}", name, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(
+            out,
+            concat!(
+                "{{\"name\":\"ryzen_wattage\",\"schema_version\":{},\"timestamp\":{},",
+                "\"tags\":{{{}}},\"fields\":{{{}}}}}"
+            ),
+            schema::VERSION,
+            timestamp,
+            tags,
+            fields
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+fn prometheus_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Statistics over a series of samples, one line per domain.
+pub fn summary(summary: &Summary, options: &TextOptions) -> String {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "{}",
+        trf(
+            "{} samples over {}",
+            &[
+                &summary.samples(),
+                &text_quantity(summary.duration.as_secs_f64(), Unit::Seconds, options)
+            ]
+        )
+    )
+    .unwrap();
+
+    let mut line = |title: String, domain: &PowerSummary| {
+        let watts = |value| text_quantity(value, Unit::Watts, options);
+        writeln!(
+            out,
+            "{}: min {}, {} {}, max {}, {} {}, {} {}",
+            title,
+            watts(domain.power.min),
+            tr("avg"),
+            watts(domain.power.mean()),
+            watts(domain.power.max),
+            tr("stddev"),
+            watts(domain.power.stddev()),
+            tr("total"),
+            text_quantity(domain.energy.value(), Unit::Joules, options)
+        )
+        .unwrap();
+    };
+
+    line(tr("Package").to_owned(), &summary.package);
+    for (core, domain) in &summary.cores {
+        line(format!("{} {}", tr("Core"), core), domain);
+    }
+    for (group, domain) in &summary.groups {
+        line(format!("{} {}", tr("Group"), group), domain);
+    }
+    for (presence, domain) in &summary.presence {
+        line(
+            format!("{} ({})", tr("Package"), tr(presence.name())),
+            domain,
+        );
+    }
+    if let Some(joules) = summary.joules_per_day() {
+        writeln!(out, "{}", suspended(summary, joules, options)).unwrap();
+    }
+
+    if let Some(distribution) = summary.distribution.as_ref().filter(|d| !d.is_empty()) {
+        out.push('\n');
+        out.push_str(&histogram(distribution, options));
+    }
+
+    let ranking = summary.core_ranking();
+    if !ranking.is_empty() {
+        writeln!(
+            out,
+            "\n{}",
+            tr("Cores by energy per busy second, most efficient first:")
+        )
+        .unwrap();
+        for (rank, core) in ranking.iter().enumerate() {
+            writeln!(
+                out,
+                "{}. {} {}: {} {}",
+                rank + 1,
+                tr("Core"),
+                core.core,
+                text_quantity(core.joules_per_busy_second, Unit::Joules, options),
+                trf(
+                    "per busy second, {} busy",
+                    &[&format!("{:.1}%", core.busy_percent)]
+                ),
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+/// How often and how long the run was suspended, with the energy per day.
+fn suspended(summary: &Summary, joules_per_day: f64, options: &TextOptions) -> String {
+    trf(
+        "Suspended {} times for {}, awake {}: {} per day of use like this",
+        &[
+            &summary.suspends,
+            &text_quantity(summary.suspended.as_secs_f64(), Unit::Seconds, options),
+            &format!("{:.1}%", summary.awake_percent()),
+            &text_quantity(joules_per_day, Unit::Joules, options),
+        ],
+    )
+}
+
+/// Buckets of [`histogram`].
+const HISTOGRAM_BUCKETS: usize = 10;
+/// Characters of the fullest bucket's bar.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Percentiles of the package power and a histogram of it.
+fn histogram(distribution: &Distribution, options: &TextOptions) -> String {
+    let watts = |value| text_quantity(value, Unit::Watts, options);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{}: p50 {}, p90 {}, p99 {}",
+        tr("Package power percentiles"),
+        watts(distribution.percentile(50.0)),
+        watts(distribution.percentile(90.0)),
+        watts(distribution.percentile(99.0)),
+    )
+    .unwrap();
+
+    let buckets = distribution.buckets(HISTOGRAM_BUCKETS);
+    let fullest = buckets.iter().map(|bucket| bucket.count).max().unwrap_or(1);
+    let ranges = buckets
+        .iter()
+        .map(|bucket| match options.screen_reader {
+            true => format!("{} {} {}", watts(bucket.low), tr("to"), watts(bucket.high)),
+            false => format!("{} - {}", watts(bucket.low), watts(bucket.high)),
+        })
+        .collect::<Vec<_>>();
+    let range_width = ranges.iter().map(String::len).max().unwrap_or(0);
+    let count_width = fullest.to_string().len();
+
+    for (bucket, range) in buckets.iter().zip(&ranges) {
+        // A bar means nothing read out.
+        if options.screen_reader {
+            writeln!(out, "{}: {} {}", range, bucket.count, tr("samples")).unwrap();
+            continue;
+        }
+        let bar = "#".repeat(bucket.count * HISTOGRAM_WIDTH / fullest.max(1));
+        let line = format!(
+            "{:>range_width$}  {:>count_width$}  {}",
+            range, bucket.count, bar
+        );
+        writeln!(out, "{}", line.trim_end()).unwrap();
+    }
+    out
+}
+
+/// One line wrapping up a monitoring session.
+pub fn session_summary(summary: &Summary, options: &TextOptions) -> String {
+    let mut out = trf(
+        "Sampled {} in {} samples: {}, {} average package power",
+        &[
+            &text_quantity(summary.duration.as_secs_f64(), Unit::Seconds, options),
+            &summary.samples(),
+            &text_quantity(summary.package.energy.value(), Unit::Joules, options),
+            &text_quantity(summary.package.power.mean(), Unit::Watts, options),
+        ],
+    );
+    if let Some(joules) = summary.joules_per_day() {
+        write!(out, "\n{}", suspended(summary, joules, options)).unwrap();
+    }
+    out
+}
+
+/// How a sink did over the session, for the summary at exit.
+pub fn sink_summary(name: &str, target: &str, status: &Status) -> String {
+    let mut out = trf(
+        "{} to {}: {} samples delivered, {} failed, {} dropped",
+        &[
+            &name,
+            &target,
+            &status.delivered,
+            &status.failed,
+            &status.dropped,
+        ],
+    );
+    if let Some(error) = &status.last_error {
+        out.push_str(&trf(", last error: {}", &[error]));
+    }
+    out
+}
+
+pub fn summary_json(summary: &Summary) -> String {
+    let domain = |domain: &PowerSummary| {
+        format!(
+            concat!(
+                "{{\"min_watts\":{},\"mean_watts\":{},\"max_watts\":{},",
+                "\"stddev_watts\":{},\"joules\":{}}}"
+            ),
+            json_number(domain.power.min),
+            json_number(domain.power.mean()),
+            json_number(domain.power.max),
+            json_number(domain.power.stddev()),
+            json_number(domain.energy.value()),
+        )
+    };
+
+    let cores = summary
+        .cores
+        .iter()
+        .map(|(core, summary)| format!("\"{}\":{}", core, domain(summary)))
+        .collect::<Vec<_>>()
+        .join(",");
+    let groups = match summary.groups.is_empty() {
+        true => String::new(),
+        false => format!(
+            ",\"groups\":{{{}}}",
+            summary
+                .groups
+                .iter()
+                .map(|(group, summary)| format!("{}:{}", json_string(group), domain(summary)))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+    };
+
+    let distribution = match &summary.distribution {
+        Some(distribution) => {
+            let buckets = distribution
+                .buckets(HISTOGRAM_BUCKETS)
+                .iter()
+                .map(|bucket| {
+                    format!(
+                        "{{\"low_watts\":{},\"high_watts\":{},\"samples\":{}}}",
+                        json_number(bucket.low),
+                        json_number(bucket.high),
+                        bucket.count
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            format!(
+                concat!(
+                    ",\"package_percentiles\":{{\"p50_watts\":{},\"p90_watts\":{},",
+                    "\"p99_watts\":{}}},\"package_histogram\":[{}]"
+                ),
+                json_number(distribution.percentile(50.0)),
+                json_number(distribution.percentile(90.0)),
+                json_number(distribution.percentile(99.0)),
+                buckets
+            )
+        }
+        None => String::new(),
+    };
+
+    let presence = match summary.presence.is_empty() {
+        true => String::new(),
+        false => format!(
+            ",\"presence\":{{{}}}",
+            summary
+                .presence
+                .iter()
+                .map(|(presence, summary)| {
+                    format!("{}:{}", json_string(presence.name()), domain(summary))
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+    };
+
+    let suspended = match summary.joules_per_day() {
+        Some(joules) => format!(
+            ",\"suspends\":{},\"suspended_seconds\":{},\"awake_percent\":{},\"joules_per_day\":{}",
+            summary.suspends,
+            json_number(summary.suspended.as_secs_f64()),
+            json_number(summary.awake_percent()),
+            json_number(joules)
+        ),
+        None => String::new(),
+    };
+
+    let ranking = summary.core_ranking();
+    let ranking = match ranking.is_empty() {
+        true => String::new(),
+        false => format!(
+            ",\"core_ranking\":[{}]",
+            ranking
+                .iter()
+                .map(|core| format!(
+                    "{{\"core\":{},\"joules_per_busy_second\":{},\"busy_percent\":{}}}",
+                    core.core,
+                    json_number(core.joules_per_busy_second),
+                    json_number(core.busy_percent)
+                ))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+    };
+
+    format!(
+        "{{\"samples\":{},\"duration_seconds\":{},\"package\":{},\"cores\":{{{}}}{}{}{}{}{}}}",
+        summary.samples(),
+        json_number(summary.duration.as_secs_f64()),
+        domain(&summary.package),
+        cores,
+        groups,
+        presence,
+        suspended,
+        distribution,
+        ranking
+    )
+}
+
+/// Summary of a [`run`](crate::run::run), like `perf stat` prints it.
+pub fn run_report(command: &[String], report: &Report, options: &TextOptions) -> String {
+    let mut out = String::new();
+
+    let status = match report.status.code() {
+        Some(code) => trf("exit status {}", &[&code]),
+        None => tr("killed by a signal").to_owned(),
+    };
+    writeln!(out, "{}: {} ({})", tr("Command"), command.join(" "), status).unwrap();
+
+    let lines = [
+        ("Wall time", report.wall_time.as_secs_f64(), Unit::Seconds),
+        ("Energy", report.energy, Unit::Joules),
+        ("Average package power", report.average_power, Unit::Watts),
+        ("Peak package power", report.peak_power, Unit::Watts),
+    ];
+    for (title, value, unit) in lines {
+        writeln!(
+            out,
+            "{}: {}",
+            tr(title),
+            text_quantity(value, unit, options)
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+pub fn run_report_json(command: &[String], report: &Report) -> String {
+    let command = command
+        .iter()
+        .map(|arg| json_string(arg))
+        .collect::<Vec<_>>()
+        .join(",");
+    let exit_code = report
+        .status
+        .code()
+        .map_or_else(|| "null".to_owned(), |code| code.to_string());
+
+    format!(
+        concat!(
+            "{{\"command\":[{}],\"exit_code\":{},\"wall_time_seconds\":{},",
+            "\"energy_joules\":{},\"average_package_watts\":{},\"peak_package_watts\":{}}}"
+        ),
+        command,
+        exit_code,
+        json_number(report.wall_time.as_secs_f64()),
+        json_number(report.energy),
+        json_number(report.average_power),
+        json_number(report.peak_power),
+    )
+}
+
+/// Results of an [`experiment`](crate::experiment): a table with one row
+/// per workload and setting with the mean and standard deviation over the
+/// successful runs, followed by how each setting compares to the first.
+pub fn experiment(cells: &[Cell], options: &TextOptions) -> String {
+    let mut out = experiment_table(cells, options);
+
+    let percent = |value: f64| match options.screen_reader {
+        true => format!("{:+.1} {}", value, tr("percent")),
+        false => format!("{:+.1}%", value),
+    };
+
+    for comparison in experiment::compare(cells) {
+        writeln!(
+            out,
+            "\n{}",
+            trf(
+                "{}: {} compared to {}",
+                &[
+                    &comparison.workload,
+                    &comparison.setting,
+                    &comparison.baseline
+                ]
+            )
+        )
+        .unwrap();
+
+        let lines = [
+            (
+                "Energy",
+                comparison.energy,
+                comparison.baseline_energy,
+                ("{} uses less energy", "{} uses more energy"),
+            ),
+            (
+                "Wall time",
+                comparison.wall_time,
+                comparison.baseline_wall_time,
+                ("{} is faster", "{} is slower"),
+            ),
+        ];
+        for (title, difference, baseline, (lower, higher)) in lines {
+            let Some(difference) = difference else {
+                writeln!(
+                    out,
+                    "  {}: {}",
+                    tr(title),
+                    tr("too few successful runs to compare")
+                )
+                .unwrap();
+                continue;
+            };
+
+            // Only a significant difference makes a winner.
+            let verdict = match (difference.significant(), difference.mean < 0.0) {
+                (false, _) => tr("no significant difference").to_owned(),
+                (true, true) => trf(lower, &[&comparison.setting]),
+                (true, false) => trf(higher, &[&comparison.setting]),
+            };
+            let relative = |value: f64| percent(value / baseline * 100.0);
+            writeln!(
+                out,
+                "  {}: {} ({}), p = {:.3}: {}",
+                tr(title),
+                relative(difference.mean),
+                trf(
+                    "95% CI {} to {}",
+                    &[&relative(difference.low), &relative(difference.high)]
+                ),
+                difference.p_value,
+                verdict
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+fn experiment_table(cells: &[Cell], options: &TextOptions) -> String {
+    let rows = cells
+        .iter()
+        .map(|cell| {
+            vec![
+                cell.workload.clone(),
+                cell.setting.clone(),
+                format!(
+                    "{}/{}",
+                    cell.energy.count,
+                    cell.energy.count + cell.failures as usize
+                ),
+                text_mean_stddev(&cell.energy, Unit::Joules, options),
+                text_mean_stddev(&cell.wall_time, Unit::Seconds, options),
+                text_mean_stddev(&cell.average_power, Unit::Watts, options),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        tr("Workload"),
+        tr("Setting"),
+        tr("Runs"),
+        tr("Energy"),
+        tr("Wall time"),
+        tr("Average package power"),
+    ];
+
+    table(&header, &rows, 2, options)
+}
+
+/// Without successful runs there is nothing to show, a single run has no
+/// deviation.
+fn text_mean_stddev(stats: &Stats, unit: Unit, options: &TextOptions) -> String {
+    if !stats.mean().is_finite() {
+        return "-".to_owned();
+    }
+    if !stats.stddev().is_finite() {
+        return text_quantity(stats.mean(), unit, options);
+    }
+    let separator = match options.screen_reader {
+        true => format!(" {} ", tr("plus or minus")),
+        false => " ± ".to_owned(),
+    };
+    format!(
+        "{}{}{}",
+        text_quantity(stats.mean(), unit, options),
+        separator,
+        text_quantity(stats.stddev(), unit, options)
+    )
+}
+
+/// Rows below `header`, the first `names` columns left aligned.
+fn table(header: &[&str], rows: &[Vec<String>], names: usize, options: &TextOptions) -> String {
+    let mut out = String::new();
+
+    // Screen readers get one line per row instead of aligned columns.
+    if options.screen_reader {
+        for row in rows {
+            let fields = header
+                .iter()
+                .zip(row)
+                .map(|(title, value)| format!("{} {}", title, value))
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", fields.join(", ")).unwrap();
+        }
+        return out;
+    }
+
+    let mut widths = header
+        .iter()
+        .map(|title| title.chars().count())
+        .collect::<Vec<_>>();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let mut line = |fields: &[&str]| {
+        let line = fields
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(index, (field, &width))| match index < names {
+                true => format!("{:<width$}", field),
+                false => format!("{:>width$}", field),
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end()).unwrap();
+    };
+
+    line(header);
+    for row in rows {
+        line(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+
+    out
+}
+
+/// The runs of every compared command, and how each differs from the first.
+pub fn comparison(outcomes: &[Outcome], options: &TextOptions) -> String {
+    let Some(baseline) = outcomes.first() else {
+        return String::new();
+    };
+    let percent = |value: f64| match options.screen_reader {
+        true => format!("{:+.1} {}", value, tr("percent")),
+        false => format!("{:+.1}%", value),
+    };
+    let delta = |delta: &Delta, unit| {
+        if !delta.absolute.is_finite() {
+            return "-".to_owned();
+        }
+        let sign = if delta.absolute < 0.0 { "" } else { "+" };
+        format!(
+            "{}{} ({})",
+            sign,
+            text_quantity(delta.absolute, unit, options),
+            percent(delta.percent)
+        )
+    };
+
+    let rows = outcomes
+        .iter()
+        .enumerate()
+        .map(|(index, outcome)| {
+            let deltas = match index {
+                0 => ["-".to_owned(), "-".to_owned(), "-".to_owned()],
+                _ => {
+                    let [energy, wall_time, power] = outcome.compare(baseline);
+                    [
+                        delta(&energy, Unit::Joules),
+                        delta(&wall_time, Unit::Seconds),
+                        delta(&power, Unit::Watts),
+                    ]
+                }
+            };
+            let [energy, wall_time, power] = deltas;
+            vec![
+                outcome.variant.label.clone(),
+                format!(
+                    "{}/{}",
+                    outcome.energy.count,
+                    outcome.energy.count + outcome.failures as usize
+                ),
+                text_mean_stddev(&outcome.energy, Unit::Joules, options),
+                energy,
+                text_mean_stddev(&outcome.wall_time, Unit::Seconds, options),
+                wall_time,
+                text_mean_stddev(&outcome.average_power, Unit::Watts, options),
+                power,
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        tr("Label"),
+        tr("Runs"),
+        tr("Energy per run"),
+        tr("Difference"),
+        tr("Wall time"),
+        tr("Difference"),
+        tr("Average package power"),
+        tr("Difference"),
+    ];
+    let mut out = table(&header, &rows, 1, options);
+
+    for outcome in &outcomes[1..] {
+        let [energy, wall_time, _] = outcome.compare(baseline);
+        let label = &outcome.variant.label;
+        writeln!(
+            out,
+            "\n{}",
+            trf("{} compared to {}", &[label, &baseline.variant.label])
+        )
+        .unwrap();
+
+        let lines = [
+            (
+                "Energy",
+                energy,
+                baseline.energy.mean(),
+                ("{} uses less energy", "{} uses more energy"),
+            ),
+            (
+                "Wall time",
+                wall_time,
+                baseline.wall_time.mean(),
+                ("{} is faster", "{} is slower"),
+            ),
+        ];
+        for (title, delta, base, (lower, higher)) in lines {
+            let Some(difference) = delta.difference else {
+                writeln!(
+                    out,
+                    "  {}: {}",
+                    tr(title),
+                    tr("too few successful runs to compare")
+                )
+                .unwrap();
+                continue;
+            };
+
+            let verdict = match (difference.significant(), difference.mean < 0.0) {
+                (false, _) => tr("no significant difference").to_owned(),
+                (true, true) => trf(lower, &[label]),
+                (true, false) => trf(higher, &[label]),
+            };
+            let relative = |value: f64| percent(value / base * 100.0);
+            writeln!(
+                out,
+                "  {}: {} ({}), p = {:.3}: {}",
+                tr(title),
+                percent(delta.percent),
+                trf(
+                    "95% CI {} to {}",
+                    &[&relative(difference.low), &relative(difference.high)]
+                ),
+                difference.p_value,
+                verdict
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+fn json_stats(stats: &Stats) -> String {
+    let (min, max) = match stats.count {
+        0 => (f64::NAN, f64::NAN),
+        _ => (stats.min, stats.max),
+    };
+    format!(
+        "{{\"mean\":{},\"stddev\":{},\"ci95\":{},\"min\":{},\"max\":{}}}",
+        json_number(stats.mean()),
+        json_number(stats.stddev()),
+        json_number(stats.confidence_interval()),
+        json_number(min),
+        json_number(max),
+    )
+}
+
+fn json_difference(difference: Option<Difference>) -> String {
+    match difference {
+        Some(difference) => format!(
+            concat!(
+                "{{\"mean\":{},\"ci95_low\":{},\"ci95_high\":{},",
+                "\"p_value\":{},\"significant\":{}}}"
+            ),
+            json_number(difference.mean),
+            json_number(difference.low),
+            json_number(difference.high),
+            json_number(difference.p_value),
+            difference.significant(),
+        ),
+        None => "null".to_owned(),
+    }
+}
+
+pub fn comparison_json(outcomes: &[Outcome]) -> String {
+    let delta = |delta: &Delta| {
+        format!(
+            "{{\"absolute\":{},\"percent\":{},\"difference\":{}}}",
+            json_number(delta.absolute),
+            json_number(delta.percent),
+            json_difference(delta.difference),
+        )
+    };
+
+    let rows = outcomes
+        .iter()
+        .enumerate()
+        .map(|(index, outcome)| {
+            let deltas = match index {
+                0 => "null".to_owned(),
+                _ => {
+                    let [energy, wall_time, power] = outcome.compare(&outcomes[0]);
+                    format!(
+                        concat!(
+                            "{{\"energy_joules\":{},\"wall_time_seconds\":{},",
+                            "\"average_package_watts\":{}}}"
+                        ),
+                        delta(&energy),
+                        delta(&wall_time),
+                        delta(&power),
+                    )
+                }
+            };
+            format!(
+                concat!(
+                    "{{\"label\":{},\"command\":[{}],\"runs\":{},\"failures\":{},",
+                    "\"energy_joules\":{},\"wall_time_seconds\":{},",
+                    "\"average_package_watts\":{},\"compared_to_baseline\":{}}}"
+                ),
+                json_string(&outcome.variant.label),
+                outcome
+                    .variant
+                    .command
+                    .iter()
+                    .map(|arg| json_string(arg))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                outcome.energy.count,
+                outcome.failures,
+                json_stats(&outcome.energy),
+                json_stats(&outcome.wall_time),
+                json_stats(&outcome.average_power),
+                deltas,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!("{{\"variants\":[{}]}}", rows)
+}
+
+/// Millions of loop iterations, the work unit of [`crate::advise`].
+fn text_work(work: f64, per: &str) -> String {
+    format!("{:.1}M/{}", work / 1e6, per)
+}
+
+/// Every measured core and the CPUs recommended for the workload.
+pub fn advice(
+    measurements: &[Measurement],
+    recommendation: &Recommendation,
+    options: &TextOptions,
+) -> String {
+    let rows = measurements
+        .iter()
+        .map(|measurement| {
+            vec![
+                measurement.core.to_string(),
+                measurement.group.clone().unwrap_or_else(|| "-".to_owned()),
+                text_quantity(measurement.watts, Unit::Watts, options),
+                text_work(measurement.work_rate, "s"),
+                text_work(measurement.work_per_joule(), "J"),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        tr("Core"),
+        "CCD",
+        tr("Power"),
+        tr("Work"),
+        tr("Work per joule"),
+    ];
+    let mut out = table(&header, &rows, 2, options);
+
+    let cpus = recommendation.cpulist();
+    let line = match recommendation.groups.as_slice() {
+        [] => trf(
+            "For {} threads, best {}: CPUs {}",
+            &[&recommendation.threads, &recommendation.goal, &cpus],
+        ),
+        groups => trf(
+            "For {} threads, best {}: CPUs {} on {}",
+            &[
+                &recommendation.threads,
+                &recommendation.goal,
+                &cpus,
+                &groups.join(", "),
+            ],
+        ),
+    };
+    writeln!(out, "\n{}", line).unwrap();
+    let work_per_joule = match recommendation.watts > 0.0 {
+        true => recommendation.work_rate / recommendation.watts,
+        false => 0.0,
+    };
+    writeln!(
+        out,
+        "{}",
+        trf(
+            "about {} doing {}, {}, measured one core at a time",
+            &[
+                &text_quantity(recommendation.watts, Unit::Watts, options),
+                &text_work(recommendation.work_rate, "s"),
+                &text_work(work_per_joule, "J"),
+            ]
+        )
+    )
+    .unwrap();
+    writeln!(out, "\n  taskset -c {} PROGRAM", cpus).unwrap();
+    writeln!(out, "  systemd-run --scope -p AllowedCPUs={} PROGRAM", cpus).unwrap();
+    out
+}
+
+pub fn advice_json(measurements: &[Measurement], recommendation: &Recommendation) -> String {
+    let cores = measurements
+        .iter()
+        .map(|measurement| {
+            format!(
+                concat!(
+                    "{{\"core\":{},\"ccd\":{},\"watts\":{},",
+                    "\"work_per_second\":{},\"work_per_joule\":{}}}"
+                ),
+                measurement.core,
+                measurement
+                    .group
+                    .as_deref()
+                    .map_or_else(|| "null".to_owned(), json_string),
+                json_number(measurement.watts),
+                json_number(measurement.work_rate),
+                json_number(measurement.work_per_joule()),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let numbers = |values: &[u32]| {
+        values
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let groups = recommendation
+        .groups
+        .iter()
+        .map(|group| json_string(group))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(
+        concat!(
+            "{{\"cores\":[{}],\"recommendation\":{{\"goal\":{},\"threads\":{},",
+            "\"cpus\":[{}],\"cpulist\":{},\"cores\":[{}],\"ccds\":[{}],",
+            "\"watts\":{},\"work_per_second\":{}}}}}"
+        ),
+        cores,
+        json_string(recommendation.goal.name()),
+        recommendation.threads,
+        numbers(&recommendation.cpus),
+        json_string(&recommendation.cpulist()),
+        numbers(&recommendation.cores),
+        groups,
+        json_number(recommendation.watts),
+        json_number(recommendation.work_rate),
+    )
+}
+
+/// One window of `attribute`.
+pub fn tree_share(
+    meter: &TreeMeter,
+    share: &Share,
+    window: Duration,
+    options: &TextOptions,
+) -> String {
+    let watts = share.joules / window.as_secs_f64();
+    let percent = match options.screen_reader {
+        true => format!("{:.1} {}", share.fraction * 100.0, tr("percent")),
+        false => format!("{:.1}%", share.fraction * 100.0),
+    };
+    format!(
+        "{} ({}): {}\n",
+        meter.root.name,
+        meter.root.pid,
+        trf(
+            "{} with {} of the busy CPU time in {} processes, {} in total",
+            &[
+                &text_quantity(watts, Unit::Watts, options),
+                &percent,
+                &share.processes,
+                &text_quantity(meter.joules, Unit::Joules, options),
+            ]
+        )
+    )
+}
+
+pub fn tree_share_json(meter: &TreeMeter, share: &Share, window: Duration) -> String {
+    format!(
+        concat!(
+            "{{\"pid\":{},\"name\":{},\"processes\":{},\"watts\":{},",
+            "\"busy_share_percent\":{},\"joules\":{},\"total_joules\":{},",
+            "\"total_cpu_seconds\":{}}}"
+        ),
+        meter.root.pid,
+        json_string(&meter.root.name),
+        share.processes,
+        json_number(share.joules / window.as_secs_f64()),
+        json_number(share.fraction * 100.0),
+        json_number(share.joules),
+        json_number(meter.joules),
+        json_number(meter.ticks as f64 / process::ticks_per_second() as f64),
+    )
+}
+
+/// What `attribute` found over its whole run.
+pub fn tree_total(meter: &TreeMeter, elapsed: Duration, options: &TextOptions) -> String {
+    let cpu_time = meter.ticks as f64 / process::ticks_per_second() as f64;
+    trf(
+        "{} ({}) and everything it ran: {} over {}, {} of CPU time",
+        &[
+            &meter.root.name,
+            &meter.root.pid,
+            &text_quantity(meter.joules, Unit::Joules, options),
+            &text_quantity(elapsed.as_secs_f64(), Unit::Seconds, options),
+            &text_quantity(cpu_time, Unit::Seconds, options),
+        ],
+    )
+}
+
+/// `ledger show`, of `day` or of every day without one.
+pub fn ledger(entries: &[(String, f64)], day: Option<&str>, options: &TextOptions) -> String {
+    if entries.is_empty() {
+        return format!("{}\n", tr("No energy in the ledger yet"));
+    }
+
+    let total = entries.iter().map(|(_, joules)| joules).sum::<f64>();
+    let rows = entries
+        .iter()
+        .map(|(name, joules)| {
+            vec![
+                name.clone(),
+                text_quantity(*joules, Unit::Joules, options),
+                text_quantity(joules / total * 100.0, Unit::Percent, options),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let title = match day {
+        Some(day) => trf("Energy per process on {}", &[&day]),
+        None => tr("Energy per process over all days").to_owned(),
+    };
+    let mut out = format!("{}\n", title);
+    out.push_str(&table(
+        &[tr("Process"), tr("Energy"), tr("Share")],
+        &rows,
+        1,
+        options,
+    ));
+    writeln!(
+        out,
+        "{}",
+        trf(
+            "{} in total",
+            &[&text_quantity(total, Unit::Joules, options)]
+        )
+    )
+    .unwrap();
+    out
+}
+
+pub fn ledger_json(entries: &[(String, f64)], day: Option<&str>) -> String {
+    let processes = entries
+        .iter()
+        .map(|(name, joules)| {
+            format!(
+                "{{\"name\":{},\"joules\":{}}}",
+                json_string(name),
+                json_number(*joules)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"day\":{},\"processes\":[{}]}}",
+        day.map_or_else(|| "null".to_owned(), json_string),
+        processes.join(",")
+    )
+}
+
+/// The answer to `history query`, one line without `by`.
+pub fn query(query: &Query, rows: &[Row], options: &TextOptions) -> String {
+    let unit = query.aggregate.unit();
+    let heading = query.to_string();
+    match (rows, query.by) {
+        ([], _) => format!("{}\n", tr("No history matches the query")),
+        ([row], None) => format!("{}: {}\n", heading, text_quantity(row.value, unit, options)),
+        _ => {
+            let rows = rows
+                .iter()
+                .map(|row| {
+                    vec![
+                        timefmt::human(row.start),
+                        text_quantity(row.value, unit, options),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            table(&[tr("Time"), &heading], &rows, 1, options)
+        }
+    }
+}
+
+pub fn query_json(query: &Query, rows: &[Row]) -> String {
+    let rows = rows
+        .iter()
+        .map(|row| {
+            format!(
+                "{{\"start\":{},\"value\":{}}}",
+                json_string(&timefmt::machine(row.start)),
+                json_number(row.value)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"query\":{},\"unit\":{},\"rows\":[{}]}}",
+        json_string(&query.to_string()),
+        json_string(query.aggregate.unit().symbol()),
+        rows.join(",")
+    )
+}
+
+/// Energy of the `--track` names over the run, by name.
+pub fn watchlist(watchlist: &Watchlist, options: &TextOptions) -> String {
+    let seconds = watchlist.duration.as_secs_f64();
+    let rows = watchlist
+        .joules
+        .iter()
+        .map(|(name, joules)| {
+            vec![
+                name.clone(),
+                text_quantity(*joules, Unit::Joules, options),
+                text_quantity(joules / seconds, Unit::Watts, options),
+                text_quantity(joules / watchlist.total * 100.0, Unit::Percent, options),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut out = format!(
+        "{}\n",
+        trf(
+            "Estimated energy of tracked processes over {}",
+            &[&text_quantity(seconds, Unit::Seconds, options)]
+        )
+    );
+    out.push_str(&table(
+        &[tr("Process"), tr("Energy"), tr("Power"), tr("Share")],
+        &rows,
+        1,
+        options,
+    ));
+    out
+}
+
+pub fn watchlist_json(watchlist: &Watchlist) -> String {
+    let processes = watchlist
+        .joules
+        .iter()
+        .map(|(name, joules)| {
+            format!(
+                "{{\"name\":{},\"joules\":{},\"mean_watts\":{}}}",
+                json_string(name),
+                json_number(*joules),
+                json_number(joules / watchlist.duration.as_secs_f64())
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"tracked\":[{}],\"package_joules\":{},\"duration_seconds\":{}}}",
+        processes.join(","),
+        json_number(watchlist.total),
+        json_number(watchlist.duration.as_secs_f64())
+    )
+}
+
+/// Energy of the run by what `--breakdown` broke it down by, most first.
+pub fn breakdown(breakdown: &Breakdown, options: &TextOptions) -> String {
+    let seconds = breakdown.duration.as_secs_f64();
+    let (title, columns, none) = match breakdown.kind {
+        breakdown::Kind::Container => (
+            "Estimated energy of containers over {}",
+            &["Container", "Image"][..],
+            "no container used any CPU time",
+        ),
+        breakdown::Kind::Unit => (
+            "Estimated energy of systemd units over {}",
+            &["Unit", "Slice"][..],
+            "no unit used any CPU time",
+        ),
+        breakdown::Kind::Vm => (
+            "Estimated energy of virtual machines over {}",
+            &["VM"][..],
+            "no virtual machine used any CPU time",
+        ),
+    };
+    let rows = breakdown
+        .ranked()
+        .into_iter()
+        .map(|(owner, joules)| {
+            let mut row = vec![owner.name.clone()];
+            if breakdown.kind.detail().is_some() {
+                row.push(owner.detail.clone().unwrap_or_default());
+            }
+            row.extend([
+                text_quantity(joules, Unit::Joules, options),
+                text_quantity(joules / seconds, Unit::Watts, options),
+                text_quantity(joules / breakdown.total * 100.0, Unit::Percent, options),
+            ]);
+            row
+        })
+        .collect::<Vec<_>>();
+    let mut out = format!(
+        "{}\n",
+        trf(title, &[&text_quantity(seconds, Unit::Seconds, options)])
+    );
+    if rows.is_empty() {
+        writeln!(out, "  {}", tr(none)).unwrap();
+        return out;
+    }
+    let mut headers = columns.iter().map(|column| tr(column)).collect::<Vec<_>>();
+    headers.extend([tr("Energy"), tr("Power"), tr("Share")]);
+    out.push_str(&table(&headers, &rows, 1, options));
+    out
+}
+
+pub fn breakdown_json(breakdown: &Breakdown) -> String {
+    let owners = breakdown
+        .ranked()
+        .into_iter()
+        .map(|(owner, joules)| {
+            let detail = match (breakdown.kind.detail(), &owner.detail) {
+                (Some(label), Some(detail)) => {
+                    format!(",{}:{}", json_string(label), json_string(detail))
+                }
+                _ => String::new(),
+            };
+            format!(
+                "{{\"name\":{}{},\"joules\":{},\"mean_watts\":{}}}",
+                json_string(&owner.name),
+                detail,
+                json_number(joules),
+                json_number(joules / breakdown.duration.as_secs_f64())
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"breakdown\":{},\"owners\":[{}],\"package_joules\":{},\"duration_seconds\":{}}}",
+        json_string(breakdown.kind.name()),
+        owners.join(","),
+        json_number(breakdown.total),
+        json_number(breakdown.duration.as_secs_f64())
+    )
+}
+
+/// What the package took of the battery's drain, `None` before the battery
+/// updated twice while discharging.
+pub fn battery(drain: &Drain, options: &TextOptions) -> Option<String> {
+    let share = drain.share()?;
+    let seconds = drain.duration().as_secs_f64();
+    let (battery, package) = (drain.battery_joules(), drain.package_joules());
+    let row = |name: &str, joules: f64, share: f64| {
+        vec![
+            name.to_owned(),
+            text_quantity(joules, Unit::Joules, options),
+            text_quantity(joules / seconds, Unit::Watts, options),
+            text_quantity(share, Unit::Percent, options),
+        ]
+    };
+    let rows = [
+        row(tr("Battery"), battery, 100.0),
+        row(tr("Package"), package, share),
+        row(tr("Rest"), battery - package, 100.0 - share),
+    ];
+
+    let mut out = format!(
+        "{}\n",
+        trf(
+            "Battery drain over {} in {} updates",
+            &[
+                &text_quantity(seconds, Unit::Seconds, options),
+                &drain.steps.len()
+            ]
+        )
+    );
+    out.push_str(&table(
+        &[tr("Source"), tr("Energy"), tr("Power"), tr("Share")],
+        &rows,
+        1,
+        options,
+    ));
+    match drain.correlation() {
+        Some(correlation) => writeln!(
+            out,
+            "{}",
+            trf(
+                "Correlation of package and battery power: {}",
+                &[&format!("{:.2}", correlation)]
+            )
+        ),
+        None => writeln!(
+            out,
+            "{}",
+            trf(
+                "a correlation needs at least {} battery updates",
+                &[&battery::MIN_STEPS]
+            )
+        ),
+    }
+    .unwrap();
+    Some(out)
+}
+
+pub fn battery_json(drain: &Drain) -> String {
+    format!(
+        concat!(
+            "{{\"battery\":{{\"updates\":{},\"duration_seconds\":{},\"battery_joules\":{},",
+            "\"package_joules\":{},\"share_percent\":{},\"correlation\":{}}}}}"
+        ),
+        drain.steps.len(),
+        json_number(drain.duration().as_secs_f64()),
+        json_number(drain.battery_joules()),
+        json_number(drain.package_joules()),
+        json_number(drain.share().unwrap_or(f64::NAN)),
+        json_number(drain.correlation().unwrap_or(f64::NAN)),
+    )
+}
+
+pub fn experiment_json(cells: &[Cell]) -> String {
+    let rows = cells
+        .iter()
+        .map(|cell| {
+            format!(
+                concat!(
+                    "{{\"workload\":{},\"setting\":{},\"runs\":{},\"failures\":{},",
+                    "\"energy_joules\":{},\"wall_time_seconds\":{},\"average_package_watts\":{}}}"
+                ),
+                json_string(&cell.workload),
+                json_string(&cell.setting),
+                cell.energy.count,
+                cell.failures,
+                json_stats(&cell.energy),
+                json_stats(&cell.wall_time),
+                json_stats(&cell.average_power),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let comparisons = experiment::compare(cells)
+        .into_iter()
+        .map(|comparison| {
+            format!(
+                concat!(
+                    "{{\"workload\":{},\"baseline\":{},\"setting\":{},",
+                    "\"energy_joules\":{},\"wall_time_seconds\":{}}}"
+                ),
+                json_string(&comparison.workload),
+                json_string(&comparison.baseline),
+                json_string(&comparison.setting),
+                json_difference(comparison.energy),
+                json_difference(comparison.wall_time),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!("{{\"cells\":[{}],\"comparisons\":[{}]}}", rows, comparisons)
+}
+
+/// GitHub Actions `::notice` workflow command summarizing a run. CI logs are
+/// read by everyone on the project, so like the other machine oriented
+/// formats this is not translated.
+pub fn gha_notice(command: &[String], report: &Report) -> String {
+    format!(
+        "::notice title={}::{:.2} J in {:.2} s, {:.2} W average, {:.2} W peak package power",
+        gha_escape_property(&format!("Energy: {}", command.join(" "))),
+        report.energy,
+        report.wall_time.as_secs_f64(),
+        report.average_power,
+        report.peak_power,
+    )
+}
+
+/// Markdown section for the GitHub Actions job summary.
+pub fn gha_summary(command: &[String], report: &Report) -> String {
+    let exit_status = report
+        .status
+        .code()
+        .map_or_else(|| "killed by a signal".to_owned(), |code| code.to_string());
+
+    format!(
+        concat!(
+            "### Energy: `{}`\n\n",
+            "| Metric | Value |\n",
+            "|---|---:|\n",
+            "| Wall time | {:.2} s |\n",
+            "| Energy | {:.2} J |\n",
+            "| Average package power | {:.2} W |\n",
+            "| Peak package power | {:.2} W |\n",
+            "| Exit status | {} |\n\n",
+        ),
+        command.join(" ").replace('`', "'"),
+        report.wall_time.as_secs_f64(),
+        report.energy,
+        report.average_power,
+        report.peak_power,
+        exit_status,
+    )
+}
+
+/// Appends [`gha_summary`] to the job summary, if running in GitHub Actions.
+pub fn append_gha_summary(command: &[String], report: &Report) -> io::Result<()> {
+    let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") else {
+        return Ok(());
+    };
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(gha_summary(command, report).as_bytes())
+}
+
+fn gha_escape_property(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+/// Quotes `s` as a JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Appends samples as CSV rows, one column per physical core.
+#[derive(Debug)]
+pub struct CsvLog {
+    path: PathBuf,
+    file: File,
+    /// Empty until the header has been written.
+    columns: Vec<String>,
+}
+
+impl CsvLog {
+    /// Opens `path` for appending. An existing header is kept, so later rows
+    /// line up with the columns already in the file.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(path)?;
+
+        let mut header = String::new();
+        // Not forever on something like /dev/full.
+        io::BufReader::new(&file)
+            .take(1 << 16)
+            .read_line(&mut header)?;
+        let columns = csv_fields(header.trim_end_matches(['\r', '\n']))
+            .into_iter()
+            .skip(1)
+            .collect();
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            columns,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, sample: &Sample) -> io::Result<()> {
+        let values = METRICS
+            .iter()
+            .flat_map(|metric| {
+                metric
+                    .values(sample)
+                    .into_iter()
+                    .map(move |(label, value)| {
+                        (metric.column_name(&label), machine_value(metric, value))
+                    })
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut out = String::new();
+
+        // Kept only once the header is written, a failed write leaves it
+        // for the next row.
+        let mut columns = None;
+        if self.columns.is_empty() {
+            let names = METRICS
+                .iter()
+                .flat_map(|metric| {
+                    metric
+                        .values(sample)
+                        .into_iter()
+                        .map(|(label, _)| metric.column_name(&label))
+                })
+                .collect::<Vec<_>>();
+            out.push_str("timestamp");
+            for column in &names {
+                out.push(',');
+                out.push_str(&csv_field(column));
+            }
+            out.push('\n');
+            columns = Some(names);
+        }
+
+        out.push_str(&csv_field(&timefmt::machine(sample.timestamp)));
+        for column in columns.as_ref().unwrap_or(&self.columns) {
+            out.push(',');
+            if let Some(value) = values.get(column) {
+                out.push_str(&csv_field(value));
+            }
+        }
+        out.push('\n');
+
+        self.file.write_all(out.as_bytes())?;
+        if let Some(columns) = columns {
+            self.columns = columns;
+        }
+        Ok(())
+    }
+}
+
+/// Quotes `s` for CSV when it needs it, as in RFC 4180. Group names and
+/// `--time-format` can put anything in a field.
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Splits one CSV line into its fields, undoing [`csv_field`].
+pub fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+
+    fields
+}
+
+/// JSON has no representation for NaN or infinity, those become `null`.
+pub fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:.3}", value)
+    } else {
+        "null".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(name: &str) -> &'static Metric {
+        METRICS.iter().find(|metric| metric.name == name).unwrap()
+    }
+
+    fn values(values: &[(u32, f64)]) -> Vec<(String, f64)> {
+        values
+            .iter()
+            .map(|(core, value)| (core.to_string(), *value))
+            .collect()
+    }
+
+    fn labels(values: &[(String, f64)]) -> Vec<&str> {
+        values.iter().map(|(label, _)| label.as_str()).collect()
+    }
+
+    #[test]
+    fn sorts_and_cuts_cores_by_power() {
+        let power = values(&[(0, 3.0), (1, 9.0), (2, 1.0), (3, 9.0)]);
+        let frequency = values(&[(0, 3600.0), (1, 4900.0), (2, 2200.0), (3, 4800.0)]);
+        let view = View {
+            sort: CoreSort::Power,
+            top: Some(3),
+            ..View::default()
+        };
+
+        let cores = view.select(metric("cores_watts"), power.clone(), &power);
+        assert_eq!(labels(&cores), ["1", "3", "0"]);
+        let cores = view.select(metric("frequency_mhz"), frequency, &power);
+        assert_eq!(labels(&cores), ["1", "3", "0"]);
+        // Only per-core metrics are cut.
+        let package = vec![(String::new(), 20.0)];
+        assert_eq!(
+            view.select(metric("package_watts"), package, &power).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn filters_cores_and_columns() {
+        let power = values(&[(0, 3.0), (4, 9.0), (5, 1.0), (8, 2.0)]);
+        let view = View {
+            cores: topology::parse_cpulist("0,4-7"),
+            columns: Some(View::parse_columns("cores_watts").unwrap()),
+            ..View::default()
+        };
+
+        let cores = view.select(metric("cores_watts"), power.clone(), &power);
+        assert_eq!(labels(&cores), ["0", "4", "5"]);
+        assert!(!view.shows(metric("package_watts")));
+        // Without per-core power, e.g. on Intel, cores are filtered as is.
+        let perf = values(&[(0, 166.0), (8, 171.0)]);
+        let view = View {
+            columns: None,
+            ..view
+        };
+        assert_eq!(
+            labels(&view.select(metric("highest_perf"), perf, &[])),
+            ["0"]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        assert_eq!(
+            View::parse_columns("package_watts, cores_watts").unwrap(),
+            ["package_watts", "cores_watts"]
+        );
+        assert!(View::parse_columns("watts")
+            .unwrap_err()
+            .contains("`watts`"));
+        assert_eq!("power".parse::<CoreSort>().unwrap(), CoreSort::Power);
+    }
+
+    #[test]
+    fn leaves_out_what_was_read_too_far_apart() {
+        let mut sample = Sample {
+            utilization: Some(0.5),
+            temperatures: BTreeMap::from([("tctl".to_owned(), 61.0)]),
+            skew: BTreeMap::from([
+                (Source::Utilization, Duration::from_micros(300)),
+                (Source::Temperature, Duration::from_millis(40)),
+            ]),
+            ..Sample::fixture()
+        };
+        assert_eq!(
+            sample.drop_skewed(Duration::from_millis(5)),
+            [Source::Temperature]
+        );
+        assert_eq!(sample.utilization, Some(0.5));
+        assert!(sample.temperatures.is_empty());
+        assert!(json(&sample)
+            .ends_with(r#""skew_seconds":{"utilization":0.000300,"temperature":0.040000}}"#));
+    }
+
+    #[test]
+    fn escapes_json_and_ndjson() {
+        use crate::json::{self, Value};
+
+        let name = "games \"big\"\n\\";
+        let sample = Sample {
+            group_power: BTreeMap::from([(name.to_owned(), 8.0)]),
+            group_uncertainty: BTreeMap::from([(name.to_owned(), 0.5)]),
+            ..Sample::fixture()
+        };
+
+        let value = json::parse(&json(&sample)).unwrap();
+        assert_eq!(value.get("package_watts"), Some(&Value::Number(42.5)));
+        assert_eq!(
+            value.get("cores_watts").and_then(|cores| cores.get("1")),
+            Some(&Value::Number(2.5))
+        );
+        assert_eq!(
+            value
+                .get("groups_watts")
+                .and_then(|groups| groups.get(name)),
+            Some(&Value::Number(8.0))
+        );
+        assert_eq!(
+            value
+                .get("groups_watts_uncertainty")
+                .and_then(|groups| groups.get(name)),
+            Some(&Value::Number(0.5))
+        );
+        assert_eq!(value.get("backend"), Some(&Value::String("msr".to_owned())));
+        assert_eq!(json_string("\u{1}\t"), "\"\\u0001\\t\"");
+
+        let host = "box \"1\"";
+        let lines = ndjson(&sample, Some(host));
+        let mut groups = 0;
+        for line in lines.lines() {
+            let value = json::parse(line).unwrap();
+            let tags = value.get("tags").unwrap();
+            assert_eq!(tags.get("host"), Some(&Value::String(host.to_owned())));
+            if tags.get("group") == Some(&Value::String(name.to_owned())) {
+                groups += 1;
+                assert_eq!(
+                    value
+                        .get("fields")
+                        .and_then(|fields| fields.get("groups_watts")),
+                    Some(&Value::Number(8.0))
+                );
+            }
+        }
+        assert_eq!(groups, 1);
+
+        for line in ndjson(&sample, None).lines() {
+            let value = json::parse(line).unwrap();
+            assert_eq!(value.get("tags").and_then(|tags| tags.get("host")), None);
+        }
+        assert!(influx(&sample, None).starts_with("ryzen_wattage "));
+    }
+
+    #[test]
+    fn labels_the_power_of_each_container() {
+        let watts = BTreeMap::from([
+            (
+                Owner {
+                    name: "web".to_owned(),
+                    detail: Some("nginx:1.27".to_owned()),
+                },
+                12.5,
+            ),
+            (
+                Owner {
+                    name: "4f1a6c3e9b2d".to_owned(),
+                    detail: None,
+                },
+                0.25,
+            ),
+        ]);
+        let page = prometheus_breakdown(breakdown::Kind::Container, &watts);
+        let lines = page.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "# TYPE ryzen_container_watts gauge");
+        assert_eq!(
+            lines[2],
+            "ryzen_container_watts{container=\"4f1a6c3e9b2d\"} 0.250"
+        );
+        assert_eq!(
+            lines[3],
+            "ryzen_container_watts{container=\"web\",image=\"nginx:1.27\"} 12.500"
+        );
+        assert!(prometheus_breakdown(breakdown::Kind::Container, &BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        for field in ["plain", "a,b", "say \"hi\"", "two\nlines", ""] {
+            assert_eq!(csv_fields(&csv_field(field)), [field]);
+        }
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+
+        let path = std::env::temp_dir().join(format!("ryzen-wattage-csv-{}", std::process::id()));
+        let sample = Sample {
+            group_power: BTreeMap::from([("games, \"big\"".to_owned(), 8.0)]),
+            ..Sample::fixture()
+        };
+        CsvLog::open(&path).unwrap().append(&sample).unwrap();
+        // Reopened, the quoted header has to line up again.
+        let mut log = CsvLog::open(&path).unwrap();
+        let columns = log.columns.clone();
+        log.append(&sample).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        let header = csv_fields(lines[0]);
+        assert_eq!(header[1..], columns[..]);
+        assert!(header
+            .iter()
+            .any(|column| column.contains("games, \"big\"")));
+        assert_eq!(
+            lines[1][lines[1].find(',').unwrap()..],
+            lines[2][lines[2].find(',').unwrap()..]
+        );
+        assert_eq!(csv_fields(lines[2]).len(), header.len());
+    }
+}
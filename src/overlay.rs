@@ -0,0 +1,111 @@
+//! Live power for game and stream overlays.
+//!
+//! `--mangohud` keeps a line of text in a file, which MangoHud shows next
+//! to the frame rate with `exec=cat FILE` in its config, rereading it as
+//! often as the rest of its values.
+//!
+//! The exporter serves [`PAGE`] on `/overlay` for an OBS browser source:
+//! large white numbers on a transparent background, which OBS's custom CSS
+//! can restyle, fetching [`json`] from `/overlay.json` every
+//! [`REFRESH_MS`].
+
+use crate::output::{json_number, Sample};
+
+/// How often the page fetches new numbers.
+pub const REFRESH_MS: u32 = 1000;
+
+/// The page of `/overlay`, with `REFRESH_MS` still to fill in.
+const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>ryzen-wattage</title>
+<style>
+html, body { margin: 0; background: transparent; }
+#power { padding: 8px; font: bold 48px system-ui, sans-serif; color: #fff;
+  text-shadow: 0 0 4px #000, 0 0 8px #000; }
+#cores { display: block; font-size: 24px; font-weight: normal; }
+</style>
+</head>
+<body>
+<div id="power">– W<span id="cores"></span></div>
+<script>
+const watts = (value) => value === null ? "– W" : value.toFixed(1) + " W";
+async function update() {
+  const power = document.getElementById("power");
+  const cores = document.getElementById("cores");
+  try {
+    const latest = await (await fetch("/overlay.json", { cache: "no-store" })).json();
+    power.firstChild.nodeValue = watts(latest.package_watts);
+    cores.textContent = latest.cores_watts === null ? "" : "cores " + watts(latest.cores_watts);
+  } catch (err) {
+    power.firstChild.nodeValue = watts(null);
+    cores.textContent = "";
+  }
+  setTimeout(update, REFRESH_MS);
+}
+update();
+</script>
+</body>
+</html>
+"#;
+
+/// The page of `/overlay`.
+pub fn page() -> String {
+    TEMPLATE.replace("REFRESH_MS", &REFRESH_MS.to_string())
+}
+
+/// What the page shows, `null` before the first sample and for the cores
+/// without per-core counters.
+pub fn json(sample: Option<&Sample>) -> String {
+    let package = sample.map_or(f64::NAN, |sample| sample.package_power);
+    let cores = sample
+        .filter(|sample| !sample.core_power.is_empty())
+        .map_or(f64::NAN, |sample| sample.cores_total_power);
+    format!(
+        "{{\"package_watts\":{},\"cores_watts\":{}}}",
+        json_number(package),
+        json_number(cores)
+    )
+}
+
+/// Package power and, with per-core counters, the cores' sum, short enough
+/// for a column of the overlay, like `65.2 W, cores 40.1 W`.
+pub fn mangohud(sample: &Sample) -> String {
+    let mut line = format!("{:.1} W", sample.package_power);
+    if !sample.core_power.is_empty() {
+        line += &format!(", cores {:.1} W", sample.cores_total_power);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mangohud_line() {
+        let mut sample = Sample {
+            package_power: 65.24,
+            cores_total_power: 40.06,
+            ..Sample::default()
+        };
+        assert_eq!(mangohud(&sample), "65.2 W");
+        sample.core_power.insert(0, 40.06);
+        assert_eq!(mangohud(&sample), "65.2 W, cores 40.1 W");
+    }
+
+    #[test]
+    fn page_numbers() {
+        assert_eq!(json(None), "{\"package_watts\":null,\"cores_watts\":null}");
+        let sample = Sample {
+            package_power: 65.0,
+            ..Sample::default()
+        };
+        assert_eq!(
+            json(Some(&sample)),
+            "{\"package_watts\":65.000,\"cores_watts\":null}"
+        );
+        assert!(page().contains("setTimeout(update, 1000)"));
+    }
+}
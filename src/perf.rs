@@ -0,0 +1,546 @@
+//! Power samples lined up with a `perf record` session, for `perf sidecar`:
+//! the samples of a recorded session that overlap the perf.data file, as
+//! CSV with times on perf's clock, like `perf script` prints them. And for
+//! `perf fold`, the stacks `perf script` prints weighted by the energy of
+//! the cores they were sampled on, as folded stacks for a flamegraph.
+//!
+//! perf stamps its samples with a clock of its own. Only with a clock chosen
+//! by `perf record -k` does it write down what the wall clock said at one of
+//! its times, in the `CLOCK_DATA` feature of the header, which is what puts
+//! the two side by side. The session is the first to last sample of
+//! `SAMPLE_TIME`, which `--timestamp-boundary` writes, or else from the
+//! clock data, taken as recording starts, to when the file was last written.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufRead, Read, Seek, SeekFrom},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Error, Result},
+    metrics::METRICS,
+    output::{csv_field, Sample},
+};
+
+const MAGIC: &[u8; 8] = b"PERFILE2";
+/// Of the header of a perf.data file written to a file, not a pipe.
+const HEADER_SIZE: u64 = 104;
+const SAMPLE_TIME: usize = 21;
+const CLOCK_DATA: usize = 29;
+
+/// The times of a `perf record` session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recording {
+    /// On perf's clock, in nanoseconds.
+    pub first: u64,
+    pub last: u64,
+    /// Nanoseconds on perf's clock when the wall clock said `wall`.
+    clock: u64,
+    wall: SystemTime,
+}
+
+impl Recording {
+    /// Reads the header of the perf.data file at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut file = File::open(path).map_err(|err| Error::io(path, err))?;
+        let written = file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| Error::io(path, err))?;
+        Self::parse(&mut file, written).map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidData => Error::config(path, err.to_string()),
+            _ => Error::io(path, err),
+        })
+    }
+
+    /// Reads a perf.data header from `file`, last written at `written`.
+    fn parse(file: &mut (impl Read + Seek), written: SystemTime) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut header = [0; HEADER_SIZE as usize];
+        file.read_exact(&mut header[..16])
+            .map_err(|_| invalid("not a perf.data file"))?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a perf.data file, or of another byte order"));
+        }
+        if u64_at(&header, 8) != HEADER_SIZE {
+            return Err(invalid(
+                "perf.data written to a pipe has no times, record it to a file",
+            ));
+        }
+        file.read_exact(&mut header[16..])?;
+
+        // One section after the data for every feature, in order.
+        let sections = u64_at(&header, 40) + u64_at(&header, 48);
+        let features = (0..256)
+            .filter(|feature| header[72 + feature / 8] & (1 << (feature % 8)) != 0)
+            .collect::<Vec<_>>();
+        let mut section = |feature: usize| -> io::Result<Option<Vec<u8>>> {
+            let Some(index) = features.iter().position(|&set| set == feature) else {
+                return Ok(None);
+            };
+            let mut entry = [0; 16];
+            file.seek(SeekFrom::Start(sections + 16 * index as u64))?;
+            file.read_exact(&mut entry)?;
+            let mut contents = vec![0; u64_at(&entry, 8).min(64) as usize];
+            file.seek(SeekFrom::Start(u64_at(&entry, 0)))?;
+            file.read_exact(&mut contents)?;
+            Ok(Some(contents))
+        };
+
+        // A u32 version and clock id, then the wall clock and the clock,
+        // both in nanoseconds.
+        let (clock, wall) = match section(CLOCK_DATA)? {
+            Some(data) if data.len() >= 24 => (
+                u64_at(&data, 16),
+                UNIX_EPOCH + Duration::from_nanos(u64_at(&data, 8)),
+            ),
+            _ => {
+                return Err(invalid(
+                    "no clock to line up with, record with `perf record -k mono`",
+                ))
+            }
+        };
+        let mut recording = Self {
+            first: clock,
+            last: clock,
+            clock,
+            wall,
+        };
+        recording.last = recording.perf_time(written);
+        if let Some(times) = section(SAMPLE_TIME)?.filter(|times| times.len() >= 16) {
+            recording.first = u64_at(&times, 0);
+            recording.last = u64_at(&times, 8);
+        }
+        Ok(recording)
+    }
+
+    /// Nanoseconds on perf's clock at `time` on the wall clock.
+    pub fn perf_time(&self, time: SystemTime) -> u64 {
+        match time.duration_since(self.wall) {
+            Ok(after) => self.clock + after.as_nanos() as u64,
+            Err(before) => self
+                .clock
+                .saturating_sub(before.duration().as_nanos() as u64),
+        }
+    }
+
+    /// The CSV of the `samples` whose windows overlap the recording, with
+    /// their windows' start and end on perf's clock.
+    pub fn sidecar(&self, samples: &[Sample]) -> String {
+        let windows = samples
+            .iter()
+            .map(|sample| {
+                let end = self.perf_time(sample.timestamp);
+                (
+                    end.saturating_sub(sample.window.as_nanos() as u64),
+                    end,
+                    sample,
+                )
+            })
+            .filter(|&(start, end, _)| end > self.first && start < self.last)
+            .collect::<Vec<_>>();
+        let columns = |sample: &Sample| {
+            METRICS
+                .iter()
+                .flat_map(|metric| {
+                    let (_, precision) = metric.unit.precision();
+                    metric
+                        .values(sample)
+                        .into_iter()
+                        .map(move |(label, value)| {
+                            (
+                                metric.column_name(&label),
+                                format!("{:.*}", precision, value),
+                            )
+                        })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut out = String::from("start,end");
+        let names = windows
+            .first()
+            .map(|&(_, _, sample)| columns(sample))
+            .unwrap_or_default();
+        for (name, _) in &names {
+            out.push(',');
+            out.push_str(&csv_field(name));
+        }
+        out.push('\n');
+        for (start, end, sample) in windows {
+            let values = columns(sample);
+            out.push_str(&format!("{},{}", seconds(start), seconds(end)));
+            for (name, _) in &names {
+                out.push(',');
+                if let Some((_, value)) = values.iter().find(|(column, _)| column == name) {
+                    out.push_str(value);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// The stacks of `perf script`, with the CPU and time each was sampled at.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Script {
+    /// Folded, outermost frame first after the command, like
+    /// `cargo;main;build`.
+    pub stacks: Vec<String>,
+    /// Nanoseconds on perf's clock, CPU and index into `stacks`.
+    pub samples: Vec<(u64, u32, usize)>,
+}
+
+impl Script {
+    /// Reads what `perf script` prints: a line of command, PID, `[CPU]`
+    /// and time for every sample, its stack below it innermost frame
+    /// first.
+    pub fn read(input: impl BufRead) -> io::Result<Self> {
+        let mut script = Self::default();
+        let mut indices = HashMap::new();
+        let mut sample = None;
+        let mut frames = Vec::new();
+        for line in input.lines().chain([Ok(String::new())]) {
+            let line = line?;
+            let frame = line.trim();
+            if !line.starts_with(char::is_whitespace) && !frame.is_empty() {
+                let invalid = || {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "no CPU and time in `{}`, print them with `perf script -F \
+                             comm,tid,cpu,time,ip,sym` of a `perf record -a`",
+                            line
+                        ),
+                    )
+                };
+                sample = Some(header(&line).ok_or_else(invalid)?);
+                continue;
+            }
+            if !frame.is_empty() {
+                frames.push(symbol(frame));
+                continue;
+            }
+            let Some((comm, time, cpu)) = sample.take() else {
+                continue;
+            };
+            let mut stack = comm;
+            for frame in frames.drain(..).rev() {
+                stack.push(';');
+                stack.push_str(&frame);
+            }
+            let index = *indices.entry(stack).or_insert_with_key(|stack: &String| {
+                script.stacks.push(stack.clone());
+                script.stacks.len() - 1
+            });
+            script.samples.push((time, cpu, index));
+        }
+        Ok(script)
+    }
+}
+
+/// The command, time and CPU of a sample's line, like
+/// `cargo 4711/4712 [003] 12345.678901: cycles:`.
+fn header(line: &str) -> Option<(String, u64, u32)> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let at = fields
+        .iter()
+        .position(|field| field.starts_with('[') && field.ends_with(']'))?;
+    let cpu = fields[at][1..fields[at].len() - 1].parse().ok()?;
+    let (seconds, micros) = fields.get(at + 1)?.strip_suffix(':')?.split_once('.')?;
+    let micros = format!("{:0<9}", micros);
+    let time = seconds.parse::<u64>().ok()? * 1_000_000_000 + micros.parse::<u64>().ok()?;
+    // The PID before the CPU, the command with its spaces before that.
+    let comm = fields[..at.checked_sub(1)?].join(" ");
+    Some((comm.replace(';', ":"), time, cpu))
+}
+
+/// A frame's symbol, like `main` of `55d0c2 main+0x1c (/usr/bin/cargo)`, or
+/// its address if perf didn't know it.
+fn symbol(frame: &str) -> String {
+    let mut fields = frame.splitn(2, char::is_whitespace);
+    let address = fields.next().unwrap_or_default();
+    let symbol = fields.next().unwrap_or_default().trim();
+    let symbol = match symbol.rfind(" (") {
+        Some(dso) if symbol.ends_with(')') => &symbol[..dso],
+        _ => symbol,
+    };
+    let symbol = match symbol.rsplit_once("+0x") {
+        Some((name, offset)) if offset.chars().all(|c| c.is_ascii_hexdigit()) => name,
+        _ => symbol,
+    };
+    match symbol {
+        "" | "[unknown]" => format!("[{}]", address),
+        symbol => symbol.replace(';', ":"),
+    }
+}
+
+impl Recording {
+    /// The joules of each stack of `script`. The energy of every core over
+    /// a window of `samples` goes in equal parts to the stacks sampled on
+    /// it within the window, the package's to every stack without per-core
+    /// readings. CPUs are mapped to the recorded `cores` the way Linux
+    /// numbers Ryzen threads, the second ones after all first ones.
+    pub fn fold(
+        &self,
+        samples: &[Sample],
+        script: &Script,
+        cores: &[u32],
+    ) -> BTreeMap<String, f64> {
+        let core = |cpu: u32| {
+            let sibling = cpu % cores.len().max(1) as u32;
+            [cpu, sibling].into_iter().find(|core| cores.contains(core))
+        };
+        let mut stacks = script.samples.clone();
+        stacks.sort_unstable();
+
+        let mut joules = BTreeMap::new();
+        for sample in samples {
+            let end = self.perf_time(sample.timestamp);
+            let start = end.saturating_sub(sample.window.as_nanos() as u64);
+            let from = stacks.partition_point(|&(time, ..)| time <= start);
+            let to = stacks.partition_point(|&(time, ..)| time <= end);
+            let window = &stacks[from..to];
+            let seconds = sample.window.as_secs_f64();
+
+            // Stacks per core, all of them under `None` without per-core
+            // readings.
+            let per_core = !sample.core_power.is_empty();
+            let mut on = BTreeMap::<Option<u32>, Vec<usize>>::new();
+            for &(_, cpu, stack) in window {
+                let core = match per_core {
+                    true => match core(cpu) {
+                        Some(core) => Some(core),
+                        None => continue,
+                    },
+                    false => None,
+                };
+                on.entry(core).or_default().push(stack);
+            }
+            for (core, stacks) in on {
+                let watts = match core {
+                    Some(core) => sample.core_power.get(&core).copied().unwrap_or_default(),
+                    None => sample.package_power,
+                };
+                let share = watts * seconds / stacks.len() as f64;
+                for stack in stacks {
+                    *joules.entry(script.stacks[stack].clone()).or_default() += share;
+                }
+            }
+        }
+        joules
+    }
+}
+
+/// Folded stacks for `flamegraph.pl --countname uJ` and the like, weighted
+/// by microjoules, leaving out those with less than one.
+pub fn folded(joules: &BTreeMap<String, f64>) -> String {
+    joules
+        .iter()
+        .map(|(stack, joules)| (stack, (joules * 1e6).round() as u64))
+        .filter(|&(_, microjoules)| microjoules > 0)
+        .map(|(stack, microjoules)| format!("{} {}\n", stack, microjoules))
+        .collect()
+}
+
+/// Nanoseconds as `perf script`'s seconds.
+fn seconds(nanos: u64) -> String {
+    format!(
+        "{}.{:06}",
+        nanos / 1_000_000_000,
+        nanos % 1_000_000_000 / 1000
+    )
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::BTreeMap, io::Cursor};
+
+    /// A perf.data header with `features`, in order, their sections after
+    /// 200 bytes of data.
+    fn perf_data(features: &[(usize, Vec<u8>)]) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        for value in [HEADER_SIZE, 0, 0, 0, HEADER_SIZE, 200, 0, 0] {
+            file.extend(value.to_le_bytes());
+        }
+        let mut bitmap = [0u8; 32];
+        for &(feature, _) in features {
+            bitmap[feature / 8] |= 1 << (feature % 8);
+        }
+        file.extend(bitmap);
+        file.resize((HEADER_SIZE + 200) as usize, 0);
+
+        let mut offset = file.len() as u64 + 16 * features.len() as u64;
+        for (_, contents) in features {
+            file.extend(offset.to_le_bytes());
+            file.extend((contents.len() as u64).to_le_bytes());
+            offset += contents.len() as u64;
+        }
+        for (_, contents) in features {
+            file.extend(contents);
+        }
+        file
+    }
+
+    fn clock_data(wall: u64, clock: u64) -> Vec<u8> {
+        let mut data = 1u32.to_le_bytes().to_vec();
+        data.extend(1u32.to_le_bytes());
+        data.extend(wall.to_le_bytes());
+        data.extend(clock.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn lines_perf_times_up_with_the_wall_clock() {
+        let wall = 1_700_000_000_000_000_000;
+        let start = UNIX_EPOCH + Duration::from_nanos(wall);
+        let written = start + Duration::from_secs(30);
+
+        let mut times = 5_000_000_000u64.to_le_bytes().to_vec();
+        times.extend(12_000_000_000u64.to_le_bytes());
+        let file = perf_data(&[
+            (SAMPLE_TIME, times),
+            (CLOCK_DATA, clock_data(wall, 4_000_000_000)),
+        ]);
+        let recording = Recording::parse(&mut Cursor::new(file), written).unwrap();
+        assert_eq!(
+            (recording.first, recording.last),
+            (5_000_000_000, 12_000_000_000)
+        );
+        assert_eq!(
+            recording.perf_time(start + Duration::from_millis(1500)),
+            5_500_000_000
+        );
+
+        // Without sample times, from the clock data to the last write.
+        let file = perf_data(&[(CLOCK_DATA, clock_data(wall, 4_000_000_000))]);
+        let recording = Recording::parse(&mut Cursor::new(file), written).unwrap();
+        assert_eq!(
+            (recording.first, recording.last),
+            (4_000_000_000, 34_000_000_000)
+        );
+
+        let err = Recording::parse(&mut Cursor::new(perf_data(&[])), written).unwrap_err();
+        assert!(err.to_string().contains("-k mono"));
+        let mut pipe = MAGIC.to_vec();
+        pipe.extend(16u64.to_le_bytes());
+        let err = Recording::parse(&mut Cursor::new(pipe), written).unwrap_err();
+        assert!(err.to_string().contains("pipe"));
+        assert!(Recording::parse(&mut Cursor::new(b"#!/bin/sh\n"), written).is_err());
+    }
+
+    #[test]
+    fn writes_the_samples_over_the_recording() {
+        let wall = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let recording = Recording {
+            first: 10_000_000_000,
+            last: 12_500_000_000,
+            clock: 10_000_000_000,
+            wall,
+        };
+        let sample = |end: u64, package_power: f64| Sample {
+            timestamp: wall + Duration::from_secs(end),
+            window: Duration::from_secs(1),
+            package_power,
+            core_power: BTreeMap::from([(0, package_power / 4.0)]),
+            cores_total_power: package_power / 4.0,
+            ..Sample::default()
+        };
+        let samples = [
+            sample(0, 10.0),
+            sample(1, 20.0),
+            sample(3, 30.0),
+            sample(4, 40.0),
+        ];
+        assert_eq!(
+            recording.sidecar(&samples),
+            "start,end,package_watts,core0_watts,cores_total_watts\n\
+             10.000000,11.000000,20.000,5.000,5.000\n\
+             12.000000,13.000000,30.000,7.500,7.500\n"
+        );
+    }
+
+    #[test]
+    fn reads_the_stacks_perf_script_prints() {
+        let script = "\
+Web Content 4711/4712 [003] 12.000500: 250000 cycles:P:
+\t    55d0c2 render+0x1c (/usr/lib/firefox/libxul.so)
+\t    55a000 main (/usr/lib/firefox/firefox)
+
+cargo 99 [000] 12.25:
+\t    7f00aa [unknown] ([unknown])
+\t    55d0c2 std::rt::lang_start+0x2 (/usr/bin/cargo)
+
+Web Content 4711/4712 [011] 13.000000:
+\t    55d0c2 render+0x1c (/usr/lib/firefox/libxul.so)
+\t    55a000 main (/usr/lib/firefox/firefox)
+";
+        let script = Script::read(script.as_bytes()).unwrap();
+        assert_eq!(
+            script.stacks,
+            [
+                "Web Content;main;render",
+                "cargo;std::rt::lang_start;[7f00aa]"
+            ]
+        );
+        assert_eq!(
+            script.samples,
+            [
+                (12_000_500_000, 3, 0),
+                (12_250_000_000, 0, 1),
+                (13_000_000_000, 11, 0)
+            ]
+        );
+
+        let err = Script::read("cargo 99 12.25: cycles:\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("-F comm,tid,cpu,time"));
+    }
+
+    #[test]
+    fn weighs_stacks_by_the_energy_of_their_cores() {
+        let wall = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let recording = Recording {
+            first: 0,
+            last: 10_000_000_000,
+            clock: 0,
+            wall,
+        };
+        let script = Script {
+            stacks: vec!["a;hot".to_owned(), "a;cold".to_owned()],
+            samples: vec![
+                (100_000_000, 0, 0),
+                (200_000_000, 0, 0),
+                (300_000_000, 0, 1),
+                // The sibling of core 1 on a machine with two cores.
+                (400_000_000, 3, 1),
+                (1_500_000_000, 1, 0),
+            ],
+        };
+        let sample = |end: u64, core_power: BTreeMap<u32, f64>| Sample {
+            timestamp: wall + Duration::from_secs(end),
+            window: Duration::from_secs(1),
+            package_power: 20.0,
+            core_power,
+            ..Sample::default()
+        };
+        let samples = [
+            sample(1, BTreeMap::from([(0, 6.0), (1, 2.0)])),
+            sample(2, BTreeMap::new()),
+        ];
+        let joules = recording.fold(&samples, &script, &[0, 1]);
+        assert_eq!(
+            joules,
+            BTreeMap::from([("a;cold".to_owned(), 4.0), ("a;hot".to_owned(), 24.0)])
+        );
+        assert_eq!(folded(&joules), "a;cold 4000000\na;hot 24000000\n");
+    }
+}
@@ -0,0 +1,274 @@
+//! Privileged one-shot operations through polkit, so changing a few CPU
+//! settings doesn't mean running the whole tool under sudo.
+//!
+//! Writes this process isn't allowed to do are handed to `pkexec`, which runs
+//! this binary again as root with the [`HELPER_COMMAND`] and asks the user's
+//! polkit agent for authorization. Installing
+//! `data/io.github.valeth.ryzen-wattage.policy` to
+//! `/usr/share/polkit-1/actions` gives the prompt its own action and message,
+//! without it pkexec falls back to its generic one.
+//!
+//! Every write is recorded in the [`crate::audit`] log first.
+//!
+//! These are the only writes to the hardware's settings, the governor, SMT
+//! and the package power limit of `limit set`; the energy counters are read
+//! from files opened read-only. `--read-only` refuses
+//! them for the rest of the process with [`forbid_writes`]; built with the
+//! `read-only` feature the code doing them isn't there at all, helper
+//! included.
+
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicBool, Ordering},
+};
+#[cfg(not(feature = "read-only"))]
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+};
+
+#[cfg(not(feature = "read-only"))]
+use crate::audit;
+use crate::{
+    cpuinfo::CpuInfo,
+    guardrail::{self, Limit},
+    sysfs::Root,
+    Error, Result,
+};
+
+/// Action of the bundled policy file.
+pub const ACTION_ID: &str = "io.github.valeth.ryzen-wattage.write-cpu-settings";
+/// Hidden command line of the helper, followed by `PATH=VALUE` pairs.
+pub const HELPER_COMMAND: &str = "privileged-write";
+
+pub const SMT_CONTROL: &str = "/sys/devices/system/cpu/smt/control";
+
+/// pkexec exit code when authorization was denied or the dialog dismissed.
+const PKEXEC_NOT_AUTHORIZED: i32 = 126;
+/// pkexec exit code when the user couldn't authenticate.
+const PKEXEC_AUTH_FAILED: i32 = 127;
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Refuses every write for the rest of the process.
+pub fn forbid_writes() {
+    READ_ONLY.store(true, Ordering::SeqCst);
+}
+
+/// Whether settings can be written, not after [`forbid_writes`] and never
+/// in a read-only build.
+pub fn writable() -> bool {
+    cfg!(not(feature = "read-only")) && !READ_ONLY.load(Ordering::SeqCst)
+}
+
+/// Writes each value to its sysfs file, asking polkit for the ones that need
+/// root.
+pub fn write_sysfs(writes: &[(PathBuf, String)]) -> Result<()> {
+    if let Some((path, _)) = writes.first().filter(|_| !writable()) {
+        return Err(Error::ReadOnly { path: path.clone() });
+    }
+    let mut denied = Vec::new();
+
+    for (path, value) in writes {
+        match write(path, value) {
+            Err(Error::PermissionDenied { .. }) if pkexec_available() => {
+                denied.push((path.clone(), value.clone()))
+            }
+            result => result?,
+        }
+    }
+
+    match denied.is_empty() {
+        true => Ok(()),
+        false => pkexec(&denied),
+    }
+}
+
+#[cfg(feature = "read-only")]
+fn write(path: &Path, _value: &str) -> Result<()> {
+    Err(Error::ReadOnly {
+        path: path.to_owned(),
+    })
+}
+
+#[cfg(not(feature = "read-only"))]
+fn write(path: &Path, value: &str) -> Result<()> {
+    if !writable() {
+        return Err(Error::ReadOnly {
+            path: path.to_owned(),
+        });
+    }
+    let mut log = audit::Log::open()
+        .map_err(|err| Error::io(audit::log_path().unwrap_or_else(|| "audit.log".into()), err))?;
+    let old = fs::read_to_string(path)
+        .ok()
+        .map(|old| old.trim_end().to_owned());
+    let result = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(value.as_bytes()));
+    let error = result.as_ref().err().map(io::Error::to_string);
+    log.record(path, old.as_deref(), value, error.as_deref())
+        .map_err(|err| Error::io(log.path(), err))?;
+    result.map_err(|err| Error::io(path, err))
+}
+
+fn pkexec_available() -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join("pkexec").is_file()))
+}
+
+fn pkexec(writes: &[(PathBuf, String)]) -> Result<()> {
+    let exe = env::current_exe().map_err(|err| Error::io("/proc/self/exe", err))?;
+    let status = Command::new("pkexec")
+        .arg(exe)
+        .arg(HELPER_COMMAND)
+        .args(
+            writes
+                .iter()
+                .map(|(path, value)| format!("{}={}", path.display(), value)),
+        )
+        .status()
+        .map_err(|err| Error::io("pkexec", err))?;
+
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(PKEXEC_NOT_AUTHORIZED | PKEXEC_AUTH_FAILED) => {
+            Err(Error::NotAuthorized { action: ACTION_ID })
+        }
+        // The helper failed and already said why.
+        _ => Err(Error::PermissionDenied {
+            path: writes[0].0.clone(),
+        }),
+    }
+}
+
+/// Entry point of the helper running as root. Only the files experiments
+/// and `limit set` change are writable, and only with values that can be a
+/// governor or SMT state, or a power limit within the bounds of the CPU.
+pub fn run_helper(args: &[String]) -> Result<(), String> {
+    let writes = args
+        .iter()
+        .map(|arg| {
+            let (path, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("expected PATH=VALUE, found `{}`", arg))?;
+            match is_allowed(Path::new(path), value) {
+                true => Ok((PathBuf::from(path), value.to_owned())),
+                false => Err(format!("refusing to write `{}` to {}", value, path)),
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    for (path, value) in writes {
+        write(&path, &value).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+fn is_allowed(path: &Path, value: &str) -> bool {
+    if path == Path::new(SMT_CONTROL) {
+        return matches!(value, "on" | "off");
+    }
+    if is_power_limit_path(path) {
+        let Some(watts) = value
+            .parse::<u64>()
+            .ok()
+            .map(|microwatts| microwatts as f64 / 1e6)
+        else {
+            return false;
+        };
+        let cpu = CpuInfo::read(&Root::system()).unwrap_or_default();
+        return guardrail::within_bounds(&cpu, Limit::PackagePower(watts)).is_ok();
+    }
+
+    let governor = !value.is_empty()
+        && value.len() <= 15
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+    governor && is_governor_path(path)
+}
+
+/// `/sys/devices/system/cpu/cpuN/cpufreq/scaling_governor`, spelled out
+/// without any `..` or symlinks of its own.
+fn is_governor_path(path: &Path) -> bool {
+    let components = path.components().collect::<Vec<_>>();
+    let [Component::RootDir, sys, devices, system, cpu, core, cpufreq, governor] = &components[..]
+    else {
+        return false;
+    };
+    let names = [sys, devices, system, cpu, cpufreq, governor].map(|part| part.as_os_str());
+
+    names
+        == [
+            "sys",
+            "devices",
+            "system",
+            "cpu",
+            "cpufreq",
+            "scaling_governor",
+        ]
+        && core
+            .as_os_str()
+            .to_str()
+            .and_then(|core| core.strip_prefix("cpu"))
+            .is_some_and(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// `/sys/class/powercap/intel-rapl:N/constraint_M_power_limit_uw`.
+fn is_power_limit_path(path: &Path) -> bool {
+    let components = path.components().collect::<Vec<_>>();
+    let [Component::RootDir, sys, class, powercap, zone, constraint] = &components[..] else {
+        return false;
+    };
+    let number =
+        |s: Option<&str>| s.is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    [sys, class, powercap].map(|part| part.as_os_str()) == ["sys", "class", "powercap"]
+        && number(
+            zone.as_os_str()
+                .to_str()
+                .and_then(|zone| zone.strip_prefix("intel-rapl:")),
+        )
+        && number(constraint.as_os_str().to_str().and_then(|file| {
+            file.strip_prefix("constraint_")?
+                .strip_suffix("_power_limit_uw")
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_allows_the_settings_it_changes() {
+        assert!(is_allowed(Path::new(SMT_CONTROL), "off"));
+        assert!(!is_allowed(Path::new(SMT_CONTROL), "forceoff"));
+        assert!(is_allowed(
+            Path::new("/sys/devices/system/cpu/cpu12/cpufreq/scaling_governor"),
+            "powersave"
+        ));
+        assert!(!is_allowed(
+            Path::new("/sys/devices/system/cpu/cpu12/../cpu0/cpufreq/scaling_governor"),
+            "powersave"
+        ));
+        assert!(!is_allowed(Path::new("/etc/passwd"), "powersave"));
+
+        assert!(is_power_limit_path(Path::new(
+            "/sys/class/powercap/intel-rapl:0/constraint_0_power_limit_uw"
+        )));
+        for path in [
+            "/sys/class/powercap/intel-rapl:0:1/constraint_0_power_limit_uw",
+            "/sys/class/powercap/intel-rapl:0/constraint_0_time_window_us",
+            "/sys/class/powercap/intel-rapl:/constraint_0_power_limit_uw",
+            "/sys/class/powercap/../powercap/intel-rapl:0/constraint_0_power_limit_uw",
+        ] {
+            assert!(!is_power_limit_path(Path::new(path)), "{}", path);
+        }
+        // Never without a CPU model the bounds are known for.
+        let limit = Path::new("/sys/class/powercap/intel-rapl:0/constraint_0_power_limit_uw");
+        assert!(!is_allowed(limit, "garbage"));
+    }
+}
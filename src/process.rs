@@ -0,0 +1,469 @@
+//! Processes from `/proc`, for estimating the energy of a process tree by
+//! its share of the busy CPU time.
+//!
+//! Neither RAPL nor the per-core counters know about processes, so a tree
+//! gets the package energy of a window in proportion to the CPU time it used
+//! in it, out of all CPU time spent busy. Children that exited within the
+//! window still count, their time is in the `cutime` of whoever reaped them.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    time::Duration,
+};
+
+use crate::{sanity::CpuTimes, sched::SchedMeter, Error, Result};
+
+/// Bytes of the executable name the kernel keeps in `comm`.
+const COMM_LEN: usize = 15;
+
+/// `_SC_CLK_TCK` on Linux.
+const SC_CLK_TCK: i32 = 2;
+
+extern "C" {
+    fn sysconf(name: i32) -> i64;
+}
+
+/// Clock ticks per second of the CPU times in `/proc`.
+pub fn ticks_per_second() -> u64 {
+    // SAFETY: sysconf only reads a configuration value.
+    match unsafe { sysconf(SC_CLK_TCK) } {
+        ticks @ 1.. => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// One line of `/proc/PID/stat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    pub ppid: u32,
+    /// `comm`, the executable name cut to 15 bytes.
+    pub name: String,
+    /// User and system time of the process itself, in clock ticks.
+    pub ticks: u64,
+    /// User and system time of its children that exited and were waited
+    /// for.
+    pub children_ticks: u64,
+    /// Clock ticks after boot the process started at, which tells it apart
+    /// from a later one with the same PID.
+    pub start: u64,
+}
+
+impl Process {
+    pub fn read(pid: u32) -> Option<Self> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        Self::parse(pid, &stat)
+    }
+
+    pub(crate) fn parse(pid: u32, stat: &str) -> Option<Self> {
+        // The name can have spaces and parentheses of its own.
+        let (_, rest) = stat.split_once('(')?;
+        let (name, fields) = rest.rsplit_once(')')?;
+        let fields = fields.split_whitespace().collect::<Vec<_>>();
+        // Fields from the state on, which is the third in proc(5).
+        let field = |number: usize| fields.get(number - 3)?.parse::<u64>().ok();
+
+        Some(Self {
+            pid,
+            ppid: field(4)? as u32,
+            name: name.to_owned(),
+            ticks: field(14)? + field(15)?,
+            children_ticks: field(16)? + field(17)?,
+            start: field(22)?,
+        })
+    }
+}
+
+/// The cgroup `pid` is in: the path in the `cpu` controller's hierarchy
+/// with cgroup v1, in the unified one otherwise.
+pub fn cgroup(pid: u32) -> Option<String> {
+    parse_cgroup(&fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?)
+}
+
+pub(crate) fn parse_cgroup(contents: &str) -> Option<String> {
+    let mut unified = None;
+    for line in contents.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if controllers.split(',').any(|controller| controller == "cpu") {
+            return Some(path.to_owned());
+        }
+        if controllers.is_empty() {
+            unified = Some(path.to_owned());
+        }
+    }
+    unified
+}
+
+/// Every process that can be read.
+pub fn all() -> Vec<Process> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(Process::read)
+        .collect()
+}
+
+/// CPU time of the tree rooted at `root` in clock ticks and the processes in
+/// it, `None` if `root` isn't among `processes`.
+pub fn tree_ticks(processes: &[Process], root: u32) -> Option<(u64, usize)> {
+    let mut children = BTreeMap::<u32, Vec<&Process>>::new();
+    for process in processes {
+        children.entry(process.ppid).or_default().push(process);
+    }
+
+    let mut pending = vec![processes.iter().find(|process| process.pid == root)?];
+    let (mut ticks, mut count) = (0, 0);
+    while let Some(process) = pending.pop() {
+        ticks += process.ticks + process.children_ticks;
+        count += 1;
+        pending.extend(children.get(&process.pid).into_iter().flatten());
+    }
+    Some((ticks, count))
+}
+
+/// The tree's share of one window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Share {
+    pub joules: f64,
+    /// Fraction of the busy CPU time, between 0 and 1.
+    pub fraction: f64,
+    pub processes: usize,
+}
+
+/// Keeps the running total of the energy of a process tree.
+#[derive(Debug)]
+pub struct TreeMeter {
+    pub root: Process,
+    /// Energy attributed so far.
+    pub joules: f64,
+    /// CPU time of the tree since the meter started, in clock ticks.
+    pub ticks: u64,
+    last_ticks: u64,
+    last_times: Option<CpuTimes>,
+}
+
+impl TreeMeter {
+    /// Starts counting at the current CPU time of the tree rooted at `pid`.
+    pub fn new(pid: u32) -> Result<Self> {
+        let path = format!("/proc/{}/stat", pid);
+        let root = Process::read(pid)
+            .ok_or_else(|| Error::io(&path, std::io::ErrorKind::NotFound.into()))?;
+        let (last_ticks, _) = tree_ticks(&all(), pid).unwrap_or((0, 1));
+
+        Ok(Self {
+            root,
+            joules: 0.0,
+            ticks: 0,
+            last_ticks,
+            last_times: CpuTimes::read(),
+        })
+    }
+
+    /// Attributes the tree's share of `joules`, the package energy since the
+    /// last call. `None` once the root has exited.
+    pub fn update(&mut self, joules: f64) -> Option<Share> {
+        let processes = all();
+        let root = processes
+            .iter()
+            .find(|process| process.pid == self.root.pid && process.start == self.root.start)?;
+        let (ticks, count) = tree_ticks(&processes, root.pid)?;
+
+        // Children reaped by something outside the tree, e.g. after a
+        // double fork, take their time with them.
+        let used = ticks.saturating_sub(self.last_ticks);
+        self.last_ticks = ticks;
+        let times = CpuTimes::read();
+        let busy = match (&self.last_times, &times) {
+            (Some(before), Some(after)) => before.busy_until(after),
+            _ => None,
+        };
+        self.last_times = times;
+
+        let fraction = match busy {
+            Some(busy) if busy > 0 => (used as f64 / busy as f64).min(1.0),
+            _ => 0.0,
+        };
+        let share = Share {
+            joules: joules * fraction,
+            fraction,
+            processes: count,
+        };
+        self.joules += share.joules;
+        self.ticks += used;
+        Some(share)
+    }
+}
+
+/// Splits windows of energy between every process on the machine, summed
+/// by name, for the [`crate::ledger`].
+///
+/// Only a process's own time counts here, the time of reaped children is
+/// already in their own names.
+#[derive(Debug, Default)]
+pub struct NameMeter {
+    /// CPU time by PID and start time at the last update.
+    last_ticks: HashMap<(u32, u64), u64>,
+    last_times: Option<CpuTimes>,
+    started: bool,
+}
+
+impl NameMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Joules per name out of `joules`, the package energy since the last
+    /// call. The first call only takes the starting point.
+    pub fn update(&mut self, joules: f64) -> BTreeMap<String, f64> {
+        self.split(joules, |process| Some(process.name.clone()))
+    }
+
+    /// [`NameMeter::update`] summed by `key` rather than by name. `key` is
+    /// only asked about processes that used CPU time, those it has none for
+    /// still count towards the whole.
+    pub fn split<K: Ord>(
+        &mut self,
+        joules: f64,
+        mut key: impl FnMut(&Process) -> Option<K>,
+    ) -> BTreeMap<K, f64> {
+        let processes = all();
+        let times = CpuTimes::read();
+        let busy = match (&self.last_times, &times) {
+            (Some(before), Some(after)) => before.busy_until(after),
+            _ => None,
+        };
+        self.last_times = times;
+
+        let mut used = BTreeMap::<K, u64>::new();
+        let mut all_used = 0;
+        let mut ticks = HashMap::with_capacity(processes.len());
+        for process in processes {
+            // Whatever started since the last update used all its time in
+            // this window.
+            let last = match self.last_ticks.get(&(process.pid, process.start)) {
+                Some(&last) => last,
+                None if self.started => 0,
+                None => process.ticks,
+            };
+            let process_used = process.ticks.saturating_sub(last);
+            all_used += process_used;
+            if let Some(key) = (process_used > 0).then(|| key(&process)).flatten() {
+                *used.entry(key).or_default() += process_used;
+            }
+            ticks.insert((process.pid, process.start), process.ticks);
+        }
+        self.last_ticks = ticks;
+        self.started = true;
+
+        let Some(busy) = busy.filter(|&busy| busy > 0) else {
+            return BTreeMap::new();
+        };
+        // Ticks are counted per process and may add up to a bit more than
+        // the machine's, which is never more than all the energy.
+        let total = all_used.max(busy);
+        used.into_iter()
+            .map(|(key, ticks)| (key, joules * ticks as f64 / total as f64))
+            .collect()
+    }
+}
+
+/// How windows of energy are split between processes: the package's by
+/// CPU time, or with `--sched` each core's by what ran on it.
+#[derive(Debug)]
+pub enum Splitter {
+    CpuTime(NameMeter),
+    Sched(SchedMeter),
+}
+
+impl Splitter {
+    /// Starts splitting now, by what ran on each core with `sched`.
+    pub fn new(sched: Option<SchedMeter>) -> Self {
+        match sched {
+            Some(sched) => Self::Sched(sched),
+            None => {
+                let mut meter = NameMeter::new();
+                meter.split(0.0, |_| None::<String>);
+                Self::CpuTime(meter)
+            }
+        }
+    }
+
+    /// Joules per `key` out of `joules` of the package and `core_joules`
+    /// of each core since the last call, see [`NameMeter::split`] and
+    /// [`SchedMeter::split`].
+    pub fn split<K: Ord>(
+        &mut self,
+        joules: f64,
+        core_joules: &BTreeMap<u32, f64>,
+        key: impl FnMut(&Process) -> Option<K>,
+    ) -> BTreeMap<K, f64> {
+        match self {
+            Self::CpuTime(meter) => meter.split(joules, key),
+            Self::Sched(meter) => meter.split(joules, core_joules, key),
+        }
+    }
+}
+
+/// Energy of the processes with a few names over a run, out of what the
+/// [`Splitter`] splits between all of them, for `--track`.
+#[derive(Debug)]
+pub struct Watchlist {
+    meter: Splitter,
+    /// Joules so far of each name on the list.
+    pub joules: BTreeMap<String, f64>,
+    /// Package energy so far.
+    pub total: f64,
+    pub duration: Duration,
+}
+
+impl Watchlist {
+    /// Starts counting the processes named `names` now.
+    pub fn new(names: &[String], meter: Splitter) -> Self {
+        Self {
+            meter,
+            joules: names.iter().map(|name| (name.clone(), 0.0)).collect(),
+            total: 0.0,
+            duration: Duration::ZERO,
+        }
+    }
+
+    /// Adds the shares of `joules`, the package energy of the `window`
+    /// since the last call, and `core_joules`, that of each core.
+    pub fn update(&mut self, joules: f64, core_joules: &BTreeMap<u32, f64>, window: Duration) {
+        let by_name = self
+            .meter
+            .split(joules, core_joules, |process| Some(process.name.clone()));
+        for (name, watched) in &mut self.joules {
+            *watched += by_name
+                .iter()
+                .filter(|(comm, _)| is_named(comm, name))
+                .map(|(_, joules)| joules)
+                .sum::<f64>();
+        }
+        self.total += joules;
+        self.duration += window;
+    }
+}
+
+/// Whether `comm`, which the kernel cuts to [`COMM_LEN`] bytes, is the name
+/// of `name`.
+pub fn is_named(comm: &str, name: &str) -> bool {
+    match name.len() > COMM_LEN {
+        true => name.as_bytes()[..COMM_LEN] == *comm.as_bytes(),
+        false => comm == name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{process, time::Instant};
+
+    fn process(pid: u32, ppid: u32, ticks: u64, children_ticks: u64) -> Process {
+        Process {
+            pid,
+            ppid,
+            name: format!("p{}", pid),
+            ticks,
+            children_ticks,
+            start: 0,
+        }
+    }
+
+    #[test]
+    fn parses_names_with_parentheses() {
+        let stat = "42 (tmux: server (1)) S 1 42 42 0 -1 4194560 530 0 0 0 120 30 5 7 20 0 1 0 \
+                    8123 11816960 1015 18446744073709551615";
+        let process = Process::parse(42, stat).unwrap();
+        assert_eq!(process.name, "tmux: server (1)");
+        assert_eq!(process.ppid, 1);
+        assert_eq!(process.ticks, 150);
+        assert_eq!(process.children_ticks, 12);
+        assert_eq!(process.start, 8123);
+
+        assert!(Process::parse(42, "42 (cut short) S 1").is_none());
+    }
+
+    #[test]
+    fn reads_cgroups_of_both_versions() {
+        assert_eq!(
+            parse_cgroup("0::/system.slice/sshd.service\n").as_deref(),
+            Some("/system.slice/sshd.service")
+        );
+        let hybrid = "12:cpu,cpuacct:/docker/0123\n1:name=systemd:/init.scope\n0::/init.scope\n";
+        assert_eq!(parse_cgroup(hybrid).as_deref(), Some("/docker/0123"));
+        assert_eq!(parse_cgroup("garbage"), None);
+        assert!(cgroup(process::id()).is_some());
+    }
+
+    #[test]
+    fn sums_the_tree_below_the_root() {
+        let processes = [
+            process(1, 0, 1000, 0),
+            process(10, 1, 5, 20),
+            process(11, 10, 7, 0),
+            process(12, 11, 3, 0),
+            process(20, 1, 500, 0),
+        ];
+        assert_eq!(tree_ticks(&processes, 10), Some((35, 3)));
+        assert_eq!(tree_ticks(&processes, 12), Some((3, 1)));
+        assert_eq!(tree_ticks(&processes, 99), None);
+    }
+
+    #[test]
+    fn attributes_this_process() {
+        let mut meter = TreeMeter::new(process::id()).unwrap();
+        let start = Instant::now();
+        while start.elapsed().as_millis() < 100 {
+            std::hint::black_box(start.elapsed());
+        }
+
+        let share = meter.update(10.0).unwrap();
+        assert!((0.0..=1.0).contains(&share.fraction));
+        assert!(share.joules <= 10.0);
+        assert!(share.processes >= 1);
+        assert_eq!(meter.joules, share.joules);
+    }
+
+    #[test]
+    fn splits_by_name() {
+        let mut meter = NameMeter::new();
+        assert!(meter.update(10.0).is_empty());
+        let start = Instant::now();
+        while start.elapsed().as_millis() < 100 {
+            std::hint::black_box(start.elapsed());
+        }
+
+        let names = meter.update(10.0);
+        assert!(names.values().sum::<f64>() <= 10.0 + 1e-9);
+        let this = Process::read(process::id()).unwrap();
+        assert!(names.get(&this.name).is_some_and(|&joules| joules > 0.0));
+    }
+
+    #[test]
+    fn watches_names_the_kernel_cut_short() {
+        assert!(is_named("firefox", "firefox"));
+        assert!(!is_named("firefox-bin", "firefox"));
+        assert!(is_named("gnome-shell-cal", "gnome-shell-calendar-server"));
+
+        let this = Process::read(process::id()).unwrap();
+        let names = [this.name.clone(), "nothing-here".to_owned()];
+        let mut watchlist = Watchlist::new(&names, Splitter::new(None));
+        let start = Instant::now();
+        while start.elapsed().as_millis() < 100 {
+            std::hint::black_box(start.elapsed());
+        }
+        watchlist.update(10.0, &BTreeMap::new(), Duration::from_secs(1));
+        assert!(watchlist.joules[&this.name] > 0.0);
+        assert_eq!(watchlist.joules["nothing-here"], 0.0);
+        assert_eq!(watchlist.total, 10.0);
+    }
+}
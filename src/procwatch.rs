@@ -0,0 +1,312 @@
+//! Processes starting and exiting, for `--capture-on`.
+//!
+//! The kernel's proc connector sends an event for every `exec` and exit
+//! over netlink, as they happen. Subscribing to it takes `CAP_NET_ADMIN`,
+//! without that [`Watcher`] compares `/proc` every time it is asked
+//! instead, which misses processes that exit within one poll.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    mem,
+    os::fd::{FromRawFd, OwnedFd},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::process::{self, Process};
+
+const AF_NETLINK: i32 = 16;
+const SOCK_DGRAM: i32 = 2;
+const SOCK_CLOEXEC: i32 = 0o2000000;
+const NETLINK_CONNECTOR: i32 = 11;
+const SOL_SOCKET: i32 = 1;
+const SO_RCVTIMEO: i32 = 20;
+const NLMSG_DONE: u16 = 3;
+const NLMSG_HEADER_LEN: usize = 16;
+const CN_MSG_LEN: usize = 20;
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_EVENT_NONE: u32 = 0;
+const PROC_EVENT_EXEC: u32 = 2;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+/// `what`, `cpu` and `timestamp_ns` of a `proc_event`.
+const PROC_EVENT_HEADER_LEN: usize = 16;
+
+#[repr(C)]
+struct SockaddrNl {
+    family: u16,
+    pad: u16,
+    pid: u32,
+    groups: u32,
+}
+
+#[repr(C)]
+struct Timeval {
+    sec: i64,
+    usec: i64,
+}
+
+extern "C" {
+    fn socket(domain: i32, kind: i32, protocol: i32) -> i32;
+    fn bind(fd: i32, addr: *const SockaddrNl, len: u32) -> i32;
+    fn send(fd: i32, buf: *const u8, len: usize, flags: i32) -> isize;
+    fn setsockopt(fd: i32, level: i32, name: i32, value: *const Timeval, len: u32) -> i32;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A process ran `exec` and is now called `name`.
+    Started {
+        pid: u32,
+        name: String,
+    },
+    Exited {
+        pid: u32,
+    },
+}
+
+/// Where the events come from.
+#[derive(Debug)]
+pub enum Watcher {
+    Connector(File),
+    /// The processes of the last poll by PID, with their start times.
+    Poll(HashMap<u32, u64>),
+}
+
+impl Watcher {
+    /// Subscribes to the proc connector, or polls `/proc` if that isn't
+    /// allowed.
+    pub fn open() -> Self {
+        match connect() {
+            Ok(socket) => Self::Connector(socket),
+            Err(_) => Self::Poll(running()),
+        }
+    }
+
+    pub fn is_polling(&self) -> bool {
+        matches!(self, Self::Poll(_))
+    }
+
+    /// The events of the next `timeout` at most, empty if there were none.
+    pub fn next(&mut self, timeout: Duration) -> io::Result<Vec<Event>> {
+        match self {
+            Self::Connector(socket) => {
+                let mut buffer = [0; 4096];
+                let len = match socket.read(&mut buffer) {
+                    Ok(len) => len,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(Vec::new()),
+                    Err(err) => return Err(err),
+                };
+                Ok(parse_messages(&buffer[..len])
+                    .into_iter()
+                    .filter_map(|(what, data)| event(what, data))
+                    .collect())
+            }
+            Self::Poll(known) => {
+                thread::sleep(timeout);
+                let now = running();
+                let mut events = now
+                    .iter()
+                    .filter(|(pid, start)| known.get(pid) != Some(start))
+                    .filter_map(|(&pid, _)| {
+                        let name = Process::read(pid)?.name;
+                        Some(Event::Started { pid, name })
+                    })
+                    .collect::<Vec<_>>();
+                events.extend(
+                    known
+                        .iter()
+                        .filter(|(pid, start)| now.get(pid) != Some(start))
+                        .map(|(&pid, _)| Event::Exited { pid }),
+                );
+                *known = now;
+                Ok(events)
+            }
+        }
+    }
+}
+
+/// Start times of the running processes by PID.
+fn running() -> HashMap<u32, u64> {
+    process::all()
+        .into_iter()
+        .map(|process| (process.pid, process.start))
+        .collect()
+}
+
+/// A netlink socket subscribed to the proc connector, read with a timeout
+/// of a second.
+fn connect() -> io::Result<File> {
+    // SAFETY: plain system calls on a descriptor owned here, with pointers
+    // to values living across each call.
+    let socket = unsafe {
+        let fd = socket(AF_NETLINK, SOCK_DGRAM | SOCK_CLOEXEC, NETLINK_CONNECTOR);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = File::from(OwnedFd::from_raw_fd(fd));
+        let addr = SockaddrNl {
+            family: AF_NETLINK as u16,
+            pad: 0,
+            pid: 0,
+            groups: CN_IDX_PROC,
+        };
+        if bind(fd, &addr, mem::size_of::<SockaddrNl>() as u32) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let timeout = Timeval { sec: 1, usec: 0 };
+        if setsockopt(
+            fd,
+            SOL_SOCKET,
+            SO_RCVTIMEO,
+            &timeout,
+            mem::size_of::<Timeval>() as u32,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let request = listen_request();
+        if send(fd, request.as_ptr(), request.len(), 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        socket
+    };
+
+    // The kernel acknowledges the subscription, with an error if it was
+    // refused.
+    let mut socket = socket;
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut buffer = [0; 4096];
+    while Instant::now() < deadline {
+        let len = socket.read(&mut buffer)?;
+        for (what, data) in parse_messages(&buffer[..len]) {
+            if what != PROC_EVENT_NONE {
+                continue;
+            }
+            return match u32_at(data, 0) {
+                Some(0) | None => Ok(socket),
+                Some(err) => Err(io::Error::from_raw_os_error(err as i32)),
+            };
+        }
+    }
+    Err(io::ErrorKind::TimedOut.into())
+}
+
+/// A netlink message asking the proc connector for its events.
+fn listen_request() -> Vec<u8> {
+    let len = NLMSG_HEADER_LEN + CN_MSG_LEN + 4;
+    let mut message = Vec::with_capacity(len);
+    // nlmsghdr: length, type, flags, sequence number and port.
+    message.extend_from_slice(&(len as u32).to_ne_bytes());
+    message.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+    message.extend_from_slice(&0u16.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    // cn_msg: the connector's id, sequence and acknowledgement numbers,
+    // the length of the data and flags.
+    message.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+    message.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&4u16.to_ne_bytes());
+    message.extend_from_slice(&0u16.to_ne_bytes());
+    message.extend_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+    message
+}
+
+/// The `what` of each `proc_event` in `buffer`, with the data after its
+/// header.
+fn parse_messages(mut buffer: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut events = Vec::new();
+    while let Some(len) = u32_at(buffer, 0).map(|len| len as usize) {
+        if len < NLMSG_HEADER_LEN || len > buffer.len() {
+            break;
+        }
+        let payload = &buffer[NLMSG_HEADER_LEN..len];
+        if let Some(event) = payload.get(CN_MSG_LEN..) {
+            if let (Some(what), Some(data)) = (u32_at(event, 0), event.get(PROC_EVENT_HEADER_LEN..))
+            {
+                events.push((what, data));
+            }
+        }
+        // Messages are aligned to 4 bytes.
+        buffer = buffer.get((len + 3) & !3..).unwrap_or_default();
+    }
+    events
+}
+
+/// The event of a `proc_event`, for whole processes only.
+fn event(what: u32, data: &[u8]) -> Option<Event> {
+    let (pid, tgid) = (u32_at(data, 0)?, u32_at(data, 4)?);
+    match what {
+        PROC_EVENT_EXEC => {
+            let name = Process::read(tgid)?.name;
+            Some(Event::Started { pid: tgid, name })
+        }
+        // Threads exit one by one, the process with its main thread.
+        PROC_EVENT_EXIT if pid == tgid => Some(Event::Exited { pid }),
+        _ => None,
+    }
+}
+
+fn u32_at(buffer: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buffer.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(what: u32, data: &[u32]) -> Vec<u8> {
+        let mut message = listen_request()[..NLMSG_HEADER_LEN + CN_MSG_LEN].to_vec();
+        message.extend_from_slice(&what.to_ne_bytes());
+        message.extend_from_slice(&[0; PROC_EVENT_HEADER_LEN - 4]);
+        for value in data {
+            message.extend_from_slice(&value.to_ne_bytes());
+        }
+        let len = message.len() as u32;
+        message[..4].copy_from_slice(&len.to_ne_bytes());
+        message
+    }
+
+    #[test]
+    fn reads_process_events() {
+        let this = std::process::id();
+        let mut buffer = message(PROC_EVENT_EXEC, &[this, this]);
+        // A thread exiting, then the process.
+        buffer.extend(message(PROC_EVENT_EXIT, &[this + 1, this, 0, 0]));
+        buffer.extend(message(PROC_EVENT_EXIT, &[this, this, 0, 0]));
+
+        let events = parse_messages(&buffer)
+            .into_iter()
+            .filter_map(|(what, data)| event(what, data))
+            .collect::<Vec<_>>();
+        let name = Process::read(this).unwrap().name;
+        assert_eq!(
+            events,
+            [
+                Event::Started { pid: this, name },
+                Event::Exited { pid: this }
+            ]
+        );
+        assert!(parse_messages(&buffer[..10]).is_empty());
+    }
+
+    #[test]
+    fn polls_for_new_processes() {
+        let mut watcher = Watcher::Poll(HashMap::new());
+        let events = watcher.next(Duration::ZERO).unwrap();
+        let this = std::process::id();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::Started { pid, .. } if *pid == this)));
+        assert!(!watcher
+            .next(Duration::ZERO)
+            .unwrap()
+            .contains(&Event::Exited { pid: this }));
+    }
+}
@@ -0,0 +1,491 @@
+//! Known telemetry quirks of specific CPUs and firmware, and the workarounds
+//! applied for them.
+
+use std::{collections::BTreeMap, fs};
+
+use crate::{cpuinfo::CpuInfo, sysfs::Root};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workaround {
+    /// Tctl is reported this many °C above the real die temperature.
+    TctlOffset(f64),
+}
+
+#[derive(Debug)]
+pub struct Quirk {
+    pub id: &'static str,
+    pub description: &'static str,
+    pub family: u32,
+    /// Matched against the start of the `model name` in `/proc/cpuinfo`.
+    pub model_name_prefix: &'static str,
+    /// Matched against the start of the DMI BIOS version, if set.
+    pub bios_version_prefix: Option<&'static str>,
+    pub workaround: Workaround,
+}
+
+impl Quirk {
+    fn matches(&self, cpu: &CpuInfo, bios_version: Option<&str>) -> bool {
+        let bios_matches = match self.bios_version_prefix {
+            Some(prefix) => bios_version.is_some_and(|version| version.starts_with(prefix)),
+            None => true,
+        };
+
+        cpu.family == self.family
+            && cpu.model_name.starts_with(self.model_name_prefix)
+            && bios_matches
+    }
+}
+
+// Tctl offsets are the same ones the k10temp driver applies.
+pub const QUIRKS: &[Quirk] = &[
+    Quirk {
+        id: "tctl-offset-1600x",
+        description: "Tctl reads 20°C above Tdie",
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen 5 1600X",
+        bios_version_prefix: None,
+        workaround: Workaround::TctlOffset(20.0),
+    },
+    Quirk {
+        id: "tctl-offset-1700x",
+        description: "Tctl reads 20°C above Tdie",
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen 7 1700X",
+        bios_version_prefix: None,
+        workaround: Workaround::TctlOffset(20.0),
+    },
+    Quirk {
+        id: "tctl-offset-1800x",
+        description: "Tctl reads 20°C above Tdie",
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen 7 1800X",
+        bios_version_prefix: None,
+        workaround: Workaround::TctlOffset(20.0),
+    },
+    Quirk {
+        id: "tctl-offset-2700x",
+        description: "Tctl reads 10°C above Tdie",
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen 7 2700X",
+        bios_version_prefix: None,
+        workaround: Workaround::TctlOffset(10.0),
+    },
+    Quirk {
+        id: "tctl-offset-threadripper-1000",
+        description: "Tctl reads 27°C above Tdie",
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen Threadripper 19",
+        bios_version_prefix: None,
+        workaround: Workaround::TctlOffset(27.0),
+    },
+    Quirk {
+        id: "tctl-offset-threadripper-2000",
+        description: "Tctl reads 27°C above Tdie",
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen Threadripper 29",
+        bios_version_prefix: None,
+        workaround: Workaround::TctlOffset(27.0),
+    },
+];
+
+/// Ranges that limits written to a CPU model have to stay inside, see
+/// [`crate::guardrail`]. They are deliberately conservative: the upper ends
+/// are the stock PPT of the fastest part of a series and class, and its
+/// Tjmax.
+#[derive(Debug)]
+pub struct ModelLimits {
+    pub family: u32,
+    /// Matched against the start of the `model name` in `/proc/cpuinfo`.
+    pub model_name_prefix: &'static str,
+    /// The class the model has to be of, `None` for any.
+    pub class: Option<TdpClass>,
+    /// Package power limit (PPT) in W.
+    pub package_power: (f64, f64),
+    /// Temperature limit in °C.
+    pub temperature: (f64, f64),
+}
+
+/// What a part is built for, from the suffix of its model number. A 15 W
+/// laptop part and a 170 W desktop part of the same family share everything
+/// else in their model name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TdpClass {
+    /// Thin and light laptops, `7840U`, and the Ryzen AI parts without a
+    /// suffix.
+    U,
+    /// Laptops, `7840HS`.
+    Hs,
+    /// Laptops, `5800H`.
+    H,
+    /// Desktop replacements, `7945HX`, `AI 9 HX 370`.
+    Hx,
+    /// Desktops at 65 W, `5600`, `5700G`.
+    Desktop,
+    /// Unlocked desktops, `7950X`, `5800X3D`, `3900XT`.
+    X,
+}
+
+impl TdpClass {
+    /// The class of a model name like `AMD Ryzen 7 PRO 7840U w/ Radeon 780M
+    /// Graphics` or `AMD Ryzen AI 9 HX 370`, `None` for suffixes that don't
+    /// say how much power a part is made for, like `GE` or the laptop parts
+    /// named without one.
+    pub fn of(model_name: &str) -> Option<Self> {
+        let mut words = model_name.split_whitespace();
+        words.find(|word| *word == "Ryzen")?;
+        let mut words = words.peekable();
+        let ai = words.next_if_eq(&"AI").is_some();
+        // The tier, 3 to 9.
+        words.next()?;
+        words.next_if_eq(&"PRO");
+
+        let suffix = words.next_if(|word| word.bytes().all(|b| b.is_ascii_uppercase()));
+        words.next_if_eq(&"PRO");
+        let number = words.next()?;
+        let digits = number.bytes().take_while(u8::is_ascii_digit).count();
+        let suffix = match (suffix, &number[digits..]) {
+            (Some(suffix), "") => suffix,
+            (None, suffix) => suffix,
+            _ => return None,
+        };
+        Some(match (suffix, digits, ai) {
+            ("", _, true) => Self::U,
+            ("U", 4, _) => Self::U,
+            ("HS", 4, _) => Self::Hs,
+            ("H", 4, _) => Self::H,
+            ("HX" | "HX3D", 4, _) | ("HX", 3, true) => Self::Hx,
+            ("" | "G", 4, false) => Self::Desktop,
+            ("X" | "XT" | "X3D", 4, false) => Self::X,
+            _ => return None,
+        })
+    }
+}
+
+// More specific entries have to come first, the first match wins.
+pub const MODEL_LIMITS: &[ModelLimits] = &[
+    ModelLimits {
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen Threadripper",
+        class: None,
+        package_power: (60.0, 280.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::X),
+        package_power: (30.0, 142.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::Desktop),
+        package_power: (30.0, 88.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::H),
+        package_power: (25.0, 54.0),
+        temperature: (60.0, 105.0),
+    },
+    ModelLimits {
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::Hs),
+        package_power: (15.0, 42.0),
+        temperature: (60.0, 105.0),
+    },
+    ModelLimits {
+        family: 0x17,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::U),
+        package_power: (8.0, 25.0),
+        temperature: (60.0, 105.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen Threadripper",
+        class: None,
+        package_power: (60.0, 350.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen 9 7",
+        class: Some(TdpClass::X),
+        package_power: (30.0, 230.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::X),
+        package_power: (30.0, 142.0),
+        temperature: (60.0, 90.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::Desktop),
+        package_power: (30.0, 88.0),
+        temperature: (60.0, 90.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::Hx),
+        package_power: (35.0, 75.0),
+        temperature: (60.0, 100.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::H),
+        package_power: (25.0, 54.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::Hs),
+        package_power: (15.0, 54.0),
+        temperature: (60.0, 100.0),
+    },
+    ModelLimits {
+        family: 0x19,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::U),
+        package_power: (8.0, 30.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x1a,
+        model_name_prefix: "AMD Ryzen 9 9",
+        class: Some(TdpClass::X),
+        package_power: (30.0, 230.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x1a,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::X),
+        package_power: (30.0, 142.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x1a,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::Desktop),
+        package_power: (30.0, 88.0),
+        temperature: (60.0, 95.0),
+    },
+    ModelLimits {
+        family: 0x1a,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::Hx),
+        package_power: (15.0, 54.0),
+        temperature: (60.0, 100.0),
+    },
+    ModelLimits {
+        family: 0x1a,
+        model_name_prefix: "AMD Ryzen",
+        class: Some(TdpClass::U),
+        package_power: (8.0, 33.0),
+        temperature: (60.0, 100.0),
+    },
+];
+
+impl ModelLimits {
+    pub fn detect(cpu: &CpuInfo) -> Option<&'static Self> {
+        let class = TdpClass::of(&cpu.model_name);
+        MODEL_LIMITS.iter().find(|limits| {
+            cpu.family == limits.family
+                && cpu.model_name.starts_with(limits.model_name_prefix)
+                && limits.class.is_none_or(|limit| class == Some(limit))
+        })
+    }
+}
+
+/// The quirks that apply to this machine.
+#[derive(Debug, Default)]
+pub struct Quirks {
+    pub bios_version: Option<String>,
+    pub applied: Vec<&'static Quirk>,
+}
+
+impl Quirks {
+    pub fn detect(root: &Root, cpu: &CpuInfo) -> Self {
+        let bios_version = fs::read_to_string(root.path("/sys/class/dmi/id/bios_version"))
+            .ok()
+            .map(|version| version.trim_end().to_owned());
+
+        let applied = QUIRKS
+            .iter()
+            .filter(|quirk| quirk.matches(cpu, bios_version.as_deref()))
+            .collect();
+
+        Self {
+            bios_version,
+            applied,
+        }
+    }
+
+    /// Keeps applying quirks that were applied on an earlier run, even if
+    /// they no longer match, e.g. because the BIOS version became unreadable.
+    pub fn keep(&mut self, ids: &[String]) {
+        for quirk in QUIRKS {
+            if ids.iter().any(|id| id == quirk.id) && !self.is_applied(quirk.id) {
+                self.applied.push(quirk);
+            }
+        }
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.applied
+            .iter()
+            .map(|quirk| quirk.id.to_owned())
+            .collect()
+    }
+
+    pub fn is_applied(&self, id: &str) -> bool {
+        self.applied.iter().any(|quirk| quirk.id == id)
+    }
+
+    /// Converts a raw Tctl reading into the real die temperature.
+    pub fn correct_tctl(&self, tctl: f64) -> f64 {
+        self.applied
+            .iter()
+            .fold(tctl, |temp, quirk| match quirk.workaround {
+                Workaround::TctlOffset(offset) => temp - offset,
+            })
+    }
+
+    /// Adds the die temperature to what [`crate::temperature::read`] found
+    /// when the kernel didn't report a `tdie` of its own, keeping `tctl` as
+    /// the hardware reported it.
+    pub fn add_tdie(&self, temperatures: &mut BTreeMap<String, f64>) {
+        let Some(&tctl) = temperatures.get("tctl") else {
+            return;
+        };
+        let tdie = self.correct_tctl(tctl);
+        if tdie != tctl {
+            temperatures.entry("tdie".to_owned()).or_insert(tdie);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    fn cpu(family: u32, model_name: &str) -> CpuInfo {
+        CpuInfo {
+            vendor: "AuthenticAMD".to_owned(),
+            family,
+            model_name: model_name.to_owned(),
+            ..CpuInfo::default()
+        }
+    }
+
+    #[test]
+    fn reads_the_bios_version_below_the_root() {
+        let fixture = Fixture::new();
+        fixture.file("/sys/class/dmi/id/bios_version", "F4 \n");
+        let quirks = Quirks::detect(fixture.root(), &cpu(0x17, "AMD Ryzen 7 2700X Eight-Core"));
+        assert_eq!(quirks.bios_version.as_deref(), Some("F4"));
+        assert_eq!(quirks.ids(), ["tctl-offset-2700x"]);
+        assert_eq!(quirks.correct_tctl(70.0), 60.0);
+
+        let mut temperatures = BTreeMap::from([("tctl".to_owned(), 70.0)]);
+        quirks.add_tdie(&mut temperatures);
+        assert_eq!(
+            temperatures,
+            BTreeMap::from([("tctl".to_owned(), 70.0), ("tdie".to_owned(), 60.0)])
+        );
+        // The kernel's own Tdie wins.
+        let mut temperatures =
+            BTreeMap::from([("tctl".to_owned(), 70.0), ("tdie".to_owned(), 59.5)]);
+        quirks.add_tdie(&mut temperatures);
+        assert_eq!(temperatures["tdie"], 59.5);
+
+        let quirks = Quirks::detect(Fixture::new().root(), &cpu(0x19, "AMD Ryzen 7 5800X"));
+        assert_eq!(quirks.bios_version, None);
+        assert!(quirks.applied.is_empty());
+        let mut temperatures = BTreeMap::from([("tctl".to_owned(), 70.0)]);
+        quirks.add_tdie(&mut temperatures);
+        assert!(!temperatures.contains_key("tdie"));
+    }
+
+    #[test]
+    fn classes_models_by_their_suffix() {
+        for (model_name, class) in [
+            (
+                "AMD Ryzen 7 PRO 7840U w/ Radeon 780M Graphics",
+                Some(TdpClass::U),
+            ),
+            ("AMD Ryzen AI 9 365 w/ Radeon 880M", Some(TdpClass::U)),
+            (
+                "AMD Ryzen 9 8945HS w/ Radeon 780M Graphics",
+                Some(TdpClass::Hs),
+            ),
+            ("AMD Ryzen 7 5800H with Radeon Graphics", Some(TdpClass::H)),
+            (
+                "AMD Ryzen 9 7945HX3D with Radeon Graphics",
+                Some(TdpClass::Hx),
+            ),
+            ("AMD Ryzen AI 9 HX 370 w/ Radeon 890M", Some(TdpClass::Hx)),
+            ("AMD Ryzen 5 5600 6-Core Processor", Some(TdpClass::Desktop)),
+            (
+                "AMD Ryzen 7 5700G with Radeon Graphics",
+                Some(TdpClass::Desktop),
+            ),
+            ("AMD Ryzen 7 5800X3D 8-Core Processor", Some(TdpClass::X)),
+            ("AMD Ryzen 9 3900XT 12-Core Processor", Some(TdpClass::X)),
+            ("AMD Ryzen 5 5600GE with Radeon Graphics", None),
+            ("AMD Ryzen 7 260 w/ Radeon 780M Graphics", None),
+            ("AMD EPYC 7763 64-Core Processor", None),
+        ] {
+            assert_eq!(TdpClass::of(model_name), class, "{}", model_name);
+        }
+    }
+
+    #[test]
+    fn bounds_laptops_below_desktops_of_the_same_family() {
+        let package_power = |family, model_name| {
+            ModelLimits::detect(&cpu(family, model_name)).map(|limits| limits.package_power.1)
+        };
+        assert_eq!(
+            package_power(0x19, "AMD Ryzen 9 7950X 16-Core Processor"),
+            Some(230.0)
+        );
+        assert_eq!(
+            package_power(0x19, "AMD Ryzen 7 5800X 8-Core Processor"),
+            Some(142.0)
+        );
+        assert_eq!(
+            package_power(0x19, "AMD Ryzen 5 5600 6-Core Processor"),
+            Some(88.0)
+        );
+        assert_eq!(
+            package_power(0x19, "AMD Ryzen 7 7840HS w/ Radeon 780M Graphics"),
+            Some(54.0)
+        );
+        assert_eq!(
+            package_power(0x19, "AMD Ryzen 7 7840U w/ Radeon 780M Graphics"),
+            Some(30.0)
+        );
+        assert_eq!(
+            package_power(0x17, "AMD Ryzen Threadripper 3970X 32-Core Processor"),
+            Some(280.0)
+        );
+        // Without a class there are no bounds, so nothing can be written.
+        assert_eq!(
+            package_power(0x19, "AMD Ryzen 5 5600GE with Radeon Graphics"),
+            None
+        );
+    }
+}
@@ -0,0 +1,553 @@
+//! Session files of `--record`: the energy counters of every window as they
+//! were read, for `replay` to compute power from later, with other windows
+//! or groupings and on another machine.
+//!
+//! The file is NDJSON, a header describing the machine and its counters,
+//! then one line per reading with its time in seconds since the recording
+//! started and the counters in joules.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write as _},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    backend::Registers,
+    cpu::{energy_delta, Snapshot},
+    firehose, import,
+    json::{self, Value},
+    migrate::Format,
+    output::{json_string, Sample},
+    state::Calibration,
+    topology, Cpu, Error, Result,
+};
+
+/// The file format, its version is in the header. [`FORMAT`](firehose::FORMAT)
+/// of firehose captures goes up with it, they have the same header.
+pub const FORMAT: Format<String> = Format {
+    name: "session file",
+    first: 1,
+    current: 1,
+    migrations: &[],
+};
+
+/// What replaying needs to know about the recording machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub start: SystemTime,
+    pub hostname: String,
+    pub cpu: String,
+    pub backend: &'static str,
+    /// Joules after which the counters wrap, `None` if they don't.
+    pub package_range: Option<f64>,
+    pub core_range: Option<f64>,
+    pub domain_range: Option<f64>,
+    pub calibration: Calibration,
+    pub smt_enabled: bool,
+    pub core_count: u32,
+    pub physical_core_count: u32,
+    /// Cores of each group by grouping, like `ccd` to `ccd0`, as far as the
+    /// recording machine could tell.
+    pub groups: BTreeMap<String, BTreeMap<String, Vec<u32>>>,
+    /// What the session is of, like the program `--capture-on` started it
+    /// for.
+    pub tag: Option<String>,
+}
+
+/// One reading of all counters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    /// Since [`Header::start`].
+    pub time: Duration,
+    pub package: f64,
+    pub cores: BTreeMap<u32, f64>,
+    pub domains: BTreeMap<&'static str, f64>,
+}
+
+/// Appends the readings of a session to a file.
+#[derive(Debug)]
+pub struct Recorder {
+    file: BufWriter<File>,
+    path: PathBuf,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates `path` and writes `header`, replacing what was there.
+    pub fn create(path: &Path, header: &Header) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let mut recorder = Self {
+            file: BufWriter::new(file),
+            path: path.to_owned(),
+            start: Instant::now(),
+        };
+        recorder.write(&header.to_json())?;
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `snapshot`, timed by when the package counter was read.
+    pub fn record(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let reading = Reading {
+            time: snapshot.package.1.saturating_duration_since(self.start),
+            package: snapshot.package.0,
+            cores: snapshot
+                .cores
+                .iter()
+                .map(|(&core, &(energy, _))| (core, energy))
+                .collect(),
+            domains: snapshot
+                .domains
+                .iter()
+                .map(|(&domain, &(energy, _))| (domain, energy))
+                .collect(),
+        };
+        self.write(&reading.to_json())
+    }
+
+    /// Flushed line by line, so an interrupted recording keeps everything
+    /// up to the last window.
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()
+    }
+}
+
+impl Header {
+    /// Describes `cpu`, `hostname` and the groupings known for its cores.
+    pub fn new(
+        cpu: &Cpu,
+        hostname: &str,
+        calibration: &Calibration,
+        groups: BTreeMap<String, BTreeMap<String, Vec<u32>>>,
+    ) -> Self {
+        Self {
+            start: SystemTime::now(),
+            hostname: hostname.to_owned(),
+            cpu: cpu.info.model_name.clone(),
+            backend: cpu.backend_name(),
+            package_range: cpu.package_energy_range(),
+            core_range: cpu.core_energy_range(),
+            domain_range: cpu.domain_energy_range(),
+            calibration: *calibration,
+            smt_enabled: cpu.smt_enabled,
+            core_count: cpu.core_count,
+            physical_core_count: cpu.physical_core_count,
+            groups,
+            tag: None,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        let start = self
+            .start
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let range = |range: Option<f64>| range.map_or("null".to_owned(), |range| range.to_string());
+        let groups = self
+            .groups
+            .iter()
+            .map(|(grouping, groups)| {
+                let groups = groups
+                    .iter()
+                    .map(|(group, cores)| {
+                        let cores = cores.iter().map(u32::to_string).collect::<Vec<_>>();
+                        format!("{}:[{}]", json_string(group), cores.join(","))
+                    })
+                    .collect::<Vec<_>>();
+                format!("{}:{{{}}}", json_string(grouping), groups.join(","))
+            })
+            .collect::<Vec<_>>();
+
+        // Left out without one, like in files from before there were tags.
+        let tag = self
+            .tag
+            .as_ref()
+            .map(|tag| format!(",\"tag\":{}", json_string(tag)))
+            .unwrap_or_default();
+
+        format!(
+            concat!(
+                "{{\"ryzen_wattage_session\":{},\"start\":{},\"hostname\":{},\"cpu\":{},",
+                "\"backend\":{},\"package_range\":{},\"core_range\":{},\"domain_range\":{},",
+                "\"calibration\":{{\"package\":{},\"cores\":{}}},\"smt_enabled\":{},",
+                "\"core_count\":{},\"physical_core_count\":{},\"groups\":{{{}}}{}}}"
+            ),
+            FORMAT.current,
+            start,
+            json_string(&self.hostname),
+            json_string(&self.cpu),
+            json_string(self.backend),
+            range(self.package_range),
+            range(self.core_range),
+            range(self.domain_range),
+            self.calibration.package,
+            self.calibration.cores,
+            self.smt_enabled,
+            self.core_count,
+            self.physical_core_count,
+            groups.join(","),
+            tag,
+        )
+    }
+
+    pub(crate) fn parse(value: &Value) -> Option<Self> {
+        if value.get("ryzen_wattage_session")?.as_f64()? != f64::from(FORMAT.current) {
+            return None;
+        }
+        let range = |key| match value.get(key)? {
+            Value::Null => Some(None),
+            range => range.as_f64().map(Some),
+        };
+        let count = |key| value.get(key)?.as_f64().map(|count| count as u32);
+        let calibration = value.get("calibration")?;
+
+        let mut groups = BTreeMap::new();
+        for (grouping, members) in value.get("groups")?.as_object()? {
+            let mut cores = BTreeMap::new();
+            for (group, members) in members.as_object()? {
+                let members = members
+                    .as_array()?
+                    .iter()
+                    .map(|core| core.as_f64().map(|core| core as u32))
+                    .collect::<Option<Vec<_>>>()?;
+                cores.insert(group.clone(), members);
+            }
+            groups.insert(grouping.clone(), cores);
+        }
+
+        Some(Self {
+            start: UNIX_EPOCH + Duration::try_from_secs_f64(value.get("start")?.as_f64()?).ok()?,
+            hostname: value.get("hostname")?.as_str()?.to_owned(),
+            cpu: value.get("cpu")?.as_str()?.to_owned(),
+            backend: backend_name(value.get("backend")?.as_str()?),
+            package_range: range("package_range")?,
+            core_range: range("core_range")?,
+            domain_range: range("domain_range")?,
+            calibration: Calibration {
+                package: calibration.get("package")?.as_f64()?,
+                cores: calibration.get("cores")?.as_f64()?,
+            },
+            smt_enabled: value.get("smt_enabled")?.as_bool()?,
+            core_count: count("core_count")?,
+            physical_core_count: count("physical_core_count")?,
+            groups,
+            tag: value.get("tag").and_then(Value::as_str).map(str::to_owned),
+        })
+    }
+}
+
+impl Reading {
+    fn to_json(&self) -> String {
+        let cores = self
+            .cores
+            .iter()
+            .map(|(core, energy)| format!("\"{}\":{}", core, energy))
+            .collect::<Vec<_>>();
+        let mut line = format!(
+            "{{\"t\":{},\"package\":{},\"cores\":{{{}}}",
+            self.time.as_secs_f64(),
+            self.package,
+            cores.join(",")
+        );
+        if !self.domains.is_empty() {
+            let domains = self
+                .domains
+                .iter()
+                .map(|(domain, energy)| format!("{}:{}", json_string(domain), energy))
+                .collect::<Vec<_>>();
+            line.push_str(&format!(",\"domains\":{{{}}}", domains.join(",")));
+        }
+        line.push('}');
+        line
+    }
+
+    fn parse(value: &Value) -> Option<Self> {
+        let cores = value
+            .get("cores")?
+            .as_object()?
+            .iter()
+            .map(|(core, energy)| Some((core.parse().ok()?, energy.as_f64()?)))
+            .collect::<Option<_>>()?;
+        // Domains this version doesn't know are left out.
+        let domains = match value.get("domains") {
+            Some(domains) => domains
+                .as_object()?
+                .iter()
+                .filter_map(|(domain, energy)| Some((domain_name(domain)?, energy)))
+                .map(|(domain, energy)| Some((domain, energy.as_f64()?)))
+                .collect::<Option<_>>()?,
+            None => BTreeMap::new(),
+        };
+
+        Some(Self {
+            time: Duration::try_from_secs_f64(value.get("t")?.as_f64()?).ok()?,
+            package: value.get("package")?.as_f64()?,
+            cores,
+            domains,
+        })
+    }
+}
+
+/// A recorded session, read back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub header: Header,
+    pub readings: Vec<Reading>,
+}
+
+impl Session {
+    /// Reads a session file, a [`firehose`] capture or samples exported
+    /// as NDJSON or CSV, see [`import`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read(path).map_err(|err| Error::io(path, err))?;
+        let refused = |refusal| Error::Migration {
+            path: path.into(),
+            refusal,
+        };
+        if let Some(version) = firehose::version(&contents) {
+            let contents = firehose::FORMAT
+                .migrate(version, contents)
+                .map_err(refused)?;
+            return firehose::parse(&contents).map_err(|line| Error::parse(path, line));
+        }
+        let contents = String::from_utf8_lossy(&contents).into_owned();
+        if import::is_export(&contents) {
+            return import::parse(&contents).map_err(|line| Error::parse(path, line));
+        }
+        // Files that don't say are left for parsing to reject.
+        let version = Self::version(&contents).unwrap_or(FORMAT.current);
+        let contents = FORMAT.migrate(version, contents).map_err(refused)?;
+        Self::parse(&contents).map_err(|line| Error::parse(path, line))
+    }
+
+    /// The version in the header of a session file.
+    fn version(contents: &str) -> Option<u32> {
+        let first = contents.lines().find(|line| !line.trim().is_empty())?;
+        let version = json::parse(first)
+            .ok()?
+            .get("ryzen_wattage_session")?
+            .as_f64()?;
+        (version.fract() == 0.0 && version >= 0.0).then_some(version as u32)
+    }
+
+    /// Reads a session file, or returns the first line that isn't valid.
+    fn parse(contents: &str) -> std::result::Result<Self, &str> {
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let first = lines.next().unwrap_or_default();
+        let header = json::parse(first)
+            .ok()
+            .as_ref()
+            .and_then(Header::parse)
+            .ok_or(first)?;
+
+        let readings = lines
+            .map(|line| {
+                json::parse(line)
+                    .ok()
+                    .as_ref()
+                    .and_then(Reading::parse)
+                    .ok_or(line)
+            })
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(Self { header, readings })
+    }
+
+    /// The cores with readings.
+    pub fn cores(&self) -> Vec<u32> {
+        self.readings
+            .first()
+            .map(|reading| reading.cores.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Samples over windows of at least `window`, each ending at the first
+    /// reading that long after its start, with core power summed per group
+    /// in `groups`. Windows can't be shorter than they were recorded with.
+    pub fn samples(&self, window: Duration, groups: &BTreeMap<String, Vec<u32>>) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        let mut start = 0;
+        for end in 1..self.readings.len() {
+            let (before, after) = (&self.readings[start], &self.readings[end]);
+            if after.time.saturating_sub(before.time) < window {
+                continue;
+            }
+            samples.push(self.sample(before, after, groups));
+            start = end;
+        }
+        samples
+    }
+
+    fn sample(
+        &self,
+        before: &Reading,
+        after: &Reading,
+        groups: &BTreeMap<String, Vec<u32>>,
+    ) -> Sample {
+        let header = &self.header;
+        let elapsed = after.time.saturating_sub(before.time);
+        let power = |before: f64, after: f64, range: Option<f64>| {
+            energy_delta(before, after, range) / elapsed.as_secs_f64()
+        };
+
+        let core_power = before
+            .cores
+            .iter()
+            .filter_map(|(&core, &energy)| {
+                let &after = after.cores.get(&core)?;
+                let power = power(energy, after, header.core_range);
+                Some((core, power * header.calibration.cores))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let domain_power = before
+            .domains
+            .iter()
+            .filter_map(|(&domain, &energy)| {
+                let &after = after.domains.get(domain)?;
+                Some((domain, power(energy, after, header.domain_range)))
+            })
+            .collect();
+        let group_power = topology::group_power(groups, &core_power);
+
+        Sample {
+            timestamp: header.start + after.time,
+            window: elapsed,
+            package_power: power(before.package, after.package, header.package_range)
+                * header.calibration.package,
+            cores_total_power: core_power.values().sum(),
+            core_counters: !core_power.is_empty(),
+            core_power,
+            domain_power,
+            group_power,
+            backend: header.backend,
+            smt_enabled: header.smt_enabled,
+            core_count: header.core_count,
+            physical_core_count: header.physical_core_count,
+            ..Sample::default()
+        }
+    }
+}
+
+/// The name of a known backend, `unknown` for others.
+pub(crate) fn backend_name(name: &str) -> &'static str {
+    ["msr", "msr-safe", "powercap", "simulated"]
+        .into_iter()
+        .find(|&known| known == name)
+        .unwrap_or("unknown")
+}
+
+pub(crate) fn domain_name(name: &str) -> Option<&'static str> {
+    [Registers::ZEN, Registers::INTEL]
+        .iter()
+        .flat_map(|registers| registers.domains)
+        .map(|&(domain, _)| domain)
+        .find(|&domain| domain == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header {
+            start: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            hostname: "server".to_owned(),
+            cpu: "AMD EPYC 7313 16-Core Processor".to_owned(),
+            backend: "msr",
+            package_range: Some(65536.0),
+            core_range: Some(65536.0),
+            domain_range: None,
+            calibration: Calibration::default(),
+            smt_enabled: true,
+            core_count: 4,
+            physical_core_count: 2,
+            groups: BTreeMap::from([(
+                "ccd".to_owned(),
+                BTreeMap::from([("ccd0".to_owned(), vec![0]), ("ccd1".to_owned(), vec![1])]),
+            )]),
+            tag: Some("backup".to_owned()),
+        }
+    }
+
+    fn reading(secs: f64, package: f64, cores: [f64; 2]) -> Reading {
+        Reading {
+            time: Duration::from_secs_f64(secs),
+            package,
+            cores: BTreeMap::from([(0, cores[0]), (1, cores[1])]),
+            domains: BTreeMap::new(),
+        }
+    }
+
+    fn session() -> Session {
+        Session {
+            header: header(),
+            readings: vec![
+                reading(0.0, 65500.0, [100.0, 200.0]),
+                reading(1.0, 65530.0, [110.0, 205.0]),
+                reading(2.0, 24.0, [130.0, 210.0]),
+                reading(3.0, 54.0, [140.0, 215.0]),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trips_through_the_file_format() {
+        let session = session();
+        let mut file = session.header.to_json();
+        for reading in &session.readings {
+            file.push('\n');
+            file.push_str(&reading.to_json());
+        }
+        assert_eq!(Session::parse(&file).unwrap(), session);
+    }
+
+    #[test]
+    fn recomputes_power_over_longer_windows() {
+        let session = session();
+        let samples = session.samples(Duration::from_secs(1), &BTreeMap::new());
+        assert_eq!(samples.len(), 3);
+        // The package counter wrapped in the second window.
+        assert_eq!(samples[1].package_power, 30.0);
+        assert_eq!(samples[0].core_power[&0], 10.0);
+
+        let samples = session.samples(Duration::from_secs(2), &session.header.groups["ccd"]);
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].package_power, 30.0);
+        assert_eq!(samples[0].group_power["ccd0"], 15.0);
+        assert_eq!(samples[0].group_power["ccd1"], 5.0);
+        assert_eq!(samples[0].window, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(Session::parse("{\"package\":1}"), Err("{\"package\":1}"));
+        let mut file = header().to_json();
+        file.push_str("\nnot json");
+        assert_eq!(Session::parse(&file), Err("not json"));
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let path =
+            std::env::temp_dir().join(format!("ryzen-wattage-session-v2-{}", std::process::id()));
+        let newer = header().to_json().replace(
+            "\"ryzen_wattage_session\":1,",
+            "\"ryzen_wattage_session\":2,",
+        );
+        for contents in [newer, "RYZEN-WATTAGE-FIREHOSE 2\n{}\n".to_owned()] {
+            fs::write(&path, contents).unwrap();
+            let err = Session::load(&path).unwrap_err();
+            assert!(matches!(&err, Error::Migration { .. }), "{}", err);
+            assert!(err.to_string().contains("version 2 is newer"));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
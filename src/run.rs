@@ -0,0 +1,143 @@
+//! Energy used while a command runs, in the spirit of `perf stat`.
+
+use std::{
+    process::{Command, ExitStatus},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{cpu::energy_delta, stats::Sum, Cpu, Error, Result};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    pub status: ExitStatus,
+    pub wall_time: Duration,
+    /// Package energy in joules.
+    pub energy: f64,
+    pub average_power: f64,
+    /// Highest average package power of any sampling window, the last one
+    /// counted with the one before if it was too short to stand alone.
+    pub peak_power: f64,
+}
+
+/// The highest power of the windows of a run so far.
+///
+/// The command rarely exits right at the end of a window, so the last one
+/// is usually a sliver, over which a few counter updates more or less make
+/// a power far off the real one. A last window shorter than half the
+/// interval is folded into the one before instead.
+#[derive(Debug, Clone, Copy, Default)]
+struct Peak {
+    peak: f64,
+    /// The latest window, in joules and seconds, which the last one may
+    /// still be folded into.
+    pending: Option<(f64, f64)>,
+}
+
+impl Peak {
+    fn add(&mut self, joules: f64, seconds: f64) {
+        self.flush();
+        self.pending = Some((joules, seconds));
+    }
+
+    /// Adds the last window, of `seconds` out of `interval`.
+    fn finish(mut self, joules: f64, seconds: f64, interval: Duration) -> f64 {
+        match &mut self.pending {
+            Some((pending, pending_seconds)) if seconds < interval.as_secs_f64() / 2.0 => {
+                *pending += joules;
+                *pending_seconds += seconds;
+            }
+            _ => self.add(joules, seconds),
+        }
+        self.flush();
+        self.peak
+    }
+
+    fn flush(&mut self) {
+        if let Some((joules, seconds)) = self.pending.take().filter(|(_, seconds)| *seconds > 0.0) {
+            self.peak = self.peak.max(joules / seconds);
+        }
+    }
+}
+
+/// Runs `command` to completion while reading the package counter every
+/// `interval`.
+///
+/// Energy is summed window by window, so the counter wrapping during a long
+/// run is corrected for as long as `interval` is short enough for it never to
+/// wrap twice within one window.
+pub fn run(cpu: &Cpu, command: &mut Command, interval: Duration) -> Result<Report> {
+    let range = cpu.package_energy_range();
+    let program = command.get_program().to_owned();
+
+    let start = cpu.package_energy()?;
+    let mut child = command.spawn().map_err(|err| Error::io(&program, err))?;
+
+    let (exited, exit_signal) = mpsc::channel::<()>();
+
+    thread::scope(|scope| {
+        let sampler = scope.spawn(move || {
+            let mut previous = start;
+            let (mut energy, mut peak) = (Sum::default(), Peak::default());
+
+            loop {
+                let done = match exit_signal.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                };
+
+                let current = cpu.package_energy()?;
+                let window_energy = energy_delta(previous.0, current.0, range);
+                let elapsed = current.1.duration_since(previous.1).as_secs_f64();
+                energy += window_energy;
+                previous = current;
+
+                if done {
+                    let peak_power = peak.finish(window_energy, elapsed, interval);
+                    return Ok((energy.value(), previous.1, peak_power));
+                }
+                peak.add(window_energy, elapsed);
+            }
+        });
+
+        let status = child.wait().map_err(|err| Error::io(&program, err));
+        drop(exited);
+
+        let (energy, end, peak_power): (f64, Instant, f64) =
+            sampler.join().expect("sampler thread panicked")?;
+        let wall_time = end.duration_since(start.1);
+
+        Ok(Report {
+            status: status?,
+            wall_time,
+            energy,
+            average_power: energy / wall_time.as_secs_f64(),
+            peak_power,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_a_short_last_window_into_the_one_before() {
+        let interval = Duration::from_secs(1);
+        let mut peak = Peak::default();
+        peak.add(30.0, 1.0);
+        peak.add(40.0, 1.0);
+        // A few counter updates more over 62.5 ms would be 80 W.
+        assert_eq!(peak.finish(5.0, 0.0625, interval), 45.0 / 1.0625);
+
+        // Half a window or more counts on its own.
+        let mut peak = Peak::default();
+        peak.add(30.0, 1.0);
+        assert_eq!(peak.finish(25.0, 0.5, interval), 50.0);
+
+        // A command shorter than that only has the one.
+        assert_eq!(Peak::default().finish(5.0, 0.0625, interval), 80.0);
+        assert_eq!(Peak::default().finish(0.0, 0.0, interval), 0.0);
+    }
+}
@@ -0,0 +1,378 @@
+//! Plausibility checks for the measured power.
+//!
+//! Some AGESA/kernel combinations leave the energy counters frozen, which
+//! shows up as a package power of (almost) nothing while the CPU is clearly
+//! busy or hot. These checks turn that into a diagnostic instead of a silent
+//! `0.00W`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    time::Duration,
+};
+
+use crate::output::Sample;
+
+/// Below this a busy or hot package cannot plausibly be running.
+const MIN_PLAUSIBLE_PACKAGE_POWER: f64 = 1.0;
+/// Utilization above which the package has to draw noticeable power.
+const BUSY_UTILIZATION: f64 = 0.5;
+/// Tctl above which the package has to draw noticeable power, in °C.
+const HOT_TEMPERATURE: f64 = 70.0;
+/// Windows in a row the package counter has to stand still on a busy CPU
+/// before [`Watchdog`] calls it stuck.
+pub const STUCK_WINDOWS: u32 = 3;
+
+/// Utilization from which the package counts as fully loaded, the only
+/// state [`reporting_deviation`] is meaningful in.
+const FULL_LOAD_UTILIZATION: f64 = 0.95;
+/// [`reporting_deviation`] below which the board is taken to under-report
+/// its current, in percent. Honest boards stay within a few percent of 100.
+const MIN_REPORTING_DEVIATION: f64 = 90.0;
+
+/// Busy time each half of a window needs before [`load_migration`] says
+/// anything, in seconds. `/proc/stat` counts in ticks of 10 ms, so with
+/// less the shares are mostly rounding.
+const MIN_MIGRATION_BUSY: f64 = 0.1;
+/// [`load_migration`] from which the per-core power of a window is
+/// smeared across the cores the load ran on, in percent.
+const MAX_LOAD_MIGRATION: f64 = 30.0;
+/// Windows in a row above [`MAX_LOAD_MIGRATION`] before [`MigrationNoise`]
+/// recommends a longer interval. A single one is just a task starting.
+pub const MIGRATION_WINDOWS: u32 = 5;
+/// How much longer the recommended interval is.
+const MIGRATION_INTERVAL_FACTOR: u32 = 4;
+
+/// Clock ticks per second in `/proc/stat`, the same on every architecture.
+pub const USER_HZ: f64 = 100.0;
+
+/// Aggregate CPU time from the first line of `/proc/stat`, in clock ticks.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    pub fn read() -> Option<Self> {
+        let stat = fs::read_to_string("/proc/stat").ok()?;
+        let line = stat.lines().next()?;
+        Self::parse(line.strip_prefix("cpu ")?)
+    }
+
+    /// CPU time of every online CPU, from the `cpuN` lines of `/proc/stat`.
+    pub fn read_per_cpu() -> Option<BTreeMap<u32, Self>> {
+        let stat = fs::read_to_string("/proc/stat").ok()?;
+        let times = stat
+            .lines()
+            .filter_map(|line| {
+                let (cpu, fields) = line.strip_prefix("cpu")?.split_once(' ')?;
+                Some((cpu.parse().ok()?, Self::parse(fields)?))
+            })
+            .collect::<BTreeMap<_, _>>();
+        (!times.is_empty()).then_some(times)
+    }
+
+    /// Parses the fields of a `/proc/stat` line after the CPU name.
+    pub(crate) fn parse(fields: &str) -> Option<Self> {
+        let fields = fields
+            .split_whitespace()
+            .map(|val| val.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        // user nice system idle iowait irq softirq steal ...
+        let idle = fields.get(3)? + fields.get(4).unwrap_or(&0);
+        let total = fields.iter().take(8).sum::<u64>();
+
+        Some(Self {
+            busy: total - idle,
+            total,
+        })
+    }
+
+    /// Busy clock ticks between `self` and a later reading.
+    pub fn busy_until(&self, later: &Self) -> Option<u64> {
+        later.busy.checked_sub(self.busy)
+    }
+
+    /// Fraction of time spent busy between `self` and a later reading.
+    pub fn utilization_until(&self, later: &Self) -> Option<f64> {
+        let total = later.total.checked_sub(self.total)?;
+        let busy = later.busy.checked_sub(self.busy)?;
+        (total > 0).then(|| busy as f64 / total as f64)
+    }
+}
+
+/// Returns a diagnostic when the package power is implausibly low for the
+/// observed utilization or temperature.
+pub fn check_package_power(
+    package_power: f64,
+    utilization: Option<f64>,
+    temperature: Option<f64>,
+) -> Option<String> {
+    if package_power >= MIN_PLAUSIBLE_PACKAGE_POWER {
+        return None;
+    }
+
+    let reason = match (utilization, temperature) {
+        (Some(util), _) if util >= BUSY_UTILIZATION => {
+            format!("while the CPU was {:.0}% busy", util * 100.0)
+        }
+        (_, Some(temp)) if temp >= HOT_TEMPERATURE => {
+            format!("while Tctl is {:.1}°C", temp)
+        }
+        _ => return None,
+    };
+
+    Some(format!(
+        "package power reads {:.2}W {}. The energy counters are most likely not \
+         being updated, which is a known symptom of some AGESA firmware and \
+         kernel driver combinations; try a BIOS update or a different kernel",
+        package_power, reason
+    ))
+}
+
+/// The "power reporting deviation" of `sample` in percent: the PPT the SMU
+/// computes from the current the board reports, as a share of the package
+/// power of the energy counters, which the SMU models from activity and
+/// the board has no say in. `None` unless `--show smu` read the PPT and the
+/// CPU was fully loaded, the known load the model is calibrated for.
+///
+/// A board that reports less current than it delivers, the way some raise
+/// their boost behavior, lets the CPU draw more than its limits, and the
+/// deviation ends up well below 100.
+pub fn reporting_deviation(sample: &Sample) -> Option<f64> {
+    let (_, ppt) = sample.smu.get("ppt")?;
+    let fully_loaded = sample
+        .utilization
+        .is_some_and(|util| util >= FULL_LOAD_UTILIZATION);
+    (fully_loaded && sample.package_power >= MIN_PLAUSIBLE_PACKAGE_POWER)
+        .then(|| ppt / sample.package_power * 100.0)
+}
+
+/// Returns a diagnostic when `deviation` says the board under-reports.
+pub fn check_reporting_deviation(deviation: f64) -> Option<String> {
+    (deviation < MIN_REPORTING_DEVIATION).then(|| {
+        format!(
+            "the power reporting deviation is {:.1}% under full load: the board reports \
+             less current than the CPU draws, so the CPU runs past its power limits and \
+             every power reading of it is too low; look for a BIOS option that restores \
+             the reference telemetry",
+            deviation
+        )
+    })
+}
+
+/// How much of the load moved to other cores from the first to the second
+/// half of a window, in percent, given the busy seconds of each core in
+/// either half: half the summed change of every core's share of the busy
+/// time. 0 when each core kept its share, 100 when the load ran on entirely
+/// different cores. `None` with too little load to tell.
+pub fn load_migration(first: &BTreeMap<u32, f64>, second: &BTreeMap<u32, f64>) -> Option<f64> {
+    let first_total = first.values().sum::<f64>();
+    let second_total = second.values().sum::<f64>();
+    if first_total.min(second_total) < MIN_MIGRATION_BUSY {
+        return None;
+    }
+
+    let share =
+        |busy: &BTreeMap<u32, f64>, total: f64, core| busy.get(core).unwrap_or(&0.0) / total;
+    let moved = first
+        .keys()
+        .chain(second.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|core| (share(first, first_total, core) - share(second, second_total, core)).abs())
+        .sum::<f64>();
+    Some(moved / 2.0 * 100.0)
+}
+
+/// Notices the scheduler moving load between cores faster than the
+/// interval, window after window. The energy counters of a core only see
+/// its share of a window, so with the load hopping around every core's
+/// power is an average over whatever happened to run on it, and comparing
+/// cores says little. Longer windows average the hopping out.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationNoise {
+    windows: u32,
+    recommended: bool,
+}
+
+impl MigrationNoise {
+    /// Counts one window's [`load_migration`], returns a recommendation the
+    /// first time there were [`MIGRATION_WINDOWS`] noisy ones in a row.
+    pub fn check(&mut self, migration: Option<f64>, interval: Duration) -> Option<String> {
+        match migration {
+            Some(migration) if migration >= MAX_LOAD_MIGRATION => self.windows += 1,
+            // Too little load says nothing either way.
+            None => return None,
+            Some(_) => self.windows = 0,
+        }
+        if self.recommended || self.windows < MIGRATION_WINDOWS {
+            return None;
+        }
+
+        self.recommended = true;
+        Some(format!(
+            "load moved between cores within each of the last {} windows, so per-core \
+             power is smeared across the cores it ran on; an --interval of {:?} or \
+             longer averages that out",
+            self.windows,
+            interval * MIGRATION_INTERVAL_FACTOR
+        ))
+    }
+}
+
+/// Notices the package counter standing still over several windows while
+/// the CPU is doing something, like after the driver wedged or the VM was
+/// migrated to another host. One such window can be a fluke, a flat 0W for
+/// good is a broken reading.
+#[derive(Debug, Clone, Default)]
+pub struct Watchdog {
+    windows: u32,
+    duration: Duration,
+}
+
+impl Watchdog {
+    /// Counts one window, returns `true` after every [`STUCK_WINDOWS`]
+    /// stuck windows in a row, whenever the counters should be opened again.
+    pub fn check(&mut self, advanced: bool, utilization: Option<f64>, window: Duration) -> bool {
+        if advanced || !utilization.is_some_and(|utilization| utilization > 0.0) {
+            *self = Self::default();
+            return false;
+        }
+
+        self.windows += 1;
+        self.duration += window;
+        self.windows.is_multiple_of(STUCK_WINDOWS)
+    }
+
+    /// How long the counter has stood still, once it counts as stuck.
+    pub fn stuck_for(&self) -> Option<Duration> {
+        (self.windows >= STUCK_WINDOWS).then_some(self.duration)
+    }
+
+    pub fn stuck_windows(&self) -> u32 {
+        self.windows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn measures_load_moving_between_cores() {
+        let busy = |cores: &[(u32, f64)]| cores.iter().copied().collect::<BTreeMap<_, _>>();
+        let steady = busy(&[(0, 0.4), (1, 0.1)]);
+        assert_eq!(load_migration(&steady, &steady), Some(0.0));
+        assert_eq!(
+            load_migration(&steady, &busy(&[(2, 0.3), (3, 0.2)])),
+            Some(100.0)
+        );
+        // A fifth of the load went from core 0 to core 2.
+        let moved = load_migration(&steady, &busy(&[(0, 0.3), (1, 0.1), (2, 0.1)])).unwrap();
+        assert!((moved - 20.0).abs() < 1e-9, "{}", moved);
+        assert_eq!(load_migration(&busy(&[(0, 0.05)]), &steady), None);
+    }
+
+    #[test]
+    fn recommends_a_longer_interval_once() {
+        let mut noise = MigrationNoise::default();
+        for _ in 1..MIGRATION_WINDOWS {
+            assert_eq!(noise.check(Some(60.0), WINDOW), None);
+        }
+        // A quiet window starts the count over, idle ones leave it alone.
+        assert_eq!(noise.check(Some(5.0), WINDOW), None);
+        for _ in 1..MIGRATION_WINDOWS {
+            assert_eq!(noise.check(Some(60.0), WINDOW), None);
+            assert_eq!(noise.check(None, WINDOW), None);
+        }
+        let recommendation = noise.check(Some(60.0), WINDOW).unwrap();
+        assert!(
+            recommendation.contains("--interval of 4s"),
+            "{}",
+            recommendation
+        );
+        assert_eq!(noise.check(Some(60.0), WINDOW), None);
+    }
+
+    #[test]
+    fn reopens_after_every_few_stuck_windows() {
+        let mut watchdog = Watchdog::default();
+        let checks = (0..2 * STUCK_WINDOWS)
+            .map(|_| watchdog.check(false, Some(0.2), WINDOW))
+            .collect::<Vec<_>>();
+
+        assert_eq!(checks.iter().filter(|&&reopen| reopen).count(), 2);
+        assert!(checks[STUCK_WINDOWS as usize - 1]);
+        assert_eq!(watchdog.stuck_for(), Some(WINDOW * 2 * STUCK_WINDOWS));
+    }
+
+    #[test]
+    fn not_stuck_before_enough_windows() {
+        let mut watchdog = Watchdog::default();
+        for _ in 1..STUCK_WINDOWS {
+            assert!(!watchdog.check(false, Some(0.2), WINDOW));
+        }
+        assert_eq!(watchdog.stuck_for(), None);
+    }
+
+    #[test]
+    fn advancing_or_idle_windows_reset_it() {
+        let mut watchdog = Watchdog::default();
+        for (advanced, utilization) in [
+            (false, Some(0.2)),
+            (false, Some(0.2)),
+            (true, Some(0.2)),
+            (false, Some(0.2)),
+            (false, Some(0.0)),
+            (false, Some(0.2)),
+            (false, None),
+        ] {
+            assert!(!watchdog.check(advanced, utilization, WINDOW));
+        }
+        assert_eq!(watchdog.stuck_windows(), 0);
+    }
+
+    #[test]
+    fn frozen_counters_on_a_busy_cpu_are_implausible() {
+        assert!(check_package_power(0.0, Some(0.9), None).is_some());
+        assert!(check_package_power(0.0, None, Some(85.0)).is_some());
+        assert!(check_package_power(0.0, Some(0.1), Some(40.0)).is_none());
+        assert!(check_package_power(45.0, Some(0.9), Some(85.0)).is_none());
+    }
+
+    #[test]
+    fn busy_ticks_from_proc_stat() {
+        let before = CpuTimes::parse("100 0 50 800 50 0 0 0 0 0").unwrap();
+        let after = CpuTimes::parse("160 0 70 900 70 0 0 0 0 0").unwrap();
+        assert_eq!(before.busy_until(&after), Some(80));
+        assert_eq!(before.utilization_until(&after), Some(0.4));
+        assert_eq!(after.busy_until(&before), None);
+    }
+
+    #[test]
+    fn deviation_needs_the_ppt_and_full_load() {
+        let mut sample = Sample {
+            package_power: 100.0,
+            utilization: Some(1.0),
+            ..Sample::default()
+        };
+        assert_eq!(reporting_deviation(&sample), None);
+
+        sample
+            .smu
+            .insert("ppt", (crate::metrics::Unit::Watts, 82.0));
+        let deviation = reporting_deviation(&sample).unwrap();
+        assert!((deviation - 82.0).abs() < 1e-9);
+        assert!(check_reporting_deviation(deviation).is_some());
+        assert!(check_reporting_deviation(98.5).is_none());
+
+        sample.utilization = Some(0.6);
+        assert_eq!(reporting_deviation(&sample), None);
+    }
+}
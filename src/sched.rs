@@ -0,0 +1,544 @@
+//! Time every thread spent on each CPU from the `sched_switch` tracepoint,
+//! for `--sched`: each core's energy goes to what ran on that core, rather
+//! than the package's to every process by its share of the CPU time.
+//!
+//! An eBPF program would add the intervals up in the kernel, but loading one
+//! takes a BPF toolchain and a loader this crate doesn't have. The
+//! tracepoint's text in a tracefs instance of our own gets to the same sums
+//! with a line per context switch, read on a thread of its own. Timestamps
+//! are on the `mono` trace clock, so intervals still running at the end of
+//! a window are cut off at [`suspend::monotonic`]. Switches read after that
+//! cut only move the time between them and the cut to the thread before.
+//!
+//! Tracing needs root, or write access to tracefs. The instance goes away
+//! with the [`Tracer`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{cpu::Threads, process::Process, suspend};
+
+pub const TRACEFS: &str = "/sys/kernel/tracing";
+/// The instances kept apart from whatever else traces, below [`TRACEFS`],
+/// one per tracer as `ryzen-wattage-<PID>-<N>`.
+const INSTANCES: &str = "instances";
+const INSTANCE_PREFIX: &str = "ryzen-wattage-";
+
+/// `O_NONBLOCK` on Linux.
+const O_NONBLOCK: i32 = 0o4000;
+/// How long the reader waits for more switches once the pipe is empty.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// One line of `trace_pipe` for the tracepoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Switch {
+    pub cpu: u32,
+    /// Nanoseconds on the trace clock.
+    pub time: u64,
+    pub prev_pid: u32,
+    pub next_pid: u32,
+    pub next_comm: String,
+}
+
+impl Switch {
+    /// Parses a line like
+    /// `bash-1234 [003] d..2. 5127.000010: sched_switch: prev_comm=bash
+    /// prev_pid=1234 prev_prio=120 prev_state=S ==> next_comm=swapper/3
+    /// next_pid=0 next_prio=120`. Names can have spaces of their own.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (head, fields) = line.split_once(": sched_switch: ")?;
+        let (before, cpu_and_rest) = head.rsplit_once(']')?;
+        let (_, cpu) = before.rsplit_once('[')?;
+        let time = cpu_and_rest.split_whitespace().last()?;
+        let (secs, micros) = time.split_once('.')?;
+        let (prev, next) = fields.split_once(" ==> ")?;
+        let field = |fields: &str, name: &str| -> Option<String> {
+            let (_, rest) = fields.split_once(&format!("{}=", name))?;
+            let end = match name.ends_with("comm") {
+                // Up to the next field, whatever the name has in it.
+                true => rest
+                    .find(" prev_pid=")
+                    .or_else(|| rest.find(" next_pid="))?,
+                false => rest.find(' ').unwrap_or(rest.len()),
+            };
+            Some(rest[..end].to_owned())
+        };
+
+        Some(Self {
+            cpu: cpu.trim().parse().ok()?,
+            time: secs.parse::<u64>().ok()? * 1_000_000_000
+                + format!("{:0<9}", micros).parse::<u64>().ok()?,
+            prev_pid: field(prev, "prev_pid")?.parse().ok()?,
+            next_pid: field(next, "next_pid")?.parse().ok()?,
+            next_comm: field(next, "next_comm")?,
+        })
+    }
+}
+
+/// A thread's time on a CPU from being switched to until it was switched
+/// away from, for `--trace`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slice {
+    pub cpu: u32,
+    pub pid: u32,
+    pub name: String,
+    /// Nanoseconds on the trace clock.
+    pub start: u64,
+    pub end: u64,
+}
+
+/// The thread on a CPU.
+#[derive(Debug, Clone, Copy)]
+struct Running {
+    pid: u32,
+    /// Since when its time isn't counted yet.
+    since: u64,
+    /// When it was switched to.
+    start: u64,
+}
+
+/// Nanoseconds on each CPU per thread, added up from the switches.
+#[derive(Debug, Default)]
+struct Intervals {
+    /// Since the last [`Intervals::take`], by CPU and thread.
+    on_cpu: HashMap<(u32, u32), u64>,
+    running: HashMap<u32, Running>,
+    /// The name each thread had when it was switched to last, for threads
+    /// that are gone by the time they are looked up.
+    names: HashMap<u32, String>,
+    /// Every slice of a thread other than the idle ones, if they are kept.
+    slices: Option<Vec<Slice>>,
+}
+
+impl Intervals {
+    fn switch(&mut self, switch: Switch) {
+        let since = match self.running.get(&switch.cpu) {
+            Some(&running) if running.pid == switch.prev_pid => {
+                *self.on_cpu.entry((switch.cpu, running.pid)).or_default() +=
+                    switch.time.saturating_sub(running.since);
+                if let Some(slices) = self.slices.as_mut().filter(|_| running.pid != 0) {
+                    slices.push(Slice {
+                        cpu: switch.cpu,
+                        pid: running.pid,
+                        name: self.names.get(&running.pid).cloned().unwrap_or_default(),
+                        start: running.start,
+                        end: switch.time.max(running.start),
+                    });
+                }
+                running.since.max(switch.time)
+            }
+            Some(running) => running.since.max(switch.time),
+            None => switch.time,
+        };
+        let running = Running {
+            pid: switch.next_pid,
+            since,
+            start: switch.time,
+        };
+        self.running.insert(switch.cpu, running);
+        if switch.next_pid != 0 {
+            self.names.insert(switch.next_pid, switch.next_comm);
+        }
+    }
+
+    /// The time of every thread but the idle ones since the last call, with
+    /// the threads still running counted up to `now`.
+    fn take(&mut self, now: u64) -> HashMap<(u32, u32), u64> {
+        for (&cpu, running) in &mut self.running {
+            *self.on_cpu.entry((cpu, running.pid)).or_default() +=
+                now.saturating_sub(running.since);
+            running.since = running.since.max(now);
+        }
+        let mut on_cpu = std::mem::take(&mut self.on_cpu);
+        on_cpu.retain(|&(_, pid), &mut nanos| pid != 0 && nanos > 0);
+        let running = self
+            .running
+            .values()
+            .map(|running| running.pid)
+            .collect::<Vec<_>>();
+        self.names
+            .retain(|pid, _| on_cpu.keys().any(|&(_, seen)| seen == *pid) || running.contains(pid));
+        on_cpu
+    }
+}
+
+/// A tracefs instance with `sched_switch` on, and the thread reading it.
+#[derive(Debug)]
+pub struct Tracer {
+    dir: PathBuf,
+    intervals: Arc<Mutex<Intervals>>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Tracer {
+    /// Starts tracing in a fresh instance, keeping every [`Slice`] for
+    /// [`Tracer::take_slices`] with `slices`.
+    pub fn start(slices: bool) -> io::Result<Self> {
+        static STARTED: AtomicU32 = AtomicU32::new(0);
+
+        let instances = Path::new(TRACEFS).join(INSTANCES);
+        remove_stale(&instances);
+        let dir = instances.join(format!(
+            "{}{}-{}",
+            INSTANCE_PREFIX,
+            process::id(),
+            STARTED.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&dir)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", dir.display(), err)))?;
+        let opened = Self::open(&dir);
+        if opened.is_err() {
+            let _ = fs::remove_dir(&dir);
+        }
+        let pipe = opened?;
+
+        let intervals = Arc::new(Mutex::new(Intervals {
+            slices: slices.then(Vec::new),
+            ..Intervals::default()
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (intervals, stop) = (Arc::clone(&intervals), Arc::clone(&stop));
+            thread::spawn(move || read(pipe, &intervals, &stop))
+        };
+        Ok(Self {
+            dir,
+            intervals,
+            stop,
+            reader: Some(reader),
+        })
+    }
+
+    fn open(dir: &Path) -> io::Result<File> {
+        fs::write(dir.join("trace_clock"), "mono")?;
+        fs::write(dir.join("events/sched/sched_switch/enable"), "1")?;
+        fs::write(dir.join("tracing_on"), "1")?;
+        // Not blocking, so the reader can notice it is to stop.
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(O_NONBLOCK)
+            .open(dir.join("trace_pipe"))
+    }
+
+    /// Nanoseconds on each CPU per thread since the last call, and the
+    /// names of the threads as they were switched to.
+    fn take(&self) -> (HashMap<(u32, u32), u64>, HashMap<u32, String>) {
+        let now = suspend::monotonic().as_nanos() as u64;
+        let mut intervals = self.intervals.lock().unwrap();
+        let on_cpu = intervals.take(now);
+        (on_cpu, intervals.names.clone())
+    }
+
+    /// The slices that ended since the last call, none unless they are
+    /// kept.
+    pub fn take_slices(&self) -> Vec<Slice> {
+        let mut intervals = self.intervals.lock().unwrap();
+        intervals
+            .slices
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        let _ = fs::write(self.dir.join("tracing_on"), "0");
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// Removes the instances of tracers that were killed before they could.
+fn remove_stale(instances: &Path) {
+    let Ok(entries) = fs::read_dir(instances) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let pid = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(INSTANCE_PREFIX)?.split_once('-'))
+            .and_then(|(pid, _)| pid.parse::<u32>().ok());
+        if pid.is_some_and(|pid| !Path::new(&format!("/proc/{}", pid)).exists()) {
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+}
+
+fn read(pipe: File, intervals: &Mutex<Intervals>, stop: &AtomicBool) {
+    let mut lines = BufReader::new(pipe);
+    let mut line = String::new();
+    while !stop.load(Ordering::Relaxed) {
+        match lines.read_line(&mut line) {
+            Ok(0) => thread::sleep(POLL_INTERVAL),
+            // A line cut off by an empty pipe is read on once there is more.
+            Ok(_) if !line.ends_with('\n') => continue,
+            Ok(_) => {
+                if let Some(switch) = Switch::parse(line.trim_end()) {
+                    intervals.lock().unwrap().switch(switch);
+                }
+                line.clear();
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+}
+
+/// Splits windows of energy between processes by what ran on each core,
+/// like [`crate::process::NameMeter`] does by CPU time.
+#[derive(Debug)]
+pub struct SchedMeter {
+    tracer: Tracer,
+    /// The core of each CPU.
+    cores: HashMap<u32, u32>,
+}
+
+impl SchedMeter {
+    /// Starts tracing the CPUs of `threads`, the threads of each core.
+    pub fn start(threads: &Threads) -> io::Result<Self> {
+        let tracer = Tracer::start(false)?;
+        tracer.take();
+        Ok(Self {
+            tracer,
+            cores: cores(threads),
+        })
+    }
+
+    /// Joules per `key` since the last call. Each core's `core_joules` go
+    /// to the threads that ran on it by how long they did. Without per-core
+    /// energy, the package's `joules` go to every thread by its time on any
+    /// CPU.
+    pub fn split<K: Ord>(
+        &mut self,
+        joules: f64,
+        core_joules: &BTreeMap<u32, f64>,
+        key: impl FnMut(&Process) -> Option<K>,
+    ) -> BTreeMap<K, f64> {
+        let (on_cpu, names) = self.tracer.take();
+        let process = |pid| owner(pid, names.get(&pid));
+        split(&on_cpu, &self.cores, joules, core_joules, process, key)
+    }
+}
+
+fn cores(threads: &Threads) -> HashMap<u32, u32> {
+    threads
+        .iter()
+        .flat_map(|(&core, cpus)| cpus.iter().map(move |&cpu| (cpu, core)))
+        .collect()
+}
+
+fn split<K: Ord>(
+    on_cpu: &HashMap<(u32, u32), u64>,
+    cores: &HashMap<u32, u32>,
+    joules: f64,
+    core_joules: &BTreeMap<u32, f64>,
+    mut process: impl FnMut(u32) -> Option<Process>,
+    mut key: impl FnMut(&Process) -> Option<K>,
+) -> BTreeMap<K, f64> {
+    let per_core = !core_joules.is_empty();
+    let core = |cpu: u32| per_core.then(|| cores.get(&cpu).copied()).flatten();
+    let mut busy = HashMap::<Option<u32>, u64>::new();
+    for (&(cpu, _), &nanos) in on_cpu {
+        *busy.entry(core(cpu)).or_default() += nanos;
+    }
+
+    let mut processes = HashMap::<u32, Option<Process>>::new();
+    let mut split = BTreeMap::new();
+    for (&(cpu, pid), &nanos) in on_cpu {
+        let core = core(cpu);
+        let energy = match core {
+            Some(core) => core_joules.get(&core).copied().unwrap_or(0.0),
+            None if per_core => continue,
+            None => joules,
+        };
+        let owner = processes.entry(pid).or_insert_with(|| process(pid));
+        let Some(key) = owner.as_ref().and_then(&mut key) else {
+            continue;
+        };
+        *split.entry(key).or_default() += energy * nanos as f64 / busy[&core] as f64;
+    }
+    split
+}
+
+/// The process of thread `pid`, or a stand-in by the thread's `name` once
+/// it is gone.
+fn owner(pid: u32, name: Option<&String>) -> Option<Process> {
+    let thread_group = fs::read_to_string(format!("/proc/{}/status", pid))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("Tgid:"))?
+                .trim()
+                .parse()
+                .ok()
+        });
+    thread_group.and_then(Process::read).or_else(|| {
+        Some(Process {
+            pid,
+            ppid: 0,
+            name: name?.clone(),
+            ticks: 0,
+            children_ticks: 0,
+            start: 0,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn switch(cpu: u32, millis: u64, prev_pid: u32, next_pid: u32) -> Switch {
+        Switch {
+            cpu,
+            time: millis * 1_000_000,
+            prev_pid,
+            next_pid,
+            next_comm: format!("t{}", next_pid),
+        }
+    }
+
+    #[test]
+    fn parses_switches() {
+        let line =
+            "  Web Content-4711 [003] d..2. 5127.000010: sched_switch: prev_comm=Web Content \
+                    prev_pid=4711 prev_prio=120 prev_state=S ==> next_comm=swapper/3 next_pid=0 \
+                    next_prio=120";
+        assert_eq!(
+            Switch::parse(line),
+            Some(Switch {
+                cpu: 3,
+                time: 5_127_000_010_000,
+                prev_pid: 4711,
+                next_pid: 0,
+                next_comm: "swapper/3".to_owned(),
+            })
+        );
+        let line = "<idle>-0 [000] d..2. 1.5: sched_switch: prev_comm=swapper/0 prev_pid=0 \
+                    prev_prio=120 prev_state=R ==> next_comm=kworker/0:1 [x] next_pid=9 next_prio=120";
+        let switch = Switch::parse(line).unwrap();
+        assert_eq!(switch.time, 1_500_000_000);
+        assert_eq!(switch.next_comm, "kworker/0:1 [x]");
+        assert_eq!(Switch::parse("CPU:3 [LOST 12 EVENTS]"), None);
+    }
+
+    #[test]
+    fn adds_up_time_on_each_cpu() {
+        let mut intervals = Intervals::default();
+        // Before the first switch on a CPU nothing says who ran.
+        intervals.switch(switch(0, 0, 0, 10));
+        intervals.switch(switch(0, 4, 10, 0));
+        intervals.switch(switch(1, 1, 0, 20));
+        intervals.switch(switch(0, 6, 0, 10));
+
+        let on_cpu = intervals.take(10_000_000);
+        assert_eq!(
+            on_cpu,
+            HashMap::from([((0, 10), 8_000_000), ((1, 20), 9_000_000)])
+        );
+        assert_eq!(intervals.names[&20], "t20");
+
+        // Read after the cut, it only moves time from the cut on.
+        intervals.switch(switch(0, 9, 10, 30));
+        let on_cpu = intervals.take(12_000_000);
+        assert_eq!(on_cpu[&(0, 30)], 2_000_000);
+        assert!(!on_cpu.contains_key(&(0, 10)));
+    }
+
+    #[test]
+    fn keeps_slices_across_windows() {
+        let mut intervals = Intervals {
+            slices: Some(Vec::new()),
+            ..Intervals::default()
+        };
+        intervals.switch(switch(2, 1, 0, 10));
+        intervals.take(5_000_000);
+        intervals.switch(switch(2, 7, 10, 0));
+        intervals.switch(switch(2, 8, 0, 11));
+        assert_eq!(
+            intervals.slices.unwrap(),
+            [Slice {
+                cpu: 2,
+                pid: 10,
+                name: "t10".to_owned(),
+                start: 1_000_000,
+                end: 7_000_000,
+            }]
+        );
+    }
+
+    #[test]
+    fn splits_each_core_by_what_ran_on_it() {
+        let threads = Threads::from([(0, vec![0, 2]), (1, vec![1, 3])]);
+        let on_cpu = HashMap::from([
+            ((0, 101), 300),
+            ((2, 102), 100),
+            ((1, 101), 500),
+            ((3, 103), 500),
+        ]);
+        // Threads 101 and 102 are both of process 100.
+        let process = |pid| {
+            Some(Process {
+                pid: if pid == 103 { pid } else { 100 },
+                ppid: 1,
+                name: format!("p{}", pid),
+                ticks: 0,
+                children_ticks: 0,
+                start: 0,
+            })
+        };
+        let key = |process: &Process| Some(process.pid);
+
+        let core_joules = BTreeMap::from([(0, 8.0), (1, 4.0)]);
+        let split = split(&on_cpu, &cores(&threads), 20.0, &core_joules, process, key);
+        assert_eq!(split, BTreeMap::from([(100, 10.0), (103, 2.0)]));
+
+        // Without per-core energy the package's goes by the time on any CPU.
+        let split = split_package(&on_cpu, &threads, process, key);
+        assert_eq!(split, BTreeMap::from([(100, 18.0), (103, 10.0)]));
+    }
+
+    fn split_package(
+        on_cpu: &HashMap<(u32, u32), u64>,
+        threads: &Threads,
+        process: impl FnMut(u32) -> Option<Process>,
+        key: impl FnMut(&Process) -> Option<u32>,
+    ) -> BTreeMap<u32, f64> {
+        split(
+            on_cpu,
+            &cores(threads),
+            28.0,
+            &BTreeMap::new(),
+            process,
+            key,
+        )
+    }
+
+    #[test]
+    fn names_threads_that_are_gone() {
+        // Beyond the largest PID Linux hands out.
+        let process = owner(u32::MAX, Some(&"worker".to_owned())).unwrap();
+        assert_eq!(process.name, "worker");
+        assert!(owner(u32::MAX, None).is_none());
+    }
+}
@@ -0,0 +1,462 @@
+//! `--schema`: what the machine readable formats look like, as JSON Schema.
+//!
+//! Every JSON sample and NDJSON line carries `schema_version`, which is
+//! [`VERSION`]. Fields may be added without changing it; it goes up when a
+//! field is removed, renamed or changes its type, so consumers can refuse
+//! what they don't understand instead of misreading it.
+//!
+//! The fields come from [`METRICS`] like the formats themselves, so the
+//! schema can't fall behind them. There is no gRPC service and with it no
+//! protobuf descriptor; the daemon speaks the JSON lines of
+//! [`crate::daemon`], described by [`Document::Daemon`], which
+//! [`crate::codegen`] turns into clients in other languages. Applets read the
+//! file of [`crate::status`], described by [`Document::Status`].
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::{json::Value, metrics::METRICS};
+
+/// Version of the JSON, NDJSON and daemon formats.
+pub const VERSION: u32 = 1;
+
+/// A document with a schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    /// A sample of `--format json`.
+    Json,
+    /// A line of `--format ndjson`.
+    Ndjson,
+    /// A response of the daemon.
+    Daemon,
+    /// The `--status-file`.
+    Status,
+}
+
+impl FromStr for Document {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            "daemon" => Ok(Self::Daemon),
+            "status" => Ok(Self::Status),
+            other => Err(format!(
+                "no schema for `{}`, expected json, ndjson, daemon or status",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Integer,
+    Number,
+    Boolean,
+    String,
+    /// An object of numbers keyed by label value, like `{"0": 4.2}`.
+    NumberMap,
+    /// An object of strings.
+    StringMap,
+}
+
+/// A member of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub kind: Kind,
+    /// Whether numbers may be `null`, for values that couldn't be measured.
+    pub nullable: bool,
+    pub required: bool,
+    pub description: String,
+}
+
+impl Field {
+    fn new(name: &str, kind: Kind, required: bool, description: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind,
+            nullable: false,
+            required,
+            description: description.to_owned(),
+        }
+    }
+}
+
+/// The members of a JSON sample, in the order they are written.
+///
+/// Metrics are only there if `--show` and `--cores` keep them, the rest
+/// always is.
+pub fn sample_fields() -> Vec<Field> {
+    let mut fields = vec![
+        Field::new(
+            "schema_version",
+            Kind::Integer,
+            true,
+            "Version of this format",
+        ),
+        Field::new(
+            "timestamp",
+            Kind::String,
+            true,
+            "End of the sampling window, RFC 3339 unless --time-format says otherwise",
+        ),
+    ];
+
+    for metric in METRICS {
+        let kind = match metric.label {
+            Some(_) => Kind::NumberMap,
+            None => Kind::Number,
+        };
+        let keyed = metric
+            .label
+            .map(|label| format!(", by {}", label))
+            .unwrap_or_default();
+        let unit = match metric.unit.spelled() {
+            "" => String::new(),
+            spelled => format!(" in {}", spelled),
+        };
+        fields.push(Field {
+            nullable: true,
+            ..Field::new(
+                metric.name,
+                kind,
+                false,
+                &format!("{}{}{}", metric.help, unit, keyed),
+            )
+        });
+        if metric.has_uncertainty() {
+            fields.push(Field {
+                nullable: true,
+                ..Field::new(
+                    &format!("{}_uncertainty", metric.name),
+                    kind,
+                    false,
+                    &format!("Estimated error of {}{}", metric.name, unit),
+                )
+            });
+        }
+    }
+
+    fields.extend([
+        Field::new("smt_enabled", Kind::Boolean, true, "Whether SMT is enabled"),
+        Field::new("core_count", Kind::Integer, true, "Logical CPUs"),
+        Field::new("physical_core_count", Kind::Integer, true, "Physical cores"),
+        Field::new(
+            "backend",
+            Kind::String,
+            true,
+            "Where the energy comes from, msr or powercap",
+        ),
+        Field::new(
+            "core_counters",
+            Kind::Boolean,
+            true,
+            "Whether per-core energy counters are read",
+        ),
+        Field::new(
+            "core_counters_denied",
+            Kind::Boolean,
+            true,
+            "Whether reading per-core counters was not permitted",
+        ),
+        Field {
+            nullable: true,
+            ..Field::new(
+                "pm_table",
+                Kind::String,
+                true,
+                "Codename of the PM table layout the smu values were decoded with",
+            )
+        },
+        Field {
+            nullable: true,
+            ..Field::new(
+                "presence",
+                Kind::String,
+                true,
+                "Whether the desktop session was active, idle or locked, from logind",
+            )
+        },
+        Field::new(
+            "skew_seconds",
+            Kind::NumberMap,
+            true,
+            "How far apart from the energy counters utilization, threads, busy, \
+             temperature, frequency, gpu and smu were read, for those that were",
+        ),
+    ]);
+    fields
+}
+
+/// The members of an NDJSON line, one per series of a sample.
+pub fn ndjson_fields() -> Vec<Field> {
+    vec![
+        Field::new(
+            "name",
+            Kind::String,
+            true,
+            "Measurement name, always ryzen_wattage",
+        ),
+        Field::new(
+            "schema_version",
+            Kind::Integer,
+            true,
+            "Version of this format",
+        ),
+        Field::new(
+            "timestamp",
+            Kind::Integer,
+            true,
+            "End of the sampling window in nanoseconds since the Unix epoch",
+        ),
+        Field::new(
+            "tags",
+            Kind::StringMap,
+            true,
+            "host unless --redact leaves it out, and the label of the series, like core",
+        ),
+        Field::new(
+            "fields",
+            Kind::NumberMap,
+            true,
+            "Metrics of the series by name, only those that could be measured",
+        ),
+    ]
+}
+
+/// The members of the `--status-file`, all of them always there.
+pub fn status_fields() -> Vec<Field> {
+    let number = |name: &str, description: &str| Field {
+        nullable: true,
+        ..Field::new(name, Kind::Number, true, description)
+    };
+    vec![
+        Field::new(
+            "schema_version",
+            Kind::Integer,
+            true,
+            "Version of this format",
+        ),
+        Field::new(
+            "timestamp",
+            Kind::String,
+            true,
+            "End of the sampling window, RFC 3339 unless --time-format says otherwise",
+        ),
+        Field::new(
+            "interval_seconds",
+            Kind::Number,
+            true,
+            "How often the file is replaced, plus the time a sample takes",
+        ),
+        Field::new(
+            "stale_after_seconds",
+            Kind::Number,
+            true,
+            "Age of timestamp past which the process that wrote the file is gone",
+        ),
+        Field::new("pid", Kind::Integer, true, "Process that writes the file"),
+        number("package_watts", "Package power in watts"),
+        number("cores_watts", "Summed core power in watts"),
+    ]
+}
+
+/// JSON Schema (draft 2020-12) of `document`.
+pub fn json_schema(document: Document) -> String {
+    let schema = match document {
+        Document::Json => with_header(object_schema(&sample_fields()), "sample"),
+        Document::Ndjson => with_header(object_schema(&ndjson_fields()), "ndjson line"),
+        Document::Daemon => with_header(daemon_schema(), "daemon response"),
+        Document::Status => with_header(object_schema(&status_fields()), "status file"),
+    };
+    format!("{}\n", schema)
+}
+
+fn with_header(schema: Value, title: &str) -> Value {
+    let Value::Object(mut members) = schema else {
+        return schema;
+    };
+    members.insert(
+        "$schema".to_owned(),
+        string("https://json-schema.org/draft/2020-12/schema"),
+    );
+    members.insert(
+        "title".to_owned(),
+        string(&format!("ryzen-wattage {} v{}", title, VERSION)),
+    );
+    Value::Object(members)
+}
+
+fn object_schema(fields: &[Field]) -> Value {
+    let properties = fields
+        .iter()
+        .map(|field| {
+            let Value::Object(mut property) = kind_schema(field) else {
+                unreachable!("kinds are objects");
+            };
+            property.insert("description".to_owned(), string(&field.description));
+            if field.name == "schema_version" {
+                property.insert("const".to_owned(), Value::Number(f64::from(VERSION)));
+            }
+            (field.name.clone(), Value::Object(property))
+        })
+        .collect();
+    let required = fields
+        .iter()
+        .filter(|field| field.required)
+        .map(|field| string(&field.name))
+        .collect();
+    object([
+        ("type", string("object")),
+        ("properties", Value::Object(properties)),
+        ("required", Value::Array(required)),
+    ])
+}
+
+fn kind_schema(field: &Field) -> Value {
+    let number = || match field.nullable {
+        true => object([("type", Value::Array(vec![string("number"), string("null")]))]),
+        false => object([("type", string("number"))]),
+    };
+    match field.kind {
+        Kind::Integer => object([("type", string("integer")), ("minimum", Value::Number(0.0))]),
+        Kind::Number => number(),
+        Kind::Boolean => object([("type", string("boolean"))]),
+        Kind::String => object([("type", string("string"))]),
+        Kind::NumberMap => object([
+            ("type", string("object")),
+            ("additionalProperties", number()),
+        ]),
+        Kind::StringMap => object([
+            ("type", string("object")),
+            ("additionalProperties", object([("type", string("string"))])),
+        ]),
+    }
+}
+
+/// One response per request line, see [`crate::daemon`].
+fn daemon_schema() -> Value {
+    let sample = || object([("$ref", string("#/$defs/sample"))]);
+    let response = |name: &str, value: Value| {
+        object([
+            ("type", string("object")),
+            (
+                "properties",
+                object([
+                    ("ok", object([("const", Value::Bool(true))])),
+                    (name, value),
+                ]),
+            ),
+            ("required", Value::Array(vec![string("ok"), string(name)])),
+        ])
+    };
+    let process = object([
+        ("type", string("object")),
+        (
+            "properties",
+            object([
+                ("name", object([("type", string("string"))])),
+                ("joules", object([("type", string("number"))])),
+            ]),
+        ),
+        (
+            "required",
+            Value::Array(vec![string("name"), string("joules")]),
+        ),
+    ]);
+    let error = object([
+        ("type", string("object")),
+        (
+            "properties",
+            object([
+                ("ok", object([("const", Value::Bool(false))])),
+                ("error", object([("type", string("string"))])),
+            ]),
+        ),
+        (
+            "required",
+            Value::Array(vec![string("ok"), string("error")]),
+        ),
+    ]);
+    let array = |items: Value| object([("type", string("array")), ("items", items)]);
+
+    object([
+        (
+            "$defs",
+            object([("sample", object_schema(&sample_fields()))]),
+        ),
+        (
+            "oneOf",
+            Value::Array(vec![
+                response("sample", sample()),
+                response("samples", array(sample())),
+                response("processes", array(process)),
+                response("subscribed", object([("const", Value::Bool(true))])),
+                error,
+            ]),
+        ),
+    ])
+}
+
+fn object<'a>(members: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+    Value::Object(
+        members
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect::<BTreeMap<_, _>>(),
+    )
+}
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{json, output, status};
+    use std::time::Duration;
+
+    /// The keys of `document` and whether all required fields are there.
+    fn check(document: &Value, fields: &[Field]) {
+        let members = document.as_object().unwrap();
+        for key in members.keys() {
+            assert!(
+                fields.iter().any(|field| &field.name == key),
+                "`{}` is missing from the schema",
+                key
+            );
+        }
+        for field in fields.iter().filter(|field| field.required) {
+            assert!(members.contains_key(&field.name), "no `{}`", field.name);
+        }
+    }
+
+    #[test]
+    fn describes_what_the_formats_write() {
+        let sample = output::Sample::fixture();
+        let json = json::parse(&output::json(&sample)).unwrap();
+        check(&json, &sample_fields());
+        assert_eq!(
+            json.get("schema_version").and_then(Value::as_f64),
+            Some(f64::from(VERSION))
+        );
+
+        for line in output::ndjson(&sample, Some("host")).lines() {
+            check(&json::parse(line).unwrap(), &ndjson_fields());
+        }
+        let file = status::json(&sample, Duration::from_secs(1));
+        check(&json::parse(&file).unwrap(), &status_fields());
+
+        for document in [
+            Document::Json,
+            Document::Ndjson,
+            Document::Daemon,
+            Document::Status,
+        ] {
+            assert!(json::parse(&json_schema(document)).is_ok());
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! `init`: a few questions on first use, answered with a config file and,
+//! for the long running modes, a systemd unit.
+//!
+//! What it writes is listed in `installed` in the state directory, which
+//! `teardown` removes again along with the list, so that trying the tool can
+//! be undone without hunting for files. Nothing else is left behind: the
+//! polkit policy comes with the package and the tool sets no capabilities or
+//! udev rules of its own. The rest of the state directory is history rather
+//! than setup, the audit log, the ledger, captures, the spool, calibration
+//! and cargo baselines stay, and the directory goes only once it is empty.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use ryzen_wattage::{
+    cpuinfo::CpuInfo,
+    i18n::{tr, trf},
+    network, state,
+    sysfs::Root,
+    BackendKind, Cpu,
+};
+
+use crate::{args::parse_duration, config::Config};
+
+extern "C" {
+    fn geteuid() -> u32;
+}
+
+/// What `init` settled on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answers {
+    pub interval: Duration,
+    /// A backend that worked, `None` to leave it to auto-detection.
+    pub backend: Option<BackendKind>,
+    pub exporter: Option<String>,
+    pub daemon: bool,
+}
+
+/// Asks on `input` and `output`, then writes the config and the unit.
+pub fn init(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    let cpu = CpuInfo::read(&Root::system()).unwrap_or_default();
+    writeln!(output, "{}", trf("CPU: {}", &[&cpu.model_name]))?;
+    let mut backend = None;
+    for kind in [BackendKind::Msr, BackendKind::Powercap] {
+        match Cpu::new(kind) {
+            Ok(_) => {
+                writeln!(output, "{}", trf("{} backend: works", &[&kind.name()]))?;
+                backend = backend.or(Some(kind));
+            }
+            Err(err) => writeln!(output, "{}", trf("{} backend: {}", &[&kind.name(), &err]))?,
+        }
+    }
+    if backend.is_none() {
+        writeln!(
+            output,
+            "{}",
+            tr("No backend can read the energy counters as this user yet, \
+                the config leaves it to auto-detection")
+        )?;
+    }
+
+    let interval = loop {
+        let answer = ask(input, output, tr("Sampling interval"), "1s")?;
+        match parse_duration(&answer) {
+            Ok(interval) => break interval,
+            Err(err) => writeln!(output, "{}", err)?,
+        }
+    };
+    // Offline builds and those without the exporter have none to ask about.
+    let port = loop {
+        if !network::built_in() || !cfg!(feature = "exporter") {
+            break None;
+        }
+        let answer = ask(
+            input,
+            output,
+            tr("Port to serve Prometheus metrics on, empty for none"),
+            "",
+        )?;
+        match answer.parse::<u16>() {
+            _ if answer.is_empty() => break None,
+            Ok(port) if port > 0 => break Some(port),
+            _ => writeln!(output, "{}", trf("invalid port `{}`", &[&answer]))?,
+        }
+    };
+    let daemon = confirm(
+        input,
+        output,
+        tr("Serve the readings to status bars and scripts as a daemon?"),
+        false,
+    )?;
+    let answers = Answers {
+        interval,
+        backend,
+        exporter: port.map(|port| format!("127.0.0.1:{}", port)),
+        daemon,
+    };
+
+    let config_path = Config::default_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    let replace = !config_path.exists()
+        || confirm(
+            input,
+            output,
+            &trf("{} exists, replace it?", &[&config_path.display()]),
+            false,
+        )?;
+    if replace {
+        install(&config_path, &config_file(&answers))?;
+        writeln!(output, "{}", trf("wrote {}", &[&config_path.display()]))?;
+    }
+
+    if (answers.exporter.is_some() || answers.daemon)
+        && confirm(input, output, tr("Write a systemd unit for it?"), true)?
+    {
+        // SAFETY: geteuid can't fail and has no side effects.
+        let system = unsafe { geteuid() } == 0;
+        let (path, systemctl) = unit_path(system)?;
+        let program = env::current_exe()?;
+        install(&path, &unit_file(&answers, &program, &config_path, system))?;
+        writeln!(output, "{}", trf("wrote {}", &[&path.display()]))?;
+        writeln!(
+            output,
+            "{}",
+            trf(
+                "start it with: {} daemon-reload && {} enable --now ryzen-wattage",
+                &[&systemctl, &systemctl]
+            )
+        )?;
+    }
+    Ok(())
+}
+
+/// Removes what `init` wrote, after asking.
+pub fn teardown(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    match state::state_dir() {
+        Some(state_dir) => teardown_in(&state_dir, input, output),
+        None => writeln!(output, "{}", tr("Nothing to remove")),
+    }
+}
+
+/// [`teardown`] of what is listed in `state_dir`.
+fn teardown_in(
+    state_dir: &Path,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let list = state_dir.join(INSTALLED);
+    let installed = installed_in(state_dir);
+    if !list.exists() {
+        writeln!(output, "{}", tr("Nothing to remove"))?;
+        return Ok(());
+    }
+    for path in installed.iter().chain([&list]) {
+        writeln!(output, "  {}", path.display())?;
+    }
+    if !confirm(input, output, tr("Remove these?"), false)? {
+        return Ok(());
+    }
+
+    for path in &installed {
+        // A unit still running would be restarted from the missing file.
+        if let Some(systemctl) = systemctl_of(path) {
+            let _ = Command::new("systemctl")
+                .args(systemctl)
+                .args(["disable", "--now", "ryzen-wattage"])
+                .stdin(Stdio::null())
+                .status();
+        }
+        match fs::remove_file(path) {
+            Ok(()) => {
+                writeln!(output, "{}", trf("removed {}", &[&path.display()]))?;
+                // The config's own directory, if nothing else is in it.
+                if let Some(dir) = path.parent().filter(|dir| dir.ends_with("ryzen-wattage")) {
+                    let _ = fs::remove_dir(dir);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => writeln!(
+                output,
+                "{}",
+                trf("cannot remove {}: {}", &[&path.display(), &err])
+            )?,
+        }
+        if let Some(systemctl) = systemctl_of(path) {
+            let _ = Command::new("systemctl")
+                .args(systemctl)
+                .arg("daemon-reload")
+                .stdin(Stdio::null())
+                .status();
+        }
+    }
+    fs::remove_file(&list)?;
+    writeln!(output, "{}", trf("removed {}", &[&list.display()]))?;
+    // Only if nothing else is kept in it.
+    if fs::remove_dir(state_dir).is_ok() {
+        writeln!(output, "{}", trf("removed {}", &[&state_dir.display()]))?;
+    }
+    Ok(())
+}
+
+/// The arguments systemctl needs for the unit at `path`, `None` if it
+/// isn't one.
+fn systemctl_of(path: &Path) -> Option<&'static [&'static str]> {
+    if path.extension()? != "service" {
+        return None;
+    }
+    match path.starts_with("/etc/systemd/system") {
+        true => Some(&[]),
+        false => Some(&["--user"]),
+    }
+}
+
+/// Asks `question`, `default` if the answer is empty or there is none.
+fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: &str,
+) -> io::Result<String> {
+    match default {
+        "" => write!(output, "{}: ", question)?,
+        default => write!(output, "{} [{}]: ", question, default)?,
+    }
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(match answer.trim() {
+        "" => default.to_owned(),
+        answer => answer.to_owned(),
+    })
+}
+
+fn confirm(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: bool,
+) -> io::Result<bool> {
+    let choices = match default {
+        true => "Y/n",
+        false => "y/N",
+    };
+    loop {
+        let answer = ask(input, output, &format!("{} [{}]", question, choices), "")?;
+        match answer.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" | "j" | "ja" => return Ok(true),
+            "n" | "no" | "nein" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+pub fn config_file(answers: &Answers) -> String {
+    let mut out = format!(
+        "# Written by `ryzen-wattage init`, options on the command line override it.\n\
+         interval = \"{}\"\n",
+        duration(answers.interval)
+    );
+    if let Some(backend) = answers.backend {
+        writeln!(out, "backend = \"{}\"", backend.name()).unwrap();
+    }
+    if let Some(exporter) = &answers.exporter {
+        writeln!(out, "exporter = \"{}\"", exporter).unwrap();
+    }
+    out
+}
+
+/// `interval` the way [`parse_duration`] reads it back.
+fn duration(interval: Duration) -> String {
+    match interval.as_millis() {
+        millis if millis % 1000 == 0 => format!("{}s", millis / 1000),
+        millis => format!("{}ms", millis),
+    }
+}
+
+/// The unit of a system service for root, of a user service otherwise.
+pub fn unit_file(answers: &Answers, program: &Path, config: &Path, system: bool) -> String {
+    let mut command = format!(
+        "{} --config {} --service",
+        program.display(),
+        config.display()
+    );
+    if answers.exporter.is_some() {
+        command += " --exporter";
+    }
+    if answers.daemon {
+        command += " --daemon";
+    }
+    let target = match system {
+        true => "multi-user.target",
+        false => "default.target",
+    };
+    format!(
+        "[Unit]\n\
+         Description=Ryzen package and core power metrics\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={}\n\
+         ExecReload=/bin/kill -HUP $MAINPID\n\
+         WatchdogSec=30\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy={}\n",
+        command, target
+    )
+}
+
+/// Where the unit goes and the systemctl that manages it.
+fn unit_path(system: bool) -> io::Result<(PathBuf, &'static str)> {
+    if system {
+        return Ok((
+            PathBuf::from("/etc/systemd/system/ryzen-wattage.service"),
+            "systemctl",
+        ));
+    }
+    let config_dir = Config::default_path()
+        .and_then(|path| Some(path.parent()?.parent()?.to_owned()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    Ok((
+        config_dir.join("systemd/user/ryzen-wattage.service"),
+        "systemctl --user",
+    ))
+}
+
+/// Writes `contents` to `path` and lists it for `teardown`.
+fn install(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)?;
+
+    let state_dir = state::state_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    let mut installed = installed_in(&state_dir);
+    if !installed.iter().any(|other| other == path) {
+        installed.push(path.to_owned());
+    }
+    fs::create_dir_all(&state_dir)?;
+    let contents = installed
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect::<String>();
+    fs::write(state_dir.join(INSTALLED), contents)
+}
+
+/// The list of what `init` wrote, in the state directory.
+const INSTALLED: &str = "installed";
+
+/// The files `init` wrote.
+pub fn installed() -> Vec<PathBuf> {
+    state::state_dir()
+        .map(|state_dir| installed_in(&state_dir))
+        .unwrap_or_default()
+}
+
+fn installed_in(state_dir: &Path) -> Vec<PathBuf> {
+    fs::read_to_string(state_dir.join(INSTALLED))
+        .map(|contents| contents.lines().map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_what_was_answered() {
+        let answers = Answers {
+            interval: Duration::from_millis(500),
+            backend: Some(BackendKind::Powercap),
+            exporter: Some("127.0.0.1:9977".to_owned()),
+            daemon: true,
+        };
+        let config = Config::parse(&config_file(&answers)).unwrap();
+        assert_eq!(config.interval, Some(answers.interval));
+        assert_eq!(config.backend, answers.backend);
+        assert_eq!(config.exporter, answers.exporter);
+
+        let unit = unit_file(
+            &answers,
+            Path::new("/usr/bin/ryzen-wattage"),
+            Path::new("/etc/ryzen-wattage.toml"),
+            false,
+        );
+        assert!(unit.contains(
+            "ExecStart=/usr/bin/ryzen-wattage --config /etc/ryzen-wattage.toml --service \
+             --exporter --daemon\n"
+        ));
+        assert!(unit.ends_with("WantedBy=default.target\n"));
+    }
+
+    #[test]
+    fn teardown_keeps_what_init_did_not_write() {
+        let dir = env::temp_dir().join(format!("ryzen-wattage-teardown-{}", std::process::id()));
+        let state_dir = dir.join("state");
+        let config = dir.join("config/ryzen-wattage/config.toml");
+        fs::create_dir_all(config.parent().unwrap()).unwrap();
+        fs::create_dir_all(&state_dir).unwrap();
+        fs::write(&config, "interval = \"1s\"\n").unwrap();
+        fs::write(state_dir.join(INSTALLED), format!("{}\n", config.display())).unwrap();
+        let kept = ["audit.log", "machine.ledger", "otlp.spool", "machine.state"];
+        for name in kept {
+            fs::write(state_dir.join(name), "").unwrap();
+        }
+
+        let mut output = Vec::new();
+        teardown_in(&state_dir, &mut "y\n".as_bytes(), &mut output).unwrap();
+        assert!(!config.exists());
+        assert!(!config.parent().unwrap().exists());
+        assert!(!state_dir.join(INSTALLED).exists());
+        for name in kept {
+            assert!(state_dir.join(name).exists(), "{} is gone", name);
+        }
+
+        // Once nothing else is left the directory goes too.
+        for name in kept {
+            fs::remove_file(state_dir.join(name)).unwrap();
+        }
+        fs::write(state_dir.join(INSTALLED), "").unwrap();
+        teardown_in(&state_dir, &mut "y\n".as_bytes(), &mut output).unwrap();
+        assert!(!state_dir.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
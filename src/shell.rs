@@ -0,0 +1,280 @@
+//! Interactive prompt for exploratory measurement sessions.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::Path,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use ryzen_wattage::{
+    cpu::Snapshot,
+    i18n::{self, tr, Lang},
+    output::{self, CsvLog, Sample, TextOptions},
+    quirks::Quirks,
+    state::Calibration,
+    topology, Cpu,
+};
+
+use crate::{
+    args::{parse_duration, Format, Show},
+    log, measure,
+};
+
+const HELP: &str = "\
+Commands:
+  sample [TIME]            Take one sample, over TIME or the current interval
+  watch [TIME]             Keep sampling until Enter is pressed
+  interval TIME            Set the sampling interval, e.g. 500ms
+  format text|json         Set the output format
+  cores LIST|all           Only show cores in LIST, e.g. 0-7,12
+  export csv|json FILE     Write the samples taken so far to FILE
+  clear                    Forget the samples taken so far
+  help                     Print this help
+  quit                     Leave the shell
+";
+
+const HELP_DE: &str = "\
+Befehle:
+  sample [ZEIT]            Eine Messung über ZEIT oder das aktuelle Intervall
+  watch [ZEIT]             Messen, bis Enter gedrückt wird
+  interval ZEIT            Messintervall setzen, z.B. 500ms
+  format text|json         Ausgabeformat setzen
+  cores LISTE|all          Nur Kerne aus LISTE anzeigen, z.B. 0-7,12
+  export csv|json DATEI    Bisherige Messungen in DATEI schreiben
+  clear                    Bisherige Messungen verwerfen
+  help                     Diese Hilfe anzeigen
+  quit                     Die Shell verlassen
+";
+
+fn help() -> &'static str {
+    match i18n::lang() {
+        Lang::En => HELP,
+        Lang::De => HELP_DE,
+    }
+}
+
+pub struct Shell<'a> {
+    cpu: &'a Cpu,
+    quirks: &'a Quirks,
+    calibration: &'a Calibration,
+    groups: &'a BTreeMap<String, Vec<u32>>,
+    text_options: &'a TextOptions,
+    pub format: Format,
+    pub interval: Duration,
+    pub show: Show,
+    /// Cores to keep in samples, all if `None`.
+    cores: Option<Vec<u32>>,
+    /// Everything sampled this session, for `export`.
+    samples: Vec<Sample>,
+}
+
+impl<'a> Shell<'a> {
+    pub fn new(
+        cpu: &'a Cpu,
+        quirks: &'a Quirks,
+        calibration: &'a Calibration,
+        groups: &'a BTreeMap<String, Vec<u32>>,
+        text_options: &'a TextOptions,
+    ) -> Self {
+        Self {
+            cpu,
+            quirks,
+            calibration,
+            groups,
+            text_options,
+            format: Format::Text,
+            interval: Duration::from_secs(1),
+            show: Show::default(),
+            cores: None,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Reads commands from stdin until `quit` or end of input.
+    pub fn run(&mut self) {
+        // Lines come from a thread so `watch` can check for Enter while it
+        // samples.
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        loop {
+            print!("ryzen-wattage> ");
+            let _ = io::stdout().flush();
+
+            let line = match lines.recv() {
+                Ok(Ok(line)) => line,
+                Ok(Err(err)) => {
+                    log::error(format_args!("cannot read input: {}", err));
+                    break;
+                }
+                Err(_) => {
+                    println!();
+                    break;
+                }
+            };
+
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                [] => {}
+                ["quit" | "exit"] => break,
+                words => {
+                    if let Err(err) = self.execute(words, &lines) {
+                        log::error(err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute(
+        &mut self,
+        words: &[&str],
+        lines: &Receiver<io::Result<String>>,
+    ) -> Result<(), String> {
+        match words {
+            ["help"] => print!("{}", help()),
+            ["sample"] => self.sample(self.interval)?,
+            ["sample", time] => self.sample(parse_duration(time)?)?,
+            ["watch"] => self.watch(self.interval, lines)?,
+            ["watch", time] => self.watch(parse_duration(time)?, lines)?,
+            ["interval", time] => self.interval = parse_duration(time)?,
+            ["format", format] => {
+                self.format = match format.parse()? {
+                    Format::Statusbar | Format::Waybar => {
+                        return Err("the shell prints text or json only".to_owned())
+                    }
+                    format => format,
+                };
+            }
+            ["cores", "all"] => self.cores = None,
+            ["cores", list] => {
+                let cores = topology::parse_cpulist(list)
+                    .filter(|cores| !cores.is_empty())
+                    .ok_or_else(|| format!("invalid core list `{}`", list))?;
+                self.cores = Some(cores);
+            }
+            ["export", format, path] => self.export(format, Path::new(path))?,
+            ["clear"] => self.samples.clear(),
+            [command, ..] => {
+                return Err(format!(
+                    "unknown command or wrong arguments for `{}`, try `help`",
+                    command
+                ))
+            }
+            [] => {}
+        }
+
+        Ok(())
+    }
+
+    fn sample(&mut self, interval: Duration) -> Result<(), String> {
+        let before = self.cpu.snapshot().map_err(|err| err.to_string())?;
+        self.measure(&before, interval)?;
+        Ok(())
+    }
+
+    fn watch(
+        &mut self,
+        interval: Duration,
+        lines: &Receiver<io::Result<String>>,
+    ) -> Result<(), String> {
+        log::info(tr("press Enter to stop"));
+
+        let mut before = self.cpu.snapshot().map_err(|err| err.to_string())?;
+        loop {
+            before = self.measure(&before, interval)?;
+
+            match lines.try_recv() {
+                Err(TryRecvError::Empty) => {}
+                Ok(_) | Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+
+            if self.format == Format::Text {
+                println!();
+            }
+        }
+    }
+
+    /// Takes and prints one sample starting at `before`, returning where it
+    /// ended.
+    fn measure(&mut self, before: &Snapshot, interval: Duration) -> Result<Snapshot, String> {
+        let (mut sample, after) = measure(
+            self.cpu,
+            self.quirks,
+            self.calibration,
+            self.groups,
+            self.show,
+            before,
+            interval,
+        )
+        .map_err(|err| err.to_string())?;
+
+        if let Some(cores) = &self.cores {
+            sample.core_power.retain(|core, _| cores.contains(core));
+            sample
+                .uncertainty
+                .cores
+                .retain(|core, _| cores.contains(core));
+            sample.highest_perf.retain(|core, _| cores.contains(core));
+            sample.core_frequency.retain(|core, _| cores.contains(core));
+            sample.core_idle.retain(|core, _| cores.contains(core));
+            let threads = self
+                .cpu
+                .threads
+                .iter()
+                .filter(|(core, _)| cores.contains(core));
+            let threads = threads.flat_map(|(_, threads)| threads).collect::<Vec<_>>();
+            sample
+                .thread_power
+                .retain(|thread, _| threads.contains(&thread));
+        }
+
+        match self.format {
+            Format::Json => println!("{}", output::json_view(&sample, &self.text_options.view)),
+            _ => print!("{}", output::text(&sample, self.text_options)),
+        }
+
+        self.samples.push(sample);
+        Ok(after)
+    }
+
+    fn export(&self, format: &str, path: &Path) -> Result<(), String> {
+        let result = match format {
+            "csv" => CsvLog::open(path).and_then(|mut log| {
+                self.samples
+                    .iter()
+                    .try_for_each(|sample| log.append(sample))
+            }),
+            "json" => {
+                let lines = self
+                    .samples
+                    .iter()
+                    .map(|sample| output::json(sample) + "\n")
+                    .collect::<String>();
+                std::fs::write(path, lines)
+            }
+            other => {
+                return Err(format!(
+                    "unknown export format `{}`, expected csv or json",
+                    other
+                ))
+            }
+        };
+
+        result.map_err(|err| format!("cannot write {}: {}", path.display(), err))?;
+        log::notice(i18n::trf(
+            "wrote {} samples to {}",
+            &[&self.samples.len(), &path.display()],
+        ));
+        Ok(())
+    }
+}
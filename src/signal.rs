@@ -0,0 +1,160 @@
+//! Catching Ctrl-C, so long running modes can clean up and summarize
+//! instead of dying mid-output, and SIGHUP, which asks `--service` to
+//! reload its configuration.
+//!
+//! Waiting between samples wakes the CPU once: [`sleep`] blocks on an
+//! eventfd the signal handler writes to, whichever thread it runs on, with
+//! a timeout, rather than checking for a signal every few milliseconds. At
+//! intervals of a second and more the timeout gets a percent of slack, so
+//! the kernel can fold the wakeup into another one.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+const SIGHUP: i32 = 1;
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+const EFD_NONBLOCK: i32 = 0o4000;
+const EFD_CLOEXEC: i32 = 0o2000000;
+const POLLIN: i16 = 1;
+const PR_SET_TIMERSLACK: i32 = 29;
+
+/// Sleeps from this long on get [`SLACK`] of their length.
+const SLACK_FROM: Duration = Duration::from_secs(1);
+/// Per mille of a long sleep the wakeup may come late by.
+const SLACK: u32 = 10;
+const MAX_SLACK: Duration = Duration::from_millis(50);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HUNG_UP: AtomicBool = AtomicBool::new(false);
+/// The eventfd [`sleep`] waits on, -1 until [`catch_interrupts`].
+static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    fn eventfd(initval: u32, flags: i32) -> i32;
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    fn ppoll(fds: *mut PollFd, nfds: u64, timeout: *const Timespec, sigmask: *const u8) -> i32;
+    fn prctl(option: i32, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> i32;
+}
+
+extern "C" fn on_interrupt(_: i32) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    let wake = WAKE.load(Ordering::SeqCst);
+    if wake >= 0 {
+        // SAFETY: write is async signal safe and the buffer lives on the
+        // stack for the call. The eventfd is never closed.
+        unsafe {
+            write(wake, 1u64.to_ne_bytes().as_ptr(), 8);
+        }
+    }
+}
+
+extern "C" fn on_hangup(_: i32) {
+    HUNG_UP.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGINT and SIGTERM set [`interrupted`] instead of terminating the
+/// process.
+pub fn catch_interrupts() {
+    if WAKE.load(Ordering::SeqCst) < 0 {
+        // SAFETY: eventfd only takes integers. Without one, sleep polls.
+        let wake = unsafe { eventfd(0, EFD_NONBLOCK | EFD_CLOEXEC) };
+        WAKE.store(wake, Ordering::SeqCst);
+    }
+    // SAFETY: the handler only stores to atomics and writes to an eventfd,
+    // which is async signal safe.
+    unsafe {
+        signal(SIGINT, on_interrupt);
+        signal(SIGTERM, on_interrupt);
+    }
+}
+
+/// Whether SIGINT or SIGTERM arrived since [`catch_interrupts`].
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Makes SIGHUP set [`take_hangup`] instead of terminating the process.
+pub fn catch_hangups() {
+    // SAFETY: as in catch_interrupts.
+    unsafe {
+        signal(SIGHUP, on_hangup);
+    }
+}
+
+/// Whether SIGHUP arrived since the last call.
+pub fn take_hangup() -> bool {
+    HUNG_UP.swap(false, Ordering::SeqCst)
+}
+
+/// Sleeps for `duration`, but returns early once [`interrupted`]. Plain
+/// sleeps just carry on after a signal.
+pub fn sleep(duration: Duration) {
+    let wake = WAKE.load(Ordering::SeqCst);
+    let slack = (duration >= SLACK_FROM).then(|| (duration * SLACK / 1000).min(MAX_SLACK));
+    if let Some(slack) = slack {
+        // SAFETY: prctl only takes integers, this one sets the slack of
+        // this thread's timers.
+        unsafe { prctl(PR_SET_TIMERSLACK, slack.as_nanos() as u64, 0, 0, 0) };
+    }
+
+    let end = Instant::now() + duration;
+    while !interrupted() {
+        let left = end.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        if wake < 0 {
+            // Before catch_interrupts or without an eventfd, in steps.
+            thread::sleep(left.min(Duration::from_millis(50)));
+            continue;
+        }
+        let mut fds = PollFd {
+            fd: wake,
+            events: POLLIN,
+            revents: 0,
+        };
+        let timeout = Timespec {
+            tv_sec: left.as_secs() as i64,
+            tv_nsec: i64::from(left.subsec_nanos()),
+        };
+        // SAFETY: both outlive the call, which writes only to `fds`. A
+        // signal makes it return early, it is looped on like a timeout.
+        unsafe { ppoll(&mut fds, 1, &timeout, std::ptr::null()) };
+    }
+
+    if slack.is_some() {
+        // SAFETY: as above, 0 goes back to the default slack.
+        unsafe { prctl(PR_SET_TIMERSLACK, 0, 0, 0, 0) };
+    }
+}
+
+/// Times the thread gave up the CPU so far, each a wakeup of its core when
+/// it runs again.
+pub fn wakeups() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/thread-self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("voluntary_ctxt_switches:"))?
+        .trim()
+        .parse()
+        .ok()
+}
@@ -0,0 +1,317 @@
+//! Where samples go besides the terminal: the CSV log, MQTT and OTLP.
+//!
+//! A failing sink doesn't stop sampling. Its errors are counted in its
+//! [`Health`], which the exporter serves on `/healthz` and as metrics and
+//! the exit summary reports. Network sinks run on a [`Worker`] thread with a
+//! bounded queue in front, so a slow or unreachable broker can't stretch the
+//! sampling windows. Samples that don't fit the queue are dropped and
+//! counted.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::output::Sample;
+
+/// Samples a worker queues while it is busy delivering.
+pub const QUEUE: usize = 64;
+
+/// Somewhere samples are delivered to, one at a time.
+pub trait Sink: Send + 'static {
+    fn deliver(&mut self, sample: &Sample) -> io::Result<()>;
+
+    /// How long a sink may go without a sample before [`Sink::idle`] is
+    /// called, for keeping connections alive.
+    fn idle_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn idle(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How a sink has been doing, shared with whoever reports on it.
+#[derive(Debug)]
+pub struct Health {
+    /// Like `mqtt`.
+    pub name: &'static str,
+    /// The file or URL samples go to.
+    pub target: String,
+    status: Mutex<Status>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub delivered: u64,
+    pub failed: u64,
+    /// Samples that didn't fit the queue.
+    pub dropped: u64,
+    /// Whether the last delivery failed.
+    pub failing: bool,
+    pub last_error: Option<String>,
+    pub last_delivered: Option<SystemTime>,
+}
+
+impl Health {
+    pub fn new(name: &'static str, target: String) -> Self {
+        Self {
+            name,
+            target,
+            status: Mutex::new(Status::default()),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Counts a delivery, true if the sink was failing until now.
+    pub fn delivered(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        status.delivered += 1;
+        status.last_delivered = Some(SystemTime::now());
+        std::mem::take(&mut status.failing)
+    }
+
+    /// Counts a failure, true if it is the first of an outage.
+    pub fn failed(&self, err: &io::Error) -> bool {
+        let mut status = self.status.lock().unwrap();
+        status.failed += 1;
+        status.last_error = Some(err.to_string());
+        !std::mem::replace(&mut status.failing, true)
+    }
+
+    pub fn dropped(&self) {
+        self.status.lock().unwrap().dropped += 1;
+    }
+
+    /// Counts the `result` of a delivery, calling `report` when an outage
+    /// starts or ends.
+    pub fn deliver(
+        &self,
+        result: io::Result<()>,
+        report: &impl Fn(&Health, Change),
+    ) -> io::Result<()> {
+        match &result {
+            Ok(()) if self.delivered() => report(self, Change::Recovered),
+            Err(err) if self.failed(err) => report(self, Change::Failing(err)),
+            _ => {}
+        }
+        result
+    }
+}
+
+/// An outage of a sink starting or ending, to log once instead of every
+/// sample.
+#[derive(Debug)]
+pub enum Change<'a> {
+    Failing(&'a io::Error),
+    Recovered,
+}
+
+/// A thread delivering samples to one sink.
+#[derive(Debug)]
+pub struct Worker {
+    sender: Option<SyncSender<Sample>>,
+    health: Arc<Health>,
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    pub fn spawn<S: Sink>(
+        mut sink: S,
+        health: Arc<Health>,
+        report: impl Fn(&Health, Change) + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Sample>(QUEUE);
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let health = Arc::clone(&health);
+            let stopping = Arc::clone(&stopping);
+            thread::spawn(move || loop {
+                let received = match sink.idle_interval() {
+                    Some(interval) => receiver.recv_timeout(interval),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let sample = match received {
+                    Ok(sample) => sample,
+                    Err(RecvTimeoutError::Timeout) => {
+                        let result = sink.idle();
+                        if let Err(err) = &result {
+                            if health.failed(err) {
+                                report(&health, Change::Failing(err));
+                            }
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                if stopping.load(Ordering::Relaxed) {
+                    // Only the newest of what is left, and not into an outage
+                    // that would hold up exiting.
+                    let sample = receiver.try_iter().last().unwrap_or(sample);
+                    if !health.status().failing {
+                        let _ = health.deliver(sink.deliver(&sample), &report);
+                    }
+                    break;
+                }
+                let _ = health.deliver(sink.deliver(&sample), &report);
+            })
+        };
+
+        Self {
+            sender: Some(sender),
+            health,
+            stopping,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queues `sample`, dropping it if the queue is full.
+    pub fn send(&self, sample: &Sample) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        match sender.try_send(sample.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.health.dropped(),
+            // The thread only ends after the sender is gone.
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    pub fn health(&self) -> &Arc<Health> {
+        &self.health
+    }
+}
+
+impl Drop for Worker {
+    /// Delivers the newest queued sample and waits for the sink to close.
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+
+    /// Fails while `failing` is set and reports every delivery. The first
+    /// one waits for the `gate` to open if there is one.
+    struct Flaky {
+        failing: Arc<AtomicBool>,
+        delivered: SyncSender<f64>,
+        gate: Option<(SyncSender<()>, Receiver<()>)>,
+    }
+
+    impl Sink for Flaky {
+        fn deliver(&mut self, sample: &Sample) -> io::Result<()> {
+            if let Some((waiting, gate)) = self.gate.take() {
+                waiting.send(()).unwrap();
+                gate.recv().unwrap();
+            }
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(io::Error::other("broker down"));
+            }
+            let _ = self.delivered.send(sample.package_power);
+            Ok(())
+        }
+    }
+
+    fn sample(package_power: f64) -> Sample {
+        Sample {
+            package_power,
+            ..Sample::fixture()
+        }
+    }
+
+    #[test]
+    fn counts_outages_once() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let (delivered, deliveries) = mpsc::sync_channel(QUEUE);
+        let (changes, reported) = mpsc::channel();
+        let health = Arc::new(Health::new("test", "nowhere".to_owned()));
+        let changes = Mutex::new(changes);
+        let worker = Worker::spawn(
+            Flaky {
+                failing: Arc::clone(&failing),
+                delivered,
+                gate: None,
+            },
+            Arc::clone(&health),
+            move |_, change| {
+                let change = matches!(change, Change::Recovered);
+                changes.lock().unwrap().send(change).unwrap();
+            },
+        );
+
+        worker.send(&sample(1.0));
+        worker.send(&sample(2.0));
+        assert!(!reported.recv().unwrap());
+        while health.status().failed < 2 {
+            thread::yield_now();
+        }
+        failing.store(false, Ordering::Relaxed);
+        worker.send(&sample(3.0));
+        assert_eq!(deliveries.recv().unwrap(), 3.0);
+        assert!(reported.recv().unwrap());
+
+        drop(worker);
+        let status = health.status();
+        assert_eq!((status.delivered, status.failed, status.dropped), (1, 2, 0));
+        assert!(!status.failing);
+        assert_eq!(status.last_error.as_deref(), Some("broker down"));
+        assert!(reported.try_recv().is_err());
+    }
+
+    #[test]
+    fn drops_what_does_not_fit_the_queue() {
+        let (delivered, deliveries) = mpsc::sync_channel(2 * QUEUE);
+        let (waiting, waits) = mpsc::sync_channel(1);
+        let (open, gate) = mpsc::sync_channel(1);
+        let health = Arc::new(Health::new("test", "nowhere".to_owned()));
+        let worker = Worker::spawn(
+            Flaky {
+                failing: Arc::new(AtomicBool::new(false)),
+                delivered,
+                gate: Some((waiting, gate)),
+            },
+            Arc::clone(&health),
+            |_, _| {},
+        );
+
+        // The first waits at the gate while the queue fills up behind it.
+        worker.send(&sample(0.0));
+        waits.recv().unwrap();
+        for n in 1..=QUEUE + 5 {
+            worker.send(&sample(n as f64));
+        }
+        assert_eq!(health.status().dropped, 5);
+
+        // Stopping skips to the newest sample.
+        let stopper = thread::spawn(move || drop(worker));
+        thread::sleep(Duration::from_millis(50));
+        open.send(()).unwrap();
+        stopper.join().unwrap();
+        assert_eq!(
+            deliveries.try_iter().collect::<Vec<_>>(),
+            [0.0, QUEUE as f64]
+        );
+    }
+}
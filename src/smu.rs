@@ -0,0 +1,216 @@
+//! The SMU's PM table, the telemetry its firmware keeps for itself, read
+//! through the out-of-tree [`ryzen_smu`](https://gitlab.com/leogx9r/ryzen_smu)
+//! driver for `--show smu`.
+//!
+//! The driver hands out the table as raw bytes in
+//! `/sys/kernel/ryzen_smu_drv/pm_table`, along with the version of its
+//! layout in `pm_table_version`. The layout changes with every generation
+//! and often with firmware updates, and AMD documents none of them, so only
+//! the [`LAYOUTS`] that are known are decoded and only their first few
+//! values, which have stayed put across versions: the power, current and
+//! temperature the SMU limits the package by, and those limits. Tables of
+//! other versions are left alone rather than guessed at.
+//!
+//! There is deliberately no way around the driver. The SMU mailbox is
+//! reached through the SMN index/data pair at 0x60 and 0x64 in the config
+//! space of the root complex, and the kernel uses the same pair, k10temp
+//! for every Tctl reading among others, under its `amd_smn_mutex`.
+//! Userspace can't take that lock, so a kernel access between our index
+//! and data writes sends the mailbox command to whatever register the
+//! kernel selected. No amount of testing makes that safe, taking the lock
+//! is what `ryzen_smu` is for.
+
+use std::{collections::BTreeMap, fs};
+
+use crate::{metrics::Unit, sysfs::Root};
+
+const DRIVER: &str = "/sys/kernel/ryzen_smu_drv";
+
+/// A value of the table, a little-endian `f32` at `offset`.
+#[derive(Debug, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    pub unit: Unit,
+}
+
+/// A layout and the table versions that have it.
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    pub codename: &'static str,
+    pub versions: &'static [u32],
+    pub fields: &'static [Field],
+}
+
+const fn field(name: &'static str, offset: usize, unit: Unit) -> Field {
+    Field { name, offset, unit }
+}
+
+/// Desktop and server parts, limited by PPT, TDC, EDC and THM.
+const SOCKET: &[Field] = &[
+    field("ppt_limit", 0x000, Unit::Watts),
+    field("ppt", 0x004, Unit::Watts),
+    field("tdc_limit", 0x008, Unit::Amperes),
+    field("tdc", 0x00c, Unit::Amperes),
+    field("thm_limit", 0x010, Unit::Celsius),
+    field("thm", 0x014, Unit::Celsius),
+    field("edc_limit", 0x020, Unit::Amperes),
+    field("edc", 0x024, Unit::Amperes),
+];
+
+/// APUs, limited by STAPM and the fast and slow PPT instead.
+const MOBILE: &[Field] = &[
+    field("stapm_limit", 0x000, Unit::Watts),
+    field("stapm", 0x004, Unit::Watts),
+    field("fast_limit", 0x008, Unit::Watts),
+    field("fast", 0x00c, Unit::Watts),
+    field("slow_limit", 0x010, Unit::Watts),
+    field("slow", 0x014, Unit::Watts),
+    field("tdc_limit", 0x020, Unit::Amperes),
+    field("tdc", 0x024, Unit::Amperes),
+    field("thm_limit", 0x040, Unit::Celsius),
+    field("thm", 0x044, Unit::Celsius),
+];
+
+/// The layouts `ryzen_monitor` and `ryzenadj` agree on.
+pub const LAYOUTS: &[Layout] = &[
+    Layout {
+        codename: "Matisse",
+        versions: &[0x240802, 0x240803, 0x240902, 0x240903],
+        fields: SOCKET,
+    },
+    Layout {
+        codename: "Vermeer",
+        versions: &[0x380804, 0x380805, 0x380904, 0x380905],
+        fields: SOCKET,
+    },
+    Layout {
+        codename: "Raphael",
+        versions: &[0x540104, 0x540105, 0x540108],
+        fields: SOCKET,
+    },
+    Layout {
+        codename: "Renoir",
+        versions: &[0x370000, 0x370001, 0x370002, 0x370003, 0x370004, 0x370005],
+        fields: MOBILE,
+    },
+    Layout {
+        codename: "Cezanne",
+        versions: &[0x400001, 0x400002, 0x400003, 0x400004, 0x400005],
+        fields: MOBILE,
+    },
+    Layout {
+        codename: "Rembrandt",
+        versions: &[0x450004, 0x450005],
+        fields: MOBILE,
+    },
+    Layout {
+        codename: "Phoenix",
+        versions: &[0x4c0006, 0x4c0007, 0x4c0008, 0x4c0009],
+        fields: MOBILE,
+    },
+];
+
+/// The table of this machine, once its layout is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PmTable {
+    pub layout: &'static Layout,
+    pub version: u32,
+}
+
+impl PmTable {
+    /// Finds the layout of the table `ryzen_smu` exports below `root`, or
+    /// says why there is none.
+    pub fn open(root: &Root) -> Result<Self, String> {
+        let path = root.path(DRIVER).join("pm_table_version");
+        let version = match fs::read(&path) {
+            Ok(bytes) => bytes
+                .get(..4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(|| format!("{} is too short", path.display()))?,
+            Err(_) if !root.path(DRIVER).exists() => {
+                return Err("the ryzen_smu driver is not loaded".to_owned())
+            }
+            Err(err) => return Err(format!("cannot read {}: {}", path.display(), err)),
+        };
+        let layout = LAYOUTS
+            .iter()
+            .find(|layout| layout.versions.contains(&version))
+            .ok_or_else(|| format!("unknown PM table version {:#x}", version))?;
+        Ok(Self { layout, version })
+    }
+
+    /// The values of the table, empty if it can't be read right now.
+    pub fn read(&self, root: &Root) -> BTreeMap<&'static str, (Unit, f64)> {
+        fs::read(root.path(DRIVER).join("pm_table"))
+            .map(|table| decode(self.layout, &table))
+            .unwrap_or_default()
+    }
+}
+
+/// The fields of `layout` in `table`, leaving out those past its end and
+/// those that aren't numbers.
+pub fn decode(layout: &Layout, table: &[u8]) -> BTreeMap<&'static str, (Unit, f64)> {
+    layout
+        .fields
+        .iter()
+        .filter_map(|field| {
+            let bytes = table.get(field.offset..field.offset + 4)?;
+            let value = f64::from(f32::from_le_bytes(bytes.try_into().unwrap()));
+            value
+                .is_finite()
+                .then_some((field.name, (field.unit, value)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    fn table(values: &[(usize, f32)]) -> Vec<u8> {
+        let mut table = vec![0; 0x100];
+        for (offset, value) in values {
+            table[*offset..*offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        table
+    }
+
+    #[test]
+    fn decodes_known_versions_only() {
+        let fixture = Fixture::new();
+        assert_eq!(
+            PmTable::open(fixture.root()),
+            Err("the ryzen_smu driver is not loaded".to_owned())
+        );
+
+        let version = fixture.root().path(DRIVER).join("pm_table_version");
+        fs::create_dir_all(version.parent().unwrap()).unwrap();
+        fs::write(&version, 0x380805u32.to_le_bytes()).unwrap();
+        fs::write(
+            fixture.root().path(DRIVER).join("pm_table"),
+            table(&[(0x000, 142.0), (0x004, 88.5), (0x014, f32::NAN)]),
+        )
+        .unwrap();
+        let pm_table = PmTable::open(fixture.root()).unwrap();
+        assert_eq!(pm_table.layout.codename, "Vermeer");
+        let values = pm_table.read(fixture.root());
+        assert_eq!(values["ppt_limit"], (Unit::Watts, 142.0));
+        assert_eq!(values["ppt"], (Unit::Watts, 88.5));
+        assert!(!values.contains_key("thm"));
+
+        fs::write(&version, 0x123456u32.to_le_bytes()).unwrap();
+        assert_eq!(
+            PmTable::open(fixture.root()),
+            Err("unknown PM table version 0x123456".to_owned())
+        );
+    }
+
+    #[test]
+    fn leaves_out_fields_past_the_end() {
+        let values = decode(&LAYOUTS[3], &table(&[(0x044, 71.5)])[..0x28]);
+        assert_eq!(values.len(), 8);
+        assert!(!values.contains_key("thm"));
+    }
+}
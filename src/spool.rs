@@ -0,0 +1,236 @@
+//! `--spool`: what a network sink couldn't deliver, kept on disk until it
+//! can.
+//!
+//! Every undelivered body is appended to a file in the state directory with
+//! the time of its sample, one line each, so a restart doesn't lose them
+//! either. Once the endpoint answers again they are sent oldest first, at
+//! most [`REPLAY_BATCH`] per delivery so catching up doesn't stall the sink
+//! for minutes. Bodies older than the retention are dropped, and so are the
+//! oldest ones once the file would outgrow [`MAX_BYTES`].
+//!
+//! The first line has the version of the [`FORMAT`], a spool of a newer one
+//! is neither replayed nor added to.
+//!
+//! Only OTLP spools. MQTT publishes retained state topics that only ever
+//! hold the latest reading, replaying old ones would just flash past.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::migrate::{self, Format};
+
+/// Largest a spool file gets.
+pub const MAX_BYTES: usize = 16 * 1024 * 1024;
+/// Bodies replayed per delivery.
+pub const REPLAY_BATCH: usize = 100;
+
+const HEADER: &str = "# ryzen-wattage spool ";
+
+/// Version 0 is the spool from before it had one, the same lines without
+/// the first.
+pub const FORMAT: Format<String> = Format {
+    name: "spool",
+    first: 0,
+    current: 1,
+    migrations: &[|contents| contents],
+};
+
+#[derive(Debug)]
+pub struct Spool {
+    path: PathBuf,
+    retention: Duration,
+}
+
+impl Spool {
+    pub fn new(path: PathBuf, retention: Duration) -> Self {
+        Self { path, retention }
+    }
+
+    /// `<state dir>/<name>.spool`.
+    pub fn in_state_dir(name: &str, retention: Duration) -> Option<Self> {
+        let path = crate::state::state_dir()?.join(format!("{}.spool", name));
+        Some(Self::new(path, retention))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keeps `body` of a sample from `time` for later.
+    pub fn push(&self, time: SystemTime, body: &str) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let line = format!("{} {}\n", unix_seconds(time), body.replace('\n', " "));
+        let size = fs::metadata(&self.path).map_or(0, |metadata| metadata.len() as usize);
+        // A new spool or one of another version is written anew.
+        if size + line.len() > MAX_BYTES || self.version()? != Some(FORMAT.current) {
+            let mut pending = self.pending()?;
+            pending.push((unix_seconds(time), body.replace('\n', " ")));
+            return self.write(&pending);
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+
+    /// Sends what is kept, oldest first and up to [`REPLAY_BATCH`], until
+    /// `send` fails. Returns how many were sent.
+    pub fn replay(&self, mut send: impl FnMut(&str) -> io::Result<()>) -> io::Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let pending = self.pending()?;
+        let mut sent = 0;
+        let mut result = Ok(());
+        for (_, body) in pending.iter().take(REPLAY_BATCH) {
+            result = send(body);
+            if result.is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        self.write(&pending[sent..])?;
+        result.map(|()| sent)
+    }
+
+    /// How many bodies are kept.
+    pub fn len(&self) -> usize {
+        self.pending().map_or(0, |pending| pending.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The version of the spool file, `None` if there is none.
+    fn version(&self) -> io::Result<Option<u32>> {
+        let mut first = String::new();
+        match File::open(&self.path) {
+            Ok(file) => BufReader::new(file).read_line(&mut first)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        Ok(Some(
+            migrate::line_version(first.as_bytes(), HEADER).unwrap_or(0),
+        ))
+    }
+
+    /// Bodies within the retention, oldest first, and within
+    /// [`MAX_BYTES`] with the newest ones kept.
+    fn pending(&self) -> io::Result<Vec<(u64, String)>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let version = migrate::line_version(contents.as_bytes(), HEADER).unwrap_or(0);
+        let contents = FORMAT
+            .migrate(version, contents)
+            .map_err(io::Error::other)?;
+        let oldest = unix_seconds(SystemTime::now()).saturating_sub(self.retention.as_secs());
+        let mut pending = contents
+            .lines()
+            .filter_map(|line| {
+                let (time, body) = line.split_once(' ')?;
+                Some((time.parse::<u64>().ok()?, body.to_owned()))
+            })
+            .filter(|(time, _)| *time >= oldest)
+            .collect::<Vec<_>>();
+
+        let mut size = 0;
+        let keep = pending
+            .iter()
+            .rev()
+            .take_while(|(time, body)| {
+                size += time.to_string().len() + body.len() + 2;
+                size <= MAX_BYTES
+            })
+            .count();
+        pending.drain(..pending.len() - keep);
+        Ok(pending)
+    }
+
+    fn write(&self, pending: &[(u64, String)]) -> io::Result<()> {
+        if pending.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            };
+        }
+        let mut contents = format!("{}{}\n", HEADER, FORMAT.current);
+        for (time, body) in pending {
+            contents.push_str(&format!("{} {}\n", time, body));
+        }
+        // Not truncating the old one before the new one is complete.
+        let partial = self.path.with_extension("spool.partial");
+        fs::write(&partial, contents)?;
+        fs::rename(partial, &self.path)
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn replays_oldest_first_until_sending_fails() {
+        let path = env::temp_dir().join(format!("ryzen-wattage-spool-{}", process::id()));
+        let spool = Spool::new(path.clone(), Duration::from_secs(3600));
+        let now = SystemTime::now();
+        spool
+            .push(now - Duration::from_secs(7200), "expired")
+            .unwrap();
+        for body in ["first", "second", "third"] {
+            spool.push(now, body).unwrap();
+        }
+        assert_eq!(spool.len(), 3);
+
+        let mut sent = Vec::new();
+        let result = spool.replay(|body| {
+            if body == "third" {
+                return Err(io::Error::other("collector down"));
+            }
+            sent.push(body.to_owned());
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(sent, ["first", "second"]);
+        assert_eq!(spool.len(), 1);
+
+        assert_eq!(spool.replay(|_| Ok(())).unwrap(), 1);
+        assert!(spool.is_empty());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn migrates_unversioned_spools_and_refuses_newer_ones() {
+        let path = env::temp_dir().join(format!("ryzen-wattage-spool-v0-{}", process::id()));
+        let spool = Spool::new(path.clone(), Duration::from_secs(3600));
+        let now = unix_seconds(SystemTime::now());
+        fs::write(&path, format!("{} first\n", now)).unwrap();
+        spool.push(SystemTime::now(), "second").unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# ryzen-wattage spool 1\n"));
+        assert_eq!(spool.len(), 2);
+
+        fs::write(&path, format!("# ryzen-wattage spool 2\n{} later\n", now)).unwrap();
+        assert!(spool.push(SystemTime::now(), "third").is_err());
+        assert!(spool.replay(|_| Ok(())).is_err());
+        assert!(fs::read_to_string(&path).unwrap().ends_with("later\n"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! Per-machine state that should survive reinstalls and updates: the
+//! backend auto-detection settled on, the quirks that were applied and
+//! calibration factors. Next to it a [`TopologyCache`], which only lives as
+//! long as the boot.
+//!
+//! Each machine gets its own file, named after a hash of its DMI
+//! identifiers, so a shared home directory doesn't mix up machines.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{cpu::Threads, sysfs::Root, topology, BackendKind};
+
+const DMI_PATH: &str = "/sys/class/dmi/id";
+const DMI_FIELDS: &[&str] = &["sys_vendor", "product_name", "board_vendor", "board_name"];
+
+/// Scale factors applied to measured power, 1.0 by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub package: f64,
+    pub cores: f64,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            package: 1.0,
+            cores: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct State {
+    pub backend: Option<BackendKind>,
+    pub quirks: Vec<String>,
+    pub calibration: Calibration,
+}
+
+impl State {
+    /// Loads the state of this machine, the default if there is none yet.
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| Self::parse(&contents))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, self.serialize())
+    }
+
+    /// `$XDG_STATE_HOME/ryzen-wattage/<machine id>`.
+    pub fn path() -> Option<PathBuf> {
+        Some(state_dir()?.join(machine_id(&Root::system())))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut state = Self::default();
+
+        for line in contents.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim() {
+                "backend" => state.backend = value.parse().ok(),
+                "quirks" => {
+                    state.quirks = value
+                        .split(',')
+                        .filter(|id| !id.is_empty())
+                        .map(str::to_owned)
+                        .collect();
+                }
+                "calibration.package" => {
+                    state.calibration.package = value.parse().unwrap_or(1.0);
+                }
+                "calibration.cores" => state.calibration.cores = value.parse().unwrap_or(1.0),
+                _ => {}
+            }
+        }
+
+        state
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = String::from("# ryzen-wattage machine state, safe to edit or delete\n");
+
+        if let Some(backend) = self.backend {
+            writeln!(out, "backend={}", backend.name()).unwrap();
+        }
+        writeln!(out, "quirks={}", self.quirks.join(",")).unwrap();
+        writeln!(out, "calibration.package={}", self.calibration.package).unwrap();
+        writeln!(out, "calibration.cores={}", self.calibration.cores).unwrap();
+
+        out
+    }
+}
+
+/// What the cached topology is only valid for. A new boot or CPUs going
+/// on- or offline changes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopologyKey {
+    /// `/proc/sys/kernel/random/boot_id`.
+    pub boot_id: String,
+    /// Online CPUs as the kernel lists them, e.g. `0-15`.
+    pub online: String,
+    /// `/sys/devices/system/cpu/smt/control`.
+    pub smt: String,
+}
+
+/// The physical cores of this machine, so starting up doesn't need to walk
+/// the sysfs directory of every CPU again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyCache {
+    pub key: TopologyKey,
+    pub threads: Threads,
+}
+
+impl TopologyCache {
+    /// The cached cores if they were cached for `key`.
+    pub fn load(key: &TopologyKey) -> Option<Threads> {
+        let contents = fs::read_to_string(Self::path()?).ok()?;
+        Self::parse(&contents)
+            .filter(|cache| cache.key == *key)
+            .map(|cache| cache.threads)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, self.serialize())
+    }
+
+    /// `$XDG_STATE_HOME/ryzen-wattage/<machine id>.topology`.
+    pub fn path() -> Option<PathBuf> {
+        Some(state_dir()?.join(format!("{}.topology", machine_id(&Root::system()))))
+    }
+
+    /// `None` unless the whole key and at least one core are there.
+    pub fn parse(contents: &str) -> Option<Self> {
+        let (mut boot_id, mut online, mut smt) = (None, None, None);
+        let mut threads = Threads::new();
+
+        for line in contents.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_owned();
+
+            match key.trim() {
+                "boot_id" => boot_id = Some(value),
+                "online" => online = Some(value),
+                "smt" => smt = Some(value),
+                key => {
+                    let core = key.strip_prefix("core")?.parse().ok()?;
+                    threads.insert(core, topology::parse_cpulist(&value)?);
+                }
+            }
+        }
+
+        Some(Self {
+            key: TopologyKey {
+                boot_id: boot_id?,
+                online: online?,
+                smt: smt?,
+            },
+            threads: Some(threads).filter(|threads| !threads.is_empty())?,
+        })
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = String::from("# ryzen-wattage topology cache, rebuilt when deleted\n");
+
+        writeln!(out, "boot_id={}", self.key.boot_id).unwrap();
+        writeln!(out, "online={}", self.key.online).unwrap();
+        writeln!(out, "smt={}", self.key.smt).unwrap();
+        for (core, threads) in &self.threads {
+            writeln!(out, "core{}={}", core, topology::format_cpulist(threads)).unwrap();
+        }
+
+        out
+    }
+}
+
+/// `$XDG_STATE_HOME/ryzen-wattage`, by default in `~/.local/state`.
+pub fn state_dir() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+
+    Some(state_home.join("ryzen-wattage"))
+}
+
+/// Stable identifier of this machine derived from its DMI data.
+pub fn machine_id(root: &Root) -> String {
+    let identifiers = DMI_FIELDS
+        .iter()
+        .map(|field| fs::read_to_string(root.path(DMI_PATH).join(field)).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\0");
+
+    format!("{:016x}", fnv1a(identifiers.as_bytes()))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    fn cache() -> TopologyCache {
+        TopologyCache {
+            key: TopologyKey {
+                boot_id: "82dd090f-7164-4708-ac7f-49ab64e8bf68".to_owned(),
+                online: "0-2,4-6".to_owned(),
+                smt: "on".to_owned(),
+            },
+            threads: Threads::from([(0, vec![0, 4]), (1, vec![1, 5]), (2, vec![2, 6])]),
+        }
+    }
+
+    #[test]
+    fn topology_cache_round_trips() {
+        let cache = cache();
+        assert_eq!(TopologyCache::parse(&cache.serialize()), Some(cache));
+    }
+
+    #[test]
+    fn incomplete_topology_caches_are_ignored() {
+        let serialized = cache().serialize();
+        let without_boot_id = serialized
+            .lines()
+            .filter(|line| !line.starts_with("boot_id="))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(TopologyCache::parse(&without_boot_id), None);
+        assert_eq!(TopologyCache::parse("boot_id=a\nonline=0\nsmt=off\n"), None);
+        assert_eq!(TopologyCache::parse(&(serialized + "core3=x\n")), None);
+    }
+
+    #[test]
+    fn machine_id_follows_the_dmi_data() {
+        let board = |name: &str| {
+            let fixture = Fixture::new();
+            fixture
+                .file(
+                    "/sys/class/dmi/id/sys_vendor",
+                    "Micro-Star International Co., Ltd.\n",
+                )
+                .file("/sys/class/dmi/id/board_name", name);
+            machine_id(fixture.root())
+        };
+        assert_eq!(board("MAG B550 TOMAHAWK\n"), board("MAG B550 TOMAHAWK\n"));
+        assert_ne!(board("MAG B550 TOMAHAWK\n"), board("B450 GAMING PLUS\n"));
+        assert_eq!(machine_id(Fixture::new().root()).len(), 16);
+    }
+}
@@ -0,0 +1,687 @@
+//! Statistics over many samples.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::AddAssign,
+    time::Duration,
+};
+
+use crate::output::{Presence, Sample};
+
+/// Minimum, maximum, mean and standard deviation of a series, updated one
+/// value at a time with Welford's algorithm.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    mean: f64,
+    /// Sum of squared differences from the mean.
+    m2: f64,
+}
+
+impl Stats {
+    pub fn push(&mut self, value: f64) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// NaN without values.
+    pub fn mean(&self) -> f64 {
+        match self.count {
+            0 => f64::NAN,
+            _ => self.mean,
+        }
+    }
+
+    /// Sample standard deviation, NaN with fewer than two values.
+    pub fn stddev(&self) -> f64 {
+        match self.count {
+            0 | 1 => f64::NAN,
+            count => (self.m2 / (count - 1) as f64).sqrt(),
+        }
+    }
+
+    /// Half width of the 95% confidence interval of the mean, NaN with fewer
+    /// than two values.
+    pub fn confidence_interval(&self) -> f64 {
+        match self.count {
+            0 | 1 => f64::NAN,
+            count => t_quantile_975((count - 1) as f64) * self.stddev() / (count as f64).sqrt(),
+        }
+    }
+}
+
+/// A running total with Neumaier's compensated summation. Adding a week of
+/// millisecond energy deltas to a plain `f64` loses the low bits of every
+/// one of them, this keeps the total exact to a few ulps.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sum {
+    sum: f64,
+    /// The low-order bits lost from `sum` so far.
+    compensation: f64,
+}
+
+impl Sum {
+    pub fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+impl AddAssign<f64> for Sum {
+    fn add_assign(&mut self, value: f64) {
+        let sum = self.sum + value;
+        // Whichever operand is smaller lost bits in the addition.
+        self.compensation += match self.sum.abs() >= value.abs() {
+            true => (self.sum - sum) + value,
+            false => (value - sum) + self.sum,
+        };
+        self.sum = sum;
+    }
+}
+
+/// Power statistics and total energy of one domain over many samples.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PowerSummary {
+    pub power: Stats,
+    /// Joules, the sum of power times window length.
+    pub energy: Sum,
+}
+
+impl PowerSummary {
+    fn push(&mut self, power: f64, window: Duration) {
+        self.power.push(power);
+        self.energy += power * window.as_secs_f64();
+    }
+}
+
+/// Every value of a series, for percentiles and a histogram, where the
+/// mean hides how bursty it was.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Distribution {
+    /// Kept sorted.
+    values: Vec<f64>,
+}
+
+/// Values from `low` up to `high`, the last bucket includes `high`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub low: f64,
+    pub high: f64,
+    pub count: usize,
+}
+
+impl Distribution {
+    pub fn push(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        let index = self.values.partition_point(|&other| other <= value);
+        self.values.insert(index, value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// How many values are less than `value`.
+    pub fn count_below(&self, value: f64) -> usize {
+        self.values.partition_point(|&other| other < value)
+    }
+
+    /// The value below which `percent` of the values lie, interpolated
+    /// between the two closest ones. NaN without values.
+    pub fn percentile(&self, percent: f64) -> f64 {
+        let Some(last) = self.values.len().checked_sub(1) else {
+            return f64::NAN;
+        };
+        let rank = percent.clamp(0.0, 100.0) / 100.0 * last as f64;
+        let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+        let fraction = rank - below as f64;
+        self.values[below] + (self.values[above] - self.values[below]) * fraction
+    }
+
+    /// `count` buckets of equal width from the lowest to the highest value,
+    /// just one if they are all the same.
+    pub fn buckets(&self, count: usize) -> Vec<Bucket> {
+        let (Some(&low), Some(&high)) = (self.values.first(), self.values.last()) else {
+            return Vec::new();
+        };
+        let count = match high > low {
+            true => count.max(1),
+            false => 1,
+        };
+        let width = (high - low) / count as f64;
+
+        let mut buckets = (0..count)
+            .map(|index| Bucket {
+                low: low + width * index as f64,
+                high: match index + 1 == count {
+                    true => high,
+                    false => low + width * (index + 1) as f64,
+                },
+                count: 0,
+            })
+            .collect::<Vec<_>>();
+        for &value in &self.values {
+            let index = match width > 0.0 {
+                true => (((value - low) / width) as usize).min(count - 1),
+                false => 0,
+            };
+            buckets[index].count += 1;
+        }
+        buckets
+    }
+}
+
+/// Statistics over consecutive samples.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    /// Total length of all sampling windows.
+    pub duration: Duration,
+    pub package: PowerSummary,
+    pub cores: BTreeMap<u32, PowerSummary>,
+    /// Core power summed per group, see [`Sample::group_power`].
+    pub groups: BTreeMap<String, PowerSummary>,
+    /// Package power while the desktop session was in use, idle or locked,
+    /// see [`Sample::presence`].
+    pub presence: BTreeMap<Presence, PowerSummary>,
+    /// Every package power value, only if asked for with
+    /// [`Summary::with_distribution`].
+    pub distribution: Option<Distribution>,
+    /// Busy seconds of each core's threads, from samples that have them.
+    pub core_busy: BTreeMap<u32, Sum>,
+    /// Time spent suspended in between, see [`crate::suspend`].
+    pub suspended: Duration,
+    pub suspends: usize,
+}
+
+/// Share of the run a core has to be busy for to be ranked, the energy of
+/// a core that hardly ran is its idle power, not what its work took.
+pub const RANK_MIN_BUSY: f64 = 0.01;
+
+/// How much energy a core took for its work.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoreEfficiency {
+    pub core: u32,
+    /// Joules per second its threads were busy.
+    pub joules_per_busy_second: f64,
+    /// Busy seconds of its threads per second of the run, in percent.
+    pub busy_percent: f64,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also keeps every package power value, for percentiles.
+    pub fn with_distribution() -> Self {
+        Self {
+            distribution: Some(Distribution::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Adds `sample`. The energy totals are only exact for samples whose
+    /// windows follow each other without gaps.
+    pub fn add(&mut self, sample: &Sample) {
+        self.duration += sample.window;
+        self.package.push(sample.package_power, sample.window);
+        if let Some(distribution) = &mut self.distribution {
+            distribution.push(sample.package_power);
+        }
+        for (&core, &power) in &sample.core_power {
+            self.cores
+                .entry(core)
+                .or_default()
+                .push(power, sample.window);
+        }
+        for (group, &power) in &sample.group_power {
+            self.groups
+                .entry(group.clone())
+                .or_default()
+                .push(power, sample.window);
+        }
+        for (&core, &busy) in &sample.core_busy {
+            *self.core_busy.entry(core).or_default() += busy;
+        }
+        if let Some(presence) = sample.presence {
+            self.presence
+                .entry(presence)
+                .or_default()
+                .push(sample.package_power, sample.window);
+        }
+    }
+
+    /// Cores that were busy for at least [`RANK_MIN_BUSY`] of the run by
+    /// their energy per busy second, the most efficient first.
+    pub fn core_ranking(&self) -> Vec<CoreEfficiency> {
+        let duration = self.duration.as_secs_f64();
+        let mut ranking = self
+            .core_busy
+            .iter()
+            .filter_map(|(&core, busy)| {
+                let busy = busy.value();
+                let energy = self.cores.get(&core)?.energy.value();
+                (duration > 0.0 && busy >= RANK_MIN_BUSY * duration).then(|| CoreEfficiency {
+                    core,
+                    joules_per_busy_second: energy / busy,
+                    busy_percent: busy / duration * 100.0,
+                })
+            })
+            .collect::<Vec<_>>();
+        ranking.sort_by(|a, b| {
+            a.joules_per_busy_second
+                .total_cmp(&b.joules_per_busy_second)
+        });
+        ranking
+    }
+
+    pub fn samples(&self) -> usize {
+        self.package.power.count
+    }
+
+    /// Counts a suspend of `duration` between two windows.
+    pub fn suspend(&mut self, duration: Duration) {
+        self.suspended += duration;
+        self.suspends += 1;
+    }
+
+    /// Package joules per day with as much time asleep as in this run, the
+    /// energy the CPU takes on a day of use like it. `None` without a
+    /// suspend, that would only be the mean power.
+    pub fn joules_per_day(&self) -> Option<f64> {
+        let seconds = (self.duration + self.suspended).as_secs_f64();
+        (self.suspends > 0 && seconds > 0.0)
+            .then(|| self.package.energy.value() / seconds * 86_400.0)
+    }
+
+    /// Share of the run spent awake, in percent.
+    pub fn awake_percent(&self) -> f64 {
+        let seconds = (self.duration + self.suspended).as_secs_f64();
+        match seconds > 0.0 {
+            true => self.duration.as_secs_f64() / seconds * 100.0,
+            false => 100.0,
+        }
+    }
+}
+
+/// How displayed power is smoothed over recent samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Mean of the last this many samples.
+    Window(usize),
+    /// Exponential moving average, the weight of the newest sample.
+    Ema(f64),
+}
+
+/// Smooths the power values of consecutive samples, each series on its
+/// own.
+#[derive(Debug, Clone)]
+pub struct Smoother {
+    smoothing: Smoothing,
+    /// Recent values of each series, just the average so far for an EMA.
+    series: BTreeMap<String, VecDeque<f64>>,
+}
+
+impl Smoother {
+    pub fn new(smoothing: Smoothing) -> Self {
+        Self {
+            smoothing,
+            series: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces the power values of `sample` with their smoothed values.
+    /// Everything else, like frequencies and uncertainties, stays as is.
+    pub fn apply(&mut self, sample: &mut Sample) {
+        sample.package_power = self.push("package", sample.package_power);
+        sample.cores_total_power = self.push("cores", sample.cores_total_power);
+        for (core, power) in &mut sample.core_power {
+            *power = self.push(&format!("core{}", core), *power);
+        }
+        for (thread, power) in &mut sample.thread_power {
+            *power = self.push(&format!("thread{}", thread), *power);
+        }
+        for (domain, power) in &mut sample.domain_power {
+            *power = self.push(&format!("domain {}", domain), *power);
+        }
+        for (group, power) in &mut sample.group_power {
+            *power = self.push(&format!("group {}", group), *power);
+        }
+        for (gpu, power) in &mut sample.gpu_power {
+            *power = self.push(&format!("gpu {}", gpu), *power);
+        }
+    }
+
+    /// Adds `value` to the series `key` and returns its smoothed value.
+    pub fn push(&mut self, key: &str, value: f64) -> f64 {
+        // A NaN would stick around for the whole window.
+        if !value.is_finite() {
+            return value;
+        }
+        let values = self.series.entry(key.to_owned()).or_default();
+
+        match self.smoothing {
+            Smoothing::Window(samples) => {
+                if values.len() == samples {
+                    values.pop_front();
+                }
+                values.push_back(value);
+                values.iter().sum::<f64>() / values.len() as f64
+            }
+            Smoothing::Ema(alpha) => {
+                let average = match values.front() {
+                    Some(average) => alpha * value + (1.0 - alpha) * average,
+                    None => value,
+                };
+                values.clear();
+                values.push_back(average);
+                average
+            }
+        }
+    }
+}
+
+/// p-value below which a difference counts as significant.
+pub const SIGNIFICANCE: f64 = 0.05;
+
+/// Difference of `other` from `baseline` in two series of repeated
+/// measurements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difference {
+    /// Difference of the means.
+    pub mean: f64,
+    /// 95% confidence interval of the difference of the means.
+    pub low: f64,
+    pub high: f64,
+    /// Two-sided p-value of the Mann-Whitney U test.
+    pub p_value: f64,
+}
+
+impl Difference {
+    /// Compares two series, `None` unless both have at least two values.
+    pub fn between(baseline: &[f64], other: &[f64]) -> Option<Self> {
+        let (baseline_stats, other_stats) = (stats_of(baseline), stats_of(other));
+        if baseline_stats.count < 2 || other_stats.count < 2 {
+            return None;
+        }
+
+        // Welch's t interval, the variances needn't be equal.
+        let variance = |stats: &Stats| stats.stddev().powi(2) / stats.count as f64;
+        let (baseline_variance, other_variance) =
+            (variance(&baseline_stats), variance(&other_stats));
+        let error = (baseline_variance + other_variance).sqrt();
+        let df = (baseline_variance + other_variance).powi(2)
+            / (baseline_variance.powi(2) / (baseline_stats.count - 1) as f64
+                + other_variance.powi(2) / (other_stats.count - 1) as f64);
+        let half_width = match error > 0.0 {
+            true => t_quantile_975(df) * error,
+            false => 0.0,
+        };
+
+        let mean = other_stats.mean() - baseline_stats.mean();
+        Some(Self {
+            mean,
+            low: mean - half_width,
+            high: mean + half_width,
+            p_value: mann_whitney(baseline, other),
+        })
+    }
+
+    pub fn significant(&self) -> bool {
+        self.p_value < SIGNIFICANCE
+    }
+}
+
+fn stats_of(values: &[f64]) -> Stats {
+    let mut stats = Stats::default();
+    values.iter().for_each(|&value| stats.push(value));
+    stats
+}
+
+/// 97.5% quantile of Student's t distribution, rounding the degrees of
+/// freedom down.
+fn t_quantile_975(df: f64) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+
+    match df.floor() as usize {
+        0 => f64::NAN,
+        df if df <= TABLE.len() => TABLE[df - 1],
+        df if df <= 60 => 2.021,
+        df if df <= 120 => 2.000,
+        _ => 1.960,
+    }
+}
+
+/// Two-sided p-value of the Mann-Whitney U test. Exact for small samples
+/// without ties, otherwise from the normal approximation with tie and
+/// continuity correction.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> f64 {
+    let (n1, n2) = (a.len(), b.len());
+    if n1 == 0 || n2 == 0 {
+        return f64::NAN;
+    }
+
+    let mut values = a
+        .iter()
+        .map(|&value| (value, true))
+        .chain(b.iter().map(|&value| (value, false)))
+        .collect::<Vec<_>>();
+    values.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // Ranks from 1, ties get the average of their ranks.
+    let (mut rank_sum, mut tie_correction, mut ties) = (0.0, 0.0, false);
+    let mut start = 0;
+    while start < values.len() {
+        let end = start
+            + values[start..]
+                .iter()
+                .take_while(|(value, _)| *value == values[start].0)
+                .count();
+        let rank = (start + end + 1) as f64 / 2.0;
+        let in_a = values[start..end].iter().filter(|(_, in_a)| *in_a).count();
+        rank_sum += rank * in_a as f64;
+
+        let tied = (end - start) as f64;
+        tie_correction += tied.powi(3) - tied;
+        ties |= end - start > 1;
+        start = end;
+    }
+
+    let u1 = rank_sum - (n1 * (n1 + 1)) as f64 / 2.0;
+    let u = u1.min((n1 * n2) as f64 - u1);
+
+    if !ties && n1 + n2 <= 40 {
+        let counts = u_distribution(n1, n2);
+        let total = counts.iter().sum::<f64>();
+        let below = counts.iter().take(u as usize + 1).sum::<f64>();
+        return (2.0 * below / total).min(1.0);
+    }
+
+    let n = (n1 + n2) as f64;
+    let mean = (n1 * n2) as f64 / 2.0;
+    let variance = (n1 * n2) as f64 / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return 1.0;
+    }
+    let z = ((u1 - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    (2.0 * (1.0 - normal_cdf(z))).min(1.0)
+}
+
+/// Number of orderings of `n1` and `n2` values giving each U statistic.
+fn u_distribution(n1: usize, n2: usize) -> Vec<f64> {
+    // counts[i][j] is the distribution for i and j values, built up with
+    // f(i, j, u) = f(i - 1, j, u - j) + f(i, j - 1, u).
+    let mut counts = vec![vec![Vec::<f64>::new(); n2 + 1]; n1 + 1];
+    for i in 0..=n1 {
+        for j in 0..=n2 {
+            counts[i][j] = match (i, j) {
+                (0, _) | (_, 0) => vec![1.0],
+                _ => (0..=i * j)
+                    .map(|u| {
+                        let with_a = match u.checked_sub(j) {
+                            Some(u) => counts[i - 1][j].get(u).copied().unwrap_or(0.0),
+                            None => 0.0,
+                        };
+                        with_a + counts[i][j - 1].get(u).copied().unwrap_or(0.0)
+                    })
+                    .collect(),
+            };
+        }
+    }
+    counts.swap_remove(n1).swap_remove(n2)
+}
+
+/// Standard normal distribution function, from the error function
+/// approximation 7.1.26 in Abramowitz and Stegun.
+fn normal_cdf(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-x * x).exp();
+    match z >= 0.0 {
+        true => 0.5 * (1.0 + erf),
+        false => 0.5 * (1.0 - erf),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_over_a_window() {
+        let mut smoother = Smoother::new(Smoothing::Window(3));
+        let smoothed = [3.0, 6.0, 9.0, 30.0].map(|value| smoother.push("package", value));
+        assert_eq!(smoothed, [3.0, 4.5, 6.0, 15.0]);
+        // Series don't mix.
+        assert_eq!(smoother.push("core0", 1.0), 1.0);
+    }
+
+    #[test]
+    fn exponential_moving_average() {
+        let mut smoother = Smoother::new(Smoothing::Ema(0.25));
+        let smoothed = [8.0, 16.0, 16.0].map(|value| smoother.push("package", value));
+        assert_eq!(smoothed, [8.0, 10.0, 11.5]);
+        assert!(smoother.push("package", f64::NAN).is_nan());
+        assert_eq!(smoother.push("package", 11.5), 11.5);
+    }
+
+    #[test]
+    fn ranks_cores_by_energy_per_busy_second() {
+        let mut summary = Summary::new();
+        for _ in 0..10 {
+            summary.add(&Sample {
+                window: Duration::from_secs(1),
+                package_power: 20.0,
+                core_power: BTreeMap::from([(0, 10.0), (1, 6.0), (2, 1.0)]),
+                // Core 2 idles, its joules are no work's.
+                core_busy: BTreeMap::from([(0, 1.0), (1, 0.5), (2, 0.001)]),
+                group_power: BTreeMap::from([("game".to_owned(), 16.0)]),
+                ..Sample::default()
+            });
+        }
+        assert_eq!(summary.groups["game"].energy.value(), 160.0);
+        assert_eq!(summary.joules_per_day(), None);
+        summary.suspend(Duration::from_secs(30));
+        assert_eq!(summary.awake_percent(), 25.0);
+        // 200 J over 40 s.
+        assert_eq!(summary.joules_per_day(), Some(432_000.0));
+
+        let ranking = summary.core_ranking();
+        assert_eq!(ranking.len(), 2);
+        assert_eq!(
+            (ranking[0].core, ranking[0].joules_per_busy_second),
+            (0, 10.0)
+        );
+        assert_eq!(
+            (ranking[1].core, ranking[1].joules_per_busy_second),
+            (1, 12.0)
+        );
+        assert_eq!(ranking[1].busy_percent, 50.0);
+    }
+
+    #[test]
+    fn sums_power_by_presence() {
+        let mut summary = Summary::new();
+        for (package_power, locked, idle) in [
+            (12.0, false, false),
+            (4.0, true, true),
+            (6.0, false, true),
+            (14.0, false, false),
+        ] {
+            summary.add(&Sample {
+                window: Duration::from_secs(1),
+                package_power,
+                presence: Some(Presence::of(locked, idle)),
+                ..Sample::default()
+            });
+        }
+        summary.add(&Sample {
+            window: Duration::from_secs(1),
+            package_power: 30.0,
+            ..Sample::default()
+        });
+        let mean = |presence| summary.presence[&presence].power.mean();
+        assert_eq!(mean(Presence::Active), 13.0);
+        assert_eq!(mean(Presence::Idle), 6.0);
+        assert_eq!(summary.presence[&Presence::Locked].energy.value(), 4.0);
+        assert_eq!(summary.package.energy.value(), 66.0);
+    }
+
+    #[test]
+    fn percentiles_and_buckets() {
+        let mut distribution = Distribution::default();
+        assert!(distribution.percentile(50.0).is_nan());
+        assert!(distribution.buckets(4).is_empty());
+
+        for value in [40.0, 10.0, 20.0, 30.0, 100.0, f64::NAN] {
+            distribution.push(value);
+        }
+        assert_eq!(distribution.len(), 5);
+        assert_eq!(distribution.percentile(0.0), 10.0);
+        assert_eq!(distribution.percentile(50.0), 30.0);
+        assert_eq!(distribution.percentile(90.0), 76.0);
+        assert_eq!(distribution.percentile(100.0), 100.0);
+
+        let counts = distribution
+            .buckets(3)
+            .iter()
+            .map(|bucket| bucket.count)
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [3, 1, 1]);
+        assert_eq!(distribution.buckets(3)[2].high, 100.0);
+
+        let mut flat = Distribution::default();
+        flat.push(5.0);
+        flat.push(5.0);
+        assert_eq!(
+            flat.buckets(10),
+            [Bucket {
+                low: 5.0,
+                high: 5.0,
+                count: 2
+            }]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! `--status-file`: the latest reading in a small JSON file, for panel
+//! applets and widgets that would otherwise each poll the command line.
+//!
+//! The file is replaced after every sample by renaming a new one over it,
+//! so a reader never sees half of it. Its `timestamp` is at most
+//! `interval_seconds` plus the time the sample took old while sampling; a
+//! file older than `stale_after_seconds` was left behind by a process that
+//! didn't get to remove it. Described by `--schema status`.
+//!
+//! `--mangohud` keeps the line of [`crate::overlay::mangohud`] the same way.
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use crate::{
+    daemon,
+    output::{json_number, json_string, Sample},
+    schema, timefmt,
+};
+
+/// Missed updates after which the file is stale.
+pub const STALE_AFTER: u32 = 3;
+
+/// `ryzen-wattage.json` next to the [`daemon::default_socket_path`].
+pub fn default_path() -> PathBuf {
+    daemon::default_socket_path().with_file_name("ryzen-wattage.json")
+}
+
+/// The status file at `path`.
+#[derive(Debug, Clone)]
+pub struct StatusFile {
+    path: PathBuf,
+    /// Written first, then renamed to `path`.
+    temporary: PathBuf,
+}
+
+impl StatusFile {
+    pub fn new(path: &Path) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Self {
+            path: path.to_owned(),
+            temporary: path.with_file_name(format!(".{}.tmp", name)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the file with `sample`, taken every `interval`.
+    pub fn write(&self, sample: &Sample, interval: Duration) -> io::Result<()> {
+        self.replace(&json(sample, interval))
+    }
+
+    /// Replaces the file with the line `contents`.
+    pub fn replace(&self, contents: &str) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o644)
+            .open(&self.temporary)?;
+        writeln!(file, "{}", contents)?;
+        fs::rename(&self.temporary, &self.path)
+    }
+
+    /// Removes the file, nothing keeps it current anymore.
+    pub fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(&self.temporary);
+    }
+}
+
+/// The contents of the file.
+pub fn json(sample: &Sample, interval: Duration) -> String {
+    let interval = interval.as_secs_f64();
+    format!(
+        concat!(
+            "{{\"schema_version\":{},\"timestamp\":{},\"interval_seconds\":{},",
+            "\"stale_after_seconds\":{},\"pid\":{},\"package_watts\":{},\"cores_watts\":{}}}"
+        ),
+        schema::VERSION,
+        json_string(&timefmt::machine(sample.timestamp)),
+        json_number(interval),
+        json_number(interval * f64::from(STALE_AFTER)),
+        process::id(),
+        json_number(sample.package_power),
+        json_number(sample.cores_total_power)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{self as parse, Value};
+
+    #[test]
+    fn replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!("ryzen-wattage-status-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let status = StatusFile::new(&dir.join("status.json"));
+        let sample = Sample {
+            package_power: 42.5,
+            ..Sample::default()
+        };
+        status.write(&sample, Duration::from_secs(2)).unwrap();
+        status.write(&sample, Duration::from_secs(2)).unwrap();
+
+        let written = fs::read_to_string(status.path()).unwrap();
+        let Value::Object(members) = parse::parse(written.trim_end()).unwrap() else {
+            panic!("not an object: {}", written);
+        };
+        assert_eq!(members["package_watts"], Value::Number(42.5));
+        assert_eq!(members["stale_after_seconds"], Value::Number(6.0));
+        assert!(!status.temporary.exists());
+
+        status.remove();
+        assert!(!status.path().exists());
+        fs::remove_dir(&dir).unwrap();
+    }
+}
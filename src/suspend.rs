@@ -0,0 +1,87 @@
+//! Time the machine spent suspended while sampling, for the energy a
+//! laptop that sleeps between uses takes per day.
+//!
+//! `CLOCK_MONOTONIC` stands still while the machine is suspended and
+//! `CLOCK_BOOTTIME` doesn't, so the two drifting apart is time asleep. The
+//! energy counters can't tell what that time took, they are reset or stand
+//! still with the package powered down, so a window around a suspend is
+//! left out and the energy awake is spread over the time awake and asleep.
+
+use std::time::Duration;
+
+const CLOCK_MONOTONIC: i32 = 1;
+const CLOCK_BOOTTIME: i32 = 7;
+
+/// Drift between the clocks that is only reading one after the other.
+const SLACK: Duration = Duration::from_millis(50);
+
+#[repr(C)]
+struct Timespec {
+    sec: i64,
+    nsec: i64,
+}
+
+extern "C" {
+    fn clock_gettime(clock: i32, time: *mut Timespec) -> i32;
+}
+
+fn read(clock: i32) -> Duration {
+    let mut time = Timespec { sec: 0, nsec: 0 };
+    // SAFETY: `time` lives across the call, which only writes to it.
+    if unsafe { clock_gettime(clock, &mut time) } != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.sec as u64, time.nsec as u32)
+}
+
+/// `CLOCK_MONOTONIC` now, the clock of the `mono` trace clock too.
+pub fn monotonic() -> Duration {
+    read(CLOCK_MONOTONIC)
+}
+
+/// Notices suspends from one window to the next.
+#[derive(Debug, Clone)]
+pub struct Suspends {
+    /// How far the boot time is ahead of the monotonic time.
+    asleep: Duration,
+}
+
+impl Suspends {
+    pub fn new() -> Self {
+        Self { asleep: asleep() }
+    }
+
+    /// Time suspended since the last call, if the machine was.
+    pub fn check(&mut self) -> Option<Duration> {
+        let asleep = asleep();
+        let suspended = asleep.saturating_sub(self.asleep);
+        self.asleep = asleep;
+        (suspended > SLACK).then_some(suspended)
+    }
+}
+
+impl Default for Suspends {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Time spent suspended since boot.
+fn asleep() -> Duration {
+    // Read the other way round, the monotonic clock could get ahead by the
+    // time between the two reads.
+    let monotonic = read(CLOCK_MONOTONIC);
+    read(CLOCK_BOOTTIME).saturating_sub(monotonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_suspend_between_reads() {
+        let mut suspends = Suspends::new();
+        assert!(read(CLOCK_BOOTTIME) >= read(CLOCK_MONOTONIC) - SLACK);
+        assert_eq!(suspends.check(), None);
+    }
+}
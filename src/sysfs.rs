@@ -0,0 +1,134 @@
+//! Where sysfs, procfs and the MSR devices are read from.
+//!
+//! Everything that reads the topology or opens counters goes through a
+//! [`Root`], the real `/` normally, a directory of fixture files in tests.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root(PathBuf);
+
+impl Root {
+    /// The files of this machine.
+    pub fn system() -> Self {
+        Self(PathBuf::from("/"))
+    }
+
+    /// A tree laid out like `/`, e.g. `dir/sys/devices/system/cpu/online`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self(dir.into())
+    }
+
+    /// `path`, an absolute path like `/sys/class/powercap`, below this root.
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        self.0.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Reads a sysfs file without the trailing newline.
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<String> {
+        let path = self.path(path);
+        fs::read_to_string(&path)
+            .map(|value| value.trim_end().to_owned())
+            .map_err(|err| Error::io(path, err))
+    }
+}
+
+impl Default for Root {
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
+/// Temporary roots for tests, filled from the `tests/fixtures` files.
+#[cfg(test)]
+pub(crate) mod fixture {
+    use std::{
+        fs,
+        os::unix::fs::FileExt,
+        path::{Path, PathBuf},
+        process,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use super::Root;
+
+    /// Topologies in the `topology.txt` format of `debug export-report`,
+    /// one `path: contents` line per file.
+    pub const ZEN3_SMT: &str = include_str!("../tests/fixtures/zen3-5950x-smt.txt");
+    pub const ZEN2_SMT_OFF: &str = include_str!("../tests/fixtures/zen2-3700x-smt-off.txt");
+    pub const ZEN3_OFFLINE: &str = include_str!("../tests/fixtures/zen3-5800x-offline.txt");
+    pub const DUAL_SOCKET: &str = include_str!("../tests/fixtures/epyc-dual-socket.txt");
+
+    /// A directory that is removed again when dropped.
+    #[derive(Debug)]
+    pub struct Fixture {
+        dir: PathBuf,
+        root: Root,
+    }
+
+    impl Fixture {
+        pub fn new() -> Self {
+            static NEXT: AtomicU32 = AtomicU32::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "ryzen-wattage-test-{}-{}",
+                process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self {
+                root: Root::new(&dir),
+                dir,
+            }
+        }
+
+        /// A root with the files listed in `listing`.
+        pub fn from_listing(listing: &str) -> Self {
+            let fixture = Self::new();
+            for line in listing.lines().filter(|line| !line.starts_with('#')) {
+                if let Some((path, contents)) = line.split_once(": ") {
+                    fixture.file(path, &format!("{}\n", contents));
+                }
+            }
+            fixture
+        }
+
+        pub fn root(&self) -> &Root {
+            &self.root
+        }
+
+        pub fn file(&self, path: impl AsRef<Path>, contents: &str) -> &Self {
+            let path = self.root.path(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+            self
+        }
+
+        /// Stores `value` at `offset` of the file, the way an MSR device
+        /// returns register values. Unlike in a device they overlap in a
+        /// file, so registers need to be at least 8 apart.
+        pub fn register(&self, path: impl AsRef<Path>, offset: u64, value: u64) -> &Self {
+            let path = self.root.path(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)
+                .unwrap();
+            file.write_all_at(&value.to_ne_bytes(), offset).unwrap();
+            self
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
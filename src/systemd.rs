@@ -0,0 +1,156 @@
+//! `--service`: telling systemd how the service is doing over the socket in
+//! `NOTIFY_SOCKET`, as sd_notify(3) does for `Type=notify` units.
+//!
+//! Readiness is sent once the first sample is in, watchdog pings after every
+//! sample that read fine, so a sampler stuck on the counters gets restarted
+//! after `WatchdogSec=`. Outside of systemd there's no socket and nothing is
+//! sent.
+
+use std::{
+    env, io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
+
+/// Where to send notifications, and how often the watchdog wants them.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    watchdog: Option<Duration>,
+    last_ping: Option<Instant>,
+}
+
+impl Notifier {
+    /// The socket systemd passed, if any. Pings are sent at half of
+    /// `WATCHDOG_USEC` as sd_watchdog_enabled(3) suggests, and only when the
+    /// watchdog is meant for this process.
+    pub fn from_env() -> io::Result<Self> {
+        let path = env::var_os("NOTIFY_SOCKET").filter(|path| !path.is_empty());
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|_| {
+                env::var("WATCHDOG_PID").map_or(true, |pid| pid == process::id().to_string())
+            })
+            .map(|usec: u64| Duration::from_micros(usec / 2));
+
+        let address = match path {
+            Some(path) => Some(match path.to_string_lossy().strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name)?,
+                None => SocketAddr::from_pathname(PathBuf::from(path))?,
+            }),
+            None => None,
+        };
+        Self::new(address, watchdog)
+    }
+
+    /// Sends to `address`, or nowhere without one.
+    pub fn new(address: Option<SocketAddr>, watchdog: Option<Duration>) -> io::Result<Self> {
+        let socket = match address {
+            Some(address) => Some((UnixDatagram::unbound()?, address)),
+            None => None,
+        };
+        Ok(Self {
+            socket,
+            watchdog,
+            last_ping: None,
+        })
+    }
+
+    /// Done starting up, or reloading.
+    pub fn ready(&self) -> io::Result<()> {
+        self.notify("READY=1")
+    }
+
+    pub fn reloading(&self) -> io::Result<()> {
+        self.notify("RELOADING=1")
+    }
+
+    pub fn stopping(&self) -> io::Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    /// A line `systemctl status` shows.
+    pub fn status(&self, status: &str) -> io::Result<()> {
+        self.notify(&format!("STATUS={}", status.trim().replace('\n', " ")))
+    }
+
+    /// Tells the watchdog the service is healthy, at most as often as it
+    /// needs to hear that.
+    pub fn ping(&mut self) -> io::Result<()> {
+        let Some(watchdog) = self.watchdog else {
+            return Ok(());
+        };
+        if self
+            .last_ping
+            .is_some_and(|last_ping| last_ping.elapsed() < watchdog)
+        {
+            return Ok(());
+        }
+        self.last_ping = Some(Instant::now());
+        self.notify("WATCHDOG=1")
+    }
+
+    fn notify(&self, state: &str) -> io::Result<()> {
+        match &self.socket {
+            Some((socket, address)) => socket.send_to_addr(state.as_bytes(), address).map(drop),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen(test: &str) -> (UnixDatagram, SocketAddr) {
+        let name = format!("ryzen-wattage-{}-{}", test, process::id());
+        let address = SocketAddr::from_abstract_name(name).unwrap();
+        let socket = UnixDatagram::bind_addr(&address).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        (socket, address)
+    }
+
+    fn received(socket: &UnixDatagram) -> Vec<String> {
+        let mut buffer = [0; 256];
+        let mut messages = Vec::new();
+        while let Ok(len) = socket.recv(&mut buffer) {
+            messages.push(String::from_utf8_lossy(&buffer[..len]).into_owned());
+        }
+        messages
+    }
+
+    #[test]
+    fn sends_states_and_rate_limits_pings() {
+        let (socket, address) = listen("states");
+        let mut notifier = Notifier::new(Some(address), Some(Duration::from_secs(60))).unwrap();
+
+        notifier.ready().unwrap();
+        notifier.ping().unwrap();
+        notifier.ping().unwrap();
+        notifier.status("60.0 W\n").unwrap();
+        notifier.stopping().unwrap();
+
+        assert_eq!(
+            received(&socket),
+            ["READY=1", "WATCHDOG=1", "STATUS=60.0 W", "STOPPING=1"]
+        );
+    }
+
+    #[test]
+    fn does_nothing_outside_of_systemd() {
+        let mut notifier = Notifier::new(None, Some(Duration::ZERO)).unwrap();
+        notifier.ready().unwrap();
+        notifier.ping().unwrap();
+
+        let (socket, address) = listen("no-watchdog");
+        let mut notifier = Notifier::new(Some(address), None).unwrap();
+        notifier.ping().unwrap();
+        assert!(received(&socket).is_empty());
+    }
+}
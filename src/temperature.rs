@@ -0,0 +1,90 @@
+//! Temperatures from the `k10temp` hwmon device of AMD CPUs.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::sysfs::Root;
+
+/// The hwmon directory of `k10temp`, if the driver is loaded.
+fn k10temp(root: &Root) -> Option<PathBuf> {
+    let hwmon = fs::read_dir(root.path("/sys/class/hwmon")).ok()?;
+
+    hwmon.flatten().map(|entry| entry.path()).find(|dir| {
+        fs::read_to_string(dir.join("name")).is_ok_and(|name| name.trim_end() == "k10temp")
+    })
+}
+
+/// Tctl in °C, as reported by the hardware. Some models add an offset that
+/// [`Quirks::correct_tctl`](crate::quirks::Quirks::correct_tctl) removes.
+pub fn tctl(root: &Root) -> Option<f64> {
+    read_millidegrees(&k10temp(root)?.join("temp1_input"))
+}
+
+/// Every temperature `k10temp` reports in °C, keyed by its lowercased label:
+/// `tctl`, `tdie` where the kernel knows the offset, and `tccd1` and up for
+/// each CCD on Zen 2 and later. Empty without `k10temp`.
+pub fn read(root: &Root) -> BTreeMap<String, f64> {
+    let Some(dir) = k10temp(root) else {
+        return BTreeMap::new();
+    };
+
+    // The kernel numbers sensors from 1 with gaps, e.g. Tccd1 is temp3.
+    (1..=32)
+        .filter_map(|index| {
+            let value = read_millidegrees(&dir.join(format!("temp{}_input", index)))?;
+            let label = match fs::read_to_string(dir.join(format!("temp{}_label", index))) {
+                Ok(label) => label.trim_end().to_lowercase(),
+                // Kernels before 5.6 only have an unlabeled Tctl.
+                Err(_) if index == 1 => "tctl".to_owned(),
+                Err(_) => return None,
+            };
+            Some((label, value))
+        })
+        .collect()
+}
+
+fn read_millidegrees(path: &Path) -> Option<f64> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim_end().parse::<f64>().ok()?;
+    Some(value / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::Fixture;
+
+    #[test]
+    fn reads_labeled_sensors_of_k10temp() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/sys/class/hwmon/hwmon1/name", "nvme\n")
+            .file("/sys/class/hwmon/hwmon1/temp1_input", "40000\n")
+            .file("/sys/class/hwmon/hwmon4/name", "k10temp\n")
+            .file("/sys/class/hwmon/hwmon4/temp1_input", "61250\n")
+            .file("/sys/class/hwmon/hwmon4/temp1_label", "Tctl\n")
+            .file("/sys/class/hwmon/hwmon4/temp3_input", "58000\n")
+            .file("/sys/class/hwmon/hwmon4/temp3_label", "Tccd1\n");
+        assert_eq!(tctl(fixture.root()), Some(61.25));
+        assert_eq!(
+            read(fixture.root()),
+            BTreeMap::from([("tccd1".to_owned(), 58.0), ("tctl".to_owned(), 61.25)])
+        );
+    }
+
+    #[test]
+    fn unlabeled_first_sensor_is_tctl() {
+        let fixture = Fixture::new();
+        fixture
+            .file("/sys/class/hwmon/hwmon0/name", "k10temp\n")
+            .file("/sys/class/hwmon/hwmon0/temp1_input", "70000\n");
+        assert_eq!(
+            read(fixture.root()),
+            BTreeMap::from([("tctl".to_owned(), 70.0)])
+        );
+        assert_eq!(tctl(Fixture::new().root()), None);
+    }
+}
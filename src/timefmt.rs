@@ -0,0 +1,964 @@
+//! Time zones and strftime-style formatting of timestamps.
+//!
+//! Machine readable output defaults to RFC 3339 in UTC and text to local
+//! time, in that zone no matter what the process environment says once
+//! `--timezone` is given. Zones come from the system's zoneinfo files, with
+//! the POSIX TZ rule at their end for times after the last transition.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Format of timestamps in text output.
+pub const HUMAN_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Zone {
+    Utc,
+    /// Seconds east of UTC.
+    Fixed(i32),
+    Tz(Box<TzFile>),
+}
+
+impl Zone {
+    /// The zone of `$TZ`, else of `/etc/localtime`, else UTC.
+    pub fn local() -> Self {
+        let from_env = env::var("TZ")
+            .ok()
+            .filter(|tz| !tz.is_empty())
+            .and_then(|tz| tz.parse().ok());
+        from_env
+            .or_else(|| {
+                let data = fs::read("/etc/localtime").ok()?;
+                TzFile::parse(&data).map(|tz| Self::Tz(Box::new(tz)))
+            })
+            .unwrap_or(Self::Utc)
+    }
+
+    /// Offset from UTC in seconds and the abbreviation, like `CEST`, at
+    /// `secs` since the epoch.
+    pub fn offset_at(&self, secs: i64) -> (i32, String) {
+        match self {
+            Self::Utc => (0, "UTC".to_owned()),
+            Self::Fixed(offset) => (*offset, format_offset(*offset, true)),
+            Self::Tz(tz) => {
+                let local = tz.local_type(secs);
+                (local.offset, local.abbreviation.clone())
+            }
+        }
+    }
+}
+
+/// `UTC`, `local`, an offset like `+02:00`, a zoneinfo name like
+/// `Europe/Berlin` or a POSIX TZ string like `CET-1CEST,M3.5.0,M10.5.0/3`.
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "unknown time zone `{}`, expected UTC, local, an offset like +02:00 or a name like Europe/Berlin",
+                s
+            )
+        };
+
+        match s {
+            "UTC" | "utc" | "Z" => return Ok(Self::Utc),
+            "local" => return Ok(Self::local()),
+            _ => {}
+        }
+        if let Some(offset) = parse_fixed_offset(s) {
+            return Ok(Self::Fixed(offset));
+        }
+
+        // A leading colon marks a file in TZ, anything else may be one too.
+        let name = s.strip_prefix(':').unwrap_or(s);
+        if let Some(tz) = zoneinfo_path(name)
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|data| TzFile::parse(&data))
+        {
+            return Ok(Self::Tz(Box::new(tz)));
+        }
+
+        let rule = PosixRule::parse(s).ok_or_else(invalid)?;
+        Ok(Self::Tz(Box::new(TzFile {
+            rule: Some(rule),
+            ..TzFile::default()
+        })))
+    }
+}
+
+/// `+02:00` and `+0200`. `UTC+5` is left to [`PosixRule`], where it means
+/// five hours behind UTC.
+fn parse_fixed_offset(s: &str) -> Option<i32> {
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    if hours.is_empty() || !(hours.len() <= 2 && minutes.len() <= 2) {
+        return None;
+    }
+    let (hours, minutes) = (hours.parse::<i32>().ok()?, minutes.parse::<i32>().ok()?);
+    (hours <= 23 && minutes <= 59).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// The zoneinfo file of a zone name, refusing names that would leave the
+/// zoneinfo directory.
+fn zoneinfo_path(name: &str) -> Option<PathBuf> {
+    let name = Path::new(name);
+    if name.is_absolute() {
+        return Some(name.to_owned());
+    }
+    if !name
+        .components()
+        .all(|part| matches!(part, Component::Normal(_)))
+    {
+        return None;
+    }
+    let dir = env::var_os("TZDIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(ZONEINFO));
+    Some(dir.join(name))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalType {
+    /// Seconds east of UTC.
+    pub offset: i32,
+    pub abbreviation: String,
+}
+
+/// A compiled zoneinfo (TZif) file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TzFile {
+    /// Times the offset changes at, ascending.
+    transitions: Vec<i64>,
+    /// Index into `types` from each transition on.
+    indices: Vec<u8>,
+    types: Vec<LocalType>,
+    /// For times after the last transition.
+    rule: Option<PosixRule>,
+}
+
+impl TzFile {
+    /// Parses version 1 files and the 64 bit data and footer of later ones.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if !data.starts_with(b"TZif") {
+            return None;
+        }
+        let version = *data.get(4)?;
+        let (v1, v1_len) = Self::parse_block(data, 4)?;
+        if version < b'2' {
+            return Some(v1);
+        }
+
+        let data = data.get(v1_len..)?;
+        if !data.starts_with(b"TZif") {
+            return None;
+        }
+        let (mut tz, len) = Self::parse_block(data, 8)?;
+        let footer = data.get(len..).unwrap_or_default();
+        tz.rule = std::str::from_utf8(footer)
+            .ok()
+            .and_then(|footer| footer.trim_matches('\n').lines().next())
+            .filter(|rule| !rule.is_empty())
+            .and_then(PosixRule::parse);
+        Some(tz)
+    }
+
+    /// Parses a header and the data following it, with transition times
+    /// `time_size` bytes wide. Returns the length of both too.
+    fn parse_block(data: &[u8], time_size: usize) -> Option<(Self, usize)> {
+        let count = |index: usize| {
+            let at = 20 + 4 * index;
+            let bytes = data.get(at..at + 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+        };
+        let (utc_count, std_count, leap_count) = (count(0)?, count(1)?, count(2)?);
+        let (time_count, type_count, char_count) = (count(3)?, count(4)?, count(5)?);
+
+        let mut pos = 44;
+        let mut take = |len: usize| {
+            let bytes = data.get(pos..pos + len)?;
+            pos += len;
+            Some(bytes)
+        };
+
+        let transitions = take(time_count * time_size)?
+            .chunks(time_size)
+            .map(|bytes| match time_size {
+                4 => i64::from(i32::from_be_bytes(bytes.try_into().unwrap())),
+                _ => i64::from_be_bytes(bytes.try_into().unwrap()),
+            })
+            .collect();
+        let indices = take(time_count)?.to_vec();
+        let types = take(type_count * 6)?.to_vec();
+        let chars = take(char_count)?;
+        take(leap_count * (time_size + 4))?;
+        take(std_count)?;
+        take(utc_count)?;
+
+        let types = types
+            .chunks(6)
+            .map(|info| {
+                let offset = i32::from_be_bytes(info[..4].try_into().unwrap());
+                let start = usize::from(info[5]).min(chars.len());
+                let end = chars[start..]
+                    .iter()
+                    .position(|&c| c == 0)
+                    .map_or(chars.len(), |len| start + len);
+                LocalType {
+                    offset,
+                    abbreviation: String::from_utf8_lossy(&chars[start..end]).into_owned(),
+                }
+            })
+            .collect::<Vec<_>>();
+        if types.is_empty() {
+            return None;
+        }
+
+        let tz = Self {
+            transitions,
+            indices,
+            types,
+            rule: None,
+        };
+        Some((tz, pos))
+    }
+
+    fn local_type(&self, secs: i64) -> LocalType {
+        let after_last = self.transitions.last().is_none_or(|&last| secs >= last);
+        if let Some(rule) = self.rule.as_ref().filter(|_| after_last) {
+            return rule.local_type(secs);
+        }
+
+        let index = match self.transitions.partition_point(|&time| time <= secs) {
+            // Before the first transition, the first type applies.
+            0 => 0,
+            next => usize::from(self.indices[next - 1]),
+        };
+        self.types.get(index).unwrap_or(&self.types[0]).clone()
+    }
+}
+
+/// A POSIX TZ string like `CET-1CEST,M3.5.0,M10.5.0/3`.
+#[derive(Debug, Clone, PartialEq)]
+struct PosixRule {
+    standard: LocalType,
+    /// Daylight saving time and when it starts and ends.
+    dst: Option<(LocalType, Change, Change)>,
+}
+
+/// A day of the year and the local time in seconds the offset changes at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Change {
+    day: RuleDay,
+    time: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleDay {
+    /// `Jn`, 1 to 365, never counting February 29th.
+    Julian(u32),
+    /// `n`, 0 to 365.
+    Ordinal(u32),
+    /// `Mm.w.d`, day `d` of week `w` of month `m`, where week 5 is the
+    /// last.
+    Month { month: u32, week: u32, weekday: u32 },
+}
+
+impl PosixRule {
+    fn parse(s: &str) -> Option<Self> {
+        let mut rest = s;
+        let standard_name = take_abbreviation(&mut rest)?;
+        let standard_offset = -take_time(&mut rest)?;
+        let standard = LocalType {
+            offset: standard_offset as i32,
+            abbreviation: standard_name,
+        };
+        if rest.is_empty() {
+            return Some(Self {
+                standard,
+                dst: None,
+            });
+        }
+
+        let dst_name = take_abbreviation(&mut rest)?;
+        let dst_offset = match rest.starts_with(',') {
+            true => standard_offset + 3600,
+            false => -take_time(&mut rest)?,
+        };
+        let dst = LocalType {
+            offset: dst_offset as i32,
+            abbreviation: dst_name,
+        };
+
+        // Without rules the US ones apply, as in glibc.
+        let (start, end) = match rest.strip_prefix(',') {
+            Some(rules) => rules.split_once(',')?,
+            None if rest.is_empty() => ("M3.2.0", "M11.1.0"),
+            None => return None,
+        };
+        Some(Self {
+            standard,
+            dst: Some((dst, Change::parse(start)?, Change::parse(end)?)),
+        })
+    }
+
+    fn local_type(&self, secs: i64) -> LocalType {
+        let Some((dst, start, end)) = &self.dst else {
+            return self.standard.clone();
+        };
+
+        let (year, _, _) =
+            civil_from_days((secs + i64::from(self.standard.offset)).div_euclid(86_400));
+        // Changes happen at local time, DST starts in standard time and ends
+        // in daylight saving time.
+        let start = start.local_secs(year) - i64::from(self.standard.offset);
+        let end = end.local_secs(year) - i64::from(dst.offset);
+        let in_dst = match start < end {
+            true => (start..end).contains(&secs),
+            // The southern hemisphere, DST spans the new year.
+            false => !(end..start).contains(&secs),
+        };
+
+        match in_dst {
+            true => dst.clone(),
+            false => self.standard.clone(),
+        }
+    }
+}
+
+impl Change {
+    fn parse(s: &str) -> Option<Self> {
+        // 02:00 unless given.
+        let (day, time) = match s.split_once('/') {
+            Some((day, mut time)) => (day, take_time(&mut time).filter(|_| time.is_empty())?),
+            None => (s, 7200),
+        };
+
+        let day = if let Some(day) = day.strip_prefix('J') {
+            RuleDay::Julian(day.parse().ok().filter(|day| (1..=365).contains(day))?)
+        } else if let Some(rule) = day.strip_prefix('M') {
+            let mut parts = rule.split('.').map(|part| part.parse::<u32>().ok());
+            let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+            if parts.next().is_some()
+                || !(1..=12).contains(&month)
+                || !(1..=5).contains(&week)
+                || weekday > 6
+            {
+                return None;
+            }
+            RuleDay::Month {
+                month,
+                week,
+                weekday,
+            }
+        } else {
+            RuleDay::Ordinal(day.parse().ok().filter(|&day| day <= 365)?)
+        };
+
+        Some(Self { day, time })
+    }
+
+    /// Seconds since the epoch of the change in `year`, as if local time
+    /// were UTC.
+    fn local_secs(&self, year: i64) -> i64 {
+        let new_year = days_from_civil(year, 1, 1);
+        let day = match self.day {
+            RuleDay::Julian(day) => {
+                let leap_day = is_leap_year(year) && day >= 60;
+                new_year + i64::from(day) - 1 + i64::from(leap_day)
+            }
+            RuleDay::Ordinal(day) => new_year + i64::from(day),
+            RuleDay::Month {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = days_from_civil(year, month, 1);
+                let first_match = first + (i64::from(weekday) - weekday_of(first)).rem_euclid(7);
+                let mut day = first_match + 7 * (i64::from(week) - 1);
+                // Week 5 means the last one, which may be the fourth.
+                while day >= first + i64::from(days_in_month(year, month)) {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        day * 86_400 + self.time
+    }
+}
+
+/// A zone abbreviation, letters or anything in angle brackets like `<+03>`.
+fn take_abbreviation(s: &mut &str) -> Option<String> {
+    let (name, rest) = match s.strip_prefix('<') {
+        Some(quoted) => {
+            let (name, rest) = quoted.split_once('>')?;
+            (name, rest)
+        }
+        None => {
+            let len = s
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(s.len());
+            s.split_at(len)
+        }
+    };
+    *s = rest;
+    (name.len() >= 3).then(|| name.to_owned())
+}
+
+/// `[+-]hh[:mm[:ss]]` in seconds, hours up to 167 as POSIX extensions
+/// allow.
+fn take_time(s: &mut &str) -> Option<i64> {
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => (1, *s),
+    };
+    let len = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == ':'))
+        .unwrap_or(rest.len());
+    let (time, rest) = rest.split_at(len);
+    *s = rest;
+
+    let mut secs = 0;
+    let mut parts = time.split(':');
+    for (part, scale) in parts.by_ref().zip([3600, 60, 1]) {
+        let value = part.parse::<i64>().ok()?;
+        if scale < 3600 && value > 59 || value > 167 {
+            return None;
+        }
+        secs += value * scale;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(sign * secs),
+    }
+}
+
+/// How timestamps are written, one for machine readable and one for human
+/// output.
+#[derive(Debug, Clone)]
+struct Style {
+    zone: Zone,
+    /// strftime-style, RFC 3339 if unset.
+    format: Option<String>,
+}
+
+static STYLES: OnceLock<(Style, Style)> = OnceLock::new();
+
+/// Sets the zone and format of [`machine`] and [`human`] timestamps, the
+/// defaults where `None`. Only the first call has an effect.
+pub fn configure(zone: Option<Zone>, format: Option<String>) {
+    STYLES.get_or_init(|| styles(zone, format));
+}
+
+fn styles(zone: Option<Zone>, format: Option<String>) -> (Style, Style) {
+    let machine = Style {
+        zone: zone.clone().unwrap_or(Zone::Utc),
+        format: format.clone(),
+    };
+    let human = Style {
+        zone: zone.unwrap_or_else(Zone::local),
+        format: Some(format.unwrap_or_else(|| HUMAN_FORMAT.to_owned())),
+    };
+    (machine, human)
+}
+
+/// A timestamp for JSON, CSV and the like, RFC 3339 in UTC by default.
+pub fn machine(time: SystemTime) -> String {
+    let (style, _) = STYLES.get_or_init(|| styles(None, None));
+    write_style(time, style)
+}
+
+/// A timestamp for text output, local time by default.
+pub fn human(time: SystemTime) -> String {
+    let (_, style) = STYLES.get_or_init(|| styles(None, None));
+    write_style(time, style)
+}
+
+fn write_style(time: SystemTime, style: &Style) -> String {
+    match &style.format {
+        Some(spec) => {
+            format(time, &style.zone, spec).unwrap_or_else(|_| rfc3339(time, &style.zone))
+        }
+        None => rfc3339(time, &style.zone),
+    }
+}
+
+/// RFC 3339 with millisecond precision, `Z` for UTC.
+pub fn rfc3339(time: SystemTime, zone: &Zone) -> String {
+    let civil = Civil::new(time, zone);
+    let offset = match zone {
+        Zone::Utc => "Z".to_owned(),
+        _ => format_offset(civil.offset, true),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}{}",
+        civil.year,
+        civil.month,
+        civil.day,
+        civil.hour,
+        civil.minute,
+        civil.second,
+        civil.nanos / 1_000_000,
+        offset
+    )
+}
+
+/// A point in time broken down in a zone.
+struct Civil {
+    /// Since the epoch, rounded down.
+    secs: i64,
+    nanos: u32,
+    offset: i32,
+    abbreviation: String,
+    /// Since the epoch in local time.
+    days: i64,
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    /// 0 for Sunday.
+    weekday: usize,
+}
+
+impl Civil {
+    fn new(time: SystemTime, zone: &Zone) -> Self {
+        let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+            Err(err) => {
+                let before = err.duration();
+                let nanos = before.subsec_nanos();
+                let secs = -(before.as_secs() as i64) - i64::from(nanos > 0);
+                (secs, (1_000_000_000 - nanos) % 1_000_000_000)
+            }
+        };
+        let (offset, abbreviation) = zone.offset_at(secs);
+        let local = secs + i64::from(offset);
+        let days = local.div_euclid(86_400);
+        let secs_of_day = local.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            secs,
+            nanos,
+            offset,
+            abbreviation,
+            days,
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+            weekday: weekday_of(days) as usize,
+        }
+    }
+}
+
+/// Makes sure `spec` only uses specifiers [`format`] knows.
+pub fn check_format(spec: &str) -> Result<(), String> {
+    format(UNIX_EPOCH, &Zone::Utc, spec).map(|_| ())
+}
+
+/// Formats `time` in `zone` like strftime. Supported are `%Y %y %m %d %e %j
+/// %H %I %p %M %S %a %A %b %B %F %T %z %:z %Z %s %%` and the fractional
+/// seconds `%3f %6f %9f` and `%.3f %.6f %.9f`, like chrono has them.
+pub fn format(time: SystemTime, zone: &Zone, spec: &str) -> Result<String, String> {
+    const WEEKDAYS: [&str; 7] = [
+        "Sunday",
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+    ];
+    const MONTHS: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+
+    let Civil {
+        secs,
+        nanos,
+        offset,
+        abbreviation,
+        days,
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        weekday,
+    } = Civil::new(time, zone);
+
+    let mut out = String::new();
+    let mut chars = spec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut specifier = String::from(chars.next().ok_or("trailing `%` in time format")?);
+        // Two and three character specifiers.
+        if matches!(specifier.as_str(), ":" | "." | "3" | "6" | "9") {
+            specifier.push(chars.next().unwrap_or(' '));
+            if specifier.starts_with('.') {
+                specifier.push(chars.next().unwrap_or(' '));
+            }
+        }
+
+        match specifier.as_str() {
+            "Y" => write!(out, "{:04}", year),
+            "y" => write!(out, "{:02}", year.rem_euclid(100)),
+            "m" => write!(out, "{:02}", month),
+            "d" => write!(out, "{:02}", day),
+            "e" => write!(out, "{:2}", day),
+            "j" => write!(out, "{:03}", days - days_from_civil(year, 1, 1) + 1),
+            "H" => write!(out, "{:02}", hour),
+            "I" => write!(out, "{:02}", (hour + 11) % 12 + 1),
+            "p" => write!(out, "{}", if hour < 12 { "AM" } else { "PM" }),
+            "M" => write!(out, "{:02}", minute),
+            "S" => write!(out, "{:02}", second),
+            "a" => write!(out, "{}", &WEEKDAYS[weekday][..3]),
+            "A" => write!(out, "{}", WEEKDAYS[weekday]),
+            "b" => write!(out, "{}", &MONTHS[month as usize - 1][..3]),
+            "B" => write!(out, "{}", MONTHS[month as usize - 1]),
+            "F" => write!(out, "{:04}-{:02}-{:02}", year, month, day),
+            "T" => write!(out, "{:02}:{:02}:{:02}", hour, minute, second),
+            "z" => write!(out, "{}", format_offset(offset, false)),
+            ":z" => write!(out, "{}", format_offset(offset, true)),
+            "Z" => write!(out, "{}", abbreviation),
+            "s" => write!(out, "{}", secs),
+            "3f" | ".3f" => write!(
+                out,
+                "{}{:03}",
+                &specifier[..specifier.len() - 2],
+                nanos / 1_000_000
+            ),
+            "6f" | ".6f" => write!(
+                out,
+                "{}{:06}",
+                &specifier[..specifier.len() - 2],
+                nanos / 1_000
+            ),
+            "9f" | ".9f" => write!(out, "{}{:09}", &specifier[..specifier.len() - 2], nanos),
+            "%" => write!(out, "%"),
+            other => {
+                return Err(format!(
+                    "unknown time format specifier `%{}`",
+                    other.trim_end()
+                ))
+            }
+        }
+        .unwrap();
+    }
+
+    Ok(out)
+}
+
+/// `+0200`, or `+02:00` with `colon`.
+/// Reads back an RFC 3339 timestamp like [`rfc3339`] writes, in any zone
+/// and with any number of fractional digits. Times before the epoch and
+/// leap seconds aren't representable and give `None`.
+pub fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    let (date, time) = s.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let year = date.next()?.parse::<i64>().ok()?;
+    let month = date.next()?.parse::<u32>().ok()?;
+    let day = date.next()?.parse::<u32>().ok()?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let (clock, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(clock) => (clock, 0),
+        None => {
+            let split = time.rfind(['+', '-'])?;
+            (&time[..split], parse_fixed_offset(&time[split..])?)
+        }
+    };
+    let (clock, nanos) = match clock.split_once('.') {
+        Some((clock, fraction))
+            if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            let digits = &fraction[..fraction.len().min(9)];
+            let scale = 10u32.pow(9 - digits.len() as u32);
+            (clock, digits.parse::<u32>().ok()? * scale)
+        }
+        Some(_) => return None,
+        None => (clock, 0),
+    };
+    let mut clock = clock.splitn(3, ':');
+    let hour = clock.next()?.parse::<i64>().ok()?;
+    let minute = clock.next()?.parse::<i64>().ok()?;
+    let second = clock.next()?.parse::<i64>().ok()?;
+    if !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..60).contains(&second) {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - i64::from(offset);
+    Some(UNIX_EPOCH + Duration::new(u64::try_from(secs).ok()?, nanos))
+}
+
+fn format_offset(offset: i32, colon: bool) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    let separator = if colon { ":" } else { "" };
+    format!(
+        "{}{:02}{}{:02}",
+        sign,
+        minutes / 60,
+        separator,
+        minutes % 60
+    )
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// 0 for Sunday, the epoch was a Thursday.
+fn weekday_of(days: i64) -> i64 {
+    (days + 4).rem_euclid(7)
+}
+
+// Howard Hinnant's days-to-civil algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// And its inverse.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /// A version 2 TZif file, with the 64 bit block repeating the 32 bit one.
+    fn tzif(transitions: &[(i64, u8)], types: &[(i32, &str)], footer: &str) -> Vec<u8> {
+        let mut chars = Vec::new();
+        let mut infos = Vec::new();
+        for (offset, name) in types {
+            infos.extend(offset.to_be_bytes());
+            infos.extend([0, chars.len() as u8]);
+            chars.extend(name.as_bytes());
+            chars.push(0);
+        }
+
+        let block = |time_size: usize| {
+            let mut out = b"TZif2".to_vec();
+            out.extend([0; 15]);
+            for count in [0, 0, 0, transitions.len(), types.len(), chars.len()] {
+                out.extend((count as u32).to_be_bytes());
+            }
+            for (time, _) in transitions {
+                match time_size {
+                    4 => out.extend((*time as i32).to_be_bytes()),
+                    _ => out.extend(time.to_be_bytes()),
+                }
+            }
+            out.extend(transitions.iter().map(|(_, index)| index));
+            out.extend(&infos);
+            out.extend(&chars);
+            out
+        };
+
+        let mut data = block(4);
+        data.extend(block(8));
+        data.extend(format!("\n{}\n", footer).bytes());
+        data
+    }
+
+    #[test]
+    fn parses_fixed_offsets() {
+        assert_eq!("+02:00".parse(), Ok(Zone::Fixed(7200)));
+        assert_eq!("-0530".parse(), Ok(Zone::Fixed(-19_800)));
+        assert_eq!("+5".parse(), Ok(Zone::Fixed(18_000)));
+        assert_eq!(parse_fixed_offset("+24:00"), None);
+        assert_eq!(parse_fixed_offset("02:00"), None);
+        // POSIX counts west of UTC as positive.
+        let zone = "UTC+5".parse::<Zone>().unwrap();
+        assert_eq!(zone.offset_at(0), (-18_000, "UTC".to_owned()));
+        assert!("Nowhere/Atlantis".parse::<Zone>().is_err());
+    }
+
+    #[test]
+    fn parses_tzif_files() {
+        // Berlin around 2025: CEST from March 30th, CET from October 26th.
+        let data = tzif(
+            &[(1_743_296_400, 1), (1_761_440_400, 0)],
+            &[(3600, "CET"), (7200, "CEST")],
+            "CET-1CEST,M3.5.0,M10.5.0/3",
+        );
+        let tz = TzFile::parse(&data).unwrap();
+        assert_eq!(tz.transitions, [1_743_296_400, 1_761_440_400]);
+        let zone = Zone::Tz(Box::new(tz));
+
+        // Before the first transition the first type applies.
+        assert_eq!(zone.offset_at(0), (3600, "CET".to_owned()));
+        assert_eq!(zone.offset_at(1_743_296_399).1, "CET");
+        assert_eq!(zone.offset_at(1_743_296_400).1, "CEST");
+        // After the last one the footer takes over, summer of 2026.
+        assert_eq!(zone.offset_at(1_783_000_000), (7200, "CEST".to_owned()));
+
+        assert!(TzFile::parse(b"TZif2").is_none());
+        assert!(TzFile::parse(b"not a zone").is_none());
+        let mut truncated = data.clone();
+        truncated.truncate(60);
+        assert!(TzFile::parse(&truncated).is_none());
+    }
+
+    #[test]
+    fn follows_posix_rules_across_changes() {
+        let berlin = "CET-1CEST,M3.5.0,M10.5.0/3".parse::<Zone>().unwrap();
+        // 2026-03-29 at 01:00 UTC, 02:00 local time becomes 03:00.
+        assert_eq!(berlin.offset_at(1_774_745_999).0, 3600);
+        assert_eq!(berlin.offset_at(1_774_746_000).0, 7200);
+        // 2026-10-25 at 01:00 UTC, 03:00 local time becomes 02:00 again.
+        assert_eq!(berlin.offset_at(1_792_889_999).0, 7200);
+        assert_eq!(berlin.offset_at(1_792_890_000).0, 3600);
+
+        // Sydney has DST over the new year.
+        let sydney = "AEST-10AEDT,M10.1.0,M4.1.0/3".parse::<Zone>().unwrap();
+        assert_eq!(sydney.offset_at(1_767_225_600).1, "AEDT");
+        assert_eq!(sydney.offset_at(1_783_000_000).1, "AEST");
+
+        let tokyo = "<+09>-9".parse::<Zone>().unwrap();
+        assert_eq!(tokyo.offset_at(0), (32_400, "+09".to_owned()));
+        // Without rules DST follows the US ones, 2026-03-08 at 07:00 UTC.
+        let new_york = "EST5EDT".parse::<Zone>().unwrap();
+        assert_eq!(new_york.offset_at(1_772_953_199).1, "EST");
+        assert_eq!(new_york.offset_at(1_772_953_200).1, "EDT");
+
+        assert_eq!(
+            Change::parse("J60/1:30"),
+            Some(Change {
+                day: RuleDay::Julian(60),
+                time: 5400
+            })
+        );
+        assert!(PosixRule::parse("CET-1CEST,M13.5.0,M10.5.0").is_none());
+        assert!(PosixRule::parse("CET-1CEST,M3.5.0").is_none());
+    }
+
+    #[test]
+    fn reads_back_rfc3339() {
+        let time = at(1_775_214_245) + Duration::from_millis(123);
+        assert_eq!(parse_rfc3339(&rfc3339(time, &Zone::Utc)), Some(time));
+        assert_eq!(parse_rfc3339("2026-04-03T05:34:05.123-05:30"), Some(time));
+        assert_eq!(
+            parse_rfc3339("2026-04-03 11:04:05Z"),
+            Some(at(1_775_214_245))
+        );
+        assert_eq!(
+            parse_rfc3339("2026-04-03T11:04:05.123456789123+00:00"),
+            Some(at(1_775_214_245) + Duration::from_nanos(123_456_789))
+        );
+        for invalid in [
+            "2026-04-03",
+            "2026-02-30T00:00:00Z",
+            "2026-04-03T24:00:00Z",
+            "2026-04-03T11:04:05",
+            "2026-04-03T11:04:05.Z",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn formats_specifiers() {
+        let time = at(1_775_214_245) + Duration::from_nanos(123_456_789);
+        let berlin = "CET-1CEST,M3.5.0,M10.5.0/3".parse::<Zone>().unwrap();
+        assert_eq!(
+            format(time, &berlin, "%Y %y %m %d %e %j %H %I %p %M %S").unwrap(),
+            "2026 26 04 03  3 093 13 01 PM 04 05"
+        );
+        assert_eq!(
+            format(time, &berlin, "%a %A %b %B %F %T %z %:z %Z %s %%").unwrap(),
+            "Fri Friday Apr April 2026-04-03 13:04:05 +0200 +02:00 CEST 1775214245 %"
+        );
+        assert_eq!(
+            format(time, &Zone::Utc, "%3f %6f %9f|%.3f").unwrap(),
+            "123 123456 123456789|.123"
+        );
+        assert!(format(time, &Zone::Utc, "%q").unwrap_err().contains("`%q`"));
+        assert!(check_format("50%").is_err());
+
+        assert_eq!(rfc3339(time, &Zone::Utc), "2026-04-03T11:04:05.123Z");
+        assert_eq!(
+            rfc3339(time, &Zone::Fixed(-19_800)),
+            "2026-04-03T05:34:05.123-05:30"
+        );
+        // Before the epoch, fractions count forward from the second before.
+        assert_eq!(
+            rfc3339(UNIX_EPOCH - Duration::from_millis(1), &Zone::Utc),
+            "1969-12-31T23:59:59.999Z"
+        );
+    }
+}
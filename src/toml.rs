@@ -0,0 +1,506 @@
+//! Parser for the subset of TOML the manifest and configuration files use:
+//! tables, arrays of tables, strings, integers, floats, booleans and arrays
+//! of those, one key per line. Inline tables, dates and multi-line strings
+//! are not supported.
+
+use std::{collections::BTreeMap, fmt};
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Integers are accepted as floats too.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Float(f) => Some(*f),
+            Self::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Self::Table(table) => Some(table),
+            _ => None,
+        }
+    }
+
+    /// Name of the type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::Integer(_) => "integer",
+            Self::Float(_) => "float",
+            Self::Boolean(_) => "boolean",
+            Self::Array(_) => "array",
+            Self::Table(_) => "table",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(input: &str) -> Result<Table, ParseError> {
+    let mut root = Table::new();
+    // Path of the table keys are currently added to.
+    let mut current = Vec::<String>::new();
+
+    let mut lines = input.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let error = |message: String| ParseError {
+            line: index + 1,
+            message,
+        };
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix("[[") {
+            let header = header
+                .strip_suffix("]]")
+                .ok_or_else(|| error("missing `]]`".to_owned()))?;
+            current = parse_key(header).map_err(error)?;
+            let (last, parents) = current.split_last().unwrap();
+            let parent = table_at(&mut root, parents).map_err(error)?;
+            match parent
+                .entry(last.clone())
+                .or_insert_with(|| Value::Array(Vec::new()))
+            {
+                Value::Array(tables) => tables.push(Value::Table(Table::new())),
+                other => {
+                    return Err(error(format!(
+                        "`{}` is a {}, not an array of tables",
+                        last,
+                        other.type_name()
+                    )))
+                }
+            }
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error("missing `]`".to_owned()))?;
+            current = parse_key(header).map_err(error)?;
+            table_at(&mut root, &current).map_err(error)?;
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected `key = value`, found `{}`", line)))?;
+        let key = parse_key(key).map_err(error)?;
+
+        // Arrays may continue over several lines until their brackets
+        // balance.
+        let mut value = value.trim().to_owned();
+        while value.starts_with('[') && !brackets_balanced(&value) {
+            let (_, next) = lines
+                .next()
+                .ok_or_else(|| error("unterminated array".to_owned()))?;
+            value.push(' ');
+            value.push_str(strip_comment(next).trim());
+        }
+
+        let value = parse_value(&value).map_err(error)?;
+        let (last, parents) = key.split_last().unwrap();
+        let path = current.iter().chain(parents).cloned().collect::<Vec<_>>();
+        let table = table_at(&mut root, &path).map_err(error)?;
+        if table.insert(last.clone(), value).is_some() {
+            return Err(error(format!("duplicate key `{}`", last)));
+        }
+    }
+
+    Ok(root)
+}
+
+/// The table at `path`, created if needed. The last element of an array of
+/// tables stands for the array.
+fn table_at<'a>(mut table: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    for key in path {
+        let value = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = match value {
+            Value::Table(table) => table,
+            Value::Array(values) => match values.last_mut() {
+                Some(Value::Table(table)) => table,
+                _ => return Err(format!("`{}` is not a table", key)),
+            },
+            other => return Err(format!("`{}` is a {}, not a table", key, other.type_name())),
+        };
+    }
+    Ok(table)
+}
+
+/// Drops a `#` comment, unless it is inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..index],
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    line
+}
+
+fn brackets_balanced(value: &str) -> bool {
+    let mut depth = 0_i32;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in value.chars() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            _ => {}
+        }
+        escaped = false;
+    }
+    depth <= 0
+}
+
+/// Parses a bare or dotted key like `a.b` or `"quoted key"`.
+fn parse_key(key: &str) -> Result<Vec<String>, String> {
+    let key = key.trim();
+    let mut parts = Vec::new();
+
+    for part in key.split('.') {
+        let part = part.trim();
+        let part = match part.as_bytes().first() {
+            Some(b'"' | b'\'') => match parse_value(part)? {
+                Value::String(s) => s,
+                _ => unreachable!(),
+            },
+            _ if !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') =>
+            {
+                part.to_owned()
+            }
+            _ => return Err(format!("invalid key `{}`", key)),
+        };
+        parts.push(part);
+    }
+
+    Ok(parts)
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    let mut parser = ValueParser { rest: value.trim() };
+    let parsed = parser.value()?;
+    match parser.rest.trim() {
+        "" => Ok(parsed),
+        rest => Err(format!("unexpected `{}` after value", rest)),
+    }
+}
+
+struct ValueParser<'a> {
+    rest: &'a str,
+}
+
+impl ValueParser<'_> {
+    fn value(&mut self) -> Result<Value, String> {
+        self.rest = self.rest.trim_start();
+
+        match self.rest.chars().next() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            Some('[') => self.array(),
+            Some('{') => Err("inline tables are not supported".to_owned()),
+            Some(_) => self.scalar(),
+            None => Err("missing value".to_owned()),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<Value, String> {
+        let mut out = String::new();
+        let mut chars = self.rest[1..].char_indices();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[index + 2..];
+                    return Ok(Value::String(out));
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('u') => {
+                        let hex = chars.by_ref().take(4).map(|(_, c)| c).collect::<String>();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid escape `\\u{}`", hex))?;
+                        out.push(c);
+                    }
+                    other => return Err(format!("invalid escape `\\{}`", other.unwrap_or(' '))),
+                },
+                c => out.push(c),
+            }
+        }
+
+        Err("unterminated string".to_owned())
+    }
+
+    fn literal_string(&mut self) -> Result<Value, String> {
+        let end = self.rest[1..]
+            .find('\'')
+            .ok_or_else(|| "unterminated string".to_owned())?;
+        let value = self.rest[1..end + 1].to_owned();
+        self.rest = &self.rest[end + 2..];
+        Ok(Value::String(value))
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.rest = &self.rest[1..];
+        let mut values = Vec::new();
+
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix(']') {
+                self.rest = rest;
+                return Ok(Value::Array(values));
+            }
+
+            values.push(self.value()?);
+
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix(',') {
+                self.rest = rest;
+            } else if !self.rest.starts_with(']') {
+                return Err("expected `,` or `]` in array".to_owned());
+            }
+        }
+    }
+
+    fn scalar(&mut self) -> Result<Value, String> {
+        let end = self
+            .rest
+            .find([',', ']', ' ', '\t'])
+            .unwrap_or(self.rest.len());
+        let (token, rest) = self.rest.split_at(end);
+        self.rest = rest;
+
+        let value = match token {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            _ => {
+                let number = token.replace('_', "");
+                if let Ok(integer) = number.parse::<i64>() {
+                    Value::Integer(integer)
+                } else if let Ok(float) = number.parse::<f64>() {
+                    Value::Float(float)
+                } else {
+                    return Err(format!("invalid value `{}`", token));
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_owned())
+    }
+
+    fn error(input: &str) -> ParseError {
+        parse(input).unwrap_err()
+    }
+
+    #[test]
+    fn parses_strings_and_escapes() {
+        let table = parse(concat!(
+            "basic = \"tab\\there \\\"quoted\\\" \\\\ \\u00e9\"\n",
+            "literal = 'C:\\path\\#not a comment'\n",
+            "hash = \"a # b\" # a comment\n",
+            "\"quoted key\" = \"\"\n",
+        ))
+        .unwrap();
+
+        assert_eq!(table["basic"], string("tab\there \"quoted\" \\ é"));
+        assert_eq!(table["literal"], string("C:\\path\\#not a comment"));
+        assert_eq!(table["hash"], string("a # b"));
+        assert_eq!(table["quoted key"], string(""));
+
+        assert!(parse("a = \"\\q\"").is_err());
+        assert!(parse("a = \"\\uzzzz\"").is_err());
+        assert!(parse("a = \"open").is_err());
+        assert!(parse("a = 'open").is_err());
+    }
+
+    #[test]
+    fn parses_scalars_and_arrays() {
+        let table = parse(concat!(
+            "integer = -1_000\n",
+            "float = 2.5\n",
+            "yes = true\n",
+            "nested = [[1, 2], [\"a\\\"]\", 'b']]\n",
+            "multi = [\n",
+            "  1, # one\n",
+            "  2,\n",
+            "]\n",
+            "empty = []\n",
+        ))
+        .unwrap();
+
+        assert_eq!(table["integer"], Value::Integer(-1000));
+        assert_eq!(table["float"].as_float(), Some(2.5));
+        assert_eq!(table["integer"].as_float(), Some(-1000.0));
+        assert_eq!(table["yes"].as_bool(), Some(true));
+        assert_eq!(
+            table["nested"],
+            Value::Array(vec![
+                Value::Array(vec![Value::Integer(1), Value::Integer(2)]),
+                Value::Array(vec![string("a\"]"), string("b")]),
+            ])
+        );
+        assert_eq!(
+            table["multi"].as_array(),
+            Some(&[Value::Integer(1), Value::Integer(2)][..])
+        );
+        assert_eq!(table["empty"].as_array(), Some(&[][..]));
+    }
+
+    #[test]
+    fn parses_tables() {
+        let table = parse(concat!(
+            "top = 1\n",
+            "[server]\n",
+            "host = \"a\"\n",
+            "tls.enabled = false\n",
+            "[[workload]]\n",
+            "name = \"one\"\n",
+            "[[workload]]\n",
+            "name = \"two\"\n",
+            "[workload.env]\n",
+            "CC = \"clang\"\n",
+        ))
+        .unwrap();
+
+        assert_eq!(table["top"], Value::Integer(1));
+        let server = table["server"].as_table().unwrap();
+        assert_eq!(server["host"], string("a"));
+        assert_eq!(
+            server["tls"].as_table().unwrap()["enabled"],
+            Value::Boolean(false)
+        );
+        let workloads = table["workload"].as_array().unwrap();
+        assert_eq!(workloads.len(), 2);
+        // Subtables go to the last table of the array.
+        let second = workloads[1].as_table().unwrap();
+        assert_eq!(second["name"], string("two"));
+        assert_eq!(second["env"].as_table().unwrap()["CC"], string("clang"));
+        assert!(workloads[0].as_table().unwrap().get("env").is_none());
+    }
+
+    #[test]
+    fn reports_malformed_input_with_its_line() {
+        assert_eq!(
+            error("a = 1\nb"),
+            ParseError {
+                line: 2,
+                message: "expected `key = value`, found `b`".to_owned()
+            }
+        );
+        assert_eq!(error("a = 1\na = 2").message, "duplicate key `a`");
+        assert_eq!(error("[table").message, "missing `]`");
+        assert_eq!(error("[[tables]").message, "missing `]]`");
+        assert_eq!(error("a = [1,\n2").message, "unterminated array");
+        assert_eq!(error("a = 1\n[a]").message, "`a` is a integer, not a table");
+        assert_eq!(
+            error("a = 1\n[[a]]").message,
+            "`a` is a integer, not an array of tables"
+        );
+        assert_eq!(
+            error("a = { b = 1 }").message,
+            "inline tables are not supported"
+        );
+        assert_eq!(error("a = 1 2").message, "unexpected `2` after value");
+        assert_eq!(error("a = [1 2]").message, "expected `,` or `]` in array");
+        assert_eq!(error("a = nope").message, "invalid value `nope`");
+        assert_eq!(error("a b = 1").message, "invalid key `a b`");
+        assert_eq!(error("a. = 1").message, "invalid key `a.`");
+        assert_eq!(error("a =").message, "missing value");
+        assert_eq!(
+            error("\n\nx").to_string(),
+            "line 3: expected `key = value`, found `x`"
+        );
+    }
+}
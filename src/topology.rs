@@ -0,0 +1,391 @@
+//! Grouping of cores into chiplets and NUMA nodes.
+//!
+//! Zen CPUs are built from core complexes (CCX) sharing one L3 cache, which
+//! sit on core complex dies (CCD). Neither is exposed by name in sysfs, so
+//! they are derived from L3 sharing and, where the kernel reports it, the die
+//! id. NUMA nodes, as set up with NPS2 or NPS4 on EPYC, come straight from
+//! their `cpulist`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    str::FromStr,
+};
+
+use crate::{cpuinfo::CpuInfo, sysfs::Root, Error, Result};
+
+const NODE_PATH: &str = "/sys/devices/system/node";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Ccd,
+    Ccx,
+    Numa,
+}
+
+impl Grouping {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ccd => "ccd",
+            Self::Ccx => "ccx",
+            Self::Numa => "numa",
+        }
+    }
+}
+
+impl fmt::Display for Grouping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Grouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ccd" => Ok(Self::Ccd),
+            "ccx" => Ok(Self::Ccx),
+            "numa" => Ok(Self::Numa),
+            other => Err(format!(
+                "unknown grouping `{}`, expected ccd, ccx or numa",
+                other
+            )),
+        }
+    }
+}
+
+/// Splits `cores` into groups named like `ccd0`, numbered in order of their
+/// lowest core. NUMA nodes keep the kernel's node number, like `numa1` for
+/// `node1`.
+pub fn groups(
+    root: &Root,
+    grouping: Grouping,
+    cpu: &CpuInfo,
+    cores: &[u32],
+) -> Result<BTreeMap<String, Vec<u32>>> {
+    let ccxs = || group_by(cores, |core| l3_cache_id(root, core));
+    let groups = match grouping {
+        Grouping::Numa => return numa_groups(root, cores),
+        Grouping::Ccx => ccxs()?,
+        Grouping::Ccd => {
+            let dies = group_by(cores, |core| die_id(root, core))?;
+            if dies.len() > 1 {
+                dies
+            } else if cpu.family == 0x17 {
+                // Zen 1 and 2 put two CCX on every die, and older kernels
+                // report the same die id for all of them.
+                ccxs()?.chunks(2).map(|pair| pair.concat()).collect()
+            } else {
+                ccxs()?
+            }
+        }
+    };
+
+    Ok(groups
+        .into_iter()
+        .enumerate()
+        .map(|(index, cores)| (format!("{}{}", grouping, index), cores))
+        .collect())
+}
+
+/// The power of `core_power` summed per group. Groups may share cores, and
+/// cores left out of `core_power` add nothing.
+pub fn group_power(
+    groups: &BTreeMap<String, Vec<u32>>,
+    core_power: &BTreeMap<u32, f64>,
+) -> BTreeMap<String, f64> {
+    groups
+        .iter()
+        .map(|(group, cores)| {
+            let power = cores.iter().filter_map(|core| core_power.get(core)).sum();
+            (group.clone(), power)
+        })
+        .collect()
+}
+
+/// The cores of each physical package, keyed by its id.
+pub fn packages(root: &Root, cores: &[u32]) -> Result<BTreeMap<u32, Vec<u32>>> {
+    let mut packages = BTreeMap::<u32, Vec<u32>>::new();
+    for &core in cores {
+        let (package, _) = die_id(root, core)?;
+        packages.entry(package).or_default().push(core);
+    }
+    Ok(packages)
+}
+
+fn numa_groups(root: &Root, cores: &[u32]) -> Result<BTreeMap<String, Vec<u32>>> {
+    Ok(numa_nodes(root)?
+        .into_iter()
+        .map(|(node, cpus)| {
+            let cores = cores
+                .iter()
+                .copied()
+                .filter(|core| cpus.contains(core))
+                .collect::<Vec<_>>();
+            (format!("{}{}", Grouping::Numa, node), cores)
+        })
+        // Nodes with memory only, or only offline cores.
+        .filter(|(_, cores)| !cores.is_empty())
+        .collect())
+}
+
+/// The CPUs of each NUMA node, keyed by the node number.
+pub fn numa_nodes(root: &Root) -> Result<BTreeMap<u32, Vec<u32>>> {
+    let dir = root.path(NODE_PATH);
+    let entries = fs::read_dir(&dir).map_err(|err| Error::io(&dir, err))?;
+
+    let mut nodes = BTreeMap::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let Some(node) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|node| node.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        let path = format!("{}/node{}/cpulist", NODE_PATH, node);
+        let list = root.read(&path)?;
+        let cpus = parse_cpulist(&list).ok_or_else(|| Error::parse(root.path(&path), &list))?;
+        nodes.insert(node, cpus);
+    }
+    Ok(nodes)
+}
+
+/// Groups `cores` by the key `id` returns for each, ordered by their lowest
+/// core.
+fn group_by<K: Ord>(cores: &[u32], id: impl Fn(u32) -> Result<K>) -> Result<Vec<Vec<u32>>> {
+    let mut groups = BTreeMap::<K, Vec<u32>>::new();
+    for &core in cores {
+        groups.entry(id(core)?).or_default().push(core);
+    }
+
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    groups.sort_by_key(|cores| cores.iter().min().copied());
+    Ok(groups)
+}
+
+/// The CPUs sharing the L3 cache with `core`, which identifies its CCX.
+fn l3_cache_id(root: &Root, core: u32) -> Result<BTreeSet<u32>> {
+    let path = format!(
+        "/sys/devices/system/cpu/cpu{}/cache/index3/shared_cpu_list",
+        core
+    );
+    let list = root.read(&path)?;
+    parse_cpulist(&list)
+        .map(BTreeSet::from_iter)
+        .ok_or_else(|| Error::parse(root.path(&path), &list))
+}
+
+fn die_id(root: &Root, core: u32) -> Result<(u32, u32)> {
+    let topology = format!("/sys/devices/system/cpu/cpu{}/topology", core);
+    let read_id = |name: &str| {
+        let path = format!("{}/{}", topology, name);
+        let value = root.read(&path)?;
+        value
+            .parse::<u32>()
+            .map_err(|_| Error::parse(root.path(&path), &value))
+    };
+
+    // Dies are only unique within a package.
+    Ok((read_id("physical_package_id")?, read_id("die_id")?))
+}
+
+/// Formats CPUs as a kernel CPU list, the reverse of [`parse_cpulist`].
+pub fn format_cpulist(cpus: &[u32]) -> String {
+    let mut cpus = cpus.to_vec();
+    cpus.sort_unstable();
+    cpus.dedup();
+
+    let mut ranges = Vec::new();
+    let mut rest = cpus.as_slice();
+    while let Some(&first) = rest.first() {
+        let len = rest
+            .iter()
+            .zip(first..)
+            .take_while(|(&cpu, expected)| cpu == *expected)
+            .count();
+        ranges.push(match len {
+            1 => first.to_string(),
+            _ => format!("{}-{}", first, first + len as u32 - 1),
+        });
+        rest = &rest[len..];
+    }
+    ranges.join(",")
+}
+
+/// Parses a kernel CPU list like `0-3,8,10-11`.
+pub fn parse_cpulist(s: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+
+    for part in s.trim().split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (first.parse::<u32>().ok()?, last.parse::<u32>().ok()?);
+                if first > last {
+                    return None;
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+
+    Some(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::fixture::{self, Fixture};
+
+    fn cpu(family: u32) -> CpuInfo {
+        CpuInfo {
+            vendor: "AuthenticAMD".to_owned(),
+            family,
+            ..CpuInfo::default()
+        }
+    }
+
+    fn groups_of(
+        listing: &str,
+        grouping: Grouping,
+        family: u32,
+        cores: impl IntoIterator<Item = u32>,
+    ) -> Vec<(String, Vec<u32>)> {
+        let fixture = Fixture::from_listing(listing);
+        let cores = cores.into_iter().collect::<Vec<_>>();
+        groups(fixture.root(), grouping, &cpu(family), &cores)
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn sums_power_per_group() {
+        let groups = BTreeMap::from([
+            ("game".to_owned(), vec![0, 1, 2]),
+            ("background".to_owned(), vec![2, 3]),
+            ("offline".to_owned(), vec![7]),
+        ]);
+        let core_power = BTreeMap::from([(0, 4.0), (1, 2.5), (2, 1.5), (3, 0.5)]);
+        assert_eq!(
+            group_power(&groups, &core_power),
+            BTreeMap::from([
+                ("background".to_owned(), 2.0),
+                ("game".to_owned(), 8.0),
+                ("offline".to_owned(), 0.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_cpulists() {
+        assert_eq!(
+            parse_cpulist("0-3,8,10-11"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpulist("5\n"), Some(vec![5]));
+        assert_eq!(parse_cpulist(""), Some(vec![]));
+        assert_eq!(parse_cpulist("3-1"), None);
+        assert_eq!(parse_cpulist("0-x"), None);
+    }
+
+    #[test]
+    fn formats_cpulists() {
+        assert_eq!(format_cpulist(&[0, 1, 2, 3, 8, 10, 11]), "0-3,8,10-11");
+        assert_eq!(format_cpulist(&[5, 4, 4]), "4-5");
+        assert_eq!(format_cpulist(&[]), "");
+        let cpus = parse_cpulist("0-2,4-10,12,14-15").unwrap();
+        assert_eq!(format_cpulist(&cpus), "0-2,4-10,12,14-15");
+    }
+
+    #[test]
+    fn cores_per_package() {
+        let fixture = Fixture::from_listing(fixture::DUAL_SOCKET);
+        let cores = (0..16).collect::<Vec<_>>();
+        let packages = packages(fixture.root(), &cores).unwrap();
+        assert_eq!(packages[&0], (0..8).collect::<Vec<_>>());
+        assert_eq!(packages[&1], (8..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn one_ccx_per_ccd_on_zen3() {
+        let ccds = groups_of(fixture::ZEN3_SMT, Grouping::Ccd, 0x19, 0..16);
+        assert_eq!(
+            ccds,
+            [
+                ("ccd0".to_owned(), (0..8).collect()),
+                ("ccd1".to_owned(), (8..16).collect()),
+            ]
+        );
+        let ccxs = groups_of(fixture::ZEN3_SMT, Grouping::Ccx, 0x19, 0..16);
+        assert_eq!(ccxs.len(), 2);
+        assert_eq!(ccxs[1], ("ccx1".to_owned(), (8..16).collect()));
+    }
+
+    #[test]
+    fn pairs_ccxs_into_ccds_on_zen2() {
+        let ccxs = groups_of(fixture::ZEN2_SMT_OFF, Grouping::Ccx, 0x17, 0..8);
+        assert_eq!(
+            ccxs,
+            [
+                ("ccx0".to_owned(), vec![0, 1, 2, 3]),
+                ("ccx1".to_owned(), vec![4, 5, 6, 7]),
+            ]
+        );
+        let ccds = groups_of(fixture::ZEN2_SMT_OFF, Grouping::Ccd, 0x17, 0..8);
+        assert_eq!(ccds, [("ccd0".to_owned(), (0..8).collect())]);
+    }
+
+    #[test]
+    fn dies_are_per_package() {
+        let ccds = groups_of(fixture::DUAL_SOCKET, Grouping::Ccd, 0x19, 0..16);
+        let cores = ccds.into_iter().map(|(_, cores)| cores).collect::<Vec<_>>();
+        assert_eq!(
+            cores,
+            [
+                vec![0, 1, 2, 3],
+                vec![4, 5, 6, 7],
+                vec![8, 9, 10, 11],
+                vec![12, 13, 14, 15]
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_cores_by_numa_node() {
+        let nodes = groups_of(fixture::DUAL_SOCKET, Grouping::Numa, 0x19, 0..16);
+        assert_eq!(
+            nodes,
+            [
+                ("numa0".to_owned(), vec![0, 1, 2, 3]),
+                ("numa1".to_owned(), vec![4, 5, 6, 7]),
+                ("numa2".to_owned(), vec![8, 9, 10, 11]),
+                ("numa3".to_owned(), vec![12, 13, 14, 15]),
+            ]
+        );
+
+        let fixture = Fixture::from_listing(fixture::DUAL_SOCKET);
+        fixture.file("/sys/devices/system/node/node4/cpulist", "\n");
+        assert_eq!(numa_nodes(fixture.root()).unwrap()[&4], []);
+        let nodes = groups(fixture.root(), Grouping::Numa, &cpu(0x19), &[0, 8]).unwrap();
+        assert_eq!(nodes.keys().collect::<Vec<_>>(), ["numa0", "numa2"]);
+    }
+
+    #[test]
+    fn skips_offline_cores() {
+        let cores = [0, 1, 2, 4, 5, 6, 7];
+        let ccds = groups_of(fixture::ZEN3_OFFLINE, Grouping::Ccd, 0x19, cores);
+        assert_eq!(ccds, [("ccd0".to_owned(), cores.to_vec())]);
+    }
+
+    #[test]
+    fn missing_cache_info_is_an_error() {
+        let fixture = Fixture::new();
+        let err = groups(fixture.root(), Grouping::Ccx, &cpu(0x19), &[0]).unwrap_err();
+        assert!(matches!(err, Error::Io { .. }), "{:?}", err);
+    }
+}
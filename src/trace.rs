@@ -0,0 +1,198 @@
+//! Samples as a trace in Chrome's trace event format, which the Perfetto UI
+//! and `chrome://tracing` open, for `--trace`: the power of the package, of
+//! every core, group and domain as counter tracks and, with `--sched`, what
+//! ran on each CPU as slices on a track of its own.
+//!
+//! Trace events have timestamps in microseconds. Samples and slices are put
+//! on the monotonic clock the slices come with, samples as counters that
+//! hold their power from the start of their window on.
+
+use std::{
+    collections::BTreeSet,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    output::{json_number, json_string, Sample},
+    sched::{Slice, Tracer},
+    suspend,
+};
+
+/// The process the counter tracks are in.
+const POWER_PID: u32 = 1;
+/// The process with a thread for every CPU the slices are on.
+const CPUS_PID: u32 = 2;
+
+/// The events of a run so far.
+#[derive(Debug)]
+pub struct Trace {
+    events: Vec<String>,
+    /// Where `sched_switch` slices come from, without `--sched` none.
+    tracer: Option<Tracer>,
+    cpus: BTreeSet<u32>,
+    /// The wall clock and the monotonic one at the same time.
+    clocks: (SystemTime, Duration),
+}
+
+impl Trace {
+    /// Starts a trace, with slices from `tracer` if there is one.
+    pub fn new(tracer: Option<Tracer>) -> Self {
+        Self {
+            events: Vec::new(),
+            tracer,
+            cpus: BTreeSet::new(),
+            clocks: (SystemTime::now(), suspend::monotonic()),
+        }
+    }
+
+    /// Adds `sample`, and the slices that ended since the last one.
+    pub fn add(&mut self, sample: &Sample) {
+        let start = sample
+            .timestamp
+            .checked_sub(sample.window)
+            .unwrap_or(sample.timestamp);
+        let ts = self.micros(start);
+        let mut counter = |name: &str, watts: f64| {
+            self.events.push(format!(
+                "{{\"name\":{},\"ph\":\"C\",\"ts\":{},\"pid\":{},\"args\":{{\"W\":{}}}}}",
+                json_string(name),
+                ts,
+                POWER_PID,
+                json_number(watts)
+            ));
+        };
+        counter("Package", sample.package_power);
+        for (core, &watts) in &sample.core_power {
+            counter(&format!("Core {}", core), watts);
+        }
+        for (group, &watts) in &sample.group_power {
+            counter(&format!("Group {}", group), watts);
+        }
+        for (domain, &watts) in &sample.domain_power {
+            counter(&format!("Domain {}", domain), watts);
+        }
+
+        if let Some(tracer) = &self.tracer {
+            for slice in tracer.take_slices() {
+                self.add_slice(&slice);
+            }
+        }
+    }
+
+    fn add_slice(&mut self, slice: &Slice) {
+        self.cpus.insert(slice.cpu);
+        self.events.push(format!(
+            "{{\"name\":{},\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":{},\"tid\":{},\
+             \"args\":{{\"pid\":{}}}}}",
+            json_string(&slice.name),
+            slice.start as f64 / 1000.0,
+            slice.end.saturating_sub(slice.start) as f64 / 1000.0,
+            CPUS_PID,
+            slice.cpu,
+            slice.pid
+        ));
+    }
+
+    /// Microseconds on the monotonic clock at `time` on the wall clock.
+    fn micros(&self, time: SystemTime) -> u64 {
+        let (wall, monotonic) = self.clocks;
+        let monotonic = match time.duration_since(wall) {
+            Ok(after) => monotonic + after,
+            Err(before) => monotonic.saturating_sub(before.duration()),
+        };
+        monotonic.as_micros() as u64
+    }
+
+    /// The trace file.
+    pub fn render(&self) -> String {
+        let mut names = Vec::new();
+        let mut name = |pid: u32, tid: Option<u32>, name: &str| {
+            let (kind, tid) = match tid {
+                Some(tid) => ("thread_name", format!(",\"tid\":{}", tid)),
+                None => ("process_name", String::new()),
+            };
+            names.push(format!(
+                "{{\"name\":\"{}\",\"ph\":\"M\",\"pid\":{}{},\"args\":{{\"name\":{}}}}}",
+                kind,
+                pid,
+                tid,
+                json_string(name)
+            ));
+        };
+        name(POWER_PID, None, "Power");
+        if !self.cpus.is_empty() {
+            name(CPUS_PID, None, "CPUs");
+        }
+        for &cpu in &self.cpus {
+            name(CPUS_PID, Some(cpu), &format!("CPU {}", cpu));
+        }
+        let events = names.iter().chain(&self.events);
+        let events = events.map(String::as_str).collect::<Vec<_>>();
+        format!(
+            "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n",
+            events.join(",\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn writes_counters_and_slices_perfetto_reads() {
+        assert!(json::parse(&Trace::new(None).render()).is_ok());
+        let mut trace = Trace::new(None);
+        let (wall, monotonic) = trace.clocks;
+        let sample = Sample {
+            timestamp: wall + Duration::from_secs(2),
+            window: Duration::from_secs(1),
+            package_power: 30.0,
+            core_power: BTreeMap::from([(0, 4.5), (1, 2.0)]),
+            ..Sample::default()
+        };
+        trace.add(&sample);
+        trace.add_slice(&Slice {
+            cpu: 3,
+            pid: 4711,
+            name: "cargo \"build\"".to_owned(),
+            start: 1_000_500,
+            end: 3_000_500,
+        });
+
+        let file = json::parse(&trace.render()).unwrap();
+        let events = file.get("traceEvents").unwrap().as_array().unwrap();
+        let names = events
+            .iter()
+            .map(|event| event.get("name").unwrap().as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "process_name",
+                "process_name",
+                "thread_name",
+                "Package",
+                "Core 0",
+                "Core 1",
+                "cargo \"build\""
+            ]
+        );
+
+        let package = &events[3];
+        let start = (monotonic + Duration::from_secs(1)).as_micros() as f64;
+        assert_eq!(package.get("ts").unwrap().as_f64(), Some(start));
+        assert_eq!(
+            package.get("args").unwrap().get("W").unwrap().as_f64(),
+            Some(30.0)
+        );
+
+        let slice = &events[6];
+        assert_eq!(slice.get("ph").unwrap().as_str(), Some("X"));
+        assert_eq!(slice.get("ts").unwrap().as_f64(), Some(1000.5));
+        assert_eq!(slice.get("dur").unwrap().as_f64(), Some(2000.0));
+        assert_eq!(slice.get("tid").unwrap().as_f64(), Some(3.0));
+    }
+}
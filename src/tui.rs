@@ -0,0 +1,200 @@
+//! Full screen dashboard redrawn in place after every sample.
+//!
+//! Plain ANSI escapes are all it needs: the alternate screen, cursor homing
+//! and clearing to the end of lines.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{c_int, c_ulong},
+    fmt::Write as _,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    graph::{Graph, Style},
+    i18n::tr,
+    output::Sample,
+    stats::Stats,
+};
+
+/// Switches to the alternate screen and hides the cursor.
+pub const ENTER: &str = "\x1b[?1049h\x1b[?25l";
+/// Undoes [`ENTER`].
+pub const LEAVE: &str = "\x1b[?25h\x1b[?1049l";
+
+const GRAPH_HEIGHT: usize = 5;
+/// Width of the labels in front of graphs and bars.
+const LABEL_WIDTH: usize = 9;
+
+#[derive(Debug)]
+pub struct Dashboard {
+    started: Instant,
+    style: Style,
+    package: Stats,
+    package_graph: Graph,
+    cores: BTreeMap<u32, Stats>,
+    latest: Option<Sample>,
+}
+
+impl Dashboard {
+    pub fn new(style: Style) -> Self {
+        let (width, _) = terminal_size();
+        Self {
+            started: Instant::now(),
+            style,
+            package: Stats::default(),
+            package_graph: Graph::new(width.saturating_sub(LABEL_WIDTH + 1), GRAPH_HEIGHT),
+            cores: BTreeMap::new(),
+            latest: None,
+        }
+    }
+
+    pub fn update(&mut self, sample: Sample) {
+        self.package.push(sample.package_power);
+        self.package_graph.push(sample.package_power);
+        for (&core, &power) in &sample.core_power {
+            self.cores.entry(core).or_default().push(power);
+        }
+        self.latest = Some(sample);
+    }
+
+    /// The whole screen, meant to be printed right after homing the cursor.
+    pub fn render(&self) -> String {
+        let (width, height) = terminal_size();
+        let mut lines = Vec::new();
+
+        let uptime = format_uptime(self.started.elapsed());
+        let backend = self.latest.as_ref().map_or("", |sample| sample.backend);
+        lines.push(format!(
+            "ryzen-wattage  {}  {} {}  {} {}  (Ctrl-C)",
+            backend,
+            tr("up"),
+            uptime,
+            tr("samples"),
+            self.package.count
+        ));
+        lines.push(String::new());
+
+        let Some(sample) = &self.latest else {
+            return finish(lines, height);
+        };
+
+        lines.push(format!(
+            "{}: {:.2}W   min {:.2}W  avg {:.2}W  max {:.2}W",
+            tr("Package"),
+            sample.package_power,
+            self.package.min,
+            self.package.mean(),
+            self.package.max
+        ));
+        lines.extend(
+            self.package_graph
+                .render(self.style, "W")
+                .lines()
+                .map(str::to_owned),
+        );
+        lines.push(String::new());
+
+        // Scale the bars to the busiest core this session, so they don't
+        // jump around with every sample.
+        let scale = self
+            .cores
+            .values()
+            .map(|stats| stats.max)
+            .fold(0.0, f64::max);
+        let stats_width = 36;
+        let bar_width = width.saturating_sub(LABEL_WIDTH + 10 + stats_width);
+
+        for (core, &power) in &sample.core_power {
+            let stats = self.cores.get(core).copied().unwrap_or_default();
+            lines.push(format!(
+                "{:<width$}{:>7.2}W {} {:>6.2}/{:>6.2}/{:>6.2}W",
+                format!("{} {}", tr("Core"), core),
+                power,
+                bar(power, scale, bar_width, self.style),
+                stats.min,
+                stats.mean(),
+                stats.max,
+                width = LABEL_WIDTH,
+            ));
+        }
+
+        for (domain, power) in &sample.domain_power {
+            lines.push(format!("{} {}: {:.2}W", tr("Domain"), domain, power));
+        }
+        for (group, power) in &sample.group_power {
+            lines.push(format!("{} {}: {:.2}W", tr("Group"), group, power));
+        }
+
+        finish(lines, height)
+    }
+}
+
+/// Joins `lines`, cut to the screen height, clearing what is left of old
+/// frames.
+fn finish(mut lines: Vec<String>, height: usize) -> String {
+    lines.truncate(height.max(1));
+
+    let mut out = String::from("\x1b[H");
+    for line in lines {
+        writeln!(out, "{}\x1b[K", line).unwrap();
+    }
+    out.push_str("\x1b[J");
+    out
+}
+
+fn bar(value: f64, max: f64, width: usize, style: Style) -> String {
+    let filled = match max > 0.0 {
+        true => ((value / max) * width as f64).round() as usize,
+        false => 0,
+    }
+    .min(width);
+
+    let (full, empty) = match style {
+        Style::Braille => ('█', '░'),
+        Style::Ascii => ('#', '.'),
+    };
+    std::iter::repeat_n(full, filled)
+        .chain(std::iter::repeat_n(empty, width - filled))
+        .collect()
+}
+
+fn format_uptime(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct WinSize {
+    rows: u16,
+    columns: u16,
+    x_pixels: u16,
+    y_pixels: u16,
+}
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+/// Columns and rows of the terminal on stdout, falling back to `COLUMNS`
+/// and `LINES` or 80x24.
+pub fn terminal_size() -> (usize, usize) {
+    const TIOCGWINSZ: c_ulong = 0x5413;
+
+    let mut size = WinSize::default();
+    // SAFETY: TIOCGWINSZ writes one `struct winsize`, which `WinSize` mirrors.
+    let result = unsafe { ioctl(1, TIOCGWINSZ, &mut size as *mut WinSize) };
+    if result == 0 && size.columns > 0 && size.rows > 0 {
+        return (usize::from(size.columns), usize::from(size.rows));
+    }
+
+    let from_env = |var: &str, default: usize| {
+        env::var(var)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    (from_env("COLUMNS", 80), from_env("LINES", 24))
+}
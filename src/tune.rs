@@ -0,0 +1,153 @@
+//! `--tune-backend`: which backend `auto` should use on this machine.
+//!
+//! Reading RAPL costs very different amounts depending on the kernel and
+//! driver: an MSR read is a syscall and an IPI, powercap goes through sysfs
+//! and on some kernels takes a lock for every file. Each backend that opens
+//! is timed reading every counter a sample reads and watched for how often
+//! its package counter advances. The pick is saved in the state, where
+//! `auto` takes it from on later runs.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{BackendKind, Cpu, Result};
+
+/// Backends `auto` can choose from.
+pub const CANDIDATES: [BackendKind; 2] = [BackendKind::Msr, BackendKind::Powercap];
+/// Sweeps timed per backend, the median counts.
+const SWEEPS: usize = 25;
+/// How long the package counter is watched for its updates.
+const WATCH: Duration = Duration::from_millis(200);
+
+/// How one backend did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Benchmark {
+    pub backend: BackendKind,
+    /// Median time to read every counter once.
+    pub sweep: Duration,
+    /// Median time between updates of the package counter, `None` if it
+    /// didn't update often enough to tell.
+    pub granularity: Option<Duration>,
+    pub core_counters: bool,
+}
+
+/// Times `cpu`'s backend.
+pub fn benchmark(cpu: &Cpu) -> Result<Benchmark> {
+    let mut sweeps = Vec::with_capacity(SWEEPS);
+    for _ in 0..SWEEPS {
+        let start = Instant::now();
+        cpu.package_energy()?;
+        cpu.core_energy()?;
+        cpu.domain_energy()?;
+        sweeps.push(start.elapsed());
+    }
+    sweeps.sort();
+
+    let mut updates = Vec::new();
+    let (mut last, _) = cpu.package_energy()?;
+    let start = Instant::now();
+    while start.elapsed() < WATCH {
+        let (energy, time) = cpu.package_energy()?;
+        if energy != last {
+            updates.push(time);
+            last = energy;
+        }
+        thread::yield_now();
+    }
+    let mut intervals = updates
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .collect::<Vec<_>>();
+    intervals.sort();
+
+    Ok(Benchmark {
+        backend: cpu.backend_kind().unwrap_or(BackendKind::Auto),
+        sweep: sweeps[SWEEPS / 2],
+        granularity: intervals.get(intervals.len() / 2).copied(),
+        core_counters: cpu.has_core_counters(),
+    })
+}
+
+/// The best of `benchmarks`: per-core counters if any backend has them,
+/// since nothing makes up for their absence, then the finer updates, then
+/// the cheaper reads. Granularities within a factor of two count as the
+/// same, they are the same hardware counter behind different drivers.
+pub fn pick(benchmarks: &[Benchmark]) -> Option<&Benchmark> {
+    benchmarks
+        .iter()
+        .reduce(|best, other| match better(other, best) {
+            true => other,
+            false => best,
+        })
+}
+
+fn better(a: &Benchmark, b: &Benchmark) -> bool {
+    if a.core_counters != b.core_counters {
+        return a.core_counters;
+    }
+    match (a.granularity, b.granularity) {
+        (Some(_), None) => return true,
+        (None, Some(_)) => return false,
+        (Some(a), Some(b)) if a * 2 < b => return true,
+        (Some(a), Some(b)) if b * 2 < a => return false,
+        _ => {}
+    }
+    a.sweep < b.sweep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn benchmark(
+        backend: BackendKind,
+        sweep_us: u64,
+        granularity_us: Option<u64>,
+        core_counters: bool,
+    ) -> Benchmark {
+        Benchmark {
+            backend,
+            sweep: Duration::from_micros(sweep_us),
+            granularity: granularity_us.map(Duration::from_micros),
+            core_counters,
+        }
+    }
+
+    #[test]
+    fn picks_coverage_then_granularity_then_speed() {
+        let msr = benchmark(BackendKind::Msr, 40, Some(1000), true);
+        let powercap = benchmark(BackendKind::Powercap, 10, Some(1000), false);
+        assert_eq!(pick(&[powercap.clone(), msr.clone()]), Some(&msr));
+
+        // Both with or both without core counters, the same counter behind
+        // a slow driver loses.
+        let powercap = Benchmark {
+            core_counters: true,
+            ..powercap
+        };
+        assert_eq!(pick(&[msr.clone(), powercap.clone()]), Some(&powercap));
+        let jittery = Benchmark {
+            granularity: Some(Duration::from_micros(1500)),
+            ..powercap.clone()
+        };
+        assert_eq!(pick(&[msr.clone(), jittery.clone()]), Some(&jittery));
+
+        // A counter that barely moves is worse than a slow one.
+        let stale = Benchmark {
+            granularity: None,
+            ..powercap
+        };
+        assert_eq!(pick(&[stale, msr.clone()]), Some(&msr));
+        assert_eq!(pick(&[]), None);
+    }
+
+    #[test]
+    fn times_a_backend() {
+        let cpu = Cpu::simulated(crate::backend::Profile::Idle);
+        let benchmark = super::benchmark(&cpu).unwrap();
+        assert!(benchmark.core_counters);
+        assert!(benchmark.sweep > Duration::ZERO);
+    }
+}
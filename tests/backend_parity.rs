@@ -0,0 +1,228 @@
+//! Every backend this machine has, checked against the others: the same
+//! fields in the output, power that agrees and the same behaviour on the
+//! command line.
+//!
+//! This needs real counters, which are usually root-only, so it only runs
+//! when opted in with `RYZEN_WATTAGE_HARDWARE_TESTS=1`, e.g.
+//! `sudo -E RYZEN_WATTAGE_HARDWARE_TESTS=1 cargo test --test backend_parity`.
+
+use std::{collections::BTreeSet, env, process::Command, thread, time::Duration};
+
+use ryzen_wattage::{
+    json::{self, Value},
+    BackendKind, Cpu,
+};
+
+const OPT_IN: &str = "RYZEN_WATTAGE_HARDWARE_TESTS";
+
+/// Backends can disagree by a bit, the counters aren't read at the same
+/// instant and some round to whole microjoules.
+const TOLERANCE_PERCENT: f64 = 5.0;
+const TOLERANCE_WATTS: f64 = 1.0;
+
+/// The backends that can be opened here, none unless opted in.
+fn backends() -> Vec<(BackendKind, Cpu)> {
+    if env::var_os(OPT_IN).is_none() {
+        eprintln!(
+            "skipped, set {}=1 to test the counters of this machine",
+            OPT_IN
+        );
+        return Vec::new();
+    }
+
+    let backends = [BackendKind::Msr, BackendKind::Powercap]
+        .into_iter()
+        .filter_map(|kind| Some((kind, Cpu::new(kind).ok()?)))
+        .collect::<Vec<_>>();
+    assert!(
+        !backends.is_empty(),
+        "no backend can be opened on this machine"
+    );
+    backends
+}
+
+fn agrees(a: f64, b: f64) -> bool {
+    let difference = (a - b).abs();
+    difference <= TOLERANCE_WATTS || difference <= a.abs().max(b.abs()) * TOLERANCE_PERCENT / 100.0
+}
+
+/// Runs the binary with `args` on `backend`, returns the exit code and
+/// stdout.
+fn run(backend: BackendKind, args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_ryzen-wattage"))
+        .args(["--backend", backend.name()])
+        .args(args)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        !stderr.contains("error:"),
+        "{} with {:?}: {}",
+        backend.name(),
+        args,
+        stderr
+    );
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn keys(value: &Value) -> BTreeSet<String> {
+    value.as_object().unwrap().keys().cloned().collect()
+}
+
+#[test]
+fn every_backend_has_advancing_counters() {
+    for (kind, cpu) in backends() {
+        assert!(cpu.energy_unit().is_some(), "{}", kind.name());
+
+        let power = cpu.power(Duration::from_millis(500)).unwrap();
+        assert!(
+            power.package > 0.0 && power.package < 1000.0,
+            "{}: {}W",
+            kind.name(),
+            power.package
+        );
+        assert!(power.cores.values().all(|&watts| watts >= 0.0));
+        assert_eq!(
+            power.cores.keys().copied().collect::<Vec<_>>(),
+            cpu.core_ids(),
+            "{}",
+            kind.name()
+        );
+    }
+}
+
+#[test]
+fn backends_agree_on_power() {
+    let backends = backends();
+    let before = backends
+        .iter()
+        .map(|(_, cpu)| cpu.snapshot().unwrap())
+        .collect::<Vec<_>>();
+    thread::sleep(Duration::from_secs(1));
+    let after = backends
+        .iter()
+        .map(|(_, cpu)| cpu.snapshot().unwrap())
+        .collect::<Vec<_>>();
+
+    let power = backends
+        .iter()
+        .zip(before.iter().zip(&after))
+        .map(|((kind, cpu), (before, after))| (kind.name(), cpu.power_between(before, after)))
+        .collect::<Vec<_>>();
+    let Some(((first, reference), others)) = power.split_first() else {
+        return;
+    };
+
+    for (name, power) in others {
+        assert!(
+            agrees(reference.package, power.package),
+            "package: {} {}W, {} {}W",
+            first,
+            reference.package,
+            name,
+            power.package
+        );
+        // Only where both have per-core counters.
+        for (core, watts) in &power.cores {
+            if let Some(&expected) = reference.cores.get(core) {
+                assert!(
+                    agrees(expected, *watts),
+                    "core {}: {} {}W, {} {}W",
+                    core,
+                    first,
+                    expected,
+                    name,
+                    watts
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn json_output_has_the_same_fields() {
+    let samples = backends()
+        .into_iter()
+        .map(|(kind, cpu)| {
+            let (code, stdout) = run(kind, &["--format", "json", "--interval", "200ms"]);
+            assert_eq!(code, Some(0));
+            let sample = json::parse(stdout.trim()).unwrap();
+            assert_eq!(
+                sample.get("backend").and_then(Value::as_str),
+                Some(cpu.backend_name())
+            );
+            (kind, cpu.has_core_counters(), sample)
+        })
+        .collect::<Vec<_>>();
+
+    for pair in samples.windows(2) {
+        let [(a, a_cores, a_sample), (b, b_cores, b_sample)] = pair else {
+            unreachable!()
+        };
+        assert_eq!(
+            keys(a_sample),
+            keys(b_sample),
+            "{} vs {}",
+            a.name(),
+            b.name()
+        );
+        if *a_cores && *b_cores {
+            let cores = |sample: &Value| keys(sample.get("cores_watts").unwrap());
+            assert_eq!(cores(a_sample), cores(b_sample));
+        }
+    }
+}
+
+#[test]
+fn cli_behaves_the_same() {
+    let backends = backends();
+    let cases: &[&[&str]] = &[
+        &["--interval", "100ms"],
+        &["--samples", "2", "--interval", "100ms"],
+        &["--format", "statusbar", "--interval", "100ms"],
+        &[
+            "--format",
+            "ndjson",
+            "--samples",
+            "2",
+            "--interval",
+            "100ms",
+        ],
+        // No CPU draws this much, so always OK.
+        &["--warn", "100000", "--interval", "100ms"],
+        &["info"],
+    ];
+
+    for args in cases {
+        let outputs = backends
+            .iter()
+            .map(|(kind, cpu)| {
+                let (code, stdout) = run(*kind, args);
+                // The first line of text output is the package, whatever the
+                // backend measures beyond that.
+                let first = stdout.lines().next().unwrap_or_default().to_owned();
+                let label = first.split(':').next().unwrap_or_default().to_owned();
+                (
+                    kind.name(),
+                    code,
+                    stdout.lines().count(),
+                    label,
+                    cpu.core_ids(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for pair in outputs.windows(2) {
+            let [a, b] = pair else { unreachable!() };
+            assert_eq!(a.1, b.1, "exit codes of {:?}: {} vs {}", args, a.0, b.0);
+            assert_eq!(a.3, b.3, "first line of {:?}: {} vs {}", args, a.0, b.0);
+            // Unless they measure different cores.
+            if a.4 == b.4 && args[0] != "info" {
+                assert_eq!(a.2, b.2, "lines of {:?}: {} vs {}", args, a.0, b.0);
+            }
+        }
+    }
+}
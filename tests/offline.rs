@@ -0,0 +1,12 @@
+//! `--offline` in a process of its own, since [`network::forbid`] can't be
+//! undone for the other tests.
+
+use ryzen_wattage::network::{self, Network};
+
+#[test]
+fn forbidding_takes_away_network_access() {
+    // An offline build never has any to begin with.
+    assert_eq!(Network::access().is_some(), network::built_in());
+    network::forbid();
+    assert!(Network::access().is_none());
+}
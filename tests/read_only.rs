@@ -0,0 +1,29 @@
+//! `--read-only` in a process of its own, since [`polkit::forbid_writes`]
+//! can't be undone for the other tests.
+
+use std::{env, fs, path::PathBuf, process};
+
+use ryzen_wattage::{polkit, Error};
+
+fn refused(path: &PathBuf) -> bool {
+    let result = polkit::write_sysfs(&[(path.clone(), "performance".to_owned())]);
+    matches!(result, Err(Error::ReadOnly { path: refused }) if &refused == path)
+}
+
+#[test]
+fn refuses_writes_before_touching_the_file() {
+    let path = env::temp_dir().join(format!("ryzen-wattage-read-only-{}", process::id()));
+    fs::write(&path, "powersave\n").unwrap();
+
+    // A read-only build never writes, forbidden or not.
+    assert_eq!(polkit::writable(), !cfg!(feature = "read-only"));
+    if cfg!(feature = "read-only") {
+        assert!(refused(&path));
+    }
+    polkit::forbid_writes();
+    assert!(!polkit::writable());
+    assert!(refused(&path));
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "powersave\n");
+    fs::remove_file(&path).unwrap();
+}
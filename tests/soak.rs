@@ -0,0 +1,395 @@
+//! Soak test of the pieces `--daemon` is made of: the sampling loop over a
+//! mock backend, with I/O error bursts, cores unplugged and plugged back in
+//! and counters wrapping every few seconds, and the socket server with
+//! clients that poll, read slowly or hang up mid-response.
+//!
+//! It checks that the power stays right over the whole run and that the
+//! memory and threads of the process don't grow. Opt in with how long to
+//! run for, in a release build:
+//! `RYZEN_WATTAGE_SOAK=6h cargo test --release --test soak -- --nocapture`.
+
+use std::{
+    env, fmt, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ryzen_wattage::{
+    backend::EnergyReader,
+    cpu::Snapshot,
+    daemon::{self, Daemon},
+    json,
+    output::Sample,
+    sysfs::Root,
+    Cpu, Error, Result,
+};
+
+const OPT_IN: &str = "RYZEN_WATTAGE_SOAK";
+
+const INTERVAL: Duration = Duration::from_millis(10);
+const CORES: u32 = 8;
+const PACKAGE_WATTS: f64 = 60.0;
+const CORE_WATTS: f64 = 5.0;
+/// Like Zen's counters, but wrapping every few seconds instead of hours.
+const UNIT: f64 = 1.0 / 65536.0;
+const RANGE: f64 = 512.0;
+
+/// Per package read: the odds of an EIO burst starting, and of a core
+/// being unplugged.
+const BURST_ODDS: u64 = 5_000;
+const HOTPLUG_ODDS: u64 = 20_000;
+
+/// Growth allowed after the warmup.
+const RSS_GROWTH_KIB: u64 = 32 * 1024;
+const THREAD_GROWTH: u64 = 16;
+
+/// A root with [`CORES`] online CPUs and SMT off, so the run doesn't
+/// depend on the machine's topology.
+fn topology(dir: &Path) -> Root {
+    let cpu = dir.join("sys/devices/system/cpu");
+    fs::create_dir_all(cpu.join("smt")).unwrap();
+    fs::write(cpu.join("online"), format!("0-{}\n", CORES - 1)).unwrap();
+    fs::write(cpu.join("smt/control"), "off\n").unwrap();
+    Root::new(dir)
+}
+
+/// How long to run for, `None` unless opted in.
+fn soak_duration() -> Option<Duration> {
+    let value = env::var(OPT_IN).ok()?;
+    let (number, unit) = value.split_at(value.trim_end_matches(char::is_alphabetic).len());
+    let number = number
+        .parse::<f64>()
+        .expect("RYZEN_WATTAGE_SOAK like 90s, 30m or 6h");
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        other => panic!("unknown unit `{}` in {}", other, OPT_IN),
+    };
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Counters of constant power, failing now and then.
+struct Faulty {
+    start: Instant,
+    faults: Arc<Mutex<Faults>>,
+}
+
+#[derive(Default)]
+struct Faults {
+    rng: u64,
+    errors_left: u32,
+    unplugged: Option<(u32, Instant)>,
+    bursts: u64,
+    hotplugs: u64,
+}
+
+impl Faults {
+    /// xorshift64, reproducible from run to run.
+    fn next(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+impl fmt::Debug for Faulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Faulty")
+    }
+}
+
+impl Faulty {
+    fn new(faults: &Arc<Mutex<Faults>>) -> Self {
+        Self {
+            start: Instant::now(),
+            faults: Arc::clone(faults),
+        }
+    }
+
+    fn counter(&self, watts: f64) -> f64 {
+        let energy = watts * self.start.elapsed().as_secs_f64();
+        (energy / UNIT).floor() * UNIT % RANGE
+    }
+}
+
+impl EnergyReader for Faulty {
+    fn name(&self) -> &'static str {
+        "faulty"
+    }
+
+    fn core_ids(&self) -> Vec<u32> {
+        (0..CORES).collect()
+    }
+
+    fn package_energy(&self) -> Result<f64> {
+        let mut faults = self.faults.lock().unwrap();
+        if faults.next().is_multiple_of(BURST_ODDS) {
+            faults.errors_left = 1 + (faults.next() % 20) as u32;
+            faults.bursts += 1;
+        }
+        if faults.unplugged.is_none() && faults.next().is_multiple_of(HOTPLUG_ODDS) {
+            let core = (faults.next() % u64::from(CORES)) as u32;
+            let back = Instant::now() + Duration::from_millis(500 + faults.next() % 2500);
+            faults.unplugged = Some((core, back));
+            faults.hotplugs += 1;
+        }
+        if faults.errors_left > 0 {
+            faults.errors_left -= 1;
+            return Err(Error::io("/dev/cpu/0/msr", io::Error::from_raw_os_error(5)));
+        }
+        Ok(self.counter(PACKAGE_WATTS))
+    }
+
+    fn core_energy(&self, core: u32) -> Result<f64> {
+        let mut faults = self.faults.lock().unwrap();
+        match faults.unplugged {
+            Some((_, back)) if Instant::now() >= back => faults.unplugged = None,
+            Some((unplugged, _)) if unplugged == core => {
+                // ENODEV, like a core taken offline.
+                return Err(Error::io(
+                    format!("/dev/cpu/{}/msr", core),
+                    io::Error::from_raw_os_error(19),
+                ));
+            }
+            _ => {}
+        }
+        Ok(self.counter(CORE_WATTS))
+    }
+
+    fn package_energy_range(&self) -> Option<f64> {
+        Some(RANGE)
+    }
+
+    fn core_energy_range(&self) -> Option<f64> {
+        Some(RANGE)
+    }
+
+    fn energy_unit(&self) -> Option<f64> {
+        Some(UNIT)
+    }
+}
+
+/// Resident memory in KiB and threads of this process.
+fn usage() -> (u64, u64) {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.split_whitespace().next()?.parse().ok())
+            .unwrap()
+    };
+    (field("VmRSS:"), field("Threads:"))
+}
+
+/// Retrying through EIO bursts, like a supervisor restarting the daemon
+/// would, minus the restart.
+fn snapshot(cpu: &Cpu, errors: &mut u64) -> Snapshot {
+    loop {
+        match cpu.snapshot() {
+            Ok(snapshot) => return snapshot,
+            Err(err) => {
+                assert!(!err.is_device_gone(), "{}", err);
+                *errors += 1;
+                thread::sleep(INTERVAL);
+            }
+        }
+    }
+}
+
+fn sample(cpu: &Cpu, before: &Snapshot, after: &Snapshot) -> Sample {
+    let power = cpu.power_between(before, after);
+    Sample {
+        timestamp: SystemTime::now(),
+        window: after.package.1.duration_since(before.package.1),
+        package_power: power.package,
+        cores_total_power: power.cores.values().sum(),
+        core_counters: !power.cores.is_empty(),
+        core_power: power.cores,
+        domain_power: power.domains,
+        backend: cpu.backend_name(),
+        smt_enabled: cpu.smt_enabled,
+        core_count: cpu.core_count,
+        physical_core_count: cpu.physical_core_count,
+        ..Sample::default()
+    }
+}
+
+/// Asks for the latest sample every 50ms and checks the answer.
+fn poller(path: &Path, done: &AtomicBool, answers: &AtomicU64) {
+    let stream = UnixStream::connect(path).unwrap();
+    let mut lines = BufReader::new(&stream).lines();
+    let mut writer = &stream;
+    while !done.load(Ordering::Relaxed) {
+        writeln!(writer, "{{\"command\":\"latest\"}}").unwrap();
+        let answer = json::parse(&lines.next().unwrap().unwrap()).unwrap();
+        // Only until the first sample is in.
+        if answer.get("ok") == Some(&json::Value::Bool(true)) {
+            answers.fetch_add(1, Ordering::Relaxed);
+        } else {
+            assert_eq!(answers.load(Ordering::Relaxed), 0, "{:?}", answer);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Asks for the whole history and reads it a trickle at a time.
+fn slow_sink(path: &Path, done: &AtomicBool) {
+    while !done.load(Ordering::Relaxed) {
+        let mut stream = UnixStream::connect(path).unwrap();
+        writeln!(stream, "{{\"command\":\"history\"}}").unwrap();
+        let mut chunk = [0; 4096];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(read) if read > 0 && !chunk[..read].contains(&b'\n') => {
+                    thread::sleep(Duration::from_millis(20))
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Connects, asks and hangs up without reading the answer.
+fn churn(path: &Path, done: &AtomicBool) {
+    while !done.load(Ordering::Relaxed) {
+        let mut stream = UnixStream::connect(path).unwrap();
+        writeln!(stream, "{{\"command\":\"history\"}}").unwrap();
+        drop(stream);
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn daemon_survives_a_long_run_with_faults() {
+    let Some(duration) = soak_duration() else {
+        eprintln!("skipped, set {}=6h or so to run the soak test", OPT_IN);
+        return;
+    };
+
+    let faults = Arc::new(Mutex::new(Faults {
+        rng: 0x2545_f491_4f6c_dd1d,
+        ..Faults::default()
+    }));
+    let root = env::temp_dir().join(format!("ryzen-wattage-soak-{}", process::id()));
+    let cpu = Cpu::with_reader(Box::new(Faulty::new(&faults)), topology(&root)).unwrap();
+    let path = env::temp_dir().join(format!("ryzen-wattage-soak-{}.sock", process::id()));
+    let listener = daemon::bind(&path, &Default::default()).unwrap();
+    let daemon = Arc::new(Daemon::new());
+    let server = Arc::clone(&daemon);
+    thread::spawn(move || server.serve(&listener));
+
+    let done = AtomicBool::new(false);
+    let answers = AtomicU64::new(0);
+    thread::scope(|scope| {
+        scope.spawn(|| poller(&path, &done, &answers));
+        scope.spawn(|| slow_sink(&path, &done));
+        scope.spawn(|| churn(&path, &done));
+
+        let start = Instant::now();
+        let warmup = (duration / 10).min(Duration::from_secs(60));
+        let mut baseline = None;
+        let (mut errors, mut windows, mut partial) = (0, 0u64, 0u64);
+        // Energy and time of the current minute and of the whole run.
+        let (mut minute, mut total) = ((0.0, 0.0), (0.0, 0.0));
+        let mut longest = Duration::ZERO;
+
+        let mut before = snapshot(&cpu, &mut errors);
+        while start.elapsed() < duration {
+            thread::sleep(INTERVAL);
+            let after = snapshot(&cpu, &mut errors);
+            let sample = sample(&cpu, &before, &after);
+            before = after;
+            daemon.record(&sample);
+
+            windows += 1;
+            longest = longest.max(sample.window);
+            assert!(sample.core_counters, "core counters were given up on");
+            if sample.core_power.len() < CORES as usize {
+                partial += 1;
+            }
+            for watts in sample.core_power.values() {
+                assert!(*watts >= 0.0, "negative core power {}", watts);
+            }
+            let energy = sample.package_power * sample.window.as_secs_f64();
+            minute.0 += energy;
+            minute.1 += sample.window.as_secs_f64();
+            total.0 += energy;
+            total.1 += sample.window.as_secs_f64();
+
+            if minute.1 >= 60.0 {
+                let (rss, threads) = usage();
+                let watts = minute.0 / minute.1;
+                eprintln!(
+                    "{:>6.0}s: {:.3}W, {} windows, {} errors, {} KiB, {} threads",
+                    start.elapsed().as_secs_f64(),
+                    watts,
+                    windows,
+                    errors,
+                    rss,
+                    threads
+                );
+                assert!(
+                    (watts - PACKAGE_WATTS).abs() < PACKAGE_WATTS * 0.01,
+                    "{}W",
+                    watts
+                );
+                minute = (0.0, 0.0);
+            }
+            if baseline.is_none() && start.elapsed() >= warmup {
+                baseline = Some(usage());
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+
+        let (rss, threads) = usage();
+        let (base_rss, base_threads) = baseline.unwrap_or((rss, threads));
+        let faults = faults.lock().unwrap();
+        eprintln!(
+            "{} windows, longest {:?}, {} with cores unplugged, {} read errors in {} bursts, \
+             {} hotplugs, {} answers",
+            windows,
+            longest,
+            partial,
+            errors,
+            faults.bursts,
+            faults.hotplugs,
+            answers.load(Ordering::Relaxed)
+        );
+
+        assert!(
+            rss <= base_rss + RSS_GROWTH_KIB,
+            "{} KiB from {} KiB",
+            rss,
+            base_rss
+        );
+        assert!(
+            threads <= base_threads + THREAD_GROWTH,
+            "{} threads from {}",
+            threads,
+            base_threads
+        );
+        let watts = total.0 / total.1;
+        assert!(
+            (watts - PACKAGE_WATTS).abs() < PACKAGE_WATTS * 0.001,
+            "{}W",
+            watts
+        );
+        // Slow clients mustn't hold up sampling.
+        assert!(longest < Duration::from_secs(1), "{:?}", longest);
+        assert!(answers.load(Ordering::Relaxed) > 0);
+    });
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+}
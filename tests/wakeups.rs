@@ -0,0 +1,41 @@
+//! [`signal::sleep`] in a process of its own, since an interrupt can't be
+//! taken back for the other tests.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use ryzen_wattage::signal;
+
+extern "C" {
+    fn getpid() -> i32;
+    fn kill(pid: i32, signal: i32) -> i32;
+}
+
+const SIGINT: i32 = 2;
+
+#[test]
+fn sleeps_through_a_window_in_one_wakeup_until_interrupted() {
+    signal::catch_interrupts();
+
+    // Checking for a signal every 50 ms woke up 20 times a second.
+    let before = signal::wakeups().unwrap();
+    let start = Instant::now();
+    signal::sleep(Duration::from_secs(1));
+    let woken = signal::wakeups().unwrap() - before;
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert!(woken <= 3, "woke up {} times", woken);
+
+    // A signal to another thread of the process still ends it early.
+    let interrupter = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(100));
+        // SAFETY: only sends a signal, which the handler catches.
+        unsafe { kill(getpid(), SIGINT) };
+    });
+    let start = Instant::now();
+    signal::sleep(Duration::from_secs(30));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(signal::interrupted());
+    interrupter.join().unwrap();
+}